@@ -0,0 +1,125 @@
+//! Command-line utilities for setting up and inspecting Coherent lasers.
+use coherent_rs::laser::{ports, LaserType, schema::status_schema};
+
+const USAGE : &str = "Usage: coherent <command>\
+    \n\nCommands:\
+    \n    list                                  List connected Coherent devices\
+    \n    schema [--json]                       Print the columns of the Discovery NX status as CSV (or JSON)\
+    \n    setup-permissions [--group <group>] [--print]\
+    \n                                          Install a udev rule so non-root users can open the laser (Linux)\
+    \n    vectors <dir>                         Write the network protocol's conformance vectors (needs `network`)";
+
+/// Command-line utilities for setting up and inspecting Coherent lasers.
+///
+/// # Usage:
+///
+/// ```shell
+/// coherent list
+/// coherent schema --json > discovery_schema.json
+/// sudo coherent setup-permissions --group dialout
+/// coherent vectors vectors
+/// ```
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let result = match args.get(1).map(String::as_str) {
+        Some("list") => list(),
+        Some("schema") => schema(&args[2..]),
+        Some("setup-permissions") => setup_permissions(&args[2..]),
+        Some("vectors") => vectors(&args[2..]),
+        _ => {
+            println!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn list() -> Result<(), String> {
+    let devices = coherent_rs::get_all_coherent_devices()
+        .map_err(|e| format!("Could not list serial ports: {:?}", e))?;
+    if devices.is_empty() {
+        println!("No Coherent devices found");
+    }
+    for device in devices {
+        println!("{:?}", device);
+    }
+    Ok(())
+}
+
+fn schema(args : &[String]) -> Result<(), String> {
+    let schema = status_schema(&LaserType::DiscoveryNX).ok_or("no schema for the Discovery NX")?;
+    match args.first().map(String::as_str) {
+        None => print!("{}", schema.to_csv()),
+        Some("--json") => println!("{}", schema.to_json()),
+        Some(other) => return Err(format!("unrecognized argument {}\n\n{}", other, USAGE)),
+    }
+    Ok(())
+}
+
+/// Writes `network::conformance::test_vectors` for every wire format this
+/// build speaks to `<dir>/<format>/`.
+#[cfg(feature = "network")]
+fn vectors(args : &[String]) -> Result<(), String> {
+    use coherent_rs::network::{codec::WireFormat, conformance::write_vectors};
+
+    let dir = args.first().ok_or(format!("vectors needs a directory\n\n{}", USAGE))?;
+    for format in WireFormat::available() {
+        write_vectors(std::path::Path::new(dir), format).map_err(|e| format!("{:?}", e))?;
+        println!("Wrote {}/{}", dir, format.name());
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "network"))]
+fn vectors(_args : &[String]) -> Result<(), String> {
+    Err("vectors requires the 'network' feature, e.g. cargo run --features network --bin coherent vectors vectors".to_string())
+}
+
+/// Writes the udev rule from `ports::udev_rule` and asks udev to apply it.
+/// With `--print`, only prints the rule.
+fn setup_permissions(args : &[String]) -> Result<(), String> {
+    let mut group = None;
+    let mut print_only = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--group" => group = Some(args.next().ok_or("--group needs a group name")?.as_str()),
+            "--print" => print_only = true,
+            other => return Err(format!("unrecognized argument {}\n\n{}", other, USAGE)),
+        }
+    }
+
+    let rule = ports::udev_rule(group);
+    if print_only {
+        print!("{}", rule);
+        return Ok(());
+    }
+    install_udev_rule(&rule)
+}
+
+#[cfg(target_os = "linux")]
+fn install_udev_rule(rule : &str) -> Result<(), String> {
+    std::fs::write(ports::UDEV_RULE_PATH, rule).map_err(|e| format!(
+        "couldn't write {} ({}) -- try again with sudo, or use --print and install it yourself",
+        ports::UDEV_RULE_PATH, e
+    ))?;
+    println!("Wrote {}", ports::UDEV_RULE_PATH);
+
+    for udevadm_args in [&["control", "--reload-rules"][..], &["trigger", "--subsystem-match=tty"][..]] {
+        let status = std::process::Command::new("udevadm").args(udevadm_args).status()
+            .map_err(|e| format!("couldn't run udevadm ({}) -- replug the laser to apply the rule", e))?;
+        if !status.success() {
+            return Err(format!("udevadm {} failed -- replug the laser to apply the rule", udevadm_args.join(" ")));
+        }
+    }
+    println!("Rule applied. Replug the laser if it was already connected.");
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn install_udev_rule(_rule : &str) -> Result<(), String> {
+    Err("setup-permissions only applies to Linux -- other platforms don't need it".to_string())
+}
@@ -1,15 +1,15 @@
 //! Forces a `Server` at a port to forget its primary client
 //! 
-use coherent_rs::Discovery;
 #[cfg(feature = "network")]
-use coherent_rs::network::{NetworkLaserClient,BasicNetworkLaserClient};
+use coherent_rs::network::DynNetworkLaserClient;
 
-/// Host a Coherent laser on a network server with a port specified in the command line.
+/// Forces the server at the address specified in the command line to forget
+/// its primary client. Works for any type of laser the server is hosting.
 /// 
 /// # Usage:
 /// 
 /// ```shell
-/// host_discovery_server COM5
+/// force_free_discovery 127.0.0.1:907
 /// ``` 
 #[cfg(feature = "network")]
 fn main() {
@@ -19,10 +19,10 @@ fn main() {
         std::process::exit(1);
     }
     let port = args[1].parse::<String>().unwrap();
-    let client = BasicNetworkLaserClient::<Discovery>::connect(port.as_str(), None);
+    let client = DynNetworkLaserClient::connect(port.as_str(), None);
     match client {
         Ok(mut client) => {
-            println!("Client connected to port {}", port);
+            println!("Client connected to port {} (hosting {:?})", port, client.get_laser_type());
             match client.force_forget_primary_client() {
                 Ok(_) => {
                     println!("Primary client forgotten");
@@ -32,7 +32,6 @@ fn main() {
                     std::process::exit(1);
                 }
             }
-            return ();
         }
         Err(e) => {
             eprintln!("Error: {:?}", e);
@@ -45,6 +44,6 @@ fn main() {
 fn main() {
     eprintln!("This binary requires the 'network' feature to be enabled.\
         \nPlease recompile with the 'network' feature enabled.\
-        \n\nExample: cargo run --features network --bin listen_and_print_discovery 127.0.0.1:907");
+        \n\nExample: cargo run --features network --bin force-free-discovery 127.0.0.1:907");
     std::process::exit(1);
-}
\ No newline at end of file
+}
@@ -1,11 +1,8 @@
 //! Host a Coherent laser on a network server with a port specified in the command line.
+#[cfg(feature = "network")]
 use std::time::Duration;
-use coherent_rs::{
-    Discovery,
-    laser::Laser,
-};
 #[cfg(feature = "network")]
-use coherent_rs::network::NetworkLaserServer;
+use coherent_rs::{Discovery, laser::Laser, network::NetworkLaserServer};
 
 /// Host a Coherent laser on a network server with a port specified in the command line.
 /// 
@@ -24,7 +21,7 @@ fn main() {
     let port = args[1].parse::<String>().unwrap();
     let laser = Discovery::find_first().unwrap();
     match NetworkLaserServer::<Discovery>::new(
-        laser, port.as_str(), Some(0.2),
+        laser, port.as_str(), Some(std::time::Duration::from_millis(200)),
     ) {
         Ok(mut server) => {
             match server.poll() {
@@ -37,7 +34,6 @@ fn main() {
                 }
             }
             while server.polling() {std::thread::sleep(Duration::from_millis(5));}
-            return ();
         }
         Err(e) => {
             eprintln!("Error: {:?}", e);
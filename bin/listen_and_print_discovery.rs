@@ -1,8 +1,7 @@
 //! Listen to a Coherent laser on a network server with a port specified in the command line.
 
-use coherent_rs::Discovery;
 #[cfg(feature = "network")]
-use coherent_rs::network::{NetworkLaserClient,BasicNetworkLaserClient};
+use coherent_rs::{Discovery, network::{NetworkLaserClient,BasicNetworkLaserClient}};
 
 /// Host a Coherent laser on a network server with a port specified in the command line.
 /// 
@@ -23,8 +22,14 @@ fn main() {
     match client {
         Ok(mut client) => {
             println!("Client connected to port {}", port);
+            // The whole status once, then only what changes
+            let mut last = client.query_status().unwrap();
+            println!{"{:?}", last};
             loop {
-                println!{"{:?}",client.query_status().unwrap()};
+                let status = client.query_status().unwrap();
+                let diff = last.diff(&status);
+                if !diff.is_empty() { println!("{}", diff); }
+                last = status;
             }
         }
         Err(e) => {
@@ -0,0 +1,77 @@
+//! Pretend to be a Discovery NX on a serial port, so the real `Discovery`
+//! code can be exercised without a laser.
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use coherent_rs::laser::simulator::DiscoverySimulator;
+
+/// Pretend to be a Discovery NX on a serial port. With no port given (on
+/// Unix), opens a pseudo-terminal and prints the path to connect the laser
+/// code to. On Windows, pass one end of a com0com pair and connect to the other.
+/// 
+/// # Usage:
+/// 
+/// ```shell
+/// simulator [--no-echo] [--prompt] [port]
+/// ``` 
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut echo = true;
+    let mut prompt = false;
+    let mut port_name = None;
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "--no-echo" => echo = false,
+            "--prompt" => prompt = true,
+            name if !name.starts_with("--") && port_name.is_none() => port_name = Some(name.to_string()),
+            _ => {
+                println!("Usage: {} [--no-echo] [--prompt] [port]", args[0]);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut port = match open_port(port_name) {
+        Ok(port) => port,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut simulator = DiscoverySimulator::new(echo, prompt, "SIM0001");
+    if let Err(e) = simulator.serve(port.as_mut(), &AtomicBool::new(true)) {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(1);
+    }
+}
+
+fn open_port(port_name : Option<String>) -> serialport::Result<Box<dyn serialport::SerialPort>> {
+    if let Some(name) = port_name {
+        let port = serialport::new(&name, 19200)
+            .timeout(Duration::from_millis(100))
+            .open()?;
+        println!("Simulating a Discovery NX on {}", name);
+        return Ok(port);
+    }
+    open_pty()
+}
+
+#[cfg(unix)]
+fn open_pty() -> serialport::Result<Box<dyn serialport::SerialPort>> {
+    use serialport::SerialPort;
+    let (mut master, slave) = serialport::TTYPort::pair()?;
+    master.set_timeout(Duration::from_millis(100))?;
+    println!("Simulating a Discovery NX on {}", slave.name().unwrap_or_default());
+    // Keep the slave end open so the pty doesn't hang up before someone connects
+    std::mem::forget(slave);
+    Ok(Box::new(master))
+}
+
+#[cfg(not(unix))]
+fn open_pty() -> serialport::Result<Box<dyn serialport::SerialPort>> {
+    Err(serialport::Error::new(
+        serialport::ErrorKind::InvalidInput,
+        "No port given -- on Windows, pass one end of a com0com pair (e.g. COM20)",
+    ))
+}
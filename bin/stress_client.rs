@@ -0,0 +1,121 @@
+//! Soak-test a network server with many concurrent clients.
+#[cfg(feature = "network")]
+use std::time::Duration;
+#[cfg(feature = "network")]
+use coherent_rs::{laser::debug::DebugLaser, network::{NetworkLaserServer, stress::{run, StressConfig, StressReport}}};
+
+#[cfg(feature = "network")]
+const USAGE : &str = "Usage: stress-client [options] [address]\
+    \n\nRuns randomized commands and queries against the server at <address>, which must be\
+    \nhosting a DebugLaser, or against one hosted in-process if no address is given.\
+    \nExits with 1 if the run fails its criteria.\
+    \n\nOptions:\
+    \n    --clients <n>           Concurrent clients (default 8)\
+    \n    --duration <seconds>    How long to run (default 60)\
+    \n    --query-fraction <f>    Fraction of operations that are status queries (default 0.5)\
+    \n    --max-p99-ms <ms>       Fail if the 99th percentile latency is above this (default 2000)\
+    \n    --max-errors <n>        Fail if more operations than this go wrong (default 0)\
+    \n    --seed <n>              Seed for the random operations (default 1)";
+
+/// Soak-test a network server with many concurrent clients, e.g. overnight
+/// before it's trusted with a real laser.
+///
+/// # Usage:
+///
+/// ```shell
+/// stress-client --clients 16 --duration 14400 127.0.0.1:907
+/// ```
+#[cfg(feature = "network")]
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let (config, address) = match parse_args(&args[1..]) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            println!("{}\n\n{}", e, USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    // Keep the server alive for the whole run
+    let mut _server = None;
+    let address = match address {
+        Some(address) => address,
+        None => match host_debug_server() {
+            Ok((server, address)) => {
+                _server = Some(server);
+                address
+            },
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    println!("Running {} clients against {} for {:?}", config.clients, address, config.duration);
+    let report = run(&address, &config, |report| println!("{}", summary(report)));
+    println!("{}", summary(&report));
+    for error in &report.first_errors {
+        println!("  {}", error);
+    }
+
+    let failures = report.failures(&config);
+    if failures.is_empty() {
+        println!("PASSED");
+    }
+    else {
+        println!("FAILED: {}", failures.join("; "));
+        std::process::exit(1);
+    }
+}
+
+#[cfg(feature = "network")]
+fn parse_args(args : &[String]) -> Result<(StressConfig, Option<String>), String> {
+    let mut config = StressConfig::default();
+    let mut address = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--clients" => config.clients = parse(value()?)?,
+            "--duration" => config.duration = Duration::from_secs_f64(parse(value()?)?),
+            "--query-fraction" => config.query_fraction = parse(value()?)?,
+            "--max-p99-ms" => config.max_p99_latency = Duration::from_millis(parse(value()?)?),
+            "--max-errors" => config.max_errors = parse(value()?)?,
+            "--seed" => config.seed = parse(value()?)?,
+            other if !other.starts_with("--") && address.is_none() => address = Some(other.to_string()),
+            other => return Err(format!("unrecognized argument {}", other)),
+        }
+    }
+    Ok((config, address))
+}
+
+#[cfg(feature = "network")]
+fn parse<T : std::str::FromStr>(value : &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value {}", value))
+}
+
+#[cfg(feature = "network")]
+fn host_debug_server() -> Result<(NetworkLaserServer<DebugLaser>, String), coherent_rs::network::TcpError> {
+    let mut server = NetworkLaserServer::new(DebugLaser::default(), "127.0.0.1:0", Some(Duration::from_millis(100)))?;
+    let address = server.local_addr()?.to_string();
+    server.poll()?;
+    Ok((server, address))
+}
+
+#[cfg(feature = "network")]
+fn summary(report : &StressReport) -> String {
+    format!(
+        "{:>8.0} s  {} operations ({:.1}/s, {} refused)  {} errors  latency p50 {:?} p99 {:?} max {:?}",
+        report.elapsed.as_secs_f64(), report.operations, report.operations_per_sec(), report.refused,
+        report.errors, report.latency_p50, report.latency_p99, report.latency_max,
+    )
+}
+
+#[cfg(not(feature = "network"))]
+fn main() {
+    eprintln!("This binary requires the 'network' feature to be enabled.\
+        \nPlease recompile with the 'network' feature enabled.\
+        \n\nExample: cargo run --features network --bin stress-client -- --duration 3600");
+    std::process::exit(1);
+}
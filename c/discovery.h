@@ -47,9 +47,21 @@ typedef enum {
     CLOSED = false
 } ShutterState;
 
+/**
+ * @brief What `discovery_get_laser_state` returns.
+ */
+typedef enum {
+    LASER_STANDBY = 0,
+    LASER_ON = 1,
+    LASER_OFF = 2, // keyswitch off, or shut down by a fault
+    LASER_STATE_ERROR = -1
+} LaserState;
+
 /**
  * @brief A struct to hold the status of a Discovery device,
  * closely matching the `DiscoveryStatus` struct in `Rust`.
+ * Fields the laser didn't report are empty strings, `NAN`
+ * (`power_variable` while tuning, `gdd`), or -1 (`gdd_curve`).
  */
 typedef struct DiscoveryStatus {
     const bool echo;
@@ -235,6 +247,17 @@ extern "C" {
      */
     API_IMPORT bool discovery_get_laser_standby(Discovery discovery);
 
+    /**
+     * @brief Gets whether the laser is on, in standby, or off -- the keyswitch
+     * is off, or a fault has shut it down. `discovery_get_laser_standby` reports
+     * an off laser as in standby.
+     * 
+     * @param discovery Raw pointer to a `Discovery` object
+     * @return `int` a `LaserState`: `LASER_STANDBY`, `LASER_ON`, `LASER_OFF`, or
+     * `LASER_STATE_ERROR` if the laser couldn't be read.
+     */
+    API_IMPORT int discovery_get_laser_state(Discovery discovery);
+
     /**
      * @brief Gets the keyswitch state of the laser.
      * 
@@ -251,6 +274,17 @@ extern "C" {
      */
     API_IMPORT bool discovery_get_tuning(Discovery discovery);
 
+    /**
+     * @brief Blocks until the laser is done tuning.
+     * 
+     * @param discovery Raw pointer to a `Discovery` object
+     * @param timeout_ms How long to wait, in milliseconds
+     * @param poll_interval_ms How often to check, in milliseconds
+     * @return `int` 0 if the laser is ready, -1 if it's still tuning after
+     * `timeout_ms` or can't be read.
+     */
+    API_IMPORT int discovery_wait_for_tuning(Discovery discovery, uint64_t timeout_ms, uint64_t poll_interval_ms);
+
     /**
      * @brief Returns a Status string for the laser. I actually don't know
      * how much to allocate but probably no more than 256 bytes...
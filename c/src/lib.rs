@@ -1,11 +1,18 @@
 //! Thin C ABI layer for the `coherent_rs` crate
+
+#[cfg(feature="network")]
 use std::ffi::{CString, c_char};
 use coherent_rs::{laser, Discovery, laser::Laser};
+#[cfg(feature="network")]
 use coherent_rs::{DiscoveryNXCommands, discoverynx::DiscoveryLaser};
 #[cfg(feature="network")]
 use coherent_rs::network::{BasicNetworkLaserClient, NetworkLaserClient, NetworkLaserServer, TcpError};
 
 /// C ABI
+///
+/// # Safety
+///
+/// The returned pointer must be released with `free_discovery` and nothing else.
 #[no_mangle]
 pub unsafe extern "C" fn discovery_find_first() -> *mut Discovery {
     match Discovery::find_first() {
@@ -14,17 +21,24 @@ pub unsafe extern "C" fn discovery_find_first() -> *mut Discovery {
     }
 }
 
+/// # Safety
+///
+/// `laser` must be null or a pointer returned by one of the `discovery_*`
+/// constructors, and not used again afterwards.
 #[no_mangle]
 pub unsafe extern "C" fn free_discovery(laser : *mut Discovery) {
     if laser.is_null() {return}
     drop(Box::from_raw(laser)); // drop is for clarity
 }
 
+/// # Safety
+///
+/// `port_name` must point to `port_name_len` bytes of UTF-8.
 #[no_mangle]
 pub unsafe extern "C" fn discovery_by_port_name(port_name : *const u8, port_name_len : usize) -> *mut Discovery {
     let port_name = unsafe {
         std::str::from_utf8(std::slice::from_raw_parts(port_name, port_name_len))
-        .map_err(|_| {return std::ptr::null_mut::<Discovery>();}).unwrap()
+        .map_err(|_| {std::ptr::null_mut::<Discovery>()}).unwrap()
     };
 
     match Discovery::from_port_name(port_name) {
@@ -33,6 +47,9 @@ pub unsafe extern "C" fn discovery_by_port_name(port_name : *const u8, port_name
     }
 }
 
+/// # Safety
+///
+/// `serial_number` must point to `serial_number_len` bytes of UTF-8.
 #[no_mangle]
 pub unsafe extern "C" fn discovery_by_serial_number(serial_number : *const u8, serial_number_len : usize) -> *mut Discovery {
     let serial_number = unsafe {
@@ -44,6 +61,7 @@ pub unsafe extern "C" fn discovery_by_serial_number(serial_number : *const u8, s
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_set_wavelength(discovery : *mut Discovery, wavelength : f32) -> i32 {
     unsafe {match discovery.as_mut().unwrap().set_wavelength(wavelength) {
@@ -52,21 +70,25 @@ pub extern "C" fn discovery_set_wavelength(discovery : *mut Discovery, wavelengt
     }}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_get_wavelength(discovery : *mut Discovery) -> f32 {
     unsafe {(*discovery).get_wavelength().unwrap()}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_get_power_variable(discovery : *mut Discovery) -> f32 {
     unsafe {(*discovery).get_power(laser::DiscoveryLaser::VariableWavelength).unwrap()}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_get_power_fixed(discovery : *mut Discovery) -> f32 {
     unsafe {(*discovery).get_power(laser::DiscoveryLaser::FixedWavelength).unwrap()}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_set_gdd(discovery : *mut Discovery, gdd : f32) -> i32 {
     unsafe {match (*discovery).set_gdd(gdd) {
@@ -75,39 +97,45 @@ pub extern "C" fn discovery_set_gdd(discovery : *mut Discovery, gdd : f32) -> i3
     }}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_get_gdd(discovery : *mut Discovery) -> f32 {
     unsafe {(*discovery).get_gdd().unwrap()}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_set_alignment_variable(discovery : *mut Discovery, alignment : bool) -> i32 {
-    unsafe {match (*discovery).set_alignment_mode(laser::DiscoveryLaser::VariableWavelength, alignment) {
+    unsafe {match (*discovery).set_alignment_mode(laser::DiscoveryLaser::VariableWavelength, alignment.into()) {
         Ok(()) => 0,
         Err(_) => -1,
     }}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_get_alignment_variable(discovery : *mut Discovery) -> bool {
-    unsafe {(*discovery).get_alignment_mode(laser::DiscoveryLaser::VariableWavelength).unwrap()}
+    unsafe {(*discovery).get_alignment_mode(laser::DiscoveryLaser::VariableWavelength).unwrap().into()}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_set_alignment_fixed(discovery : *mut Discovery, alignment : bool) -> i32 {
-    unsafe {match (*discovery).set_alignment_mode(laser::DiscoveryLaser::FixedWavelength, alignment) {
+    unsafe {match (*discovery).set_alignment_mode(laser::DiscoveryLaser::FixedWavelength, alignment.into()) {
         Ok(()) => 0,
         Err(_) => -1,
     }}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_get_alignment_fixed(discovery : *mut Discovery) -> bool {
-    unsafe {(*discovery).get_alignment_mode(laser::DiscoveryLaser::FixedWavelength).unwrap()}
+    unsafe {(*discovery).get_alignment_mode(laser::DiscoveryLaser::FixedWavelength).unwrap().into()}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
-pub extern "C" fn discovery_get_status_string(discovery : *mut Discovery, status : *mut u8, status_len : *mut usize) -> () {
+pub extern "C" fn discovery_get_status_string(discovery : *mut Discovery, status : *mut u8, status_len : *mut usize) {
     unsafe {
         let status_string = (*discovery).get_status().unwrap();
         let status_string = status_string.as_bytes();
@@ -117,6 +145,7 @@ pub extern "C" fn discovery_get_status_string(discovery : *mut Discovery, status
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_get_tuning(discovery : *mut Discovery) -> bool {
     unsafe { match (*discovery).get_tuning().unwrap() {
@@ -125,6 +154,21 @@ pub extern "C" fn discovery_get_tuning(discovery : *mut Discovery) -> bool {
     }}
 }
 
+/// Blocks until the laser is done tuning. Returns -1 if it's still tuning
+/// after `timeout_ms`, or the laser can't be read.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn discovery_wait_for_tuning(discovery : *mut Discovery, timeout_ms : u64, poll_interval_ms : u64) -> i32 {
+    unsafe {match (*discovery).wait_for_tuning(
+        std::time::Duration::from_millis(timeout_ms),
+        std::time::Duration::from_millis(poll_interval_ms),
+    ) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }}
+}
+
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_set_shutter_variable(discovery : *mut Discovery, state : bool) -> i32 {
     unsafe {match (*discovery).set_shutter(laser::DiscoveryLaser::VariableWavelength, if state {laser::ShutterState::Open} else {laser::ShutterState::Closed}) {
@@ -133,11 +177,13 @@ pub extern "C" fn discovery_set_shutter_variable(discovery : *mut Discovery, sta
     }}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_get_shutter_variable(discovery : *mut Discovery) -> bool {
     unsafe {(*discovery).get_shutter(laser::DiscoveryLaser::VariableWavelength).unwrap() == laser::ShutterState::Open}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_set_shutter_fixed(discovery : *mut Discovery, state : bool) -> i32 {
     unsafe {match (*discovery).set_shutter(laser::DiscoveryLaser::FixedWavelength, if state {laser::ShutterState::Open} else {laser::ShutterState::Closed}) {
@@ -146,11 +192,13 @@ pub extern "C" fn discovery_set_shutter_fixed(discovery : *mut Discovery, state
     }}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_get_shutter_fixed(discovery : *mut Discovery) -> bool {
     unsafe {(*discovery).get_shutter(laser::DiscoveryLaser::FixedWavelength).unwrap() == laser::ShutterState::Open}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_set_laser_to_standby(discovery : *mut Discovery, state : bool) -> i32 {
     unsafe {match (*discovery).set_to_standby(state) {
@@ -159,22 +207,44 @@ pub extern "C" fn discovery_set_laser_to_standby(discovery : *mut Discovery, sta
     }}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_get_laser_standby(discovery : *mut Discovery) -> bool {
     unsafe {match (*discovery).get_standby().unwrap()
     {
-        laser::LaserState::Standby => true,
+        laser::LaserState::Standby | laser::LaserState::Off => true,
         laser::LaserState::On => false,
     }}
 }
 
+/// 0 for standby, 1 for on, 2 for off (the keyswitch is off, or a fault
+/// has shut the laser down), or -1 if the laser couldn't be read.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn discovery_get_laser_state(discovery : *mut Discovery) -> i32 {
+    let discovery = unsafe {&mut *discovery};
+    let state = discovery.get_standby().and_then(|queried| Ok(laser::LaserState::resolve(
+        queried,
+        discovery.get_keyswitch_on()?.into(),
+        !discovery.get_faults()?.is_empty(),
+    )));
+    match state {
+        Ok(laser::LaserState::Standby) => 0,
+        Ok(laser::LaserState::On) => 1,
+        Ok(laser::LaserState::Off) => 2,
+        Err(_) => -1,
+    }
+}
+
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_get_keyswitch(discovery : *mut Discovery) -> bool {
     unsafe {(*discovery).get_keyswitch_on().unwrap()}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
-pub extern "C" fn discovery_get_serial(discovery : *mut Discovery, serial: *mut u8, serial_len : *mut usize) -> () {
+pub extern "C" fn discovery_get_serial(discovery : *mut Discovery, serial: *mut u8, serial_len : *mut usize) {
     unsafe {
         let serial_number = (*discovery).get_serial().unwrap();
         let serial_number = serial_number.as_bytes();
@@ -184,6 +254,7 @@ pub extern "C" fn discovery_get_serial(discovery : *mut Discovery, serial: *mut
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_get_status(discovery : *mut Discovery, status: *mut u8, status_len : *mut usize) {
     unsafe {
@@ -195,6 +266,7 @@ pub extern "C" fn discovery_get_status(discovery : *mut Discovery, status: *mut
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_get_fault_text(discovery : *mut Discovery, error: *mut u8, error_len : *mut usize) {
     unsafe {
@@ -206,6 +278,7 @@ pub extern "C" fn discovery_get_fault_text(discovery : *mut Discovery, error: *m
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn discovery_clear_faults(discovery : *mut Discovery) -> i32 {
     unsafe {match (*discovery).clear_faults() {
@@ -224,6 +297,7 @@ pub extern "C" fn discovery_clear_faults(discovery : *mut Discovery) -> i32 {
 #[no_mangle]
 /// Returns a pointer to a `NetworkLaserServer` object,
 /// or `std::ptr::null_mut()` if the server could not be created.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn connect_discovery_client(port : *const u8, port_len : usize) -> *mut BasicNetworkLaserClient<Discovery> {
     let port = unsafe {
         std::str::from_utf8(std::slice::from_raw_parts(port, port_len)).unwrap()
@@ -239,6 +313,7 @@ pub extern "C" fn connect_discovery_client(port : *const u8, port_len : usize) -
 #[no_mangle]
 /// Returns a pointer to a `NetworkLaserServer` object,
 /// or `std::ptr::null_mut()` if the server could not be created.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn connect_discovery_client_with_timeout(port : *const u8, port_len : usize, timeout : u32) -> *mut BasicNetworkLaserClient<Discovery> {
     let port = unsafe {
         std::str::from_utf8(std::slice::from_raw_parts(port, port_len)).unwrap()
@@ -251,6 +326,7 @@ pub extern "C" fn connect_discovery_client_with_timeout(port : *const u8, port_l
 }
 
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn free_discovery_client(client : *mut BasicNetworkLaserClient<Discovery>) {
@@ -258,6 +334,7 @@ pub extern "C" fn free_discovery_client(client : *mut BasicNetworkLaserClient<Di
     drop(unsafe {Box::from_raw(client)});
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn set_discovery_client_variable_shutter(
@@ -277,6 +354,7 @@ pub extern "C" fn set_discovery_client_variable_shutter(
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn set_discovery_client_fixed_shutter(
@@ -296,13 +374,14 @@ pub extern "C" fn set_discovery_client_fixed_shutter(
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn set_discovery_client_wavelength(
     client : *mut BasicNetworkLaserClient<Discovery>,
     wavelength : f32,
 ) -> i32 {
-    match unsafe {(*client).command(DiscoveryNXCommands::Wavelength{wavelength_nm : wavelength})} {
+    match unsafe {(*client).command(DiscoveryNXCommands::Wavelength{wavelength_nm : wavelength.into()})} {
         Ok(()) => 0,
         Err(TcpError::NotPrimaryClient) => -2,
         Err(TcpError::Disconnected) => -3,
@@ -310,6 +389,7 @@ pub extern "C" fn set_discovery_client_wavelength(
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn set_discovery_client_to_standby(
@@ -325,6 +405,7 @@ pub extern "C" fn set_discovery_client_to_standby(
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn set_discovery_client_variable_alignment(
@@ -333,7 +414,7 @@ pub extern "C" fn set_discovery_client_variable_alignment(
 ) -> i32 {
     match unsafe {(*client).command(DiscoveryNXCommands::AlignmentMode{
         laser : DiscoveryLaser::VariableWavelength,
-        alignment_mode_on : alignment
+        alignment_mode_on : alignment.into()
     })} {
         Ok(()) => 0,
         Err(TcpError::NotPrimaryClient) => -2,
@@ -342,6 +423,7 @@ pub extern "C" fn set_discovery_client_variable_alignment(
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn set_discovery_client_fixed_alignment(
@@ -350,7 +432,7 @@ pub extern "C" fn set_discovery_client_fixed_alignment(
 ) -> i32 {
     match unsafe {(*client).command(DiscoveryNXCommands::AlignmentMode{
         laser : DiscoveryLaser::FixedWavelength,
-        alignment_mode_on : alignment
+        alignment_mode_on : alignment.into()
     })} {
         Ok(()) => 0,
         Err(TcpError::NotPrimaryClient) => -2,
@@ -359,13 +441,14 @@ pub extern "C" fn set_discovery_client_fixed_alignment(
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn set_discovery_client_gdd(
     client : *mut BasicNetworkLaserClient<Discovery>,
     gdd : f32
 ) -> i32 {
-    match unsafe {(*client).command(DiscoveryNXCommands::Gdd{gdd_val : gdd})}{
+    match unsafe {(*client).command(DiscoveryNXCommands::Gdd{gdd_val : gdd.into()})}{
         Ok(()) => 0,
         Err(TcpError::NotPrimaryClient) => -2,
         Err(TcpError::Disconnected) => -3,
@@ -373,13 +456,14 @@ pub extern "C" fn set_discovery_client_gdd(
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn set_discovery_client_gdd_curve(
     client : *mut BasicNetworkLaserClient<Discovery>,
     curve : i32
 ) -> i32 {
-    if curve > 255 || curve < 0 {
+    if !(0..=255).contains(&curve) {
         return -1;
     }
     match unsafe {(*client).command(DiscoveryNXCommands::GddCurve {curve_num : curve as u8})} {
@@ -390,6 +474,7 @@ pub extern "C" fn set_discovery_client_gdd_curve(
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn demand_primary_client(
@@ -401,6 +486,7 @@ pub extern "C" fn demand_primary_client(
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn release_primary_client(
@@ -442,30 +528,35 @@ pub struct CDiscoveryStatus {
 
 #[cfg(feature = "network")]
 fn discovery_status_to_csafe(status : <Discovery as Laser>::LaserStatus) -> CDiscoveryStatus {
+    // C has no `Option`: fields the laser didn't report are empty strings,
+    // NaN, or curve -1.
+    let fault_text = status.fault_text.unwrap_or_default();
+    let gdd_curve_n = status.gdd_curve_n.unwrap_or_default();
     CDiscoveryStatus{
         echo : status.echo,
-        laser : if status.laser == laser::LaserState::On {true} else {false},
-        variable_shutter : if status.variable_shutter == laser::ShutterState::Open {true} else {false},
-        fixed_shutter : if status.fixed_shutter == laser::ShutterState::Open {true} else {false},
-        keyswitch : status.keyswitch,
-        faults : status.faults,
-        fault_text : CString::new(status.fault_text.clone()).unwrap().into_raw(),
-        fault_text_len : status.fault_text.len(),
-        tuning : if status.tuning == laser::TuningStatus::Tuning {true} else {false},
-        alignment_var : status.alignment_var,
-        alignment_fixed : status.alignment_fixed,
+        laser : status.laser == laser::LaserState::On,
+        variable_shutter : status.variable_shutter == laser::ShutterState::Open,
+        fixed_shutter : status.fixed_shutter == laser::ShutterState::Open,
+        keyswitch : status.keyswitch.into(),
+        faults : status.faults.bits(),
+        fault_text_len : fault_text.len(),
+        fault_text : CString::new(fault_text).unwrap().into_raw(),
+        tuning : status.tuning == laser::TuningStatus::Tuning,
+        alignment_var : status.alignment_var.into(),
+        alignment_fixed : status.alignment_fixed.into(),
         status : CString::new(status.status.clone()).unwrap().into_raw(),
         status_len : status.status.len(),
         wavelength : status.wavelength,
-        power_var : status.power_var,
+        power_var : status.power_var.unwrap_or(f32::NAN),
         power_fixed : status.power_fixed,
-        gdd_curve : status.gdd_curve,
-        gdd_curve_n : CString::new(status.gdd_curve_n.clone()).unwrap().into_raw(),
-        gdd_curve_n_len : status.gdd_curve_n.len(),
-        gdd : status.gdd,
+        gdd_curve : status.gdd_curve.unwrap_or(-1),
+        gdd_curve_n_len : gdd_curve_n.len(),
+        gdd_curve_n : CString::new(gdd_curve_n).unwrap().into_raw(),
+        gdd : status.gdd.unwrap_or(f32::NAN),
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn discovery_client_query_status(client : *mut BasicNetworkLaserClient<Discovery>)
@@ -528,6 +619,7 @@ pub extern "C" fn discovery_client_query_status(client : *mut BasicNetworkLaserC
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn host_discovery_server(laser : *mut Discovery, port : *const u8, port_len : usize) -> *mut NetworkLaserServer<Discovery> {
@@ -544,6 +636,7 @@ pub extern "C" fn host_discovery_server(laser : *mut Discovery, port : *const u8
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn poll_server(server : *mut NetworkLaserServer<Discovery>) -> i32 {
@@ -553,6 +646,7 @@ pub extern "C" fn poll_server(server : *mut NetworkLaserServer<Discovery>) -> i3
     }
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn stop_polling(server : *mut NetworkLaserServer<Discovery>) {
@@ -560,6 +654,7 @@ pub extern "C" fn stop_polling(server : *mut NetworkLaserServer<Discovery>) {
     unsafe {(*server).stop_polling()}
 }
 
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[cfg(feature = "network")]
 #[no_mangle]
 pub extern "C" fn free_server(server : *mut NetworkLaserServer<Discovery>) {
@@ -569,13 +664,14 @@ pub extern "C" fn free_server(server : *mut NetworkLaserServer<Discovery>) {
 
 #[cfg(test)]
 mod tests{
-    use coherent_rs;
+    #[cfg(feature="network")]
     use coherent_rs::laser::Laser;
     #[cfg(feature="network")]
     use coherent_rs::network::{NetworkLaserServer};
 
     #[cfg(feature = "network")]
     #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
     /// Test what happens if the client disconnects
     fn disconnected_server() {
 
@@ -584,15 +680,15 @@ mod tests{
         assert!(laser.is_ok());
         let laser = laser.unwrap();
         let port = "127.0.0.1:907";
-        let network_laser = NetworkLaserServer::new(laser, port, Some(1.0));
+        let network_laser = NetworkLaserServer::new(laser, port, Some(std::time::Duration::from_secs(1)));
         
         assert!(network_laser.is_ok());
         let mut network_laser = network_laser.unwrap();
         assert!(network_laser.poll().is_ok());
 
 
-        let mut client = super::connect_discovery_client(
-            port.as_ptr() as *const u8, port.len()
+        let client = super::connect_discovery_client(
+            port.as_ptr(), port.len()
         );
         assert!(!client.is_null());
 
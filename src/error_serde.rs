@@ -0,0 +1,115 @@
+//! error_serde.rs
+//!
+//! `serde` for the foreign error types wrapped by `CoherentError` and
+//! `TcpError`, so errors can be sent across the network. None of them
+//! implement `serde` themselves, so each is sent as its message (plus its
+//! kind, where that can be rebuilt) and comes back as the closest error of
+//! the same type.
+
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+
+/// `serialport::Error` as its kind and description. An `Io` kind loses the
+/// underlying `std::io::ErrorKind`, coming back as `Other`.
+pub(crate) mod serial_error {
+    use super::*;
+    use serialport::ErrorKind;
+
+    #[derive(Serialize, Deserialize)]
+    struct SerialError {
+        kind : String,
+        description : String,
+    }
+
+    pub fn serialize<S : Serializer>(error : &serialport::Error, serializer : S) -> Result<S::Ok, S::Error> {
+        let kind = match error.kind {
+            ErrorKind::NoDevice => "NoDevice",
+            ErrorKind::InvalidInput => "InvalidInput",
+            ErrorKind::Unknown => "Unknown",
+            ErrorKind::Io(_) => "Io",
+        };
+        SerialError{kind : kind.to_string(), description : error.description.clone()}.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D : Deserializer<'de>>(deserializer : D) -> Result<serialport::Error, D::Error> {
+        let error = SerialError::deserialize(deserializer)?;
+        let kind = match error.kind.as_str() {
+            "NoDevice" => ErrorKind::NoDevice,
+            "InvalidInput" => ErrorKind::InvalidInput,
+            "Io" => ErrorKind::Io(std::io::ErrorKind::Other),
+            _ => ErrorKind::Unknown,
+        };
+        Ok(serialport::Error::new(kind, error.description))
+    }
+}
+
+/// `std::io::Error` as its message, coming back with kind `Other`.
+pub(crate) mod io_error {
+    use super::*;
+
+    pub fn serialize<S : Serializer>(error : &std::io::Error, serializer : S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&error.to_string())
+    }
+
+    pub fn deserialize<'de, D : Deserializer<'de>>(deserializer : D) -> Result<std::io::Error, D::Error> {
+        Ok(std::io::Error::other(String::deserialize(deserializer)?))
+    }
+}
+
+/// `rmp_serde::encode::Error` as its message, coming back as `Syntax`.
+pub(crate) mod encode_error {
+    use super::*;
+
+    pub fn serialize<S : Serializer>(error : &rmp_serde::encode::Error, serializer : S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&error.to_string())
+    }
+
+    pub fn deserialize<'de, D : Deserializer<'de>>(deserializer : D) -> Result<rmp_serde::encode::Error, D::Error> {
+        Ok(rmp_serde::encode::Error::Syntax(String::deserialize(deserializer)?))
+    }
+}
+
+/// `rmp_serde::decode::Error` as its message, coming back as `Syntax`.
+pub(crate) mod decode_error {
+    use super::*;
+
+    pub fn serialize<S : Serializer>(error : &rmp_serde::decode::Error, serializer : S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&error.to_string())
+    }
+
+    pub fn deserialize<'de, D : Deserializer<'de>>(deserializer : D) -> Result<rmp_serde::decode::Error, D::Error> {
+        Ok(rmp_serde::decode::Error::Syntax(String::deserialize(deserializer)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CoherentError;
+
+    fn round_trip(error : &CoherentError) -> CoherentError {
+        rmp_serde::from_slice(&rmp_serde::to_vec(error).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert!(matches!(
+            round_trip(&CoherentError::SoftLimitError("GDD too large".to_string())),
+            CoherentError::SoftLimitError(message) if message == "GDD too large"
+        ));
+        assert!(matches!(round_trip(&CoherentError::LockedError), CoherentError::LockedError));
+
+        let serial = CoherentError::SerialError(serialport::Error::new(serialport::ErrorKind::NoDevice, "unplugged"));
+        match round_trip(&serial) {
+            CoherentError::SerialError(e) => {
+                assert_eq!(e.kind, serialport::ErrorKind::NoDevice);
+                assert_eq!(e.description, "unplugged");
+            },
+            other => panic!("{:?}", other),
+        }
+
+        let write = CoherentError::WriteError(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "pipe closed"));
+        match round_trip(&write) {
+            CoherentError::WriteError(e) => assert_eq!(e.to_string(), "pipe closed"),
+            other => panic!("{:?}", other),
+        }
+    }
+}
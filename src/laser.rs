@@ -8,10 +8,38 @@ use crate::CoherentError;
 #[cfg(feature = "network")]
 use serde::{Serialize, Deserialize};
 
+#[macro_use]
+mod instrument;
 pub mod discoverynx;
 pub mod debug;
+pub mod mock;
+pub mod simulator;
+pub mod ports;
+pub mod calibration;
+pub mod power_meter;
+pub mod hooks;
+pub mod lock;
+pub mod history;
+pub mod retry;
+pub mod schema;
+pub mod units;
+pub mod shared;
+pub mod sampling;
+pub mod gating;
+pub mod config;
+pub mod open;
+pub mod keepalive;
+pub mod terminal;
+pub mod transcript;
+pub mod cancel;
+pub mod snapshot;
+pub mod registry;
+pub mod command_log;
+pub mod fault_monitor;
+pub mod safety;
 
 pub use discoverynx::{Discovery, DiscoveryNXCommands, DiscoveryNXQueries, DiscoveryLaser};
+pub use units::{Nanometers, GddFs2};
 
 #[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
 /// The Coherent laser models currently supported by this library.
@@ -34,11 +62,65 @@ impl From<u16> for LaserType {
     }
 }
 
+/// Code that's generic over the `Laser` type, for a model only known at
+/// runtime -- see `LaserType::visit`.
+pub trait LaserTypeVisitor {
+    type Output;
+
+    /// Called with the `Laser` type of the model.
+    fn visit<L : Laser>(self) -> Self::Output;
+
+    /// Called for `LaserType::UnrecognizedDevice`, which has no `Laser` type.
+    fn unrecognized(self) -> Self::Output;
+}
+
+impl LaserType {
+    /// Calls `visitor` with the `Laser` type of this model. This is the one
+    /// place a `LaserType` is matched to its `Laser`, so a new model only
+    /// needs adding here.
+    pub fn visit<V : LaserTypeVisitor>(&self, visitor : V) -> V::Output {
+        match self {
+            LaserType::DiscoveryNX => visitor.visit::<Discovery>(),
+            LaserType::DebugLaser => visitor.visit::<debug::DebugLaser>(),
+            LaserType::UnrecognizedDevice => visitor.unrecognized(),
+        }
+    }
+}
+
+/// Whether the laser is on. Encoded by name, so statuses recorded before
+/// `Off` existed still read the same; a client from before it can't read a
+/// status that says `Off`, though.
 #[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum LaserState {
     Standby,
     On,
+    /// Can't be turned on as it is: the keyswitch is off, or a fault has
+    /// shut it down. Not something the laser can be told to be -- asking
+    /// for it puts it in standby, the nearest it can get over serial.
+    Off,
+}
+
+impl LaserState {
+    /// What a laser that answers `queried` to "are you on?" is really
+    /// doing: `Off` with the key off, or in standby with a fault
+    /// outstanding. A laser that's on with a fault (a warning) is still on.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use coherent_rs::laser::{LaserState, Keyswitch};
+    /// assert_eq!(LaserState::resolve(LaserState::Standby, Keyswitch::Off, false), LaserState::Off);
+    /// assert_eq!(LaserState::resolve(LaserState::Standby, Keyswitch::On, true), LaserState::Off);
+    /// assert_eq!(LaserState::resolve(LaserState::On, Keyswitch::On, true), LaserState::On);
+    /// ```
+    pub fn resolve(queried : LaserState, keyswitch : Keyswitch, faulted : bool) -> LaserState {
+        match queried {
+            _ if keyswitch == Keyswitch::Off => LaserState::Off,
+            LaserState::Standby if faulted => LaserState::Off,
+            state => state,
+        }
+    }
 }
 
 /// The state of the laser shutter.
@@ -73,6 +155,70 @@ impl std::ops::Not for ShutterState {
     }
 }
 
+/// Whether an output is in alignment mode (low power, for lining up optics).
+/// Can be coerced from `bool` with `On` being `true`. Sent over the network
+/// as a `bool`, as it was before it had a type of its own.
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "network", serde(from = "bool", into = "bool"))]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AlignmentMode {
+    On,
+    Off,
+}
+
+impl From<bool> for AlignmentMode {
+    fn from(on : bool) -> Self {
+        if on {
+            AlignmentMode::On
+        } else {
+            AlignmentMode::Off
+        }
+    }
+}
+
+impl From<AlignmentMode> for bool {
+    fn from(mode : AlignmentMode) -> Self {
+        mode == AlignmentMode::On
+    }
+}
+
+impl std::ops::Not for AlignmentMode {
+    type Output = Self;
+    fn not(self) -> Self {
+        match self {
+            AlignmentMode::On => AlignmentMode::Off,
+            AlignmentMode::Off => AlignmentMode::On,
+        }
+    }
+}
+
+/// The position of the laser's keyswitch. The laser can't be turned on
+/// with it `Off`. Can be coerced from `bool` with `On` being `true`, and
+/// sent over the network as a `bool`.
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "network", serde(from = "bool", into = "bool"))]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Keyswitch {
+    On,
+    Off,
+}
+
+impl From<bool> for Keyswitch {
+    fn from(on : bool) -> Self {
+        if on {
+            Keyswitch::On
+        } else {
+            Keyswitch::Off
+        }
+    }
+}
+
+impl From<Keyswitch> for bool {
+    fn from(keyswitch : Keyswitch) -> Self {
+        keyswitch == Keyswitch::On
+    }
+}
+
 #[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TuningStatus {
@@ -90,9 +236,9 @@ impl From<bool> for TuningStatus {
     }
 }
 
-impl Into<bool> for TuningStatus {
-    fn into(self) -> bool {
-        match self {
+impl From<TuningStatus> for bool {
+    fn from(val: TuningStatus) -> Self {
+        match val {
             TuningStatus::Tuning => true,
             TuningStatus::Ready => false,
         }
@@ -109,6 +255,132 @@ impl std::ops::Not for TuningStatus {
     }
 }
 
+/// The laser's faults, read on their own rather than out of a whole status.
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct FaultReport {
+    /// The fault code -- 0 means no faults
+    pub code : u8,
+    /// The laser's description of the faults, if it gives one
+    pub text : String,
+}
+
+impl FaultReport {
+    pub fn is_clear(&self) -> bool {
+        self.code == 0
+    }
+}
+
+/// Something a laser model can do beyond opening and closing its shutters
+/// and turning on and off.
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LaserFeature {
+    /// Setting the wavelength
+    Tuning,
+    /// Setting the GDD
+    Gdd,
+    /// Choosing between stored GDD calibration curves
+    GddCurves,
+    /// A low-power alignment mode
+    AlignmentMode,
+    /// Operating hours, temperatures and the like
+    Diagnostics,
+    /// Reporting power corrected by a `PowerCalibration`
+    PowerCalibration,
+}
+
+/// What a laser model can do, so generic tools can build their controls
+/// without knowing which model they're talking to.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::{Laser, LaserFeature, debug::DebugLaser};
+///
+/// let capabilities = DebugLaser::default().capabilities();
+/// assert_eq!(capabilities.outputs, 2);
+/// assert!(capabilities.supports(LaserFeature::Gdd));
+/// assert_eq!(capabilities.tuning_range_nm, Some((700.0, 1000.0)));
+/// ```
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[derive(Debug, PartialEq, Clone)]
+pub struct LaserCapabilities {
+    pub laser_type : LaserType,
+    /// The (shortest, longest) wavelength it tunes to, in nm. `None` if it
+    /// doesn't tune.
+    pub tuning_range_nm : Option<(f32, f32)>,
+    /// The (lowest, highest) GDD it can be set to, in fs^2. `None` if it
+    /// has no GDD control.
+    pub gdd_range_fs2 : Option<(f32, f32)>,
+    /// The number of beams, each with its own shutter
+    pub outputs : u8,
+    pub features : Vec<LaserFeature>,
+}
+
+impl LaserCapabilities {
+    pub fn supports(&self, feature : LaserFeature) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+/// A single field of a laser status with the model-specific type erased,
+/// for tooling that wants to treat every laser's status the same way.
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "network", serde(untagged))]
+#[derive(Debug, PartialEq, Clone)]
+pub enum StatusValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+    /// A field with no value, like the calibrated power of an uncalibrated output.
+    Missing,
+}
+
+/// Operations every laser supports, for tools that need to act on
+/// whatever laser is connected without model-specific code. Each model's
+/// `CommandEnum` converts from these with `TryFrom`, failing with
+/// `CoherentError::InvalidArgumentsError` if the model can't do it.
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CommonCommand {
+    /// Open the shutter of the laser's main beam.
+    OpenShutter,
+    /// Close the shutter of the laser's main beam.
+    CloseShutter,
+    Standby,
+    On,
+    ClearFaults,
+    /// Tune the main beam to this wavelength, in nm.
+    SetWavelength(f32),
+}
+
+/// Accessors shared by every model's status struct, so monitoring code
+/// can work with any laser without knowing its status layout.
+pub trait LaserStatus {
+    /// The laser's fault code -- 0 means no faults.
+    fn faults(&self) -> u8;
+
+    /// Whether light is leaving the laser, i.e. it's on and at least one
+    /// shutter is open.
+    fn is_emitting(&self) -> bool;
+
+    /// Output power of the laser's main beam, in mW.
+    fn primary_power(&self) -> f32;
+
+    /// When the status was read from the laser.
+    fn timestamp(&self) -> std::time::SystemTime;
+}
+
+/// Seconds since the Unix epoch, as stored in status structs' `timestamp` field.
+pub fn unix_timestamp() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
 pub trait LaserCommand : Sized {
     fn to_string(&self) -> String;
 }
@@ -128,23 +400,38 @@ pub trait Query : LaserCommand{
 
 /// Coherent Lasers operate using two types of commands:
 /// * Commands - These are commands that are sent to the laser
-/// to change its state or configuration.
+///   to change its state or configuration.
 /// 
 /// * Queries - These are commands that are sent to the laser
-/// to request information about its state or configuration.
+///   to request information about its state or configuration.
 /// 
 /// Lasers implement `Send` to allow for threading and 
 /// Network interfaces.
 pub trait Laser: Into<LaserType> + Send {
 
     #[cfg(feature = "network")]
-    type CommandEnum : LaserCommand + Serialize + Deserialize<'static> + core::fmt::Debug;
+    type CommandEnum : LaserCommand + Serialize + serde::de::DeserializeOwned + core::fmt::Debug + Send + 'static
+        + TryFrom<CommonCommand, Error = CoherentError>;
 
     #[cfg(not(feature = "network"))]
-    type CommandEnum : LaserCommand + core::fmt::Debug;
+    type CommandEnum : LaserCommand + core::fmt::Debug + Send + 'static
+        + TryFrom<CommonCommand, Error = CoherentError>;
 
     #[cfg(feature = "network")]
-    type LaserStatus: Serialize + Deserialize<'static> + core::fmt::Debug; // for status communication over serial
+    type LaserStatus: LaserStatus + Serialize + serde::de::DeserializeOwned + core::fmt::Debug; // for status communication over serial
+
+    #[cfg(not(feature = "network"))]
+    type LaserStatus: LaserStatus + core::fmt::Debug;
+
+    /// The laser's soft limits, in whatever shape its `CommandEnum` needs:
+    /// `discoverynx::limits::SoftLimits` for `DiscoveryNXCommands`.
+    type Limits : 'static;
+
+    /// The (shortest, longest) time a `NetworkLaserServer` may wait between
+    /// status reads. Polling faster than the laser can answer starves
+    /// clients' commands of it.
+    const POLLING_INTERVAL_BOUNDS : (std::time::Duration, std::time::Duration) =
+        (std::time::Duration::from_millis(200), std::time::Duration::from_secs(3600));
 
     /// Create a new instance of the laser by opening a
     /// serial connection to the specified port. If no port
@@ -156,22 +443,25 @@ pub trait Laser: Into<LaserType> + Send {
     /// # Arguments
     /// 
     /// * `port` - The serial port to connect to. If `None`, the
-    /// function will attempt to automatically detect the laser
-    /// by scanning all available serial ports. If it cannot find
-    /// the laser on a comm port, it will scan for USB devices.
+    ///   function will attempt to automatically detect the laser
+    ///   by scanning all available serial ports. If it cannot find
+    ///   the laser on a comm port, it will scan for USB devices.
+    ///   Names are matched as in `from_port_name`.
     /// 
     /// * `serial_number` - The serial number of the laser to connect to.
-    /// If this is specified and `port` is specified, the serial number will be
-    /// checked against the laser connected to the specified port.
+    ///   If this is specified and `port` is specified, the serial number will be
+    ///   checked against the laser connected to the specified port.
     /// 
     /// # Returns
     /// 
     /// A `Result` containing the laser object if successful, or a `CoherentError` if not.
+    /// `SerialError` if the system's serial ports can't be listed (e.g. for
+    /// lack of permissions, or under WSL).
     /// 
     /// # Examples
     /// 
-    /// ```rust
-    /// use coherent_rs::{Discovery};
+    /// ```no_run
+    /// use coherent_rs::{Discovery, laser::Laser};
     /// 
     /// // Open a specific port, but it doesn't exist
     /// let discovery = Discovery::new(Some("NotAPort"), None);
@@ -190,15 +480,19 @@ pub trait Laser: Into<LaserType> + Send {
     /// let discovery = Discovery::new(Some("COM5"), Some("123456")).unwrap();
     /// ```
     fn new(port_name : Option<&str>, serial_number : Option<&str>) -> Result<Self, CoherentError>{
+        Self::open_with(port_name, serial_number, &open::LaserOpenOptions::default())
+    }
+
+    /// `new`, opening the port with `options` rather than the defaults.
+    /// See `open::LaserOpenOptions::open` for a builder-style way in.
+    fn open_with(port_name : Option<&str>, serial_number : Option<&str>, options : &open::LaserOpenOptions)
+        -> Result<Self, CoherentError> {
         if let Some(name) = port_name {
-            let port_info = serialport::available_ports()?
-                .into_iter()
-                .find(|port| port.port_name == name)
-                .ok_or(CoherentError::UnrecognizedDevice)?;
+            let port_info = ports::find_port(name)?;
     
             if let Some(serial) = serial_number {
                 if let serialport::SerialPortType::UsbPort(info) = &port_info.port_type {
-                    if info.serial_number.as_deref() != Some(&serial) {
+                    if info.serial_number.as_deref() != Some(serial) {
                         return Err(CoherentError::UnrecognizedDevice);
                     }
                 } else {
@@ -206,27 +500,25 @@ pub trait Laser: Into<LaserType> + Send {
                 }
             }
     
-            return Self::from_port_info(&port_info);
+            return Self::from_port_info_with(&port_info, options);
         }
     
         if let Some(serial) = serial_number {
-            let port_info = serialport::available_ports()?
+            let port_info = ports::available_ports()?
                 .into_iter()
-                .find(|port| Self::is_valid_device(port))
+                .find(|port| Self::is_valid_device(port) && match &port.port_type {
+                    serialport::SerialPortType::UsbPort(info) => info.serial_number.as_deref() == Some(serial),
+                    _ => false,
+                })
                 .ok_or(CoherentError::UnrecognizedDevice)?;
     
-            if let serialport::SerialPortType::UsbPort(info) = &port_info.port_type {
-                if info.serial_number.as_deref() != Some(&serial) {
-                    return Err(CoherentError::UnrecognizedDevice);
-                }
-            } else {
-                return Err(CoherentError::UnrecognizedDevice);
-            }
-    
-            return Self::from_port_info(&port_info);
+            return Self::from_port_info_with(&port_info, options);
         }
     
-        Self::find_first()
+        let port_info = ports::available_ports()?.into_iter().find(|port| {
+            Self::is_valid_device(port)
+        }).ok_or(CoherentError::NoRecognizedLasers)?;
+        Self::from_port_info_with(&port_info, options)
     }
 
     /// Send a command to the laser directly over the serial port. Maybe I shouldn't expose this in the trait??
@@ -240,20 +532,29 @@ pub trait Laser: Into<LaserType> + Send {
     /// Create a new instance of the laser from a `SerialPortInfo` object
     /// specifying where to access the laser.
     fn from_port_info(serialportinfo : &serialport::SerialPortInfo) -> Result<Self, CoherentError>;
+
+    /// `from_port_info` with the port opened as `options` say. Lasers that
+    /// don't take options ignore them.
+    fn from_port_info_with(serialportinfo : &serialport::SerialPortInfo, _options : &open::LaserOpenOptions)
+        -> Result<Self, CoherentError> {
+        Self::from_port_info(serialportinfo)
+    }
     
-    /// Create a new instance of the laser from a port name.
+    /// Create a new instance of the laser from a port name. On Windows this
+    /// may be `COM12`, `\\.\COM12`, or a USB device instance path like
+    /// `USB\VID_0D4D&PID_0204\<serial>` (see `ports::find_port`).
+    /// `SerialError` if the system's serial ports can't be listed.
     fn from_port_name(port_name : &str) -> Result<Self, CoherentError> {
-        let port_info = serialport::available_ports().unwrap().into_iter().filter(|port| {
-            port.port_name == port_name
-        }).next().ok_or(CoherentError::UnrecognizedDevice)?;
+        let port_info = ports::find_port(port_name)?;
         Self::from_port_info(&port_info)
     }
 
     /// Find the first instance of a laser of the class on any available port.
+    /// `SerialError` if the system's serial ports can't be listed.
     fn find_first() -> Result<Self, CoherentError> {
-        let port_info = serialport::available_ports().unwrap().into_iter().filter(|port| {
+        let port_info = ports::available_ports()?.into_iter().find(|port| {
             Self::is_valid_device(port)
-        }).next().ok_or(CoherentError::NoRecognizedLasers)?;
+        }).ok_or(CoherentError::NoRecognizedLasers)?;
         Self::from_port_info(&port_info)
     }
 
@@ -266,8 +567,29 @@ pub trait Laser: Into<LaserType> + Send {
     /// Send a query to the laser that expects a response
     fn query<Q : Query>(&mut self, query : Q) -> Result<Q::Result, CoherentError>;
 
+    /// Runs the checks `send_command` would run before touching the
+    /// laser -- the operator lock, soft limits, anything else known without
+    /// asking it -- and returns the serial commands it would then write, in
+    /// order, without writing anything. Fails with the error `send_command`
+    /// would. By default there are no checks, and just the one command.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use coherent_rs::{CoherentError, DiscoveryNXCommands, Nanometers};
+    /// use coherent_rs::laser::{Laser, debug::DebugLaser};
+    ///
+    /// let mut laser = DebugLaser::default();
+    /// let command = DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)};
+    /// assert_eq!(laser.validate(&command).unwrap(), vec!["WV=800".to_string()]);
+    /// laser.lock("alice").unwrap();
+    /// assert!(matches!(laser.validate(&command), Err(CoherentError::LockedError)));
+    /// ```
+    fn validate(&mut self, command : &Self::CommandEnum) -> Result<Vec<String>, CoherentError> {
+        Ok(vec![command.to_string()])
+    }
+
     /// Returns a struct containing the current status of the laser
-    #[cfg(feature = "network")]
     fn status(&mut self) -> Result<Self::LaserStatus, CoherentError>;
     
     /// Executes all of the desired queries and returns them
@@ -276,6 +598,130 @@ pub trait Laser: Into<LaserType> + Send {
     #[cfg(feature = "network")]
     fn serialized_status(&mut self) -> Result<Vec<u8>, CoherentError>;
 
+    /// Sends one of the operations all lasers share, translated
+    /// into this model's command.
+    fn send_common_command(&mut self, command : CommonCommand) -> Result<(), CoherentError> {
+        self.send_command(command.try_into()?)
+    }
+
+    /// Sends `command` to the laser as it is, for when it has to go out
+    /// whatever the laser is thought to be doing -- e.g. a `SafetyPolicy`
+    /// closing a shutter. Lasers that might otherwise skip, change or
+    /// expand a command (skip-unchanged checks, tuning hooks, presets, soft
+    /// limits) leave all that out here. By default just `send_command`.
+    fn force_command(&mut self, command : Self::CommandEnum) -> Result<(), CoherentError> {
+        self.send_command(command)
+    }
+
+    /// The laser's `OperatorLock`, if it has one. Lets a `NetworkLaserServer`
+    /// lock and unlock it on behalf of a client.
+    fn operator_lock(&mut self) -> Option<&mut lock::OperatorLock> {
+        None
+    }
+
+    /// The laser's `ParameterHistory`, if it's keeping one. Lets a
+    /// `NetworkLaserServer` attribute changes to the client that made them.
+    fn parameter_history(&mut self) -> Option<&mut history::ParameterHistory> {
+        None
+    }
+
+    /// The laser's soft limits, if it has them. Lets a `NetworkLaserServer`
+    /// apply the limits in its config file.
+    fn soft_limits(&mut self) -> Option<&mut Self::Limits> {
+        None
+    }
+
+    /// Applies the named set of settings in the laser's presets (see
+    /// `discoverynx::presets`), waiting for it to finish tuning before the
+    /// settings that follow. Fails with `InvalidArgumentsError` if there's no
+    /// such preset, or the laser doesn't keep any.
+    fn apply_preset(&mut self, name : &str) -> Result<(), CoherentError> {
+        Err(CoherentError::InvalidArgumentsError(format!("No preset named `{}`: this laser has no presets", name)))
+    }
+
+    /// The settings the laser is at -- wavelength, shutters and so on -- to
+    /// save and `restore` after a restart. Fails with
+    /// `CommandNotExecutedError` for lasers that can't.
+    fn snapshot(&mut self) -> Result<snapshot::LaserSnapshot, CoherentError> {
+        Err(CoherentError::CommandNotExecutedError)
+    }
+
+    /// Puts back the settings in `snapshot`, waiting for the laser to finish
+    /// tuning before the settings that follow. Settings left out of it (see
+    /// `LaserSnapshot::without`) are left alone. Fails with
+    /// `CommandNotExecutedError` for lasers that can't.
+    fn restore(&mut self, _snapshot : &snapshot::LaserSnapshot) -> Result<(), CoherentError> {
+        Err(CoherentError::CommandNotExecutedError)
+    }
+
+    /// Sends `command` exactly as written and returns the laser's reply as
+    /// it came, without the line ending -- for debugging firmware. Skips
+    /// the soft limits, the operator lock and the parameter history. Fails
+    /// with `CommandNotExecutedError` for lasers that can't.
+    fn raw_command(&mut self, _command : &str) -> Result<String, CoherentError> {
+        Err(CoherentError::CommandNotExecutedError)
+    }
+
+    /// Tries to get the serial link working again after a command that
+    /// hung, before anything else is sent -- e.g. by reopening the port. A
+    /// `NetworkLaserServer` calls it when a command it gave up on finally
+    /// returns. By default does nothing.
+    fn recover_link(&mut self) -> Result<(), CoherentError> {
+        Ok(())
+    }
+
+    /// The laser's current faults. By default read from a whole `status`,
+    /// without any text.
+    fn fault_report(&mut self) -> Result<FaultReport, CoherentError> {
+        Ok(FaultReport{code : self.status()?.faults(), text : String::new()})
+    }
+
+    /// Whether the laser is tuning. Lasers that don't tune are always `Ready`.
+    fn tuning_status(&mut self) -> Result<TuningStatus, CoherentError> {
+        Ok(TuningStatus::Ready)
+    }
+
+    /// Checks the tuning status every `poll_interval` until the laser is
+    /// `Ready`, or returns `TimeoutError` after `timeout` (or `Cancelled`
+    /// once the `cancel_token` is cancelled).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use coherent_rs::{Discovery, laser::Laser};
+    ///
+    /// let mut discovery = Discovery::find_first().unwrap();
+    /// discovery.set_wavelength(1040.0).unwrap();
+    /// discovery.wait_for_tuning(Duration::from_secs(30), Duration::from_millis(100)).unwrap();
+    /// ```
+    fn wait_for_tuning(&mut self, timeout : std::time::Duration, poll_interval : std::time::Duration)
+        -> Result<(), CoherentError> {
+        let deadline = retry::Deadline::after(timeout);
+        let cancellation = self.cancel_token().map(|token| token.watch());
+        while self.tuning_status()? == TuningStatus::Tuning {
+            deadline.check()?;
+            cancellation.as_ref().map_or(Ok(()), |cancellation| cancellation.check())?;
+            std::thread::sleep(deadline.cap(poll_interval));
+        }
+        Ok(())
+    }
+
+    /// Stops this laser's blocking helpers (`wait_for_tuning`, sweeps...)
+    /// from another thread; see `cancel`. `None` for lasers that can't.
+    fn cancel_token(&self) -> Option<cancel::CancelToken> {
+        None
+    }
+
+    /// How far the latest wavelength change has got, `None` if there hasn't
+    /// been one. Lasers that don't tune never have one.
+    fn tuning_progress(&mut self) -> Result<Option<discoverynx::progress::TuningProgress>, CoherentError> {
+        Ok(None)
+    }
+
+    /// What this model can do: its ranges, outputs and optional features.
+    fn capabilities(&self) -> LaserCapabilities;
+
     fn into_laser_type() -> LaserType;
 }
 
@@ -290,9 +736,37 @@ mod tests {
         assert_eq!(ShutterState::Closed, ShutterState::from(false));
     }
 
+    #[test]
+    fn test_laser_type_visit() {
+        struct TypeOf;
+        impl LaserTypeVisitor for TypeOf {
+            type Output = Option<LaserType>;
+            fn visit<L : Laser>(self) -> Self::Output { Some(L::into_laser_type()) }
+            fn unrecognized(self) -> Self::Output { None }
+        }
+
+        for laser_type in [LaserType::DiscoveryNX, LaserType::DebugLaser] {
+            assert_eq!(laser_type.visit(TypeOf), Some(laser_type));
+        }
+        assert_eq!(LaserType::UnrecognizedDevice.visit(TypeOf), None);
+    }
+
+    #[test]
+    fn test_alignment_and_keyswitch_from_bool() {
+        assert_eq!(AlignmentMode::On, AlignmentMode::from(true));
+        assert_eq!(AlignmentMode::Off, !AlignmentMode::On);
+        assert!(!bool::from(AlignmentMode::Off));
+        assert_eq!(Keyswitch::Off, Keyswitch::from(false));
+        assert!(bool::from(Keyswitch::On));
+    }
+
     #[test]
     fn print_available_ports(){
-        let ports = serialport::available_ports().unwrap();
+        // Listing ports can fail in a sandbox, or under WSL
+        let ports = match ports::available_ports() {
+            Ok(ports) => ports,
+            Err(e) => { eprintln!("Could not list ports: {:?}", e); return; },
+        };
         for port in ports {
             println!("{:?}", port);
             match port.port_type {
@@ -329,4 +803,26 @@ mod tests {
         assert_eq!(laser_type, laser_type_deserialized);
     }
 
+    /// Still plain booleans on the wire, so older peers can read them
+    #[cfg(feature = "network")]
+    #[test]
+    fn test_serde_alignment_and_keyswitch(){
+        let mut buf = Vec::new();
+        AlignmentMode::On.serialize(&mut rmp_serde::Serializer::new(&mut buf)).unwrap();
+        assert_eq!(buf, rmp_serde::to_vec(&true).unwrap());
+        assert_eq!(Keyswitch::deserialize(&mut rmp_serde::Deserializer::new(&rmp_serde::to_vec(&false).unwrap()[..])).unwrap(), Keyswitch::Off);
+    }
+
+    #[cfg(feature = "network")]
+    #[test]
+    fn test_serde_laser_state(){
+        use rmp_serde::Serializer;
+
+        for state in [LaserState::Standby, LaserState::On, LaserState::Off] {
+            let mut buf = Vec::new();
+            state.serialize(&mut Serializer::new(&mut buf)).unwrap();
+            assert_eq!(LaserState::deserialize(&mut rmp_serde::Deserializer::new(&buf[..])).unwrap(), state);
+        }
+    }
+
 }
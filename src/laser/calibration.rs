@@ -0,0 +1,121 @@
+//! calibration.rs
+//!
+//! The laser reports power at its own output, but what reaches the sample
+//! depends on the rig (optics, objective transmission, ...). A
+//! `PowerCalibration` maps the laser's raw readout to the power measured
+//! where it matters, e.g. after the objective.
+
+use crate::CoherentError;
+use crate::laser::config;
+
+/// A linear map from the laser's raw power readout (mW) to the power
+/// measured elsewhere on the rig: `scale * raw + offset_mw`.
+#[cfg_attr(feature = "network", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerCalibration {
+    pub scale : f32,
+    pub offset_mw : f32,
+}
+
+impl Default for PowerCalibration {
+    /// The identity calibration -- reports the raw value.
+    fn default() -> Self {
+        PowerCalibration{scale : 1.0, offset_mw : 0.0}
+    }
+}
+
+impl PowerCalibration {
+    pub fn new(scale : f32, offset_mw : f32) -> Self {
+        PowerCalibration{scale, offset_mw}
+    }
+
+    /// The calibrated power for a raw readout of `raw_mw`.
+    pub fn apply(&self, raw_mw : f32) -> f32 {
+        self.scale * raw_mw + self.offset_mw
+    }
+}
+
+/// Reads a calibration file: one `<output> <scale> <offset_mw>` line per
+/// laser output, with `#` starting a comment. What the output names mean is
+/// up to the laser (the Discovery uses `variable` and `fixed`).
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::calibration::{parse_power_calibrations, PowerCalibration};
+///
+/// let calibrations = parse_power_calibrations(
+///     "# measured after the 25x objective\nvariable 0.42 -1.5\nfixed 0.8 0\n"
+/// ).unwrap();
+/// assert_eq!(calibrations[0], ("variable".to_string(), PowerCalibration::new(0.42, -1.5)));
+/// ```
+pub fn parse_power_calibrations(text : &str) -> Result<Vec<(String, PowerCalibration)>, CoherentError> {
+    let mut calibrations = Vec::new();
+    let mut errors = Vec::new();
+    for (line_number, line) in config::content_lines(text) {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let [output, scale, offset] = fields.as_slice() else {
+            errors.push(config::ConfigError::new(line_number, "line", "`<output> <scale> <offset_mw>`", line)
+                .suggest("e.g. `variable 0.42 -1.5`"));
+            continue;
+        };
+        let scale = config::number(line_number, "scale", scale, "a number", None)
+            .and_then(|scale| if scale > 0.0 { Ok(scale) } else {
+                Err(config::ConfigError::new(line_number, "scale", "positive", &scale.to_string())
+                    .suggest("the scale is measured power over the laser's readout"))
+            });
+        let offset = config::number(line_number, "offset_mw", offset, "a power in mW", None);
+        match (scale, offset) {
+            (Ok(scale), Ok(offset)) => calibrations.push((output.to_string(), PowerCalibration::new(scale, offset))),
+            (scale, offset) => errors.extend(scale.err().into_iter().chain(offset.err())),
+        }
+    }
+    config::report(calibrations, errors)
+}
+
+/// Reads and parses a calibration file (see `parse_power_calibrations`).
+pub fn load_power_calibrations<P : AsRef<std::path::Path>>(path : P)
+    -> Result<Vec<(String, PowerCalibration)>, CoherentError> {
+    config::load(path, "calibration file", parse_power_calibrations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(PowerCalibration::default().apply(123.0), 123.0);
+        assert_eq!(PowerCalibration::new(0.5, -10.0).apply(100.0), 40.0);
+    }
+
+    #[test]
+    fn test_parse_power_calibrations() {
+        let calibrations = parse_power_calibrations(
+            "# rig 2\n\nvariable 0.5 -10 # after objective\n  fixed 0.25 0\n"
+        ).unwrap();
+        assert_eq!(calibrations, vec![
+            ("variable".to_string(), PowerCalibration::new(0.5, -10.0)),
+            ("fixed".to_string(), PowerCalibration::new(0.25, 0.0)),
+        ]);
+
+        assert!(parse_power_calibrations("").unwrap().is_empty());
+        assert!(matches!(
+            parse_power_calibrations("variable 0.5"),
+            Err(CoherentError::InvalidArgumentsError(_))
+        ));
+        assert!(matches!(
+            parse_power_calibrations("variable half 0"),
+            Err(CoherentError::InvalidArgumentsError(_))
+        ));
+        assert!(parse_power_calibrations("variable -0.5 0").is_err());
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        assert!(matches!(
+            load_power_calibrations("/definitely/not/a/calibration.txt"),
+            Err(CoherentError::InvalidArgumentsError(_))
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+//! cancel.rs
+//!
+//! Stopping a long operation part way through -- a tune that's waiting for
+//! the laser to settle, a GDD sweep -- from another thread, e.g. a GUI's
+//! Stop button, rather than waiting for it to finish. Each laser that
+//! supports it hands out clones of its `CancelToken` (`Laser::cancel_token`);
+//! `cancel` stops every blocking helper that's running on the laser at the
+//! time with `CoherentError::Cancelled`, and nothing started afterwards.
+
+use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
+use std::time::{Duration, Instant};
+
+use crate::CoherentError;
+
+/// How often `Cancellation::sleep` checks whether it's been cancelled.
+const CHECK_INTERVAL : Duration = Duration::from_millis(20);
+
+/// Cancels the operations watching it. Clones share the same operations.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::{CoherentError, laser::cancel::CancelToken};
+///
+/// let token = CancelToken::new();
+/// let tuning = token.watch();
+/// token.clone().cancel();
+/// assert!(matches!(tuning.check(), Err(CoherentError::Cancelled)));
+/// // Only what was running at the time
+/// assert!(token.watch().check().is_ok());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancels : Arc<AtomicU64>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    /// Stops every operation watching the token now.
+    pub fn cancel(&self) {
+        self.cancels.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Starts watching for `cancel`, for one operation.
+    pub fn watch(&self) -> Cancellation {
+        Cancellation{token : self.clone(), since : self.cancels.load(Ordering::SeqCst)}
+    }
+}
+
+/// One operation's view of a `CancelToken`: cancelled once the token's
+/// cancelled after it started watching.
+#[derive(Debug, Clone)]
+pub struct Cancellation {
+    token : CancelToken,
+    since : u64,
+}
+
+impl Cancellation {
+    pub fn is_cancelled(&self) -> bool {
+        self.token.cancels.load(Ordering::SeqCst) != self.since
+    }
+
+    /// `Cancelled` once it's cancelled.
+    pub fn check(&self) -> Result<(), CoherentError> {
+        if self.is_cancelled() { Err(CoherentError::Cancelled) } else { Ok(()) }
+    }
+
+    /// Sleeps for `duration`, or until it's cancelled, with `Cancelled`.
+    pub fn sleep(&self, duration : Duration) -> Result<(), CoherentError> {
+        let until = Instant::now() + duration;
+        loop {
+            self.check()?;
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(());
+            }
+            std::thread::sleep(left.min(CHECK_INTERVAL));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel() {
+        let token = CancelToken::new();
+        let (first, second) = (token.watch(), token.watch());
+        assert!(!first.is_cancelled());
+        first.sleep(Duration::from_millis(30)).unwrap();
+
+        let canceller = token.clone();
+        let started = Instant::now();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+        assert!(matches!(first.sleep(Duration::from_secs(10)), Err(CoherentError::Cancelled)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(second.is_cancelled());
+        assert!(!token.watch().is_cancelled());
+    }
+}
@@ -0,0 +1,143 @@
+//! command_log.rs
+//!
+//! A log of every command sent to the laser, whether or not it worked: when
+//! it was sent, what it was, how long it took and how it went. Kept in
+//! memory, and optionally appended to a file, for working out afterwards
+//! how the laser ended up in an odd state. Where a `ParameterHistory` has
+//! the settings that changed, this has every attempt, refused ones too.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::CoherentError;
+
+/// One command and how it went.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandRecord {
+    pub timestamp : f64, // seconds since the Unix epoch, when it was sent
+    /// As it's written to the port, e.g. `WV=920`
+    pub command : String,
+    pub duration : Duration,
+    /// The error, as text, if it failed
+    pub result : Result<(), String>,
+}
+
+impl std::fmt::Display for CommandRecord {
+    /// A line of the log file: tab-separated `timestamp seconds command
+    /// result`, the result being `ok` or the error.
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let result = match &self.result {
+            Ok(()) => "ok".to_string(),
+            // Tabs and newlines would break up the line
+            Err(e) => e.replace(['\t', '\n', '\r'], " "),
+        };
+        write!(f, "{}\t{:.3}\t{}\t{}", self.timestamp, self.duration.as_secs_f64(), self.command, result)
+    }
+}
+
+/// The commands sent to a laser, oldest first.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::command_log::CommandLog;
+/// use coherent_rs::CoherentError;
+///
+/// let mut log = CommandLog::default();
+/// log.record("WV=920", std::time::Duration::from_millis(20), &Ok(()));
+/// log.record("GDD=500", std::time::Duration::from_millis(5), &Err(CoherentError::SoftLimitError("GDD".to_string())));
+/// assert_eq!(log.last(1)[0].command, "GDD=500");
+/// assert!(log.last(1)[0].result.is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct CommandLog {
+    records : Vec<CommandRecord>,
+    persist_path : Option<PathBuf>,
+    /// The most commands kept in memory; the oldest are dropped first.
+    /// Defaults to 1,000. The file, if any, keeps everything.
+    pub capacity : usize,
+}
+
+impl Default for CommandLog {
+    fn default() -> Self {
+        CommandLog{records : Vec::new(), persist_path : None, capacity : 1_000}
+    }
+}
+
+impl CommandLog {
+    /// A log that also appends every command to the file at `path`, one
+    /// line each (see `CommandRecord`'s `Display`).
+    pub fn persisted<P : AsRef<Path>>(path : P) -> Self {
+        CommandLog{persist_path : Some(path.as_ref().to_path_buf()), ..Default::default()}
+    }
+
+    /// Records `command` having been sent now, `duration` ago. The record is
+    /// kept in memory even if writing it to the file fails.
+    pub fn record(&mut self, command : &str, duration : Duration, result : &Result<(), CoherentError>) {
+        let record = CommandRecord{
+            timestamp : crate::laser::unix_timestamp() - duration.as_secs_f64(),
+            command : command.to_string(),
+            duration,
+            result : result.as_ref().map(|_| ()).map_err(|e| format!("{:?}", e)),
+        };
+        if let Some(path) = &self.persist_path {
+            let written = std::fs::OpenOptions::new().create(true).append(true).open(path)
+                .and_then(|mut file| writeln!(file, "{}", record));
+            if let Err(e) = written {
+                eprintln!("Could not write command log {}: {}", path.display(), e);
+            }
+        }
+        self.records.push(record);
+        if self.records.len() > self.capacity {
+            let excess = self.records.len() - self.capacity;
+            self.records.drain(..excess);
+        }
+    }
+
+    pub fn records(&self) -> &[CommandRecord] {
+        &self.records
+    }
+
+    /// The last `n` commands (or all of them, if there are fewer), oldest
+    /// first.
+    pub fn last(&self, n : usize) -> &[CommandRecord] {
+        &self.records[self.records.len().saturating_sub(n)..]
+    }
+
+    /// Forgets the commands in memory. A persisted file is left alone.
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log() {
+        let path = std::env::temp_dir().join(format!("coherent-commands-{}.tsv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut log = CommandLog::persisted(&path);
+        log.capacity = 2;
+        log.record("WV=920", Duration::from_millis(250), &Ok(()));
+        log.record("S=1", Duration::ZERO, &Err(CoherentError::LockedError));
+        log.record("GDD=-5000", Duration::ZERO, &Ok(()));
+
+        assert_eq!(log.records().iter().map(|record| record.command.as_str()).collect::<Vec<_>>(), vec!["S=1", "GDD=-5000"]);
+        assert_eq!(log.last(1)[0].command, "GDD=-5000");
+        assert_eq!(log.last(10).len(), 2);
+        assert_eq!(log.records()[0].result, Err("LockedError".to_string()));
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("\t0.250\tWV=920\tok"), "{}", lines[0]);
+        assert!(lines[1].ends_with("\tS=1\tLockedError"), "{}", lines[1]);
+
+        log.clear();
+        assert!(log.last(1).is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+//! config.rs
+//!
+//! Checking the rig files that lab staff edit by hand -- power calibrations,
+//! wavelength profiles, beam roles. Every problem in a file is reported at
+//! once, each with its line, the setting, what was expected, and where
+//! possible a likely fix, so a typo doesn't take a round trip through a
+//! Rust developer.
+
+use crate::CoherentError;
+
+/// One problem in a rig file.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::CoherentError;
+/// use coherent_rs::laser::discoverynx::profile::WavelengthProfile;
+///
+/// let Err(CoherentError::InvalidArgumentsError(message)) = WavelengthProfile::parse("800 gdd=-6000\n920 gd=-4000\n")
+///     else { panic!() };
+/// assert_eq!(message, "line 2: `gd` should be one of gdd, curve, align, found `gd=-4000` -- did you mean `gdd`?");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    /// Counting from 1
+    pub line : usize,
+    /// The setting, or the column for files without named settings
+    pub key : String,
+    pub expected : String,
+    pub found : String,
+    pub suggestion : Option<String>,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: `{}` should be {}, found `{}`", self.line, self.key, self.expected, self.found)?;
+        match &self.suggestion {
+            Some(suggestion) => write!(f, " -- {}", suggestion),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ConfigError {
+    pub fn new(line : usize, key : &str, expected : &str, found : &str) -> Self {
+        ConfigError{
+            line,
+            key : key.to_string(),
+            expected : expected.to_string(),
+            found : found.to_string(),
+            suggestion : None,
+        }
+    }
+
+    pub fn suggest(mut self, suggestion : impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+
+    /// Suggests the one of `candidates` `found` was probably meant to be.
+    pub fn suggest_closest(self, found : &str, candidates : &[&str]) -> Self {
+        match closest(found, candidates) {
+            Some(candidate) => self.suggest(format!("did you mean `{}`?", candidate)),
+            None => self,
+        }
+    }
+}
+
+/// The lines of a rig file that have something on them, numbered from 1,
+/// with `#` comments and surrounding whitespace removed.
+pub fn content_lines(text : &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+/// `value` as a finite number, within `range` if there is one. `what`
+/// describes it for the error, e.g. "a wavelength in nm".
+pub fn number(line : usize, key : &str, value : &str, what : &str, range : Option<(f32, f32)>)
+    -> Result<f32, ConfigError> {
+    let expected = match range {
+        Some((low, high)) => format!("{} between {} and {}", what, low, high),
+        None => what.to_string(),
+    };
+    let parsed = value.parse::<f32>().ok().filter(|parsed| parsed.is_finite())
+        .ok_or_else(|| ConfigError::new(line, key, &expected, value))?;
+    match range {
+        Some((low, high)) if parsed < low || parsed > high => {
+            let nearest = parsed.clamp(low, high);
+            Err(ConfigError::new(line, key, &expected, value).suggest(format!("the nearest allowed is {}", nearest)))
+        },
+        _ => Ok(parsed),
+    }
+}
+
+/// The one of `candidates` closest to `word`, if it's close enough to be a
+/// typo: at most two edits, and fewer than the word has letters.
+pub fn closest<'a>(word : &str, candidates : &[&'a str]) -> Option<&'a str> {
+    let word = word.to_lowercase();
+    candidates.iter()
+        .map(|candidate| (edit_distance(&word, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= 2 && *distance < word.chars().count())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a : &str, b : &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// `parsed` if there were no `errors`, otherwise every error, one per line,
+/// as an `InvalidArgumentsError`.
+pub fn report<T>(parsed : T, errors : Vec<ConfigError>) -> Result<T, CoherentError> {
+    if errors.is_empty() { return Ok(parsed); }
+    Err(CoherentError::InvalidArgumentsError(
+        errors.iter().map(ConfigError::to_string).collect::<Vec<_>>().join("\n")
+    ))
+}
+
+/// Reads the rig file at `path` with `parse`, naming the file in any error.
+/// `what` describes the file, e.g. "profile".
+pub fn load<T, P : AsRef<std::path::Path>>(path : P, what : &str, parse : impl FnOnce(&str) -> Result<T, CoherentError>)
+    -> Result<T, CoherentError> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|e| CoherentError::InvalidArgumentsError(
+        format!("Could not read {} {}: {}", what, path.display(), e)
+    ))?;
+    parse(&text).map_err(|e| match e {
+        CoherentError::InvalidArgumentsError(problems) => CoherentError::InvalidArgumentsError(
+            format!("In {} {}:\n{}", what, path.display(), problems)
+        ),
+        e => e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closest() {
+        assert_eq!(closest("varaible", &["variable", "fixed"]), Some("variable"));
+        assert_eq!(closest("Fixed", &["variable", "fixed"]), Some("fixed"));
+        assert_eq!(closest("power", &["gdd", "curve", "align"]), None);
+        assert_eq!(closest("x", &["gdd", "curve", "align"]), None);
+        assert_eq!(edit_distance("curv", "curve"), 1);
+    }
+
+    #[test]
+    fn test_number() {
+        assert_eq!(number(1, "gdd", "-6000", "a GDD in fs^2", None), Ok(-6000.0));
+        assert!(number(1, "gdd", "NaN", "a GDD in fs^2", None).is_err());
+        let error = number(4, "wavelength", "1400", "a wavelength in nm", Some((660.0, 1320.0))).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 4: `wavelength` should be a wavelength in nm between 660 and 1320, found `1400` \
+                -- the nearest allowed is 1320"
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        assert_eq!(report(3, Vec::new()).unwrap(), 3);
+        let errors = vec![ConfigError::new(1, "a", "b", "c"), ConfigError::new(2, "d", "e", "f")];
+        match report((), errors) {
+            Err(CoherentError::InvalidArgumentsError(message)) => assert_eq!(message.lines().count(), 2),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(content_lines("# header\n\n  a b # c\n").collect::<Vec<_>>(), vec![(3, "a b")]);
+    }
+}
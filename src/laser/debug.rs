@@ -5,9 +5,81 @@
 use serde::Serialize;
 
 use crate::{CoherentError, Laser};
-use crate::laser::discoverynx::{DiscoveryNXCommands, DiscoveryNXStatus, DiscoveryLaser};
-use crate::laser::{Query, LaserState, ShutterState, LaserType, TuningStatus};
+use crate::laser::discoverynx::{DiscoveryNXCommands, DiscoveryLaser, DiscoveryPowerCalibration, FEATURES};
+use crate::laser::calibration::PowerCalibration;
+use crate::laser::hooks::{TuningHooks, TuningEvent};
+use crate::laser::discoverynx::profile::{WavelengthProfile, DispersionMap};
+use crate::laser::discoverynx::roles::BeamRoles;
+use crate::laser::discoverynx::limits::SoftLimits;
+use crate::laser::discoverynx::faults::FaultFlags;
+use crate::laser::discoverynx::progress::{TuningMove, TuningProgress};
+use crate::laser::discoverynx::sweep::{self, GddSweep};
+use crate::laser::discoverynx::presets::{self, Presets};
+use crate::laser::discoverynx::restore;
+use crate::laser::snapshot::LaserSnapshot;
+use crate::laser::lock::OperatorLock;
+use crate::laser::history::ParameterHistory;
+use crate::laser::discoverynx::DiscoveryNXStatus;
+use crate::laser::simulator::DiscoverySimulator;
+use crate::laser::open::LaserOpenOptions;
+use crate::laser::cancel::CancelToken;
+use crate::laser::{Query, LaserCommand, LaserState, ShutterState, AlignmentMode, Keyswitch, LaserType, TuningStatus, FaultReport, StatusValue, LaserCapabilities};
+use crate::laser::units::{Nanometers, GddFs2};
+
+
+/// What the `DebugLaser` does with a setting outside its `HeadRanges`.
+/// Discovery heads differ: some refuse the command, some quietly clamp
+/// it to the nearest value they can reach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutOfRange {
+    /// Fail with `CommandNotExecutedError`, leaving the setting alone
+    #[default]
+    Reject,
+    /// Accept it, but set the nearest value in range
+    Clamp,
+}
+
+/// The settings the simulated head accepts, and what it does with the rest.
+/// Defaults to 700-1000 nm and -10000-10000 fs^2 GDD, rejecting anything
+/// outside them.
+///
+/// # Example
+///
+/// ```
+/// use coherent_rs::laser::debug::{DebugLaser, HeadRanges, OutOfRange};
+/// let mut laser = DebugLaser::default();
+/// laser.ranges = HeadRanges{wavelength_nm : (680.0, 1300.0), out_of_range : OutOfRange::Clamp, ..Default::default()};
+/// laser.set_wavelength(1400.0).unwrap();
+/// assert_eq!(laser.get_wavelength().unwrap(), 1300.0);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeadRanges {
+    pub wavelength_nm : (f32, f32),
+    pub gdd_fs2 : (f32, f32),
+    pub out_of_range : OutOfRange,
+}
 
+impl Default for HeadRanges {
+    fn default() -> Self {
+        HeadRanges{
+            wavelength_nm : (700.0, 1000.0),
+            gdd_fs2 : (-10000.0, 10000.0),
+            out_of_range : OutOfRange::Reject,
+        }
+    }
+}
+
+impl HeadRanges {
+    /// `value` as the head would set it, given the range `(min, max)`.
+    fn apply(&self, value : f32, (min, max) : (f32, f32)) -> Result<f32, CoherentError> {
+        if (min..=max).contains(&value) { return Ok(value); }
+        match self.out_of_range {
+            OutOfRange::Reject => Err(CoherentError::CommandNotExecutedError),
+            OutOfRange::Clamp if value.is_nan() => Err(CoherentError::CommandNotExecutedError),
+            OutOfRange::Clamp => Ok(value.clamp(min, max)),
+        }
+    }
+}
 
 /// Mimics the Coherent laser model Discovery NX -- and uses its `DiscoveryNXCommands`.
 /// 
@@ -17,12 +89,14 @@ use crate::laser::{Query, LaserState, ShutterState, LaserType, TuningStatus};
 #[repr(C)]
 pub struct DebugLaser{
     pub serial_number : String,
+    /// The port it pretends to be on
+    pub port_name : String,
     echo : bool, // whether or not the laser will echo commands, which affects parsing
     _prompt : bool, // whether or not the laser will echo prompts, which affects parsing
     _variable_shutter : bool,
     _fixed_shutter : bool,
-    _variable_alignment : bool,
-    _fixed_alignment : bool,
+    _variable_alignment : AlignmentMode,
+    _fixed_alignment : AlignmentMode,
     _variable_power : f32,
     _fixed_power : f32,
     _variable_wavelength : f32,
@@ -31,11 +105,33 @@ pub struct DebugLaser{
     _gdd_curve_n : String,
     _gdd_curve : i32,
     _status : String,
+    _laser_state : LaserState,
+    _keyswitch : Keyswitch,
+    _faults : u8,
     _fault_text : String,
+    _operating_hours : f32,
+    _baseplate_temperature : f32,
+    _humidity : f32,
+    _diode_current : f32,
+    _heatsink_ok : bool,
+    _tuning_move : Option<TuningMove>,
+    pub power_calibration : DiscoveryPowerCalibration,
+    pub tuning_hooks : TuningHooks,
+    pub wavelength_profile : Option<WavelengthProfile>,
+    pub beam_roles : BeamRoles,
+    pub soft_limits : SoftLimits,
+    operator_lock : OperatorLock,
+    pub parameter_history : Option<ParameterHistory>,
+    /// What the simulated head accepts
+    pub ranges : HeadRanges,
+    /// As in `Discovery`
+    pub diagnostics_in_status : bool,
+    cancel_token : CancelToken,
+    pub presets : Presets,
 }
 
-impl Into<LaserType> for DebugLaser {
-    fn into(self) -> LaserType {
+impl From<DebugLaser> for LaserType {
+    fn from(_val: DebugLaser) -> Self {
         LaserType::DebugLaser
     }
 }
@@ -44,12 +140,13 @@ impl Default for DebugLaser{
     fn default() -> Self {
         DebugLaser{
             serial_number : "DEBUG".to_string(),
+            port_name : "DEBUG".to_string(),
             echo : true,
             _prompt : false,
             _variable_shutter : false,
             _fixed_shutter : false,
-            _variable_alignment : false,
-            _fixed_alignment : false,
+            _variable_alignment : AlignmentMode::Off,
+            _fixed_alignment : AlignmentMode::Off,
             _variable_power : 1000.0,
             _fixed_power : 5000.0,
             _variable_wavelength : 920.0,
@@ -58,15 +155,39 @@ impl Default for DebugLaser{
             _gdd_curve_n : "Default".to_string(),
             _gdd_curve : 0,
             _status : "OK".to_string(),
+            _laser_state : LaserState::On,
+            _keyswitch : Keyswitch::On,
+            _faults : 0,
             _fault_text : "No faults".to_string(),
+            _operating_hours : 1250.0,
+            _baseplate_temperature : 25.0,
+            _humidity : 8.0,
+            _diode_current : 27.5,
+            _heatsink_ok : true,
+            _tuning_move : None,
+            power_calibration : DiscoveryPowerCalibration::default(),
+            tuning_hooks : TuningHooks::default(),
+            wavelength_profile : None,
+            beam_roles : BeamRoles::default(),
+            soft_limits : SoftLimits::default(),
+            operator_lock : OperatorLock::default(),
+            parameter_history : None,
+            ranges : HeadRanges::default(),
+            diagnostics_in_status : false,
+            cancel_token : CancelToken::new(),
+            presets : Presets::new(),
         }
     }
 }
 
 impl Laser for DebugLaser {
     type CommandEnum = DiscoveryNXCommands;
-    #[cfg(feature = "network")]
     type LaserStatus = DiscoveryNXStatus;
+    type Limits = SoftLimits;
+
+    /// Answers instantly, so tests can poll fast.
+    const POLLING_INTERVAL_BOUNDS : (std::time::Duration, std::time::Duration) =
+        (std::time::Duration::from_millis(10), std::time::Duration::from_secs(3600));
 
     /// Does nothing.
     fn send_serial_command(&mut self, _command : &str) -> Result<(), CoherentError> {
@@ -90,10 +211,51 @@ impl Laser for DebugLaser {
     /// 
     /// # Example
     /// 
-    /// ```no_run
-    /// let laser = DebugLaser::from_port_info(&serialportinfo);
     /// ```
-    fn from_port_info(_serialportinfo : &serialport::SerialPortInfo)-> Result<Self, CoherentError> {
+    /// use coherent_rs::laser::{Laser, debug::DebugLaser};
+    /// let serialportinfo = serialport::SerialPortInfo{
+    ///     port_name : "NotAPort".to_string(),
+    ///     port_type : serialport::SerialPortType::Unknown,
+    /// };
+    /// let laser = DebugLaser::from_port_info(&serialportinfo).unwrap();
+    /// ```
+    fn from_port_info(serialportinfo : &serialport::SerialPortInfo)-> Result<Self, CoherentError> {
+        let serial_number = match &serialportinfo.port_type {
+            serialport::SerialPortType::UsbPort(info) => info.serial_number.as_deref(),
+            _ => None,
+        };
+        Ok(DebugLaser::with_identity(&serialportinfo.port_name, serial_number.unwrap_or("DEBUG")))
+    }
+
+    /// Never looks at the real ports: a `DebugLaser` with the given port
+    /// name and serial number (each `DEBUG` if `None`), so code matching
+    /// lasers by either can be tested with several of them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use coherent_rs::laser::{Laser, debug::DebugLaser};
+    /// let mut rig_a = DebugLaser::new(None, Some("SN-A")).unwrap();
+    /// let rig_b = DebugLaser::new(Some("COM5"), Some("SN-B")).unwrap();
+    /// assert_eq!(rig_a.get_serial().unwrap(), "SN-A");
+    /// assert_eq!(rig_b.port_name, "COM5");
+    /// ```
+    fn new(port_name : Option<&str>, serial_number : Option<&str>) -> Result<Self, CoherentError> {
+        Ok(DebugLaser::with_identity(port_name.unwrap_or("DEBUG"), serial_number.unwrap_or("DEBUG")))
+    }
+
+    /// As `new`: there's no port to open.
+    fn open_with(port_name : Option<&str>, serial_number : Option<&str>, _options : &LaserOpenOptions)
+        -> Result<Self, CoherentError> {
+        DebugLaser::new(port_name, serial_number)
+    }
+
+    fn from_port_name(port_name : &str) -> Result<Self, CoherentError> {
+        Ok(DebugLaser::with_identity(port_name, "DEBUG"))
+    }
+
+    /// Always the default `DebugLaser` -- there's always one to find.
+    fn find_first() -> Result<Self, CoherentError> {
         Ok(DebugLaser::default())
     }
 
@@ -109,70 +271,69 @@ impl Laser for DebugLaser {
     /// 
     /// # Example
     /// 
-    /// ```no_run
-    ///
+    /// ```
+    /// use coherent_rs::laser::{Laser, DiscoveryLaser, ShutterState, debug::DebugLaser};
+    /// use coherent_rs::DiscoveryNXCommands;
+    /// let mut laser = DebugLaser::default();
+    /// laser.send_command(
+    ///     DiscoveryNXCommands::Shutter{
+    ///         laser : DiscoveryLaser::VariableWavelength,
+    ///         state : ShutterState::Open
+    ///     }
+    /// ).unwrap();
     /// ```
     fn send_command(&mut self, command : DiscoveryNXCommands) -> Result<(), CoherentError> {
-        
+        self.operator_lock.check()?;
+        let command = self.soft_limits.apply(command)?;
         match command {
-            DiscoveryNXCommands::Echo{echo_on} => {
-                self.echo = echo_on;
-            },
-            DiscoveryNXCommands::Wavelength{wavelength_nm} => {
-                if wavelength_nm < 700.0 || wavelength_nm > 1000.0 {
-                    return Err(CoherentError::CommandNotExecutedError);
+            DiscoveryNXCommands::Wavelength{wavelength_nm}
+                if !self.tuning_hooks.is_empty() || self.wavelength_profile.is_some() => {
+                let event = TuningEvent{from_nm : self._variable_wavelength, to_nm : wavelength_nm.0};
+                self.tuning_hooks.run_before(&event)?;
+                // Tuning is instantaneous, so the after-hooks run right away
+                if let Err(e) = self.apply_command(command) {
+                    self.tuning_hooks.run_after(&TuningEvent{to_nm : event.from_nm, ..event})?;
+                    return Err(e);
                 }
-                self._variable_wavelength = wavelength_nm;
-            },
-            DiscoveryNXCommands::Gdd{gdd_val} => {
-                if gdd_val < -10000.0 || gdd_val > 10000.0 {
-                    return Err(CoherentError::CommandNotExecutedError);
+                let profile_commands = self.wavelength_profile.as_ref()
+                    .map(|profile| profile.commands_for(wavelength_nm.0))
+                    .unwrap_or_default();
+                for command in profile_commands {
+                    let command = self.soft_limits.apply(command)?;
+                    self.apply_command(command)?;
                 }
-                self._gdd = gdd_val;
+                self.tuning_hooks.run_after(&event)
             },
-            DiscoveryNXCommands::Shutter{laser, state} => {
-                match laser {
-                    DiscoveryLaser::VariableWavelength => {
-                        self._variable_shutter = state == ShutterState::Open;
-                    },
-                    DiscoveryLaser::FixedWavelength => {
-                        self._fixed_shutter = state == ShutterState::Open;
-                    }
-                }
-            },
-            DiscoveryNXCommands::GddCurve{curve_num} => {
-                self._gdd_curve = curve_num.into();
-            },
-            DiscoveryNXCommands::GddCurveN{curve_name} => {
-                self._gdd_curve_n = curve_name;
-            },
-            DiscoveryNXCommands::AlignmentMode{laser, alignment_mode_on} => {
-                match laser {
-                    DiscoveryLaser::VariableWavelength => {
-                        self._variable_alignment = alignment_mode_on;
-                    },
-                    DiscoveryLaser::FixedWavelength => {
-                        self._fixed_alignment = alignment_mode_on;
-                    }
-                }
-            },
-            DiscoveryNXCommands::Laser{state} => {
-                match state {
-                    LaserState::Standby => {
-                        self._status = "Standby".to_string();
-                    },
-                    LaserState::On => {
-                        self._status = "On".to_string();
-                    }
-                }
-            },
-            DiscoveryNXCommands::FaultClear => {
-                self._fault_text = "No faults".to_string();
-            }
-            _ => {}
+            DiscoveryNXCommands::ApplyPreset{name} => self.apply_preset(&name),
+            command => self.apply_command(command),
         }
+    }
 
-        Ok(())
+    /// Also checks what the simulated head would refuse: settings outside
+    /// its `ranges` (unless it clamps them), or turning on with the key off
+    /// or a fault outstanding.
+    fn validate(&mut self, command : &DiscoveryNXCommands) -> Result<Vec<String>, CoherentError> {
+        self.operator_lock.check()?;
+        if let DiscoveryNXCommands::ApplyPreset{name} = command {
+            let mut serial = Vec::new();
+            for command in self.presets.find(name)?.commands() {
+                serial.extend(self.validate(&command)?);
+            }
+            return Ok(serial);
+        }
+        let clamped = self.soft_limits.clamped(command);
+        let command = clamped.as_ref().unwrap_or(command);
+        self.soft_limits.check(command)?;
+        self.check_head(command)?;
+        let mut serial = vec![command.to_string()];
+        if let (DiscoveryNXCommands::Wavelength{wavelength_nm}, Some(profile)) = (command, self.wavelength_profile.as_ref()) {
+            for command in profile.commands_for(wavelength_nm.0) {
+                let command = self.soft_limits.apply(command)?;
+                self.check_head(&command)?;
+                serial.push(command.to_string());
+            }
+        }
+        Ok(serial)
     }
 
     /// Always fails! Queries are implemented using the actual serial communication,
@@ -195,26 +356,36 @@ impl Laser for DebugLaser {
         Err(CoherentError::CommandNotExecutedError)
     }
 
-    #[cfg(feature = "network")]
     fn status(&mut self) -> Result<Self::LaserStatus, CoherentError> {
         Ok(DiscoveryNXStatus {
             echo : self.echo,
-            laser : LaserState::On,
+            laser : LaserState::resolve(self.get_standby()?, self._keyswitch, self._faults != 0),
             variable_shutter : self._variable_shutter.into(),
             fixed_shutter : self._fixed_shutter.into(),
-            keyswitch : true,
-            faults : 0,
-            fault_text : self._fault_text.clone(),
+            keyswitch : self._keyswitch,
+            faults : FaultFlags::from_bits(self._faults),
+            fault_text : Some(self._fault_text.clone()),
             tuning : self._tuning_status.into(),
             alignment_var : self._variable_alignment,
             alignment_fixed : self._fixed_alignment,
-            power_var : self._variable_power,
+            power_var : (!self._tuning_status).then_some(self._variable_power),
             power_fixed : self._fixed_power,
+            calibrated_power_var : (!self._tuning_status)
+                .then(|| self.power_calibration.apply(&DiscoveryLaser::VariableWavelength, self._variable_power))
+                .flatten(),
+            calibrated_power_fixed : self.power_calibration.apply(&DiscoveryLaser::FixedWavelength, self._fixed_power),
             wavelength : self._variable_wavelength,
-            gdd : self._gdd,
-            gdd_curve_n : self._gdd_curve_n.clone(),
-            gdd_curve : self._gdd_curve,
+            gdd : Some(self._gdd),
+            gdd_curve_n : Some(self._gdd_curve_n.clone()),
+            gdd_curve : Some(self._gdd_curve),
             status : self._status.clone(),
+            locked : self.operator_lock.is_locked(),
+            timestamp : crate::laser::unix_timestamp(),
+            operating_hours : self.diagnostics_in_status.then_some(self._operating_hours),
+            baseplate_temperature : self.diagnostics_in_status.then_some(self._baseplate_temperature),
+            humidity : self.diagnostics_in_status.then_some(self._humidity),
+            diode_current : self.diagnostics_in_status.then_some(self._diode_current),
+            heatsink_ok : self.diagnostics_in_status.then_some(self._heatsink_ok),
         })
     }
 
@@ -227,6 +398,73 @@ impl Laser for DebugLaser {
         Ok(buf)
     } 
 
+    fn operator_lock(&mut self) -> Option<&mut OperatorLock> {
+        Some(&mut self.operator_lock)
+    }
+
+    fn parameter_history(&mut self) -> Option<&mut ParameterHistory> {
+        self.parameter_history.as_mut()
+    }
+
+    fn soft_limits(&mut self) -> Option<&mut SoftLimits> {
+        Some(&mut self.soft_limits)
+    }
+
+    /// Answered as a Discovery NX with echo and the prompt off would.
+    fn raw_command(&mut self, command : &str) -> Result<String, CoherentError> {
+        let mut simulator = DiscoverySimulator::from_laser(std::mem::take(self), false, false, "DEBUG");
+        let reply = simulator.respond(command);
+        *self = simulator.into_laser();
+        Ok(reply.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    fn fault_report(&mut self) -> Result<FaultReport, CoherentError> {
+        Ok(FaultReport{code : self._faults, text : self.get_fault_text()?})
+    }
+
+    fn tuning_status(&mut self) -> Result<TuningStatus, CoherentError> {
+        self.get_tuning()
+    }
+
+    fn cancel_token(&self) -> Option<CancelToken> {
+        Some(self.cancel_token.clone())
+    }
+
+    /// The `restore::SNAPSHOT_FIELDS`, read with `status`.
+    fn snapshot(&mut self) -> Result<LaserSnapshot, CoherentError> {
+        Ok(restore::snapshot(&self.status()?))
+    }
+
+    /// Waits up to the `tuning_hooks.settle_timeout` for tuning to finish.
+    fn restore(&mut self, snapshot : &LaserSnapshot) -> Result<(), CoherentError> {
+        restore::restore(self, snapshot, self.tuning_hooks.settle_timeout)
+    }
+
+    fn apply_preset(&mut self, name : &str) -> Result<(), CoherentError> {
+        let commands = self.presets.find(name)?.commands();
+        presets::send_settling(self, commands, self.tuning_hooks.settle_timeout)
+    }
+
+    /// As in `Discovery`. Tuning is instantaneous, so always done.
+    fn tuning_progress(&mut self) -> Result<Option<TuningProgress>, CoherentError> {
+        let Some(TuningMove{from_nm, to_nm, started}) = self._tuning_move else { return Ok(None) };
+        let ready = self.get_tuning()? == TuningStatus::Ready;
+        Ok(Some(TuningProgress::estimate(
+            from_nm.unwrap_or(self._variable_wavelength), to_nm, self._variable_wavelength, started.elapsed(), ready
+        )))
+    }
+
+    /// A Discovery NX's, with the simulated head's `ranges`.
+    fn capabilities(&self) -> LaserCapabilities {
+        LaserCapabilities{
+            laser_type : LaserType::DebugLaser,
+            tuning_range_nm : Some(self.ranges.wavelength_nm),
+            gdd_range_fs2 : Some(self.ranges.gdd_fs2),
+            outputs : 2,
+            features : FEATURES.to_vec(),
+        }
+    }
+
     fn into_laser_type() -> LaserType {
         LaserType::DebugLaser
     }
@@ -235,6 +473,152 @@ impl Laser for DebugLaser {
 /// Convenience functions
 impl DebugLaser {
 
+    /// A `DebugLaser` that reports the given port and serial number.
+    pub fn with_identity(port_name : &str, serial_number : &str) -> Self {
+        DebugLaser{
+            port_name : port_name.to_string(),
+            serial_number : serial_number.to_string(),
+            ..DebugLaser::default()
+        }
+    }
+
+    /// Refuses every state-changing command until `unlock` is called with
+    /// the same token. See `lock::OperatorLock`.
+    pub fn lock(&mut self, token : &str) -> Result<(), CoherentError> {
+        self.operator_lock.lock(token)
+    }
+
+    pub fn unlock(&mut self, token : &str) -> Result<(), CoherentError> {
+        self.operator_lock.unlock(token)
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.operator_lock.is_locked()
+    }
+    /// Updates the simulated state for a command, without running any hooks.
+    fn apply_command(&mut self, command : DiscoveryNXCommands) -> Result<(), CoherentError> {
+        let parameter = self.parameter_history.as_ref().and_then(|_| command.parameter_change())
+            .map(|(parameter, _)| parameter);
+        let before = parameter.map(|parameter| self.parameter_value(parameter));
+        self.apply_to_state(command)?;
+        if let (Some(parameter), Some(before)) = (parameter, before) {
+            // What the head actually set, which may be clamped
+            let after = self.parameter_value(parameter);
+            self.record_change(parameter, before, after);
+        }
+        Ok(())
+    }
+
+    /// The current value of the status field `parameter`, for the
+    /// `parameter_history`. `Missing` if it can't be read.
+    fn parameter_value(&mut self, parameter : &str) -> StatusValue {
+        let debug_text = |value : &dyn std::fmt::Debug| StatusValue::Text(format!("{:?}", value));
+        let value = match parameter {
+            "echo" => Ok(StatusValue::Bool(self.echo)),
+            "laser" => self.get_standby().map(|state| debug_text(&state)),
+            "variable_shutter" => self.get_shutter(DiscoveryLaser::VariableWavelength).map(|state| debug_text(&state)),
+            "fixed_shutter" => self.get_shutter(DiscoveryLaser::FixedWavelength).map(|state| debug_text(&state)),
+            "alignment_var" => self.get_alignment_mode(DiscoveryLaser::VariableWavelength).map(|mode| StatusValue::Bool(mode.into())),
+            "alignment_fixed" => self.get_alignment_mode(DiscoveryLaser::FixedWavelength).map(|mode| StatusValue::Bool(mode.into())),
+            "wavelength" => self.get_wavelength().map(|wavelength| StatusValue::Float(wavelength as f64)),
+            "gdd" => self.get_gdd().map(|gdd| StatusValue::Float(gdd as f64)),
+            "gdd_curve" => self.get_gdd_curve().map(|curve| StatusValue::Integer(curve as i64)),
+            "gdd_curve_n" => self.get_gdd_curve_n().map(StatusValue::Text),
+            _ => Ok(StatusValue::Missing),
+        };
+        value.unwrap_or(StatusValue::Missing)
+    }
+
+    /// Adds a change to the `parameter_history`, if there is one. The
+    /// command has already run, so failing to persist it doesn't fail it.
+    fn record_change(&mut self, parameter : &str, before : StatusValue, after : StatusValue) {
+        if let Some(history) = self.parameter_history.as_mut() {
+            if let Err(e) = history.record(parameter, before, after) {
+                eprintln!("Could not record {} in the parameter history: {:?}", parameter, e);
+            }
+        }
+    }
+
+    /// Whether the head would refuse `command` in its current state. Doesn't
+    /// cover the soft limits or the lock.
+    fn check_head(&self, command : &DiscoveryNXCommands) -> Result<(), CoherentError> {
+        match command {
+            DiscoveryNXCommands::Wavelength{wavelength_nm} => self.ranges.apply(wavelength_nm.0, self.ranges.wavelength_nm).map(|_| ()),
+            DiscoveryNXCommands::Gdd{gdd_val} => self.ranges.apply(gdd_val.0, self.ranges.gdd_fs2).map(|_| ()),
+            // Like the real laser, it won't turn on with the key off or a
+            // fault outstanding
+            DiscoveryNXCommands::Laser{state : LaserState::On} if self._keyswitch == Keyswitch::Off || self._faults != 0 => {
+                Err(CoherentError::CommandNotExecutedError)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn apply_to_state(&mut self, command : DiscoveryNXCommands) -> Result<(), CoherentError> {
+        match command {
+            DiscoveryNXCommands::Echo{echo_on} => {
+                self.echo = echo_on;
+            },
+            DiscoveryNXCommands::Wavelength{wavelength_nm} => {
+                let from_nm = self._variable_wavelength;
+                self._variable_wavelength = self.ranges.apply(wavelength_nm.0, self.ranges.wavelength_nm)?;
+                self._tuning_move = Some(TuningMove{
+                    from_nm : Some(from_nm), to_nm : self._variable_wavelength, started : std::time::Instant::now()
+                });
+            },
+            DiscoveryNXCommands::Gdd{gdd_val} => {
+                self._gdd = self.ranges.apply(gdd_val.0, self.ranges.gdd_fs2)?;
+            },
+            DiscoveryNXCommands::Shutter{laser, state} => {
+                match laser {
+                    DiscoveryLaser::VariableWavelength => {
+                        self._variable_shutter = state == ShutterState::Open;
+                    },
+                    DiscoveryLaser::FixedWavelength => {
+                        self._fixed_shutter = state == ShutterState::Open;
+                    }
+                }
+            },
+            DiscoveryNXCommands::GddCurve{curve_num} => {
+                self._gdd_curve = curve_num.into();
+            },
+            DiscoveryNXCommands::GddCurveN{curve_name} => {
+                self._gdd_curve_n = curve_name;
+            },
+            DiscoveryNXCommands::AlignmentMode{laser, alignment_mode_on} => {
+                match laser {
+                    DiscoveryLaser::VariableWavelength => {
+                        self._variable_alignment = alignment_mode_on;
+                    },
+                    DiscoveryLaser::FixedWavelength => {
+                        self._fixed_alignment = alignment_mode_on;
+                    }
+                }
+            },
+            DiscoveryNXCommands::Laser{state} => {
+                match state {
+                    LaserState::Standby | LaserState::Off => {
+                        self._status = "Standby".to_string();
+                        self._laser_state = LaserState::Standby;
+                    },
+                    LaserState::On => {
+                        self.check_head(&command)?;
+                        self._status = "On".to_string();
+                        self._laser_state = state;
+                    }
+                }
+            },
+            DiscoveryNXCommands::FaultClear => {
+                self._faults = 0;
+                self._fault_text = "No faults".to_string();
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+
     /// Set the wavelength of the variable-wavelength laser
     /// 
     /// # Arguments
@@ -244,25 +628,42 @@ impl DebugLaser {
     /// # Example
     /// 
     /// ```
-    /// let mut discovery = Discovery::find_first().unwrap();
-    /// discovery.set_wavelength(840.0).unwrap();
+    /// use coherent_rs::laser::debug::DebugLaser;
+    /// let mut laser = DebugLaser::default();
+    /// laser.set_wavelength(840.0).unwrap();
+    /// assert_eq!(laser.get_wavelength().unwrap(), 840.0);
     /// ```
-    pub fn set_wavelength(&mut self, wavelength : f32) -> Result<(), CoherentError> {
-        self.send_command(DiscoveryNXCommands::Wavelength{wavelength_nm : wavelength})
+    pub fn set_wavelength(&mut self, wavelength : impl Into<Nanometers>) -> Result<(), CoherentError> {
+        self.send_command(DiscoveryNXCommands::Wavelength{wavelength_nm : wavelength.into()})
     }
 
     pub fn get_wavelength(&mut self) -> Result<f32, CoherentError> {
         Ok(self._variable_wavelength)
     }
 
-    pub fn set_gdd(&mut self, gdd : f32) -> Result<(), CoherentError> {
-        self.send_command(DiscoveryNXCommands::Gdd{gdd_val : gdd})
+    pub fn set_gdd(&mut self, gdd : impl Into<GddFs2>) -> Result<(), CoherentError> {
+        self.send_command(DiscoveryNXCommands::Gdd{gdd_val : gdd.into()})
     }
 
     pub fn get_gdd(&mut self) -> Result<f32, CoherentError> {
         Ok(self._gdd)
     }
 
+    /// As in `Discovery`.
+    pub fn sweep_gdd(&mut self, range : (f32, f32), step : f32, dwell : std::time::Duration, measure : impl FnMut() -> f32)
+        -> Result<GddSweep, CoherentError> {
+        let cancellation = self.cancel_token.watch();
+        let sweep = sweep::run(range, step, dwell, &cancellation, |gdd| self.set_gdd(gdd), measure)?;
+        self.set_gdd(sweep.optimum_fs2)?;
+        Ok(sweep)
+    }
+
+    /// As in `Discovery`.
+    pub fn save_preset(&mut self, name : &str) -> Result<(), CoherentError> {
+        let preset = presets::Preset::from_status(&self.status()?);
+        self.presets.insert(name, preset).map(|_| ())
+    }
+
     pub fn set_shutter(&mut self, laser : DiscoveryLaser, state : ShutterState) -> Result<(), CoherentError> {
         self.send_command(DiscoveryNXCommands::Shutter{laser, state})
     }
@@ -302,11 +703,11 @@ impl DebugLaser {
         Ok(self._gdd_curve_n.clone())
     }
     
-    pub fn set_alignment_mode(&mut self, laser : DiscoveryLaser, mode : bool) -> Result<(), CoherentError> {
+    pub fn set_alignment_mode(&mut self, laser : DiscoveryLaser, mode : AlignmentMode) -> Result<(), CoherentError> {
         self.send_command(DiscoveryNXCommands::AlignmentMode{laser, alignment_mode_on : mode})
     }
 
-    pub fn get_alignment_mode(&mut self, laser : DiscoveryLaser) -> Result<bool, CoherentError> {
+    pub fn get_alignment_mode(&mut self, laser : DiscoveryLaser) -> Result<AlignmentMode, CoherentError> {
         match laser {
             DiscoveryLaser::VariableWavelength => Ok(self._variable_alignment),
             DiscoveryLaser::FixedWavelength => Ok(self._fixed_alignment)
@@ -320,6 +721,49 @@ impl DebugLaser {
         }
     }
 
+    pub fn get_calibrated_power(&mut self, laser : DiscoveryLaser) -> Result<f32, CoherentError> {
+        let calibration = self.power_calibration.get(&laser).unwrap_or_default();
+        Ok(calibration.apply(self.get_power(laser)?))
+    }
+
+    /// Opens the shutter of the output playing `role` (see `beam_roles`).
+    pub fn open_shutter_for(&mut self, role : &str) -> Result<(), CoherentError> {
+        self.set_shutter_for(role, ShutterState::Open)
+    }
+
+    pub fn close_shutter_for(&mut self, role : &str) -> Result<(), CoherentError> {
+        self.set_shutter_for(role, ShutterState::Closed)
+    }
+
+    pub fn set_shutter_for(&mut self, role : &str, state : ShutterState) -> Result<(), CoherentError> {
+        let laser = self.beam_roles.output_for(role)?;
+        self.set_shutter(laser, state)
+    }
+
+    pub fn get_shutter_for(&mut self, role : &str) -> Result<ShutterState, CoherentError> {
+        let laser = self.beam_roles.output_for(role)?;
+        self.get_shutter(laser)
+    }
+
+    /// The calibrated power of the output playing `role`.
+    pub fn get_power_for(&mut self, role : &str) -> Result<f32, CoherentError> {
+        let laser = self.beam_roles.output_for(role)?;
+        self.get_calibrated_power(laser)
+    }
+
+    pub fn set_power_calibration(&mut self, laser : DiscoveryLaser, calibration : Option<PowerCalibration>) {
+        self.power_calibration.set(&laser, calibration);
+    }
+
+    pub fn load_power_calibration<P : AsRef<std::path::Path>>(&mut self, path : P) -> Result<(), CoherentError> {
+        self.power_calibration = DiscoveryPowerCalibration::load(path)?;
+        Ok(())
+    }
+
+    pub fn set_dispersion_map(&mut self, map : Option<DispersionMap>) {
+        self.wavelength_profile = map.map(WavelengthProfile::from);
+    }
+
     pub fn get_serial(&mut self) -> Result<String, CoherentError> {
         Ok(self.serial_number.clone())
     }
@@ -331,15 +775,41 @@ impl DebugLaser {
     }
 
     pub fn get_standby(&mut self) -> Result<LaserState, CoherentError> {
-        if self._status == "Standby" {
-            Ok(LaserState::Standby)
-        } else {
-            Ok(LaserState::On)
-        }
+        Ok(self._laser_state)
     }
 
     pub fn get_keyswitch_on(&mut self) -> Result<bool, CoherentError> {
-        Ok(true)
+        Ok(self._keyswitch.into())
+    }
+
+    /// Turns the simulated keyswitch. Turning it off drops the laser to
+    /// standby, and it won't turn back on until the key is back on.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use coherent_rs::laser::{LaserState, Keyswitch, debug::DebugLaser};
+    /// let mut laser = DebugLaser::default();
+    /// laser.set_keyswitch(Keyswitch::Off);
+    /// assert_eq!(laser.get_standby().unwrap(), LaserState::Standby);
+    /// assert!(laser.set_to_standby(false).is_err());
+    /// ```
+    pub fn set_keyswitch(&mut self, keyswitch : Keyswitch) {
+        self._keyswitch = keyswitch;
+        if keyswitch == Keyswitch::Off { self.drop_to_standby(); }
+    }
+
+    /// Simulates the laser faulting: sets the fault byte and text, and drops
+    /// to standby until the fault is cleared (`clear_faults`).
+    pub fn inject_fault(&mut self, fault : u8, text : &str) {
+        self._faults = fault;
+        self._fault_text = text.to_string();
+        if fault != 0 { self.drop_to_standby(); }
+    }
+
+    fn drop_to_standby(&mut self) {
+        self._laser_state = LaserState::Standby;
+        self._status = "Standby".to_string();
     }
 
     pub fn get_status(&mut self) -> Result<String, CoherentError> {
@@ -350,8 +820,8 @@ impl DebugLaser {
         self.send_command(DiscoveryNXCommands::FaultClear)
     }
 
-    pub fn get_faults(&mut self) -> Result<u8, CoherentError> {
-        Ok(0)
+    pub fn get_faults(&mut self) -> Result<FaultFlags, CoherentError> {
+        Ok(FaultFlags::from_bits(self._faults))
     }
 
     pub fn get_fault_text(&mut self) -> Result<String, CoherentError> {
@@ -364,12 +834,33 @@ impl DebugLaser {
             false => Ok(TuningStatus::Ready),
         }
     }
+
+    pub fn get_operating_hours(&mut self) -> Result<f32, CoherentError> {
+        Ok(self._operating_hours)
+    }
+
+    pub fn get_baseplate_temperature(&mut self) -> Result<f32, CoherentError> {
+        Ok(self._baseplate_temperature)
+    }
+
+    pub fn get_humidity(&mut self) -> Result<f32, CoherentError> {
+        Ok(self._humidity)
+    }
+
+    pub fn get_diode_current(&mut self) -> Result<f32, CoherentError> {
+        Ok(self._diode_current)
+    }
+
+    pub fn get_heatsink_ok(&mut self) -> Result<bool, CoherentError> {
+        Ok(self._heatsink_ok)
+    }
     
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::laser::units::{Nanometers, GddFs2};
 
     #[test]
     fn test_commands(){
@@ -382,6 +873,29 @@ mod tests {
         ).unwrap();
     }
 
+    #[test]
+    fn test_wait_for_tuning() {
+        let (timeout, poll_interval) = (std::time::Duration::from_millis(30), std::time::Duration::from_millis(10));
+        let mut laser = DebugLaser::default();
+        laser.wait_for_tuning(timeout, poll_interval).unwrap();
+
+        laser._tuning_status = true;
+        let start = std::time::Instant::now();
+        assert!(matches!(laser.wait_for_tuning(timeout, poll_interval), Err(CoherentError::TimeoutError)));
+        assert!(start.elapsed() >= timeout);
+
+        // Stopped from another thread long before it would time out
+        let token = laser.cancel_token().unwrap();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            token.cancel();
+        });
+        let start = std::time::Instant::now();
+        assert!(matches!(laser.wait_for_tuning(std::time::Duration::from_secs(30), poll_interval), Err(CoherentError::Cancelled)));
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        canceller.join().unwrap();
+    }
+
     #[test]
     fn test_shutter() {
         use std::thread;
@@ -436,7 +950,7 @@ mod tests {
         println!("Wavelength: {:?}", wv);
 
         discovery.send_command(
-            DiscoveryNXCommands::Wavelength{wavelength_nm : 840.0}
+            DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(840.0)}
         ).unwrap();
 
         let new_wv = discovery.get_wavelength().unwrap();
@@ -444,7 +958,7 @@ mod tests {
         println!("Wavelength: {:?}", new_wv);
 
         discovery.send_command(
-            DiscoveryNXCommands::Wavelength{wavelength_nm : wv}
+            DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(wv)}
         ).unwrap();
 
         let new_wv = discovery.get_wavelength().unwrap();
@@ -453,9 +967,315 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_common_commands() {
+        use crate::laser::CommonCommand;
+        let mut discovery = DebugLaser::find_first().unwrap();
+
+        discovery.send_common_command(CommonCommand::OpenShutter).unwrap();
+        assert_eq!(discovery.get_shutter(DiscoveryLaser::VariableWavelength).unwrap(), ShutterState::Open);
+        discovery.send_common_command(CommonCommand::CloseShutter).unwrap();
+        assert_eq!(discovery.get_shutter(DiscoveryLaser::VariableWavelength).unwrap(), ShutterState::Closed);
+
+        discovery.send_common_command(CommonCommand::Standby).unwrap();
+        assert_eq!(discovery.get_standby().unwrap(), LaserState::Standby);
+        discovery.send_common_command(CommonCommand::On).unwrap();
+        assert_eq!(discovery.get_standby().unwrap(), LaserState::On);
+
+        discovery.send_common_command(CommonCommand::ClearFaults).unwrap();
+        assert_eq!(discovery.get_fault_text().unwrap(), "No faults");
+
+        discovery.send_common_command(CommonCommand::SetWavelength(900.0)).unwrap();
+        assert_eq!(discovery.get_wavelength().unwrap(), 900.0);
+    }
+
+    #[test]
+    fn test_invalid_args() {
+        let mut discovery = DebugLaser::find_first().unwrap();
+
+        assert!(discovery.send_command(
+            DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(0.0)}
+        ).is_err());
+        assert_eq!(discovery.get_wavelength().unwrap(), 920.0);
+
+        assert!(discovery.send_command(
+            DiscoveryNXCommands::Gdd{gdd_val : GddFs2(50000.0)}
+        ).is_err());
+        assert_eq!(discovery.get_gdd().unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_gdd() {
+        let mut discovery = DebugLaser::find_first().unwrap();
+
+        let current_gdd = discovery.get_gdd().unwrap();
+
+        discovery.send_command(DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-500.0)}).unwrap();
+        assert_eq!(discovery.get_gdd().unwrap(), -500.0);
+
+        discovery.send_command(DiscoveryNXCommands::Gdd{gdd_val : GddFs2(current_gdd)}).unwrap();
+        assert_eq!(discovery.get_gdd().unwrap(), current_gdd);
+    }
+
+    #[test]
+    fn test_convenience_funcs() {
+        let mut discovery = DebugLaser::find_first().unwrap();
+
+        discovery.set_wavelength(840.0).unwrap();
+        assert_eq!(discovery.get_wavelength().unwrap(), 840.0);
+        assert_eq!(discovery.get_tuning().unwrap(), TuningStatus::Ready);
+
+        for fixed in [false, true] {
+            let laser = || if fixed {DiscoveryLaser::FixedWavelength} else {DiscoveryLaser::VariableWavelength};
+
+            discovery.set_shutter(laser(), ShutterState::Open).unwrap();
+            assert_eq!(discovery.get_shutter(laser()).unwrap(), ShutterState::Open);
+            discovery.set_shutter(laser(), ShutterState::Closed).unwrap();
+            assert_eq!(discovery.get_shutter(laser()).unwrap(), ShutterState::Closed);
+
+            discovery.set_alignment_mode(laser(), AlignmentMode::On).unwrap();
+            assert_eq!(discovery.get_alignment_mode(laser()).unwrap(), AlignmentMode::On);
+            discovery.set_alignment_mode(laser(), AlignmentMode::Off).unwrap();
+            assert_eq!(discovery.get_alignment_mode(laser()).unwrap(), AlignmentMode::Off);
+        }
+
+        discovery.set_to_standby(true).unwrap();
+        assert_eq!(discovery.get_standby().unwrap(), LaserState::Standby);
+        discovery.set_to_standby(false).unwrap();
+        assert_eq!(discovery.get_standby().unwrap(), LaserState::On);
+    }
+
+    #[test]
+    fn test_tuning_hooks() {
+        use std::sync::{Arc, Mutex};
+
+        let mut discovery = DebugLaser::default();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        {
+            let calls = calls.clone();
+            discovery.tuning_hooks.before_tuning(move |e| {
+                calls.lock().unwrap().push(("before", e.from_nm, e.to_nm)); Ok(())
+            });
+        }
+        {
+            let calls = calls.clone();
+            discovery.tuning_hooks.after_tuning(move |e| {
+                calls.lock().unwrap().push(("after", e.from_nm, e.to_nm)); Ok(())
+            });
+        }
+
+        discovery.set_wavelength(800.0).unwrap();
+        // Out of range: the after-hooks are told the laser stayed put
+        assert!(discovery.set_wavelength(5000.0).is_err());
+        // Other commands don't trigger hooks
+        discovery.set_gdd(100.0).unwrap();
+
+        assert_eq!(*calls.lock().unwrap(), vec![
+            ("before", 920.0, 800.0), ("after", 920.0, 800.0),
+            ("before", 800.0, 5000.0), ("after", 800.0, 800.0),
+        ]);
+
+        // A failing before-hook cancels the change
+        discovery.tuning_hooks.before_tuning(|_| Err(CoherentError::LaserUnavailableError));
+        assert!(matches!(discovery.set_wavelength(850.0), Err(CoherentError::LaserUnavailableError)));
+        assert_eq!(discovery.get_wavelength().unwrap(), 800.0);
+    }
+
+    #[test]
+    fn test_sweep_gdd() {
+        let mut discovery = DebugLaser::default();
+        // Two-photon signal peaking at -4000 fs^2
+        let mut gdds = (0..=8).map(|i| i as f32 * -1000.0);
+        let measure = || 1.0 / (1.0 + (gdds.next().unwrap() + 4000.0).abs());
+        let sweep = discovery.sweep_gdd((0.0, -8000.0), 1000.0, std::time::Duration::ZERO, measure).unwrap();
+        assert_eq!((sweep.optimum_fs2, sweep.peak_signal), (-4000.0, 1.0));
+        assert_eq!(sweep.points.len(), 9);
+        assert_eq!(discovery.get_gdd().unwrap(), -4000.0);
+
+        // Past the GDD the laser can do
+        assert!(discovery.sweep_gdd((0.0, 5000.0), 1000.0, std::time::Duration::ZERO, || 0.0).is_err());
+        assert_eq!(discovery.get_gdd().unwrap(), -4000.0);
+    }
+
+    #[test]
+    fn test_wavelength_profile() {
+        let mut discovery = DebugLaser{
+            wavelength_profile : Some(
+                WavelengthProfile::parse("800 gdd=-6000 curve=1\n1000 gdd=-2000 curve=2\n").unwrap()
+            ),
+            ..Default::default()
+        };
+        discovery.set_wavelength(850.0).unwrap();
+        assert_eq!(discovery.get_gdd().unwrap(), -5000.0);
+        assert_eq!(discovery.get_gdd_curve().unwrap(), 1);
+
+        // A rejected wavelength leaves the GDD alone
+        assert!(discovery.set_wavelength(5000.0).is_err());
+        assert_eq!(discovery.get_gdd().unwrap(), -5000.0);
+    }
+
+    #[test]
+    fn test_power_calibration() {
+        let mut discovery = DebugLaser::default();
+        assert_eq!(discovery.get_calibrated_power(DiscoveryLaser::VariableWavelength).unwrap(), 1000.0);
+
+        discovery.set_power_calibration(DiscoveryLaser::VariableWavelength, Some(PowerCalibration::new(0.5, -10.0)));
+        assert_eq!(discovery.get_power(DiscoveryLaser::VariableWavelength).unwrap(), 1000.0);
+        assert_eq!(discovery.get_calibrated_power(DiscoveryLaser::VariableWavelength).unwrap(), 490.0);
+        assert_eq!(discovery.get_calibrated_power(DiscoveryLaser::FixedWavelength).unwrap(), 5000.0);
+
+        let status = discovery.status().unwrap();
+        assert_eq!(status.power_var, Some(1000.0));
+        assert_eq!(status.calibrated_power_var, Some(490.0));
+        assert_eq!(status.calibrated_power_fixed, None);
+
+        let path = std::env::temp_dir().join(format!("coherent-calibration-{}.txt", std::process::id()));
+        std::fs::write(&path, "fixed 0.1 0\n").unwrap();
+        discovery.load_power_calibration(&path).unwrap();
+        assert_eq!(discovery.get_calibrated_power(DiscoveryLaser::FixedWavelength).unwrap(), 500.0);
+        // Loading replaces the whole calibration
+        assert_eq!(discovery.get_calibrated_power(DiscoveryLaser::VariableWavelength).unwrap(), 1000.0);
+
+        std::fs::write(&path, "tunable 0.1 0\n").unwrap();
+        assert!(matches!(
+            discovery.load_power_calibration(&path),
+            Err(CoherentError::InvalidArgumentsError(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+
+    #[test]
+    fn test_identity() {
+        let mut a = DebugLaser::with_identity("/dev/ttyUSB0", "SN-A");
+        let mut b = DebugLaser::new(Some("/dev/ttyUSB1"), Some("SN-B")).unwrap();
+        assert_eq!(a.get_serial().unwrap(), "SN-A");
+        assert_eq!((b.get_serial().unwrap(), b.port_name.as_str()), ("SN-B".to_string(), "/dev/ttyUSB1"));
+
+        // Independent state
+        a.set_wavelength(800.0).unwrap();
+        assert_eq!(b.get_wavelength().unwrap(), 920.0);
+
+        let port = serialport::SerialPortInfo{
+            port_name : "COM7".to_string(),
+            port_type : serialport::SerialPortType::UsbPort(serialport::UsbPortInfo{
+                vid : 0x0d4d, pid : 0x0204, serial_number : Some("SN-C".to_string()),
+                manufacturer : None, product : None,
+            }),
+        };
+        let c = DebugLaser::from_port_info(&port).unwrap();
+        assert_eq!((c.serial_number.as_str(), c.port_name.as_str()), ("SN-C", "COM7"));
+        assert_eq!(DebugLaser::from_port_name("COM8").unwrap().port_name, "COM8");
+        assert_eq!(DebugLaser::find_first().unwrap().serial_number, "DEBUG");
+    }
+
+    #[test]
+    fn test_head_ranges() {
+        let mut laser = DebugLaser::default();
+        assert!(matches!(laser.set_wavelength(1100.0), Err(CoherentError::CommandNotExecutedError)));
+        assert_eq!(laser.get_wavelength().unwrap(), 920.0);
+
+        laser.ranges = HeadRanges{
+            wavelength_nm : (680.0, 1080.0),
+            gdd_fs2 : (-20000.0, 0.0),
+            out_of_range : OutOfRange::Reject,
+        };
+        assert_eq!(laser.capabilities().tuning_range_nm, Some((680.0, 1080.0)));
+        assert_eq!(laser.capabilities().gdd_range_fs2, Some((-20000.0, 0.0)));
+        laser.set_wavelength(1050.0).unwrap();
+        laser.set_gdd(-15000.0).unwrap();
+        assert!(laser.set_gdd(100.0).is_err());
+
+        laser.ranges.out_of_range = OutOfRange::Clamp;
+        laser.parameter_history = Some(ParameterHistory::default());
+        laser.set_wavelength(1100.0).unwrap();
+        laser.set_gdd(5000.0).unwrap();
+        assert_eq!(laser.get_wavelength().unwrap(), 1080.0);
+        assert_eq!(laser.get_gdd().unwrap(), 0.0);
+        assert!(laser.set_wavelength(f32::NAN).is_err());
+
+        // The history has what was actually set
+        let changes = laser.parameter_history.as_ref().unwrap().changes();
+        assert_eq!(changes[0].after, StatusValue::Float(1080.0));
+        assert_eq!(changes[1].after, StatusValue::Float(0.0));
+    }
+
+    #[test]
+    fn test_keyswitch_and_faults() {
+        use crate::laser::LaserStatus;
+
+        let mut laser = DebugLaser::default();
+        laser.set_shutter(DiscoveryLaser::VariableWavelength, ShutterState::Open).unwrap();
+        assert!(laser.status().unwrap().is_emitting());
+
+        // Standby really is standby
+        laser.set_to_standby(true).unwrap();
+        let status = laser.status().unwrap();
+        assert_eq!(status.laser, LaserState::Standby);
+        assert!(!status.is_emitting());
+        laser.set_to_standby(false).unwrap();
+        assert_eq!(laser.get_standby().unwrap(), LaserState::On);
+
+        // No turning back on with the key off
+        laser.set_keyswitch(Keyswitch::Off);
+        let status = laser.status().unwrap();
+        assert_eq!(status.keyswitch, Keyswitch::Off);
+        assert_eq!(status.laser, LaserState::Off);
+        assert_eq!(laser.get_standby().unwrap(), LaserState::Standby);
+        assert!(matches!(laser.set_to_standby(false), Err(CoherentError::CommandNotExecutedError)));
+        laser.set_keyswitch(Keyswitch::On);
+        laser.set_to_standby(false).unwrap();
+
+        // Nor with a fault outstanding
+        laser.inject_fault(3, "Chiller flow");
+        let status = laser.status().unwrap();
+        assert_eq!(status.faults(), 3);
+        assert_eq!(status.fault_text.as_deref(), Some("Chiller flow"));
+        assert_eq!(status.laser, LaserState::Off);
+        assert!(laser.set_to_standby(false).is_err());
+        laser.clear_faults().unwrap();
+        assert_eq!(laser.get_faults().unwrap(), FaultFlags::empty());
+        laser.set_to_standby(false).unwrap();
+        assert!(laser.status().unwrap().is_emitting());
+    }
+
+    #[test]
+    fn test_operator_lock() {
+        let mut discovery = DebugLaser::default();
+        discovery.lock("rig-2").unwrap();
+        assert!(discovery.is_locked());
+        assert!(matches!(discovery.set_wavelength(900.0), Err(CoherentError::LockedError)));
+        assert!(matches!(discovery.send_command(DiscoveryNXCommands::FaultClear), Err(CoherentError::LockedError)));
+        // Queries still work
+        assert_eq!(discovery.get_wavelength().unwrap(), 920.0);
+
+        assert!(discovery.unlock("guess").is_err());
+        discovery.unlock("rig-2").unwrap();
+        discovery.set_wavelength(900.0).unwrap();
+        assert_eq!(discovery.get_wavelength().unwrap(), 900.0);
+    }
+
+    #[test]
+    fn test_parameter_history() {
+        let mut discovery = DebugLaser{
+            wavelength_profile : Some(WavelengthProfile::parse("800 gdd=-6000").unwrap()),
+            parameter_history : Some(ParameterHistory::default()),
+            ..Default::default()
+        };
+        discovery.set_wavelength(800.0).unwrap();
+        // Rejected commands change nothing
+        assert!(discovery.set_gdd(-50000.0).is_err());
+
+        let history = discovery.parameter_history.as_ref().unwrap();
+        let parameters = history.changes().iter().map(|change| change.parameter.as_str()).collect::<Vec<_>>();
+        // The profile's GDD is recorded along with the wavelength
+        assert_eq!(parameters, vec!["wavelength", "gdd"]);
+        assert_eq!(history.diff(0.0)["gdd"], (StatusValue::Float(0.0), StatusValue::Float(-6000.0)));
+    }
 
     #[cfg(feature = "network")]
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_serde_command(){
         use rmp_serde::Serializer;
         use serde::{Serialize, Deserialize};
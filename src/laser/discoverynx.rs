@@ -2,6 +2,7 @@
 //! 
 //! DiscoveryNX laser model implementation.
 
+use std::collections::{BTreeSet, HashMap};
 use std::io::{Write, BufRead};
 
 #[cfg(feature = "network")]
@@ -10,13 +11,94 @@ use serde::{Serialize, Deserialize};
 use rmp_serde::Serializer;
 
 use crate::{CoherentError, Laser};
-use crate::laser::{LaserCommand, Query, LaserState, ShutterState, LaserType, TuningStatus};
+use crate::laser::{LaserCommand, Query, LaserState, ShutterState, AlignmentMode, Keyswitch, LaserType, TuningStatus, FaultReport, LaserStatus, CommonCommand, StatusValue, LaserCapabilities, LaserFeature};
+use crate::laser::units::{Nanometers, GddFs2};
+use crate::laser::calibration::{PowerCalibration, load_power_calibrations};
+use crate::laser::power_meter::{PowerMeter, fit_power_calibration};
+use crate::laser::hooks::{TuningHooks, TuningEvent};
+use crate::laser::lock::OperatorLock;
+use crate::laser::history::ParameterHistory;
+use crate::laser::retry::{RetryPolicy, Deadline, InvalidResponseHook};
+use crate::laser::open::LaserOpenOptions;
+use crate::laser::terminal::TerminalStyle;
+use crate::laser::transcript::SerialTranscript;
+use crate::laser::cancel::CancelToken;
+use crate::laser::snapshot::LaserSnapshot;
+use crate::laser::command_log::{CommandLog, CommandRecord};
+use presets::Presets;
+
+pub mod profile;
+pub mod limits;
+pub mod fields;
+pub mod roles;
+pub mod faults;
+pub mod builder;
+pub mod reconnect;
+pub mod transaction;
+pub mod progress;
+pub mod sweep;
+pub mod presets;
+pub mod restore;
+pub mod heartbeat;
+pub use fields::{StatusField, StatusDiff, FieldChange, get_field};
+use profile::{WavelengthProfile, DispersionMap};
+use roles::BeamRoles;
+use limits::SoftLimits;
+use faults::FaultFlags;
+use builder::DiscoveryBuilder;
+use reconnect::ReconnectPolicy;
+use transaction::Transaction;
+use progress::{TuningMove, TuningProgress};
+use sweep::GddSweep;
 
 const BAUDRATE : u32 = 19200;
 const DATABITS : serialport::DataBits = serialport::DataBits::Eight;
 const STOPBITS : serialport::StopBits = serialport::StopBits::One;
 const PARITY : serialport::Parity = serialport::Parity::None;
 
+/// How often `tune_with_hooks` and `set_wavelength_blocking` check
+/// whether tuning has finished.
+const TUNING_POLL_INTERVAL : std::time::Duration = std::time::Duration::from_millis(100);
+
+/// How far the wavelength read back after `set_wavelength_blocking` may be
+/// from the one asked for, in nm.
+pub const WAVELENGTH_TOLERANCE_NM : f32 = 1.0;
+
+/// How far a wavelength (nm) or GDD (fs^2) read back after a verified
+/// command may be from the one set.
+pub const VERIFY_TOLERANCE : f64 = 1.0;
+
+/// Whether `send_command` skips a wavelength, GDD or shutter command that
+/// wouldn't change anything, for software that re-asserts its settings
+/// over and over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SkipUnchanged {
+    /// Always send the command
+    #[default]
+    Never,
+    /// Skip it if the setting is what this `Discovery` last set it to, or
+    /// last read in a `status`. Costs nothing, but can't see changes made
+    /// elsewhere (the front panel, another program).
+    LastKnown,
+    /// Read the setting first, and skip the command if it's already there.
+    /// Costs a query per command.
+    Query,
+}
+
+/// The Discovery NX's nominal GDD precompensation range, in fs^2. What a
+/// given head reaches depends on the wavelength.
+pub const GDD_RANGE_FS2 : (f32, f32) = (-20000.0, 0.0);
+
+/// Everything a Discovery NX can do that a `LaserFeature` describes.
+pub(crate) const FEATURES : [LaserFeature; 6] = [
+    LaserFeature::Tuning,
+    LaserFeature::Gdd,
+    LaserFeature::GddCurves,
+    LaserFeature::AlignmentMode,
+    LaserFeature::Diagnostics,
+    LaserFeature::PowerCalibration,
+];
+
 
 /// The Coherent laser model Discovery NX.
 #[derive(Debug)]
@@ -25,28 +107,138 @@ pub struct Discovery{
     pub port : Box<dyn serialport::SerialPort>,
     pub serial_number : String,
     echo : bool, // whether or not the laser will echo commands, which affects parsing
-    _prompt : bool, // whether or not the laser will echo prompts, which affects parsing
+    terminal : TerminalStyle, // the prompt the laser puts before each line, which affects parsing
+    pub power_calibration : DiscoveryPowerCalibration,
+    pub tuning_hooks : TuningHooks,
+    /// If set, the GDD etc. for each wavelength is applied after tuning.
+    pub wavelength_profile : Option<WavelengthProfile>,
+    /// Which output does what on this rig, for the `_for(role)` methods.
+    pub beam_roles : BeamRoles,
+    pub soft_limits : SoftLimits,
+    operator_lock : OperatorLock,
+    /// Records every setting changed through this struct, if `Some`.
+    /// Costs a query per command, to read the value before the change.
+    pub parameter_history : Option<ParameterHistory>,
+    /// Applied to every query. Tries once by default.
+    pub retry_policy : RetryPolicy,
+    /// Applied to every command. Tries once by default; see
+    /// `RetryPolicy::for_commands` to send a command again while the laser
+    /// is too busy to take it.
+    pub command_retry_policy : RetryPolicy,
+    /// Have `status` send all its queries before reading any reply, rather
+    /// than waiting out a round trip for each: several times faster, for
+    /// polling faster than every 100 ms or so. Off by default. A failed read
+    /// retries the whole batch.
+    pub pipelined_status : bool,
+    /// Run on every reply that can't be parsed, before any retry.
+    pub invalid_response_hook : InvalidResponseHook,
+    /// Read each setting back after the command that sets it, and fail with
+    /// `VerificationFailed` if the laser didn't take it -- it can accept a
+    /// command without applying it while it's busy. Costs a query per
+    /// command. Off by default; see `send_command_verified` for one command.
+    pub verify_commands : bool,
+    /// Have `status` also read the operating hours, baseplate temperature,
+    /// humidity, diode current and heatsink status. Off by default: five
+    /// more queries per status. Any the laser refuses are left `None` and
+    /// added to the `unsupported_fields`, like the other optional fields.
+    pub diagnostics_in_status : bool,
+    /// Reopen the port and try again when a call fails as if the link
+    /// dropped. Off by default.
+    pub reconnect_policy : Option<ReconnectPolicy>,
+    /// Skip wavelength, GDD and shutter commands that wouldn't change
+    /// anything. Off by default.
+    pub skip_unchanged : SkipUnchanged,
+    no_reply : bool, // whether the last read got no line at all, for the `reconnect_policy`
+    deadline : Option<Deadline>, // set by `with_timeout`
+    preloaded_status : Option<DiscoveryNXStatus>, // see `DiscoveryBuilder::preload_status`
+    unsupported_fields : BTreeSet<StatusField>, // refused by the firmware, so left out of `status`
+    last_wavelength_nm : Option<f32>, // the latest wavelength read, where a `tuning_move` starts
+    known : HashMap<&'static str, StatusValue>, // settings as last set or read, for `SkipUnchanged::LastKnown`
+    tuning_move : Option<TuningMove>, // the latest wavelength sent, for `tuning_progress`
+    transcript : Option<SerialTranscript>, // taps the port, see `LaserOpenOptions::transcript`
+    cancel_token : CancelToken, // stops `wait_for_tuning` and sweeps, see `cancel`
+    /// The named settings `apply_preset` can apply. Empty by default.
+    pub presets : Presets,
+    persisted_snapshot : Option<(std::path::PathBuf, LaserSnapshot)>, // see `persist_snapshots`
+    /// Every command through `send_command` and how it went. Keeps the last
+    /// 1,000 in memory by default; see `CommandLog::persisted` to keep them
+    /// all in a file too.
+    pub command_log : CommandLog,
 }
 
-impl Into<LaserType> for Discovery {
-    fn into(self) -> LaserType {
+impl From<Discovery> for LaserType {
+    fn from(_val: Discovery) -> Self {
         LaserType::DiscoveryNX
     }
 }
 
-impl Into<LaserType> for &Discovery {
-    fn into(self) -> LaserType {
+impl From<&Discovery> for LaserType {
+    fn from(_val: &Discovery) -> Self {
         LaserType::DiscoveryNX
     }
 }
 
 #[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum DiscoveryLaser {
     VariableWavelength,
     FixedWavelength,
 }
 
+impl DiscoveryLaser {
+    /// The output named in a rig file: `variable` or `fixed`.
+    pub fn from_output_name(name : &str) -> Option<DiscoveryLaser> {
+        match name {
+            "variable" => Some(DiscoveryLaser::VariableWavelength),
+            "fixed" => Some(DiscoveryLaser::FixedWavelength),
+            _ => None,
+        }
+    }
+}
+
+/// Rig-specific power calibration for each of the Discovery's outputs.
+/// `None` means the output is uncalibrated and only raw values are reported.
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DiscoveryPowerCalibration {
+    pub variable : Option<PowerCalibration>,
+    pub fixed : Option<PowerCalibration>,
+}
+
+impl DiscoveryPowerCalibration {
+    pub fn get(&self, laser : &DiscoveryLaser) -> Option<PowerCalibration> {
+        match laser {
+            DiscoveryLaser::VariableWavelength => self.variable,
+            DiscoveryLaser::FixedWavelength => self.fixed,
+        }
+    }
+
+    pub fn set(&mut self, laser : &DiscoveryLaser, calibration : Option<PowerCalibration>) {
+        match laser {
+            DiscoveryLaser::VariableWavelength => self.variable = calibration,
+            DiscoveryLaser::FixedWavelength => self.fixed = calibration,
+        }
+    }
+
+    /// `raw_mw` with the output's calibration applied, if it has one.
+    pub fn apply(&self, laser : &DiscoveryLaser, raw_mw : f32) -> Option<f32> {
+        self.get(laser).map(|calibration| calibration.apply(raw_mw))
+    }
+
+    /// Reads a calibration file (see `calibration::parse_power_calibrations`)
+    /// with `variable` and/or `fixed` entries.
+    pub fn load<P : AsRef<std::path::Path>>(path : P) -> Result<Self, CoherentError> {
+        let mut calibration = DiscoveryPowerCalibration::default();
+        for (output, entry) in load_power_calibrations(path)? {
+            let laser = DiscoveryLaser::from_output_name(&output).ok_or_else(|| CoherentError::InvalidArgumentsError(
+                format!("Unknown Discovery output `{}` (expected `variable` or `fixed`)", output)
+            ))?;
+            calibration.set(&laser, Some(entry));
+        }
+        Ok(calibration)
+    }
+}
+
 /// Commands to change parameters of the DiscoveryNX
 #[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
 #[derive(Debug, PartialEq)]
@@ -55,13 +247,35 @@ pub enum DiscoveryNXCommands {
     Laser{state : LaserState}, // Set the laser to standby
     Shutter{laser : DiscoveryLaser, state: ShutterState}, // Open or close the shutter
     FaultClear, // Clear any faults
-    AlignmentMode{laser : DiscoveryLaser, alignment_mode_on : bool}, // Set the laser to alignment mode
-    Wavelength{wavelength_nm : f32}, // Set the wavelength
+    AlignmentMode{laser : DiscoveryLaser, alignment_mode_on : AlignmentMode}, // Set the laser to alignment mode
+    Wavelength{wavelength_nm : Nanometers}, // Set the wavelength
     Heartbeat,
-    GddCurve{curve_num : u8}, // Set the GDD calibration curve
+    GddCurve{curve_num : u8}, // Select the GDD calibration curve by number (`GDDCURVE=`)
     GddCurveN{curve_name : String}, // Set the GDD calibration curve by name
-    Gdd{gdd_val : f32},
+    Gdd{gdd_val : GddFs2}, // Set the GDD value itself (`GDD=`)
     SetCurveN{new_curve_name : String}, // Sets name of current calibration curve
+    /// Applies one of the laser's `presets`, see `Laser::apply_preset`. Not
+    /// a serial command: `send_command` expands it into the preset's.
+    ApplyPreset{name : String},
+}
+
+/// The main beam of the Discovery is the variable-wavelength one.
+impl TryFrom<CommonCommand> for DiscoveryNXCommands {
+    type Error = CoherentError;
+    fn try_from(command : CommonCommand) -> Result<Self, Self::Error> {
+        Ok(match command {
+            CommonCommand::OpenShutter => DiscoveryNXCommands::Shutter{
+                laser : DiscoveryLaser::VariableWavelength, state : ShutterState::Open
+            },
+            CommonCommand::CloseShutter => DiscoveryNXCommands::Shutter{
+                laser : DiscoveryLaser::VariableWavelength, state : ShutterState::Closed
+            },
+            CommonCommand::Standby => DiscoveryNXCommands::Laser{state : LaserState::Standby},
+            CommonCommand::On => DiscoveryNXCommands::Laser{state : LaserState::On},
+            CommonCommand::ClearFaults => DiscoveryNXCommands::FaultClear,
+            CommonCommand::SetWavelength(nm) => DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(nm)},
+        })
+    }
 }
 
 #[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
@@ -71,19 +285,81 @@ pub struct DiscoveryNXStatus {
     pub laser : LaserState,
     pub variable_shutter : ShutterState,
     pub fixed_shutter : ShutterState,
-    pub keyswitch : bool,
-    pub faults : u8,
-    pub fault_text : String,
+    pub keyswitch : Keyswitch,
+    pub faults : FaultFlags,
+    // The fields below marked `serde(default)` are `None` when the laser
+    // didn't report them, so they can be told apart from a reported zero.
+    #[cfg_attr(feature = "network", serde(default))]
+    pub fault_text : Option<String>, // `None` if the firmware refuses `?FT`
     pub tuning : TuningStatus,
-    pub alignment_var : bool,
-    pub alignment_fixed : bool,
+    pub alignment_var : AlignmentMode,
+    pub alignment_fixed : AlignmentMode,
     pub status : String,
     pub wavelength : f32,
-    pub power_var : f32,
+    #[cfg_attr(feature = "network", serde(default))]
+    pub power_var : Option<f32>, // `None` while tuning
     pub power_fixed : f32,
-    pub gdd_curve : i32,
-    pub gdd_curve_n : String,
-    pub gdd : f32,
+    pub calibrated_power_var : Option<f32>, // `None` if the output is uncalibrated
+    pub calibrated_power_fixed : Option<f32>,
+    #[cfg_attr(feature = "network", serde(default))]
+    pub gdd_curve : Option<i32>, // The GDD fields are `None` on firmware without GDD
+    #[cfg_attr(feature = "network", serde(default))]
+    pub gdd_curve_n : Option<String>,
+    #[cfg_attr(feature = "network", serde(default))]
+    pub gdd : Option<f32>,
+    pub locked : bool, // whether an `OperatorLock` is blocking commands
+    pub timestamp : f64, // seconds since the Unix epoch
+    // The diagnostics below are `None` unless `Discovery::diagnostics_in_status` is set
+    pub operating_hours : Option<f32>,
+    pub baseplate_temperature : Option<f32>, // °C
+    pub humidity : Option<f32>, // relative, in %
+    pub diode_current : Option<f32>, // A
+    pub heatsink_ok : Option<bool>,
+}
+
+impl LaserStatus for DiscoveryNXStatus {
+    fn faults(&self) -> u8 {
+        self.faults.bits()
+    }
+
+    fn is_emitting(&self) -> bool {
+        self.laser == LaserState::On
+            && (self.variable_shutter == ShutterState::Open || self.fixed_shutter == ShutterState::Open)
+    }
+
+    /// The variable-wavelength beam, 0 while it's tuning.
+    fn primary_power(&self) -> f32 {
+        self.power_var.unwrap_or(0.0)
+    }
+
+    fn timestamp(&self) -> std::time::SystemTime {
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs_f64(self.timestamp.max(0.0))
+    }
+}
+
+impl DiscoveryNXCommands {
+    /// The status field this command sets and the value it sets it to, for
+    /// a `ParameterHistory`. `None` for commands that don't set anything.
+    pub fn parameter_change(&self) -> Option<(&'static str, StatusValue)> {
+        let debug_text = |value : &dyn std::fmt::Debug| StatusValue::Text(format!("{:?}", value));
+        Some(match self {
+            DiscoveryNXCommands::Echo{echo_on} => ("echo", StatusValue::Bool(*echo_on)),
+            DiscoveryNXCommands::Laser{state} => ("laser", debug_text(state)),
+            DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::VariableWavelength, state} => ("variable_shutter", debug_text(state)),
+            DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::FixedWavelength, state} => ("fixed_shutter", debug_text(state)),
+            DiscoveryNXCommands::AlignmentMode{laser : DiscoveryLaser::VariableWavelength, alignment_mode_on} =>
+                ("alignment_var", StatusValue::Bool((*alignment_mode_on).into())),
+            DiscoveryNXCommands::AlignmentMode{laser : DiscoveryLaser::FixedWavelength, alignment_mode_on} =>
+                ("alignment_fixed", StatusValue::Bool((*alignment_mode_on).into())),
+            DiscoveryNXCommands::Wavelength{wavelength_nm} => ("wavelength", StatusValue::Float(wavelength_nm.0 as f64)),
+            DiscoveryNXCommands::GddCurve{curve_num} => ("gdd_curve", StatusValue::Integer(*curve_num as i64)),
+            DiscoveryNXCommands::GddCurveN{curve_name} => ("gdd_curve_n", StatusValue::Text(curve_name.clone())),
+            DiscoveryNXCommands::SetCurveN{new_curve_name} => ("gdd_curve_n", StatusValue::Text(new_curve_name.clone())),
+            DiscoveryNXCommands::Gdd{gdd_val} => ("gdd", StatusValue::Float(gdd_val.0 as f64)),
+            DiscoveryNXCommands::FaultClear | DiscoveryNXCommands::Heartbeat
+                | DiscoveryNXCommands::ApplyPreset{..} => return None,
+        })
+    }
 }
 
 impl LaserCommand for DiscoveryNXCommands {
@@ -91,24 +367,26 @@ impl LaserCommand for DiscoveryNXCommands {
         match &self {
             DiscoveryNXCommands::Echo{echo_on : echo} => format!("E={}", if *echo {"1"} else {"0"}),
             DiscoveryNXCommands::Laser{state} => format!("L={}", match state {
-                LaserState::Standby => "0",
+                LaserState::Standby | LaserState::Off => "0",
                 LaserState::On => "1",
             }),
             DiscoveryNXCommands::FaultClear => String::from("FC"),
             DiscoveryNXCommands::AlignmentMode{laser, alignment_mode_on : mode} => match laser {
-                DiscoveryLaser::VariableWavelength => format!("ALIGN={}", if *mode {"1"} else {"0"}),
-                DiscoveryLaser::FixedWavelength => format!("ALIGNFIXED={}", if *mode {"1"} else {"0"}),
+                DiscoveryLaser::VariableWavelength => format!("ALIGN={}", if *mode == AlignmentMode::On {"1"} else {"0"}),
+                DiscoveryLaser::FixedWavelength => format!("ALIGNFIXED={}", if *mode == AlignmentMode::On {"1"} else {"0"}),
             },
             DiscoveryNXCommands::Shutter{laser, state} => match laser {
                 DiscoveryLaser::VariableWavelength => format!("S={}", if *state == ShutterState::Open {"1"} else {"0"}),
                 DiscoveryLaser::FixedWavelength => format!("SFIXED={}", if *state == ShutterState::Open {"1"} else {"0"}),
             },
-            DiscoveryNXCommands::Wavelength{wavelength_nm : wavelength} => format!("WV={}", wavelength),
+            DiscoveryNXCommands::Wavelength{wavelength_nm : wavelength} => format!("WV={}", wavelength.0),
             DiscoveryNXCommands::Heartbeat => String::from("HB"),
-            DiscoveryNXCommands::GddCurve{curve_num : curve} => format!("GDD={}", curve),
+            DiscoveryNXCommands::GddCurve{curve_num : curve} => format!("GDDCURVE={}", curve),
             DiscoveryNXCommands::GddCurveN{curve_name : name} => format!("GDDCURVEN={}", name),
-            DiscoveryNXCommands::Gdd{gdd_val : gdd} => format!("GDD={}", gdd),
+            DiscoveryNXCommands::Gdd{gdd_val : gdd} => format!("GDD={}", gdd.0),
             DiscoveryNXCommands::SetCurveN{new_curve_name : name} => format!("SETCURVEN={}", name),
+            // Never sent, but named in logs and journals
+            DiscoveryNXCommands::ApplyPreset{name} => format!("PRESET={}", name),
         }
     }
 }
@@ -118,6 +396,15 @@ impl LaserCommand for DiscoveryNXCommands {
 pub mod DiscoveryNXQueries {
     use super::*;
 
+    /// A `0` or `1` reply. Anything else is garbled, not `false`.
+    fn parse_bit(result : &str) -> Result<bool, CoherentError> {
+        match result {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            _ => Err(CoherentError::InvalidResponseError(result.to_string())),
+        }
+    }
+
     #[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
     #[derive(Default, Debug)]
     pub struct Echo {}
@@ -129,7 +416,7 @@ pub mod DiscoveryNXQueries {
     impl Query for Echo {
         type Result = bool;
         fn parse_result(&self, result : &str) -> Result<Self::Result, CoherentError> {
-            Ok(result.contains("1"))
+            parse_bit(result)
         }
     }
 
@@ -189,9 +476,9 @@ pub mod DiscoveryNXQueries {
         }
     }
     impl Query for Keyswitch {
-        type Result = bool;
+        type Result = crate::laser::Keyswitch;
         fn parse_result(&self, result : &str) -> Result<Self::Result, CoherentError> {
-            Ok(result.contains("1"))
+            parse_bit(result).map(Into::into)
         }
     }
 
@@ -204,9 +491,9 @@ pub mod DiscoveryNXQueries {
         }
     }
     impl Query for Faults {
-        type Result = u8;
+        type Result = FaultFlags;
         fn parse_result(&self, result : &str) -> Result<Self::Result, CoherentError> {
-            Ok(result.parse().map_err(|_| CoherentError::InvalidResponseError(result.to_string()))?)
+            result.parse().map(FaultFlags::from_bits).map_err(|_| CoherentError::InvalidResponseError(result.to_string()))
         }
     }
 
@@ -258,9 +545,9 @@ pub mod DiscoveryNXQueries {
         }
     }
     impl Query for AlignmentMode {
-        type Result = bool;
+        type Result = crate::laser::AlignmentMode;
         fn parse_result(&self, result : &str) -> Result<Self::Result, CoherentError> {
-            Ok(result.contains("1"))
+            parse_bit(result).map(Into::into)
         }
     }
 
@@ -299,7 +586,7 @@ pub mod DiscoveryNXQueries {
     impl Query for Wavelength {
         type Result = f32;
         fn parse_result(&self, result : &str) -> Result<Self::Result, CoherentError> {
-            Ok(result.parse::<f32>().map_err(|_| CoherentError::InvalidResponseError(result.to_string()))?)
+            result.parse::<f32>().map_err(|_| CoherentError::InvalidResponseError(result.to_string()))
         }
     }
 
@@ -319,7 +606,7 @@ pub mod DiscoveryNXQueries {
     impl Query for Power {
         type Result = f32;
         fn parse_result(&self, result : &str) -> Result<Self::Result, CoherentError> {
-            Ok(result.parse().map_err(|_| CoherentError::InvalidResponseError(result.to_string()))?)
+            result.parse().map_err(|_| CoherentError::InvalidResponseError(result.to_string()))
         }
     }
 
@@ -334,7 +621,7 @@ pub mod DiscoveryNXQueries {
     impl Query for GddCurve {
         type Result = i32;
         fn parse_result(&self, result : &str) -> Result<Self::Result, CoherentError> {
-            Ok(result.parse().map_err(|_| CoherentError::InvalidResponseError(result.to_string()))?)
+            result.parse().map_err(|_| CoherentError::InvalidResponseError(result.to_string()))
         }
     }
 
@@ -364,7 +651,7 @@ pub mod DiscoveryNXQueries {
     impl Query for Gdd {
         type Result = f32;
         fn parse_result(&self, result : &str) -> Result<Self::Result, CoherentError> {
-            Ok(result.parse().map_err(|_| CoherentError::InvalidResponseError(result.to_string()))?)
+            result.parse().map_err(|_| CoherentError::InvalidResponseError(result.to_string()))
         }
     }
     
@@ -382,22 +669,171 @@ pub mod DiscoveryNXQueries {
             Ok(result.to_string())
         }
     }
+
+    // The diagnostic queries below (`?HH`, `?BT`, `?RH`, `?DC`, `?HS`) and
+    // their reply formats are unverified: they aren't from the Discovery NX
+    // operator's manual, and haven't been tried on a laser. Firmware that
+    // refuses them only loses those fields from the status.
+
+    /// Hours the laser has been on, in total.
+    #[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+    #[derive(Default, Debug)]
+    pub struct OperatingHours {}
+    impl LaserCommand for OperatingHours {
+        fn to_string(&self) -> String {
+            String::from("?HH")
+        }
+    }
+    impl Query for OperatingHours {
+        type Result = f32;
+        fn parse_result(&self, result : &str) -> Result<Self::Result, CoherentError> {
+            result.parse().map_err(|_| CoherentError::InvalidResponseError(result.to_string()))
+        }
+    }
+
+    /// The laser head's baseplate, in °C.
+    #[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+    #[derive(Default, Debug)]
+    pub struct BaseplateTemperature {}
+    impl LaserCommand for BaseplateTemperature {
+        fn to_string(&self) -> String {
+            String::from("?BT")
+        }
+    }
+    impl Query for BaseplateTemperature {
+        type Result = f32;
+        fn parse_result(&self, result : &str) -> Result<Self::Result, CoherentError> {
+            result.parse().map_err(|_| CoherentError::InvalidResponseError(result.to_string()))
+        }
+    }
+
+    /// Relative humidity inside the laser head, in percent.
+    #[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+    #[derive(Default, Debug)]
+    pub struct Humidity {}
+    impl LaserCommand for Humidity {
+        fn to_string(&self) -> String {
+            String::from("?RH")
+        }
+    }
+    impl Query for Humidity {
+        type Result = f32;
+        fn parse_result(&self, result : &str) -> Result<Self::Result, CoherentError> {
+            result.parse().map_err(|_| CoherentError::InvalidResponseError(result.to_string()))
+        }
+    }
+
+    /// The pump diodes' current, in A.
+    #[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+    #[derive(Default, Debug)]
+    pub struct DiodeCurrent {}
+    impl LaserCommand for DiodeCurrent {
+        fn to_string(&self) -> String {
+            String::from("?DC")
+        }
+    }
+    impl Query for DiodeCurrent {
+        type Result = f32;
+        fn parse_result(&self, result : &str) -> Result<Self::Result, CoherentError> {
+            result.parse().map_err(|_| CoherentError::InvalidResponseError(result.to_string()))
+        }
+    }
+
+    /// Whether the heatsink is within its operating temperature (`1`) or
+    /// not (`0`).
+    #[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+    #[derive(Default, Debug)]
+    pub struct HeatsinkStatus {}
+    impl LaserCommand for HeatsinkStatus {
+        fn to_string(&self) -> String {
+            String::from("?HS")
+        }
+    }
+    impl Query for HeatsinkStatus {
+        type Result = bool;
+        fn parse_result(&self, result : &str) -> Result<Self::Result, CoherentError> {
+            parse_bit(result)
+        }
+    }
+}
+
+
+/// Binds each name to the parsed reply to its query: one query at a time,
+/// or with `pipelined_status`, all sent before any reply is read. A query
+/// followed by `=> StatusField` is optional, for the fields some firmware
+/// can't report: its name is bound to an `Option`, `None` if the laser
+/// refuses it, and its field is added to the `unsupported_fields` so it
+/// isn't asked again.
+macro_rules! status_queries {
+    ($laser:expr; $($name:ident = $query:expr $(=> $field:expr)?),* $(,)?) => {
+        let mut replies = if $laser.pipelined_status {
+            let queries = [$(status_queries!(@asked $laser, $query $(, $field)?)),*].into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+            Some($laser.query_pipelined(&queries)?.into_iter())
+        } else { None };
+        $(
+            let $name = status_queries!(@reply $laser, replies, $query $(, $field)?);
+        )*
+    };
+    (@asked $laser:expr, $query:expr) => {
+        Some($query.to_string())
+    };
+    (@asked $laser:expr, $query:expr, $field:expr) => {
+        (!$laser.unsupported_fields.contains(&$field)).then(|| $query.to_string())
+    };
+    (@read $laser:expr, $replies:ident, $query:expr) => {
+        match $replies.as_mut() {
+            Some(replies) => {
+                let reply = replies.next().ok_or(CoherentError::InvalidResponseError(String::new()))?;
+                let parsed = parse_reply(&$query, &reply);
+                $laser.invalid_response_hook.check(&$query.to_string(), parsed)
+            },
+            None => $laser.query($query),
+        }
+    };
+    (@reply $laser:expr, $replies:ident, $query:expr) => {
+        status_queries!(@read $laser, $replies, $query)?
+    };
+    (@reply $laser:expr, $replies:ident, $query:expr, $field:expr) => {
+        if $laser.unsupported_fields.contains(&$field) {
+            None
+        } else {
+            match status_queries!(@read $laser, $replies, $query) {
+                Err(CoherentError::CommandNotExecutedError) => {
+                    $laser.unsupported_fields.insert($field);
+                    None
+                },
+                result => Some(result?),
+            }
+        }
+    };
 }
 
+/// Parses the laser's `reply` to `query`, stripped of any echo and prompt.
+/// `CommandNotExecutedError` if the laser refused the query, as firmware
+/// that doesn't know it does.
+fn parse_reply<Q : Query>(query : &Q, reply : &str) -> Result<Q::Result, CoherentError> {
+    if reply.contains("COMMAND NOT EXECUTED") {
+        return Err(CoherentError::CommandNotExecutedError);
+    }
+    query.parse_result(reply)
+}
 
 impl Laser for Discovery {
     type CommandEnum = DiscoveryNXCommands;
     
-    #[cfg(feature = "network")]
     type LaserStatus = DiscoveryNXStatus;
+    type Limits = SoftLimits;
 
     fn send_serial_command(&mut self, command : &str) -> Result<(), CoherentError> {
+        if let Some(deadline) = self.deadline { deadline.check()?; }
         let command = command.to_string() + "\r\n"; // Need to end with <CR><LF>
         self.port.write_all(command.as_bytes()).map_err(
-            |e| CoherentError::WriteError(e)
+            CoherentError::WriteError
         )?;
         self.port.flush().map_err(
-            |e| CoherentError::WriteError(e)
+            CoherentError::WriteError
         )?;
         Ok(())
     }
@@ -406,7 +842,7 @@ impl Laser for Discovery {
     fn is_valid_device(serialportinfo : &serialport::SerialPortInfo)->bool {
         match &serialportinfo.port_type {
             serialport::SerialPortType::UsbPort(info) => {
-                LaserType::from(info.pid.clone()) == LaserType::DiscoveryNX
+                LaserType::from(info.pid) == LaserType::DiscoveryNX
             },
             _ => false
         }
@@ -425,70 +861,33 @@ impl Laser for Discovery {
     /// # Example
     /// 
     /// ```no_run
-    /// let port_info = serialport::available_ports().unwrap().into_iter().filter(|port| {
-    ///    DiscoveryNX::is_valid_device(port)
-    /// }).next().unwrap();
+    /// use coherent_rs::{Discovery, get_all_coherent_devices, laser::Laser};
+    /// let port_info = get_all_coherent_devices().unwrap().into_iter().next().unwrap();
     /// 
-    /// let discovery = DiscoveryNX::from_port_info(&port_info);
+    /// let discovery = Discovery::from_port_info(&port_info);
     /// ```
     fn from_port_info(serialportinfo : &serialport::SerialPortInfo)-> Result<Self, CoherentError> {
-        let mut serial_port = match serialport::new(&serialportinfo.port_name, BAUDRATE)
+        Discovery::from_port_info_with(serialportinfo, &LaserOpenOptions::default())
+    }
+
+    /// Opens the port at `options.baud_rate` (19200 baud if `None`) with
+    /// `options.timeout`, and queries with `options.retry_policy`. With
+    /// `options.transcript`, the port is tapped before the handshake.
+    fn from_port_info_with(serialportinfo : &serialport::SerialPortInfo, options : &LaserOpenOptions)
+        -> Result<Self, CoherentError> {
+        let serial_port = match serialport::new(&serialportinfo.port_name, options.baud_rate.unwrap_or(BAUDRATE))
             .data_bits(DATABITS)
             .stop_bits(STOPBITS)
             .parity(PARITY)
-            .timeout(std::time::Duration::from_secs(2))
+            .timeout(options.timeout)
             .open() {
                 Ok(port) => port,
                 Err(e) => return Err(CoherentError::SerialError(e)),
             };
 
-        serial_port.clear(serialport::ClearBuffer::Input)
-            .map_err(|e| CoherentError::SerialError(e))?;
-
-        // First check if Echo is on
-        serial_port.write_all("?E\r\n".to_string().as_bytes()).map_err(
-            |e| CoherentError::WriteError(e)
-        )?;
-        serial_port.flush().map_err(
-            |e| CoherentError::WriteError(e)
-        )?;
-
-        // Read the result
-        let mut buf = String::new();
-        let mut reader = std::io::BufReader::new(&mut serial_port);
-        reader.read_line(&mut buf)
-            .map_err(|_| CoherentError::InvalidResponseError("Error reading line".to_string()))?;
-        let echo_on = buf.contains("E 1\r\n");
-        let prompt_on = buf.contains("Chameleon");
-        if !buf.contains("\r\n") { return Err(CoherentError::InvalidResponseError(buf)); }
-
-        // Get the serial number
-        serial_port.write_all(
-            "?SN\r\n".to_string().as_bytes()
-        ).map_err(|e| CoherentError::WriteError(e))?;
-        serial_port.flush().map_err(|e| CoherentError::WriteError(e))?;
-
-
-        let mut buf = String::new();
-        let mut reader = std::io::BufReader::new(&mut serial_port);
-        reader.read_line(&mut buf)
-            .map_err(|_| CoherentError::InvalidResponseError("Error reading line".to_string()))?;
-        if !buf.contains("\r\n") { return Err(CoherentError::InvalidResponseError(buf)); }
-
-        let serial_num : &str;
-        if echo_on { serial_num = buf.split("?SN ").collect::<Vec<&str>>()[1].trim(); }
-        else { serial_num = buf.trim(); }
-        
-        // serial_port.clear(serialport::ClearBuffer::All)
-        //     .map_err(|e| CoherentError::SerialError(e))?; 
-
-
-        Ok(Discovery{
-            port : serial_port,
-            serial_number : serial_num.to_string(),
-            echo : echo_on,
-            _prompt : prompt_on,
-        })
+        let mut discovery = Discovery::from_tapped_port(serial_port, options.transcript.as_ref())?;
+        discovery.retry_policy = options.retry_policy.clone();
+        Ok(discovery)
     }
 
     /// Interface for sending a command to change laser settings.
@@ -504,49 +903,69 @@ impl Laser for Discovery {
     /// # Example
     /// 
     /// ```no_run
+    /// use coherent_rs::{Discovery, DiscoveryNXCommands};
+    /// use coherent_rs::laser::{Laser, DiscoveryLaser, ShutterState};
     /// let mut discovery = Discovery::find_first().unwrap();
     /// discovery.send_command(
-    ///     DiscoveryNXCommands::Shutter(
-    ///         (DiscoveryLaser::VariableWavelength, ShutterState::Closed)
-    ///      )
+    ///     DiscoveryNXCommands::Shutter{
+    ///         laser : DiscoveryLaser::VariableWavelength,
+    ///         state : ShutterState::Closed
+    ///     }
     /// ).unwrap();
     /// ```
     fn send_command(&mut self, command : DiscoveryNXCommands) -> Result<(), CoherentError> {
-        let command_str = command.to_string();
-        self.send_serial_command(&command_str)?;
-        // Confirm the echo
-        let mut buf = String::new();
-        let mut reader = std::io::BufReader::new(&mut self.port);
-        reader.read_line(&mut buf)
-            .map_err(|_| CoherentError::InvalidResponseError("Error reading line".to_string()))?;
-        if buf.contains("COMMAND NOT EXECUTED") {
-            return Err(CoherentError::CommandNotExecutedError);
-        }
-        if self._prompt {buf = buf.split("Chameleon>").collect::<Vec<&str>>()[1].to_string();}
-        if self.echo {
-            let split_on_command = buf.split(&(command_str.clone()+" ")).collect::<Vec<&str>>();
-            if split_on_command.len() != 2 {
-                return Err(
-                    CoherentError::InvalidResponseError(
-                        format!{"Echo does not match command. Expected : {}, Got : {}", command_str, buf}
-                    )
-                )
+        let (text, started) = (LaserCommand::to_string(&command), std::time::Instant::now());
+        let result = traced!("send_command", text, {
+            self.operator_lock.check()?;
+            let command = self.soft_limits.apply(command)?;
+            if self.is_unchanged(&command)? { return Ok(()); }
+            self.preloaded_status = None;
+            match command {
+                DiscoveryNXCommands::Wavelength{wavelength_nm}
+                    if !self.tuning_hooks.is_empty() || self.wavelength_profile.is_some() => {
+                    self.tune_with_hooks(wavelength_nm.0)
+                },
+                DiscoveryNXCommands::ApplyPreset{name} => self.apply_preset(&name),
+                command => self.write_command(command),
             }
-            if split_on_command[1].trim() != "" {
-                return Err(CoherentError::InvalidArgumentsError(
-                    split_on_command[1].to_string()
-                ));
+        });
+        self.command_log.record(&text, started.elapsed(), &result);
+        result
+    }
+
+    /// Straight to `write_command`: no operator lock, soft limits,
+    /// `skip_unchanged`, tuning hooks or presets.
+    fn force_command(&mut self, command : DiscoveryNXCommands) -> Result<(), CoherentError> {
+        let (text, started) = (LaserCommand::to_string(&command), std::time::Instant::now());
+        let result = traced!("force_command", text, {
+            self.preloaded_status = None;
+            self.write_command(command)
+        });
+        self.command_log.record(&text, started.elapsed(), &result);
+        result
+    }
+
+    /// The settings the `wavelength_profile` applies after tuning are
+    /// checked and listed too, as are a preset's commands.
+    fn validate(&mut self, command : &DiscoveryNXCommands) -> Result<Vec<String>, CoherentError> {
+        self.operator_lock.check()?;
+        if let DiscoveryNXCommands::ApplyPreset{name} = command {
+            let mut serial = Vec::new();
+            for command in self.presets.find(name)?.commands() {
+                serial.extend(self.validate(&command)?);
             }
+            return Ok(serial);
         }
-        else {
-            if buf.trim() != "" {
-                return Err(CoherentError::InvalidResponseError(
-                    format!{"Expected no response, Got : {}", buf}
-                ));
+        let clamped = self.soft_limits.clamped(command);
+        let command = clamped.as_ref().unwrap_or(command);
+        self.soft_limits.check(command)?;
+        let mut serial = vec![command.to_string()];
+        if let (DiscoveryNXCommands::Wavelength{wavelength_nm}, Some(profile)) = (command, self.wavelength_profile.as_ref()) {
+            for command in profile.commands_for(wavelength_nm.0) {
+                serial.push(self.soft_limits.apply(command)?.to_string());
             }
         }
-
-        Ok(())
+        Ok(serial)
     }
 
     /// Send a query to the laser that expects a response
@@ -561,124 +980,92 @@ impl Laser for Discovery {
     /// 
     /// # Example
     /// 
-    /// ```
-    /// let mut discovery = DiscoveryNX::find_first().unwrap();
-    /// let wavelength = discovery.query(DiscoveryNXQueries::Wavelength).unwrap();
+    /// ```no_run
+    /// use coherent_rs::{Discovery, DiscoveryNXQueries, laser::Laser};
+    /// let mut discovery = Discovery::find_first().unwrap();
+    /// let wavelength = discovery.query(DiscoveryNXQueries::Wavelength{}).unwrap();
     /// println!("Wavelength : {:?}", wavelength);
     /// ```
     fn query<Q:Query>(&mut self, query : Q) -> Result<Q::Result, CoherentError> {
-        let query_str = query.to_string();
-        self.send_serial_command(&query_str)?;
-        self.port.flush()
-            .map_err(|e| CoherentError::InvalidResponseError(e.to_string()))?;
-        let mut buf = String::new();
-        let mut reader = std::io::BufReader::new(&mut self.port);
-        reader.read_line(&mut buf)
-            .map_err(|_| CoherentError::InvalidResponseError("Error reading line".to_string()))?;
-        if self._prompt {buf = buf.split("Chameleon>").collect::<Vec<&str>>()[1].to_string();}
-        let buf : Vec<&str> = buf.trim().split(&(query_str+" ")).collect();
-        let buf = match self.echo {
-            false => buf[0],
-            true => buf[1],
-        };
-        self.port.flush().map_err(|e| CoherentError::InvalidResponseError(e.to_string()))?;
-        query.parse_result(buf)
+        traced!("query", LaserCommand::to_string(&query), {
+            self.with_reconnect(|laser| laser.query_retrying(&query))
+        })
     }
 
-    #[cfg(feature = "network")]
     /// Query the laser for all settings and return a struct containing all of them.
+    /// See `pipelined_status` to speed this up.
     fn status(&mut self) -> Result<Self::LaserStatus, CoherentError> {
-        let echo = self.query(
-            DiscoveryNXQueries::Echo{}
-        )?;
-
-        let laser = self.query(
-            DiscoveryNXQueries::Laser{}
-        )?;
-
-        let variable_shutter = self.query(
-            DiscoveryNXQueries::Shutter{laser : DiscoveryLaser::VariableWavelength}
-        )?;
-
-        let fixed_shutter = self.query(
-            DiscoveryNXQueries::Shutter{laser : DiscoveryLaser::FixedWavelength}
-        )?;
-
-        let keyswitch = self.query(
-            DiscoveryNXQueries::Keyswitch{}
-        )?;
-
-        let faults = self.query(
-            DiscoveryNXQueries::Faults{}
-        )?;
-
-        let fault_text = self.query(
-            DiscoveryNXQueries::FaultText{}
-        )?;
-
-        let tuning = self.query(
-            DiscoveryNXQueries::Tuning{}
-        )?;
-
-        let alignment_var = self.query(
-            DiscoveryNXQueries::AlignmentMode{laser : DiscoveryLaser::VariableWavelength}
-        )?;
-
-        let alignment_fixed = self.query(
-            DiscoveryNXQueries::AlignmentMode{laser : DiscoveryLaser::FixedWavelength}
-        )?;
-
-        let status = self.query(
-            DiscoveryNXQueries::Status{}
-        )?;
-
-        let wavelength = self.query(
-            DiscoveryNXQueries::Wavelength{}
-        )?;
-
-        let power_var = self.query(
-            DiscoveryNXQueries::Power{laser : DiscoveryLaser::VariableWavelength}
-        )?;
-
-        let power_fixed = self.query(
-            DiscoveryNXQueries::Power{laser : DiscoveryLaser::FixedWavelength}
-        )?;
-
-        let gdd_curve = self.query(
-            DiscoveryNXQueries::GddCurve{}
-        )?;
-
-        let gdd_curve_n = self.query(
-            DiscoveryNXQueries::GddCurveN{}
-        )?;
-
-        let gdd = self.query(
-            DiscoveryNXQueries::Gdd{}
-        )?;
+        traced!("status", "status", {
+            if let Some(status) = self.preloaded_status.take() {
+                return Ok(status);
+            }
+            status_queries!(self;
+                echo = DiscoveryNXQueries::Echo{},
+                laser = DiscoveryNXQueries::Laser{},
+                variable_shutter = DiscoveryNXQueries::Shutter{laser : DiscoveryLaser::VariableWavelength},
+                fixed_shutter = DiscoveryNXQueries::Shutter{laser : DiscoveryLaser::FixedWavelength},
+                keyswitch = DiscoveryNXQueries::Keyswitch{},
+                faults = DiscoveryNXQueries::Faults{},
+                fault_text = DiscoveryNXQueries::FaultText{} => StatusField::FaultText,
+                tuning = DiscoveryNXQueries::Tuning{},
+                alignment_var = DiscoveryNXQueries::AlignmentMode{laser : DiscoveryLaser::VariableWavelength},
+                alignment_fixed = DiscoveryNXQueries::AlignmentMode{laser : DiscoveryLaser::FixedWavelength},
+                status = DiscoveryNXQueries::Status{},
+                wavelength = DiscoveryNXQueries::Wavelength{},
+                power_var = DiscoveryNXQueries::Power{laser : DiscoveryLaser::VariableWavelength},
+                power_fixed = DiscoveryNXQueries::Power{laser : DiscoveryLaser::FixedWavelength},
+                gdd_curve = DiscoveryNXQueries::GddCurve{} => StatusField::GddCurve,
+                gdd_curve_n = DiscoveryNXQueries::GddCurveN{} => StatusField::GddCurveN,
+                gdd = DiscoveryNXQueries::Gdd{} => StatusField::Gdd,
+            );
+            let laser = LaserState::resolve(laser, keyswitch, !faults.is_empty());
+            // Mid-tune the variable beam's power reading is meaningless
+            let power_var = (tuning == TuningStatus::Ready).then_some(power_var);
+            let diagnostics = if self.diagnostics_in_status { self.diagnostics()? } else { Default::default() };
+            self.last_wavelength_nm = Some(wavelength);
+            self.known.extend([
+                ("wavelength", StatusValue::Float(wavelength as f64)),
+                ("variable_shutter", StatusValue::Text(format!("{:?}", variable_shutter))),
+                ("fixed_shutter", StatusValue::Text(format!("{:?}", fixed_shutter))),
+            ]);
+            if let Some(gdd) = gdd {
+                self.known.insert("gdd", StatusValue::Float(gdd as f64));
+            }
 
-        Ok(DiscoveryNXStatus{
-            echo,
-            laser,
-            variable_shutter,
-            fixed_shutter,
-            keyswitch,
-            faults,
-            fault_text,
-            tuning,
-            alignment_var,
-            alignment_fixed,
-            status,
-            wavelength,
-            power_var,
-            power_fixed,
-            gdd_curve,
-            gdd_curve_n,
-            gdd,
+            Ok(DiscoveryNXStatus{
+                echo,
+                laser,
+                variable_shutter,
+                fixed_shutter,
+                keyswitch,
+                faults,
+                fault_text,
+                tuning,
+                alignment_var,
+                alignment_fixed,
+                status,
+                wavelength,
+                power_var,
+                power_fixed,
+                calibrated_power_var : power_var.and_then(|power| self.power_calibration.apply(&DiscoveryLaser::VariableWavelength, power)),
+                calibrated_power_fixed : self.power_calibration.apply(&DiscoveryLaser::FixedWavelength, power_fixed),
+                gdd_curve,
+                gdd_curve_n,
+                gdd,
+                locked : self.operator_lock.is_locked(),
+                timestamp : crate::laser::unix_timestamp(),
+                operating_hours : diagnostics.0,
+                baseplate_temperature : diagnostics.1,
+                humidity : diagnostics.2,
+                diode_current : diagnostics.3,
+                heatsink_ok : diagnostics.4,
+            })
         })
     }
 
     /// Query the laser for all settings and return a serialized version
-    /// to be passed through a socket. Average speed is ~70 ms.
+    /// to be passed through a socket. Average speed is ~70 ms, less with
+    /// `pipelined_status`.
     #[cfg(feature = "network")]
     fn serialized_status(&mut self) -> Result<Vec<u8>, CoherentError>{
         let laser_status = self.status()?;
@@ -692,49 +1079,824 @@ impl Laser for Discovery {
         Ok(buf)
     }
 
-    fn into_laser_type() -> LaserType {
-        LaserType::DiscoveryNX
+    fn operator_lock(&mut self) -> Option<&mut OperatorLock> {
+        Some(&mut self.operator_lock)
     }
 
-}
+    fn parameter_history(&mut self) -> Option<&mut ParameterHistory> {
+        self.parameter_history.as_mut()
+    }
 
-/// Convenience functions
-impl Discovery {
+    fn soft_limits(&mut self) -> Option<&mut SoftLimits> {
+        Some(&mut self.soft_limits)
+    }
 
-    /// Set the wavelength of the variable-wavelength laser
-    /// 
-    /// # Arguments
-    /// 
-    /// * `wavelength` - The wavelength to set the laser to (in nanometers).
-    /// 
-    /// # Example
-    /// 
-    /// ```
-    /// let mut discovery = Discovery::find_first().unwrap();
-    /// discovery.set_wavelength(840.0).unwrap();
-    /// ```
-    pub fn set_wavelength(&mut self, wavelength : f32) -> Result<(), CoherentError> {
-        self.send_command(DiscoveryNXCommands::Wavelength{wavelength_nm : wavelength})
+    fn raw_command(&mut self, command : &str) -> Result<String, CoherentError> {
+        self.with_reconnect(|laser| {
+            laser.send_serial_command(command)?;
+            Ok(laser.read_reply()?.trim_end_matches(['\r', '\n']).to_string())
+        })
     }
 
-    pub fn get_wavelength(&mut self) -> Result<f32, CoherentError> {
-        self.query(DiscoveryNXQueries::Wavelength{})
+    /// Reopens the port with `reconnect`.
+    fn recover_link(&mut self) -> Result<(), CoherentError> {
+        self.reconnect()
     }
 
-    pub fn set_gdd(&mut self, gdd : f32) -> Result<(), CoherentError> {
-        self.send_command(DiscoveryNXCommands::Gdd{gdd_val : gdd})
+    fn fault_report(&mut self) -> Result<FaultReport, CoherentError> {
+        Ok(FaultReport{code : self.get_faults()?.bits(), text : self.get_fault_text()?})
     }
 
-    pub fn get_gdd(&mut self) -> Result<f32, CoherentError> {
-        self.query(DiscoveryNXQueries::Gdd{})
+    fn tuning_status(&mut self) -> Result<TuningStatus, CoherentError> {
+        self.get_tuning()
     }
 
-    pub fn set_shutter(&mut self, laser : DiscoveryLaser, state : ShutterState) -> Result<(), CoherentError> {
-        self.send_command(DiscoveryNXCommands::Shutter{laser, state})
+    /// Also gives up if a `with_timeout` deadline passes first.
+    fn wait_for_tuning(&mut self, timeout : std::time::Duration, poll_interval : std::time::Duration)
+        -> Result<(), CoherentError> {
+        let deadline = self.deadline.map_or(Deadline::after(timeout), |outer| outer.min(Deadline::after(timeout)));
+        let cancellation = self.cancel_token.watch();
+        while self.get_tuning()? == TuningStatus::Tuning {
+            deadline.check()?;
+            cancellation.check()?;
+            std::thread::sleep(deadline.cap(poll_interval));
+        }
+        Ok(())
     }
 
-    pub fn get_shutter(&mut self, laser : DiscoveryLaser) -> Result<ShutterState, CoherentError> {
-        self.query(DiscoveryNXQueries::Shutter{laser})
+    fn cancel_token(&self) -> Option<CancelToken> {
+        Some(self.cancel_token.clone())
+    }
+
+    /// The `restore::SNAPSHOT_FIELDS`, read with `status`.
+    fn snapshot(&mut self) -> Result<LaserSnapshot, CoherentError> {
+        Ok(restore::snapshot(&self.status()?))
+    }
+
+    /// Waits up to the `tuning_hooks.settle_timeout` for tuning to finish.
+    fn restore(&mut self, snapshot : &LaserSnapshot) -> Result<(), CoherentError> {
+        restore::restore(self, snapshot, self.tuning_hooks.settle_timeout)
+    }
+
+    /// Waits up to the `tuning_hooks.settle_timeout` for tuning to finish.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use coherent_rs::{Discovery, laser::Laser};
+    /// use coherent_rs::laser::discoverynx::presets::Presets;
+    ///
+    /// let mut discovery = Discovery::find_first().unwrap();
+    /// discovery.presets = Presets::load("presets.toml").unwrap();
+    /// discovery.apply_preset("imaging-920").unwrap();
+    /// ```
+    fn apply_preset(&mut self, name : &str) -> Result<(), CoherentError> {
+        let commands = self.presets.find(name)?.commands();
+        presets::send_settling(self, commands, self.tuning_hooks.settle_timeout)
+    }
+
+    /// How far the latest wavelength change has got, reading the wavelength
+    /// and tuning status to find out. `None` if no wavelength has been set.
+    /// It starts from the last wavelength read before the change (by
+    /// `status`, `get_wavelength`, or tuning hooks); if there wasn't one,
+    /// from where the laser is at the first call.
+    fn tuning_progress(&mut self) -> Result<Option<TuningProgress>, CoherentError> {
+        let Some(TuningMove{from_nm, to_nm, started}) = self.tuning_move else { return Ok(None) };
+        let ready = self.get_tuning()? == TuningStatus::Ready;
+        let current_nm = self.get_wavelength()?;
+        let from_nm = from_nm.unwrap_or(current_nm);
+        self.tuning_move = Some(TuningMove{from_nm : Some(from_nm), to_nm, started});
+        Ok(Some(TuningProgress::estimate(from_nm, to_nm, current_nm, started.elapsed(), ready)))
+    }
+
+    fn capabilities(&self) -> LaserCapabilities {
+        LaserCapabilities{
+            laser_type : LaserType::DiscoveryNX,
+            tuning_range_nm : Some(profile::WAVELENGTH_RANGE_NM),
+            gdd_range_fs2 : Some(GDD_RANGE_FS2),
+            outputs : 2,
+            features : FEATURES.to_vec(),
+        }
+    }
+
+    fn into_laser_type() -> LaserType {
+        LaserType::DiscoveryNX
+    }
+
+}
+
+impl Discovery {
+    /// Settings to open a `Discovery` with. See `DiscoveryBuilder`.
+    pub fn builder() -> DiscoveryBuilder {
+        DiscoveryBuilder::new()
+    }
+
+    /// The prompt the laser puts before each line, as found on connecting.
+    pub fn terminal_style(&self) -> TerminalStyle {
+        self.terminal
+    }
+
+    /// Wraps an already-open serial port (e.g. a `MockSerialPort` in tests)
+    /// and performs the same handshake as `from_port_info`: checks whether
+    /// echo and the prompt are on, then reads the serial number.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use coherent_rs::{Discovery, laser::mock::MockSerialPort};
+    ///
+    /// let port = MockSerialPort::discovery(true, false, "SN1234");
+    /// let discovery = Discovery::from_serial_port(Box::new(port)).unwrap();
+    /// assert_eq!(discovery.serial_number, "SN1234");
+    /// ```
+    pub fn from_serial_port(mut serial_port : Box<dyn serialport::SerialPort>) -> Result<Self, CoherentError> {
+        serial_port.clear(serialport::ClearBuffer::Input)
+            .map_err(CoherentError::SerialError)?;
+
+        // First check if Echo is on
+        serial_port.write_all("?E\r\n".to_string().as_bytes()).map_err(
+            CoherentError::WriteError
+        )?;
+        serial_port.flush().map_err(
+            CoherentError::WriteError
+        )?;
+
+        // Read the result
+        let mut buf = String::new();
+        let mut reader = std::io::BufReader::new(&mut serial_port);
+        reader.read_line(&mut buf)
+            .map_err(|_| CoherentError::InvalidResponseError("Error reading line".to_string()))?;
+        let echo_on = buf.contains("E 1\r\n");
+        let terminal = TerminalStyle::detect(&buf);
+        if !buf.contains("\r\n") { return Err(CoherentError::InvalidResponseError(buf)); }
+
+        // Get the serial number
+        serial_port.write_all(
+            "?SN\r\n".to_string().as_bytes()
+        ).map_err(CoherentError::WriteError)?;
+        serial_port.flush().map_err(CoherentError::WriteError)?;
+
+
+        let mut buf = String::new();
+        let mut reader = std::io::BufReader::new(&mut serial_port);
+        reader.read_line(&mut buf)
+            .map_err(|_| CoherentError::InvalidResponseError("Error reading line".to_string()))?;
+        if !buf.contains("\r\n") { return Err(CoherentError::InvalidResponseError(buf)); }
+
+        let serial_num = terminal.strip_reply(&buf, "?SN", echo_on)?;
+
+        Ok(Discovery{
+            port : serial_port,
+            serial_number : serial_num.to_string(),
+            echo : echo_on,
+            terminal,
+            power_calibration : DiscoveryPowerCalibration::default(),
+            tuning_hooks : TuningHooks::default(),
+            wavelength_profile : None,
+            beam_roles : BeamRoles::default(),
+            soft_limits : SoftLimits::default(),
+            operator_lock : OperatorLock::default(),
+            parameter_history : None,
+            retry_policy : RetryPolicy::default(),
+            command_retry_policy : RetryPolicy::default(),
+            pipelined_status : false,
+            invalid_response_hook : InvalidResponseHook::default(),
+            verify_commands : false,
+            diagnostics_in_status : false,
+            reconnect_policy : None,
+            skip_unchanged : SkipUnchanged::Never,
+            no_reply : false,
+            deadline : None,
+            preloaded_status : None,
+            unsupported_fields : BTreeSet::new(),
+            last_wavelength_nm : None,
+            known : HashMap::new(),
+            tuning_move : None,
+            transcript : None,
+            cancel_token : CancelToken::new(),
+            presets : Presets::new(),
+            persisted_snapshot : None,
+            command_log : CommandLog::default(),
+        })
+    }
+
+    /// `from_serial_port`, recording everything on the port in `transcript`
+    /// if there is one.
+    pub(crate) fn from_tapped_port(port : Box<dyn serialport::SerialPort>, transcript : Option<&SerialTranscript>)
+        -> Result<Self, CoherentError> {
+        let Some(transcript) = transcript else { return Discovery::from_serial_port(port) };
+        let mut discovery = Discovery::from_serial_port(transcript.tap(port))?;
+        discovery.transcript = Some(transcript.clone());
+        Ok(discovery)
+    }
+
+    /// The status fields this laser's firmware can't report -- it refused
+    /// the query for them -- which `status` leaves at their defaults without
+    /// asking again. Only the GDD fields are ever left out: an older head
+    /// without them is still usable.
+    pub fn unsupported_fields(&self) -> &BTreeSet<StatusField> {
+        &self.unsupported_fields
+    }
+
+    /// Asks for every status field again, e.g. after a firmware update, or
+    /// if the laser refused a query only because it was busy.
+    pub fn forget_unsupported_fields(&mut self) {
+        self.unsupported_fields.clear();
+    }
+
+    /// Where the port's traffic is being recorded, if it's tapped.
+    pub fn transcript(&self) -> Option<&SerialTranscript> {
+        self.transcript.as_ref()
+    }
+
+    /// Sends a query and parses the reply, under the `retry_policy`.
+    fn query_retrying<Q : Query>(&mut self, query : &Q) -> Result<Q::Result, CoherentError> {
+        let policy = self.retry_policy.clone();
+        let outer_deadline = self.deadline;
+        self.deadline = Deadline::earliest(outer_deadline, policy.deadline());
+        let mut attempt = 0;
+        let result = policy.run_until(self.deadline, || {
+            attempt += 1;
+            if attempt > 1 {
+                // Whatever the failed try left behind would be read as this try's reply
+                let _ = self.port.clear(serialport::ClearBuffer::Input);
+            }
+            let result = self.query_once(query);
+            self.invalid_response_hook.check(&query.to_string(), result)
+        });
+        self.deadline = outer_deadline;
+        result
+    }
+
+    /// Sends a query and parses the reply, once.
+    fn query_once<Q : Query>(&mut self, query : &Q) -> Result<Q::Result, CoherentError> {
+        let query_str = query.to_string();
+        self.send_serial_command(&query_str)?;
+        self.port.flush()
+            .map_err(|e| CoherentError::InvalidResponseError(e.to_string()))?;
+        let buf = self.read_reply()?;
+        let response = self.terminal.strip_reply(&buf, &query_str, self.echo)?;
+        self.port.flush().map_err(|e| CoherentError::InvalidResponseError(e.to_string()))?;
+        parse_reply(query, response)
+    }
+
+    /// Sends every query in `queries` before reading any reply, then reads
+    /// the replies in order, stripped of any echo and prompt. Retried as a
+    /// whole under the `retry_policy`.
+    fn query_pipelined(&mut self, queries : &[String]) -> Result<Vec<String>, CoherentError> {
+        self.with_reconnect(|laser| laser.query_pipelined_retrying(queries))
+    }
+
+    fn query_pipelined_retrying(&mut self, queries : &[String]) -> Result<Vec<String>, CoherentError> {
+        let policy = self.retry_policy.clone();
+        let outer_deadline = self.deadline;
+        self.deadline = Deadline::earliest(outer_deadline, policy.deadline());
+        let mut attempt = 0;
+        let result = policy.run_until(self.deadline, || {
+            attempt += 1;
+            if attempt > 1 {
+                let _ = self.port.clear(serialport::ClearBuffer::Input);
+            }
+            for query in queries {
+                self.send_serial_command(query)?;
+            }
+            self.read_replies(queries.len())?.iter().zip(queries)
+                .map(|(reply, query)| self.terminal.strip_reply(reply, query, self.echo).map(str::to_string))
+                .collect()
+        });
+        self.deadline = outer_deadline;
+        result
+    }
+
+    /// Reads a line from the laser. If there's a `deadline`, the read gives
+    /// up when it passes (with `TimeoutError`) even if the port would
+    /// otherwise wait longer.
+    fn read_reply(&mut self) -> Result<String, CoherentError> {
+        Ok(self.read_replies(1)?.remove(0))
+    }
+
+    /// Reads `count` lines through one buffer, so replies that arrive
+    /// together aren't lost between reads.
+    fn read_replies(&mut self, count : usize) -> Result<Vec<String>, CoherentError> {
+        let port_timeout = self.port.timeout();
+        if let Some(deadline) = self.deadline {
+            deadline.check()?;
+            self.port.set_timeout(deadline.cap(port_timeout)).map_err(CoherentError::SerialError)?;
+        }
+        let mut reader = std::io::BufReader::new(&mut self.port);
+        let read = (0..count).map(|_| {
+            let mut buf = String::new();
+            reader.read_line(&mut buf).map(|_| buf)
+        }).collect::<Result<Vec<_>, _>>();
+        drop(reader);
+        self.no_reply = read.is_err();
+        if self.deadline.is_some() {
+            let _ = self.port.set_timeout(port_timeout);
+        }
+        match read {
+            Ok(lines) => Ok(lines),
+            Err(_) if self.deadline.is_some_and(|deadline| deadline.is_expired()) => Err(CoherentError::TimeoutError),
+            Err(_) => Err(CoherentError::InvalidResponseError("Error reading line".to_string())),
+        }
+    }
+
+    /// Sends a command and checks the laser's reply, without running any
+    /// hooks, under the `command_retry_policy`. With `verify_commands`, reads
+    /// the setting back. Records the change in the `parameter_history`, if
+    /// there is one.
+    fn write_command(&mut self, command : DiscoveryNXCommands) -> Result<(), CoherentError> {
+        let change = command.parameter_change();
+        let before = match (&change, &self.parameter_history) {
+            (Some((parameter, _)), Some(_)) => Some(self.parameter_value(parameter)),
+            _ => None,
+        };
+        let (from_nm, started) = (self.last_wavelength_nm, std::time::Instant::now());
+        let policy = self.command_retry_policy.clone();
+        let outer_deadline = self.deadline;
+        self.deadline = Deadline::earliest(outer_deadline, policy.deadline());
+        let result = policy.run_until(self.deadline, || {
+            self.transmit_command(&command)?;
+            match (&change, self.verify_commands) {
+                (Some((parameter, expected)), true) => self.verify_parameter(parameter, expected),
+                _ => Ok(()),
+            }
+        });
+        self.deadline = outer_deadline;
+        if let Some((parameter, after)) = &change {
+            match result {
+                Ok(()) => self.known.insert(parameter, after.clone()),
+                Err(_) => self.known.remove(parameter),
+            };
+        }
+        result?;
+        if let DiscoveryNXCommands::Wavelength{wavelength_nm} = &command {
+            self.tuning_move = Some(TuningMove{from_nm, to_nm : wavelength_nm.0, started});
+        }
+        if let Some((parameter, after)) = &change {
+            self.persist_change(&command, parameter, after);
+        }
+        if let (Some((parameter, after)), Some(before)) = (change, before) {
+            self.record_change(parameter, before, after);
+        }
+        Ok(())
+    }
+
+    /// Whether `command` would leave things as they are, so the
+    /// `skip_unchanged` setting lets it be skipped. Only wavelength, GDD and
+    /// shutter commands are ever skipped.
+    fn is_unchanged(&mut self, command : &DiscoveryNXCommands) -> Result<bool, CoherentError> {
+        if !matches!(command, DiscoveryNXCommands::Wavelength{..} | DiscoveryNXCommands::Gdd{..} | DiscoveryNXCommands::Shutter{..}) {
+            return Ok(false);
+        }
+        let Some((parameter, value)) = command.parameter_change() else { return Ok(false) };
+        let current = match self.skip_unchanged {
+            SkipUnchanged::Never => return Ok(false),
+            SkipUnchanged::LastKnown => self.known.get(parameter).cloned(),
+            SkipUnchanged::Query => {
+                let current = self.read_parameter(parameter)?;
+                self.known.insert(parameter, current.clone());
+                Some(current)
+            },
+        };
+        Ok(current == Some(value))
+    }
+
+    /// Reads `parameter` back and checks it's `expected` (to within
+    /// `VERIFY_TOLERANCE`, for numbers).
+    fn verify_parameter(&mut self, parameter : &str, expected : &StatusValue) -> Result<(), CoherentError> {
+        let actual = self.read_parameter(parameter)?;
+        let matches = match (expected, &actual) {
+            (StatusValue::Float(expected), StatusValue::Float(actual)) => (expected - actual).abs() <= VERIFY_TOLERANCE,
+            (expected, actual) => expected == actual,
+        };
+        if matches { return Ok(()); }
+        Err(CoherentError::VerificationFailed{parameter : parameter.to_string(), expected : expected.clone(), actual})
+    }
+
+    /// The current value of the status field `parameter`, for the
+    /// `parameter_history`. `Missing` if it can't be read.
+    fn parameter_value(&mut self, parameter : &str) -> StatusValue {
+        self.read_parameter(parameter).unwrap_or(StatusValue::Missing)
+    }
+
+    /// Reads the status field `parameter` from the laser, in the form
+    /// `DiscoveryNXCommands::parameter_change` gives it. `Missing` for
+    /// fields no command sets.
+    fn read_parameter(&mut self, parameter : &str) -> Result<StatusValue, CoherentError> {
+        let debug_text = |value : &dyn std::fmt::Debug| StatusValue::Text(format!("{:?}", value));
+        match parameter {
+            "echo" => Ok(StatusValue::Bool(self.echo)),
+            "laser" => self.get_standby().map(|state| debug_text(&state)),
+            "variable_shutter" => self.get_shutter(DiscoveryLaser::VariableWavelength).map(|state| debug_text(&state)),
+            "fixed_shutter" => self.get_shutter(DiscoveryLaser::FixedWavelength).map(|state| debug_text(&state)),
+            "alignment_var" => self.get_alignment_mode(DiscoveryLaser::VariableWavelength).map(|mode| StatusValue::Bool(mode.into())),
+            "alignment_fixed" => self.get_alignment_mode(DiscoveryLaser::FixedWavelength).map(|mode| StatusValue::Bool(mode.into())),
+            "wavelength" => self.get_wavelength().map(|wavelength| StatusValue::Float(wavelength as f64)),
+            "gdd" => self.get_gdd().map(|gdd| StatusValue::Float(gdd as f64)),
+            "gdd_curve" => self.get_gdd_curve().map(|curve| StatusValue::Integer(curve as i64)),
+            "gdd_curve_n" => self.get_gdd_curve_n().map(StatusValue::Text),
+            _ => Ok(StatusValue::Missing),
+        }
+    }
+
+    /// Saves the snapshot `persist_snapshots` is keeping with `parameter`
+    /// set to `after`, if it's keeping one. As with `record_change`, failing
+    /// to save it doesn't fail the command.
+    fn persist_change(&mut self, command : &DiscoveryNXCommands, parameter : &str, after : &StatusValue) {
+        let Some((path, snapshot)) = self.persisted_snapshot.as_mut() else { return };
+        if let DiscoveryNXCommands::GddCurveN{..} = command {
+            // Selected by name, so the number saved is out of date
+            snapshot.settings.remove("gdd_curve");
+        }
+        if restore::is_snapshot_field(parameter) {
+            snapshot.settings.insert(parameter.to_string(), after.clone());
+        }
+        snapshot.taken = crate::laser::unix_timestamp();
+        if let Err(e) = snapshot.save(&*path) {
+            eprintln!("Could not save the snapshot after setting {}: {:?}", parameter, e);
+        }
+    }
+
+    /// Adds a change to the `parameter_history`, if there is one. The
+    /// command has already run, so failing to persist it doesn't fail it.
+    fn record_change(&mut self, parameter : &str, before : StatusValue, after : StatusValue) {
+        if let Some(history) = self.parameter_history.as_mut() {
+            if let Err(e) = history.record(parameter, before, after) {
+                eprintln!("Could not record {} in the parameter history: {:?}", parameter, e);
+            }
+        }
+    }
+
+    /// Sends a command and checks the laser's reply. The laser answers an
+    /// `Echo` command in the mode it's just been put in.
+    fn transmit_command(&mut self, command : &DiscoveryNXCommands) -> Result<(), CoherentError> {
+        let echo = self.echo;
+        if let DiscoveryNXCommands::Echo{echo_on} = command {
+            self.echo = *echo_on;
+        }
+        let command_str = command.to_string();
+        let result = self.with_reconnect(|laser| laser.transmit_command_str(&command_str));
+        if result.is_err() { self.echo = echo; }
+        result
+    }
+
+    fn transmit_command_str(&mut self, command_str : &str) -> Result<(), CoherentError> {
+        #[cfg(feature = "opentelemetry")]
+        let _span = crate::network::telemetry::serial_span(command_str);
+        self.send_serial_command(command_str)?;
+        // Confirm the echo
+        let mut buf = self.read_reply()?;
+        if buf.contains("COMMAND NOT EXECUTED") {
+            return Err(CoherentError::CommandNotExecutedError);
+        }
+        buf = self.terminal.strip_prompt(&buf)?.to_string();
+        if self.echo {
+            let split_on_command = buf.split(&(command_str.to_string()+" ")).collect::<Vec<&str>>();
+            if split_on_command.len() != 2 {
+                return Err(
+                    CoherentError::InvalidResponseError(
+                        format!{"Echo does not match command. Expected : {}, Got : {}", command_str, buf}
+                    )
+                )
+            }
+            if split_on_command[1].trim() != "" {
+                return Err(CoherentError::InvalidArgumentsError(
+                    split_on_command[1].to_string()
+                ));
+            }
+        }
+        else {
+            if buf.trim() != "" {
+                return Err(CoherentError::InvalidResponseError(
+                    format!{"Expected no response, Got : {}", buf}
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs `operation`, and if it fails as if the link dropped (per the
+    /// `reconnect_policy`), reconnects and runs it once more.
+    fn with_reconnect<T, F>(&mut self, mut operation : F) -> Result<T, CoherentError>
+    where F : FnMut(&mut Self) -> Result<T, CoherentError> {
+        self.no_reply = false;
+        match operation(self) {
+            Err(e) if self.deadline.is_none_or(|deadline| !deadline.is_expired())
+                && self.reconnect_policy.as_ref().is_some_and(|policy| policy.is_link_failure(&e, self.no_reply)) => {
+                self.reconnect()?;
+                operation(self)
+            },
+            result => result,
+        }
+    }
+
+    /// Tunes with the `tuning_hooks` around the change: the before-hooks, the
+    /// wavelength command, a wait for tuning to finish (only if there are
+    /// after-hooks or a profile), the `wavelength_profile` settings, then the
+    /// after-hooks. If the laser rejects the wavelength the after-hooks still
+    /// run, with `to_nm` set to the wavelength it stayed at, so anything the
+    /// before-hooks changed gets put back.
+    fn tune_with_hooks(&mut self, wavelength_nm : f32) -> Result<(), CoherentError> {
+        let from_nm = self.get_wavelength()?;
+        self.tuning_hooks.run_before(&TuningEvent{from_nm, to_nm : wavelength_nm})?;
+
+        if let Err(e) = self.write_command(DiscoveryNXCommands::Wavelength{wavelength_nm : wavelength_nm.into()}) {
+            self.tuning_hooks.run_after(&TuningEvent{from_nm, to_nm : from_nm})?;
+            return Err(e);
+        }
+        let profile_commands = self.wavelength_profile.as_ref()
+            .map(|profile| profile.commands_for(wavelength_nm))
+            .unwrap_or_default();
+        if self.tuning_hooks.has_after() || !profile_commands.is_empty() {
+            self.wait_for_tuning(self.tuning_hooks.settle_timeout, TUNING_POLL_INTERVAL)?;
+            for command in profile_commands {
+                let command = self.soft_limits.apply(command)?;
+                self.write_command(command)?;
+            }
+            self.tuning_hooks.run_after(&TuningEvent{from_nm, to_nm : wavelength_nm})?;
+        }
+        Ok(())
+    }
+}
+
+/// Convenience functions
+impl Discovery {
+
+    /// Refuses every state-changing command until `unlock` is called with
+    /// the same token. See `lock::OperatorLock`.
+    pub fn lock(&mut self, token : &str) -> Result<(), CoherentError> {
+        self.operator_lock.lock(token)
+    }
+
+    pub fn unlock(&mut self, token : &str) -> Result<(), CoherentError> {
+        self.operator_lock.unlock(token)
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.operator_lock.is_locked()
+    }
+
+    /// Reopens the port, finding the laser again by its serial number, as
+    /// the `reconnect_policy` says (or the default one, if there isn't one).
+    /// Echo is turned back on or off as it was; the prompt is taken as it's
+    /// found. `Reconnecting` if the laser can't be found.
+    ///
+    /// Called on its own when a call fails with a `reconnect_policy` set.
+    pub fn reconnect(&mut self) -> Result<(), CoherentError> {
+        let had_policy = self.reconnect_policy.is_some();
+        let mut policy = self.reconnect_policy.take().unwrap_or_default();
+        let reopened = policy.reopen(&self.serial_number);
+        if had_policy { self.reconnect_policy = Some(policy); }
+        let reopened = reopened?;
+        let echo = self.echo;
+        self.port = match (&self.transcript, &reopened.transcript) {
+            // Keep recording on the new port
+            (Some(transcript), None) => transcript.tap(reopened.port),
+            _ => reopened.port,
+        };
+        self.terminal = reopened.terminal;
+        self.preloaded_status = None;
+        if reopened.echo != echo {
+            // Answered in the mode it's being put in
+            self.echo = echo;
+            let command = DiscoveryNXCommands::Echo{echo_on : echo}.to_string();
+            if let Err(e) = self.transmit_command_str(&command) {
+                self.echo = reopened.echo;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `operation` with a wall-clock bound: every query, command and
+    /// wait in it (including retries under the `retry_policy`) gives up with
+    /// `TimeoutError` once `timeout` has passed. Nests -- the earliest
+    /// deadline wins.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use coherent_rs::{Discovery, laser::Laser};
+    /// let mut discovery = Discovery::find_first().unwrap();
+    /// // Never stall an acquisition loop for more than 2 s
+    /// let wavelength = discovery.with_timeout(Duration::from_secs(2), |discovery| {
+    ///     discovery.set_gdd(-5000.0)?;
+    ///     discovery.get_wavelength()
+    /// });
+    /// ```
+    pub fn with_timeout<T, F>(&mut self, timeout : std::time::Duration, operation : F) -> Result<T, CoherentError>
+    where F : FnOnce(&mut Self) -> Result<T, CoherentError> {
+        let outer_deadline = self.deadline;
+        self.deadline = Deadline::earliest(outer_deadline, Some(Deadline::after(timeout)));
+        let result = operation(self);
+        self.deadline = outer_deadline;
+        result
+    }
+
+    /// Runs `steps`, a batch of commands that belong together, with the
+    /// laser to itself and time between commands (see `Transaction`). If
+    /// `steps` fails, every setting it changed is put back before its error
+    /// is returned.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use coherent_rs::{Discovery, laser::Laser};
+    /// let mut discovery = Discovery::find_first().unwrap();
+    /// // Both, or neither
+    /// discovery.transaction(|tx| {
+    ///     tx.set_wavelength(1040.0)?;
+    ///     tx.set_gdd(-8000.0)
+    /// }).unwrap();
+    /// ```
+    pub fn transaction<T, F>(&mut self, steps : F) -> Result<T, CoherentError>
+    where F : FnOnce(&mut Transaction) -> Result<T, CoherentError> {
+        let mut transaction = Transaction::new(self);
+        let result = steps(&mut transaction);
+        if result.is_err() {
+            if let Err(e) = transaction.roll_back() {
+                eprintln!("Could not put back every setting after a failed transaction: {:?}", e);
+            }
+        }
+        result
+    }
+
+    /// Sends `command` with `verify_commands` on, whatever the laser's
+    /// setting: fails with `VerificationFailed` if the setting it changes
+    /// doesn't read back as what it was set to.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use coherent_rs::{Discovery, DiscoveryNXCommands, CoherentError};
+    /// use coherent_rs::laser::{Laser, DiscoveryLaser, ShutterState};
+    /// let mut discovery = Discovery::find_first().unwrap();
+    /// let open = DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::VariableWavelength, state : ShutterState::Open};
+    /// match discovery.send_command_verified(open) {
+    ///     Err(CoherentError::VerificationFailed{actual, ..}) => println!("Still {:?}", actual),
+    ///     result => result.unwrap(),
+    /// }
+    /// ```
+    pub fn send_command_verified(&mut self, command : DiscoveryNXCommands) -> Result<(), CoherentError> {
+        let verify = std::mem::replace(&mut self.verify_commands, true);
+        let result = self.send_command(command);
+        self.verify_commands = verify;
+        result
+    }
+
+    /// Set the wavelength of the variable-wavelength laser
+    /// 
+    /// # Arguments
+    /// 
+    /// * `wavelength` - The wavelength to set the laser to (in nanometers).
+    /// 
+    /// # Example
+    /// 
+    /// ```no_run
+    /// use coherent_rs::{Discovery, laser::Laser};
+    /// let mut discovery = Discovery::find_first().unwrap();
+    /// discovery.set_wavelength(840.0).unwrap();
+    /// ```
+    pub fn set_wavelength(&mut self, wavelength : impl Into<Nanometers>) -> Result<(), CoherentError> {
+        self.send_command(DiscoveryNXCommands::Wavelength{wavelength_nm : wavelength.into()})
+    }
+
+    /// Sets the wavelength, waits for the laser to finish tuning, and reads
+    /// the wavelength back. Returns the wavelength it reached, or
+    /// `CommandNotExecutedError` if that's more than `WAVELENGTH_TOLERANCE_NM`
+    /// from `wavelength`. The whole thing gives up with `TimeoutError` after
+    /// `timeout`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use coherent_rs::Discovery;
+    /// use coherent_rs::laser::Laser;
+    ///
+    /// let mut discovery = Discovery::find_first().unwrap();
+    /// let reached = discovery.set_wavelength_blocking(1040.0, Duration::from_secs(30)).unwrap();
+    /// println!("Tuned to {} nm", reached);
+    /// ```
+    pub fn set_wavelength_blocking(&mut self, wavelength : impl Into<Nanometers>, timeout : std::time::Duration) -> Result<f32, CoherentError> {
+        let wavelength = wavelength.into().0;
+        self.with_timeout(timeout, |discovery| {
+            discovery.set_wavelength(wavelength)?;
+            discovery.wait_for_tuning(timeout, TUNING_POLL_INTERVAL)?;
+            let reached = discovery.get_wavelength()?;
+            if (reached - wavelength).abs() > WAVELENGTH_TOLERANCE_NM {
+                return Err(CoherentError::CommandNotExecutedError);
+            }
+            Ok(reached)
+        })
+    }
+
+    pub fn get_wavelength(&mut self) -> Result<f32, CoherentError> {
+        let wavelength = self.query(DiscoveryNXQueries::Wavelength{})?;
+        self.last_wavelength_nm = Some(wavelength);
+        Ok(wavelength)
+    }
+
+    pub fn set_gdd(&mut self, gdd : impl Into<GddFs2>) -> Result<(), CoherentError> {
+        self.send_command(DiscoveryNXCommands::Gdd{gdd_val : gdd.into()})
+    }
+
+    pub fn get_gdd(&mut self) -> Result<f32, CoherentError> {
+        self.query(DiscoveryNXQueries::Gdd{})
+    }
+
+    /// Steps the GDD from `range.0` to `range.1` every `step` fs^2, waits
+    /// `dwell` at each, and calls `measure` for a signal that's largest
+    /// with the shortest pulses (e.g. two-photon fluorescence). Leaves the
+    /// GDD at the best one found, unless it's cancelled (see `cancel`).
+    /// See `sweep`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use coherent_rs::{Discovery, laser::Laser};
+    ///
+    /// let mut discovery = Discovery::find_first().unwrap();
+    /// # let read_photodiode = || 0.0;
+    /// let sweep = discovery.sweep_gdd((-10000.0, 0.0), 500.0, Duration::from_millis(200), read_photodiode).unwrap();
+    /// println!("Best GDD: {} fs^2", sweep.optimum_fs2);
+    /// ```
+    pub fn sweep_gdd(&mut self, range : (f32, f32), step : f32, dwell : std::time::Duration, measure : impl FnMut() -> f32)
+        -> Result<GddSweep, CoherentError> {
+        let cancellation = self.cancel_token.watch();
+        let sweep = sweep::run(range, step, dwell, &cancellation, |gdd| self.set_gdd(gdd), measure)?;
+        self.set_gdd(sweep.optimum_fs2)?;
+        Ok(sweep)
+    }
+
+    /// The last `n` commands sent through `send_command` (or all of them,
+    /// if there have been fewer), oldest first, with when each was sent and
+    /// how it went. See `command_log`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use coherent_rs::{Discovery, laser::Laser};
+    ///
+    /// let mut discovery = Discovery::find_first().unwrap();
+    /// discovery.set_wavelength(920.0).unwrap();
+    /// for record in discovery.command_history(20) {
+    ///     println!("{}", record);
+    /// }
+    /// ```
+    pub fn command_history(&self, n : usize) -> &[CommandRecord] {
+        self.command_log.last(n)
+    }
+
+    /// `HB`, past the operator lock and the `command_log` (see
+    /// `start_heartbeat`).
+    fn send_heartbeat(&mut self) -> Result<(), CoherentError> {
+        self.write_command(DiscoveryNXCommands::Heartbeat)
+    }
+
+    /// Saves a `snapshot` to `path` now, then again after every command
+    /// that changes one of its settings, so the file always holds the
+    /// settings last commanded. Read it back after a restart with
+    /// `LaserSnapshot::load`, and apply it with `restore`. `None` stops.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use coherent_rs::{Discovery, laser::{Laser, snapshot::LaserSnapshot}};
+    ///
+    /// let mut discovery = Discovery::find_first().unwrap();
+    /// if let Ok(snapshot) = LaserSnapshot::load("laser.snapshot") {
+    ///     // Never open a shutter on startup
+    ///     discovery.restore(&snapshot.without("variable_shutter").without("fixed_shutter")).unwrap();
+    /// }
+    /// discovery.persist_snapshots(Some("laser.snapshot")).unwrap();
+    /// ```
+    pub fn persist_snapshots<P : AsRef<std::path::Path>>(&mut self, path : Option<P>) -> Result<(), CoherentError> {
+        self.persisted_snapshot = None;
+        let Some(path) = path else { return Ok(()) };
+        let snapshot = self.snapshot()?;
+        snapshot.save(path.as_ref())?;
+        self.persisted_snapshot = Some((path.as_ref().to_path_buf(), snapshot));
+        Ok(())
+    }
+
+    /// Saves the current wavelength, GDD, shutters and alignment modes as
+    /// the preset `name` in `presets`, replacing any already called that.
+    /// See `Presets::save` to write them to a file.
+    pub fn save_preset(&mut self, name : &str) -> Result<(), CoherentError> {
+        let preset = presets::Preset::from_status(&self.status()?);
+        self.presets.insert(name, preset).map(|_| ())
+    }
+
+    pub fn set_shutter(&mut self, laser : DiscoveryLaser, state : ShutterState) -> Result<(), CoherentError> {
+        self.send_command(DiscoveryNXCommands::Shutter{laser, state})
+    }
+
+    pub fn get_shutter(&mut self, laser : DiscoveryLaser) -> Result<ShutterState, CoherentError> {
+        self.query(DiscoveryNXQueries::Shutter{laser})
     }
 
     pub fn set_gdd_curve(&mut self, curve : u8) -> Result<(), CoherentError> {
@@ -753,11 +1915,11 @@ impl Discovery {
         self.query(DiscoveryNXQueries::GddCurveN{})
     }
     
-    pub fn set_alignment_mode(&mut self, laser : DiscoveryLaser, mode : bool) -> Result<(), CoherentError> {
+    pub fn set_alignment_mode(&mut self, laser : DiscoveryLaser, mode : AlignmentMode) -> Result<(), CoherentError> {
         self.send_command(DiscoveryNXCommands::AlignmentMode{laser, alignment_mode_on : mode})
     }
 
-    pub fn get_alignment_mode(&mut self, laser : DiscoveryLaser) -> Result<bool, CoherentError> {
+    pub fn get_alignment_mode(&mut self, laser : DiscoveryLaser) -> Result<AlignmentMode, CoherentError> {
         self.query(DiscoveryNXQueries::AlignmentMode{laser})
     }
 
@@ -765,6 +1927,68 @@ impl Discovery {
         self.query(DiscoveryNXQueries::Power{laser})
     }
 
+    /// The power of `laser` with its rig calibration applied (see
+    /// `power_calibration`), or the raw readout if it has none.
+    pub fn get_calibrated_power(&mut self, laser : DiscoveryLaser) -> Result<f32, CoherentError> {
+        let calibration = self.power_calibration.get(&laser).unwrap_or_default();
+        Ok(calibration.apply(self.get_power(laser)?))
+    }
+
+    /// Opens the shutter of the output playing `role` (see `beam_roles`).
+    pub fn open_shutter_for(&mut self, role : &str) -> Result<(), CoherentError> {
+        self.set_shutter_for(role, ShutterState::Open)
+    }
+
+    pub fn close_shutter_for(&mut self, role : &str) -> Result<(), CoherentError> {
+        self.set_shutter_for(role, ShutterState::Closed)
+    }
+
+    pub fn set_shutter_for(&mut self, role : &str, state : ShutterState) -> Result<(), CoherentError> {
+        let laser = self.beam_roles.output_for(role)?;
+        self.set_shutter(laser, state)
+    }
+
+    pub fn get_shutter_for(&mut self, role : &str) -> Result<ShutterState, CoherentError> {
+        let laser = self.beam_roles.output_for(role)?;
+        self.get_shutter(laser)
+    }
+
+    /// The calibrated power of the output playing `role`.
+    pub fn get_power_for(&mut self, role : &str) -> Result<f32, CoherentError> {
+        let laser = self.beam_roles.output_for(role)?;
+        self.get_calibrated_power(laser)
+    }
+
+    /// Sets (or, with `None`, clears) the power calibration for one output.
+    pub fn set_power_calibration(&mut self, laser : DiscoveryLaser, calibration : Option<PowerCalibration>) {
+        self.power_calibration.set(&laser, calibration);
+    }
+
+    /// Replaces the power calibration with the one in the file at `path`.
+    pub fn load_power_calibration<P : AsRef<std::path::Path>>(&mut self, path : P) -> Result<(), CoherentError> {
+        self.power_calibration = DiscoveryPowerCalibration::load(path)?;
+        Ok(())
+    }
+
+    /// Sets the GDD from `map` once the laser finishes each wavelength
+    /// change, replacing any `wavelength_profile`. `None` clears it.
+    pub fn set_dispersion_map(&mut self, map : Option<DispersionMap>) {
+        self.wavelength_profile = map.map(WavelengthProfile::from);
+    }
+
+    /// Calibrates `laser`'s power readout against an external meter: reads
+    /// the laser and the average of `samples` meter readings, stores the
+    /// resulting scale (see `power_meter::fit_power_calibration`) and returns it.
+    /// The shutter should be open with the meter in the beam.
+    pub fn calibrate_power<M : PowerMeter>(&mut self, laser : DiscoveryLaser, meter : &mut M, samples : usize)
+        -> Result<PowerCalibration, CoherentError> {
+        let raw = self.get_power(laser)?;
+        let measured = meter.read_average_mw(samples, std::time::Duration::from_millis(100))?;
+        let calibration = fit_power_calibration(&[(raw, measured)])?;
+        self.set_power_calibration(laser, Some(calibration));
+        Ok(calibration)
+    }
+
     pub fn get_serial(&mut self) -> Result<String, CoherentError> {
         self.query(DiscoveryNXQueries::Serial{})
     }
@@ -780,7 +2004,7 @@ impl Discovery {
     }
 
     pub fn get_keyswitch_on(&mut self) -> Result<bool, CoherentError> {
-        self.query(DiscoveryNXQueries::Keyswitch{})
+        self.query(DiscoveryNXQueries::Keyswitch{}).map(bool::from)
     }
 
     pub fn get_status(&mut self) -> Result<String, CoherentError> {
@@ -791,7 +2015,7 @@ impl Discovery {
         self.send_command(DiscoveryNXCommands::FaultClear)
     }
 
-    pub fn get_faults(&mut self) -> Result<u8, CoherentError> {
+    pub fn get_faults(&mut self) -> Result<FaultFlags, CoherentError> {
         self.query(DiscoveryNXQueries::Faults{})
     }
 
@@ -802,14 +2026,985 @@ impl Discovery {
     pub fn get_tuning(&mut self) -> Result<TuningStatus, CoherentError> {
         self.query(DiscoveryNXQueries::Tuning{})
     }
+
+    pub fn get_operating_hours(&mut self) -> Result<f32, CoherentError> {
+        self.query(DiscoveryNXQueries::OperatingHours{})
+    }
+
+    /// In °C.
+    pub fn get_baseplate_temperature(&mut self) -> Result<f32, CoherentError> {
+        self.query(DiscoveryNXQueries::BaseplateTemperature{})
+    }
+
+    /// Relative humidity, in %.
+    pub fn get_humidity(&mut self) -> Result<f32, CoherentError> {
+        self.query(DiscoveryNXQueries::Humidity{})
+    }
+
+    /// In A.
+    pub fn get_diode_current(&mut self) -> Result<f32, CoherentError> {
+        self.query(DiscoveryNXQueries::DiodeCurrent{})
+    }
+
+    /// Whether the heatsink is within its operating temperature.
+    pub fn get_heatsink_ok(&mut self) -> Result<bool, CoherentError> {
+        self.query(DiscoveryNXQueries::HeatsinkStatus{})
+    }
+
+    /// The extra readings `diagnostics_in_status` adds to the status, in its
+    /// order: `None` for any the laser refuses.
+    #[allow(clippy::type_complexity)]
+    fn diagnostics(&mut self) -> Result<(Option<f32>, Option<f32>, Option<f32>, Option<f32>, Option<bool>), CoherentError> {
+        status_queries!(self;
+            operating_hours = DiscoveryNXQueries::OperatingHours{} => StatusField::OperatingHours,
+            baseplate_temperature = DiscoveryNXQueries::BaseplateTemperature{} => StatusField::BaseplateTemperature,
+            humidity = DiscoveryNXQueries::Humidity{} => StatusField::Humidity,
+            diode_current = DiscoveryNXQueries::DiodeCurrent{} => StatusField::DiodeCurrent,
+            heatsink_ok = DiscoveryNXQueries::HeatsinkStatus{} => StatusField::HeatsinkOk,
+        );
+        Ok((operating_hours, baseplate_temperature, humidity, diode_current, heatsink_ok))
+    }
     
 }
 
+#[cfg(test)]
+mod golden;
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::laser::mock::{MockSerialPort, format_reply};
+    use crate::laser::units::{Nanometers, GddFs2};
+
+    /// Every combination of (echo, prompt) the laser can be configured with.
+    const MODES : [(bool, bool); 4] = [(false, false), (true, false), (false, true), (true, true)];
+
+    /// A `Discovery` on a mock port that has completed the handshake and will
+    /// answer each `(command, response)` pair as a laser in the given mode would.
+    fn mock_discovery(echo : bool, prompt : bool, exchanges : &[(&str, &str)])
+        -> (Discovery, MockSerialPort) {
+        let port = exchanges.iter().fold(
+            MockSerialPort::discovery(echo, prompt, "SN1234"),
+            |port, (command, response)| port.expect(command, &format_reply(command, response, echo, prompt))
+        );
+        let discovery = Discovery::from_serial_port(Box::new(port.clone())).unwrap();
+        (discovery, port)
+    }
+
+    #[test]
+    fn test_mock_handshake() {
+        for (echo, prompt) in MODES {
+            let (discovery, port) = mock_discovery(echo, prompt, &[]);
+            assert_eq!(discovery.serial_number, "SN1234", "echo {echo}, prompt {prompt}");
+            assert_eq!(discovery.echo, echo);
+            assert_eq!(discovery.terminal_style(), if prompt { TerminalStyle::Chameleon } else { TerminalStyle::Plain });
+            assert!(port.is_finished());
+        }
+
+        // Laser never answers
+        let port = MockSerialPort::new();
+        assert!(matches!(
+            Discovery::from_serial_port(Box::new(port)),
+            Err(CoherentError::InvalidResponseError(_))
+        ));
+
+        // Echo on, but the serial number reply doesn't echo `?SN`
+        let port = MockSerialPort::new()
+            .expect("?E", "?E 1\r\n")
+            .expect("?SN", "SN1234\r\n");
+        assert!(matches!(
+            Discovery::from_serial_port(Box::new(port)),
+            Err(CoherentError::InvalidResponseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_mock_queries() {
+        for (echo, prompt) in MODES {
+            let (mut discovery, port) = mock_discovery(echo, prompt, &[
+                ("?E", if echo {"1"} else {"0"}),
+                ("?L", "1"),
+                ("?S", "0"),
+                ("?SFIXED", "1"),
+                ("?K", "1"),
+                ("?F", "3"),
+                ("?FT", "Chiller flow low"),
+                ("?TS", "1"),
+                ("?ALIGNVAR", "0"),
+                ("?ALIGNFIXED", "1"),
+                ("?ST", "Starting"),
+                ("?WV", "920"),
+                ("?PVAR", "1250.5"),
+                ("?PFIXED", "800"),
+                ("?GDDCURVE", "2"),
+                ("?GDDCURVEN", "Objective A"),
+                ("?GDD", "-1500"),
+                ("?SN", "SN1234"),
+            ]);
+
+            assert_eq!(discovery.query(DiscoveryNXQueries::Echo{}).unwrap(), echo);
+            assert_eq!(discovery.query(DiscoveryNXQueries::Laser{}).unwrap(), LaserState::On);
+            assert_eq!(
+                discovery.query(DiscoveryNXQueries::Shutter{laser : DiscoveryLaser::VariableWavelength}).unwrap(),
+                ShutterState::Closed
+            );
+            assert_eq!(
+                discovery.query(DiscoveryNXQueries::Shutter{laser : DiscoveryLaser::FixedWavelength}).unwrap(),
+                ShutterState::Open
+            );
+            assert_eq!(discovery.query(DiscoveryNXQueries::Keyswitch{}).unwrap(), Keyswitch::On);
+            assert_eq!(discovery.query(DiscoveryNXQueries::Faults{}).unwrap(), FaultFlags::INTERLOCK | FaultFlags::CHILLER);
+            assert_eq!(discovery.query(DiscoveryNXQueries::FaultText{}).unwrap(), "Chiller flow low");
+            assert_eq!(discovery.query(DiscoveryNXQueries::Tuning{}).unwrap(), TuningStatus::Tuning);
+            assert_eq!(discovery.query(
+                DiscoveryNXQueries::AlignmentMode{laser : DiscoveryLaser::VariableWavelength}
+            ).unwrap(), AlignmentMode::Off);
+            assert_eq!(discovery.query(
+                DiscoveryNXQueries::AlignmentMode{laser : DiscoveryLaser::FixedWavelength}
+            ).unwrap(), AlignmentMode::On);
+            assert_eq!(discovery.query(DiscoveryNXQueries::Status{}).unwrap(), "Starting");
+            assert_eq!(discovery.query(DiscoveryNXQueries::Wavelength{}).unwrap(), 920.0);
+            assert_eq!(
+                discovery.query(DiscoveryNXQueries::Power{laser : DiscoveryLaser::VariableWavelength}).unwrap(),
+                1250.5
+            );
+            assert_eq!(
+                discovery.query(DiscoveryNXQueries::Power{laser : DiscoveryLaser::FixedWavelength}).unwrap(),
+                800.0
+            );
+            assert_eq!(discovery.query(DiscoveryNXQueries::GddCurve{}).unwrap(), 2);
+            assert_eq!(discovery.query(DiscoveryNXQueries::GddCurveN{}).unwrap(), "Objective A");
+            assert_eq!(discovery.query(DiscoveryNXQueries::Gdd{}).unwrap(), -1500.0);
+            assert_eq!(discovery.query(DiscoveryNXQueries::Serial{}).unwrap(), "SN1234");
+
+            assert!(port.is_finished(), "echo {echo}, prompt {prompt}: {:?}", port.unexpected());
+        }
+    }
+
+    #[test]
+    fn test_mock_pipelined_status() {
+        let status_exchanges = [
+            ("?E", "0"), ("?L", "1"), ("?S", "0"), ("?SFIXED", "1"), ("?K", "1"), ("?F", "0"), ("?FT", "System OK"),
+            ("?TS", "0"), ("?ALIGNVAR", "0"), ("?ALIGNFIXED", "0"), ("?ST", "OK"), ("?WV", "920"),
+            ("?PVAR", "1250.5"), ("?PFIXED", "800"), ("?GDDCURVE", "2"), ("?GDDCURVEN", "Objective A"), ("?GDD", "-1500"),
+        ];
+        for (echo, prompt) in MODES {
+            let (mut sequential, _) = mock_discovery(echo, prompt, &status_exchanges);
+            let (mut pipelined, port) = mock_discovery(echo, prompt, &status_exchanges);
+            pipelined.pipelined_status = true;
+            let (expected, status) = (sequential.status().unwrap(), pipelined.status().unwrap());
+            assert_eq!(
+                DiscoveryNXStatus{timestamp : expected.timestamp, ..status},
+                expected,
+                "echo {echo}, prompt {prompt}"
+            );
+            assert!(port.is_finished(), "{:?}", port.unexpected());
+        }
+
+        // Every query goes out before the laser has said anything
+        let (mut pipelined, port) = mock_discovery(false, false, &status_exchanges[..16]);
+        pipelined.pipelined_status = true;
+        assert!(matches!(pipelined.status(), Err(CoherentError::InvalidResponseError(_))));
+        assert_eq!(port.written().len(), 2 + status_exchanges.len());
+    }
+
+    #[test]
+    fn test_mock_unsupported_status_fields() {
+        let exchanges = [
+            ("?E", "0"), ("?L", "1"), ("?S", "0"), ("?SFIXED", "1"), ("?K", "1"), ("?F", "0"), ("?FT", "System OK"),
+            ("?TS", "0"), ("?ALIGNVAR", "0"), ("?ALIGNFIXED", "0"), ("?ST", "OK"), ("?WV", "920"),
+            ("?PVAR", "1250.5"), ("?PFIXED", "800"), ("?GDDCURVE", "2"), ("?GDDCURVEN", "COMMAND NOT EXECUTED"), ("?GDD", "-1500"),
+        ];
+        for pipelined in [false, true] {
+            // The second status doesn't ask for the curve name
+            let second = exchanges.iter().filter(|(query, _)| *query != "?GDDCURVEN").copied().collect::<Vec<_>>();
+            let (mut discovery, port) = mock_discovery(false, false, &[&exchanges[..], &second].concat());
+            discovery.pipelined_status = pipelined;
+            for _ in 0..2 {
+                let status = discovery.status().unwrap();
+                assert_eq!((status.gdd_curve, status.gdd_curve_n, status.gdd), (Some(2), None, Some(-1500.0)));
+            }
+            assert_eq!(discovery.unsupported_fields().iter().collect::<Vec<_>>(), vec![&StatusField::GddCurveN]);
+            assert!(port.is_finished(), "pipelined {pipelined}: {:?}", port.unexpected());
+            discovery.forget_unsupported_fields();
+            assert!(discovery.unsupported_fields().is_empty());
+        }
+
+        // Mid-tune, and on firmware without `?FT`
+        let mut tuning = exchanges;
+        tuning[6] = ("?FT", "COMMAND NOT EXECUTED");
+        tuning[7] = ("?TS", "1");
+        let (mut discovery, _) = mock_discovery(false, false, &tuning);
+        let status = discovery.status().unwrap();
+        assert_eq!((status.fault_text.as_deref(), status.power_var, status.power_fixed), (None, None, 800.0));
+        assert_eq!(status.primary_power(), 0.0);
+        assert_eq!(status.get(StatusField::PowerVar), StatusValue::Missing);
+
+        // A required field the laser refuses still fails the status
+        let mut refused = exchanges;
+        refused[11] = ("?WV", "COMMAND NOT EXECUTED");
+        let (mut discovery, _) = mock_discovery(false, false, &refused);
+        assert!(matches!(discovery.status(), Err(CoherentError::CommandNotExecutedError)));
+    }
+
+    #[test]
+    fn test_mock_skip_unchanged() {
+        let (mut discovery, port) = mock_discovery(false, false, &[
+            ("WV=800", ""),
+            ("S=1", ""),
+            ("GDD=-1000", "COMMAND NOT EXECUTED"),
+            ("GDD=-1000", ""),
+            ("WV=900", ""),
+        ]);
+        discovery.skip_unchanged = SkipUnchanged::LastKnown;
+        for _ in 0..2 {
+            discovery.set_wavelength(800.0).unwrap();
+            discovery.set_shutter(DiscoveryLaser::VariableWavelength, ShutterState::Open).unwrap();
+        }
+        // A failed command leaves the setting unknown, so it's sent again
+        assert!(discovery.set_gdd(-1000.0).is_err());
+        discovery.set_gdd(-1000.0).unwrap();
+        discovery.set_gdd(-1000.0).unwrap();
+        discovery.set_wavelength(900.0).unwrap();
+        // Only wavelengths, GDDs and shutters are skipped
+        assert!(discovery.set_gdd_curve(1).is_err());
+        assert_eq!(port.unexpected(), vec!["GDDCURVE=1".to_string()]);
+
+        let (mut discovery, port) = mock_discovery(false, false, &[
+            ("?WV", "800"),
+            ("?GDD", "-500"),
+            ("GDD=0", ""),
+        ]);
+        discovery.skip_unchanged = SkipUnchanged::Query;
+        discovery.set_wavelength(800.0).unwrap();
+        discovery.set_gdd(0.0).unwrap();
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+    }
 
     #[test]
+    fn test_mock_tuning_progress() {
+        let (mut discovery, port) = mock_discovery(false, false, &[
+            ("?WV", "800"),
+            ("WV=1000", ""),
+            ("?TS", "1"), ("?WV", "850"),
+            ("?TS", "1"), ("?WV", "950"),
+            ("?TS", "0"), ("?WV", "999.5"),
+        ]);
+        assert_eq!(discovery.tuning_progress().unwrap(), None);
+        discovery.get_wavelength().unwrap();
+        discovery.set_wavelength(1000.0).unwrap();
+
+        let progress = discovery.tuning_progress().unwrap().unwrap();
+        assert_eq!((progress.from_nm, progress.to_nm, progress.current_nm), (800.0, 1000.0, 850.0));
+        assert_eq!(progress.percent(), 25.0);
+        assert!(progress.eta.is_some());
+        assert_eq!(discovery.tuning_progress().unwrap().unwrap().fraction, 0.75);
+        assert!(discovery.tuning_progress().unwrap().unwrap().is_done());
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+
+        // Without a wavelength read first, it counts from the first check
+        let (mut discovery, _) = mock_discovery(false, false, &[("WV=1000", ""), ("?TS", "1"), ("?WV", "850")]);
+        discovery.set_wavelength(1000.0).unwrap();
+        assert_eq!(discovery.tuning_progress().unwrap().unwrap().fraction, 0.0);
+    }
+
+    #[test]
+    fn test_mock_capabilities() {
+        let (discovery, port) = mock_discovery(false, false, &[]);
+        let capabilities = discovery.capabilities();
+        assert_eq!(capabilities.laser_type, LaserType::DiscoveryNX);
+        assert_eq!(capabilities.tuning_range_nm, Some(profile::WAVELENGTH_RANGE_NM));
+        assert_eq!(capabilities.outputs, 2);
+        assert!(capabilities.supports(LaserFeature::AlignmentMode));
+        // Answered without asking the laser
+        assert_eq!(port.written().len(), 2);
+    }
+
+    #[test]
+    fn test_mock_status_off() {
+        let status_exchanges = [
+            ("?E", "0"), ("?L", "0"), ("?S", "0"), ("?SFIXED", "0"), ("?K", "0"), ("?F", "0"), ("?FT", "System OK"),
+            ("?TS", "0"), ("?ALIGNVAR", "0"), ("?ALIGNFIXED", "0"), ("?ST", "OK"), ("?WV", "920"),
+            ("?PVAR", "0"), ("?PFIXED", "0"), ("?GDDCURVE", "2"), ("?GDDCURVEN", "Objective A"), ("?GDD", "-1500"),
+        ];
+        let (mut discovery, port) = mock_discovery(false, false, &status_exchanges);
+        let status = discovery.status().unwrap();
+        assert_eq!(status.laser, LaserState::Off);
+        assert!(!status.is_emitting());
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+
+        // A fault with the key on also reads as off
+        let mut exchanges = status_exchanges;
+        exchanges[4] = ("?K", "1");
+        exchanges[5] = ("?F", "2");
+        let (mut discovery, _) = mock_discovery(false, false, &exchanges);
+        assert_eq!(discovery.status().unwrap().laser, LaserState::Off);
+    }
+
+    #[test]
+    fn test_mock_discovery_prompt() {
+        let terminal = TerminalStyle::Discovery;
+        let port = MockSerialPort::discovery_with_terminal(true, terminal, "SN1234")
+            .expect("?WV", &terminal.format_reply("?WV", "920", true))
+            .expect("WV=800", &terminal.format_reply("WV=800", "", true))
+            .expect("GDD=1", &terminal.format_reply("GDD=1", "", false));
+        let mut discovery = Discovery::from_serial_port(Box::new(port.clone())).unwrap();
+        assert_eq!(discovery.terminal_style(), terminal);
+        assert_eq!(discovery.serial_number, "SN1234");
+        assert_eq!(discovery.get_wavelength().unwrap(), 920.0);
+        discovery.set_wavelength(800.0).unwrap();
+        // Echo missing
+        assert!(matches!(discovery.set_gdd(1.0), Err(CoherentError::InvalidResponseError(_))));
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+    }
+
+    #[test]
+    fn test_mock_command_retries() {
+        use crate::laser::retry::RetryPolicy;
+
+        let port = MockSerialPort::discovery(false, false, "SN1234")
+            .expect("GDD=-5000", "COMMAND NOT EXECUTED\r\n")
+            .expect("GDD=-5000", "COMMAND NOT EXECUTED\r\n")
+            .expect("GDD=-5000", "\r\n")
+            .expect("GDD=0", "COMMAND NOT EXECUTED\r\n")
+            .expect("GDD=0", "COMMAND NOT EXECUTED\r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port.clone())).unwrap();
+        discovery.command_retry_policy = RetryPolicy::for_commands(3).with_backoff(std::time::Duration::ZERO, 1.0);
+        discovery.set_gdd(-5000.0).unwrap();
+
+        discovery.command_retry_policy.max_attempts = 2;
+        assert!(matches!(discovery.set_gdd(0.0), Err(CoherentError::CommandNotExecutedError)));
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+
+        // Refused straight away by default
+        let port = MockSerialPort::discovery(false, false, "SN1234")
+            .expect("GDD=0", "COMMAND NOT EXECUTED\r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port.clone())).unwrap();
+        assert!(matches!(discovery.set_gdd(0.0), Err(CoherentError::CommandNotExecutedError)));
+        assert!(port.is_finished());
+    }
+
+    #[test]
+    fn test_mock_verify_commands() {
+        let port = MockSerialPort::discovery(false, false, "SN1234")
+            .expect("GDD=-5000", "\r\n")
+            .expect("?GDD", "-5000.4\r\n")
+            // Busy: accepted, but not applied
+            .expect("S=1", "\r\n")
+            .expect("?S", "0\r\n")
+            // Not checked unless asked
+            .expect("S=1", "\r\n")
+            .expect("S=0", "\r\n")
+            .expect("?S", "0\r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port.clone())).unwrap();
+        discovery.verify_commands = true;
+        discovery.set_gdd(-5000.0).unwrap();
+        assert!(matches!(
+            discovery.set_shutter(DiscoveryLaser::VariableWavelength, ShutterState::Open),
+            Err(CoherentError::VerificationFailed{parameter, expected, actual})
+                if parameter == "variable_shutter"
+                && expected == StatusValue::Text("Open".to_string())
+                && actual == StatusValue::Text("Closed".to_string())
+        ));
+
+        discovery.verify_commands = false;
+        discovery.set_shutter(DiscoveryLaser::VariableWavelength, ShutterState::Open).unwrap();
+        discovery.send_command_verified(
+            DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::VariableWavelength, state : ShutterState::Closed}
+        ).unwrap();
+        assert!(!discovery.verify_commands);
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+    }
+
+    #[test]
+    fn test_mock_reconnect() {
+        use std::sync::{Arc, Mutex};
+        use crate::laser::retry::RetryPolicy;
+
+        // The laser stops answering, then comes back power-cycled, with echo on
+        let dead = MockSerialPort::discovery(false, false, "SN1234");
+        let revived = MockSerialPort::discovery(true, false, "SN1234")
+            .expect("E=0", &format_reply("E=0", "", false, false))
+            .expect("?WV", "920\r\n")
+            .expect("WV=800", "\r\n");
+        let ports = Arc::new(Mutex::new(vec![revived.clone()]));
+        let mut discovery = Discovery::from_serial_port(Box::new(dead.clone())).unwrap();
+        discovery.reconnect_policy = Some(ReconnectPolicy::new(RetryPolicy::new(2).with_backoff(std::time::Duration::ZERO, 1.0))
+            .with_opener(move |_| match ports.lock().unwrap().pop() {
+                Some(port) => Discovery::from_serial_port(Box::new(port)),
+                None => Err(CoherentError::NoRecognizedLasers),
+            })
+        );
+
+        assert_eq!(discovery.get_wavelength().unwrap(), 920.0);
+        assert_eq!(dead.written().last().unwrap(), "?WV");
+        assert!(!discovery.echo);
+        discovery.set_wavelength(800.0).unwrap();
+        assert!(revived.is_finished(), "{:?}", revived.unexpected());
+
+        // Gone for good
+        assert!(matches!(discovery.get_wavelength(), Err(CoherentError::Reconnecting)));
+
+        // Without a policy, a timeout is just an error
+        let mut discovery = Discovery::from_serial_port(Box::new(MockSerialPort::discovery(false, false, "SN1234"))).unwrap();
+        assert!(matches!(discovery.get_wavelength(), Err(CoherentError::InvalidResponseError(_))));
+    }
+
+    #[test]
+    fn test_mock_diagnostics() {
+        let diagnostics = [("?HH", "1250.5"), ("?BT", "24.8"), ("?RH", "9"), ("?DC", "27.5"), ("?HS", "1")];
+        let (mut discovery, port) = mock_discovery(true, true, &diagnostics);
+        assert_eq!(discovery.get_operating_hours().unwrap(), 1250.5);
+        assert_eq!(discovery.get_baseplate_temperature().unwrap(), 24.8);
+        assert_eq!(discovery.get_humidity().unwrap(), 9.0);
+        assert_eq!(discovery.get_diode_current().unwrap(), 27.5);
+        assert!(discovery.get_heatsink_ok().unwrap());
+        assert!(port.is_finished());
+
+        // Only read into the status when asked for
+        let status_exchanges = [
+            ("?E", "0"), ("?L", "1"), ("?S", "0"), ("?SFIXED", "1"), ("?K", "1"), ("?F", "0"), ("?FT", "System OK"),
+            ("?TS", "0"), ("?ALIGNVAR", "0"), ("?ALIGNFIXED", "0"), ("?ST", "OK"), ("?WV", "920"),
+            ("?PVAR", "1250.5"), ("?PFIXED", "800"), ("?GDDCURVE", "2"), ("?GDDCURVEN", "Objective A"), ("?GDD", "-1500"),
+        ];
+        let (mut discovery, port) = mock_discovery(false, false, &status_exchanges);
+        let status = discovery.status().unwrap();
+        assert_eq!((status.operating_hours, status.heatsink_ok), (None, None));
+        assert!(port.is_finished());
+
+        for pipelined in [false, true] {
+            let exchanges = status_exchanges.iter().chain(diagnostics.iter()).copied().collect::<Vec<_>>();
+            let (mut discovery, port) = mock_discovery(false, false, &exchanges);
+            discovery.diagnostics_in_status = true;
+            discovery.pipelined_status = pipelined;
+            let status = discovery.status().unwrap();
+            assert_eq!(status.operating_hours, Some(1250.5));
+            assert_eq!(status.baseplate_temperature, Some(24.8));
+            assert_eq!(status.humidity, Some(9.0));
+            assert_eq!(status.diode_current, Some(27.5));
+            assert_eq!(status.heatsink_ok, Some(true));
+            assert!(port.is_finished(), "{:?}", port.unexpected());
+        }
+
+        // Firmware without some of them still gives a status, without those
+        let refused = status_exchanges.iter().chain(diagnostics.iter())
+            .map(|&(query, reply)| (query, if query == "?DC" || query == "?HS" { "COMMAND NOT EXECUTED" } else { reply }))
+            .collect::<Vec<_>>();
+        let second = refused.iter().filter(|(query, _)| *query != "?DC" && *query != "?HS").copied().collect::<Vec<_>>();
+        let (mut discovery, port) = mock_discovery(false, false, &[&refused[..], &second].concat());
+        discovery.diagnostics_in_status = true;
+        for _ in 0..2 {
+            let status = discovery.status().unwrap();
+            assert_eq!((status.humidity, status.diode_current, status.heatsink_ok), (Some(9.0), None, None));
+        }
+        assert!(discovery.unsupported_fields().contains(&StatusField::DiodeCurrent));
+        assert!(discovery.unsupported_fields().contains(&StatusField::HeatsinkOk));
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+
+        let (mut discovery, _) = mock_discovery(false, false, &[("?HS", "OK")]);
+        assert!(matches!(discovery.get_heatsink_ok(), Err(CoherentError::InvalidResponseError(_))));
+    }
+
+    #[test]
+    fn test_mock_calibrated_power() {
+        let (mut discovery, port) = mock_discovery(true, false, &[
+            ("?PVAR", "1000"),
+            ("?PVAR", "1000"),
+        ]);
+        discovery.set_power_calibration(
+            DiscoveryLaser::VariableWavelength, Some(PowerCalibration::new(0.25, 5.0))
+        );
+        assert_eq!(discovery.get_power(DiscoveryLaser::VariableWavelength).unwrap(), 1000.0);
+        assert_eq!(discovery.get_calibrated_power(DiscoveryLaser::VariableWavelength).unwrap(), 255.0);
+        assert!(port.is_finished());
+    }
+
+    #[test]
+    fn test_mock_calibrate_power() {
+        use crate::laser::power_meter::AnalogPowerMeter;
+
+        let (mut discovery, port) = mock_discovery(false, false, &[
+            ("?PFIXED", "2000"),
+            ("?PFIXED", "2000"),
+        ]);
+        let mut meter = AnalogPowerMeter::new(|| Ok(5.0), PowerCalibration::new(100.0, 0.0));
+        let calibration = discovery.calibrate_power(DiscoveryLaser::FixedWavelength, &mut meter, 1).unwrap();
+        assert_eq!(calibration, PowerCalibration::new(0.25, 0.0));
+        assert_eq!(discovery.get_calibrated_power(DiscoveryLaser::FixedWavelength).unwrap(), 500.0);
+        assert_eq!(discovery.power_calibration.variable, None);
+        assert!(port.is_finished());
+    }
+
+    #[test]
+    fn test_mock_tuning_hooks() {
+        use std::sync::{Arc, Mutex};
+
+        let (mut discovery, port) = mock_discovery(true, true, &[
+            ("?WV", "920"),
+            ("WV=800", ""),
+            ("?TS", "1"),
+            ("?TS", "0"),
+        ]);
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        for name in ["before", "after"] {
+            let calls = calls.clone();
+            let hook = move |e : &TuningEvent| { calls.lock().unwrap().push((name, e.from_nm, e.to_nm)); Ok(()) };
+            if name == "before" {
+                discovery.tuning_hooks.before_tuning(hook);
+            } else {
+                discovery.tuning_hooks.after_tuning(hook);
+            }
+        }
+        discovery.set_wavelength(800.0).unwrap();
+        assert_eq!(*calls.lock().unwrap(), vec![("before", 920.0, 800.0), ("after", 920.0, 800.0)]);
+        assert!(port.is_finished());
+
+        // Still tuning when the timeout runs out
+        let (mut discovery, _) = mock_discovery(false, false, &[("?TS", "1")]);
+        assert!(matches!(
+            discovery.wait_for_tuning(std::time::Duration::ZERO, TUNING_POLL_INTERVAL),
+            Err(CoherentError::TimeoutError)
+        ));
+    }
+
+    #[test]
+    fn test_mock_raw_command() {
+        // The reply as the laser sent it, echo and all
+        let (mut discovery, port) = mock_discovery(true, false, &[("?WV", "920"), ("WV=5000", "COMMAND NOT EXECUTED")]);
+        assert_eq!(discovery.raw_command("?WV").unwrap(), "?WV 920");
+        assert_eq!(discovery.raw_command("WV=5000").unwrap(), "WV=5000 COMMAND NOT EXECUTED");
+        assert!(port.is_finished());
+    }
+
+    #[test]
+    fn test_mock_set_wavelength_blocking() {
+        let (mut discovery, port) = mock_discovery(false, false, &[
+            ("WV=800", ""),
+            ("?TS", "1"),
+            ("?TS", "0"),
+            ("?WV", "800"),
+        ]);
+        assert_eq!(discovery.set_wavelength_blocking(800.0, std::time::Duration::from_secs(5)).unwrap(), 800.0);
+        assert!(port.is_finished());
+
+        // Stopped short
+        let (mut discovery, _) = mock_discovery(false, false, &[("WV=800", ""), ("?TS", "0"), ("?WV", "920")]);
+        assert!(matches!(
+            discovery.set_wavelength_blocking(800.0, std::time::Duration::from_secs(5)),
+            Err(CoherentError::CommandNotExecutedError)
+        ));
+
+        // Never finished
+        let (mut discovery, _) = mock_discovery(false, false, &[("WV=800", ""), ("?TS", "1"), ("?TS", "1")]);
+        assert!(matches!(
+            discovery.set_wavelength_blocking(800.0, std::time::Duration::from_millis(150)),
+            Err(CoherentError::TimeoutError)
+        ));
+    }
+
+    #[test]
+    fn test_mock_wavelength_profile() {
+        let (mut discovery, port) = mock_discovery(false, false, &[
+            ("?WV", "920"),
+            ("WV=800", ""),
+            ("?TS", "0"),
+            ("GDDCURVE=1", ""),
+            ("GDD=-6000", ""),
+        ]);
+        discovery.wavelength_profile = Some(WavelengthProfile::parse("800 gdd=-6000 curve=1").unwrap());
+        discovery.set_wavelength(800.0).unwrap();
+        assert!(port.is_finished());
+    }
+
+    #[test]
+    fn test_mock_dispersion_map() {
+        // The GDD goes out only once tuning is done
+        let (mut discovery, port) = mock_discovery(false, false, &[
+            ("?WV", "920"),
+            ("WV=900", ""),
+            ("?TS", "1"),
+            ("?TS", "0"),
+            ("GDD=-4000", ""),
+        ]);
+        discovery.set_dispersion_map(Some(profile::DispersionMap::new(vec![(1000.0, -2000.0), (800.0, -6000.0)])));
+        discovery.set_wavelength(900.0).unwrap();
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+        discovery.set_dispersion_map(None);
+        assert!(discovery.wavelength_profile.is_none());
+    }
+
+    #[test]
+    fn test_mock_validate() {
+        let (mut discovery, port) = mock_discovery(false, false, &[]);
+        discovery.wavelength_profile = Some(WavelengthProfile::parse("800 gdd=-6000 curve=1").unwrap());
+        assert_eq!(
+            discovery.validate(&DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)}).unwrap(),
+            vec!["WV=800", "GDDCURVE=1", "GDD=-6000"]
+        );
+        // The profile's GDD is past the limits, so tuning would fail partway
+        discovery.soft_limits.max_abs_gdd = Some(5000.0);
+        assert!(matches!(
+            discovery.validate(&DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)}),
+            Err(CoherentError::SoftLimitError(_))
+        ));
+        discovery.lock("alice").unwrap();
+        assert!(matches!(discovery.validate(&DiscoveryNXCommands::FaultClear), Err(CoherentError::LockedError)));
+        // Only the handshake was ever written
+        assert_eq!(port.written().len(), 2);
+    }
+
+    #[test]
+    fn test_mock_parameter_history() {
+        let (mut discovery, port) = mock_discovery(false, false, &[
+            ("?GDD", "-500"),
+            ("GDD=-2000", ""),
+            ("?S", "0"),
+            ("S=1", ""),
+            ("FC", ""),
+        ]);
+        discovery.parameter_history = Some(ParameterHistory::default());
+        discovery.set_gdd(-2000.0).unwrap();
+        discovery.set_shutter(DiscoveryLaser::VariableWavelength, ShutterState::Open).unwrap();
+        discovery.clear_faults().unwrap();
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+
+        let diff = discovery.parameter_history.as_ref().unwrap().diff(0.0);
+        assert_eq!(diff["gdd"], (StatusValue::Float(-500.0), StatusValue::Float(-2000.0)));
+        assert_eq!(
+            diff["variable_shutter"],
+            (StatusValue::Text("Closed".to_string()), StatusValue::Text("Open".to_string()))
+        );
+        assert_eq!(diff.len(), 2);
+    }
+
+    #[test]
+    fn test_mock_soft_limits() {
+        let (mut discovery, port) = mock_discovery(false, false, &[]);
+        discovery.soft_limits.max_abs_gdd = Some(5000.0);
+        assert!(matches!(discovery.set_gdd(-8000.0), Err(CoherentError::SoftLimitError(_))));
+        // Nothing reached the laser
+        assert_eq!(port.written(), vec!["?E".to_string(), "?SN".to_string()]);
+        assert!(port.is_finished());
+    }
+
+    #[test]
+    fn test_mock_clamped_limits() {
+        let (mut discovery, port) = mock_discovery(false, false, &[("WV=1040", "")]);
+        discovery.soft_limits.wavelength_nm = Some((800.0, 1040.0));
+        discovery.soft_limits.clamp = true;
+        assert_eq!(discovery.validate(&DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(1100.0)}).unwrap(), vec!["WV=1040"]);
+        discovery.set_wavelength(1100.0).unwrap();
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+    }
+
+    #[test]
+    fn test_mock_persist_snapshots() {
+        let (mut discovery, port) = mock_discovery(false, false, &[
+            ("?E", "0"), ("?L", "1"), ("?S", "0"), ("?SFIXED", "1"), ("?K", "1"), ("?F", "0"), ("?FT", "System OK"),
+            ("?TS", "0"), ("?ALIGNVAR", "0"), ("?ALIGNFIXED", "0"), ("?ST", "OK"), ("?WV", "920"),
+            ("?PVAR", "1250.5"), ("?PFIXED", "800"), ("?GDDCURVE", "2"), ("?GDDCURVEN", "Objective A"), ("?GDD", "-1500"),
+            ("WV=800", ""), ("GDDCURVEN=Objective B", ""), ("SETCURVEN=Objective C", ""),
+        ]);
+        let path = std::env::temp_dir().join(format!("coherent-persisted-{}.snapshot", std::process::id()));
+        discovery.persist_snapshots(Some(&path)).unwrap();
+        assert_eq!(LaserSnapshot::load(&path).unwrap().settings["wavelength"], StatusValue::Float(920.0));
+
+        discovery.set_wavelength(800.0).unwrap();
+        discovery.set_gdd_curve_n("Objective B").unwrap();
+        discovery.send_command(DiscoveryNXCommands::SetCurveN{new_curve_name : "Objective C".to_string()}).unwrap();
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+        let saved = LaserSnapshot::load(&path).unwrap();
+        assert_eq!(saved.settings["wavelength"], StatusValue::Float(800.0));
+        assert_eq!(saved.settings["fixed_shutter"], StatusValue::Text("Open".to_string()));
+        // Out of date once a curve's selected by name, and names aren't restored
+        assert!(!saved.settings.contains_key("gdd_curve") && !saved.settings.contains_key("gdd_curve_n"));
+        assert!(restore::commands(&saved).is_ok());
+
+        discovery.persist_snapshots(None::<&std::path::Path>).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_mock_command_history() {
+        let (mut discovery, port) = mock_discovery(false, false, &[("WV=900", ""), ("S=1", "COMMAND NOT EXECUTED")]);
+        discovery.soft_limits.max_abs_gdd = Some(10000.0);
+        discovery.set_wavelength(900.0).unwrap();
+        assert!(discovery.set_gdd(-50000.0).is_err());
+        assert!(discovery.set_shutter(DiscoveryLaser::VariableWavelength, ShutterState::Open).is_err());
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+
+        let history = discovery.command_history(10);
+        assert_eq!(history.iter().map(|record| record.command.as_str()).collect::<Vec<_>>(), vec!["WV=900", "GDD=-50000", "S=1"]);
+        assert!(history[0].result.is_ok());
+        assert!(history[1].result.as_ref().unwrap_err().starts_with("SoftLimitError"));
+        assert!(history[0].timestamp <= history[2].timestamp);
+        assert_eq!(discovery.command_history(1)[0].command, "S=1");
+    }
+
+    #[test]
+    fn test_mock_operator_lock() {
+        let (mut discovery, port) = mock_discovery(false, false, &[]);
+        discovery.lock("rig-2").unwrap();
+        assert!(matches!(discovery.set_gdd(-2000.0), Err(CoherentError::LockedError)));
+        assert!(matches!(
+            discovery.set_shutter(DiscoveryLaser::FixedWavelength, ShutterState::Open),
+            Err(CoherentError::LockedError)
+        ));
+        // Nothing reached the laser
+        assert_eq!(port.written(), vec!["?E".to_string(), "?SN".to_string()]);
+        assert!(port.is_finished());
+    }
+
+    #[test]
+    fn test_mock_malformed_queries() {
+        for (echo, prompt) in MODES {
+            // Unparseable values
+            let (mut discovery, _) = mock_discovery(echo, prompt, &[
+                ("?WV", "garbage"),
+                ("?L", "2"),
+                ("?F", "-1"),
+                ("?K", "l"),
+            ]);
+            assert!(matches!(
+                discovery.query(DiscoveryNXQueries::Wavelength{}),
+                Err(CoherentError::InvalidResponseError(_))
+            ));
+            assert!(matches!(
+                discovery.query(DiscoveryNXQueries::Laser{}),
+                Err(CoherentError::InvalidResponseError(_))
+            ));
+            assert!(matches!(
+                discovery.query(DiscoveryNXQueries::Faults{}),
+                Err(CoherentError::InvalidResponseError(_))
+            ));
+            // Not a `false`
+            assert!(matches!(
+                discovery.query(DiscoveryNXQueries::Keyswitch{}),
+                Err(CoherentError::InvalidResponseError(reply)) if reply == "l"
+            ));
+
+            // No reply at all
+            let (mut discovery, port) = mock_discovery(echo, prompt, &[]);
+            assert!(matches!(
+                discovery.query(DiscoveryNXQueries::Gdd{}),
+                Err(CoherentError::InvalidResponseError(_))
+            ));
+            assert_eq!(port.unexpected(), vec!["?GDD".to_string()]);
+        }
+
+        // Reply is missing the echoed command or the prompt the laser said it would send
+        let port = MockSerialPort::discovery(true, false, "SN1234").expect("?WV", "920\r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port)).unwrap();
+        assert!(matches!(
+            discovery.query(DiscoveryNXQueries::Wavelength{}),
+            Err(CoherentError::InvalidResponseError(_))
+        ));
+
+        let port = MockSerialPort::discovery(false, true, "SN1234").expect("?WV", "920\r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port)).unwrap();
+        assert!(matches!(
+            discovery.query(DiscoveryNXQueries::Wavelength{}),
+            Err(CoherentError::InvalidResponseError(_))
+        ));
+
+        // Line noise
+        let port = MockSerialPort::discovery(false, false, "SN1234")
+            .expect_bytes("?WV", &[0xff, 0xfe, 0x00, b'\r', b'\n']);
+        let mut discovery = Discovery::from_serial_port(Box::new(port)).unwrap();
+        assert!(matches!(
+            discovery.query(DiscoveryNXQueries::Wavelength{}),
+            Err(CoherentError::InvalidResponseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_mock_query_retry() {
+        // A garbled reply, then a good one
+        let port = MockSerialPort::discovery(false, false, "SN1234")
+            .expect("?WV", "garbage\r\n")
+            .expect("?WV", "920\r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port.clone())).unwrap();
+        discovery.retry_policy = RetryPolicy::new(2).with_backoff(std::time::Duration::ZERO, 1.0);
+        assert_eq!(discovery.get_wavelength().unwrap(), 920.0);
+        assert!(port.is_finished());
+
+        // By default, queries are tried once
+        let port = MockSerialPort::discovery(false, false, "SN1234")
+            .expect("?WV", "garbage\r\n")
+            .expect("?WV", "920\r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port.clone())).unwrap();
+        assert!(matches!(
+            discovery.query(DiscoveryNXQueries::Wavelength{}),
+            Err(CoherentError::InvalidResponseError(_))
+        ));
+        assert!(!port.is_finished());
+
+        // The hook sees each garbled reply, and can give up on the query
+        let port = MockSerialPort::discovery(false, false, "SN1234")
+            .expect("?WV", "garbage\r\n")
+            .expect("?WV", "920\r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port.clone())).unwrap();
+        discovery.retry_policy = RetryPolicy::new(2).with_backoff(std::time::Duration::ZERO, 1.0);
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let hook_seen = seen.clone();
+        discovery.invalid_response_hook = InvalidResponseHook::new(move |query, reply| {
+            hook_seen.lock().unwrap().push((query.to_string(), reply.to_string()));
+            Err(CoherentError::LaserUnavailableError)
+        });
+        assert!(matches!(discovery.get_wavelength(), Err(CoherentError::LaserUnavailableError)));
+        assert_eq!(*seen.lock().unwrap(), vec![("?WV".to_string(), "garbage".to_string())]);
+        assert!(!port.is_finished());
+    }
+
+    #[test]
+    fn test_mock_with_timeout() {
+        // Nothing is sent once the time's up
+        let (mut discovery, port) = mock_discovery(false, false, &[("?WV", "920")]);
+        assert!(matches!(
+            discovery.with_timeout(std::time::Duration::ZERO, |discovery| discovery.get_wavelength()),
+            Err(CoherentError::TimeoutError)
+        ));
+        assert!(port.written().iter().all(|line| line != "?WV"));
+        assert_eq!(discovery.get_wavelength().unwrap(), 920.0);
+
+        // The bound covers waits that would otherwise go on much longer
+        let (mut discovery, _) = mock_discovery(false, false, &[("?TS", "1"); 20]);
+        let start = std::time::Instant::now();
+        assert!(matches!(
+            discovery.with_timeout(std::time::Duration::from_millis(250), |discovery| {
+                discovery.wait_for_tuning(std::time::Duration::from_secs(10), TUNING_POLL_INTERVAL)
+            }),
+            Err(CoherentError::TimeoutError)
+        ));
+        assert!(start.elapsed() < std::time::Duration::from_millis(400), "{:?}", start.elapsed());
+
+        // As does a retry policy's total timeout, for each query
+        let (mut discovery, _) = mock_discovery(false, false, &[]);
+        discovery.retry_policy = RetryPolicy::new(1000)
+            .with_backoff(std::time::Duration::from_millis(10), 1.0)
+            .with_total_timeout(std::time::Duration::from_millis(100));
+        let start = std::time::Instant::now();
+        assert!(discovery.get_wavelength().is_err());
+        assert!(start.elapsed() < std::time::Duration::from_millis(250), "{:?}", start.elapsed());
+    }
+
+    #[test]
+    fn test_mock_commands() {
+        for (echo, prompt) in MODES {
+            let (mut discovery, port) = mock_discovery(echo, prompt, &[
+                ("S=1", ""),
+                ("SFIXED=0", ""),
+                ("WV=840", ""),
+                ("GDD=-500", ""),
+                ("ALIGN=1", ""),
+                ("ALIGNFIXED=0", ""),
+                ("L=0", ""),
+                ("FC", ""),
+                ("HB", ""),
+                ("GDDCURVEN=Objective A", ""),
+                ("GDDCURVE=2", ""),
+            ]);
+
+            discovery.set_shutter(DiscoveryLaser::VariableWavelength, ShutterState::Open).unwrap();
+            discovery.set_shutter(DiscoveryLaser::FixedWavelength, ShutterState::Closed).unwrap();
+            discovery.set_wavelength(840.0).unwrap();
+            discovery.set_gdd(-500.0).unwrap();
+            discovery.set_alignment_mode(DiscoveryLaser::VariableWavelength, AlignmentMode::On).unwrap();
+            discovery.set_alignment_mode(DiscoveryLaser::FixedWavelength, AlignmentMode::Off).unwrap();
+            discovery.send_command(DiscoveryNXCommands::Laser{state : LaserState::Standby}).unwrap();
+            discovery.send_command(DiscoveryNXCommands::FaultClear).unwrap();
+            discovery.send_command(DiscoveryNXCommands::Heartbeat).unwrap();
+            discovery.send_command(
+                DiscoveryNXCommands::GddCurveN{curve_name : "Objective A".to_string()}
+            ).unwrap();
+            discovery.set_gdd_curve(2).unwrap();
+
+            assert!(port.is_finished(), "echo {echo}, prompt {prompt}: {:?}", port.unexpected());
+        }
+    }
+
+    #[test]
+    fn test_mock_common_commands() {
+        use crate::laser::CommonCommand;
+
+        let (mut discovery, port) = mock_discovery(false, false, &[
+            ("S=1", ""),
+            ("S=0", ""),
+            ("L=0", ""),
+            ("L=1", ""),
+            ("FC", ""),
+            ("WV=1040", ""),
+        ]);
+
+        for command in [
+            CommonCommand::OpenShutter,
+            CommonCommand::CloseShutter,
+            CommonCommand::Standby,
+            CommonCommand::On,
+            CommonCommand::ClearFaults,
+            CommonCommand::SetWavelength(1040.0),
+        ] {
+            discovery.send_common_command(command).unwrap();
+        }
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+    }
+
+    #[test]
+    fn test_mock_rejected_commands() {
+        for (echo, prompt) in MODES {
+            // The laser refuses
+            let port = MockSerialPort::discovery(echo, prompt, "SN1234")
+                .expect("WV=5000", &format_reply("WV=5000", "COMMAND NOT EXECUTED", echo, prompt));
+            let mut discovery = Discovery::from_serial_port(Box::new(port)).unwrap();
+            assert!(matches!(
+                discovery.set_wavelength(5000.0),
+                Err(CoherentError::CommandNotExecutedError)
+            ));
+
+            // No reply at all
+            let (mut discovery, _) = mock_discovery(echo, prompt, &[]);
+            assert!(discovery.set_wavelength(840.0).is_err());
+        }
+
+        // Echo on: trailing text after the echo is a complaint about the arguments
+        let port = MockSerialPort::discovery(true, false, "SN1234")
+            .expect("WV=840", "WV=840 Out of range\r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port)).unwrap();
+        assert!(matches!(
+            discovery.set_wavelength(840.0),
+            Err(CoherentError::InvalidArgumentsError(_))
+        ));
+
+        // Echo on: echo doesn't match what was sent
+        let port = MockSerialPort::discovery(true, false, "SN1234")
+            .expect("WV=840", "WV=920 \r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port)).unwrap();
+        assert!(matches!(
+            discovery.set_wavelength(840.0),
+            Err(CoherentError::InvalidResponseError(_))
+        ));
+
+        // Echo off: any reply at all is unexpected
+        let port = MockSerialPort::discovery(false, false, "SN1234")
+            .expect("WV=840", "garbage\r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port)).unwrap();
+        assert!(matches!(
+            discovery.set_wavelength(840.0),
+            Err(CoherentError::InvalidResponseError(_))
+        ));
+
+        // Prompt on, but it's missing
+        let port = MockSerialPort::discovery(false, true, "SN1234")
+            .expect("WV=840", "\r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port)).unwrap();
+        assert!(matches!(
+            discovery.set_wavelength(840.0),
+            Err(CoherentError::InvalidResponseError(_))
+        ));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
     fn test_commands(){
         let mut discovery = Discovery::find_first().unwrap();
 
@@ -821,6 +3016,7 @@ mod tests {
     }
     
     #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
     fn test_queries() {
         let mut discovery = Discovery::find_first().unwrap();
         let echo = discovery.query(DiscoveryNXQueries::Echo{}).unwrap();
@@ -848,13 +3044,14 @@ mod tests {
     }
 
     #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
     fn test_shutter() {
         use std::thread;
         let mut discovery = Discovery::find_first().unwrap();
 
         if 
             discovery.query(DiscoveryNXQueries::Laser{}).unwrap() == LaserState::Standby 
-            || !discovery.query(DiscoveryNXQueries::Keyswitch{}).unwrap()
+            || discovery.query(DiscoveryNXQueries::Keyswitch{}).unwrap() == Keyswitch::Off
         {
             println!("Laser is off, cannot execute shutter commands");
             return;
@@ -907,6 +3104,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
     fn test_wavelength(){
         let mut discovery = Discovery::find_first().unwrap();
 
@@ -917,7 +3115,7 @@ mod tests {
         println!("Wavelength: {:?}", wv);
 
         discovery.send_command(
-            DiscoveryNXCommands::Wavelength{wavelength_nm : 840.0}
+            DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(840.0)}
         ).unwrap();
 
         while discovery.query(DiscoveryNXQueries::Tuning{}).unwrap().into() {
@@ -935,7 +3133,7 @@ mod tests {
         println!("Wavelength: {:?}", new_wv);
 
         discovery.send_command(
-            DiscoveryNXCommands::Wavelength{wavelength_nm : wv}
+            DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(wv)}
         ).unwrap();
 
         while discovery.query(DiscoveryNXQueries::Tuning{}).unwrap().into() {
@@ -955,13 +3153,14 @@ mod tests {
     }
 
     #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
     fn test_invalid_args() {
         let mut discovery = Discovery::find_first().unwrap();
 
         println!("Testing invalid wavelength");
 
         let result = discovery.send_command(
-            DiscoveryNXCommands::Wavelength{wavelength_nm : 0.0}
+            DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(0.0)}
         );
 
         assert!(result.is_err());
@@ -975,7 +3174,7 @@ mod tests {
         println!("Testing invalid GDD");
 
         let result = discovery.send_command(
-            DiscoveryNXCommands::Gdd{gdd_val : 50000.0}
+            DiscoveryNXCommands::Gdd{gdd_val : GddFs2(50000.0)}
         );
 
         assert!(result.is_err());
@@ -991,6 +3190,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
     fn test_gdd() {
         let mut discovery = Discovery::find_first().unwrap();
 
@@ -1001,7 +3201,7 @@ mod tests {
         println!("GDD: {:?}... Setting to 0", current_gdd);
 
         discovery.send_command(
-            DiscoveryNXCommands::Gdd{gdd_val : 0.0}
+            DiscoveryNXCommands::Gdd{gdd_val : GddFs2(0.0)}
         ).unwrap();
 
         let new_gdd = discovery.query(
@@ -1011,7 +3211,7 @@ mod tests {
         println!("New GDD: {:?}", new_gdd);
 
         discovery.send_command(
-            DiscoveryNXCommands::Gdd{gdd_val : current_gdd}
+            DiscoveryNXCommands::Gdd{gdd_val : GddFs2(current_gdd)}
         ).unwrap();
 
         let new_gdd = discovery.query(
@@ -1022,6 +3222,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
     fn test_convenience_funcs() {
         let mut discovery = Discovery::find_first().unwrap();
 
@@ -1087,26 +3288,27 @@ mod tests {
         println!("Shutter state: {:?}", discovery.get_shutter(DiscoveryLaser::FixedWavelength).unwrap());
 
         println!("Setting variable alignment mode to true");
-        discovery.set_alignment_mode(DiscoveryLaser::VariableWavelength, true).unwrap();
+        discovery.set_alignment_mode(DiscoveryLaser::VariableWavelength, AlignmentMode::On).unwrap();
         println!("Alignment mode: {:?}", discovery.get_alignment_mode(DiscoveryLaser::VariableWavelength).unwrap());
 
         std::thread::sleep(std::time::Duration::from_millis(300));
         println!("Setting variable alignment mode to false");
-        discovery.set_alignment_mode(DiscoveryLaser::VariableWavelength, false).unwrap();
+        discovery.set_alignment_mode(DiscoveryLaser::VariableWavelength, AlignmentMode::Off).unwrap();
         println!("Alignment mode: {:?}", discovery.get_alignment_mode(DiscoveryLaser::VariableWavelength).unwrap());
 
         std::thread::sleep(std::time::Duration::from_millis(300));
         println!("Setting fixed alignment mode to true");
-        discovery.set_alignment_mode(DiscoveryLaser::FixedWavelength, true).unwrap();
+        discovery.set_alignment_mode(DiscoveryLaser::FixedWavelength, AlignmentMode::On).unwrap();
         println!("Alignment mode: {:?}", discovery.get_alignment_mode(DiscoveryLaser::FixedWavelength).unwrap());
         std::thread::sleep(std::time::Duration::from_millis(300));
         println!("Setting fixed alignment mode to false");
-        discovery.set_alignment_mode(DiscoveryLaser::FixedWavelength, false).unwrap();
+        discovery.set_alignment_mode(DiscoveryLaser::FixedWavelength, AlignmentMode::Off).unwrap();
         println!("Alignment mode: {:?}", discovery.get_alignment_mode(DiscoveryLaser::FixedWavelength).unwrap());
     }
 
     #[cfg(feature = "network")]
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_serde_command(){
         use rmp_serde::Serializer;
         let command = DiscoveryNXCommands::Echo{echo_on : true};
@@ -1140,8 +3342,56 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_laser_status_accessors() {
+        let mut status = DiscoveryNXStatus{
+            echo : false,
+            laser : LaserState::On,
+            variable_shutter : ShutterState::Closed,
+            fixed_shutter : ShutterState::Closed,
+            keyswitch : Keyswitch::On,
+            faults : FaultFlags::empty(),
+            fault_text : Some("No faults".to_string()),
+            tuning : TuningStatus::Ready,
+            alignment_var : AlignmentMode::Off,
+            alignment_fixed : AlignmentMode::Off,
+            status : "Ready".to_string(),
+            wavelength : 920.0,
+            power_var : Some(1250.0),
+            power_fixed : 800.0,
+            calibrated_power_var : None,
+            calibrated_power_fixed : None,
+            gdd_curve : Some(0),
+            gdd_curve_n : Some("Default".to_string()),
+            gdd : Some(0.0),
+            locked : false,
+            timestamp : 1700000000.0,
+            operating_hours : None,
+            baseplate_temperature : None,
+            humidity : None,
+            diode_current : None,
+            heatsink_ok : None,
+        };
+
+        assert_eq!(status.faults(), 0);
+        assert!(!status.is_emitting());
+        assert_eq!(status.primary_power(), 1250.0);
+        status.power_var = None;
+        assert_eq!(status.primary_power(), 0.0);
+        assert_eq!(
+            status.timestamp().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+            1700000000
+        );
+
+        status.fixed_shutter = ShutterState::Open;
+        assert!(status.is_emitting());
+        status.laser = LaserState::Standby;
+        assert!(!status.is_emitting());
+    }
+
     #[cfg(feature = "network")]
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_serde_query(){
         use rmp_serde::Serializer;
 
@@ -1153,19 +3403,28 @@ mod tests {
             laser : LaserState::On,
             variable_shutter : ShutterState::Open,
             fixed_shutter : ShutterState::Closed,
-            keyswitch : true,
-            faults : 0,
-            fault_text : "No faults".to_string(),
+            keyswitch : Keyswitch::On,
+            faults : FaultFlags::empty(),
+            fault_text : Some("No faults".to_string()),
             tuning : TuningStatus::Ready,
-            alignment_var : true,
-            alignment_fixed : false,
+            alignment_var : AlignmentMode::On,
+            alignment_fixed : AlignmentMode::Off,
             status : "Ready".to_string(),
             wavelength : 840.0,
-            power_var : 100.0,
+            power_var : Some(100.0),
             power_fixed : 100.0,
-            gdd_curve : 0,
-            gdd_curve_n : "Test".to_string(),
-            gdd : 0.0,
+            calibrated_power_var : Some(42.0),
+            calibrated_power_fixed : None,
+            gdd_curve : Some(0),
+            gdd_curve_n : None,
+            gdd : Some(0.0),
+            locked : true,
+            timestamp : 1700000000.5,
+            operating_hours : Some(1250.0),
+            baseplate_temperature : None,
+            humidity : None,
+            diode_current : None,
+            heatsink_ok : Some(false),
         };
 
         test_status.serialize(&mut Serializer::new(&mut buf)).unwrap();
@@ -1179,19 +3438,23 @@ mod tests {
                 assert_eq!(status.laser, LaserState::On);
                 assert_eq!(status.variable_shutter, ShutterState::Open);
                 assert_eq!(status.fixed_shutter, ShutterState::Closed);
-                assert_eq!(status.keyswitch, true);
-                assert_eq!(status.faults, 0);
-                assert_eq!(status.fault_text, "No faults".to_string());
+                assert_eq!(status.keyswitch, Keyswitch::On);
+                assert_eq!(status.faults, FaultFlags::empty());
+                assert_eq!(status.fault_text, Some("No faults".to_string()));
                 assert_eq!(status.tuning, TuningStatus::Ready);
-                assert_eq!(status.alignment_var, true);
-                assert_eq!(status.alignment_fixed, false);
+                assert_eq!(status.alignment_var, AlignmentMode::On);
+                assert_eq!(status.alignment_fixed, AlignmentMode::Off);
                 assert_eq!(status.status, "Ready".to_string());
                 assert_eq!(status.wavelength, 840.0);
-                assert_eq!(status.power_var, 100.0);
+                assert_eq!(status.power_var, Some(100.0));
                 assert_eq!(status.power_fixed, 100.0);
-                assert_eq!(status.gdd_curve, 0);
-                assert_eq!(status.gdd_curve_n, "Test".to_string());
-                assert_eq!(status.gdd, 0.0);
+                assert_eq!(status.gdd_curve, Some(0));
+                assert_eq!(status.gdd_curve_n, None);
+                assert_eq!(status.gdd, Some(0.0));
+                assert_eq!(status.timestamp, 1700000000.5);
+                assert_eq!(status.operating_hours, Some(1250.0));
+                assert_eq!(status.humidity, None);
+                assert_eq!(status.heatsink_ok, Some(false));
             },
             _ => panic!("Wrong status type")
         }
@@ -1203,6 +3466,7 @@ mod tests {
 
     #[cfg(feature = "network")]
     #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
     fn test_polled_real_query(){
 
         let mut discovery = Discovery::find_first().unwrap();
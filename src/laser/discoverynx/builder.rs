@@ -0,0 +1,159 @@
+//! builder.rs
+//!
+//! Opening a `Discovery` with more than a port and a serial number: how
+//! to open the port, whether it should echo, whether to record what goes
+//! over it, and whether to read a whole status straight away so the first
+//! `status` call doesn't wait on the laser.
+
+use crate::CoherentError;
+use crate::laser::Laser;
+use crate::laser::open::LaserOpenOptions;
+use crate::laser::transcript::SerialTranscript;
+use super::{Discovery, DiscoveryNXCommands};
+
+/// Settings for a `Discovery`, applied when it's opened. Start with
+/// `Discovery::builder`.
+///
+/// # Example
+///
+/// ```no_run
+/// use coherent_rs::Discovery;
+/// use coherent_rs::laser::Laser;
+///
+/// let mut discovery = Discovery::builder()
+///     .serial("1234")
+///     .echo_off()
+///     .preload_status()
+///     .open()
+///     .unwrap();
+/// // Read while opening
+/// println!("{:?}", discovery.status().unwrap());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DiscoveryBuilder {
+    port_name : Option<String>,
+    serial_number : Option<String>,
+    options : LaserOpenOptions,
+    echo : Option<bool>,
+    preload_status : bool,
+}
+
+impl DiscoveryBuilder {
+    /// The first Discovery found, opened with the default `LaserOpenOptions`,
+    /// echoing however it already does.
+    pub fn new() -> Self {
+        DiscoveryBuilder::default()
+    }
+
+    /// The port to open. Matched as in `Laser::from_port_name`.
+    pub fn port(mut self, port_name : &str) -> Self {
+        self.port_name = Some(port_name.to_string());
+        self
+    }
+
+    /// The serial number of the laser to open. With a `port`, checked
+    /// against the laser there.
+    pub fn serial(mut self, serial_number : &str) -> Self {
+        self.serial_number = Some(serial_number.to_string());
+        self
+    }
+
+    pub fn options(mut self, options : LaserOpenOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Turns echo on once connected, if it isn't already.
+    pub fn echo_on(mut self) -> Self {
+        self.echo = Some(true);
+        self
+    }
+
+    /// Turns echo off once connected, if it isn't already: replies are
+    /// shorter, so queries are a little faster.
+    pub fn echo_off(mut self) -> Self {
+        self.echo = Some(false);
+        self
+    }
+
+    /// Records every byte sent and received on the port in `transcript`,
+    /// starting with the handshake (see `transcript`).
+    pub fn transcript(mut self, transcript : SerialTranscript) -> Self {
+        self.options.transcript = Some(transcript);
+        self
+    }
+
+    /// Reads a whole status once connected. The next `status` call returns
+    /// it without asking the laser, unless a command has been sent since.
+    pub fn preload_status(mut self) -> Self {
+        self.preload_status = true;
+        self
+    }
+
+    /// Opens the laser and applies the settings.
+    pub fn open(self) -> Result<Discovery, CoherentError> {
+        let discovery = Discovery::open_with(self.port_name.as_deref(), self.serial_number.as_deref(), &self.options)?;
+        self.apply(discovery)
+    }
+
+    /// Applies the settings to a laser on an already-open port (e.g. a
+    /// `MockSerialPort` in tests), as `Discovery::from_serial_port`. The
+    /// port, serial number and options are ignored, except the retry policy
+    /// and transcript.
+    pub fn open_port(self, port : Box<dyn serialport::SerialPort>) -> Result<Discovery, CoherentError> {
+        let mut discovery = Discovery::from_tapped_port(port, self.options.transcript.as_ref())?;
+        discovery.retry_policy = self.options.retry_policy.clone();
+        self.apply(discovery)
+    }
+
+    fn apply(self, mut discovery : Discovery) -> Result<Discovery, CoherentError> {
+        if let Some(echo_on) = self.echo {
+            if echo_on != discovery.echo {
+                discovery.send_command(DiscoveryNXCommands::Echo{echo_on})?;
+            }
+        }
+        if self.preload_status {
+            discovery.preloaded_status = Some(discovery.status()?);
+        }
+        Ok(discovery)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::mock::{MockSerialPort, format_reply};
+
+    #[test]
+    fn test_builder() {
+        let status_exchanges = [
+            ("?E", "0"), ("?L", "1"), ("?S", "0"), ("?SFIXED", "1"), ("?K", "1"), ("?F", "0"), ("?FT", "System OK"),
+            ("?TS", "0"), ("?ALIGNVAR", "0"), ("?ALIGNFIXED", "0"), ("?ST", "OK"), ("?WV", "920"),
+            ("?PVAR", "1250.5"), ("?PFIXED", "800"), ("?GDDCURVE", "2"), ("?GDDCURVEN", "Objective A"), ("?GDD", "-1500"),
+        ];
+        // Echo is on until `E=0`, which is answered without it
+        let port = status_exchanges.iter().fold(
+            MockSerialPort::discovery(true, false, "SN1234").expect("E=0", &format_reply("E=0", "", false, false)),
+            |port, (command, response)| port.expect(command, &format_reply(command, response, false, false))
+        );
+        let mut discovery = Discovery::builder().echo_off().preload_status().open_port(Box::new(port.clone())).unwrap();
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+        let written = port.written().len();
+
+        // Already read
+        assert_eq!(discovery.status().unwrap().wavelength, 920.0);
+        assert_eq!(port.written().len(), written);
+        // Only once
+        assert!(discovery.status().is_err());
+
+        // A command makes the preloaded status stale
+        let port = status_exchanges.iter().fold(
+            MockSerialPort::discovery(false, false, "SN1234"),
+            |port, (command, response)| port.expect(command, &format_reply(command, response, false, false))
+        ).expect("WV=800", "\r\n");
+        let mut discovery = Discovery::builder().echo_off().preload_status().open_port(Box::new(port.clone())).unwrap();
+        discovery.set_wavelength(800.0).unwrap();
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+        assert!(discovery.status().is_err());
+    }
+}
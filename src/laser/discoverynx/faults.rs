@@ -0,0 +1,155 @@
+//! faults.rs
+//!
+//! The Discovery's fault byte (`?F`), with a name for each bit. Bits
+//! without a name are kept rather than dropped, so a newer firmware's
+//! faults still show up as faults.
+//!
+//! The names are provisional: which bit means which fault hasn't been
+//! checked against a table in the Discovery NX operator's manual, or
+//! against a laser, and may be wrong. Anything that has to act on a
+//! particular fault -- a `SafetyPolicy` naming some bits and not others,
+//! say -- should treat any bit as a fault until the mapping is confirmed.
+
+#[cfg(feature = "network")]
+use serde::{Serialize, Deserialize};
+
+/// The faults a Discovery reports. Sent over the network as the plain
+/// byte.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::discoverynx::faults::FaultFlags;
+///
+/// let faults = FaultFlags::from_bits(0b1000_0010);
+/// assert!(faults.contains(FaultFlags::CHILLER));
+/// assert!(!faults.contains(FaultFlags::INTERLOCK));
+/// assert_eq!(faults.unknown(), 0b1000_0000);
+/// assert_eq!(faults.to_string(), "chiller, unknown (0x80)");
+/// ```
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize), serde(transparent))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FaultFlags(u8);
+
+/// Provisional bit assignments -- see the module docs.
+impl FaultFlags {
+    /// An interlock is open
+    pub const INTERLOCK : FaultFlags = FaultFlags(1 << 0);
+    /// The chiller's flow or temperature is out of range
+    pub const CHILLER : FaultFlags = FaultFlags(1 << 1);
+    /// A pump diode's current or temperature is out of range
+    pub const DIODE : FaultFlags = FaultFlags(1 << 2);
+    /// The laser head is too hot or too cold
+    pub const HEAD_TEMPERATURE : FaultFlags = FaultFlags(1 << 3);
+    /// The power supply has a fault
+    pub const POWER_SUPPLY : FaultFlags = FaultFlags(1 << 4);
+    /// The controller lost contact with the laser head
+    pub const COMMUNICATION : FaultFlags = FaultFlags(1 << 5);
+
+    /// Every named flag, with its name.
+    pub const NAMED : [(FaultFlags, &'static str); 6] = [
+        (FaultFlags::INTERLOCK, "interlock"),
+        (FaultFlags::CHILLER, "chiller"),
+        (FaultFlags::DIODE, "diode"),
+        (FaultFlags::HEAD_TEMPERATURE, "head temperature"),
+        (FaultFlags::POWER_SUPPLY, "power supply"),
+        (FaultFlags::COMMUNICATION, "communication"),
+    ];
+
+    pub const fn empty() -> Self {
+        FaultFlags(0)
+    }
+
+    /// Keeps every bit, named or not.
+    pub const fn from_bits(bits : u8) -> Self {
+        FaultFlags(bits)
+    }
+
+    /// The byte the laser sent.
+    pub const fn bits(&self) -> u8 {
+        self.0
+    }
+
+    /// No faults at all.
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether every fault in `other` is set.
+    pub const fn contains(&self, other : FaultFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The bits set that have no name here.
+    pub fn unknown(&self) -> u8 {
+        let named = FaultFlags::NAMED.iter().fold(0, |bits, (flag, _)| bits | flag.0);
+        self.0 & !named
+    }
+
+    /// The names of the named faults that are set.
+    pub fn names(&self) -> Vec<&'static str> {
+        FaultFlags::NAMED.iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+impl std::ops::BitOr for FaultFlags {
+    type Output = Self;
+    fn bitor(self, other : Self) -> Self {
+        FaultFlags(self.0 | other.0)
+    }
+}
+
+impl From<u8> for FaultFlags {
+    fn from(bits : u8) -> Self {
+        FaultFlags::from_bits(bits)
+    }
+}
+
+impl From<FaultFlags> for u8 {
+    fn from(flags : FaultFlags) -> Self {
+        flags.bits()
+    }
+}
+
+/// The names of the faults set, then any unknown bits in hex, or "none".
+impl std::fmt::Display for FaultFlags {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() { return write!(f, "none"); }
+        let mut parts = self.names().iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        if self.unknown() != 0 {
+            parts.push(format!("unknown ({:#04x})", self.unknown()));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        let faults = FaultFlags::INTERLOCK | FaultFlags::DIODE;
+        assert_eq!(faults.bits(), 0b101);
+        assert!(faults.contains(FaultFlags::INTERLOCK | FaultFlags::DIODE));
+        assert!(!faults.contains(FaultFlags::INTERLOCK | FaultFlags::CHILLER));
+        assert_eq!(faults.names(), vec!["interlock", "diode"]);
+        assert_eq!(faults.unknown(), 0);
+        assert_eq!(FaultFlags::empty().to_string(), "none");
+        assert_eq!(FaultFlags::from_bits(0xc0).to_string(), "unknown (0xc0)");
+        assert_eq!(u8::from(FaultFlags::from(0xff)), 0xff);
+    }
+
+    #[cfg(feature = "network")]
+    #[test]
+    fn test_sent_as_a_byte() {
+        let mut buf = Vec::new();
+        FaultFlags::CHILLER.serialize(&mut rmp_serde::Serializer::new(&mut buf)).unwrap();
+        let mut plain = Vec::new();
+        2u8.serialize(&mut rmp_serde::Serializer::new(&mut plain)).unwrap();
+        assert_eq!(buf, plain);
+    }
+}
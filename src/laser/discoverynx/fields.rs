@@ -0,0 +1,396 @@
+//! fields.rs
+//!
+//! Reflection over `DiscoveryNXStatus`: a `StatusField` for each field, so
+//! generic code (dashboards, CSV writers, delta encoding, partial queries)
+//! can walk a status without a hand-written match arm per field.
+
+#[cfg(feature = "network")]
+use serde::{Serialize, Deserialize};
+
+use crate::laser::StatusValue;
+use crate::laser::schema::{Column, ColumnType};
+use crate::laser::units::{Unit, Quantity};
+use super::DiscoveryNXStatus;
+
+/// A field of `DiscoveryNXStatus`.
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum StatusField {
+    Echo,
+    Laser,
+    VariableShutter,
+    FixedShutter,
+    Keyswitch,
+    Faults,
+    FaultText,
+    Tuning,
+    AlignmentVar,
+    AlignmentFixed,
+    Status,
+    Wavelength,
+    PowerVar,
+    PowerFixed,
+    CalibratedPowerVar,
+    CalibratedPowerFixed,
+    GddCurve,
+    GddCurveN,
+    Gdd,
+    Locked,
+    Timestamp,
+    OperatingHours,
+    BaseplateTemperature,
+    Humidity,
+    DiodeCurrent,
+    HeatsinkOk,
+}
+
+impl StatusField {
+    /// Every field, in the order they're declared in `DiscoveryNXStatus`.
+    pub const ALL : [StatusField; 26] = [
+        StatusField::Echo,
+        StatusField::Laser,
+        StatusField::VariableShutter,
+        StatusField::FixedShutter,
+        StatusField::Keyswitch,
+        StatusField::Faults,
+        StatusField::FaultText,
+        StatusField::Tuning,
+        StatusField::AlignmentVar,
+        StatusField::AlignmentFixed,
+        StatusField::Status,
+        StatusField::Wavelength,
+        StatusField::PowerVar,
+        StatusField::PowerFixed,
+        StatusField::CalibratedPowerVar,
+        StatusField::CalibratedPowerFixed,
+        StatusField::GddCurve,
+        StatusField::GddCurveN,
+        StatusField::Gdd,
+        StatusField::Locked,
+        StatusField::Timestamp,
+        StatusField::OperatingHours,
+        StatusField::BaseplateTemperature,
+        StatusField::Humidity,
+        StatusField::DiodeCurrent,
+        StatusField::HeatsinkOk,
+    ];
+
+    /// The field's name in `DiscoveryNXStatus`, which is also its key in a
+    /// `StatusMap` and its parameter name in a `ParameterHistory`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatusField::Echo => "echo",
+            StatusField::Laser => "laser",
+            StatusField::VariableShutter => "variable_shutter",
+            StatusField::FixedShutter => "fixed_shutter",
+            StatusField::Keyswitch => "keyswitch",
+            StatusField::Faults => "faults",
+            StatusField::FaultText => "fault_text",
+            StatusField::Tuning => "tuning",
+            StatusField::AlignmentVar => "alignment_var",
+            StatusField::AlignmentFixed => "alignment_fixed",
+            StatusField::Status => "status",
+            StatusField::Wavelength => "wavelength",
+            StatusField::PowerVar => "power_var",
+            StatusField::PowerFixed => "power_fixed",
+            StatusField::CalibratedPowerVar => "calibrated_power_var",
+            StatusField::CalibratedPowerFixed => "calibrated_power_fixed",
+            StatusField::GddCurve => "gdd_curve",
+            StatusField::GddCurveN => "gdd_curve_n",
+            StatusField::Gdd => "gdd",
+            StatusField::Locked => "locked",
+            StatusField::Timestamp => "timestamp",
+            StatusField::OperatingHours => "operating_hours",
+            StatusField::BaseplateTemperature => "baseplate_temperature",
+            StatusField::Humidity => "humidity",
+            StatusField::DiodeCurrent => "diode_current",
+            StatusField::HeatsinkOk => "heatsink_ok",
+        }
+    }
+
+    /// The field's column in the status schema: its type, unit, and whether
+    /// it can be `Missing`. See `schema::status_schema`.
+    pub fn column(&self) -> Column {
+        use ColumnType::*;
+        let (kind, unit, nullable) = match self {
+            StatusField::Echo | StatusField::Keyswitch | StatusField::AlignmentVar
+                | StatusField::AlignmentFixed | StatusField::Locked => (Bool, None, false),
+            StatusField::Laser | StatusField::VariableShutter | StatusField::FixedShutter
+                | StatusField::Tuning | StatusField::Status => (Text, None, false),
+            StatusField::FaultText | StatusField::GddCurveN => (Text, None, true),
+            StatusField::Faults => (Integer, None, false),
+            StatusField::GddCurve => (Integer, None, true),
+            StatusField::Wavelength | StatusField::PowerFixed
+                | StatusField::Timestamp => (Float, self.unit(), false),
+            StatusField::PowerVar | StatusField::Gdd | StatusField::CalibratedPowerVar | StatusField::CalibratedPowerFixed | StatusField::OperatingHours
+                | StatusField::BaseplateTemperature | StatusField::Humidity
+                | StatusField::DiodeCurrent => (Float, self.unit(), true),
+            StatusField::HeatsinkOk => (Bool, None, true),
+        };
+        Column{name : self.name(), kind, unit, nullable}
+    }
+
+    /// The unit the field's value is in, if it has one.
+    pub fn unit(&self) -> Option<Unit> {
+        match self {
+            StatusField::Wavelength => Some(Unit::Nanometers),
+            StatusField::PowerVar | StatusField::PowerFixed
+                | StatusField::CalibratedPowerVar | StatusField::CalibratedPowerFixed => Some(Unit::Milliwatts),
+            StatusField::Gdd => Some(Unit::FemtosecondsSquared),
+            StatusField::Timestamp => Some(Unit::Seconds),
+            StatusField::OperatingHours => Some(Unit::Hours),
+            StatusField::BaseplateTemperature => Some(Unit::Celsius),
+            StatusField::Humidity => Some(Unit::Percent),
+            StatusField::DiodeCurrent => Some(Unit::Amperes),
+            _ => None,
+        }
+    }
+
+    /// The field called `name` (see `name`), if there is one.
+    pub fn from_name(name : &str) -> Option<StatusField> {
+        StatusField::ALL.into_iter().find(|field| field.name() == name)
+    }
+}
+
+/// The value of `field` in `status`, type-erased the same way as in a
+/// `StatusMap`: enums become the name of their variant, and a field the
+/// laser didn't report, an uncalibrated power or a diagnostic that wasn't
+/// read is `Missing`.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::{Laser, StatusValue, debug::DebugLaser};
+/// use coherent_rs::laser::discoverynx::{StatusField, get_field};
+///
+/// let mut laser = DebugLaser::default();
+/// let status = laser.status().unwrap();
+/// for field in StatusField::ALL {
+///     println!("{} : {:?}", field.name(), get_field(&status, field));
+/// }
+/// assert_eq!(get_field(&status, StatusField::Wavelength), StatusValue::Float(920.0));
+/// ```
+pub fn get_field(status : &DiscoveryNXStatus, field : StatusField) -> StatusValue {
+    let text = |value : &dyn std::fmt::Debug| StatusValue::Text(format!("{:?}", value));
+    let float = |value : Option<f32>| value.map_or(StatusValue::Missing, |value| StatusValue::Float(value as f64));
+    let text_or_missing = |value : &Option<String>| value.clone().map_or(StatusValue::Missing, StatusValue::Text);
+    match field {
+        StatusField::Echo => StatusValue::Bool(status.echo),
+        StatusField::Laser => text(&status.laser),
+        StatusField::VariableShutter => text(&status.variable_shutter),
+        StatusField::FixedShutter => text(&status.fixed_shutter),
+        StatusField::Keyswitch => StatusValue::Bool(status.keyswitch.into()),
+        StatusField::Faults => StatusValue::Integer(status.faults.bits() as i64),
+        StatusField::FaultText => text_or_missing(&status.fault_text),
+        StatusField::Tuning => text(&status.tuning),
+        StatusField::AlignmentVar => StatusValue::Bool(status.alignment_var.into()),
+        StatusField::AlignmentFixed => StatusValue::Bool(status.alignment_fixed.into()),
+        StatusField::Status => StatusValue::Text(status.status.clone()),
+        StatusField::Wavelength => StatusValue::Float(status.wavelength as f64),
+        StatusField::PowerVar => float(status.power_var),
+        StatusField::PowerFixed => StatusValue::Float(status.power_fixed as f64),
+        StatusField::CalibratedPowerVar => float(status.calibrated_power_var),
+        StatusField::CalibratedPowerFixed => float(status.calibrated_power_fixed),
+        StatusField::GddCurve => status.gdd_curve.map_or(StatusValue::Missing, |curve| StatusValue::Integer(curve as i64)),
+        StatusField::GddCurveN => text_or_missing(&status.gdd_curve_n),
+        StatusField::Gdd => float(status.gdd),
+        StatusField::Locked => StatusValue::Bool(status.locked),
+        StatusField::Timestamp => StatusValue::Float(status.timestamp),
+        StatusField::OperatingHours => float(status.operating_hours),
+        StatusField::BaseplateTemperature => float(status.baseplate_temperature),
+        StatusField::Humidity => float(status.humidity),
+        StatusField::DiodeCurrent => float(status.diode_current),
+        StatusField::HeatsinkOk => status.heatsink_ok.map_or(StatusValue::Missing, StatusValue::Bool),
+    }
+}
+
+impl DiscoveryNXStatus {
+    /// See `get_field`.
+    pub fn get(&self, field : StatusField) -> StatusValue {
+        get_field(self, field)
+    }
+
+    /// The value of `field` with its unit, e.g. `Quantity{value : 920.0, unit : Nanometers}`
+    /// for the wavelength. `None` for fields without a unit, or without a value.
+    pub fn quantity(&self, field : StatusField) -> Option<Quantity> {
+        match (get_field(self, field), field.unit()) {
+            (StatusValue::Float(value), Some(unit)) => Some(Quantity::new(value, unit)),
+            _ => None,
+        }
+    }
+
+    /// Every field and its value, in declaration order.
+    pub fn fields(&self) -> impl Iterator<Item = (StatusField, StatusValue)> + '_ {
+        StatusField::ALL.into_iter().map(|field| (field, get_field(self, field)))
+    }
+
+    /// The fields that differ between this status and a `later` one, with
+    /// the value in each. The timestamp is left out -- it always changes.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use coherent_rs::laser::{Laser, debug::DebugLaser};
+    /// use coherent_rs::laser::discoverynx::StatusField;
+    ///
+    /// let mut laser = DebugLaser::default();
+    /// let before = laser.status().unwrap();
+    /// laser.set_wavelength(850.0).unwrap();
+    /// let diff = before.diff(&laser.status().unwrap());
+    /// assert_eq!(diff.fields(), vec![StatusField::Wavelength]);
+    /// assert_eq!(diff.to_string(), "wavelength: 920 -> 850");
+    /// ```
+    pub fn diff(&self, later : &DiscoveryNXStatus) -> StatusDiff {
+        StatusDiff{
+            changes : StatusField::ALL.into_iter()
+                .filter(|field| *field != StatusField::Timestamp)
+                .filter_map(|field| {
+                    let (before, after) = (get_field(self, field), get_field(later, field));
+                    (before != after).then_some(FieldChange{field, before, after})
+                })
+                .collect(),
+        }
+    }
+}
+
+/// One field that changed between two statuses.
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    pub field : StatusField,
+    pub before : StatusValue,
+    pub after : StatusValue,
+}
+
+/// What changed between two statuses, from `DiscoveryNXStatus::diff`:
+/// one `FieldChange` per changed field, in declaration order.
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StatusDiff {
+    pub changes : Vec<FieldChange>,
+}
+
+impl StatusDiff {
+    /// Nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// The fields that changed.
+    pub fn fields(&self) -> Vec<StatusField> {
+        self.changes.iter().map(|change| change.field).collect()
+    }
+
+    /// How `field` changed, if it did.
+    pub fn get(&self, field : StatusField) -> Option<&FieldChange> {
+        self.changes.iter().find(|change| change.field == field)
+    }
+
+    pub fn contains(&self, field : StatusField) -> bool {
+        self.get(field).is_some()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, FieldChange> {
+        self.changes.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a StatusDiff {
+    type Item = &'a FieldChange;
+    type IntoIter = std::slice::Iter<'a, FieldChange>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.changes.iter()
+    }
+}
+
+/// A value as it reads in a log line: bare numbers and text, `-` if missing.
+fn display_value(value : &StatusValue) -> String {
+    match value {
+        StatusValue::Bool(b) => b.to_string(),
+        StatusValue::Integer(i) => i.to_string(),
+        StatusValue::Float(x) => x.to_string(),
+        StatusValue::Text(text) => text.clone(),
+        StatusValue::Missing => "-".to_string(),
+    }
+}
+
+/// `name: before -> after` for each change, comma-separated, for logs.
+impl std::fmt::Display for StatusDiff {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let changes = self.changes.iter()
+            .map(|change| format!(
+                "{}: {} -> {}", change.field.name(), display_value(&change.before), display_value(&change.after)
+            ))
+            .collect::<Vec<_>>();
+        write!(f, "{}", changes.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::{Laser, debug::DebugLaser};
+
+    #[test]
+    fn test_names() {
+        for field in StatusField::ALL {
+            assert_eq!(StatusField::from_name(field.name()), Some(field));
+        }
+        assert_eq!(StatusField::from_name("gdd_curve_n"), Some(StatusField::GddCurveN));
+        assert_eq!(StatusField::from_name("colour"), None);
+    }
+
+    #[cfg(feature = "network")]
+    #[test]
+    fn test_matches_status_map() {
+        let mut laser = DebugLaser::default();
+        laser.set_power_calibration(
+            super::super::DiscoveryLaser::VariableWavelength,
+            Some(crate::laser::calibration::PowerCalibration{scale : 2.0, offset_mw : 0.0}),
+        );
+        let status = laser.status().unwrap();
+        let map = crate::network::to_status_map(&status).unwrap();
+        assert_eq!(map.len(), StatusField::ALL.len());
+        for (field, value) in status.fields() {
+            assert_eq!(map[field.name()], value, "{}", field.name());
+        }
+    }
+
+    #[test]
+    fn test_get_field() {
+        let mut laser = DebugLaser::default();
+        laser.set_wavelength(850.0).unwrap();
+        let status = laser.status().unwrap();
+        assert_eq!(status.get(StatusField::Wavelength), StatusValue::Float(850.0));
+        assert_eq!(status.get(StatusField::CalibratedPowerFixed), StatusValue::Missing);
+        assert_eq!(status.get(StatusField::Laser), StatusValue::Text("On".to_string()));
+        assert_eq!(status.quantity(StatusField::Wavelength), Some(Quantity::new(850.0, Unit::Nanometers)));
+        assert_eq!(status.quantity(StatusField::CalibratedPowerVar), None);
+        assert_eq!(status.quantity(StatusField::Echo), None);
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut laser = DebugLaser::default();
+        let before = laser.status().unwrap();
+        assert!(before.diff(&laser.status().unwrap()).is_empty());
+
+        laser.set_gdd(-5000.0).unwrap();
+        laser.set_shutter(super::super::DiscoveryLaser::VariableWavelength, crate::laser::ShutterState::Open).unwrap();
+        let after = laser.status().unwrap();
+        let diff = before.diff(&after);
+        assert_eq!(diff.fields(), vec![StatusField::VariableShutter, StatusField::Gdd]);
+        assert_eq!(
+            diff.get(StatusField::Gdd),
+            Some(&FieldChange{field : StatusField::Gdd, before : StatusValue::Float(0.0), after : StatusValue::Float(-5000.0)})
+        );
+        assert!(!diff.contains(StatusField::Wavelength));
+        assert_eq!(diff.to_string(), "variable_shutter: Closed -> Open, gdd: 0 -> -5000");
+        // And back
+        assert_eq!(after.diff(&before).get(StatusField::Gdd).unwrap().after, StatusValue::Float(0.0));
+    }
+}
@@ -0,0 +1,150 @@
+//! golden.rs
+//!
+//! The exact serial string for every `DiscoveryNXCommands` variant and every
+//! `DiscoveryNXQueries` query, checked against the RS-232 command table in
+//! the Chameleon Discovery NX operator's manual. If one of these fails, the
+//! wire protocol changed -- check the manual before touching the table.
+//! This is the only table of command strings; add new commands here.
+
+use super::*;
+
+/// `(command, expected string, what the manual says it does)`
+fn command_table() -> Vec<(DiscoveryNXCommands, &'static str, &'static str)> {
+    vec![
+        (DiscoveryNXCommands::Echo{echo_on : true}, "E=1", "Echo on"),
+        (DiscoveryNXCommands::Echo{echo_on : false}, "E=0", "Echo off"),
+        (DiscoveryNXCommands::Laser{state : LaserState::On}, "L=1", "Laser on"),
+        (DiscoveryNXCommands::Laser{state : LaserState::Standby}, "L=0", "Laser to standby"),
+        (
+            DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::VariableWavelength, state : ShutterState::Open},
+            "S=1", "Open the tunable shutter"
+        ),
+        (
+            DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::VariableWavelength, state : ShutterState::Closed},
+            "S=0", "Close the tunable shutter"
+        ),
+        (
+            DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::FixedWavelength, state : ShutterState::Open},
+            "SFIXED=1", "Open the fixed (1040 nm) shutter"
+        ),
+        (
+            DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::FixedWavelength, state : ShutterState::Closed},
+            "SFIXED=0", "Close the fixed (1040 nm) shutter"
+        ),
+        (DiscoveryNXCommands::FaultClear, "FC", "Clear faults"),
+        (
+            DiscoveryNXCommands::AlignmentMode{laser : DiscoveryLaser::VariableWavelength, alignment_mode_on : AlignmentMode::On},
+            "ALIGN=1", "Tunable alignment mode on"
+        ),
+        (
+            DiscoveryNXCommands::AlignmentMode{laser : DiscoveryLaser::VariableWavelength, alignment_mode_on : AlignmentMode::Off},
+            "ALIGN=0", "Tunable alignment mode off"
+        ),
+        (
+            DiscoveryNXCommands::AlignmentMode{laser : DiscoveryLaser::FixedWavelength, alignment_mode_on : AlignmentMode::On},
+            "ALIGNFIXED=1", "Fixed alignment mode on"
+        ),
+        (
+            DiscoveryNXCommands::AlignmentMode{laser : DiscoveryLaser::FixedWavelength, alignment_mode_on : AlignmentMode::Off},
+            "ALIGNFIXED=0", "Fixed alignment mode off"
+        ),
+        (DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(920.0)}, "WV=920", "Tune to wavelength (nm)"),
+        (DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(812.5)}, "WV=812.5", "Tune to wavelength (nm)"),
+        (DiscoveryNXCommands::Heartbeat, "HB", "Heartbeat"),
+        (DiscoveryNXCommands::GddCurve{curve_num : 0}, "GDDCURVE=0", "Select GDD curve by number"),
+        (DiscoveryNXCommands::GddCurve{curve_num : 12}, "GDDCURVE=12", "Select GDD curve by number"),
+        (
+            DiscoveryNXCommands::GddCurveN{curve_name : "25x".to_string()},
+            "GDDCURVEN=25x", "Select GDD curve by name"
+        ),
+        (DiscoveryNXCommands::Gdd{gdd_val : GddFs2(0.0)}, "GDD=0", "Set GDD (fs^2)"),
+        (DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-12500.0)}, "GDD=-12500", "Set GDD (fs^2)"),
+        (
+            DiscoveryNXCommands::SetCurveN{new_curve_name : "25x".to_string()},
+            "SETCURVEN=25x", "Rename the current GDD curve"
+        ),
+    ]
+}
+
+/// `(query string as built, expected string, what the manual says it reports)`
+fn query_table() -> Vec<(String, &'static str, &'static str)> {
+    use DiscoveryNXQueries::*;
+    vec![
+        (Echo{}.to_string(), "?E", "Echo on/off"),
+        (Laser{}.to_string(), "?L", "Laser on/standby"),
+        (Shutter{laser : DiscoveryLaser::VariableWavelength}.to_string(), "?S", "Tunable shutter"),
+        (Shutter{laser : DiscoveryLaser::FixedWavelength}.to_string(), "?SFIXED", "Fixed shutter"),
+        (Keyswitch{}.to_string(), "?K", "Keyswitch"),
+        (Faults{}.to_string(), "?F", "Fault code"),
+        (FaultText{}.to_string(), "?FT", "Fault text"),
+        (Tuning{}.to_string(), "?TS", "Tuning status"),
+        (AlignmentMode{laser : DiscoveryLaser::VariableWavelength}.to_string(), "?ALIGNVAR", "Tunable alignment mode"),
+        (AlignmentMode{laser : DiscoveryLaser::FixedWavelength}.to_string(), "?ALIGNFIXED", "Fixed alignment mode"),
+        (Status{}.to_string(), "?ST", "Status text"),
+        (Wavelength{}.to_string(), "?WV", "Wavelength (nm)"),
+        (Power{laser : DiscoveryLaser::VariableWavelength}.to_string(), "?PVAR", "Tunable power (mW)"),
+        (Power{laser : DiscoveryLaser::FixedWavelength}.to_string(), "?PFIXED", "Fixed power (mW)"),
+        (GddCurve{}.to_string(), "?GDDCURVE", "Current GDD curve number"),
+        (GddCurveN{}.to_string(), "?GDDCURVEN", "Current GDD curve name"),
+        (Gdd{}.to_string(), "?GDD", "GDD (fs^2)"),
+        (Serial{}.to_string(), "?SN", "Serial number"),
+    ]
+}
+
+/// Which variant a command is. The `match` fails to compile when a command
+/// is added, as a reminder to add it here and to `command_table`.
+fn variant_index(command : &DiscoveryNXCommands) -> usize {
+    match command {
+        DiscoveryNXCommands::Echo{..} => 0,
+        DiscoveryNXCommands::Laser{..} => 1,
+        DiscoveryNXCommands::Shutter{..} => 2,
+        DiscoveryNXCommands::FaultClear => 3,
+        DiscoveryNXCommands::AlignmentMode{..} => 4,
+        DiscoveryNXCommands::Wavelength{..} => 5,
+        DiscoveryNXCommands::Heartbeat => 6,
+        DiscoveryNXCommands::GddCurve{..} => 7,
+        DiscoveryNXCommands::GddCurveN{..} => 8,
+        DiscoveryNXCommands::Gdd{..} => 9,
+        DiscoveryNXCommands::SetCurveN{..} => 10,
+        // Not a serial command, so not in the manual
+        DiscoveryNXCommands::ApplyPreset{..} => N_VARIANTS,
+    }
+}
+const N_VARIANTS : usize = 11;
+
+#[test]
+fn golden_commands() {
+    for (command, expected, description) in command_table() {
+        assert_eq!(command.to_string(), expected, "{} ({:?})", description, command);
+    }
+}
+
+#[test]
+fn golden_command_table_covers_every_variant() {
+    let covered = command_table().iter()
+        .map(|(command, _, _)| variant_index(command))
+        .collect::<std::collections::HashSet<_>>();
+    for idx in 0..N_VARIANTS {
+        assert!(covered.contains(&idx), "variant {} has no golden string", idx);
+    }
+}
+
+#[test]
+fn golden_queries() {
+    for (query, expected, description) in query_table() {
+        assert_eq!(query, expected, "{}", description);
+    }
+}
+
+/// No two distinct commands or queries may share a wire string -- selecting
+/// a GDD curve mustn't be confused with setting the GDD, for one.
+#[test]
+fn golden_strings_are_unique() {
+    let mut seen = std::collections::HashSet::new();
+    for (_, expected, _) in command_table() {
+        assert!(seen.insert(expected.to_string()), "duplicate command string {}", expected);
+    }
+    for (_, expected, _) in query_table() {
+        assert!(seen.insert(expected.to_string()), "duplicate query string {}", expected);
+    }
+}
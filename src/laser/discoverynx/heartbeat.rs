@@ -0,0 +1,162 @@
+//! heartbeat.rs
+//!
+//! Keeping the Discovery's communication watchdog fed. With the watchdog
+//! enabled at the controller, the laser treats a link that's been quiet too
+//! long as a lost host; `Discovery::start_heartbeat` sends `HB` every so
+//! often from a thread of its own so a long idle stretch -- an overnight
+//! imaging run at a fixed wavelength, say -- never looks like one.
+//!
+//! The thread takes the `SharedLaser`'s lock for each beat, so a heartbeat
+//! never lands in the middle of someone else's command and reply.
+
+use std::sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::CoherentError;
+use crate::laser::shared::SharedLaser;
+use super::Discovery;
+
+/// The name of the thread `start_heartbeat` starts.
+pub const HEARTBEAT_THREAD : &str = "coherent-heartbeat";
+
+/// The longest the heartbeat thread sleeps before checking whether it's
+/// been stopped, or whether the laser it was waiting on is free.
+const STOP_CHECK : Duration = Duration::from_millis(10);
+
+/// A running heartbeat. Dropping it stops the thread.
+pub struct Heartbeat {
+    stop : Arc<AtomicBool>,
+    sent : Arc<AtomicU64>,
+    failed : Arc<AtomicU64>,
+    thread : Option<JoinHandle<()>>,
+}
+
+impl Heartbeat {
+    /// How many heartbeats the laser has taken.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::SeqCst)
+    }
+
+    /// How many heartbeats failed -- the laser didn't answer, or the port
+    /// couldn't be written.
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::SeqCst)
+    }
+
+    /// Stops sending heartbeats, and waits for the thread to finish.
+    pub fn stop(mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Discovery {
+    /// Sends `HB` to `laser` every `interval` from a new thread, until the
+    /// `Heartbeat` returned is stopped or dropped. If another thread has the
+    /// laser when a beat is due, the beat goes as soon as it's free.
+    /// Pick an `interval` well inside the watchdog's timeout.
+    ///
+    /// Heartbeats aren't refused by an operator lock, and aren't added to
+    /// the `command_log`, where they'd push out the commands that matter.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use coherent_rs::{Discovery, laser::{Laser, shared::SharedLaser}};
+    ///
+    /// let laser = SharedLaser::new(Discovery::find_first().unwrap());
+    /// let heartbeat = Discovery::start_heartbeat(&laser, Duration::from_secs(1)).unwrap();
+    /// // ... hours without a command ...
+    /// println!("{} heartbeats, {} failed", heartbeat.sent(), heartbeat.failed());
+    /// heartbeat.stop();
+    /// ```
+    pub fn start_heartbeat(laser : &SharedLaser<Discovery>, interval : Duration) -> Result<Heartbeat, CoherentError> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sent, failed) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+        let thread = {
+            let laser = laser.clone();
+            let stop = Arc::clone(&stop);
+            let (sent, failed) = (Arc::clone(&sent), Arc::clone(&failed));
+            std::thread::Builder::new()
+                .name(HEARTBEAT_THREAD.to_string())
+                .spawn(move || {
+                    let mut next = Instant::now() + interval;
+                    while sleep_until(next, &stop) {
+                        let result = match laser.try_with(Discovery::send_heartbeat) {
+                            Err(CoherentError::LaserBusyError) => {
+                                next = Instant::now() + STOP_CHECK;
+                                continue;
+                            },
+                            result => result.and_then(|result| result),
+                        };
+                        next = Instant::now() + interval;
+                        match result {
+                            Ok(()) => sent.fetch_add(1, Ordering::SeqCst),
+                            Err(_) => failed.fetch_add(1, Ordering::SeqCst),
+                        };
+                    }
+                })
+                .map_err(CoherentError::WriteError)?
+        };
+        Ok(Heartbeat{stop, sent, failed, thread : Some(thread)})
+    }
+}
+
+/// Sleeps until `until`, or `false` if `stop` is set first.
+fn sleep_until(until : Instant, stop : &AtomicBool) -> bool {
+    loop {
+        if stop.load(Ordering::SeqCst) { return false; }
+        let now = Instant::now();
+        if now >= until { return true; }
+        std::thread::sleep((until - now).min(STOP_CHECK));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::mock::{MockSerialPort, format_reply};
+
+    fn wait_for(condition : impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !condition() {
+            if Instant::now() > deadline { return false; }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        true
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let port = (0..20).fold(MockSerialPort::discovery(false, false, "SN1234"), |port, _| port.expect("HB", &format_reply("HB", "", false, false)));
+        let mut discovery = Discovery::from_serial_port(Box::new(port.clone())).unwrap();
+        discovery.lock("rig-2").unwrap();
+        let laser = SharedLaser::new(discovery);
+        let heartbeat = Discovery::start_heartbeat(&laser, Duration::from_millis(20)).unwrap();
+        assert!(wait_for(|| heartbeat.sent() >= 1));
+
+        // Held off while another thread has the laser
+        let busy = laser.lock().unwrap();
+        let sent = heartbeat.sent();
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(heartbeat.sent(), sent);
+        drop(busy);
+        assert!(wait_for(|| heartbeat.sent() > sent));
+        assert_eq!(heartbeat.failed(), 0);
+        let sent = heartbeat.sent();
+        heartbeat.stop();
+
+        assert!(port.written().iter().filter(|command| *command == "HB").count() as u64 >= sent);
+        assert!(laser.with(|laser| laser.command_history(10).is_empty()).unwrap());
+    }
+}
@@ -0,0 +1,176 @@
+//! limits.rs
+//!
+//! Soft limits: a narrower operating envelope than the firmware enforces,
+//! for shared rigs where a technically valid setting (e.g. a wavelength
+//! the downstream optics aren't coated for) can still do damage. They're
+//! checked in the command path of the laser itself, before anything is
+//! written to the port, so they apply to commands from a
+//! `NetworkLaserServer`'s clients as well as local code. A wavelength or
+//! GDD outside them is refused, or with `clamp`, brought into range.
+
+use crate::CoherentError;
+use crate::laser::ShutterState;
+use crate::laser::units::{Nanometers, GddFs2};
+use super::DiscoveryNXCommands;
+
+/// Limits on the commands a Discovery will accept. Everything is
+/// unrestricted by default.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::{debug::DebugLaser, discoverynx::limits::SoftLimits};
+///
+/// let mut laser = DebugLaser::default();
+/// laser.soft_limits = SoftLimits{wavelength_nm : Some((750.0, 950.0)), ..Default::default()};
+/// assert!(laser.set_wavelength(980.0).is_err());
+///
+/// // Brought into range instead
+/// laser.soft_limits.clamp = true;
+/// laser.set_wavelength(980.0).unwrap();
+/// assert_eq!(laser.get_wavelength().unwrap(), 950.0);
+///
+/// // Deliberately stepping outside the envelope
+/// laser.soft_limits.unsafe_override = true;
+/// laser.set_wavelength(980.0).unwrap();
+/// assert_eq!(laser.get_wavelength().unwrap(), 980.0);
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SoftLimits {
+    /// The largest GDD magnitude allowed, in fs^2
+    pub max_abs_gdd : Option<f32>,
+    /// The (lowest, highest) wavelength allowed, in nm
+    pub wavelength_nm : Option<(f32, f32)>,
+    /// Refuse to open either shutter
+    pub shutter_lockout : bool,
+    /// Set the nearest wavelength or GDD inside the limits, rather than
+    /// refusing one outside them. Shutters are still refused.
+    pub clamp : bool,
+    /// Skip every check. Only for someone who knows why they need it.
+    pub unsafe_override : bool,
+}
+
+impl SoftLimits {
+    /// `command` as it should be sent: checked, and with `clamp`, its
+    /// wavelength or GDD brought inside the limits. `SoftLimitError` if it
+    /// can't be sent at all.
+    pub fn apply(&self, command : DiscoveryNXCommands) -> Result<DiscoveryNXCommands, CoherentError> {
+        let command = self.clamped(&command).unwrap_or(command);
+        self.check(&command)?;
+        Ok(command)
+    }
+
+    /// `command` brought inside the limits, if `clamp` is set and it's a
+    /// wavelength or GDD outside them. `None` otherwise -- including for
+    /// NaN, which `check` refuses.
+    pub fn clamped(&self, command : &DiscoveryNXCommands) -> Option<DiscoveryNXCommands> {
+        if !self.clamp || self.unsafe_override { return None; }
+        match command {
+            DiscoveryNXCommands::Wavelength{wavelength_nm} => {
+                let (low, high) = self.wavelength_nm?;
+                (!wavelength_nm.0.is_nan() && !(low..=high).contains(&wavelength_nm.0))
+                    .then(|| DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(wavelength_nm.0.clamp(low, high))})
+            },
+            DiscoveryNXCommands::Gdd{gdd_val} => {
+                let max = self.max_abs_gdd?;
+                (!gdd_val.0.is_nan() && gdd_val.0.abs() > max)
+                    .then(|| DiscoveryNXCommands::Gdd{gdd_val : GddFs2(gdd_val.0.clamp(-max, max))})
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether `command` is inside the limits, or `SoftLimitError`
+    /// explaining why not.
+    pub fn check(&self, command : &DiscoveryNXCommands) -> Result<(), CoherentError> {
+        if self.unsafe_override { return Ok(()); }
+        match command {
+            DiscoveryNXCommands::Wavelength{wavelength_nm} => {
+                if let Some((low, high)) = self.wavelength_nm {
+                    if !(low..=high).contains(&wavelength_nm.0) {
+                        return Err(CoherentError::SoftLimitError(format!(
+                            "Wavelength {} nm is outside the allowed {}-{} nm", wavelength_nm.0, low, high
+                        )));
+                    }
+                }
+            },
+            DiscoveryNXCommands::Gdd{gdd_val} => {
+                if let Some(max) = self.max_abs_gdd {
+                    if gdd_val.0.abs() > max {
+                        return Err(CoherentError::SoftLimitError(format!(
+                            "GDD {} fs^2 exceeds the allowed +/-{} fs^2", gdd_val.0, max
+                        )));
+                    }
+                }
+            },
+            DiscoveryNXCommands::Shutter{laser, state : ShutterState::Open} if self.shutter_lockout => {
+                return Err(CoherentError::SoftLimitError(format!(
+                    "Shutters are locked out (tried to open {:?})", laser
+                )));
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::discoverynx::DiscoveryLaser;
+
+    #[test]
+    fn test_check() {
+        let limits = SoftLimits{
+            max_abs_gdd : Some(5000.0),
+            wavelength_nm : Some((750.0, 950.0)),
+            shutter_lockout : true,
+            clamp : false,
+            unsafe_override : false,
+        };
+        let open = DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::FixedWavelength, state : ShutterState::Open};
+        let close = DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::FixedWavelength, state : ShutterState::Closed};
+
+        assert!(limits.check(&DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(950.0)}).is_ok());
+        assert!(limits.check(&DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-5000.0)}).is_ok());
+        assert!(limits.check(&close).is_ok());
+        assert!(limits.check(&DiscoveryNXCommands::FaultClear).is_ok());
+
+        for command in [
+            DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(700.0)},
+            DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-5001.0)},
+            DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::FixedWavelength, state : ShutterState::Open},
+        ] {
+            assert!(matches!(limits.check(&command), Err(CoherentError::SoftLimitError(_))), "{:?}", command);
+        }
+
+        let overridden = SoftLimits{unsafe_override : true, ..limits};
+        assert!(overridden.check(&open).is_ok());
+        assert!(SoftLimits::default().check(&open).is_ok());
+    }
+
+    #[test]
+    fn test_clamp() {
+        let limits = SoftLimits{
+            max_abs_gdd : Some(5000.0),
+            wavelength_nm : Some((800.0, 1040.0)),
+            clamp : true,
+            ..Default::default()
+        };
+        assert_eq!(
+            limits.apply(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(1300.0)}).unwrap(),
+            DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(1040.0)}
+        );
+        assert_eq!(
+            limits.apply(DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-9000.0)}).unwrap(),
+            DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-5000.0)}
+        );
+        assert_eq!(
+            limits.apply(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(920.0)}).unwrap(),
+            DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(920.0)}
+        );
+        assert!(limits.apply(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(f32::NAN)}).is_err());
+        let strict = SoftLimits{clamp : false, ..limits};
+        assert!(strict.apply(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(1300.0)}).is_err());
+    }
+}
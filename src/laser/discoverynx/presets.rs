@@ -0,0 +1,441 @@
+//! presets.rs
+//!
+//! Named sets of settings for the Discovery -- "imaging 920", "alignment",
+//! "uncaging 1040" -- saved to a file lab staff can read and edit, and
+//! applied by name with `Laser::apply_preset` or, over the network, the
+//! `DiscoveryNXCommands::ApplyPreset` command. A preset file is TOML, one
+//! table per preset, any setting of which can be left out to leave it alone:
+//!
+//! ```toml
+//! [imaging-920]
+//! wavelength_nm = 920
+//! gdd_fs2 = -5000
+//! variable_shutter = "open"
+//! fixed_shutter = "closed"
+//! alignment_var = false
+//! ```
+//!
+//! A file whose name ends in `.json` is read and written as JSON instead,
+//! with the `json` feature.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+#[cfg(feature = "network")]
+use serde::{Serialize, Deserialize};
+
+use crate::CoherentError;
+use crate::laser::{config, Laser, ShutterState, AlignmentMode, Nanometers, GddFs2};
+use super::{DiscoveryNXCommands, DiscoveryNXStatus, DiscoveryLaser, GDD_RANGE_FS2, TUNING_POLL_INTERVAL};
+use super::profile::WAVELENGTH_RANGE_NM;
+
+/// The settings in a preset file, in the order they're written.
+const KEYS : [&str; 6] = ["wavelength_nm", "gdd_fs2", "variable_shutter", "fixed_shutter", "alignment_var", "alignment_fixed"];
+
+/// The settings one preset applies. `None` leaves a setting alone.
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "network", serde(default))]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Preset {
+    pub wavelength_nm : Option<f32>,
+    pub gdd_fs2 : Option<f32>,
+    pub variable_shutter : Option<ShutterState>,
+    pub fixed_shutter : Option<ShutterState>,
+    pub alignment_var : Option<AlignmentMode>,
+    pub alignment_fixed : Option<AlignmentMode>,
+}
+
+impl Preset {
+    /// Every setting a preset covers, as `status` read them. The GDD is
+    /// left out on firmware without it.
+    pub fn from_status(status : &DiscoveryNXStatus) -> Self {
+        Preset{
+            wavelength_nm : Some(status.wavelength),
+            gdd_fs2 : status.gdd,
+            variable_shutter : Some(status.variable_shutter),
+            fixed_shutter : Some(status.fixed_shutter),
+            alignment_var : Some(status.alignment_var),
+            alignment_fixed : Some(status.alignment_fixed),
+        }
+    }
+
+    /// The commands that apply the preset, in the order to send them: any
+    /// shutters it closes, the alignment modes, the wavelength, the GDD,
+    /// then any shutters it opens -- so no light gets out until everything
+    /// else is set. The GDD comes after the wavelength so it isn't replaced
+    /// by a `wavelength_profile`'s.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use coherent_rs::laser::{ShutterState, LaserCommand};
+    /// use coherent_rs::laser::discoverynx::presets::Preset;
+    ///
+    /// let preset = Preset{wavelength_nm : Some(920.0), variable_shutter : Some(ShutterState::Open), ..Default::default()};
+    /// let commands = preset.commands().iter().map(LaserCommand::to_string).collect::<Vec<_>>();
+    /// assert_eq!(commands, vec!["WV=920", "S=1"]);
+    /// ```
+    pub fn commands(&self) -> Vec<DiscoveryNXCommands> {
+        let shutters = [
+            (DiscoveryLaser::VariableWavelength, self.variable_shutter),
+            (DiscoveryLaser::FixedWavelength, self.fixed_shutter),
+        ];
+        let shutter_commands = |wanted : ShutterState| shutters.into_iter()
+            .filter(move |(_, state)| *state == Some(wanted))
+            .map(move |(laser, _)| DiscoveryNXCommands::Shutter{laser, state : wanted});
+
+        let mut commands = shutter_commands(ShutterState::Closed).collect::<Vec<_>>();
+        for (laser, mode) in [
+            (DiscoveryLaser::VariableWavelength, self.alignment_var),
+            (DiscoveryLaser::FixedWavelength, self.alignment_fixed),
+        ] {
+            if let Some(alignment_mode_on) = mode {
+                commands.push(DiscoveryNXCommands::AlignmentMode{laser, alignment_mode_on});
+            }
+        }
+        if let Some(wavelength_nm) = self.wavelength_nm {
+            commands.push(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(wavelength_nm)});
+        }
+        if let Some(gdd_fs2) = self.gdd_fs2 {
+            commands.push(DiscoveryNXCommands::Gdd{gdd_val : GddFs2(gdd_fs2)});
+        }
+        commands.extend(shutter_commands(ShutterState::Open));
+        commands
+    }
+}
+
+/// Named `Preset`s, kept in order of name.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::discoverynx::presets::Presets;
+///
+/// let presets = Presets::parse("[imaging-920]\nwavelength_nm = 920\ngdd_fs2 = -5000\n\n[alignment]\nalignment_var = true\n").unwrap();
+/// assert_eq!(presets.names().collect::<Vec<_>>(), vec!["alignment", "imaging-920"]);
+/// assert_eq!(presets.get("imaging-920").unwrap().gdd_fs2, Some(-5000.0));
+/// assert_eq!(Presets::parse(&presets.to_toml()).unwrap(), presets);
+/// ```
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "network", serde(transparent))]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Presets {
+    presets : BTreeMap<String, Preset>,
+}
+
+impl Presets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a preset file (see the module docs): a `[name]` line (or
+    /// `["name"]` for a name with spaces) starts each preset, followed by
+    /// its `setting = value` lines. Shutters are `"open"` or `"closed"`, and
+    /// alignment modes `true` or `false`. `#` starts a comment. Reports
+    /// every problem in the file (see `config`).
+    pub fn parse(text : &str) -> Result<Self, CoherentError> {
+        let mut presets = Presets::new();
+        let mut errors = Vec::new();
+        let mut current : Option<String> = None;
+        for (line_number, line) in config::content_lines(text) {
+            if let Some(header) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                let name = unquote(header.trim());
+                match check_name(name) {
+                    Err(e) => errors.push(config::ConfigError::new(line_number, "preset", "a name", header).suggest(e)),
+                    Ok(()) if presets.presets.contains_key(name) => errors.push(
+                        config::ConfigError::new(line_number, "preset", "a new name", name)
+                            .suggest(format!("`{}` is already defined above", name))
+                    ),
+                    Ok(()) => { presets.presets.insert(name.to_string(), Preset::default()); },
+                }
+                current = Some(name.to_string());
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=').map(|(key, value)| (key.trim(), unquote(value.trim()))) else {
+                errors.push(config::ConfigError::new(line_number, line, "`<setting> = <value>` or `[<preset name>]`", line));
+                continue;
+            };
+            let Some(preset) = current.as_ref().and_then(|name| presets.presets.get_mut(name)) else {
+                if current.is_none() {
+                    errors.push(config::ConfigError::new(line_number, key, "inside a preset", line)
+                        .suggest("start the preset with its name, e.g. `[imaging-920]`"));
+                }
+                continue;
+            };
+            let shutter = |value : &str| match value.to_lowercase().as_str() {
+                "open" => Ok(ShutterState::Open),
+                "closed" => Ok(ShutterState::Closed),
+                _ => Err(config::ConfigError::new(line_number, key, "\"open\" or \"closed\"", value)),
+            };
+            let alignment = |value : &str| match value {
+                "true" => Ok(AlignmentMode::On),
+                "false" => Ok(AlignmentMode::Off),
+                _ => Err(config::ConfigError::new(line_number, key, "true or false", value)
+                    .suggest("use true for alignment mode and false for normal operation")),
+            };
+            let result = match key {
+                "wavelength_nm" => config::number(line_number, key, value, "a wavelength in nm", Some(WAVELENGTH_RANGE_NM))
+                    .map(|wavelength| preset.wavelength_nm = Some(wavelength)),
+                "gdd_fs2" => config::number(line_number, key, value, "a GDD in fs^2", Some(GDD_RANGE_FS2))
+                    .map(|gdd| preset.gdd_fs2 = Some(gdd)),
+                "variable_shutter" => shutter(value).map(|state| preset.variable_shutter = Some(state)),
+                "fixed_shutter" => shutter(value).map(|state| preset.fixed_shutter = Some(state)),
+                "alignment_var" => alignment(value).map(|mode| preset.alignment_var = Some(mode)),
+                "alignment_fixed" => alignment(value).map(|mode| preset.alignment_fixed = Some(mode)),
+                _ => Err(config::ConfigError::new(line_number, key, &format!("one of {}", KEYS.join(", ")), line)
+                    .suggest_closest(key, &KEYS)),
+            };
+            if let Err(e) = result { errors.push(e); }
+        }
+        config::report(presets, errors)
+    }
+
+    /// The presets as a preset file, which `parse` reads back.
+    pub fn to_toml(&self) -> String {
+        let mut text = String::new();
+        for (name, preset) in &self.presets {
+            if !text.is_empty() { text.push('\n'); }
+            let bare = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            text.push_str(&if bare { format!("[{}]\n", name) } else { format!("[\"{}\"]\n", name) });
+            let shutter = |state : ShutterState| if state == ShutterState::Open { "\"open\"" } else { "\"closed\"" };
+            let settings = [
+                preset.wavelength_nm.map(|nm| nm.to_string()),
+                preset.gdd_fs2.map(|gdd| gdd.to_string()),
+                preset.variable_shutter.map(|state| shutter(state).to_string()),
+                preset.fixed_shutter.map(|state| shutter(state).to_string()),
+                preset.alignment_var.map(|mode| bool::from(mode).to_string()),
+                preset.alignment_fixed.map(|mode| bool::from(mode).to_string()),
+            ];
+            for (key, value) in KEYS.iter().zip(settings) {
+                if let Some(value) = value {
+                    text.push_str(&format!("{} = {}\n", key, value));
+                }
+            }
+        }
+        text
+    }
+
+    /// Reads and parses a preset file (see `parse`), or with the `json`
+    /// feature, a `.json` file of the same presets.
+    pub fn load<P : AsRef<std::path::Path>>(path : P) -> Result<Self, CoherentError> {
+        if is_json(path.as_ref()) {
+            return config::load(path, "presets", from_json);
+        }
+        config::load(path, "presets", Presets::parse)
+    }
+
+    /// Writes the presets to `path`: as JSON if it ends in `.json` (with the
+    /// `json` feature), otherwise as a preset file.
+    pub fn save<P : AsRef<std::path::Path>>(&self, path : P) -> Result<(), CoherentError> {
+        let path = path.as_ref();
+        let text = if is_json(path) { to_json(self)? } else { self.to_toml() };
+        std::fs::write(path, text).map_err(|e| CoherentError::InvalidArgumentsError(
+            format!("Could not write presets {}: {}", path.display(), e)
+        ))
+    }
+
+    /// The names of the presets, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.presets.keys().map(String::as_str)
+    }
+
+    pub fn get(&self, name : &str) -> Option<&Preset> {
+        self.presets.get(name)
+    }
+
+    /// The preset called `name`, or an `InvalidArgumentsError` naming the
+    /// closest one there is.
+    pub fn find(&self, name : &str) -> Result<&Preset, CoherentError> {
+        self.presets.get(name).ok_or_else(|| {
+            let names = self.names().collect::<Vec<_>>();
+            CoherentError::InvalidArgumentsError(match config::closest(name, &names) {
+                Some(closest) => format!("No preset named `{}` -- did you mean `{}`?", name, closest),
+                None => format!("No preset named `{}`", name),
+            })
+        })
+    }
+
+    /// Adds or replaces the preset called `name`, returning the one it
+    /// replaced. The name can't be empty or contain `"`, `#`, or brackets,
+    /// which wouldn't survive a preset file.
+    pub fn insert(&mut self, name : &str, preset : Preset) -> Result<Option<Preset>, CoherentError> {
+        check_name(name).map_err(|e| CoherentError::InvalidArgumentsError(format!("Preset name `{}`: {}", name, e)))?;
+        Ok(self.presets.insert(name.to_string(), preset))
+    }
+
+    pub fn remove(&mut self, name : &str) -> Option<Preset> {
+        self.presets.remove(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.presets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.presets.is_empty()
+    }
+}
+
+/// Sends `commands` (e.g. a preset's, see `Preset::commands`) through
+/// `Laser::send_command`, waiting up to `settle_timeout` for the laser to
+/// finish tuning after a wavelength before the rest. Stops at the first that
+/// fails.
+pub(crate) fn send_settling<L : Laser<CommandEnum = DiscoveryNXCommands>>(laser : &mut L, commands : Vec<DiscoveryNXCommands>, settle_timeout : Duration)
+    -> Result<(), CoherentError> {
+    for command in commands {
+        let tunes = matches!(command, DiscoveryNXCommands::Wavelength{..});
+        laser.send_command(command)?;
+        if tunes {
+            laser.wait_for_tuning(settle_timeout, TUNING_POLL_INTERVAL)?;
+        }
+    }
+    Ok(())
+}
+
+fn unquote(value : &str) -> &str {
+    value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value)
+}
+
+fn check_name(name : &str) -> Result<(), &'static str> {
+    if name.trim().is_empty() {
+        return Err("a preset needs a name");
+    }
+    if name.contains(['"', '#', '[', ']', '\n']) {
+        return Err("names can't contain quotes, `#` or brackets");
+    }
+    Ok(())
+}
+
+fn is_json(path : &std::path::Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+#[cfg(feature = "json")]
+fn from_json(text : &str) -> Result<Presets, CoherentError> {
+    let presets : Presets = serde_json::from_str(text).map_err(|e| CoherentError::InvalidArgumentsError(e.to_string()))?;
+    for name in presets.names() {
+        check_name(name).map_err(|e| CoherentError::InvalidArgumentsError(format!("Preset name `{}`: {}", name, e)))?;
+    }
+    Ok(presets)
+}
+
+#[cfg(not(feature = "json"))]
+fn from_json(_text : &str) -> Result<Presets, CoherentError> {
+    Err(CoherentError::InvalidArgumentsError("Reading presets from JSON needs the `json` feature".to_string()))
+}
+
+#[cfg(feature = "json")]
+fn to_json(presets : &Presets) -> Result<String, CoherentError> {
+    serde_json::to_string_pretty(presets).map_err(|e| CoherentError::InvalidArgumentsError(e.to_string()))
+}
+
+#[cfg(not(feature = "json"))]
+fn to_json(_presets : &Presets) -> Result<String, CoherentError> {
+    Err(CoherentError::InvalidArgumentsError("Writing presets as JSON needs the `json` feature".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::{LaserCommand, debug::DebugLaser};
+
+    fn presets() -> Presets {
+        Presets::parse(
+            "# rig 2\n\
+            [imaging-920]\n\
+            wavelength_nm = 920\n\
+            gdd_fs2 = -5000  # measured 2026-03\n\
+            variable_shutter = \"open\"\n\
+            fixed_shutter = \"closed\"\n\
+            \n\
+            [\"align 1040\"]\n\
+            fixed_shutter = closed\n\
+            alignment_fixed = true\n"
+        ).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let presets = presets();
+        assert_eq!(presets.names().collect::<Vec<_>>(), vec!["align 1040", "imaging-920"]);
+        assert_eq!(presets.get("imaging-920"), Some(&Preset{
+            wavelength_nm : Some(920.0),
+            gdd_fs2 : Some(-5000.0),
+            variable_shutter : Some(ShutterState::Open),
+            fixed_shutter : Some(ShutterState::Closed),
+            ..Default::default()
+        }));
+        assert_eq!(presets.get("align 1040").unwrap().alignment_fixed, Some(AlignmentMode::On));
+        assert_eq!(Presets::parse(&presets.to_toml()).unwrap(), presets);
+
+        let Err(CoherentError::InvalidArgumentsError(message)) = Presets::parse(
+            "wavelength_nm = 920\n[a]\nwavelenght_nm = 920\ngdd_fs2 = 500\nvariable_shutter = ajar\n[a]\n"
+        ) else { panic!() };
+        let lines = message.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5, "{}", message);
+        assert!(lines[0].starts_with("line 1:"));
+        assert!(lines[1].ends_with("did you mean `wavelength_nm`?"));
+        assert!(lines[2].starts_with("line 4:"));
+        assert!(lines[3].contains("\"open\" or \"closed\""));
+        assert!(lines[4].contains("already defined"));
+    }
+
+    #[test]
+    fn test_commands() {
+        let commands = presets().get("imaging-920").unwrap().commands();
+        assert_eq!(
+            commands.iter().map(LaserCommand::to_string).collect::<Vec<_>>(),
+            vec!["SFIXED=0", "WV=920", "GDD=-5000", "S=1"]
+        );
+        assert!(Preset::default().commands().is_empty());
+    }
+
+    #[test]
+    fn test_insert_and_find() {
+        let mut presets = presets();
+        assert!(presets.insert("", Preset::default()).is_err());
+        assert!(presets.insert("rig #2", Preset::default()).is_err());
+        assert_eq!(presets.insert("uncaging", Preset::default()).unwrap(), None);
+        assert_eq!(presets.len(), 3);
+        let Err(CoherentError::InvalidArgumentsError(message)) = presets.find("imaging-930") else { panic!() };
+        assert!(message.ends_with("did you mean `imaging-920`?"), "{}", message);
+        assert_eq!(presets.remove("uncaging"), Some(Preset::default()));
+        assert!(presets.find("uncaging").is_err());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("coherent-presets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("presets.toml");
+        presets().save(&path).unwrap();
+        assert_eq!(Presets::load(&path).unwrap(), presets());
+        let json = dir.join("presets.json");
+        #[cfg(feature = "json")]
+        {
+            presets().save(&json).unwrap();
+            assert_eq!(Presets::load(&json).unwrap(), presets());
+        }
+        #[cfg(not(feature = "json"))]
+        assert!(presets().save(&json).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut laser = DebugLaser::default();
+        laser.presets = presets();
+        laser.apply_preset("imaging-920").unwrap();
+        assert_eq!(laser.get_wavelength().unwrap(), 920.0);
+        assert_eq!(laser.get_gdd().unwrap(), -5000.0);
+        assert_eq!(laser.get_shutter(DiscoveryLaser::VariableWavelength).unwrap(), ShutterState::Open);
+
+        laser.send_command(DiscoveryNXCommands::ApplyPreset{name : "align 1040".to_string()}).unwrap();
+        assert_eq!(laser.get_alignment_mode(DiscoveryLaser::FixedWavelength).unwrap(), AlignmentMode::On);
+        assert!(laser.apply_preset("missing").is_err());
+        assert_eq!(
+            laser.validate(&DiscoveryNXCommands::ApplyPreset{name : "imaging-920".to_string()}).unwrap(),
+            vec!["SFIXED=0", "WV=920", "GDD=-5000", "S=1"]
+        );
+    }
+}
@@ -0,0 +1,324 @@
+//! profile.rs
+//!
+//! Per-wavelength settings for the Discovery: the GDD (and GDD curve, and
+//! alignment mode) that go with each wavelength on a given rig, applied
+//! automatically once the laser finishes tuning. A `DispersionMap` is the
+//! common case of just the GDD, as measured on the rig.
+
+use crate::CoherentError;
+use crate::laser::config;
+use crate::laser::AlignmentMode;
+use crate::laser::units::GddFs2;
+use super::{DiscoveryNXCommands, DiscoveryLaser, GDD_RANGE_FS2};
+
+/// The Discovery NX's tuning range, which profile wavelengths must be in.
+pub const WAVELENGTH_RANGE_NM : (f32, f32) = (660.0, 1320.0);
+
+/// The settings to apply at one wavelength. `None` leaves a setting alone.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProfileEntry {
+    pub wavelength_nm : f32,
+    pub gdd : Option<f32>,
+    pub gdd_curve : Option<u8>,
+    pub alignment : Option<AlignmentMode>,
+}
+
+/// A table of `ProfileEntry`s, sorted by wavelength. Between two entries
+/// the GDD is interpolated linearly; the curve and alignment mode come from
+/// the nearest entry. Outside the table, the nearest end is used.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::discoverynx::profile::WavelengthProfile;
+///
+/// let profile = WavelengthProfile::parse("800 gdd=-6000 curve=1\n1000 gdd=-2000 curve=1\n").unwrap();
+/// assert_eq!(profile.settings_for(900.0).unwrap().gdd, Some(-4000.0));
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WavelengthProfile {
+    entries : Vec<ProfileEntry>,
+}
+
+impl WavelengthProfile {
+    pub fn new(mut entries : Vec<ProfileEntry>) -> Self {
+        entries.sort_by(|a, b| a.wavelength_nm.total_cmp(&b.wavelength_nm));
+        WavelengthProfile{entries}
+    }
+
+    pub fn entries(&self) -> &[ProfileEntry] {
+        &self.entries
+    }
+
+    /// Reads a profile: one line per wavelength, the wavelength in nm then
+    /// any of `gdd=<fs^2>`, `curve=<n>`, `align=<0|1>`. `#` starts a comment.
+    /// Reports every problem in the file (see `config`).
+    pub fn parse(text : &str) -> Result<Self, CoherentError> {
+        let mut entries = Vec::new();
+        let mut errors = Vec::new();
+        for (line_number, line) in config::content_lines(text) {
+            let mut fields = line.split_whitespace();
+            let wavelength = fields.next().unwrap_or("");
+            let mut entry = ProfileEntry::default();
+            match config::number(line_number, "wavelength", wavelength, "a wavelength in nm", Some(WAVELENGTH_RANGE_NM)) {
+                Ok(wavelength_nm) => entry.wavelength_nm = wavelength_nm,
+                Err(e) => errors.push(e),
+            }
+            for field in fields {
+                let Some((key, value)) = field.split_once('=') else {
+                    errors.push(config::ConfigError::new(line_number, field, "`<setting>=<value>`", field)
+                        .suggest("settings go after the wavelength, e.g. `800 gdd=-6000 curve=1`"));
+                    continue;
+                };
+                let result = match key {
+                    "gdd" => config::number(line_number, key, value, "a GDD in fs^2", None)
+                        .map(|gdd| entry.gdd = Some(gdd)),
+                    "curve" => value.parse().map(|curve| entry.gdd_curve = Some(curve)).map_err(|_| {
+                        config::ConfigError::new(line_number, key, "a curve number from 0 to 255", value)
+                    }),
+                    "align" => match value {
+                        "0" | "1" => {
+                            entry.alignment = Some((value == "1").into());
+                            Ok(())
+                        },
+                        _ => Err(config::ConfigError::new(line_number, key, "0 or 1", value)
+                            .suggest("use 1 for alignment mode and 0 for normal operation")),
+                    },
+                    _ => Err(config::ConfigError::new(line_number, key, "one of gdd, curve, align", field)
+                        .suggest_closest(key, &["gdd", "curve", "align"])),
+                };
+                if let Err(e) = result { errors.push(e); }
+            }
+            entries.push(entry);
+        }
+        config::report(WavelengthProfile::new(entries), errors)
+    }
+
+    /// Reads and parses a profile file (see `parse`).
+    pub fn load<P : AsRef<std::path::Path>>(path : P) -> Result<Self, CoherentError> {
+        config::load(path, "profile", WavelengthProfile::parse)
+    }
+
+    /// The settings for `wavelength_nm`, or `None` if the profile is empty.
+    /// The returned entry's `wavelength_nm` is the one asked for.
+    pub fn settings_for(&self, wavelength_nm : f32) -> Option<ProfileEntry> {
+        let above = self.entries.partition_point(|e| e.wavelength_nm < wavelength_nm);
+        let (low, high) = match above {
+            _ if self.entries.is_empty() => return None,
+            0 => (&self.entries[0], &self.entries[0]),
+            n if n == self.entries.len() => (&self.entries[n - 1], &self.entries[n - 1]),
+            n => (&self.entries[n - 1], &self.entries[n]),
+        };
+        let nearest = if wavelength_nm - low.wavelength_nm <= high.wavelength_nm - wavelength_nm { low } else { high };
+
+        let gdd = match (low.gdd, high.gdd) {
+            (Some(g_low), Some(g_high)) if high.wavelength_nm > low.wavelength_nm => {
+                let t = (wavelength_nm - low.wavelength_nm) / (high.wavelength_nm - low.wavelength_nm);
+                Some(g_low + t * (g_high - g_low))
+            },
+            _ => nearest.gdd,
+        };
+
+        Some(ProfileEntry{
+            wavelength_nm,
+            gdd,
+            gdd_curve : nearest.gdd_curve,
+            alignment : nearest.alignment,
+        })
+    }
+
+    /// The commands that apply the profile at `wavelength_nm`, in the order
+    /// to send them: the curve first, since selecting one changes the GDD.
+    pub fn commands_for(&self, wavelength_nm : f32) -> Vec<DiscoveryNXCommands> {
+        let settings = match self.settings_for(wavelength_nm) {
+            Some(settings) => settings,
+            None => return Vec::new(),
+        };
+        let mut commands = Vec::new();
+        if let Some(curve_num) = settings.gdd_curve {
+            commands.push(DiscoveryNXCommands::GddCurve{curve_num});
+        }
+        if let Some(gdd_val) = settings.gdd {
+            commands.push(DiscoveryNXCommands::Gdd{gdd_val : GddFs2(gdd_val)});
+        }
+        if let Some(alignment_mode_on) = settings.alignment {
+            commands.push(DiscoveryNXCommands::AlignmentMode{
+                laser : DiscoveryLaser::VariableWavelength, alignment_mode_on
+            });
+        }
+        commands
+    }
+}
+
+/// The GDD that precompensates a rig's dispersion at each wavelength,
+/// interpolated linearly between the measured points and clamped to the
+/// nearest one outside them. Install it with `Discovery::set_dispersion_map`
+/// to have the GDD set after every wavelength change.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::discoverynx::profile::DispersionMap;
+///
+/// let map = DispersionMap::parse("wavelength_nm,gdd_fs2\n800,-6000\n1000,-2000\n").unwrap();
+/// assert_eq!(map.gdd_for(900.0), Some(-4000.0));
+/// assert_eq!(map.gdd_for(1200.0), Some(-2000.0));
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DispersionMap {
+    points : Vec<(f32, f32)>,
+}
+
+impl DispersionMap {
+    /// From `(wavelength in nm, GDD in fs^2)` points, in any order.
+    pub fn new(mut points : Vec<(f32, f32)>) -> Self {
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        DispersionMap{points}
+    }
+
+    /// The `(wavelength, GDD)` points, sorted by wavelength.
+    pub fn points(&self) -> &[(f32, f32)] {
+        &self.points
+    }
+
+    /// Reads a map: one line per point, the wavelength in nm then the GDD
+    /// in fs^2, separated by a comma or whitespace, as spreadsheets export
+    /// them. A header line at the top is skipped, and `#` starts a comment.
+    /// Reports every problem in the file (see `config`).
+    pub fn parse(text : &str) -> Result<Self, CoherentError> {
+        let mut points = Vec::new();
+        let mut errors = Vec::new();
+        for (index, (line_number, line)) in config::content_lines(text).enumerate() {
+            let fields = line.split(|c : char| c == ',' || c.is_whitespace())
+                .filter(|field| !field.is_empty())
+                .collect::<Vec<_>>();
+            if index == 0 && fields.first().is_some_and(|field| field.parse::<f32>().is_err()) {
+                continue;
+            }
+            let [wavelength, gdd] = fields[..] else {
+                errors.push(config::ConfigError::new(line_number, "point", "`<wavelength>,<gdd>`", line)
+                    .suggest("one point per line, e.g. `800,-6000`"));
+                continue;
+            };
+            let wavelength = config::number(line_number, "wavelength", wavelength, "a wavelength in nm", Some(WAVELENGTH_RANGE_NM));
+            let gdd = config::number(line_number, "gdd", gdd, "a GDD in fs^2", Some(GDD_RANGE_FS2));
+            match (wavelength, gdd) {
+                (Ok(wavelength), Ok(gdd)) => points.push((wavelength, gdd)),
+                (wavelength, gdd) => errors.extend(wavelength.err().into_iter().chain(gdd.err())),
+            }
+        }
+        config::report(DispersionMap::new(points), errors)
+    }
+
+    /// Reads and parses a map file (see `parse`).
+    pub fn load<P : AsRef<std::path::Path>>(path : P) -> Result<Self, CoherentError> {
+        config::load(path, "dispersion map", DispersionMap::parse)
+    }
+
+    /// The GDD for `wavelength_nm`, or `None` if the map is empty.
+    pub fn gdd_for(&self, wavelength_nm : f32) -> Option<f32> {
+        WavelengthProfile::from(self.clone()).settings_for(wavelength_nm)?.gdd
+    }
+}
+
+/// A profile that sets only the GDD.
+impl From<DispersionMap> for WavelengthProfile {
+    fn from(map : DispersionMap) -> Self {
+        WavelengthProfile::new(map.points.into_iter()
+            .map(|(wavelength_nm, gdd)| ProfileEntry{wavelength_nm, gdd : Some(gdd), ..Default::default()})
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> WavelengthProfile {
+        WavelengthProfile::parse(
+            "# rig 2, 25x objective\n\
+            1000 gdd=-2000 curve=2\n\
+            800 gdd=-6000 curve=1 align=0\n\
+            920 curve=3 # no GDD measured here\n"
+        ).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let profile = profile();
+        let wavelengths = profile.entries().iter().map(|e| e.wavelength_nm).collect::<Vec<_>>();
+        assert_eq!(wavelengths, vec![800.0, 920.0, 1000.0]);
+        assert_eq!(profile.entries()[0], ProfileEntry{
+            wavelength_nm : 800.0, gdd : Some(-6000.0), gdd_curve : Some(1), alignment : Some(AlignmentMode::Off)
+        });
+
+        assert!(WavelengthProfile::parse("").unwrap().entries().is_empty());
+        for bad in ["gdd=1", "800 gdd=lots", "800 curve=-1", "800 align=2", "800 power=3", "2000", "800 gdd"] {
+            assert!(
+                matches!(WavelengthProfile::parse(bad), Err(CoherentError::InvalidArgumentsError(_))),
+                "{}", bad
+            );
+        }
+
+        // Every problem, each on its own line
+        let Err(CoherentError::InvalidArgumentsError(message)) = WavelengthProfile::parse(
+            "800 gdd=-6000
+# fine
+900 curve=one
+1000 algn=1
+"
+        ) else { panic!() };
+        assert_eq!(message.lines().collect::<Vec<_>>(), vec![
+            "line 3: `curve` should be a curve number from 0 to 255, found `one`",
+            "line 4: `algn` should be one of gdd, curve, align, found `algn=1` -- did you mean `align`?",
+        ]);
+    }
+
+    #[test]
+    fn test_settings_for() {
+        let profile = profile();
+        // Exactly on an entry
+        assert_eq!(profile.settings_for(800.0).unwrap().gdd, Some(-6000.0));
+        // 920 has no GDD, so 900 takes the nearest entry's (none)
+        assert_eq!(profile.settings_for(900.0).unwrap().gdd, None);
+        assert_eq!(profile.settings_for(900.0).unwrap().gdd_curve, Some(3));
+        // 920 has no GDD to interpolate from, so 990 takes 1000's
+        assert_eq!(profile.settings_for(990.0).unwrap().gdd, Some(-2000.0));
+        // Clamped outside the table
+        assert_eq!(profile.settings_for(700.0).unwrap().gdd, Some(-6000.0));
+        assert_eq!(profile.settings_for(1100.0).unwrap().gdd_curve, Some(2));
+        assert_eq!(WavelengthProfile::default().settings_for(900.0), None);
+
+        let linear = WavelengthProfile::parse("800 gdd=-6000\n1000 gdd=-2000\n").unwrap();
+        assert_eq!(linear.settings_for(850.0).unwrap().gdd, Some(-5000.0));
+    }
+
+    #[test]
+    fn test_dispersion_map() {
+        let map = DispersionMap::parse("# rig 1\n1000 -2000\n800,\t-6000\n").unwrap();
+        assert_eq!(map.points(), &[(800.0, -6000.0), (1000.0, -2000.0)]);
+        assert_eq!(map.gdd_for(850.0), Some(-5000.0));
+        assert_eq!(map.gdd_for(700.0), Some(-6000.0));
+        assert_eq!(DispersionMap::default().gdd_for(850.0), None);
+        assert_eq!(
+            WavelengthProfile::from(map).commands_for(1000.0),
+            vec![DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-2000.0)}]
+        );
+
+        let Err(CoherentError::InvalidArgumentsError(message)) = DispersionMap::parse(
+            "wavelength,gdd\n800,-6000\n900\n2000,5000\n"
+        ) else { panic!() };
+        assert_eq!(message.lines().count(), 3, "{}", message);
+    }
+
+    #[test]
+    fn test_commands_for() {
+        assert_eq!(profile().commands_for(800.0), vec![
+            DiscoveryNXCommands::GddCurve{curve_num : 1},
+            DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-6000.0)},
+            DiscoveryNXCommands::AlignmentMode{laser : DiscoveryLaser::VariableWavelength, alignment_mode_on : AlignmentMode::Off},
+        ]);
+        assert_eq!(profile().commands_for(920.0), vec![DiscoveryNXCommands::GddCurve{curve_num : 3}]);
+        assert!(WavelengthProfile::default().commands_for(920.0).is_empty());
+    }
+}
@@ -0,0 +1,101 @@
+//! progress.rs
+//!
+//! How far along a wavelength change is. `TuningStatus` only says tuning or
+//! ready; front-ends showing a progress bar want to know how much of the
+//! way the laser has come, and roughly how long the rest will take.
+
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "network")]
+use serde::{Serialize, Deserialize};
+
+/// A wavelength change that's been sent: where it started, if known,
+/// where it's going, and when.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct TuningMove {
+    pub from_nm : Option<f32>,
+    pub to_nm : f32,
+    pub started : Instant,
+}
+
+/// How far the latest wavelength change has got, from the wavelength the
+/// laser reads back.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::{Laser, debug::DebugLaser};
+///
+/// let mut laser = DebugLaser::default();
+/// assert!(laser.tuning_progress().unwrap().is_none());
+/// laser.set_wavelength(800.0).unwrap();
+/// let progress = laser.tuning_progress().unwrap().unwrap();
+/// assert_eq!((progress.from_nm, progress.to_nm), (920.0, 800.0));
+/// assert_eq!(progress.percent(), 100.0);
+/// ```
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TuningProgress {
+    pub from_nm : f32,
+    pub to_nm : f32,
+    pub current_nm : f32,
+    /// From 0 (still at `from_nm`) to 1 (done)
+    pub fraction : f32,
+    /// Since the wavelength was sent
+    pub elapsed : Duration,
+    /// The time left at the rate so far. `None` until the laser has moved.
+    pub eta : Option<Duration>,
+}
+
+impl TuningProgress {
+    /// How far the laser has come from `from_nm` toward `to_nm` at
+    /// `current_nm`, `elapsed` after the change was sent. Done once the
+    /// laser says it's `ready`, wherever it reads.
+    pub fn estimate(from_nm : f32, to_nm : f32, current_nm : f32, elapsed : Duration, ready : bool) -> Self {
+        let distance = to_nm - from_nm;
+        let fraction = if ready || distance == 0.0 {
+            1.0
+        } else {
+            ((current_nm - from_nm) / distance).clamp(0.0, 1.0)
+        };
+        let eta = match fraction {
+            f if f >= 1.0 => Some(Duration::ZERO),
+            f if f > 0.0 => Some(elapsed.mul_f32((1.0 - f) / f)),
+            _ => None,
+        };
+        TuningProgress{from_nm, to_nm, current_nm, fraction, elapsed, eta}
+    }
+
+    pub fn percent(&self) -> f32 {
+        self.fraction * 100.0
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.fraction >= 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let second = Duration::from_secs(1);
+        let halfway = TuningProgress::estimate(800.0, 1000.0, 900.0, second, false);
+        assert_eq!(halfway.fraction, 0.5);
+        assert_eq!(halfway.eta, Some(second));
+
+        // Tuning down, and overshooting
+        assert_eq!(TuningProgress::estimate(1000.0, 800.0, 950.0, second, false).percent(), 25.0);
+        assert!(TuningProgress::estimate(1000.0, 800.0, 790.0, second, false).is_done());
+
+        // Not moved yet, or not moved at all
+        assert_eq!(TuningProgress::estimate(800.0, 1000.0, 800.0, second, false).eta, None);
+        assert!(TuningProgress::estimate(800.0, 800.0, 800.0, second, false).is_done());
+
+        // Ready is ready, even a little off target
+        let ready = TuningProgress::estimate(800.0, 1000.0, 999.6, second, true);
+        assert_eq!((ready.fraction, ready.eta), (1.0, Some(Duration::ZERO)));
+    }
+}
@@ -0,0 +1,173 @@
+//! reconnect.rs
+//!
+//! Getting a `Discovery` back after the USB-serial link drops (a bumped
+//! cable, a hub reset). With a `ReconnectPolicy` set, a query or command
+//! that fails the way a dead port fails has the port reopened -- found again
+//! by the laser's serial number, since it may come back under another name
+//! -- and, once the laser answers, is tried once more. If the laser can't be
+//! found again the caller gets `CoherentError::Reconnecting`, and the next
+//! call tries again.
+
+use crate::CoherentError;
+use crate::laser::Laser;
+use crate::laser::ports;
+use crate::laser::open::LaserOpenOptions;
+use crate::laser::retry::{RetryPolicy, RetryableError, ErrorClass};
+use super::Discovery;
+
+/// Opens the laser with the given serial number, by whatever means.
+type Opener = Box<dyn FnMut(&str) -> Result<Discovery, CoherentError> + Send>;
+
+/// When and how to reopen a `Discovery`'s port. Off unless set as the
+/// laser's `reconnect_policy`.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use coherent_rs::{Discovery, laser::Laser};
+/// use coherent_rs::laser::retry::RetryPolicy;
+/// use coherent_rs::laser::discoverynx::reconnect::ReconnectPolicy;
+///
+/// let mut discovery = Discovery::find_first().unwrap();
+/// // Look for the laser for about 10 s before giving up on a call
+/// discovery.reconnect_policy = Some(ReconnectPolicy::new(
+///     RetryPolicy::new(10).with_backoff(Duration::from_secs(1), 1.0)
+/// ));
+/// ```
+pub struct ReconnectPolicy {
+    /// How many times to try reopening the port on one call, and how long
+    /// to wait between tries. Any error counts as a failed try.
+    pub retry_policy : RetryPolicy,
+    /// How to open the port again
+    pub open_options : LaserOpenOptions,
+    /// The failures that mean the link dropped. A reply that never came is
+    /// a `Timeout`. A `with_timeout` deadline passing never reconnects.
+    pub triggers : Vec<ErrorClass>,
+    opener : Option<Opener>,
+}
+
+impl std::fmt::Debug for ReconnectPolicy {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReconnectPolicy")
+            .field("retry_policy", &self.retry_policy)
+            .field("open_options", &self.open_options)
+            .field("triggers", &self.triggers)
+            .field("opener", &self.opener.is_some())
+            .finish()
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy::new(RetryPolicy::new(5).with_max_backoff(std::time::Duration::from_secs(5)))
+    }
+}
+
+impl ReconnectPolicy {
+    /// Reopens with the default `LaserOpenOptions` after I/O errors and
+    /// timeouts.
+    pub fn new(retry_policy : RetryPolicy) -> Self {
+        ReconnectPolicy{
+            retry_policy,
+            open_options : LaserOpenOptions::default(),
+            triggers : vec![ErrorClass::Io, ErrorClass::Timeout],
+            opener : None,
+        }
+    }
+
+    pub fn with_open_options(mut self, open_options : LaserOpenOptions) -> Self {
+        self.open_options = open_options;
+        self
+    }
+
+    /// Replaces the classes of failure that mean the link dropped.
+    pub fn reconnect_on(mut self, classes : &[ErrorClass]) -> Self {
+        self.triggers = classes.to_vec();
+        self
+    }
+
+    /// Opens the laser with `opener` (given the serial number) instead of
+    /// searching the serial ports -- for a port that isn't a USB device, or
+    /// a `MockSerialPort` in tests.
+    pub fn with_opener<F>(mut self, opener : F) -> Self
+    where F : FnMut(&str) -> Result<Discovery, CoherentError> + Send + 'static {
+        self.opener = Some(Box::new(opener));
+        self
+    }
+
+    /// Whether `error` means the link dropped. `no_reply` is whether the
+    /// last read got no line at all, which the reply parsing reports as an
+    /// `InvalidResponseError`.
+    pub(crate) fn is_link_failure(&self, error : &CoherentError, no_reply : bool) -> bool {
+        let class = match error {
+            CoherentError::InvalidResponseError(_) if no_reply => Some(ErrorClass::Timeout),
+            error => error.error_class(),
+        };
+        class.is_some_and(|class| self.triggers.contains(&class))
+    }
+
+    /// Opens the laser with `serial_number` again, trying as the
+    /// `retry_policy` says. `Reconnecting` if it never turns up.
+    pub(crate) fn reopen(&mut self, serial_number : &str) -> Result<Discovery, CoherentError> {
+        // Any failure to find it is worth another try
+        let policy = self.retry_policy.clone().retry_on(&[ErrorClass::Timeout]);
+        let options = &self.open_options;
+        let opener = &mut self.opener;
+        policy.run(|| {
+            let found = match opener {
+                Some(opener) => opener(serial_number),
+                None => find_by_serial(serial_number, options),
+            };
+            match found {
+                Ok(discovery) if discovery.serial_number == serial_number => Ok(discovery),
+                _ => Err(CoherentError::TimeoutError),
+            }
+        }).map_err(|_| CoherentError::Reconnecting)
+    }
+}
+
+/// Opens each Discovery plugged in until one answers with `serial_number`.
+fn find_by_serial(serial_number : &str, options : &LaserOpenOptions) -> Result<Discovery, CoherentError> {
+    ports::available_ports()?
+        .into_iter()
+        .filter(Discovery::is_valid_device)
+        .filter_map(|port| Discovery::from_port_info_with(&port, options).ok())
+        .find(|discovery| discovery.serial_number == serial_number)
+        .ok_or(CoherentError::UnrecognizedDevice)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_is_link_failure() {
+        let policy = ReconnectPolicy::default();
+        assert!(policy.is_link_failure(&CoherentError::WriteError(std::io::ErrorKind::BrokenPipe.into()), false));
+        assert!(policy.is_link_failure(&CoherentError::TimeoutError, false));
+        assert!(policy.is_link_failure(&CoherentError::InvalidResponseError(String::new()), true));
+        // A garbled reply came from something
+        assert!(!policy.is_link_failure(&CoherentError::InvalidResponseError("9#0".to_string()), false));
+        assert!(!policy.is_link_failure(&CoherentError::CommandNotExecutedError, false));
+        assert!(!policy.reconnect_on(&[ErrorClass::Io]).is_link_failure(&CoherentError::TimeoutError, false));
+    }
+
+    #[test]
+    fn test_reopen_gives_up() {
+        use std::sync::{Arc, atomic::{AtomicU32, Ordering}};
+        use crate::laser::mock::MockSerialPort;
+
+        let tries = Arc::new(AtomicU32::new(0));
+        let counter = tries.clone();
+        let mut policy = ReconnectPolicy::new(RetryPolicy::new(3).with_backoff(Duration::ZERO, 1.0))
+            .with_opener(move |_| {
+                // Found, but it's another laser
+                counter.fetch_add(1, Ordering::Relaxed);
+                Discovery::from_serial_port(Box::new(MockSerialPort::discovery(false, false, "SN9999")))
+            });
+        assert!(matches!(policy.reopen("SN1234"), Err(CoherentError::Reconnecting)));
+        assert_eq!(tries.load(Ordering::Relaxed), 3);
+    }
+}
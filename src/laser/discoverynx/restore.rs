@@ -0,0 +1,140 @@
+//! restore.rs
+//!
+//! `Laser::snapshot` and `Laser::restore` for the Discovery (and the
+//! `DebugLaser`): which settings a snapshot keeps, and the commands that put
+//! them back.
+
+use std::time::Duration;
+
+use crate::CoherentError;
+use crate::laser::{Laser, LaserState, ShutterState, StatusValue, snapshot::LaserSnapshot};
+use super::{DiscoveryNXCommands, DiscoveryNXStatus, StatusField, get_field};
+use super::presets::{self, Preset};
+
+/// The settings in a Discovery's snapshot.
+pub const SNAPSHOT_FIELDS : [StatusField; 8] = [
+    StatusField::Laser,
+    StatusField::VariableShutter,
+    StatusField::FixedShutter,
+    StatusField::AlignmentVar,
+    StatusField::AlignmentFixed,
+    StatusField::Wavelength,
+    StatusField::GddCurve,
+    StatusField::Gdd,
+];
+
+/// The `SNAPSHOT_FIELDS` of `status`, leaving out any it didn't report.
+pub(crate) fn snapshot(status : &DiscoveryNXStatus) -> LaserSnapshot {
+    LaserSnapshot{
+        taken : status.timestamp,
+        settings : SNAPSHOT_FIELDS.into_iter()
+            .map(|field| (field.name().to_string(), get_field(status, field)))
+            .filter(|(_, value)| *value != StatusValue::Missing)
+            .collect(),
+    }
+}
+
+/// Whether a change to `parameter` belongs in a snapshot.
+pub(crate) fn is_snapshot_field(parameter : &str) -> bool {
+    SNAPSHOT_FIELDS.iter().any(|field| field.name() == parameter)
+}
+
+/// The commands that put `snapshot` back: the laser on (or to standby)
+/// first, then the GDD curve, since selecting one changes the GDD, then the
+/// rest in the order a `Preset` applies them. `InvalidArgumentsError`
+/// listing every setting that isn't one of `SNAPSHOT_FIELDS`, or doesn't
+/// hold a value it could.
+pub(crate) fn commands(snapshot : &LaserSnapshot) -> Result<Vec<DiscoveryNXCommands>, CoherentError> {
+    let (mut laser_state, mut gdd_curve, mut preset) = (None, None, Preset::default());
+    let mut errors = Vec::new();
+    for (name, value) in &snapshot.settings {
+        let shutter = || match value {
+            StatusValue::Text(state) if state == "Open" => Some(ShutterState::Open),
+            StatusValue::Text(state) if state == "Closed" => Some(ShutterState::Closed),
+            _ => None,
+        };
+        let float = || match value {
+            StatusValue::Float(x) => Some(*x as f32),
+            StatusValue::Integer(i) => Some(*i as f32),
+            _ => None,
+        };
+        let alignment = || match value {
+            StatusValue::Bool(on) => Some((*on).into()),
+            _ => None,
+        };
+        let restored = match StatusField::from_name(name) {
+            Some(StatusField::Laser) => {
+                laser_state = match value {
+                    StatusValue::Text(state) if state == "On" => Some(LaserState::On),
+                    StatusValue::Text(state) if state == "Standby" || state == "Off" => Some(LaserState::Standby),
+                    _ => None,
+                };
+                laser_state.is_some()
+            },
+            Some(StatusField::VariableShutter) => { preset.variable_shutter = shutter(); preset.variable_shutter.is_some() },
+            Some(StatusField::FixedShutter) => { preset.fixed_shutter = shutter(); preset.fixed_shutter.is_some() },
+            Some(StatusField::AlignmentVar) => { preset.alignment_var = alignment(); preset.alignment_var.is_some() },
+            Some(StatusField::AlignmentFixed) => { preset.alignment_fixed = alignment(); preset.alignment_fixed.is_some() },
+            Some(StatusField::Wavelength) => { preset.wavelength_nm = float(); preset.wavelength_nm.is_some() },
+            Some(StatusField::Gdd) => { preset.gdd_fs2 = float(); preset.gdd_fs2.is_some() },
+            Some(StatusField::GddCurve) => {
+                gdd_curve = match value {
+                    StatusValue::Integer(curve) => u8::try_from(*curve).ok(),
+                    _ => None,
+                };
+                gdd_curve.is_some()
+            },
+            _ => false,
+        };
+        if !restored {
+            errors.push(format!("Can't restore `{}` to {:?}", name, value));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(CoherentError::InvalidArgumentsError(errors.join("\n")));
+    }
+    let mut commands = Vec::new();
+    commands.extend(laser_state.map(|state| DiscoveryNXCommands::Laser{state}));
+    commands.extend(gdd_curve.map(|curve_num| DiscoveryNXCommands::GddCurve{curve_num}));
+    commands.extend(preset.commands());
+    Ok(commands)
+}
+
+/// Sends the `commands` for `snapshot` through `Laser::send_command`,
+/// waiting up to `settle_timeout` for tuning to finish before the rest.
+pub(crate) fn restore<L : Laser<CommandEnum = DiscoveryNXCommands>>(laser : &mut L, snapshot : &LaserSnapshot, settle_timeout : Duration)
+    -> Result<(), CoherentError> {
+    presets::send_settling(laser, commands(snapshot)?, settle_timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::{LaserCommand, debug::DebugLaser, DiscoveryLaser, AlignmentMode};
+
+    #[test]
+    fn test_commands() {
+        let mut laser = DebugLaser::default();
+        laser.set_wavelength(850.0).unwrap();
+        laser.set_gdd_curve(2).unwrap();
+        laser.set_shutter(DiscoveryLaser::VariableWavelength, ShutterState::Open).unwrap();
+        let snapshot = snapshot(&laser.status().unwrap());
+        assert_eq!(
+            commands(&snapshot.clone().without("gdd")).unwrap().iter().map(LaserCommand::to_string).collect::<Vec<_>>(),
+            vec!["L=1", "GDDCURVE=2", "SFIXED=0", "ALIGN=0", "ALIGNFIXED=0", "WV=850", "S=1"]
+        );
+
+        let mut restarted = DebugLaser::default();
+        restarted.restore(&snapshot.clone().without("variable_shutter")).unwrap();
+        let status = restarted.status().unwrap();
+        assert_eq!((status.wavelength, status.gdd_curve), (850.0, Some(2)));
+        assert_eq!(status.variable_shutter, ShutterState::Closed);
+        assert_eq!(status.alignment_var, AlignmentMode::Off);
+
+        let mut garbled = snapshot;
+        garbled.settings.insert("power_var".to_string(), StatusValue::Float(1200.0));
+        garbled.settings.insert("gdd_curve".to_string(), StatusValue::Integer(300));
+        let Err(CoherentError::InvalidArgumentsError(message)) = commands(&garbled) else { panic!() };
+        assert_eq!(message.lines().count(), 2, "{}", message);
+    }
+}
@@ -0,0 +1,111 @@
+//! roles.rs
+//!
+//! Which of the Discovery's outputs does what on a given rig. On one rig the
+//! tunable beam images and the fixed beam uncages; on the next it's the
+//! other way around. Naming the roles once, in a file that stays with the
+//! rig, lets protocol and analysis code ask for `"imaging"` instead of
+//! hard-coding an output.
+
+use crate::CoherentError;
+use crate::laser::config;
+use super::DiscoveryLaser;
+
+/// Experiment roles, each assigned to one of the Discovery's outputs. An
+/// output can play several roles.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::debug::DebugLaser;
+/// use coherent_rs::laser::discoverynx::{DiscoveryLaser, roles::BeamRoles};
+///
+/// let mut laser = DebugLaser::default();
+/// laser.beam_roles = BeamRoles::parse("imaging variable\nuncaging fixed # 1040 nm\n").unwrap();
+/// assert_eq!(laser.beam_roles.output_for("uncaging").unwrap(), DiscoveryLaser::FixedWavelength);
+/// laser.open_shutter_for("imaging").unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BeamRoles {
+    roles : Vec<(String, DiscoveryLaser)>,
+}
+
+impl BeamRoles {
+    pub fn new() -> Self {
+        BeamRoles::default()
+    }
+
+    /// Assigns `role` to `output`, replacing any earlier assignment of it.
+    pub fn assign(mut self, role : &str, output : DiscoveryLaser) -> Self {
+        self.roles.retain(|(name, _)| name != role);
+        self.roles.push((role.to_string(), output));
+        self
+    }
+
+    /// The output playing `role`, or `InvalidArgumentsError` if none is.
+    pub fn output_for(&self, role : &str) -> Result<DiscoveryLaser, CoherentError> {
+        self.roles.iter()
+            .find(|(name, _)| name == role)
+            .map(|(_, output)| *output)
+            .ok_or_else(|| CoherentError::InvalidArgumentsError(format!("No output is assigned the role `{}`", role)))
+    }
+
+    /// The roles `output` plays, in the order they were assigned.
+    pub fn roles_of(&self, output : DiscoveryLaser) -> Vec<&str> {
+        self.roles.iter().filter(|(_, assigned)| *assigned == output).map(|(name, _)| name.as_str()).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, DiscoveryLaser)> + '_ {
+        self.roles.iter().map(|(name, output)| (name.as_str(), *output))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roles.is_empty()
+    }
+
+    /// Reads role assignments: one `<role> <variable|fixed>` line per role,
+    /// with `#` starting a comment. Reports every problem in the file (see
+    /// `config`).
+    pub fn parse(text : &str) -> Result<Self, CoherentError> {
+        let mut roles = BeamRoles::new();
+        let mut errors = Vec::new();
+        for (line_number, line) in config::content_lines(text) {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let [role, output] = fields.as_slice() else {
+                errors.push(config::ConfigError::new(line_number, "line", "`<role> <variable|fixed>`", line)
+                    .suggest("e.g. `imaging variable`"));
+                continue;
+            };
+            match DiscoveryLaser::from_output_name(output) {
+                Some(output) => roles = roles.assign(role, output),
+                None => errors.push(config::ConfigError::new(line_number, role, "`variable` or `fixed`", output)
+                    .suggest_closest(output, &["variable", "fixed"])),
+            }
+        }
+        config::report(roles, errors)
+    }
+
+    /// Reads and parses a roles file (see `parse`).
+    pub fn load<P : AsRef<std::path::Path>>(path : P) -> Result<Self, CoherentError> {
+        config::load(path, "beam roles", BeamRoles::parse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roles() {
+        let roles = BeamRoles::parse("# rig 3\nimaging fixed\nuncaging variable\nimaging variable\n").unwrap();
+        assert_eq!(roles.output_for("imaging").unwrap(), DiscoveryLaser::VariableWavelength);
+        assert_eq!(roles.roles_of(DiscoveryLaser::VariableWavelength), vec!["uncaging", "imaging"]);
+        assert!(roles.roles_of(DiscoveryLaser::FixedWavelength).is_empty());
+        assert!(matches!(roles.output_for("stimulation"), Err(CoherentError::InvalidArgumentsError(_))));
+
+        assert!(BeamRoles::parse("").unwrap().is_empty());
+        assert!(BeamRoles::parse("imaging").is_err());
+        assert!(BeamRoles::parse("imaging tunable").is_err());
+        let Err(CoherentError::InvalidArgumentsError(message)) = BeamRoles::parse("imaging varible") else { panic!() };
+        assert!(message.ends_with("did you mean `variable`?"), "{}", message);
+    }
+}
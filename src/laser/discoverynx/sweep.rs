@@ -0,0 +1,111 @@
+//! sweep.rs
+//!
+//! Finding the GDD that gives the shortest pulses at the sample: step the
+//! GDD across a range, measure something that peaks with the shortest
+//! pulse (two-photon signal, SHG power...) at each point, and keep the
+//! best. Labs otherwise do this by hand at every new wavelength.
+
+use std::time::Duration;
+
+use crate::CoherentError;
+use crate::laser::cancel::Cancellation;
+use super::GDD_RANGE_FS2;
+
+/// Every point of a `sweep_gdd`, and the best of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GddSweep {
+    /// `(GDD in fs^2, signal)` at each step, in the order measured
+    pub points : Vec<(f32, f32)>,
+    /// The GDD with the largest signal
+    pub optimum_fs2 : f32,
+    pub peak_signal : f32,
+}
+
+/// The GDDs from `range.0` to `range.1` (inclusive, in either direction)
+/// every `step` fs^2. Both ends must be within `GDD_RANGE_FS2`.
+pub(crate) fn gdd_steps(range : (f32, f32), step : f32) -> Result<Vec<f32>, CoherentError> {
+    let in_range = |gdd : f32| gdd.is_finite() && (GDD_RANGE_FS2.0..=GDD_RANGE_FS2.1).contains(&gdd);
+    if !(step.is_finite() && step > 0.0) {
+        return Err(CoherentError::InvalidArgumentsError(format!("GDD step must be positive, not {}", step)));
+    }
+    if !in_range(range.0) || !in_range(range.1) {
+        return Err(CoherentError::InvalidArgumentsError(format!(
+            "GDD sweep {:?} is outside {:?} fs^2", range, GDD_RANGE_FS2
+        )));
+    }
+    let span = range.1 - range.0;
+    let count = (span.abs() / step + 1e-3).floor() as usize;
+    Ok((0..=count).map(|i| range.0 + span.signum() * step * i as f32).collect())
+}
+
+/// Sets each GDD in turn with `set`, waits `dwell`, then reads `measure`.
+/// Measurements that aren't numbers are recorded but never the optimum;
+/// `InvalidResponseError` if none were. `Cancelled` as soon as
+/// `cancellation` is, between or during steps.
+pub(crate) fn run(
+    range : (f32, f32),
+    step : f32,
+    dwell : Duration,
+    cancellation : &Cancellation,
+    mut set : impl FnMut(f32) -> Result<(), CoherentError>,
+    mut measure : impl FnMut() -> f32,
+) -> Result<GddSweep, CoherentError> {
+    let mut points = Vec::new();
+    for gdd in gdd_steps(range, step)? {
+        cancellation.check()?;
+        set(gdd)?;
+        cancellation.sleep(dwell)?;
+        points.push((gdd, measure()));
+    }
+    let (optimum_fs2, peak_signal) = points.iter()
+        .filter(|(_, signal)| !signal.is_nan())
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .copied()
+        .ok_or(CoherentError::InvalidResponseError("No GDD sweep measurement was a number".to_string()))?;
+    Ok(GddSweep{points, optimum_fs2, peak_signal})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::cancel::CancelToken;
+
+    #[test]
+    fn test_gdd_steps() {
+        assert_eq!(gdd_steps((-3000.0, -1000.0), 1000.0).unwrap(), vec![-3000.0, -2000.0, -1000.0]);
+        assert_eq!(gdd_steps((-1000.0, -3000.0), 1500.0).unwrap(), vec![-1000.0, -2500.0]);
+        assert_eq!(gdd_steps((-500.0, -500.0), 100.0).unwrap(), vec![-500.0]);
+        for (range, step) in [((-3000.0, -1000.0), 0.0), ((-3000.0, 1000.0), 100.0), ((f32::NAN, 0.0), 100.0)] {
+            assert!(matches!(gdd_steps(range, step), Err(CoherentError::InvalidArgumentsError(_))));
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let token = CancelToken::new();
+        let cancellation = token.watch();
+        let mut set = Vec::new();
+        let mut signals = [1.0, f32::NAN, 5.0, 2.0].into_iter();
+        let sweep = run((0.0, -3000.0), 1000.0, Duration::ZERO, &cancellation, |gdd| { set.push(gdd); Ok(()) }, || signals.next().unwrap()).unwrap();
+        assert_eq!(set, vec![0.0, -1000.0, -2000.0, -3000.0]);
+        assert_eq!((sweep.optimum_fs2, sweep.peak_signal), (-2000.0, 5.0));
+        assert_eq!(sweep.points.len(), 4);
+
+        assert!(matches!(
+            run((0.0, 0.0), 1.0, Duration::ZERO, &cancellation, |_| Ok(()), || f32::NAN),
+            Err(CoherentError::InvalidResponseError(_))
+        ));
+        assert!(matches!(
+            run((0.0, -1000.0), 500.0, Duration::ZERO, &cancellation, |_| Err(CoherentError::LockedError), || 1.0),
+            Err(CoherentError::LockedError)
+        ));
+
+        // Stops before the next step once cancelled
+        let mut steps = 0;
+        assert!(matches!(
+            run((0.0, -3000.0), 1000.0, Duration::ZERO, &cancellation, |_| { steps += 1; Ok(()) }, || { token.cancel(); 1.0 }),
+            Err(CoherentError::Cancelled)
+        ));
+        assert_eq!(steps, 1);
+    }
+}
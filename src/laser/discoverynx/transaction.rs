@@ -0,0 +1,161 @@
+//! transaction.rs
+//!
+//! Settings that have to change together -- a wavelength and the GDD that
+//! goes with it -- sent as one batch with `Discovery::transaction`. The
+//! batch has the laser to itself for as long as it runs, leaves time
+//! between commands for the laser to take each one, and if any step fails,
+//! puts back every setting it had already changed.
+
+use std::time::Duration;
+
+use crate::CoherentError;
+use crate::laser::{Laser, ShutterState, AlignmentMode, Nanometers, GddFs2};
+use super::{Discovery, DiscoveryLaser, DiscoveryNXCommands, TUNING_POLL_INTERVAL};
+
+/// The default wait between the commands in a transaction. The laser can
+/// refuse a command that follows another too closely.
+pub const TRANSACTION_SPACING : Duration = Duration::from_millis(50);
+
+/// The commands sent so far in a `Discovery::transaction`, and how to undo
+/// them. Each command waits `spacing` after the one before it, and a
+/// wavelength change waits for tuning to finish (up to the laser's
+/// `tuning_hooks.settle_timeout`) before the next command goes.
+pub struct Transaction<'a> {
+    laser : &'a mut Discovery,
+    /// The commands that put back what's been changed, oldest first
+    undo : Vec<DiscoveryNXCommands>,
+    /// Wait this long between commands
+    pub spacing : Duration,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(laser : &'a mut Discovery) -> Self {
+        Transaction{laser, undo : Vec::new(), spacing : TRANSACTION_SPACING}
+    }
+
+    /// Sends `command` through `Laser::send_command`, after reading the
+    /// setting it changes so it can be put back. If it fails, it's put back
+    /// along with the rest.
+    pub fn send_command(&mut self, command : DiscoveryNXCommands) -> Result<(), CoherentError> {
+        if !self.undo.is_empty() { std::thread::sleep(self.spacing); }
+        let undo = undo_command(self.laser, &command)?;
+        let tunes = matches!(command, DiscoveryNXCommands::Wavelength{..});
+        // Before sending: a command that fails may still have been applied
+        self.undo.extend(undo);
+        self.laser.send_command(command)?;
+        if tunes {
+            self.laser.wait_for_tuning(self.laser.tuning_hooks.settle_timeout, TUNING_POLL_INTERVAL)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_wavelength(&mut self, wavelength : impl Into<Nanometers>) -> Result<(), CoherentError> {
+        self.send_command(DiscoveryNXCommands::Wavelength{wavelength_nm : wavelength.into()})
+    }
+
+    pub fn set_gdd(&mut self, gdd : impl Into<GddFs2>) -> Result<(), CoherentError> {
+        self.send_command(DiscoveryNXCommands::Gdd{gdd_val : gdd.into()})
+    }
+
+    pub fn set_gdd_curve(&mut self, curve : u8) -> Result<(), CoherentError> {
+        self.send_command(DiscoveryNXCommands::GddCurve{curve_num : curve})
+    }
+
+    pub fn set_gdd_curve_n(&mut self, name : &str) -> Result<(), CoherentError> {
+        self.send_command(DiscoveryNXCommands::GddCurveN{curve_name : name.to_string()})
+    }
+
+    pub fn set_shutter(&mut self, laser : DiscoveryLaser, state : ShutterState) -> Result<(), CoherentError> {
+        self.send_command(DiscoveryNXCommands::Shutter{laser, state})
+    }
+
+    pub fn set_alignment_mode(&mut self, laser : DiscoveryLaser, mode : AlignmentMode) -> Result<(), CoherentError> {
+        self.send_command(DiscoveryNXCommands::AlignmentMode{laser, alignment_mode_on : mode})
+    }
+
+    /// The laser, to read it between steps. Commands sent through it
+    /// directly aren't undone.
+    pub fn laser(&mut self) -> &mut Discovery {
+        self.laser
+    }
+
+    /// Sends the undo commands, newest first. Keeps going past any that
+    /// fail, and returns the first failure.
+    pub(crate) fn roll_back(&mut self) -> Result<(), CoherentError> {
+        let mut result = Ok(());
+        while let Some(command) = self.undo.pop() {
+            std::thread::sleep(self.spacing);
+            if let Err(e) = self.laser.send_command(command) {
+                if result.is_ok() { result = Err(e); }
+            }
+        }
+        result
+    }
+}
+
+/// The command that puts back what `command` is about to change, read from
+/// the laser. `None` for commands that don't change a setting.
+fn undo_command(laser : &mut Discovery, command : &DiscoveryNXCommands) -> Result<Option<DiscoveryNXCommands>, CoherentError> {
+    Ok(Some(match command {
+        DiscoveryNXCommands::Echo{..} => DiscoveryNXCommands::Echo{echo_on : laser.echo},
+        DiscoveryNXCommands::Laser{..} => DiscoveryNXCommands::Laser{state : laser.get_standby()?},
+        DiscoveryNXCommands::Shutter{laser : output, ..} =>
+            DiscoveryNXCommands::Shutter{laser : *output, state : laser.get_shutter(*output)?},
+        DiscoveryNXCommands::AlignmentMode{laser : output, ..} =>
+            DiscoveryNXCommands::AlignmentMode{laser : *output, alignment_mode_on : laser.get_alignment_mode(*output)?},
+        DiscoveryNXCommands::Wavelength{..} => DiscoveryNXCommands::Wavelength{wavelength_nm : laser.get_wavelength()?.into()},
+        DiscoveryNXCommands::GddCurve{..} => DiscoveryNXCommands::GddCurve{
+            curve_num : u8::try_from(laser.get_gdd_curve()?)
+                .map_err(|_| CoherentError::InvalidResponseError("GDD curve out of range".to_string()))?
+        },
+        DiscoveryNXCommands::GddCurveN{..} => DiscoveryNXCommands::GddCurveN{curve_name : laser.get_gdd_curve_n()?},
+        DiscoveryNXCommands::SetCurveN{..} => DiscoveryNXCommands::SetCurveN{new_curve_name : laser.get_gdd_curve_n()?},
+        DiscoveryNXCommands::Gdd{..} => DiscoveryNXCommands::Gdd{gdd_val : laser.get_gdd()?.into()},
+        DiscoveryNXCommands::FaultClear | DiscoveryNXCommands::Heartbeat => return Ok(None),
+        DiscoveryNXCommands::ApplyPreset{name} => return Err(CoherentError::InvalidArgumentsError(format!(
+            "Preset `{}` can't be undone as one command: send its settings one by one", name
+        ))),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CoherentError;
+    use crate::laser::Discovery;
+    use crate::laser::mock::MockSerialPort;
+
+    #[test]
+    fn test_transaction() {
+        let port = MockSerialPort::discovery(false, false, "SN1234")
+            .expect("?WV", "920\r\n")
+            .expect("WV=800", "\r\n")
+            .expect("?TS", "0\r\n")
+            .expect("?GDD", "0\r\n")
+            .expect("GDD=-5000", "\r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port.clone())).unwrap();
+        let gdd = discovery.transaction(|tx| {
+            tx.set_wavelength(800.0)?;
+            tx.set_gdd(-5000.0)?;
+            Ok(-5000.0)
+        }).unwrap();
+        assert_eq!(gdd, -5000.0);
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+
+        // The GDD is refused, so everything goes back
+        let port = MockSerialPort::discovery(false, false, "SN1234")
+            .expect("?WV", "920\r\n")
+            .expect("WV=800", "\r\n")
+            .expect("?TS", "0\r\n")
+            .expect("?GDD", "0\r\n")
+            .expect("GDD=-90000", "COMMAND NOT EXECUTED\r\n")
+            .expect("GDD=0", "\r\n")
+            .expect("WV=920", "\r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port.clone())).unwrap();
+        let result = discovery.transaction(|tx| {
+            tx.set_wavelength(800.0)?;
+            tx.set_gdd(-90000.0)
+        });
+        assert!(matches!(result, Err(CoherentError::CommandNotExecutedError)));
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+    }
+}
@@ -0,0 +1,205 @@
+//! fault_monitor.rs
+//!
+//! Noticing the laser fault, and clear, without writing a polling loop. A
+//! `FaultMonitor` reads the laser's `fault_report` (`?F` and `?FT` on a
+//! Discovery) every so often from a thread of its own, and calls back when
+//! faults appear and when they clear, with the faults decoded.
+
+use std::sync::{Arc, Mutex, MutexGuard, atomic::{AtomicBool, AtomicU8, Ordering}};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::CoherentError;
+use crate::laser::{Laser, FaultReport, shared::SharedLaser, discoverynx::faults::FaultFlags};
+
+/// The name of the thread `FaultMonitor::start` starts.
+pub const FAULT_MONITOR_THREAD : &str = "coherent-fault-monitor";
+
+/// The longest the monitor's thread sleeps before checking whether it's
+/// been stopped.
+const STOP_CHECK : Duration = Duration::from_millis(10);
+
+/// A change in the laser's faults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultChange {
+    /// The faults that just appeared (for `on_fault`) or just cleared (for
+    /// `on_clear`)
+    pub changed : FaultFlags,
+    /// Every fault set now
+    pub faults : FaultFlags,
+    /// The laser's description of `faults`
+    pub text : String,
+}
+
+/// A callback for a `FaultChange`. It runs on the monitor's thread, so it
+/// shouldn't take long.
+pub type FaultCallback = Box<dyn FnMut(&FaultChange) + Send>;
+
+#[derive(Default)]
+struct Callbacks {
+    on_fault : Vec<FaultCallback>,
+    on_clear : Vec<FaultCallback>,
+}
+
+/// A running fault monitor. Dropping it stops the thread.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use coherent_rs::laser::{debug::DebugLaser, shared::SharedLaser, fault_monitor::FaultMonitor};
+///
+/// let laser = SharedLaser::new(DebugLaser::default());
+/// let monitor = FaultMonitor::start(laser, Duration::from_secs(1)).unwrap();
+/// monitor.on_fault(|change| eprintln!("Laser fault: {} ({})", change.changed, change.text));
+/// monitor.on_clear(|change| eprintln!("Cleared: {}", change.changed));
+/// assert!(monitor.faults().is_empty());
+/// monitor.stop();
+/// ```
+pub struct FaultMonitor {
+    stop : Arc<AtomicBool>,
+    faults : Arc<AtomicU8>,
+    callbacks : Arc<Mutex<Callbacks>>,
+    thread : Option<JoinHandle<()>>,
+}
+
+impl FaultMonitor {
+    /// Reads `laser`'s faults every `interval` from a new thread, waiting
+    /// its turn if another thread has the laser. Faults already set when it
+    /// starts are reported as appearing on the first read. A read that
+    /// fails is skipped -- see `keepalive` to hear about a laser that's
+    /// stopped answering.
+    pub fn start<L : Laser + 'static>(laser : SharedLaser<L>, interval : Duration) -> Result<Self, CoherentError> {
+        Self::spawn(laser, interval, |laser| laser.fault_report())
+    }
+
+    /// Starts a monitor that gets each fault report from `read`.
+    pub(super) fn spawn<L, F>(laser : SharedLaser<L>, interval : Duration, mut read : F) -> Result<Self, CoherentError>
+    where L : Laser + 'static, F : FnMut(&mut L) -> Result<FaultReport, CoherentError> + Send + 'static {
+        let stop = Arc::new(AtomicBool::new(false));
+        let faults = Arc::new(AtomicU8::new(0));
+        let callbacks = Arc::new(Mutex::new(Callbacks::default()));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            let faults = Arc::clone(&faults);
+            let callbacks = Arc::clone(&callbacks);
+            std::thread::Builder::new()
+                .name(FAULT_MONITOR_THREAD.to_string())
+                .spawn(move || {
+                    let mut next = Instant::now();
+                    while sleep_until(next, &stop) {
+                        next = Instant::now() + interval;
+                        let Ok(Ok(report)) = laser.with(&mut read) else { continue; };
+                        let now = FaultFlags::from_bits(report.code);
+                        let before = FaultFlags::from_bits(faults.swap(now.bits(), Ordering::SeqCst));
+                        let (appeared, cleared) = (now.bits() & !before.bits(), before.bits() & !now.bits());
+                        let mut callbacks = lock(&callbacks);
+                        if appeared != 0 {
+                            let change = FaultChange{changed : appeared.into(), faults : now, text : report.text.clone()};
+                            callbacks.on_fault.iter_mut().for_each(|callback| callback(&change));
+                        }
+                        if cleared != 0 {
+                            let change = FaultChange{changed : cleared.into(), faults : now, text : report.text};
+                            callbacks.on_clear.iter_mut().for_each(|callback| callback(&change));
+                        }
+                    }
+                })
+                .map_err(CoherentError::WriteError)?
+        };
+        Ok(FaultMonitor{stop, faults, callbacks, thread : Some(thread)})
+    }
+
+    /// Registers a callback for when faults appear.
+    pub fn on_fault<F : FnMut(&FaultChange) + Send + 'static>(&self, callback : F) {
+        lock(&self.callbacks).on_fault.push(Box::new(callback));
+    }
+
+    /// Registers a callback for when faults clear.
+    pub fn on_clear<F : FnMut(&FaultChange) + Send + 'static>(&self, callback : F) {
+        lock(&self.callbacks).on_clear.push(Box::new(callback));
+    }
+
+    /// The faults as of the last read.
+    pub fn faults(&self) -> FaultFlags {
+        FaultFlags::from_bits(self.faults.load(Ordering::SeqCst))
+    }
+
+    /// Stops reading, and waits for the thread to finish.
+    pub fn stop(mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for FaultMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The callbacks, locked. One that panicked can't leave the list half
+/// changed, so a poisoned lock is taken anyway.
+fn lock(callbacks : &Mutex<Callbacks>) -> MutexGuard<'_, Callbacks> {
+    callbacks.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Sleeps until `until`, or `false` if `stop` is set first.
+fn sleep_until(until : Instant, stop : &AtomicBool) -> bool {
+    loop {
+        if stop.load(Ordering::SeqCst) { return false; }
+        let now = Instant::now();
+        if now >= until { return true; }
+        std::thread::sleep((until - now).min(STOP_CHECK));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::debug::DebugLaser;
+
+    fn wait_for(condition : impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !condition() {
+            if Instant::now() > deadline { return false; }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        true
+    }
+
+    #[test]
+    fn test_fault_monitor() {
+        let mut faulted = DebugLaser::default();
+        faulted.inject_fault(FaultFlags::INTERLOCK.bits(), "Interlock open");
+        let laser = SharedLaser::new(faulted);
+        let monitor = FaultMonitor::start(laser.clone(), Duration::from_millis(20)).unwrap();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let (on_fault, on_clear) = (Arc::clone(&changes), Arc::clone(&changes));
+        monitor.on_fault(move |change| on_fault.lock().unwrap().push(("fault", change.clone())));
+        monitor.on_clear(move |change| on_clear.lock().unwrap().push(("clear", change.clone())));
+
+        // Already faulted when it started
+        assert!(wait_for(|| monitor.faults() == FaultFlags::INTERLOCK));
+        laser.with(|laser| laser.inject_fault((FaultFlags::INTERLOCK | FaultFlags::CHILLER).bits(), "Chiller")).unwrap();
+        assert!(wait_for(|| monitor.faults().contains(FaultFlags::CHILLER)));
+        laser.with(|laser| laser.inject_fault(FaultFlags::CHILLER.bits(), "Chiller")).unwrap();
+        assert!(wait_for(|| monitor.faults() == FaultFlags::CHILLER));
+        laser.with(|laser| laser.clear_faults()).unwrap().unwrap();
+        assert!(wait_for(|| monitor.faults().is_empty()));
+        monitor.stop();
+
+        let changes = changes.lock().unwrap();
+        // The callbacks may have been registered after the first read
+        let changes = changes.iter()
+            .skip_while(|(kind, change)| *kind == "fault" && change.changed == FaultFlags::INTERLOCK)
+            .map(|(kind, change)| (*kind, change.changed, change.faults))
+            .collect::<Vec<_>>();
+        assert_eq!(changes, vec![
+            ("fault", FaultFlags::CHILLER, FaultFlags::INTERLOCK | FaultFlags::CHILLER),
+            ("clear", FaultFlags::INTERLOCK, FaultFlags::CHILLER),
+            ("clear", FaultFlags::CHILLER, FaultFlags::empty()),
+        ]);
+    }
+}
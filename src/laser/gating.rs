@@ -0,0 +1,240 @@
+//! gating.rs
+//!
+//! Software-timed illumination epochs: opening and closing the shutter on a
+//! schedule, from a thread of its own. Each transition is timed from the
+//! start of the schedule rather than from the one before, so the time the
+//! laser takes to answer doesn't accumulate over a long run. Serial round
+//! trips make this good to tens of milliseconds -- fine for epochs of
+//! hundreds of milliseconds or more, not for anything that needs a hardware
+//! trigger.
+
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::CoherentError;
+use crate::laser::{Laser, CommonCommand, ShutterState, shared::SharedLaser};
+
+/// The name of the thread `gate_shutter` starts.
+pub const GATE_THREAD : &str = "coherent-shutter-gate";
+
+/// The longest the gating thread sleeps before checking whether it's been
+/// stopped.
+const STOP_CHECK : Duration = Duration::from_millis(10);
+
+/// Shutter states and how long to hold each, in order.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use coherent_rs::laser::gating::ShutterSchedule;
+///
+/// // 2 s of light, then 1 s dark, five times over, then 10 s more light
+/// let schedule = ShutterSchedule::alternating(Duration::from_secs(2), Duration::from_secs(1), 5)
+///     .open_for(Duration::from_secs(10));
+/// assert_eq!(schedule.duration(), Duration::from_secs(25));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShutterSchedule {
+    pub epochs : Vec<(ShutterState, Duration)>,
+}
+
+impl ShutterSchedule {
+    pub fn new() -> Self {
+        ShutterSchedule::default()
+    }
+
+    /// `cycles` of `open` with the shutter open followed by `closed` with it
+    /// closed.
+    pub fn alternating(open : Duration, closed : Duration, cycles : usize) -> Self {
+        (0..cycles).fold(ShutterSchedule::new(), |schedule, _| schedule.open_for(open).closed_for(closed))
+    }
+
+    pub fn open_for(mut self, duration : Duration) -> Self {
+        self.epochs.push((ShutterState::Open, duration));
+        self
+    }
+
+    pub fn closed_for(mut self, duration : Duration) -> Self {
+        self.epochs.push((ShutterState::Closed, duration));
+        self
+    }
+
+    /// From the first transition to the end of the last epoch.
+    pub fn duration(&self) -> Duration {
+        self.epochs.iter().map(|(_, duration)| *duration).sum()
+    }
+}
+
+/// When one epoch actually started, relative to the start of the schedule.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EpochTiming {
+    pub state : ShutterState,
+    pub scheduled : Duration,
+    /// When the laser acknowledged the shutter command
+    pub actual : Duration,
+}
+
+impl EpochTiming {
+    /// How far behind schedule the epoch started.
+    pub fn lateness(&self) -> Duration {
+        self.actual.saturating_sub(self.scheduled)
+    }
+}
+
+/// What a finished (or stopped) schedule did.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GateReport {
+    /// The epochs that started, in order
+    pub epochs : Vec<EpochTiming>,
+    /// Whether `ShutterGate::stop` cut the schedule short
+    pub stopped : bool,
+}
+
+impl GateReport {
+    /// The latest any epoch started.
+    pub fn max_lateness(&self) -> Duration {
+        self.epochs.iter().map(EpochTiming::lateness).max().unwrap_or_default()
+    }
+}
+
+/// A running `gate_shutter`. Dropping it leaves the schedule running to
+/// the end.
+pub struct ShutterGate {
+    stop : Arc<AtomicBool>,
+    thread : JoinHandle<Result<GateReport, CoherentError>>,
+}
+
+impl ShutterGate {
+    /// Ends the schedule early, closing the shutter, and waits for the
+    /// thread to finish.
+    pub fn stop(self) -> Result<GateReport, CoherentError> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.wait()
+    }
+
+    /// Waits for the schedule to finish.
+    pub fn wait(self) -> Result<GateReport, CoherentError> {
+        self.thread.join().map_err(|_| CoherentError::LaserUnavailableError)?
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+}
+
+/// Runs `schedule` on `laser`'s shutter from a new thread, and returns
+/// straight away. The shutter is closed once the schedule ends, however it
+/// ends -- including when a shutter command fails, which ends it with that
+/// error. Other threads can use the laser in between transitions.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use coherent_rs::laser::{debug::DebugLaser, shared::SharedLaser};
+/// use coherent_rs::laser::gating::{gate_shutter, ShutterSchedule};
+///
+/// let laser = SharedLaser::new(DebugLaser::default());
+/// let schedule = ShutterSchedule::alternating(Duration::from_millis(200), Duration::from_millis(100), 2);
+/// let report = gate_shutter(laser, schedule).unwrap().wait().unwrap();
+/// assert_eq!(report.epochs.len(), 4);
+/// assert!(report.max_lateness() < Duration::from_millis(100));
+/// ```
+pub fn gate_shutter<L : Laser + 'static>(laser : SharedLaser<L>, schedule : ShutterSchedule)
+    -> Result<ShutterGate, CoherentError> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let stop = Arc::clone(&stop);
+        std::thread::Builder::new()
+            .name(GATE_THREAD.to_string())
+            .spawn(move || {
+                let report = run_schedule(&laser, &schedule, &stop);
+                let closed = laser.with(|laser| laser.send_common_command(CommonCommand::CloseShutter))
+                    .and_then(|result| result);
+                let report = report?;
+                closed.map(|_| report)
+            })
+            .map_err(CoherentError::WriteError)?
+    };
+    Ok(ShutterGate{stop, thread})
+}
+
+fn run_schedule<L : Laser>(laser : &SharedLaser<L>, schedule : &ShutterSchedule, stop : &AtomicBool)
+    -> Result<GateReport, CoherentError> {
+    let start = Instant::now();
+    let mut report = GateReport::default();
+    let mut scheduled = Duration::ZERO;
+    for &(state, duration) in &schedule.epochs {
+        if !sleep_until(start + scheduled, stop) {
+            report.stopped = true;
+            return Ok(report);
+        }
+        let command = match state {
+            ShutterState::Open => CommonCommand::OpenShutter,
+            ShutterState::Closed => CommonCommand::CloseShutter,
+        };
+        laser.with(|laser| laser.send_common_command(command))??;
+        report.epochs.push(EpochTiming{state, scheduled, actual : start.elapsed()});
+        scheduled += duration;
+    }
+    report.stopped = !sleep_until(start + scheduled, stop);
+    Ok(report)
+}
+
+/// Sleeps until `until`, or `false` if `stop` is set first.
+fn sleep_until(until : Instant, stop : &AtomicBool) -> bool {
+    loop {
+        if stop.load(Ordering::SeqCst) { return false; }
+        let now = Instant::now();
+        if now >= until { return true; }
+        std::thread::sleep((until - now).min(STOP_CHECK));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::{debug::DebugLaser, discoverynx::DiscoveryLaser};
+
+    fn shutter(laser : &SharedLaser<DebugLaser>) -> ShutterState {
+        laser.with(|laser| laser.get_shutter(DiscoveryLaser::VariableWavelength)).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_gate_shutter() {
+        let laser = SharedLaser::new(DebugLaser::default());
+        let schedule = ShutterSchedule::new()
+            .closed_for(Duration::from_millis(100))
+            .open_for(Duration::from_millis(300))
+            .closed_for(Duration::from_millis(100));
+        let gate = gate_shutter(laser.clone(), schedule).unwrap();
+
+        std::thread::sleep(Duration::from_millis(250));
+        assert_eq!(shutter(&laser), ShutterState::Open);
+        let report = gate.wait().unwrap();
+        assert!(!report.stopped);
+        assert_eq!(
+            report.epochs.iter().map(|epoch| epoch.scheduled.as_millis()).collect::<Vec<_>>(),
+            vec![0, 100, 400]
+        );
+        assert!(report.max_lateness() < Duration::from_millis(50), "{:?}", report);
+        assert_eq!(shutter(&laser), ShutterState::Closed);
+    }
+
+    #[test]
+    fn test_stop() {
+        let laser = SharedLaser::new(DebugLaser::default());
+        let gate = gate_shutter(laser.clone(), ShutterSchedule::new().open_for(Duration::from_secs(60))).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(shutter(&laser), ShutterState::Open);
+
+        let stopped = Instant::now();
+        let report = gate.stop().unwrap();
+        assert!(stopped.elapsed() < Duration::from_millis(100));
+        assert!(report.stopped);
+        assert_eq!(report.epochs.len(), 1);
+        assert_eq!(shutter(&laser), ShutterState::Closed);
+    }
+}
@@ -0,0 +1,258 @@
+//! history.rs
+//!
+//! A changelog of the laser's settings: every parameter a command changes,
+//! its value before and after, when, and who asked for it -- local code or
+//! a client of a `NetworkLaserServer`. Kept in memory, and optionally
+//! appended to a file so it survives restarts.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::CoherentError;
+use crate::laser::StatusValue;
+
+/// Where a change came from.
+#[cfg_attr(feature = "network", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeOrigin {
+    /// Code running alongside the laser (including a server's own `command`)
+    Local,
+    /// A client of a `NetworkLaserServer`
+    Client(SocketAddr),
+}
+
+impl std::fmt::Display for ChangeOrigin {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangeOrigin::Local => write!(f, "local"),
+            ChangeOrigin::Client(address) => write!(f, "{}", address),
+        }
+    }
+}
+
+/// One parameter changing. `parameter` is named as in the laser's status.
+#[cfg_attr(feature = "network", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterChange {
+    pub timestamp : f64, // seconds since the Unix epoch
+    pub parameter : String,
+    /// `StatusValue::Missing` if it couldn't be read before the change
+    pub before : StatusValue,
+    pub after : StatusValue,
+    pub origin : ChangeOrigin,
+}
+
+/// The history of a laser's settings, oldest first.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::{debug::DebugLaser, history::ParameterHistory, unix_timestamp, StatusValue};
+///
+/// let mut laser = DebugLaser::default();
+/// laser.parameter_history = Some(ParameterHistory::default());
+/// let yesterday = unix_timestamp() - 24.0 * 3600.0;
+/// laser.set_wavelength(800.0).unwrap();
+/// laser.set_wavelength(850.0).unwrap();
+///
+/// let diff = laser.parameter_history.as_ref().unwrap().diff(yesterday);
+/// assert_eq!(diff["wavelength"], (StatusValue::Float(920.0), StatusValue::Float(850.0)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ParameterHistory {
+    changes : Vec<ParameterChange>,
+    persist_path : Option<PathBuf>,
+    /// Attributed to the changes recorded from now on. A `NetworkLaserServer`
+    /// sets this around each client's command.
+    pub origin : ChangeOrigin,
+    /// The most changes kept in memory; the oldest are dropped first.
+    /// Defaults to 10,000. The file, if any, keeps everything.
+    pub capacity : usize,
+}
+
+impl Default for ParameterHistory {
+    fn default() -> Self {
+        ParameterHistory{
+            changes : Vec::new(),
+            persist_path : None,
+            origin : ChangeOrigin::Local,
+            capacity : 10_000,
+        }
+    }
+}
+
+impl ParameterHistory {
+    /// A history that appends every change to the file at `path`,
+    /// starting from the changes already in it (if it exists).
+    pub fn persisted<P : AsRef<Path>>(path : P) -> Result<Self, CoherentError> {
+        let path = path.as_ref();
+        let mut history = match std::fs::read_to_string(path) {
+            Ok(text) => ParameterHistory::parse(&text)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ParameterHistory::default(),
+            Err(e) => return Err(CoherentError::InvalidArgumentsError(
+                format!("Could not read history {}: {}", path.display(), e)
+            )),
+        };
+        history.persist_path = Some(path.to_path_buf());
+        Ok(history)
+    }
+
+    /// Reads changes in the format written by a persisted history: one per
+    /// line, tab-separated `timestamp origin parameter before after`.
+    pub fn parse(text : &str) -> Result<Self, CoherentError> {
+        let mut history = ParameterHistory::default();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let invalid = || CoherentError::InvalidArgumentsError(format!("Invalid history line `{}`", line));
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let [timestamp, origin, parameter, before, after] = fields[..] else { return Err(invalid()); };
+            history.changes.push(ParameterChange{
+                timestamp : timestamp.parse().map_err(|_| invalid())?,
+                origin : match origin {
+                    "local" => ChangeOrigin::Local,
+                    address => ChangeOrigin::Client(address.parse().map_err(|_| invalid())?),
+                },
+                parameter : parameter.to_string(),
+                before : decode_value(before).ok_or_else(invalid)?,
+                after : decode_value(after).ok_or_else(invalid)?,
+            });
+        }
+        Ok(history)
+    }
+
+    /// Records `parameter` changing from `before` to `after`, now, from the
+    /// current `origin`. The change is kept in memory even if writing it to
+    /// the file fails.
+    pub fn record(&mut self, parameter : &str, before : StatusValue, after : StatusValue) -> Result<(), CoherentError> {
+        let change = ParameterChange{
+            timestamp : crate::laser::unix_timestamp(),
+            parameter : parameter.to_string(),
+            before,
+            after,
+            origin : self.origin.clone(),
+        };
+        let line = format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            change.timestamp, change.origin, change.parameter, encode_value(&change.before), encode_value(&change.after)
+        );
+        self.changes.push(change);
+        if self.changes.len() > self.capacity {
+            let excess = self.changes.len() - self.capacity;
+            self.changes.drain(..excess);
+        }
+
+        if let Some(path) = &self.persist_path {
+            std::fs::OpenOptions::new().create(true).append(true).open(path)
+                .and_then(|mut file| file.write_all(line.as_bytes()))
+                .map_err(|e| CoherentError::InvalidArgumentsError(
+                    format!("Could not write history {}: {}", path.display(), e)
+                ))?;
+        }
+        Ok(())
+    }
+
+    pub fn changes(&self) -> &[ParameterChange] {
+        &self.changes
+    }
+
+    /// The changes made at or after `since` (seconds since the Unix epoch).
+    pub fn since(&self, since : f64) -> &[ParameterChange] {
+        let start = self.changes.partition_point(|change| change.timestamp < since);
+        &self.changes[start..]
+    }
+
+    /// What changed since `since`: for each parameter, its value before the
+    /// first change and after the last. Parameters that ended up where they
+    /// started are left out.
+    pub fn diff(&self, since : f64) -> BTreeMap<String, (StatusValue, StatusValue)> {
+        let mut diff = BTreeMap::<String, (StatusValue, StatusValue)>::new();
+        for change in self.since(since) {
+            diff.entry(change.parameter.clone())
+                .and_modify(|(_, after)| *after = change.after.clone())
+                .or_insert_with(|| (change.before.clone(), change.after.clone()));
+        }
+        diff.retain(|_, (before, after)| before != after);
+        diff
+    }
+
+    /// Forgets the changes in memory. A persisted file is left alone.
+    pub fn clear(&mut self) {
+        self.changes.clear();
+    }
+}
+
+/// `StatusValue`s in the history file, tagged with their type:
+/// `b:true`, `i:3`, `f:920`, `s:text`, or `-` for `Missing`.
+pub(crate) fn encode_value(value : &StatusValue) -> String {
+    match value {
+        StatusValue::Bool(b) => format!("b:{}", b),
+        StatusValue::Integer(i) => format!("i:{}", i),
+        StatusValue::Float(x) => format!("f:{}", x),
+        // Tabs and newlines would break up the line
+        StatusValue::Text(text) => format!("s:{}", text.replace(['\t', '\n', '\r'], " ")),
+        StatusValue::Missing => "-".to_string(),
+    }
+}
+
+pub(crate) fn decode_value(field : &str) -> Option<StatusValue> {
+    match field.split_once(':') {
+        Some(("b", b)) => b.parse().ok().map(StatusValue::Bool),
+        Some(("i", i)) => i.parse().ok().map(StatusValue::Integer),
+        Some(("f", x)) => x.parse().ok().map(StatusValue::Float),
+        Some(("s", text)) => Some(StatusValue::Text(text.to_string())),
+        _ if field == "-" => Some(StatusValue::Missing),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let mut history = ParameterHistory::default();
+        history.record("wavelength", StatusValue::Float(920.0), StatusValue::Float(800.0)).unwrap();
+        let later = crate::laser::unix_timestamp();
+        history.origin = ChangeOrigin::Client("10.0.0.2:5000".parse().unwrap());
+        history.record("wavelength", StatusValue::Float(800.0), StatusValue::Float(850.0)).unwrap();
+        history.record("gdd", StatusValue::Float(0.0), StatusValue::Float(-5000.0)).unwrap();
+        history.record("gdd", StatusValue::Float(-5000.0), StatusValue::Float(0.0)).unwrap();
+
+        let diff = history.diff(0.0);
+        assert_eq!(diff.len(), 1, "GDD ended where it started: {:?}", diff);
+        assert_eq!(diff["wavelength"], (StatusValue::Float(920.0), StatusValue::Float(850.0)));
+        assert_eq!(history.diff(later)["wavelength"].0, StatusValue::Float(800.0));
+        assert_eq!(history.since(later).len(), 3);
+        assert_eq!(history.changes()[0].origin, ChangeOrigin::Local);
+        assert!(history.diff(f64::MAX).is_empty());
+
+        history.capacity = 2;
+        history.record("echo", StatusValue::Bool(true), StatusValue::Bool(false)).unwrap();
+        assert_eq!(history.changes().len(), 2);
+        assert_eq!(history.changes()[0].parameter, "gdd");
+    }
+
+    #[test]
+    fn test_persisted() {
+        let path = std::env::temp_dir().join(format!("coherent-history-{}.tsv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut history = ParameterHistory::persisted(&path).unwrap();
+        history.record("gdd_curve_n", StatusValue::Missing, StatusValue::Text("rig\t2".to_string())).unwrap();
+        history.origin = ChangeOrigin::Client("127.0.0.1:4000".parse().unwrap());
+        history.record("laser", StatusValue::Text("Standby".to_string()), StatusValue::Text("On".to_string())).unwrap();
+        drop(history);
+
+        let reloaded = ParameterHistory::persisted(&path).unwrap();
+        assert_eq!(reloaded.changes().len(), 2);
+        assert_eq!(reloaded.changes()[0].after, StatusValue::Text("rig 2".to_string()));
+        assert_eq!(reloaded.changes()[1].origin, ChangeOrigin::Client("127.0.0.1:4000".parse().unwrap()));
+
+        assert!(ParameterHistory::parse("1700000000\tlocal\tgdd\tf:0").is_err());
+        assert!(ParameterHistory::parse("1700000000\tnowhere\tgdd\tf:0\tf:1").is_err());
+        assert!(ParameterHistory::parse("1700000000\tlocal\tgdd\tf:0\tx:1").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+//! hooks.rs
+//!
+//! Callbacks that run around wavelength changes, so equipment that depends
+//! on the wavelength (a Pockels cell lookup table, a modulator gating the
+//! beam) stays in step with the laser. The hooks live on the laser, so they
+//! fire for every wavelength command -- whether it came from local code or
+//! from a client of a `NetworkLaserServer` hosting the laser.
+
+use std::time::Duration;
+
+use crate::CoherentError;
+
+/// What a tuning hook is told about the wavelength change.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TuningEvent {
+    /// The wavelength before the change, in nm
+    pub from_nm : f32,
+    /// The wavelength being tuned to, in nm
+    pub to_nm : f32,
+}
+
+/// A callback run before or after tuning. An error from a before-hook
+/// cancels the wavelength change.
+pub type TuningHook = Box<dyn FnMut(&TuningEvent) -> Result<(), CoherentError> + Send>;
+
+/// The hooks registered on a laser.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::debug::DebugLaser;
+///
+/// let mut laser = DebugLaser::default();
+/// laser.tuning_hooks.before_tuning(|event| {
+///     println!("Blanking the Pockels cell before tuning to {} nm", event.to_nm);
+///     Ok(())
+/// });
+/// laser.tuning_hooks.after_tuning(|event| {
+///     println!("Loading the Pockels LUT for {} nm", event.to_nm);
+///     Ok(())
+/// });
+/// laser.set_wavelength(800.0).unwrap();
+/// ```
+pub struct TuningHooks {
+    before : Vec<TuningHook>,
+    after : Vec<TuningHook>,
+    /// How long to wait for the laser to finish tuning before running
+    /// the after-hooks. Defaults to 30 seconds.
+    pub settle_timeout : Duration,
+}
+
+impl Default for TuningHooks {
+    fn default() -> Self {
+        TuningHooks{
+            before : Vec::new(),
+            after : Vec::new(),
+            settle_timeout : Duration::from_secs(30),
+        }
+    }
+}
+
+impl std::fmt::Debug for TuningHooks {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TuningHooks")
+            .field("before", &self.before.len())
+            .field("after", &self.after.len())
+            .field("settle_timeout", &self.settle_timeout)
+            .finish()
+    }
+}
+
+impl TuningHooks {
+    /// Registers a hook to run before the wavelength command is sent.
+    pub fn before_tuning<F>(&mut self, hook : F)
+    where F : FnMut(&TuningEvent) -> Result<(), CoherentError> + Send + 'static {
+        self.before.push(Box::new(hook));
+    }
+
+    /// Registers a hook to run once the laser has finished tuning.
+    pub fn after_tuning<F>(&mut self, hook : F)
+    where F : FnMut(&TuningEvent) -> Result<(), CoherentError> + Send + 'static {
+        self.after.push(Box::new(hook));
+    }
+
+    /// Removes every hook.
+    pub fn clear(&mut self) {
+        self.before.clear();
+        self.after.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.before.is_empty() && self.after.is_empty()
+    }
+
+    /// Runs the before-hooks in the order they were registered,
+    /// stopping at the first error.
+    pub fn run_before(&mut self, event : &TuningEvent) -> Result<(), CoherentError> {
+        self.before.iter_mut().try_for_each(|hook| hook(event))
+    }
+
+    /// Runs the after-hooks in the order they were registered,
+    /// stopping at the first error.
+    pub fn run_after(&mut self, event : &TuningEvent) -> Result<(), CoherentError> {
+        self.after.iter_mut().try_for_each(|hook| hook(event))
+    }
+
+    /// Whether there are after-hooks -- if not, there's no need to wait
+    /// for tuning to finish.
+    pub fn has_after(&self) -> bool {
+        !self.after.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_hook_order_and_errors() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut hooks = TuningHooks::default();
+        assert!(hooks.is_empty());
+
+        for name in ["first", "second"] {
+            let calls = calls.clone();
+            hooks.before_tuning(move |event| {
+                calls.lock().unwrap().push(format!("{} {}", name, event.to_nm));
+                Ok(())
+            });
+        }
+        hooks.before_tuning(|_| Err(CoherentError::InvalidArgumentsError("LUT missing".to_string())));
+        {
+            let calls = calls.clone();
+            hooks.before_tuning(move |_| { calls.lock().unwrap().push("never".to_string()); Ok(()) });
+        }
+
+        let event = TuningEvent{from_nm : 920.0, to_nm : 800.0};
+        assert!(matches!(hooks.run_before(&event), Err(CoherentError::InvalidArgumentsError(_))));
+        assert_eq!(*calls.lock().unwrap(), vec!["first 800".to_string(), "second 800".to_string()]);
+        assert!(!hooks.has_after());
+        assert!(hooks.run_after(&event).is_ok());
+
+        hooks.clear();
+        assert!(hooks.is_empty());
+    }
+}
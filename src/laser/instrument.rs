@@ -0,0 +1,102 @@
+//! instrument.rs
+//!
+//! `tracing` spans around a laser's commands, queries and status reads,
+//! with the `tracing` feature: each records the command string, how long
+//! it took and whether it worked, and a failure is logged as a warning in
+//! the span -- so an error a polling loop drops on the floor still shows up
+//! wherever the application sends its traces. Without the feature, `traced!`
+//! just runs its body.
+
+/// Runs `$body`, which returns a `Result`, in a span named `$name` for the
+/// command `$command` (anything `Display`). `return` and `?` in the body
+/// finish the span, not the enclosing function.
+macro_rules! traced {
+    ($name:literal, $command:expr, $body:expr) => {{
+        #[cfg(feature = "tracing")]
+        let (span, started) = (
+            ::tracing::info_span!(
+                $name,
+                command = %$command,
+                elapsed_ms = ::tracing::field::Empty,
+                outcome = ::tracing::field::Empty,
+            ),
+            std::time::Instant::now(),
+        );
+        #[cfg(feature = "tracing")]
+        let entered = span.enter();
+        #[allow(clippy::redundant_closure_call)]
+        let result = (|| $body)();
+        #[cfg(feature = "tracing")]
+        {
+            $crate::laser::instrument::finish(&span, started, &result);
+            drop(entered);
+        }
+        result
+    }};
+}
+
+/// Records how long the span's operation took and how it went.
+#[cfg(feature = "tracing")]
+pub(crate) fn finish<T>(span : &::tracing::Span, started : std::time::Instant, result : &Result<T, crate::CoherentError>) {
+    span.record("elapsed_ms", started.elapsed().as_secs_f64() * 1000.0);
+    match result {
+        Ok(_) => { span.record("outcome", "ok"); },
+        Err(e) => {
+            span.record("outcome", "error");
+            ::tracing::warn!(error = ?e, "failed");
+        },
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use tracing::{Event, Metadata, Subscriber, field::{Field, Visit}, span::{Attributes, Id, Record}};
+    use crate::laser::{Laser, Discovery, DiscoveryNXQueries, mock::MockSerialPort};
+
+    /// Keeps the name of every span and event, and every field recorded.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Visit for Recorder {
+        fn record_debug(&mut self, field : &Field, value : &dyn std::fmt::Debug) {
+            self.0.lock().unwrap().push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _metadata : &Metadata<'_>) -> bool { true }
+        fn new_span(&self, span : &Attributes<'_>) -> Id {
+            self.0.lock().unwrap().push(span.metadata().name().to_string());
+            span.record(&mut self.clone());
+            Id::from_u64(1)
+        }
+        fn record(&self, _span : &Id, values : &Record<'_>) { values.record(&mut self.clone()); }
+        fn record_follows_from(&self, _span : &Id, _follows : &Id) {}
+        fn event(&self, event : &Event<'_>) { event.record(&mut self.clone()); }
+        fn enter(&self, _span : &Id) {}
+        fn exit(&self, _span : &Id) {}
+    }
+
+    #[test]
+    fn test_spans() {
+        let recorder = Recorder::default();
+        let port = MockSerialPort::discovery(false, false, "SN1234")
+            .expect("?WV", "920\r\n")
+            .expect("WV=800", "COMMAND NOT EXECUTED\r\n");
+        let mut discovery = Discovery::from_serial_port(Box::new(port)).unwrap();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            discovery.query(DiscoveryNXQueries::Wavelength{}).unwrap();
+            assert!(discovery.set_wavelength(800.0).is_err());
+        });
+        let recorded = recorder.0.lock().unwrap().clone();
+        let query = recorded.iter().position(|line| line == "query").unwrap();
+        assert_eq!(recorded[query + 1], "command=?WV");
+        assert!(recorded[query + 2].starts_with("elapsed_ms="));
+        assert_eq!(recorded[query + 3], "outcome=\"ok\"");
+        let command = recorded.iter().position(|line| line == "send_command").unwrap();
+        assert_eq!(recorded[command + 1], "command=WV=800");
+        assert!(recorded[command + 2..].contains(&"error=CommandNotExecutedError".to_string()));
+        assert!(recorded[command + 2..].contains(&"outcome=\"error\"".to_string()));
+    }
+}
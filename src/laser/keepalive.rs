@@ -0,0 +1,181 @@
+//! keepalive.rs
+//!
+//! Noticing that the laser has gone away -- switched off at the controller,
+//! or its USB adapter reset -- while nobody is using it, rather than on the
+//! next command someone sends. A thread of its own reads the laser's faults
+//! every so often (a cheap query every laser answers), and when the laser
+//! stops answering, flips a flag and says so.
+//!
+//! With a `Discovery`'s `reconnect_policy` set, the keep-alive's query is
+//! what reconnects it, so the laser is usually back by the time anyone
+//! needs it.
+
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::CoherentError;
+use crate::laser::{Laser, shared::SharedLaser};
+
+/// The name of the thread `keep_alive` starts.
+pub const KEEPALIVE_THREAD : &str = "coherent-keepalive";
+
+/// How many checks in a row have to fail before the laser is taken to be
+/// gone, so one dropped reply isn't reported as a lost laser.
+pub const MISSED_CHECKS : u32 = 2;
+
+/// The longest the keep-alive thread sleeps before checking whether it's
+/// been stopped.
+const STOP_CHECK : Duration = Duration::from_millis(10);
+
+/// A change in whether the laser is answering.
+#[derive(Debug)]
+pub enum ConnectionEvent {
+    /// It stopped answering, with the error from the last check
+    Lost(CoherentError),
+    /// It's answering again
+    Restored,
+}
+
+/// A running `keep_alive`. Dropping it stops the thread.
+pub struct KeepAlive {
+    stop : Arc<AtomicBool>,
+    connected : Arc<AtomicBool>,
+    thread : Option<JoinHandle<()>>,
+}
+
+impl KeepAlive {
+    /// Whether the laser answered the last checks. `true` until
+    /// `MISSED_CHECKS` in a row have failed.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    /// Stops checking, and waits for the thread to finish.
+    pub fn stop(mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for KeepAlive {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Reads `laser`'s faults every `interval` from a new thread, calling
+/// `on_change` when it stops answering and when it starts again. If another
+/// thread has the laser when a check is due, the check is skipped -- the
+/// laser's in use, so whoever has it will find out soon enough.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use coherent_rs::laser::{debug::DebugLaser, shared::SharedLaser};
+/// use coherent_rs::laser::keepalive::{keep_alive, ConnectionEvent};
+///
+/// let laser = SharedLaser::new(DebugLaser::default());
+/// let keepalive = keep_alive(laser, Duration::from_secs(5), |event| match event {
+///     ConnectionEvent::Lost(e) => eprintln!("Lost the laser: {:?}", e),
+///     ConnectionEvent::Restored => eprintln!("The laser is back"),
+/// }).unwrap();
+/// assert!(keepalive.is_connected());
+/// keepalive.stop();
+/// ```
+pub fn keep_alive<L, F>(laser : SharedLaser<L>, interval : Duration, mut on_change : F)
+    -> Result<KeepAlive, CoherentError>
+where L : Laser + 'static, F : FnMut(&ConnectionEvent) + Send + 'static {
+    let stop = Arc::new(AtomicBool::new(false));
+    let connected = Arc::new(AtomicBool::new(true));
+    let thread = {
+        let stop = Arc::clone(&stop);
+        let connected = Arc::clone(&connected);
+        std::thread::Builder::new()
+            .name(KEEPALIVE_THREAD.to_string())
+            .spawn(move || {
+                let mut missed = 0;
+                let mut next = Instant::now() + interval;
+                while sleep_until(next, &stop) {
+                    next = Instant::now() + interval;
+                    let result = match laser.try_with(|laser| laser.fault_report()) {
+                        Err(CoherentError::LaserBusyError) => continue,
+                        result => result.and_then(|result| result),
+                    };
+                    match result {
+                        Ok(_) => {
+                            missed = 0;
+                            if !connected.swap(true, Ordering::SeqCst) {
+                                on_change(&ConnectionEvent::Restored);
+                            }
+                        },
+                        Err(e) => {
+                            missed += 1;
+                            if missed >= MISSED_CHECKS && connected.swap(false, Ordering::SeqCst) {
+                                on_change(&ConnectionEvent::Lost(e));
+                            }
+                        },
+                    }
+                }
+            })
+            .map_err(CoherentError::WriteError)?
+    };
+    Ok(KeepAlive{stop, connected, thread : Some(thread)})
+}
+
+/// Sleeps until `until`, or `false` if `stop` is set first.
+fn sleep_until(until : Instant, stop : &AtomicBool) -> bool {
+    loop {
+        if stop.load(Ordering::SeqCst) { return false; }
+        let now = Instant::now();
+        if now >= until { return true; }
+        std::thread::sleep((until - now).min(STOP_CHECK));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use crate::laser::{Discovery, mock::MockSerialPort};
+
+    fn wait_for(condition : impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !condition() {
+            if Instant::now() > deadline { return false; }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        true
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        // Answers one check, then goes quiet
+        let port = MockSerialPort::discovery(false, false, "SN1234")
+            .expect("?F", "0\r\n")
+            .expect("?FT", "System OK\r\n");
+        let laser = SharedLaser::new(Discovery::from_serial_port(Box::new(port.clone())).unwrap());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        let keepalive = keep_alive(laser.clone(), Duration::from_millis(20), move |event| {
+            seen.lock().unwrap().push(matches!(event, ConnectionEvent::Lost(_)));
+        }).unwrap();
+        assert!(keepalive.is_connected());
+
+        assert!(wait_for(|| !keepalive.is_connected()));
+        assert_eq!(*events.lock().unwrap(), vec![true]);
+        // Skipped while another thread has the laser
+        let busy = laser.lock().unwrap();
+        let _ = port.clone().expect("?F", "0\r\n").expect("?FT", "System OK\r\n");
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!keepalive.is_connected());
+        drop(busy);
+
+        assert!(wait_for(|| keepalive.is_connected()));
+        assert_eq!(*events.lock().unwrap(), vec![true, false]);
+        keepalive.stop();
+    }
+}
@@ -0,0 +1,96 @@
+//! lock.rs
+//!
+//! A software keyswitch. While a laser is locked it refuses every command
+//! that would change its state -- from local code or from any client of a
+//! `NetworkLaserServer` -- until it's unlocked with the same token. Queries
+//! and status still work, so the rig can be watched while it's frozen.
+
+use crate::CoherentError;
+
+/// The lock state of one laser.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::lock::OperatorLock;
+///
+/// let mut lock = OperatorLock::default();
+/// lock.lock("service-2024").unwrap();
+/// assert!(lock.check().is_err());
+/// assert!(lock.unlock("wrong").is_err());
+/// lock.unlock("service-2024").unwrap();
+/// assert!(lock.check().is_ok());
+/// ```
+#[derive(Default, Clone)]
+pub struct OperatorLock {
+    token : Option<String>,
+}
+
+impl std::fmt::Debug for OperatorLock {
+    /// Never prints the token.
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OperatorLock").field("locked", &self.is_locked()).finish()
+    }
+}
+
+impl OperatorLock {
+    /// Locks with `token`. Locking again with the same token is a no-op;
+    /// with a different one it fails, so one operator can't take over
+    /// another's lock.
+    pub fn lock(&mut self, token : &str) -> Result<(), CoherentError> {
+        if token.is_empty() {
+            return Err(CoherentError::InvalidArgumentsError("The lock token can't be empty".to_string()));
+        }
+        match &self.token {
+            Some(held) if held != token => Err(CoherentError::LockedError),
+            _ => {
+                self.token = Some(token.to_string());
+                Ok(())
+            },
+        }
+    }
+
+    /// Unlocks, if `token` is the one it was locked with.
+    /// Unlocking an unlocked laser succeeds.
+    pub fn unlock(&mut self, token : &str) -> Result<(), CoherentError> {
+        match &self.token {
+            Some(held) if held != token => Err(CoherentError::LockedError),
+            _ => {
+                self.token = None;
+                Ok(())
+            },
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.token.is_some()
+    }
+
+    /// `LockedError` if locked -- call before anything that changes state.
+    pub fn check(&self) -> Result<(), CoherentError> {
+        if self.is_locked() { Err(CoherentError::LockedError) } else { Ok(()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_unlock() {
+        let mut lock = OperatorLock::default();
+        assert!(!lock.is_locked());
+        assert!(lock.unlock("anything").is_ok());
+        assert!(matches!(lock.lock(""), Err(CoherentError::InvalidArgumentsError(_))));
+
+        lock.lock("a").unwrap();
+        lock.lock("a").unwrap();
+        assert!(matches!(lock.lock("b"), Err(CoherentError::LockedError)));
+        assert!(matches!(lock.unlock("b"), Err(CoherentError::LockedError)));
+        assert!(matches!(lock.check(), Err(CoherentError::LockedError)));
+        assert_eq!(format!("{:?}", lock), "OperatorLock { locked: true }");
+
+        lock.unlock("a").unwrap();
+        assert!(lock.check().is_ok());
+    }
+}
@@ -0,0 +1,311 @@
+//! mock.rs
+//!
+//! An in-memory `SerialPort` that replies from a scripted transcript, so the
+//! serial protocol code can be exercised without a laser plugged in.
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serialport::{SerialPort, ClearBuffer, DataBits, FlowControl, Parity, StopBits};
+
+use crate::laser::terminal::TerminalStyle;
+
+/// One step of a transcript: the line the laser should receive
+/// (without the trailing `\r\n`) and the raw bytes it replies with.
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    pub expect : String,
+    pub reply : Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    transcript : VecDeque<Exchange>,
+    pending_write : Vec<u8>,
+    to_read : VecDeque<u8>,
+    written : Vec<String>,
+    unexpected : Vec<String>,
+}
+
+/// A `SerialPort` double that plays back a transcript. Every complete line
+/// written to it is checked against the next `Exchange`; if it matches, the
+/// reply is queued up to be read. If it doesn't, the line is recorded as
+/// unexpected and nothing is sent back -- just like a real laser ignoring
+/// you -- so the next read times out.
+///
+/// Clones share the same transcript, so keep one around to inspect the
+/// port after handing the other to a laser.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::{Discovery, laser::{Laser, mock::MockSerialPort}};
+///
+/// let port = MockSerialPort::discovery(false, false, "SN1234")
+///     .expect("?WV", "920\r\n");
+/// let mut discovery = Discovery::from_serial_port(Box::new(port.clone())).unwrap();
+/// assert_eq!(discovery.get_wavelength().unwrap(), 920.0);
+/// assert!(port.is_finished());
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockSerialPort {
+    state : Arc<Mutex<MockState>>,
+    timeout : Duration,
+}
+
+impl Default for MockSerialPort {
+    fn default() -> Self {
+        MockSerialPort{
+            state : Arc::new(Mutex::new(MockState::default())),
+            timeout : Duration::from_millis(10),
+        }
+    }
+}
+
+impl MockSerialPort {
+    /// An empty transcript.
+    pub fn new() -> Self {
+        MockSerialPort::default()
+    }
+
+    /// A transcript that starts with the handshake `Discovery` performs on
+    /// connection (`?E` then `?SN`), answered as a laser with the given
+    /// echo and prompt settings would.
+    pub fn discovery(echo : bool, prompt : bool, serial_number : &str) -> Self {
+        MockSerialPort::discovery_with_terminal(echo, chameleon(prompt), serial_number)
+    }
+
+    /// Like `discovery`, for a laser whose terminal has the given prompt.
+    pub fn discovery_with_terminal(echo : bool, terminal : TerminalStyle, serial_number : &str) -> Self {
+        MockSerialPort::new()
+            .expect("?E", &terminal.format_reply("?E", if echo {"1"} else {"0"}, echo))
+            .expect("?SN", &terminal.format_reply("?SN", serial_number, echo))
+    }
+
+    /// Appends an exchange to the transcript: when `line` is written,
+    /// reply with `reply` verbatim.
+    pub fn expect(self, line : &str, reply : &str) -> Self {
+        self.expect_bytes(line, reply.as_bytes())
+    }
+
+    /// Like `expect`, but the reply can be arbitrary (e.g. non-UTF-8) bytes.
+    pub fn expect_bytes(self, line : &str, reply : &[u8]) -> Self {
+        self.state.lock().unwrap().transcript.push_back(
+            Exchange{expect : line.to_string(), reply : reply.to_vec()}
+        );
+        self
+    }
+
+    /// Every line written to the port so far, without the `\r\n`.
+    pub fn written(&self) -> Vec<String> {
+        self.state.lock().unwrap().written.clone()
+    }
+
+    /// Lines written to the port that didn't match the transcript.
+    pub fn unexpected(&self) -> Vec<String> {
+        self.state.lock().unwrap().unexpected.clone()
+    }
+
+    /// Whether every exchange in the transcript has been played and
+    /// nothing unexpected was written.
+    pub fn is_finished(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.transcript.is_empty() && state.unexpected.is_empty()
+    }
+}
+
+/// Formats a reply the way a Discovery would, given its echo and prompt settings.
+/// With echo on the laser repeats the command before the response, and with
+/// the prompt on it prefixes the line with `Chameleon> `.
+pub fn format_reply(command : &str, response : &str, echo : bool, prompt : bool) -> String {
+    chameleon(prompt).format_reply(command, response, echo)
+}
+
+/// The `Chameleon>` prompt if `prompt` is on.
+fn chameleon(prompt : bool) -> TerminalStyle {
+    if prompt { TerminalStyle::Chameleon } else { TerminalStyle::Plain }
+}
+
+impl Read for MockSerialPort {
+    fn read(&mut self, buf : &mut [u8]) -> std::io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.to_read.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Operation timed out"));
+        }
+        let n = buf.len().min(state.to_read.len());
+        for (slot, byte) in buf.iter_mut().zip(state.to_read.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MockSerialPort {
+    fn write(&mut self, buf : &[u8]) -> std::io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        state.pending_write.extend_from_slice(buf);
+
+        while let Some(end) = state.pending_write.windows(2).position(|w| w == b"\r\n") {
+            let line = String::from_utf8_lossy(&state.pending_write[..end]).to_string();
+            state.pending_write.drain(..end + 2);
+            state.written.push(line.clone());
+
+            match state.transcript.front() {
+                Some(exchange) if exchange.expect == line => {
+                    let exchange = state.transcript.pop_front().unwrap();
+                    state.to_read.extend(exchange.reply);
+                },
+                _ => state.unexpected.push(line),
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for MockSerialPort {
+    fn name(&self) -> Option<String> {
+        Some("MockSerialPort".to_string())
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(19200)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(DataBits::Eight)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(Parity::None)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(StopBits::One)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, _baud_rate : u32) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, _data_bits : DataBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, _flow_control : FlowControl) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_parity(&mut self, _parity : Parity) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, _stop_bits : StopBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout : Duration) -> serialport::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _level : bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, _level : bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(self.state.lock().unwrap().to_read.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear : ClearBuffer) -> serialport::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        match buffer_to_clear {
+            ClearBuffer::Input => state.to_read.clear(),
+            ClearBuffer::Output => state.pending_write.clear(),
+            ClearBuffer::All => {
+                state.to_read.clear();
+                state.pending_write.clear();
+            }
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcript_playback() {
+        let mut port = MockSerialPort::new()
+            .expect("?WV", "920\r\n");
+
+        port.write_all(b"?WV\r\n").unwrap();
+        let mut buf = [0u8; 16];
+        let n = port.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"920\r\n");
+        assert!(port.is_finished());
+
+        // Out of script: nothing comes back
+        port.write_all(b"?GDD\r\n").unwrap();
+        assert_eq!(port.read(&mut buf).unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!(port.unexpected(), vec!["?GDD".to_string()]);
+        assert_eq!(port.written(), vec!["?WV".to_string(), "?GDD".to_string()]);
+    }
+
+    #[test]
+    fn test_format_reply() {
+        assert_eq!(format_reply("?WV", "920", false, false), "920\r\n");
+        assert_eq!(format_reply("?WV", "920", true, false), "?WV 920\r\n");
+        assert_eq!(format_reply("?WV", "920", true, true), "Chameleon> ?WV 920\r\n");
+    }
+}
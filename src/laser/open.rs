@@ -0,0 +1,142 @@
+//! open.rs
+//!
+//! Settings for opening a laser's serial port, for rigs where the defaults
+//! don't fit -- a long USB extension that needs a longer timeout, a
+//! controller set to another baud rate, a flaky cable worth retrying over.
+
+use std::time::Duration;
+
+use crate::CoherentError;
+use crate::laser::Laser;
+use crate::laser::retry::RetryPolicy;
+use crate::laser::transcript::SerialTranscript;
+
+/// How to open a laser: passed to `Laser::open_with` and `Laser::from_port_info_with`.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use coherent_rs::Discovery;
+/// use coherent_rs::laser::open::LaserOpenOptions;
+///
+/// let discovery : Discovery = LaserOpenOptions::new()
+///     .with_timeout(Duration::from_secs(5))
+///     .with_baud_rate(9600)
+///     .with_retries(3)
+///     .open(Some("COM5"), None)
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct LaserOpenOptions {
+    /// How long one read or write on the port may block
+    pub timeout : Duration,
+    /// `None` for the laser's own default
+    pub baud_rate : Option<u32>,
+    /// The laser's retry policy once it's open
+    pub retry_policy : RetryPolicy,
+    /// Records everything sent and received on the port, from the moment
+    /// it's opened. Lasers without a serial port ignore it.
+    pub transcript : Option<SerialTranscript>,
+}
+
+impl Default for LaserOpenOptions {
+    fn default() -> Self {
+        LaserOpenOptions{
+            timeout : Duration::from_secs(2),
+            baud_rate : None,
+            retry_policy : RetryPolicy::default(),
+            transcript : None,
+        }
+    }
+}
+
+impl LaserOpenOptions {
+    /// A 2 second timeout, the laser's default baud rate, and each query
+    /// tried once.
+    pub fn new() -> Self {
+        LaserOpenOptions::default()
+    }
+
+    pub fn with_timeout(mut self, timeout : Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_baud_rate(mut self, baud_rate : u32) -> Self {
+        self.baud_rate = Some(baud_rate);
+        self
+    }
+
+    /// Tries each query up to `max_attempts` times in all, on timeouts,
+    /// I/O errors and garbled replies (see `RetryPolicy::new`).
+    pub fn with_retries(mut self, max_attempts : u32) -> Self {
+        self.retry_policy.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, policy : RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    pub fn with_transcript(mut self, transcript : SerialTranscript) -> Self {
+        self.transcript = Some(transcript);
+        self
+    }
+
+    /// See `Laser::open_with`.
+    pub fn open<L : Laser>(&self, port_name : Option<&str>, serial_number : Option<&str>) -> Result<L, CoherentError> {
+        L::open_with(port_name, serial_number, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::{Discovery, debug::DebugLaser};
+
+    #[test]
+    fn test_options() {
+        let options = LaserOpenOptions::new().with_timeout(Duration::from_millis(500)).with_retries(3);
+        assert_eq!(options.retry_policy.max_attempts, 3);
+        assert_eq!(options.baud_rate, None);
+        let laser : DebugLaser = options.open(Some("COM5"), Some("SN-A")).unwrap();
+        assert_eq!((laser.port_name.as_str(), laser.serial_number.as_str()), ("COM5", "SN-A"));
+        assert!(matches!(
+            LaserOpenOptions::new().open::<Discovery>(Some("NotAPort"), None),
+            Err(CoherentError::UnrecognizedDevice) | Err(CoherentError::SerialError(_))
+        ));
+    }
+
+    /// The options reach the port, over a pseudo-terminal to the simulator.
+    #[cfg(unix)]
+    #[test]
+    fn test_open_discovery_with_options() {
+        use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+        use serialport::{SerialPort, TTYPort};
+        use crate::laser::simulator::DiscoverySimulator;
+
+        let (mut master, slave) = TTYPort::pair().unwrap();
+        master.set_timeout(Duration::from_millis(50)).unwrap();
+        let port_info = serialport::SerialPortInfo{
+            port_name : slave.name().unwrap(),
+            port_type : serialport::SerialPortType::Unknown,
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let server_running = running.clone();
+        let server = std::thread::spawn(move || {
+            DiscoverySimulator::new(false, false, "SIM0001").serve(&mut master, &server_running).unwrap();
+        });
+
+        let options = LaserOpenOptions::new().with_timeout(Duration::from_millis(750)).with_baud_rate(9600).with_retries(4);
+        let discovery = Discovery::from_port_info_with(&port_info, &options).unwrap();
+        assert_eq!(discovery.serial_number, "SIM0001");
+        assert_eq!(discovery.port.timeout(), Duration::from_millis(750));
+        assert_eq!(discovery.retry_policy.max_attempts, 4);
+
+        running.store(false, Ordering::Relaxed);
+        server.join().unwrap();
+        drop(slave);
+    }
+}
@@ -0,0 +1,403 @@
+//! ports.rs
+//!
+//! Matching user-supplied port names against the ports `serialport`
+//! enumerates. Windows is the awkward one: `COM10` and above are often
+//! written `\\.\COM10`, case is not significant, and COM numbers get
+//! reshuffled when hubs are replugged, so a laser can also be named by its
+//! USB device instance path (`USB\VID_0D4D&PID_0204\<serial>`). On Linux
+//! the stable names are the `/dev/serial/by-id/...` symlinks, which are
+//! resolved to the `/dev/ttyACM*` device `serialport` reports.
+//!
+//! On macOS `serialport` sometimes reports a laser's `/dev/cu.usbmodem*`
+//! port without its USB vendor, product, or serial number, so
+//! `available_ports` fills those in from the IOKit registry (via `ioreg`).
+
+use serialport::{SerialPortInfo, SerialPortType, UsbPortInfo};
+
+use crate::CoherentError;
+
+/// Strips the Win32 device namespace prefix (`\\.\` or `\\?\`) and
+/// upper-cases `COM` port names, so `\\.\com12` and `COM12` compare equal.
+/// Anything that isn't a COM port is returned without the prefix but
+/// otherwise untouched -- `/dev/ttyUSB0` is case-sensitive.
+pub fn normalize_port_name(port_name : &str) -> String {
+    let name = port_name.trim();
+    let name = name.strip_prefix(r"\\.\")
+        .or_else(|| name.strip_prefix(r"\\?\"))
+        .unwrap_or(name);
+
+    if is_com_port(name) { name.to_ascii_uppercase() } else { name.to_string() }
+}
+
+fn is_com_port(name : &str) -> bool {
+    name.len() > 3
+        && name[..3].eq_ignore_ascii_case("COM")
+        && name[3..].chars().all(|c| c.is_ascii_digit())
+}
+
+/// The parts of a USB device instance path that identify a device.
+#[derive(Debug, PartialEq, Clone)]
+pub struct UsbInstancePath {
+    pub vid : u16,
+    pub pid : u16,
+    /// `None` when Windows generated the instance id itself
+    /// (e.g. `6&1A2B3C4D&0&2`) because the device has no serial number.
+    pub serial_number : Option<String>,
+}
+
+impl UsbInstancePath {
+    /// Parses `USB\VID_xxxx&PID_yyyy[&MI_zz]\<instance>`, ignoring case.
+    /// Returns `None` for anything else.
+    pub fn parse(path : &str) -> Option<Self> {
+        let mut parts = path.trim().split('\\');
+        if !parts.next()?.eq_ignore_ascii_case("USB") { return None; }
+
+        let mut vid = None;
+        let mut pid = None;
+        for field in parts.next()?.split('&') {
+            let (key, value) = field.split_once('_')?;
+            match key.to_ascii_uppercase().as_str() {
+                "VID" => vid = Some(u16::from_str_radix(value, 16).ok()?),
+                "PID" => pid = Some(u16::from_str_radix(value, 16).ok()?),
+                _ => {},
+            }
+        }
+
+        let instance = parts.next()?;
+        if parts.next().is_some() || instance.is_empty() { return None; }
+        let serial_number = if instance.contains('&') { None } else { Some(instance.to_string()) };
+
+        Some(UsbInstancePath{vid : vid?, pid : pid?, serial_number})
+    }
+
+    /// Whether `port` is the device this path names.
+    pub fn matches(&self, port : &SerialPortInfo) -> bool {
+        match &port.port_type {
+            SerialPortType::UsbPort(info) => {
+                info.vid == self.vid
+                && info.pid == self.pid
+                && match &self.serial_number {
+                    Some(serial) => info.serial_number.as_deref()
+                        .is_some_and(|s| s.eq_ignore_ascii_case(serial)),
+                    None => true,
+                }
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Whether `port_name` refers to `port`, either by (normalized) name
+/// or by USB device instance path.
+pub fn port_matches(port : &SerialPortInfo, port_name : &str) -> bool {
+    if let Some(path) = UsbInstancePath::parse(port_name) {
+        return path.matches(port);
+    }
+    resolve_port_name(&port.port_name) == resolve_port_name(port_name)
+}
+
+/// `normalize_port_name`, then follows symlinks (e.g. `/dev/serial/by-id/...`)
+/// to the device they point at. Names that aren't paths are left alone.
+fn resolve_port_name(port_name : &str) -> String {
+    let name = normalize_port_name(port_name);
+    #[cfg(unix)]
+    if let Ok(path) = std::fs::canonicalize(&name) {
+        return path.to_string_lossy().to_string();
+    }
+    name
+}
+
+/// Where `setup-permissions` installs the udev rule.
+pub const UDEV_RULE_PATH : &str = "/etc/udev/rules.d/99-coherent.rules";
+
+/// A udev rule that lets members of `group` (everyone, if `None`) open
+/// any serial device with Coherent's vendor ID without root.
+pub fn udev_rule(group : Option<&str>) -> String {
+    let access = match group {
+        Some(group) => format!("MODE=\"0660\", GROUP=\"{}\"", group),
+        None => "MODE=\"0666\"".to_string(),
+    };
+    format!(
+        "# Coherent lasers -- generated by coherent-rs\n\
+        SUBSYSTEM==\"tty\", ATTRS{{idVendor}}==\"{:04x}\", {}\n",
+        crate::COHERENT_VENDOR_ID, access
+    )
+}
+
+/// All the serial ports on the system, like `serialport::available_ports`,
+/// but on macOS any USB metadata `serialport` missed is looked up in the
+/// IOKit registry.
+pub fn available_ports() -> Result<Vec<SerialPortInfo>, CoherentError> {
+    #[allow(unused_mut)]
+    let mut ports = serialport::available_ports()?;
+    #[cfg(target_os = "macos")]
+    if ports.iter().any(|port| missing_usb_metadata(port)) {
+        if let Some(devices) = ioreg_usb_devices() {
+            fill_usb_metadata(&mut ports, &devices);
+        }
+    }
+    Ok(ports)
+}
+
+#[cfg(target_os = "macos")]
+fn ioreg_usb_devices() -> Option<Vec<IoregUsbDevice>> {
+    let output = std::process::Command::new("ioreg")
+        .args(["-r", "-c", "IOUSBHostDevice", "-l", "-w0"])
+        .output().ok()?;
+    if !output.status.success() { return None; }
+    Some(parse_ioreg(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// A USB device from the IOKit registry and the serial ports it provides.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct IoregUsbDevice {
+    pub vid : Option<u16>,
+    pub pid : Option<u16>,
+    pub serial_number : Option<String>,
+    pub manufacturer : Option<String>,
+    pub product : Option<String>,
+    /// `/dev/cu.*` and `/dev/tty.*` paths of the device's serial interfaces
+    pub ports : Vec<String>,
+}
+
+/// Reads the output of `ioreg -r -c IOUSBHostDevice -l`. Each
+/// `IOUSBHostDevice` (or the older `IOUSBDevice`) node starts a new device;
+/// properties printed after it, including those of its serial interface
+/// children, belong to it until the next one.
+pub fn parse_ioreg(output : &str) -> Vec<IoregUsbDevice> {
+    let mut devices : Vec<IoregUsbDevice> = Vec::new();
+    for line in output.lines() {
+        if line.contains("+-o ") {
+            if line.contains("<class IOUSBHostDevice") || line.contains("<class IOUSBDevice") {
+                devices.push(IoregUsbDevice::default());
+            }
+            continue;
+        }
+        let device = match devices.last_mut() {
+            Some(device) => device,
+            None => continue,
+        };
+        let (key, value) = match line.split_once(" = ") {
+            Some((key, value)) => (
+                key.trim().trim_start_matches('|').trim().trim_matches('"'),
+                value.trim(),
+            ),
+            None => continue,
+        };
+        let string = || Some(value.trim_matches('"').to_string());
+        match key {
+            "idVendor" => device.vid = value.parse().ok(),
+            "idProduct" => device.pid = value.parse().ok(),
+            "USB Serial Number" | "kUSBSerialNumberString" => device.serial_number = string(),
+            "USB Vendor Name" | "kUSBVendorString" => device.manufacturer = string(),
+            "USB Product Name" | "kUSBProductString" => device.product = string(),
+            "IOCalloutDevice" | "IODialinDevice" => device.ports.push(value.trim_matches('"').to_string()),
+            _ => {},
+        }
+    }
+    devices
+}
+
+/// Whether `serialport` left out the USB details a laser is found by.
+pub fn missing_usb_metadata(port : &SerialPortInfo) -> bool {
+    match &port.port_type {
+        SerialPortType::UsbPort(info) => info.serial_number.is_none(),
+        SerialPortType::Unknown => true,
+        _ => false,
+    }
+}
+
+/// Replaces missing USB metadata in `ports` with what `devices` says about
+/// the same port name. Ports `serialport` already described fully are
+/// left alone.
+pub fn fill_usb_metadata(ports : &mut [SerialPortInfo], devices : &[IoregUsbDevice]) {
+    for port in ports.iter_mut().filter(|port| missing_usb_metadata(port)) {
+        let device = match devices.iter().find(|d| d.ports.contains(&port.port_name)) {
+            Some(device) => device,
+            None => continue,
+        };
+        let (vid, pid) = match (device.vid, device.pid) {
+            (Some(vid), Some(pid)) => (vid, pid),
+            _ => continue,
+        };
+        let known = match &port.port_type {
+            SerialPortType::UsbPort(info) => Some(info.clone()),
+            _ => None,
+        };
+        port.port_type = SerialPortType::UsbPort(UsbPortInfo{
+            vid,
+            pid,
+            serial_number : device.serial_number.clone(),
+            manufacturer : known.as_ref().and_then(|k| k.manufacturer.clone()).or(device.manufacturer.clone()),
+            product : known.as_ref().and_then(|k| k.product.clone()).or(device.product.clone()),
+        });
+    }
+}
+
+/// Finds the port named `port_name` among `ports`.
+pub fn find_port_in(ports : Vec<SerialPortInfo>, port_name : &str) -> Result<SerialPortInfo, CoherentError> {
+    ports.into_iter()
+        .find(|port| port_matches(port, port_name))
+        .ok_or(CoherentError::UnrecognizedDevice)
+}
+
+/// Finds the available port named `port_name`. Accepts anything
+/// `port_matches` does.
+pub fn find_port(port_name : &str) -> Result<SerialPortInfo, CoherentError> {
+    find_port_in(available_ports()?, port_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::UsbPortInfo;
+
+    fn usb_port(name : &str, serial : Option<&str>) -> SerialPortInfo {
+        SerialPortInfo{
+            port_name : name.to_string(),
+            port_type : SerialPortType::UsbPort(UsbPortInfo{
+                vid : 0x0D4D,
+                pid : 0x0204,
+                serial_number : serial.map(str::to_string),
+                manufacturer : None,
+                product : None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_normalize_port_name() {
+        assert_eq!(normalize_port_name(r"\\.\COM10"), "COM10");
+        assert_eq!(normalize_port_name(r"\\?\com12"), "COM12");
+        assert_eq!(normalize_port_name("com3"), "COM3");
+        assert_eq!(normalize_port_name(" COM3 "), "COM3");
+        assert_eq!(normalize_port_name("/dev/ttyUSB0"), "/dev/ttyUSB0");
+        assert_eq!(normalize_port_name("COMX"), "COMX");
+    }
+
+    #[test]
+    fn test_parse_instance_path() {
+        assert_eq!(
+            UsbInstancePath::parse(r"USB\VID_0D4D&PID_0204\GDP.1234"),
+            Some(UsbInstancePath{vid : 0x0D4D, pid : 0x0204, serial_number : Some("GDP.1234".to_string())})
+        );
+        assert_eq!(
+            UsbInstancePath::parse(r"usb\vid_0d4d&pid_0204&mi_00\6&1A2B3C4D&0&0000"),
+            Some(UsbInstancePath{vid : 0x0D4D, pid : 0x0204, serial_number : None})
+        );
+        assert_eq!(UsbInstancePath::parse("COM12"), None);
+        assert_eq!(UsbInstancePath::parse(r"USB\VID_0D4D\1234"), None);
+        assert_eq!(UsbInstancePath::parse(r"USB\VID_ZZZZ&PID_0204\1234"), None);
+        assert_eq!(UsbInstancePath::parse(r"FTDIBUS\VID_0D4D+PID_0204\0000"), None);
+    }
+
+    #[test]
+    fn test_find_port() {
+        let ports = || vec![
+            usb_port("COM3", Some("1111")),
+            usb_port("COM12", Some("2222")),
+            SerialPortInfo{port_name : "COM1".to_string(), port_type : SerialPortType::PciPort},
+        ];
+        assert_eq!(find_port_in(ports(), "COM12").unwrap().port_name, "COM12");
+        assert_eq!(find_port_in(ports(), r"\\.\COM12").unwrap().port_name, "COM12");
+        assert_eq!(find_port_in(ports(), "com12").unwrap().port_name, "COM12");
+        assert_eq!(find_port_in(ports(), r"USB\VID_0D4D&PID_0204\2222").unwrap().port_name, "COM12");
+        assert_eq!(find_port_in(ports(), r"USB\VID_0D4D&PID_0204\6&0&0").unwrap().port_name, "COM3");
+        assert!(find_port_in(ports(), r"USB\VID_0D4D&PID_0204\3333").is_err());
+        assert!(find_port_in(ports(), "COM13").is_err());
+    }
+
+    const IOREG : &str = r#"+-o Discovery@01100000  <class IOUSBHostDevice, id 0x100000a1b, registered, matched, active, busy 0 (3 ms), retain 30>
+  | {
+  |   "idProduct" = 516
+  |   "USB Product Name" = "Discovery"
+  |   "USB Vendor Name" = "Coherent"
+  |   "idVendor" = 3405
+  |   "USB Serial Number" = "GDP.1234"
+  | }
+  |
+  +-o AppleUSBACMData  <class AppleUSBACMData, id 0x100000a2c, registered, matched, active, busy 0 (0 ms), retain 7>
+    +-o IOSerialBSDClient  <class IOSerialBSDClient, id 0x100000a31, registered, matched, active, busy 0 (0 ms), retain 6>
+        {
+          "IOCalloutDevice" = "/dev/cu.usbmodem11101"
+          "IODialinDevice" = "/dev/tty.usbmodem11101"
+        }
+
++-o USB Keyboard@01200000  <class IOUSBHostDevice, id 0x100000b00, registered, matched, active, busy 0 (1 ms), retain 20>
+  | {
+  |   "idProduct" = 591
+  |   "idVendor" = 1452
+  | }
+"#;
+
+    #[test]
+    fn test_parse_ioreg() {
+        let devices = parse_ioreg(IOREG);
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0], IoregUsbDevice{
+            vid : Some(3405),
+            pid : Some(516),
+            serial_number : Some("GDP.1234".to_string()),
+            manufacturer : Some("Coherent".to_string()),
+            product : Some("Discovery".to_string()),
+            ports : vec!["/dev/cu.usbmodem11101".to_string(), "/dev/tty.usbmodem11101".to_string()],
+        });
+        assert_eq!(devices[1].vid, Some(1452));
+        assert!(devices[1].ports.is_empty());
+        assert!(parse_ioreg("").is_empty());
+    }
+
+    #[test]
+    fn test_fill_usb_metadata() {
+        let mut ports = vec![
+            SerialPortInfo{port_name : "/dev/cu.usbmodem11101".to_string(), port_type : SerialPortType::Unknown},
+            SerialPortInfo{port_name : "/dev/cu.Bluetooth".to_string(), port_type : SerialPortType::BluetoothPort},
+            usb_port("/dev/cu.usbmodem9", Some("9999")),
+        ];
+        fill_usb_metadata(&mut ports, &parse_ioreg(IOREG));
+        match &ports[0].port_type {
+            SerialPortType::UsbPort(info) => {
+                assert_eq!((info.vid, info.pid), (3405, 516));
+                assert_eq!(info.serial_number.as_deref(), Some("GDP.1234"));
+            },
+            other => panic!("expected a USB port, got {:?}", other),
+        }
+        assert_eq!(ports[1].port_type, SerialPortType::BluetoothPort);
+        assert_eq!(ports[2], usb_port("/dev/cu.usbmodem9", Some("9999")));
+
+        // A USB port missing only its serial number gets it filled in
+        let mut ports = vec![usb_port("/dev/tty.usbmodem11101", None)];
+        fill_usb_metadata(&mut ports, &parse_ioreg(IOREG));
+        match &ports[0].port_type {
+            SerialPortType::UsbPort(info) => assert_eq!(info.serial_number.as_deref(), Some("GDP.1234")),
+            other => panic!("expected a USB port, got {:?}", other),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_port_by_symlink() {
+        let dir = std::env::temp_dir().join(format!("coherent-by-id-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let device = dir.join("ttyACM0");
+        let by_id = dir.join("usb-Coherent_Discovery_GDP.1234-if00");
+        std::fs::write(&device, b"").unwrap();
+        let _ = std::fs::remove_file(&by_id);
+        std::os::unix::fs::symlink(&device, &by_id).unwrap();
+
+        let ports = vec![usb_port(device.to_str().unwrap(), Some("GDP.1234"))];
+        let found = find_port_in(ports, by_id.to_str().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(found.unwrap().port_name, device.to_str().unwrap());
+    }
+
+    #[test]
+    fn test_udev_rule() {
+        assert_eq!(
+            udev_rule(None),
+            "# Coherent lasers -- generated by coherent-rs\n\
+            SUBSYSTEM==\"tty\", ATTRS{idVendor}==\"0d4d\", MODE=\"0666\"\n"
+        );
+        assert!(udev_rule(Some("dialout")).ends_with("MODE=\"0660\", GROUP=\"dialout\"\n"));
+    }
+}
@@ -0,0 +1,182 @@
+//! power_meter.rs
+//!
+//! External power measurements, for checking what the laser says against
+//! what actually reaches the rig. Anything that can report a power in mW
+//! implements `PowerMeter`: a Thorlabs PM100 wrapper, a photodiode on a DAQ
+//! channel (see `AnalogPowerMeter`), or a closure in tests.
+
+use std::time::Duration;
+
+use crate::CoherentError;
+use crate::laser::calibration::PowerCalibration;
+
+/// A source of external power measurements.
+///
+/// # Example
+///
+/// Wrapping a vendor driver is a matter of implementing `read_power_mw`:
+///
+/// ```rust
+/// use coherent_rs::{CoherentError, laser::power_meter::PowerMeter};
+///
+/// struct ThorlabsPM {
+///     // handle to the vendor's driver would go here
+///     last_reading_w : f32,
+/// }
+///
+/// impl PowerMeter for ThorlabsPM {
+///     fn read_power_mw(&mut self) -> Result<f32, CoherentError> {
+///         // The PM100 series reports Watts
+///         Ok(self.last_reading_w * 1000.0)
+///     }
+/// }
+///
+/// let mut meter = ThorlabsPM{last_reading_w : 0.25};
+/// assert_eq!(meter.read_power_mw().unwrap(), 250.0);
+/// ```
+pub trait PowerMeter {
+    /// A single power reading, in mW.
+    fn read_power_mw(&mut self) -> Result<f32, CoherentError>;
+
+    /// The mean of `samples` readings taken `interval` apart.
+    fn read_average_mw(&mut self, samples : usize, interval : Duration) -> Result<f32, CoherentError> {
+        if samples == 0 {
+            return Err(CoherentError::InvalidArgumentsError("Need at least one sample".to_string()));
+        }
+        let mut total = 0.0;
+        for i in 0..samples {
+            if i > 0 { std::thread::sleep(interval); }
+            total += self.read_power_mw()?;
+        }
+        Ok(total / samples as f32)
+    }
+}
+
+impl<M : PowerMeter + ?Sized> PowerMeter for &mut M {
+    fn read_power_mw(&mut self) -> Result<f32, CoherentError> {
+        (**self).read_power_mw()
+    }
+}
+
+impl<M : PowerMeter + ?Sized> PowerMeter for Box<M> {
+    fn read_power_mw(&mut self) -> Result<f32, CoherentError> {
+        (**self).read_power_mw()
+    }
+}
+
+/// A power meter read through an analog input, e.g. a photodiode or the
+/// analog output of a meter head wired to a DAQ. `read_volts` samples the
+/// input; `calibration` converts Volts to mW (`scale` in mW/V).
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::{calibration::PowerCalibration, power_meter::{AnalogPowerMeter, PowerMeter}};
+///
+/// // 2 V out of the DAQ with a 100 mW/V head
+/// let mut meter = AnalogPowerMeter::new(|| Ok(2.0), PowerCalibration::new(100.0, 0.0));
+/// assert_eq!(meter.read_power_mw().unwrap(), 200.0);
+/// ```
+pub struct AnalogPowerMeter<F : FnMut() -> Result<f32, CoherentError>> {
+    read_volts : F,
+    pub calibration : PowerCalibration,
+}
+
+impl<F : FnMut() -> Result<f32, CoherentError>> AnalogPowerMeter<F> {
+    pub fn new(read_volts : F, calibration : PowerCalibration) -> Self {
+        AnalogPowerMeter{read_volts, calibration}
+    }
+}
+
+impl<F : FnMut() -> Result<f32, CoherentError>> PowerMeter for AnalogPowerMeter<F> {
+    fn read_power_mw(&mut self) -> Result<f32, CoherentError> {
+        Ok(self.calibration.apply((self.read_volts)()?))
+    }
+}
+
+/// Fits a `PowerCalibration` mapping the laser's readout to what `meter`
+/// sees, by least squares over `(laser readout, meter reading)` pairs
+/// (collected e.g. at a few wavelengths). A single pair gives a pure scale.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::{calibration::PowerCalibration, power_meter::fit_power_calibration};
+///
+/// let fit = fit_power_calibration(&[(1000.0, 400.0), (2000.0, 800.0)]).unwrap();
+/// assert_eq!(fit, PowerCalibration::new(0.4, 0.0));
+/// ```
+pub fn fit_power_calibration(pairs : &[(f32, f32)]) -> Result<PowerCalibration, CoherentError> {
+    match pairs {
+        [] => Err(CoherentError::InvalidArgumentsError("Need at least one measurement".to_string())),
+        [(raw, measured)] => {
+            if *raw == 0.0 {
+                return Err(CoherentError::InvalidArgumentsError("Laser readout was 0 mW".to_string()));
+            }
+            Ok(PowerCalibration::new(measured / raw, 0.0))
+        },
+        _ => {
+            let n = pairs.len() as f64;
+            let mean_raw = pairs.iter().map(|(r, _)| *r as f64).sum::<f64>() / n;
+            let mean_measured = pairs.iter().map(|(_, m)| *m as f64).sum::<f64>() / n;
+            let covariance = pairs.iter()
+                .map(|(r, m)| (*r as f64 - mean_raw) * (*m as f64 - mean_measured))
+                .sum::<f64>();
+            let variance = pairs.iter().map(|(r, _)| (*r as f64 - mean_raw).powi(2)).sum::<f64>();
+            if variance == 0.0 {
+                return Err(CoherentError::InvalidArgumentsError(
+                    "All laser readouts were the same -- can't fit an offset".to_string()
+                ));
+            }
+            let scale = covariance / variance;
+            Ok(PowerCalibration::new(scale as f32, (mean_measured - scale * mean_raw) as f32))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_average() {
+        let mut readings = [100.0, 110.0, 120.0].into_iter();
+        let mut meter = AnalogPowerMeter::new(
+            move || readings.next().ok_or(CoherentError::TimeoutError),
+            PowerCalibration::default(),
+        );
+        assert_eq!(meter.read_average_mw(3, Duration::ZERO).unwrap(), 110.0);
+        assert!(matches!(meter.read_average_mw(1, Duration::ZERO), Err(CoherentError::TimeoutError)));
+        assert!(matches!(
+            meter.read_average_mw(0, Duration::ZERO),
+            Err(CoherentError::InvalidArgumentsError(_))
+        ));
+    }
+
+    #[test]
+    fn test_dyn_meter() {
+        let mut meter : Box<dyn PowerMeter> = Box::new(
+            AnalogPowerMeter::new(|| Ok(0.5), PowerCalibration::new(100.0, 1.0))
+        );
+        assert_eq!(meter.read_power_mw().unwrap(), 51.0);
+        // Helpers that take a meter by value accept a borrowed one too
+        fn average<M : PowerMeter>(mut meter : M) -> f32 {
+            meter.read_average_mw(2, Duration::ZERO).unwrap()
+        }
+        assert_eq!(average(&mut meter), 51.0);
+    }
+
+    #[test]
+    fn test_fit_power_calibration() {
+        let truth = PowerCalibration::new(0.45, -12.0);
+        let pairs = [800.0, 1000.0, 1400.0, 2000.0].map(|raw| (raw, truth.apply(raw)));
+        let fit = fit_power_calibration(&pairs).unwrap();
+        assert!((fit.scale - truth.scale).abs() < 1e-4);
+        assert!((fit.offset_mw - truth.offset_mw).abs() < 1e-2);
+
+        assert_eq!(fit_power_calibration(&[(1000.0, 250.0)]).unwrap(), PowerCalibration::new(0.25, 0.0));
+        assert!(fit_power_calibration(&[]).is_err());
+        assert!(fit_power_calibration(&[(0.0, 250.0)]).is_err());
+        assert!(fit_power_calibration(&[(1000.0, 250.0), (1000.0, 260.0)]).is_err());
+    }
+}
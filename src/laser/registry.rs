@@ -0,0 +1,152 @@
+//! registry.rs
+//!
+//! A process-wide table of `SharedLaser`s by alias, for hosts that load
+//! plugins -- microscope software with a module per device, say -- where
+//! each plugin would otherwise open the laser's port itself, and all but
+//! the first would find it taken. The first plugin to need the laser opens
+//! and registers it; the others get the same handle. Using it is optional:
+//! nothing else in the crate touches it.
+//!
+//! Every plugin must link the same copy of this crate for them to see the
+//! same registry -- plugins built as separate dynamic libraries that each
+//! carry their own copy each get their own.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use crate::CoherentError;
+use crate::laser::{Laser, shared::SharedLaser};
+
+/// Each entry is a `SharedLaser<L>` for some `L`.
+type Registry = HashMap<String, Box<dyn Any + Send + Sync>>;
+
+static REGISTRY : OnceLock<Mutex<Registry>> = OnceLock::new();
+
+/// The registry, locked. Nothing can be left half-done in a `HashMap` by a
+/// thread that panicked, so a poisoned lock is taken anyway.
+fn registry() -> MutexGuard<'static, Registry> {
+    REGISTRY.get_or_init(Default::default).lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Makes `handle` available to the whole process as `alias`.
+/// `InvalidArgumentsError` if a laser is already registered under `alias`
+/// -- `unregister_laser` it first to replace it.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::{debug::DebugLaser, shared::SharedLaser};
+/// use coherent_rs::laser::registry::{register_laser, get_laser, unregister_laser};
+///
+/// register_laser("rig-2", SharedLaser::new(DebugLaser::default())).unwrap();
+///
+/// // Elsewhere, e.g. in another plugin
+/// let laser = get_laser::<DebugLaser>("rig-2").unwrap();
+/// laser.with(|laser| laser.set_wavelength(850.0)).unwrap().unwrap();
+/// # unregister_laser("rig-2");
+/// ```
+pub fn register_laser<L : Laser + 'static>(alias : &str, handle : SharedLaser<L>) -> Result<(), CoherentError> {
+    let mut registry = registry();
+    if registry.contains_key(alias) {
+        return Err(CoherentError::InvalidArgumentsError(format!("A laser is already registered as `{}`", alias)));
+    }
+    registry.insert(alias.to_string(), Box::new(handle));
+    Ok(())
+}
+
+/// The laser registered as `alias`, if there is one and it's an `L`.
+pub fn get_laser<L : Laser + 'static>(alias : &str) -> Option<SharedLaser<L>> {
+    registry().get(alias)?.downcast_ref::<SharedLaser<L>>().cloned()
+}
+
+/// The laser registered as `alias`, or if there isn't one, the laser `open`
+/// returns, registered as `alias`. Other threads asking for a laser wait
+/// while `open` runs, so two plugins starting at once can't both open the
+/// port -- so `open` mustn't use the registry itself. `InvalidArgumentsError`
+/// if `alias` is a different type of laser.
+///
+/// # Example
+///
+/// ```no_run
+/// use coherent_rs::{Discovery, laser::{Laser, registry::get_or_open_laser}};
+///
+/// // Whichever plugin gets here first opens the port
+/// let laser = get_or_open_laser("discovery", Discovery::find_first).unwrap();
+/// println!("{:?}", laser.status().unwrap());
+/// ```
+pub fn get_or_open_laser<L : Laser + 'static>(alias : &str, open : impl FnOnce() -> Result<L, CoherentError>)
+    -> Result<SharedLaser<L>, CoherentError> {
+    let mut registry = registry();
+    if let Some(entry) = registry.get(alias) {
+        return entry.downcast_ref::<SharedLaser<L>>().cloned().ok_or_else(|| CoherentError::InvalidArgumentsError(
+            format!("The laser registered as `{}` is a different type", alias)
+        ));
+    }
+    let handle = SharedLaser::new(open()?);
+    registry.insert(alias.to_string(), Box::new(handle.clone()));
+    Ok(handle)
+}
+
+/// Removes the laser registered as `alias`, returning whether there was
+/// one. Handles already taken keep working; the laser closes when the last
+/// is dropped.
+pub fn unregister_laser(alias : &str) -> bool {
+    registry().remove(alias).is_some()
+}
+
+/// The aliases registered, in no particular order.
+pub fn registered_lasers() -> Vec<String> {
+    registry().keys().cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::debug::DebugLaser;
+    use crate::laser::Discovery;
+
+    // The registry is shared by every test in the process, so each test
+    // uses aliases of its own.
+
+    #[test]
+    fn test_register() {
+        let laser = SharedLaser::new(DebugLaser::default());
+        register_laser("registry-test", laser.clone()).unwrap();
+        assert!(matches!(
+            register_laser("registry-test", SharedLaser::new(DebugLaser::default())),
+            Err(CoherentError::InvalidArgumentsError(_))
+        ));
+        assert!(registered_lasers().contains(&"registry-test".to_string()));
+
+        let found = get_laser::<DebugLaser>("registry-test").unwrap();
+        found.with(|laser| laser.set_wavelength(850.0)).unwrap().unwrap();
+        assert_eq!(laser.with(|laser| laser.get_wavelength()).unwrap().unwrap(), 850.0);
+        assert!(get_laser::<Discovery>("registry-test").is_none());
+        assert!(get_laser::<DebugLaser>("registry-missing").is_none());
+
+        assert!(unregister_laser("registry-test"));
+        assert!(!unregister_laser("registry-test"));
+        assert!(get_laser::<DebugLaser>("registry-test").is_none());
+        assert_eq!(laser.handles(), 2);
+    }
+
+    #[test]
+    fn test_get_or_open() {
+        let opened = std::sync::atomic::AtomicUsize::new(0);
+        let open = || {
+            opened.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(DebugLaser::default())
+        };
+        let first = get_or_open_laser("registry-open", open).unwrap();
+        let second = get_or_open_laser("registry-open", open).unwrap();
+        assert_eq!(opened.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(first.handles(), 3);
+        drop(second);
+
+        assert!(get_or_open_laser::<Discovery>("registry-open", || Err(CoherentError::LaserUnavailableError)).is_err());
+        assert!(get_or_open_laser::<DebugLaser>("registry-failed", || Err(CoherentError::LaserUnavailableError)).is_err());
+        assert!(get_laser::<DebugLaser>("registry-failed").is_none());
+        unregister_laser("registry-open");
+    }
+}
@@ -0,0 +1,372 @@
+//! retry.rs
+//!
+//! How hard to try before giving up. A `RetryPolicy` is shared by the
+//! serial layer (`Discovery` queries) and the network layer (client
+//! operations), so both recover from the same transient failures -- a
+//! dropped byte, a busy laser, a slow server -- in the same, tunable way.
+//! A `Deadline` puts a wall-clock bound on the whole thing, retries and
+//! waits included.
+
+use std::time::{Duration, Instant};
+
+use crate::CoherentError;
+
+/// The kinds of failure a `RetryPolicy` can retry. Errors that can't be
+/// fixed by trying again (bad arguments, soft limits, a locked laser) have
+/// no class and are never retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Nothing came back in time
+    Timeout,
+    /// The port or socket failed to read or write
+    Io,
+    /// Something came back, but it couldn't be understood
+    InvalidResponse,
+    /// The laser refused the command (e.g. busy tuning), or quietly didn't
+    /// apply it
+    NotExecuted,
+    /// Another thread had the laser (see `shared::SharedLaser::try_lock`)
+    Busy,
+}
+
+/// An error a `RetryPolicy` can judge.
+pub trait RetryableError {
+    /// The class of failure, or `None` if retrying can't help.
+    fn error_class(&self) -> Option<ErrorClass>;
+}
+
+impl RetryableError for CoherentError {
+    fn error_class(&self) -> Option<ErrorClass> {
+        match self {
+            CoherentError::TimeoutError | CoherentError::CommandTimedOut => Some(ErrorClass::Timeout),
+            CoherentError::SerialError(_) | CoherentError::WriteError(_) => Some(ErrorClass::Io),
+            CoherentError::InvalidResponseError(_) => Some(ErrorClass::InvalidResponse),
+            CoherentError::CommandNotExecutedError | CoherentError::VerificationFailed{..} => Some(ErrorClass::NotExecuted),
+            CoherentError::LaserBusyError => Some(ErrorClass::Busy),
+            _ => None,
+        }
+    }
+}
+
+/// A point in time an operation has to be finished by. Set one with
+/// `Discovery::with_timeout` (or a network client's `with_timeout`) to bound
+/// a series of calls, or with `RetryPolicy::with_total_timeout` to bound
+/// each call on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    pub fn after(timeout : Duration) -> Self {
+        Deadline(Instant::now() + timeout)
+    }
+
+    /// The time left, or zero once it's passed.
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.0
+    }
+
+    /// `TimeoutError` if the deadline has passed.
+    pub fn check(&self) -> Result<(), CoherentError> {
+        if self.is_expired() { Err(CoherentError::TimeoutError) } else { Ok(()) }
+    }
+
+    /// `timeout`, cut down to the time left. Never zero, which serial ports
+    /// and sockets don't accept as a timeout.
+    pub fn cap(&self, timeout : Duration) -> Duration {
+        timeout.min(self.remaining()).max(Duration::from_millis(1))
+    }
+
+    /// Whichever of two (optional) deadlines comes first.
+    pub fn earliest(a : Option<Deadline>, b : Option<Deadline>) -> Option<Deadline> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// How many times to try an operation, how long to wait between tries,
+/// and which failures are worth another try. The default tries once, with
+/// no bound on how long that takes.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use coherent_rs::CoherentError;
+/// use coherent_rs::laser::retry::{RetryPolicy, ErrorClass};
+///
+/// let policy = RetryPolicy::new(3)
+///     .with_backoff(Duration::from_millis(1), 2.0)
+///     .retry_on(&[ErrorClass::Timeout]);
+///
+/// let mut tries = 0;
+/// let result = policy.run(|| {
+///     tries += 1;
+///     if tries < 3 { Err(CoherentError::TimeoutError) } else { Ok(tries) }
+/// });
+/// assert_eq!(result.unwrap(), 3);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Tries in total, including the first. 0 is treated as 1.
+    pub max_attempts : u32,
+    /// The wait before the first retry
+    pub initial_backoff : Duration,
+    /// Each wait is this many times the one before
+    pub multiplier : f64,
+    /// No wait is longer than this
+    pub max_backoff : Duration,
+    /// The failures worth retrying
+    pub retryable : Vec<ErrorClass>,
+    /// If `Some`, the most time an operation may take, retries and waits
+    /// included. No retry is started that couldn't finish in time.
+    pub total_timeout : Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(1)
+    }
+}
+
+impl RetryPolicy {
+    /// Tries up to `max_attempts` times on timeouts, I/O errors and
+    /// garbled responses, waiting 100 ms, then 200 ms, ... (at most 2 s).
+    pub fn new(max_attempts : u32) -> Self {
+        RetryPolicy{
+            max_attempts,
+            initial_backoff : Duration::from_millis(100),
+            multiplier : 2.0,
+            max_backoff : Duration::from_secs(2),
+            retryable : vec![ErrorClass::Timeout, ErrorClass::Io, ErrorClass::InvalidResponse],
+            total_timeout : None,
+        }
+    }
+
+    /// Tries a command up to `max_attempts` times while the laser refuses
+    /// it (`COMMAND NOT EXECUTED`, as it does while it's busy) or, with
+    /// verification on, doesn't apply it -- both safe to send again. Waits
+    /// as `new` does. Timeouts and I/O errors aren't retried: the command
+    /// may have gone through.
+    pub fn for_commands(max_attempts : u32) -> Self {
+        RetryPolicy::new(max_attempts).retry_on(&[ErrorClass::NotExecuted])
+    }
+
+    pub fn with_backoff(mut self, initial_backoff : Duration, multiplier : f64) -> Self {
+        self.initial_backoff = initial_backoff;
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn with_max_backoff(mut self, max_backoff : Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn with_total_timeout(mut self, total_timeout : Duration) -> Self {
+        self.total_timeout = Some(total_timeout);
+        self
+    }
+
+    /// Replaces the classes of failure that are retried.
+    pub fn retry_on(mut self, classes : &[ErrorClass]) -> Self {
+        self.retryable = classes.to_vec();
+        self
+    }
+
+    /// The wait after failed try number `attempt` (counting from 1).
+    pub fn backoff(&self, attempt : u32) -> Duration {
+        let factor = self.multiplier.max(0.0).powi(attempt.saturating_sub(1) as i32);
+        self.initial_backoff.mul_f64(factor).min(self.max_backoff)
+    }
+
+    /// Whether to try again after `error` on try number `attempt`.
+    pub fn should_retry<E : RetryableError>(&self, error : &E, attempt : u32) -> bool {
+        attempt < self.max_attempts
+            && error.error_class().is_some_and(|class| self.retryable.contains(&class))
+    }
+
+    /// The deadline for an operation starting now, per `total_timeout`.
+    pub fn deadline(&self) -> Option<Deadline> {
+        self.total_timeout.map(Deadline::after)
+    }
+
+    /// Runs `operation` until it succeeds, fails with an error that isn't
+    /// retryable, or runs out of tries or time. Returns the last result.
+    pub fn run<T, E, F>(&self, operation : F) -> Result<T, E>
+    where E : RetryableError, F : FnMut() -> Result<T, E> {
+        self.run_until(self.deadline(), operation)
+    }
+
+    /// Like `run`, but retries only while there's time to before `deadline`
+    /// (rather than the policy's own `total_timeout`). `operation` is
+    /// expected to bound its own waits by the deadline.
+    pub fn run_until<T, E, F>(&self, deadline : Option<Deadline>, mut operation : F) -> Result<T, E>
+    where E : RetryableError, F : FnMut() -> Result<T, E> {
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Err(e) if self.should_retry(&e, attempt)
+                    && deadline.is_none_or(|deadline| self.backoff(attempt) < deadline.remaining()) => {
+                    std::thread::sleep(self.backoff(attempt));
+                    attempt += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Called with the query sent and the reply to it each time a reply can't
+/// be understood, before the `RetryPolicy` decides whether to try again --
+/// to log the garbled line, say, or put the port back in a known state. An
+/// error from the hook gives up on the query with that error instead.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::retry::{InvalidResponseHook, RetryPolicy};
+/// use coherent_rs::{Discovery, laser::{Laser, mock::MockSerialPort}};
+///
+/// let port = MockSerialPort::discovery(false, false, "SN1234")
+///     .expect("?WV", "9#0\r\n")
+///     .expect("?WV", "920\r\n");
+/// let mut discovery = Discovery::from_serial_port(Box::new(port)).unwrap();
+/// discovery.retry_policy = RetryPolicy::new(2);
+/// discovery.invalid_response_hook = InvalidResponseHook::new(|query, reply| {
+///     eprintln!("Couldn't make sense of `{}` in reply to {}", reply, query);
+///     Ok(())
+/// });
+/// assert_eq!(discovery.get_wavelength().unwrap(), 920.0);
+/// ```
+#[derive(Default)]
+pub struct InvalidResponseHook(Option<ResponseCallback>);
+
+/// Takes the query and the reply.
+type ResponseCallback = Box<dyn FnMut(&str, &str) -> Result<(), CoherentError> + Send>;
+
+impl std::fmt::Debug for InvalidResponseHook {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("InvalidResponseHook").field(&self.0.is_some()).finish()
+    }
+}
+
+impl InvalidResponseHook {
+    pub fn new<F>(hook : F) -> Self
+    where F : FnMut(&str, &str) -> Result<(), CoherentError> + Send + 'static {
+        InvalidResponseHook(Some(Box::new(hook)))
+    }
+
+    /// Passes `result` through, running the hook first if it's an
+    /// `InvalidResponseError`.
+    pub fn check<T>(&mut self, query : &str, result : Result<T, CoherentError>) -> Result<T, CoherentError> {
+        match (&mut self.0, result) {
+            (Some(hook), Err(CoherentError::InvalidResponseError(reply))) => {
+                hook(query, &reply)?;
+                Err(CoherentError::InvalidResponseError(reply))
+            },
+            (_, result) => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new(5)
+            .with_backoff(Duration::from_millis(100), 2.0)
+            .with_max_backoff(Duration::from_millis(300));
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(300));
+        assert_eq!(policy.backoff(10), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_run() {
+        let policy = RetryPolicy::new(3).with_backoff(Duration::ZERO, 1.0);
+
+        // Gives up after max_attempts
+        let mut tries = 0;
+        let result : Result<(), _> = policy.run(|| { tries += 1; Err(CoherentError::TimeoutError) });
+        assert!(matches!(result, Err(CoherentError::TimeoutError)));
+        assert_eq!(tries, 3);
+
+        // Never retries what can't be fixed by retrying
+        let mut tries = 0;
+        let result : Result<(), _> = policy.run(|| { tries += 1; Err(CoherentError::LockedError) });
+        assert!(result.is_err());
+        assert_eq!(tries, 1);
+
+        // Or classes it wasn't asked to retry
+        let mut tries = 0;
+        let result : Result<(), _> = policy.run(|| { tries += 1; Err(CoherentError::CommandNotExecutedError) });
+        assert!(result.is_err());
+        assert_eq!(tries, 1);
+
+        // The default tries once
+        let mut tries = 0;
+        let result : Result<(), _> = RetryPolicy::default().run(|| { tries += 1; Err(CoherentError::TimeoutError) });
+        assert!(result.is_err());
+        assert_eq!(tries, 1);
+    }
+
+    #[test]
+    fn test_invalid_response_hook() {
+        let mut seen = Vec::new();
+        let mut hook = InvalidResponseHook::new(move |query, reply| {
+            seen.push(format!("{} {}", query, reply));
+            if seen.len() > 1 { Err(CoherentError::LaserUnavailableError) } else { Ok(()) }
+        });
+        assert_eq!(hook.check("?WV", Ok(920.0)).unwrap(), 920.0);
+        assert!(matches!(hook.check::<f32>("?WV", Err(CoherentError::TimeoutError)), Err(CoherentError::TimeoutError)));
+        assert!(matches!(
+            hook.check::<f32>("?WV", Err(CoherentError::InvalidResponseError("9#0".to_string()))),
+            Err(CoherentError::InvalidResponseError(reply)) if reply == "9#0"
+        ));
+        // The hook's own error wins
+        assert!(matches!(
+            hook.check::<f32>("?WV", Err(CoherentError::InvalidResponseError("9#0".to_string()))),
+            Err(CoherentError::LaserUnavailableError)
+        ));
+        assert!(InvalidResponseHook::default().check::<f32>("?WV", Err(CoherentError::InvalidResponseError(String::new()))).is_err());
+    }
+
+    #[test]
+    fn test_total_timeout() {
+        let policy = RetryPolicy::new(1000)
+            .with_backoff(Duration::from_millis(10), 1.0)
+            .with_total_timeout(Duration::from_millis(100));
+        let start = Instant::now();
+        let mut tries = 0;
+        let result : Result<(), _> = policy.run(|| { tries += 1; Err(CoherentError::TimeoutError) });
+        assert!(result.is_err());
+        assert!(start.elapsed() < Duration::from_millis(150), "{:?}", start.elapsed());
+        assert!(tries > 1 && tries <= 10, "{}", tries);
+
+        // A deadline that's already passed leaves time for one try
+        let mut tries = 0;
+        let result : Result<(), _> = policy.run_until(
+            Some(Deadline::after(Duration::ZERO)),
+            || { tries += 1; Err(CoherentError::TimeoutError) }
+        );
+        assert!(result.is_err());
+        assert_eq!(tries, 1);
+
+        let soon = Deadline::after(Duration::from_millis(50));
+        let later = Deadline::after(Duration::from_secs(50));
+        assert_eq!(Deadline::earliest(Some(later), Some(soon)), Some(soon));
+        assert_eq!(Deadline::earliest(None, Some(later)), Some(later));
+        assert!(soon.cap(Duration::from_secs(2)) <= Duration::from_millis(50));
+        assert_eq!(Deadline::after(Duration::ZERO).cap(Duration::from_secs(2)), Duration::from_millis(1));
+        assert!(matches!(Deadline::after(Duration::ZERO).check(), Err(CoherentError::TimeoutError)));
+    }
+}
@@ -0,0 +1,314 @@
+//! safety.rs
+//!
+//! Making the laser safe when it faults, without waiting for someone to
+//! notice. A `SafetyPolicy` says which faults -- and whether the keyswitch
+//! going off -- should close both shutters, and whether to drop the laser to
+//! standby too. `FaultMonitor::start_with_safety` enforces it from the
+//! monitor's thread on every read: while the policy is tripped, both
+//! shutters are closed again, and each action taken is logged (with the
+//! `tracing` feature). A laser whose state can't be read is taken to have
+//! tripped it.
+
+use std::time::Duration;
+
+use crate::CoherentError;
+use crate::laser::{Laser, FaultReport, LaserState, ShutterState, Keyswitch, shared::SharedLaser};
+use crate::laser::discoverynx::{DiscoveryLaser, DiscoveryNXCommands, DiscoveryNXQueries, DiscoveryNXStatus, faults::FaultFlags};
+use crate::laser::fault_monitor::FaultMonitor;
+
+/// When to make the laser safe, and how.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SafetyPolicy {
+    /// The faults that trip the policy -- any of them will do
+    pub faults : FaultFlags,
+    /// Whether the keyswitch being off trips the policy
+    pub keyswitch_off : bool,
+    /// Whether to put the laser in standby, as well as closing the shutters
+    pub standby : bool,
+}
+
+/// Any fault, or the keyswitch turned off, closes the shutters.
+impl Default for SafetyPolicy {
+    fn default() -> Self {
+        SafetyPolicy{faults : FaultFlags::from_bits(u8::MAX), keyswitch_off : true, standby : false}
+    }
+}
+
+impl SafetyPolicy {
+    /// Trips on any of `faults` only.
+    pub fn faults(faults : FaultFlags) -> Self {
+        SafetyPolicy{faults, keyswitch_off : false, standby : false}
+    }
+
+    /// Also trips when the keyswitch is off.
+    pub fn on_keyswitch_off(mut self) -> Self {
+        self.keyswitch_off = true;
+        self
+    }
+
+    /// Also puts the laser in standby.
+    pub fn with_standby(mut self) -> Self {
+        self.standby = true;
+        self
+    }
+
+    /// Whether the policy is tripped by the laser's state in `status`.
+    pub fn is_tripped(&self, status : &DiscoveryNXStatus) -> bool {
+        self.trip_reason(status.faults, status.keyswitch).is_some()
+    }
+
+    /// What trips the policy, out of `faults` and `keyswitch`, if anything.
+    fn trip_reason(&self, faults : FaultFlags, keyswitch : Keyswitch) -> Option<String> {
+        if faults.bits() & self.faults.bits() != 0 {
+            Some(format!("fault {}", faults))
+        } else if self.keyswitch_off && keyswitch == Keyswitch::Off {
+            Some("keyswitch off".to_string())
+        } else {
+            None
+        }
+    }
+
+    /// The commands that make the laser safe once the policy is tripped:
+    /// both shutters closed, and standby if the policy asks for it. They're
+    /// sent whatever the laser says its shutters are doing, so a shutter
+    /// misreported as closed is closed all the same.
+    pub fn actions(&self) -> Vec<DiscoveryNXCommands> {
+        let mut actions = [DiscoveryLaser::VariableWavelength, DiscoveryLaser::FixedWavelength].into_iter()
+            .map(|laser| DiscoveryNXCommands::Shutter{laser, state : ShutterState::Closed})
+            .collect::<Vec<_>>();
+        if self.standby {
+            actions.push(DiscoveryNXCommands::Laser{state : LaserState::Standby});
+        }
+        actions
+    }
+
+    /// Reads `laser`'s faults and keyswitch and, if they trip the policy,
+    /// sends it the commands that make it safe with `Laser::force_command`,
+    /// so nothing the laser thinks it knows keeps them from going out.
+    /// They're sent even if the laser has an operator lock -- the lock is
+    /// set aside for them, and put back after.
+    ///
+    /// The state comes from a whole `status` if that reads; if not, the
+    /// faults (and, if the policy cares, the keyswitch) are read on their
+    /// own. If those can't be read either, the policy is taken as tripped:
+    /// better to close the shutters needlessly than to leave them open on a
+    /// laser that can't say whether it's faulted. Returns the faults, or the
+    /// first error reading them or sending a command.
+    pub fn enforce<L>(&self, laser : &mut L) -> Result<FaultReport, CoherentError>
+    where L : Laser<CommandEnum = DiscoveryNXCommands, LaserStatus = DiscoveryNXStatus> {
+        let (report, reason) = match laser.status() {
+            Ok(status) => {
+                let reason = self.trip_reason(status.faults, status.keyswitch);
+                (Ok(FaultReport{code : status.faults.bits(), text : status.fault_text.unwrap_or_default()}), reason)
+            },
+            Err(e) => {
+                let report = laser.fault_report();
+                let keyswitch = if self.keyswitch_off {
+                    laser.query(DiscoveryNXQueries::Keyswitch{}).map(Some)
+                } else {
+                    Ok(None)
+                };
+                let reason = match (&report, keyswitch) {
+                    (Ok(report), Ok(keyswitch)) => self.trip_reason(
+                        FaultFlags::from_bits(report.code), keyswitch.unwrap_or(Keyswitch::On)
+                    ),
+                    _ => Some(format!("laser state unreadable: {:?}", e)),
+                };
+                (report, reason)
+            },
+        };
+        if reason.is_none() { return report; }
+
+        let held = laser.operator_lock().map(std::mem::take);
+        let mut result = Ok(());
+        for action in self.actions() {
+            #[cfg(feature = "tracing")]
+            let text = crate::laser::LaserCommand::to_string(&action);
+            let sent = laser.force_command(action);
+            #[cfg(feature = "tracing")]
+            match &sent {
+                Ok(()) => ::tracing::warn!(reason = %reason.as_deref().unwrap_or_default(), command = %text, "safety policy sent a command"),
+                Err(e) => ::tracing::error!(reason = %reason.as_deref().unwrap_or_default(), command = %text, error = ?e, "safety policy could not send a command"),
+            }
+            result = result.and(sent);
+        }
+        if let (Some(held), Some(lock)) = (held, laser.operator_lock()) {
+            *lock = held;
+        }
+        result.and(report)
+    }
+}
+
+impl FaultMonitor {
+    /// Like `start`, but reads the whole status rather than the fault
+    /// report, and enforces `policy` on each read (see
+    /// `SafetyPolicy::enforce`): while it's tripped, both shutters are
+    /// closed (and the laser put in standby, if the policy says so). A read
+    /// whose safety commands fail is skipped, so the next read tries again.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use coherent_rs::laser::{debug::DebugLaser, shared::SharedLaser, fault_monitor::FaultMonitor};
+    /// use coherent_rs::laser::{safety::SafetyPolicy, discoverynx::faults::FaultFlags};
+    ///
+    /// let laser = SharedLaser::new(DebugLaser::default());
+    /// let policy = SafetyPolicy::faults(FaultFlags::INTERLOCK | FaultFlags::CHILLER)
+    ///     .on_keyswitch_off()
+    ///     .with_standby();
+    /// let monitor = FaultMonitor::start_with_safety(laser, Duration::from_secs(1), policy).unwrap();
+    /// monitor.stop();
+    /// ```
+    pub fn start_with_safety<L>(laser : SharedLaser<L>, interval : Duration, policy : SafetyPolicy) -> Result<Self, CoherentError>
+    where L : Laser<CommandEnum = DiscoveryNXCommands, LaserStatus = DiscoveryNXStatus> + 'static {
+        FaultMonitor::spawn(laser, interval, move |laser| policy.enforce(laser))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use crate::Discovery;
+    use crate::laser::{CommonCommand, debug::DebugLaser};
+    use crate::laser::mock::{MockSerialPort, format_reply};
+
+    fn wait_for(condition : impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !condition() {
+            if Instant::now() > deadline { return false; }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        true
+    }
+
+    fn open_both(laser : &mut DebugLaser) {
+        for laser_line in [DiscoveryLaser::VariableWavelength, DiscoveryLaser::FixedWavelength] {
+            laser.send_command(DiscoveryNXCommands::Shutter{laser : laser_line, state : ShutterState::Open}).unwrap();
+        }
+    }
+
+    /// A Discovery on a port that plays back `exchanges`, with echo and the
+    /// prompt off.
+    fn mock_discovery(exchanges : &[(&str, &str)]) -> (Discovery, MockSerialPort) {
+        let port = exchanges.iter().fold(
+            MockSerialPort::discovery(false, false, "SN1234"),
+            |port, (command, reply)| port.expect(command, &format_reply(command, reply, false, false))
+        );
+        (Discovery::from_serial_port(Box::new(port.clone())).unwrap(), port)
+    }
+
+    fn shutters_closed(laser : &SharedLaser<DebugLaser>) -> bool {
+        laser.status().map(|status| status.variable_shutter == ShutterState::Closed
+            && status.fixed_shutter == ShutterState::Closed).unwrap_or(false)
+    }
+
+    #[test]
+    fn test_safety_policy_actions() {
+        let mut laser = DebugLaser::default();
+        laser.send_common_command(CommonCommand::On).unwrap();
+        open_both(&mut laser);
+        let mut status = laser.status().unwrap();
+        let policy = SafetyPolicy::faults(FaultFlags::INTERLOCK);
+        assert!(!policy.is_tripped(&status));
+
+        // Only the faults it names trip it
+        status.faults = FaultFlags::CHILLER;
+        assert!(!policy.is_tripped(&status));
+        status.faults = FaultFlags::CHILLER | FaultFlags::INTERLOCK;
+        assert!(policy.is_tripped(&status));
+
+        // The keyswitch only if asked
+        status.faults = FaultFlags::empty();
+        status.keyswitch = Keyswitch::Off;
+        assert!(!policy.is_tripped(&status));
+        assert!(policy.clone().on_keyswitch_off().is_tripped(&status));
+
+        // Both shutters, whatever they're said to be doing
+        let close_both = vec![
+            DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::VariableWavelength, state : ShutterState::Closed},
+            DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::FixedWavelength, state : ShutterState::Closed},
+        ];
+        assert_eq!(policy.actions(), close_both);
+        let actions = policy.with_standby().actions();
+        assert_eq!(actions[..2], close_both[..]);
+        assert_eq!(actions[2..], [DiscoveryNXCommands::Laser{state : LaserState::Standby}]);
+    }
+
+    #[test]
+    fn test_safety_without_status() {
+        let interlock = FaultFlags::INTERLOCK.bits().to_string();
+
+        // The status fails part way, but the faults read on their own trip it
+        let (mut discovery, port) = mock_discovery(&[
+            ("?E", "0"), ("?L", "COMMAND NOT EXECUTED"),
+            ("?F", &interlock), ("?FT", "Interlock open"), ("?K", "1"),
+            ("S=0", ""), ("SFIXED=0", ""),
+        ]);
+        let report = SafetyPolicy::default().enforce(&mut discovery).unwrap();
+        assert_eq!(report.code, FaultFlags::INTERLOCK.bits());
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+
+        // Nothing tripped, nothing sent
+        let (mut discovery, port) = mock_discovery(&[("?E", "0"), ("?L", "COMMAND NOT EXECUTED"), ("?F", "0"), ("?FT", "System OK"), ("?K", "1")]);
+        assert_eq!(SafetyPolicy::default().enforce(&mut discovery).unwrap().code, 0);
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+
+        // And if the faults can't be read either, it's taken as tripped
+        let (mut discovery, port) = mock_discovery(&[
+            ("?E", "0"), ("?L", "COMMAND NOT EXECUTED"), ("?F", "COMMAND NOT EXECUTED"),
+            ("S=0", ""), ("SFIXED=0", ""),
+        ]);
+        assert!(SafetyPolicy::faults(FaultFlags::INTERLOCK).enforce(&mut discovery).is_err());
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+    }
+
+    #[test]
+    fn test_safety_ignores_skip_unchanged() {
+        use crate::laser::discoverynx::SkipUnchanged;
+
+        // Both shutters read as closed, which the status leaves as the last
+        // known state -- but they're closed again all the same
+        let interlock = FaultFlags::INTERLOCK.bits().to_string();
+        let (mut discovery, port) = mock_discovery(&[
+            ("?E", "0"), ("?L", "1"), ("?S", "0"), ("?SFIXED", "0"), ("?K", "1"), ("?F", &interlock), ("?FT", "Interlock open"),
+            ("?TS", "0"), ("?ALIGNVAR", "0"), ("?ALIGNFIXED", "0"), ("?ST", "OK"), ("?WV", "920"),
+            ("?PVAR", "1250.5"), ("?PFIXED", "800"), ("?GDDCURVE", "2"), ("?GDDCURVEN", "Objective A"), ("?GDD", "-1500"),
+            ("S=0", ""), ("SFIXED=0", ""),
+        ]);
+        discovery.skip_unchanged = SkipUnchanged::LastKnown;
+        SafetyPolicy::default().enforce(&mut discovery).unwrap();
+        assert!(port.is_finished(), "{:?}", port.unexpected());
+        assert!(port.written().ends_with(&["S=0".to_string(), "SFIXED=0".to_string()]));
+    }
+
+    #[test]
+    fn test_safety_monitor() {
+        let mut debug = DebugLaser::default();
+        open_both(&mut debug);
+        debug.lock("rig-2").unwrap();
+        let laser = SharedLaser::new(debug);
+        let monitor = FaultMonitor::start_with_safety(laser.clone(), Duration::from_millis(20), SafetyPolicy::default()).unwrap();
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!shutters_closed(&laser));
+
+        // Closed despite the operator lock, which is still held after
+        laser.with(|laser| laser.inject_fault(FaultFlags::INTERLOCK.bits(), "Interlock open")).unwrap();
+        assert!(wait_for(|| shutters_closed(&laser)));
+        assert!(laser.with(|laser| laser.is_locked()).unwrap());
+        assert_eq!(monitor.faults(), FaultFlags::INTERLOCK);
+
+        // And again if it's opened while the fault's still there
+        laser.with(|laser| { laser.unlock("rig-2").unwrap(); open_both(laser); }).unwrap();
+        assert!(wait_for(|| shutters_closed(&laser)));
+
+        // The keyswitch
+        laser.with(|laser| { laser.clear_faults().unwrap(); open_both(laser); }).unwrap();
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!shutters_closed(&laser));
+        laser.with(|laser| laser.set_keyswitch(Keyswitch::Off)).unwrap();
+        assert!(wait_for(|| shutters_closed(&laser)));
+        monitor.stop();
+    }
+}
@@ -0,0 +1,222 @@
+//! sampling.rs
+//!
+//! Readings taken on an external trigger -- typically once per microscope
+//! frame -- rather than on a polling clock, so laser power and wavelength can
+//! be lined up with each imaging frame. The acquisition software calls
+//! `FrameSampler::trigger` from its frame callback; the sampler reads the
+//! laser straight away, timestamps the reading, and buffers it until the
+//! software collects the lot with `drain`.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::CoherentError;
+use crate::laser::{Laser, unix_timestamp, shared::SharedLaser, debug::DebugLaser, discoverynx::{Discovery, DiscoveryLaser}};
+
+/// What a `FrameSampler` reads for each trigger.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BeamReading {
+    pub power_mw : f32,
+    pub wavelength_nm : f32,
+}
+
+/// One trigger's reading.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameSample {
+    /// The index the trigger was given, e.g. the frame number
+    pub frame : u64,
+    /// When `trigger` was called, in seconds since the Unix epoch
+    pub triggered : f64,
+    /// When the reading came back, in seconds since the Unix epoch. Minus
+    /// `triggered`, how long the frame waited on the laser.
+    pub sampled : f64,
+    pub reading : BeamReading,
+}
+
+/// Reads the laser for one trigger.
+pub type ReadBeam<L> = Box<dyn FnMut(&mut L) -> Result<BeamReading, CoherentError> + Send>;
+
+struct Buffer {
+    samples : VecDeque<FrameSample>,
+    capacity : usize,
+    /// Samples pushed out of a full buffer before they were drained
+    overwritten : u64,
+    /// Triggers whose reading failed
+    failed : u64,
+}
+
+/// Takes a `BeamReading` whenever it's triggered. Clones share the laser and
+/// the buffer, so one can go to the acquisition callback and another to
+/// whatever collects the samples. Once `capacity` samples are waiting, each
+/// new one pushes out the oldest.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::{debug::DebugLaser, shared::SharedLaser, sampling::FrameSampler};
+///
+/// let laser = SharedLaser::new(DebugLaser::default());
+/// let sampler = FrameSampler::<DebugLaser>::primary_beam(laser, 1000);
+///
+/// // e.g. from the microscope's frame-done callback
+/// let on_frame = sampler.clone();
+/// for frame in 0..3 {
+///     on_frame.trigger(frame).unwrap();
+/// }
+///
+/// let samples = sampler.drain();
+/// assert_eq!(samples.iter().map(|sample| sample.frame).collect::<Vec<_>>(), vec![0, 1, 2]);
+/// assert_eq!(samples[0].reading.wavelength_nm, 920.0);
+/// ```
+pub struct FrameSampler<L : Laser> {
+    laser : SharedLaser<L>,
+    read : Arc<Mutex<ReadBeam<L>>>,
+    buffer : Arc<Mutex<Buffer>>,
+}
+
+impl<L : Laser> Clone for FrameSampler<L> {
+    fn clone(&self) -> Self {
+        FrameSampler{
+            laser : self.laser.clone(),
+            read : Arc::clone(&self.read),
+            buffer : Arc::clone(&self.buffer),
+        }
+    }
+}
+
+impl<L : Laser> FrameSampler<L> {
+    /// Samples `laser` with `read`, keeping up to `capacity` samples.
+    pub fn new<F>(laser : SharedLaser<L>, capacity : usize, read : F) -> Self
+    where F : FnMut(&mut L) -> Result<BeamReading, CoherentError> + Send + 'static {
+        FrameSampler{
+            laser,
+            read : Arc::new(Mutex::new(Box::new(read))),
+            buffer : Arc::new(Mutex::new(Buffer{
+                samples : VecDeque::with_capacity(capacity.min(4096)),
+                capacity : capacity.max(1),
+                overwritten : 0,
+                failed : 0,
+            })),
+        }
+    }
+
+    /// Reads the laser now, buffers the sample, and returns it. Waits for the
+    /// laser if another thread has it. A failed reading isn't buffered, only
+    /// counted.
+    pub fn trigger(&self, frame : u64) -> Result<FrameSample, CoherentError> {
+        let triggered = unix_timestamp();
+        let reading = {
+            let mut read = self.read.lock().map_err(|_| CoherentError::LaserUnavailableError)?;
+            self.laser.with(|laser| read(laser)).and_then(|reading| reading)
+        };
+        let reading = match reading {
+            Ok(reading) => reading,
+            Err(e) => {
+                if let Ok(mut buffer) = self.buffer.lock() { buffer.failed += 1; }
+                return Err(e);
+            },
+        };
+        let sample = FrameSample{frame, triggered, sampled : unix_timestamp(), reading};
+
+        let mut buffer = self.buffer.lock().map_err(|_| CoherentError::LaserUnavailableError)?;
+        if buffer.samples.len() >= buffer.capacity {
+            buffer.samples.pop_front();
+            buffer.overwritten += 1;
+        }
+        buffer.samples.push_back(sample);
+        Ok(sample)
+    }
+
+    /// A callback for acquisition software that wants a plain
+    /// `FnMut(frame)`. Failures are counted in `failed`.
+    pub fn callback(&self) -> impl FnMut(u64) + Send + 'static where L : 'static {
+        let sampler = self.clone();
+        move |frame| { let _ = sampler.trigger(frame); }
+    }
+
+    /// Takes every buffered sample, oldest first.
+    pub fn drain(&self) -> Vec<FrameSample> {
+        self.buffer.lock().map(|mut buffer| buffer.samples.drain(..).collect()).unwrap_or_default()
+    }
+
+    /// The most recent sample, left in the buffer.
+    pub fn latest(&self) -> Option<FrameSample> {
+        self.buffer.lock().ok()?.samples.back().copied()
+    }
+
+    /// Samples waiting to be drained.
+    pub fn len(&self) -> usize {
+        self.buffer.lock().map(|buffer| buffer.samples.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Samples lost to a full buffer.
+    pub fn overwritten(&self) -> u64 {
+        self.buffer.lock().map(|buffer| buffer.overwritten).unwrap_or(0)
+    }
+
+    /// Triggers whose reading failed.
+    pub fn failed(&self) -> u64 {
+        self.buffer.lock().map(|buffer| buffer.failed).unwrap_or(0)
+    }
+}
+
+impl FrameSampler<Discovery> {
+    /// Samples the power and wavelength of the tunable beam.
+    pub fn primary_beam(laser : SharedLaser<Discovery>, capacity : usize) -> Self {
+        FrameSampler::new(laser, capacity, |laser : &mut Discovery| Ok(BeamReading{
+            power_mw : laser.get_power(DiscoveryLaser::VariableWavelength)?,
+            wavelength_nm : laser.get_wavelength()?,
+        }))
+    }
+}
+
+impl FrameSampler<DebugLaser> {
+    /// Samples the power and wavelength of the tunable beam.
+    pub fn primary_beam(laser : SharedLaser<DebugLaser>, capacity : usize) -> Self {
+        FrameSampler::new(laser, capacity, |laser : &mut DebugLaser| Ok(BeamReading{
+            power_mw : laser.get_power(DiscoveryLaser::VariableWavelength)?,
+            wavelength_nm : laser.get_wavelength()?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::discoverynx::DiscoveryNXCommands;
+    use crate::laser::units::Nanometers;
+
+    #[test]
+    fn test_sampling() {
+        let laser = SharedLaser::new(DebugLaser::default());
+        let sampler = FrameSampler::<DebugLaser>::primary_beam(laser.clone(), 3);
+        assert!(sampler.is_empty() && sampler.latest().is_none());
+
+        let mut on_frame = sampler.callback();
+        let acquisition = std::thread::spawn(move || {
+            for frame in 0..5 { on_frame(frame); }
+        });
+        acquisition.join().unwrap();
+        assert_eq!(sampler.len(), 3);
+        assert_eq!(sampler.overwritten(), 2);
+        assert_eq!(sampler.latest().unwrap().frame, 4);
+
+        // Each sample sees the laser as it was at its trigger
+        laser.send_command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)}).unwrap();
+        let sample = sampler.trigger(5).unwrap();
+        assert_eq!(sample.reading.wavelength_nm, 800.0);
+        assert!(sample.triggered <= sample.sampled);
+        let samples = sampler.drain();
+        assert_eq!(samples.iter().map(|sample| sample.frame).collect::<Vec<_>>(), vec![3, 4, 5]);
+        assert_eq!(samples[0].reading.wavelength_nm, 920.0);
+        assert!(sampler.is_empty());
+
+        let failing = FrameSampler::new(laser, 3, |_| Err(CoherentError::TimeoutError));
+        assert!(failing.trigger(0).is_err());
+        assert_eq!((failing.failed(), failing.len()), (1, 0));
+    }
+}
@@ -0,0 +1,214 @@
+//! schema.rs
+//!
+//! The column layout of each laser's status -- names, types, units -- for
+//! logging and database tooling that creates its tables up front. Generated
+//! from the status structs themselves, so it stays in sync as fields are
+//! added. `status_to_json` exports a status with each value's unit beside it.
+
+use crate::laser::{LaserType, StatusValue};
+use crate::laser::discoverynx::{DiscoveryNXStatus, StatusField};
+use crate::laser::units::Unit;
+
+/// The type of a status column, matching the `StatusValue` it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Bool,
+    Integer,
+    Float,
+    Text,
+}
+
+impl ColumnType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColumnType::Bool => "bool",
+            ColumnType::Integer => "integer",
+            ColumnType::Float => "float",
+            ColumnType::Text => "text",
+        }
+    }
+}
+
+/// One column of a status schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Column {
+    /// The field's name, as in the status struct and a `StatusMap`
+    pub name : &'static str,
+    pub kind : ColumnType,
+    /// `None` for unitless fields.
+    pub unit : Option<Unit>,
+    /// Whether the value can be `StatusValue::Missing`
+    pub nullable : bool,
+}
+
+/// The columns of a laser's status, in the order they're declared.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::{LaserType, schema::{status_schema, ColumnType}, units::Unit};
+///
+/// let schema = status_schema(&LaserType::DiscoveryNX).unwrap();
+/// let wavelength = schema.column("wavelength").unwrap();
+/// assert_eq!((wavelength.kind, wavelength.unit), (ColumnType::Float, Some(Unit::Nanometers)));
+/// println!("{}", schema.to_json());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusSchema {
+    pub columns : Vec<Column>,
+}
+
+impl StatusSchema {
+    pub fn column(&self, name : &str) -> Option<&Column> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+
+    /// The column names, comma-separated: a header row for a CSV log.
+    pub fn csv_header(&self) -> String {
+        self.columns.iter().map(|column| column.name).collect::<Vec<_>>().join(",")
+    }
+
+    /// The schema itself as CSV, one column per row:
+    /// `name,type,unit,nullable`.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("name,type,unit,nullable\n");
+        for column in &self.columns {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                column.name, column.kind.name(), column.unit.map_or("", |unit| unit.symbol()), column.nullable
+            ));
+        }
+        csv
+    }
+
+    /// The schema as a JSON array of
+    /// `{"name", "type", "unit" (or null), "nullable"}` objects.
+    pub fn to_json(&self) -> String {
+        let columns = self.columns.iter().map(|column| format!(
+            "{{\"name\":\"{}\",\"type\":\"{}\",\"unit\":{},\"nullable\":{}}}",
+            column.name,
+            column.kind.name(),
+            column.unit.map_or("null".to_string(), |unit| format!("\"{}\"", unit.symbol())),
+            column.nullable,
+        )).collect::<Vec<_>>();
+        format!("[{}]", columns.join(","))
+    }
+}
+
+/// The status schema of `laser_type`, or `None` for an unrecognized device.
+pub fn status_schema(laser_type : &LaserType) -> Option<StatusSchema> {
+    match laser_type {
+        // The debug laser reports a `DiscoveryNXStatus` too
+        LaserType::DiscoveryNX | LaserType::DebugLaser => Some(StatusSchema{
+            columns : StatusField::ALL.iter().map(StatusField::column).collect(),
+        }),
+        LaserType::UnrecognizedDevice => None,
+    }
+}
+
+/// `status` as a JSON object of field name to `{"value", "unit"}`, with the
+/// unit left out for unitless fields and a missing value as `null`, e.g.
+/// `{"echo":{"value":false},...,"wavelength":{"value":920,"unit":"nm"},...}`.
+pub fn status_to_json(status : &DiscoveryNXStatus) -> String {
+    let fields = status.fields().map(|(field, value)| {
+        let unit = field.unit().map_or(String::new(), |unit| format!(",\"unit\":\"{}\"", unit.symbol()));
+        format!("\"{}\":{{\"value\":{}{}}}", field.name(), json_value(&value), unit)
+    }).collect::<Vec<_>>();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn json_value(value : &StatusValue) -> String {
+    match value {
+        StatusValue::Bool(b) => b.to_string(),
+        StatusValue::Integer(i) => i.to_string(),
+        StatusValue::Float(x) if x.is_finite() => x.to_string(),
+        StatusValue::Float(_) | StatusValue::Missing => "null".to_string(),
+        StatusValue::Text(text) => json_string(text),
+    }
+}
+
+fn json_string(text : &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats() {
+        let schema = StatusSchema{columns : vec![
+            Column{name : "wavelength", kind : ColumnType::Float, unit : Some(Unit::Nanometers), nullable : false},
+            Column{name : "calibrated_power_var", kind : ColumnType::Float, unit : Some(Unit::Milliwatts), nullable : true},
+            Column{name : "echo", kind : ColumnType::Bool, unit : None, nullable : false},
+        ]};
+        assert_eq!(schema.csv_header(), "wavelength,calibrated_power_var,echo");
+        assert_eq!(
+            schema.to_csv(),
+            "name,type,unit,nullable\nwavelength,float,nm,false\ncalibrated_power_var,float,mW,true\necho,bool,,false\n"
+        );
+        assert_eq!(
+            schema.to_json(),
+            "[{\"name\":\"wavelength\",\"type\":\"float\",\"unit\":\"nm\",\"nullable\":false},\
+            {\"name\":\"calibrated_power_var\",\"type\":\"float\",\"unit\":\"mW\",\"nullable\":true},\
+            {\"name\":\"echo\",\"type\":\"bool\",\"unit\":null,\"nullable\":false}]"
+        );
+    }
+
+    #[test]
+    fn test_discovery_schema() {
+        let schema = status_schema(&LaserType::DiscoveryNX).unwrap();
+        assert_eq!(schema.columns.len(), StatusField::ALL.len());
+        assert_eq!(schema.columns[0].name, "echo");
+        assert!(schema.column("calibrated_power_fixed").unwrap().nullable);
+        assert_eq!(schema.column("gdd").unwrap().unit, Some(Unit::FemtosecondsSquared));
+        assert!(status_schema(&LaserType::UnrecognizedDevice).is_none());
+    }
+
+    #[cfg(feature = "network")]
+    #[test]
+    fn test_schema_matches_status() {
+        use crate::laser::{Laser, debug::DebugLaser};
+
+        let status = DebugLaser::default().status().unwrap();
+        let schema = status_schema(&LaserType::DebugLaser).unwrap();
+        let map = crate::network::to_status_map(&status).unwrap();
+        assert_eq!(schema.columns.len(), map.len());
+        for column in schema.columns {
+            let kind = match &map[column.name] {
+                StatusValue::Bool(_) => ColumnType::Bool,
+                StatusValue::Integer(_) => ColumnType::Integer,
+                StatusValue::Float(_) => ColumnType::Float,
+                StatusValue::Text(_) => ColumnType::Text,
+                StatusValue::Missing => { assert!(column.nullable, "{}", column.name); continue; },
+            };
+            assert_eq!(kind, column.kind, "{}", column.name);
+        }
+    }
+
+    #[test]
+    fn test_status_to_json() {
+        use crate::laser::{Laser, debug::DebugLaser};
+
+        let mut status = DebugLaser::default().status().unwrap();
+        status.fault_text = Some("Say \"hi\"\n".to_string());
+        let json = status_to_json(&status);
+        assert!(json.starts_with("{\"echo\":{\"value\":"), "{}", json);
+        assert!(json.contains("\"wavelength\":{\"value\":920,\"unit\":\"nm\"}"), "{}", json);
+        assert!(json.contains("\"calibrated_power_var\":{\"value\":null,\"unit\":\"mW\"}"), "{}", json);
+        assert!(json.contains("\"fault_text\":{\"value\":\"Say \\\"hi\\\"\\n\"}"), "{}", json);
+    }
+}
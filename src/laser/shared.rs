@@ -0,0 +1,193 @@
+//! shared.rs
+//!
+//! `SharedLaser`: one laser shared between threads -- a network server, a
+//! GUI, a logger -- behind a single `Mutex`, so every consumer locks it the
+//! same way. Each method takes the lock for exactly one call and releases it
+//! before returning; the `try_` variants fail with `LaserBusyError` instead
+//! of waiting when another thread has it.
+
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+
+use crate::CoherentError;
+use crate::laser::{Laser, Query, retry::{RetryPolicy, ErrorClass}};
+
+/// A laser that can be cloned and handed to other threads. Clones all refer
+/// to the same laser.
+///
+/// A lock poisoned by a thread that panicked while holding it is reported as
+/// `LaserUnavailableError`: whatever that thread was doing to the laser may
+/// only have half happened.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::{Laser, debug::DebugLaser, shared::SharedLaser};
+/// use coherent_rs::laser::{Nanometers, discoverynx::DiscoveryNXCommands};
+///
+/// let laser = SharedLaser::new(DebugLaser::default());
+/// let gui = laser.clone();
+/// std::thread::spawn(move || {
+///     gui.send_command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(850.0)}).unwrap();
+/// }).join().unwrap();
+/// assert_eq!(laser.with(|laser| laser.get_wavelength()).unwrap().unwrap(), 850.0);
+/// ```
+pub struct SharedLaser<L : Laser> {
+    laser : Arc<Mutex<L>>,
+}
+
+impl<L : Laser> Clone for SharedLaser<L> {
+    fn clone(&self) -> Self {
+        SharedLaser{laser : Arc::clone(&self.laser)}
+    }
+}
+
+impl<L : Laser> From<L> for SharedLaser<L> {
+    fn from(laser : L) -> Self {
+        SharedLaser::new(laser)
+    }
+}
+
+impl<L : Laser> SharedLaser<L> {
+    pub fn new(laser : L) -> Self {
+        SharedLaser{laser : Arc::new(Mutex::new(laser))}
+    }
+
+    /// Waits for exclusive access to the laser. Prefer `with`, which can't
+    /// hold the lock longer than it needs to.
+    pub fn lock(&self) -> Result<MutexGuard<'_, L>, CoherentError> {
+        self.laser.lock().map_err(|_| CoherentError::LaserUnavailableError)
+    }
+
+    /// Exclusive access to the laser if no other thread has it, otherwise
+    /// `LaserBusyError`.
+    pub fn try_lock(&self) -> Result<MutexGuard<'_, L>, CoherentError> {
+        self.laser.try_lock().map_err(|e| match e {
+            TryLockError::WouldBlock => CoherentError::LaserBusyError,
+            TryLockError::Poisoned(_) => CoherentError::LaserUnavailableError,
+        })
+    }
+
+    /// `try_lock`, tried again as long as `policy` allows while the laser is
+    /// busy -- for threads that mustn't block indefinitely on it, but can
+    /// afford to wait a little. Other errors aren't retried.
+    pub fn try_lock_with(&self, policy : &RetryPolicy) -> Result<MutexGuard<'_, L>, CoherentError> {
+        let policy = policy.clone().retry_on(&[ErrorClass::Busy]);
+        policy.run(|| self.try_lock())
+    }
+
+    /// Runs `f` with exclusive access to the laser. `f` must not use this
+    /// `SharedLaser` (or a clone of it) itself -- that would deadlock.
+    pub fn with<R>(&self, f : impl FnOnce(&mut L) -> R) -> Result<R, CoherentError> {
+        let mut laser = self.lock()?;
+        Ok(f(&mut laser))
+    }
+
+    /// Like `with`, but `LaserBusyError` if another thread has the laser.
+    pub fn try_with<R>(&self, f : impl FnOnce(&mut L) -> R) -> Result<R, CoherentError> {
+        let mut laser = self.try_lock()?;
+        Ok(f(&mut laser))
+    }
+
+    pub fn send_command(&self, command : L::CommandEnum) -> Result<(), CoherentError> {
+        self.lock()?.send_command(command)
+    }
+
+    pub fn try_send_command(&self, command : L::CommandEnum) -> Result<(), CoherentError> {
+        self.try_lock()?.send_command(command)
+    }
+
+    pub fn query<Q : Query>(&self, query : Q) -> Result<Q::Result, CoherentError> {
+        self.lock()?.query(query)
+    }
+
+    pub fn try_query<Q : Query>(&self, query : Q) -> Result<Q::Result, CoherentError> {
+        self.try_lock()?.query(query)
+    }
+
+    pub fn status(&self) -> Result<L::LaserStatus, CoherentError> {
+        self.lock()?.status()
+    }
+
+    pub fn try_status(&self) -> Result<L::LaserStatus, CoherentError> {
+        self.try_lock()?.status()
+    }
+
+    /// How many `SharedLaser`s (this one included) refer to the laser.
+    pub fn handles(&self) -> usize {
+        Arc::strong_count(&self.laser)
+    }
+
+    /// The laser back, if this is the last handle to it. Otherwise returns
+    /// `self` unchanged.
+    pub fn into_inner(self) -> Result<L, Self> {
+        match Arc::try_unwrap(self.laser) {
+            Ok(mutex) => Ok(mutex.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner())),
+            Err(laser) => Err(SharedLaser{laser}),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::debug::DebugLaser;
+    use crate::laser::discoverynx::DiscoveryNXCommands;
+    use crate::laser::units::Nanometers;
+
+    #[test]
+    fn test_shared() {
+        let laser = SharedLaser::new(DebugLaser::default());
+        let other = laser.clone();
+        assert_eq!(laser.handles(), 2);
+
+        other.send_command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)}).unwrap();
+        assert_eq!(laser.status().unwrap().wavelength, 800.0);
+
+        let laser = laser.into_inner().unwrap_err();
+        drop(other);
+        assert_eq!(laser.into_inner().ok().unwrap().get_wavelength().unwrap(), 800.0);
+    }
+
+    #[test]
+    fn test_try_while_busy() {
+        let laser = SharedLaser::new(DebugLaser::default());
+        let other = laser.clone();
+        let guard = laser.lock().unwrap();
+        assert!(matches!(other.try_status(), Err(CoherentError::LaserBusyError)));
+        assert!(matches!(
+            other.try_send_command(DiscoveryNXCommands::FaultClear),
+            Err(CoherentError::LaserBusyError)
+        ));
+        drop(guard);
+        assert!(other.try_status().is_ok());
+    }
+
+    #[test]
+    fn test_try_lock_with() {
+        use std::time::Duration;
+
+        let laser = SharedLaser::new(DebugLaser::default());
+        let policy = RetryPolicy::new(50).with_backoff(Duration::from_millis(5), 1.0);
+        let guard = laser.lock().unwrap();
+        let other = laser.clone();
+        let waiter = std::thread::spawn(move || other.try_lock_with(&policy).map(|_| ()));
+        std::thread::sleep(Duration::from_millis(30));
+        drop(guard);
+        assert!(waiter.join().unwrap().is_ok());
+
+        let _guard = laser.lock().unwrap();
+        let impatient = RetryPolicy::new(3).with_backoff(Duration::from_millis(1), 1.0);
+        assert!(matches!(laser.try_lock_with(&impatient), Err(CoherentError::LaserBusyError)));
+    }
+
+    #[test]
+    fn test_poisoned() {
+        let laser = SharedLaser::new(DebugLaser::default());
+        let other = laser.clone();
+        let _ = std::thread::spawn(move || {
+            other.with(|_| panic!("dropped the laser")).unwrap();
+        }).join();
+        assert!(matches!(laser.status(), Err(CoherentError::LaserUnavailableError)));
+        assert!(matches!(laser.try_status(), Err(CoherentError::LaserUnavailableError)));
+    }
+}
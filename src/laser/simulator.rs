@@ -0,0 +1,520 @@
+//! simulator.rs
+//!
+//! Speaks the Discovery NX's serial dialect from the laser's side of the
+//! cable, so the real `Discovery` struct can be driven end-to-end over a
+//! pseudo-terminal (or a com0com pair on Windows) without hardware. Or,
+//! with no terminal at all, over a `SimulatedPort` -- an in-memory
+//! `SerialPort` that hands each line straight to the simulator, which is
+//! what `Discovery::simulated` opens.
+
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard, atomic::{AtomicBool, Ordering}};
+use std::time::Duration;
+
+use serialport::{SerialPort, ClearBuffer, DataBits, FlowControl, Parity, StopBits};
+
+use crate::CoherentError;
+use crate::laser::{Laser, Discovery, DiscoveryNXCommands, DiscoveryLaser, LaserState, ShutterState, Nanometers, GddFs2, debug::DebugLaser};
+use crate::laser::mock::format_reply;
+
+const NOT_EXECUTED : &str = "COMMAND NOT EXECUTED";
+
+/// The laser half of a serial conversation with a Discovery NX. State is
+/// kept in a `DebugLaser`; this struct handles the wire format -- echo,
+/// prompt, and the reply to each command or query.
+#[derive(Debug)]
+pub struct DiscoverySimulator {
+    laser : DebugLaser,
+    serial_number : String,
+    echo : bool,
+    prompt : bool,
+}
+
+impl Default for DiscoverySimulator {
+    fn default() -> Self {
+        DiscoverySimulator::new(true, false, "SIM0001")
+    }
+}
+
+impl DiscoverySimulator {
+    /// A simulated laser that starts with the given echo and prompt settings.
+    pub fn new(echo : bool, prompt : bool, serial_number : &str) -> Self {
+        DiscoverySimulator::from_laser(DebugLaser::default(), echo, prompt, serial_number)
+    }
+
+    /// A simulated laser whose state starts as `laser`'s.
+    pub fn from_laser(laser : DebugLaser, echo : bool, prompt : bool, serial_number : &str) -> Self {
+        DiscoverySimulator{
+            laser,
+            serial_number : serial_number.to_string(),
+            echo,
+            prompt,
+        }
+    }
+
+    /// The simulated laser's state, once the conversation is over.
+    pub fn into_laser(self) -> DebugLaser {
+        self.laser
+    }
+
+    /// The simulated laser's state.
+    pub fn laser(&mut self) -> &mut DebugLaser {
+        &mut self.laser
+    }
+
+    /// The bytes the laser sends back after receiving `line`
+    /// (without its trailing `\r\n`).
+    pub fn respond(&mut self, line : &str) -> String {
+        let line = line.trim();
+        let response = if line.starts_with('?') {
+            self.answer_query(line)
+        } else {
+            self.execute(line)
+        };
+        format_reply(line, &response, self.echo, self.prompt)
+    }
+
+    fn answer_query(&mut self, query : &str) -> String {
+        let laser = &mut self.laser;
+        let as_bit = |b : bool| if b {"1".to_string()} else {"0".to_string()};
+        let response = match query {
+            "?E" => Ok(as_bit(self.echo)),
+            "?L" => laser.get_standby().map(|s| as_bit(s == LaserState::On)),
+            "?S" => laser.get_shutter(DiscoveryLaser::VariableWavelength).map(|s| as_bit(s == ShutterState::Open)),
+            "?SFIXED" => laser.get_shutter(DiscoveryLaser::FixedWavelength).map(|s| as_bit(s == ShutterState::Open)),
+            "?K" => laser.get_keyswitch_on().map(as_bit),
+            "?F" => laser.get_faults().map(|f| f.bits().to_string()),
+            "?FT" => laser.get_fault_text(),
+            "?TS" => laser.get_tuning().map(|t| as_bit(t.into())),
+            "?ALIGNVAR" => laser.get_alignment_mode(DiscoveryLaser::VariableWavelength).map(|mode| as_bit(mode.into())),
+            "?ALIGNFIXED" => laser.get_alignment_mode(DiscoveryLaser::FixedWavelength).map(|mode| as_bit(mode.into())),
+            "?ST" => laser.get_status(),
+            "?WV" => laser.get_wavelength().map(|w| w.to_string()),
+            "?PVAR" => laser.get_power(DiscoveryLaser::VariableWavelength).map(|p| p.to_string()),
+            "?PFIXED" => laser.get_power(DiscoveryLaser::FixedWavelength).map(|p| p.to_string()),
+            "?GDDCURVE" => laser.get_gdd_curve().map(|c| c.to_string()),
+            "?GDDCURVEN" => laser.get_gdd_curve_n(),
+            "?GDD" => laser.get_gdd().map(|g| g.to_string()),
+            "?SN" => Ok(self.serial_number.clone()),
+            "?HH" => laser.get_operating_hours().map(|h| h.to_string()),
+            "?BT" => laser.get_baseplate_temperature().map(|t| t.to_string()),
+            "?RH" => laser.get_humidity().map(|h| h.to_string()),
+            "?DC" => laser.get_diode_current().map(|c| c.to_string()),
+            "?HS" => laser.get_heatsink_ok().map(as_bit),
+            _ => return NOT_EXECUTED.to_string(),
+        };
+        response.unwrap_or(NOT_EXECUTED.to_string())
+    }
+
+    fn execute(&mut self, line : &str) -> String {
+        let command = match parse_command(line) {
+            Some(command) => command,
+            None => return NOT_EXECUTED.to_string(),
+        };
+        if let DiscoveryNXCommands::Echo{echo_on} = command {
+            self.echo = echo_on;
+        }
+        match self.laser.send_command(command) {
+            Ok(()) => String::new(),
+            Err(_) => NOT_EXECUTED.to_string(),
+        }
+    }
+
+    /// Answers everything written to `port` until `running` is cleared or the
+    /// other end hangs up. Read timeouts are used to check `running`.
+    pub fn serve(&mut self, port : &mut dyn SerialPort, running : &AtomicBool) -> std::io::Result<()> {
+        let mut pending = Vec::new();
+        let mut buf = [0u8; 256];
+        while running.load(Ordering::Relaxed) {
+            let n = match port.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            };
+            pending.extend_from_slice(&buf[..n]);
+
+            while let Some(end) = pending.windows(2).position(|w| w == b"\r\n") {
+                let line = String::from_utf8_lossy(&pending[..end]).to_string();
+                pending.drain(..end + 2);
+                let reply = self.respond(&line);
+                port.write_all(reply.as_bytes())?;
+                port.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct SimulatedState {
+    pending_write : Vec<u8>,
+    to_read : VecDeque<u8>,
+}
+
+/// A `SerialPort` with a `DiscoverySimulator` on the other end: each
+/// complete line written to it is answered by the simulator, and the reply
+/// is there to be read. Unlike a `MockSerialPort` there's no transcript to
+/// write -- the simulator answers whatever it's asked, as the laser would.
+///
+/// Clones share the same simulator, so keep one around to look at (or
+/// change) the simulated laser after handing the other to a `Discovery`.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::{Discovery, laser::{Laser, simulator::{DiscoverySimulator, SimulatedPort}}};
+///
+/// let port = SimulatedPort::new(DiscoverySimulator::new(false, true, "SIM0002"));
+/// let mut discovery = Discovery::from_serial_port(Box::new(port.clone())).unwrap();
+/// discovery.set_wavelength(940.0).unwrap();
+/// assert_eq!(port.simulator().laser().get_wavelength().unwrap(), 940.0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SimulatedPort {
+    state : Arc<Mutex<SimulatedState>>,
+    simulator : Arc<Mutex<DiscoverySimulator>>,
+    timeout : Duration,
+}
+
+impl SimulatedPort {
+    pub fn new(simulator : DiscoverySimulator) -> Self {
+        SimulatedPort{
+            state : Arc::new(Mutex::new(SimulatedState::default())),
+            simulator : Arc::new(Mutex::new(simulator)),
+            timeout : Duration::from_millis(10),
+        }
+    }
+
+    /// The simulator on the other end. Don't hold on to it while a
+    /// `Discovery` is using the port -- it'll wait for it.
+    pub fn simulator(&self) -> MutexGuard<'_, DiscoverySimulator> {
+        self.simulator.lock().unwrap()
+    }
+}
+
+impl Discovery {
+    /// A `Discovery` talking to a `DiscoverySimulator` in memory instead of
+    /// a laser, through the same parsing and formatting code as a real one
+    /// -- unlike a `DebugLaser`, which skips the serial protocol altogether.
+    /// To get at the simulated laser too, open a `SimulatedPort` with
+    /// `from_serial_port` instead.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use coherent_rs::{Discovery, laser::{Laser, ShutterState, DiscoveryLaser}};
+    ///
+    /// let mut discovery = Discovery::simulated().unwrap();
+    /// assert_eq!(discovery.serial_number, "SIM0001");
+    /// discovery.set_shutter(DiscoveryLaser::FixedWavelength, ShutterState::Open).unwrap();
+    /// assert_eq!(discovery.status().unwrap().fixed_shutter, ShutterState::Open);
+    /// ```
+    pub fn simulated() -> Result<Self, CoherentError> {
+        Discovery::from_serial_port(Box::new(SimulatedPort::new(DiscoverySimulator::default())))
+    }
+}
+
+impl Read for SimulatedPort {
+    fn read(&mut self, buf : &mut [u8]) -> std::io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.to_read.is_empty() {
+            return Err(std::io::Error::new(ErrorKind::TimedOut, "Operation timed out"));
+        }
+        let n = buf.len().min(state.to_read.len());
+        for (slot, byte) in buf.iter_mut().zip(state.to_read.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for SimulatedPort {
+    fn write(&mut self, buf : &[u8]) -> std::io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        state.pending_write.extend_from_slice(buf);
+
+        while let Some(end) = state.pending_write.windows(2).position(|w| w == b"\r\n") {
+            let line = String::from_utf8_lossy(&state.pending_write[..end]).to_string();
+            state.pending_write.drain(..end + 2);
+            let reply = self.simulator.lock().unwrap().respond(&line);
+            state.to_read.extend(reply.into_bytes());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for SimulatedPort {
+    fn name(&self) -> Option<String> {
+        Some("SimulatedPort".to_string())
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(19200)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(DataBits::Eight)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(Parity::None)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(StopBits::One)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, _baud_rate : u32) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, _data_bits : DataBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, _flow_control : FlowControl) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_parity(&mut self, _parity : Parity) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, _stop_bits : StopBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout : Duration) -> serialport::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _level : bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, _level : bool) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(self.state.lock().unwrap().to_read.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear : ClearBuffer) -> serialport::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        match buffer_to_clear {
+            ClearBuffer::Input => state.to_read.clear(),
+            ClearBuffer::Output => state.pending_write.clear(),
+            ClearBuffer::All => {
+                state.to_read.clear();
+                state.pending_write.clear();
+            }
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads a command string back into the `DiscoveryNXCommands` it came from.
+fn parse_command(line : &str) -> Option<DiscoveryNXCommands> {
+    let (name, value) = line.split_once('=').unwrap_or((line, ""));
+    let bit = || match value {
+        "0" => Some(false),
+        "1" => Some(true),
+        _ => None,
+    };
+    Some(match name {
+        "E" => DiscoveryNXCommands::Echo{echo_on : bit()?},
+        "L" => DiscoveryNXCommands::Laser{
+            state : if bit()? {LaserState::On} else {LaserState::Standby}
+        },
+        "S" => DiscoveryNXCommands::Shutter{
+            laser : DiscoveryLaser::VariableWavelength, state : bit()?.into()
+        },
+        "SFIXED" => DiscoveryNXCommands::Shutter{
+            laser : DiscoveryLaser::FixedWavelength, state : bit()?.into()
+        },
+        "FC" => DiscoveryNXCommands::FaultClear,
+        "ALIGN" => DiscoveryNXCommands::AlignmentMode{
+            laser : DiscoveryLaser::VariableWavelength, alignment_mode_on : bit()?.into()
+        },
+        "ALIGNFIXED" => DiscoveryNXCommands::AlignmentMode{
+            laser : DiscoveryLaser::FixedWavelength, alignment_mode_on : bit()?.into()
+        },
+        "WV" => DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(value.parse().ok()?)},
+        "HB" => DiscoveryNXCommands::Heartbeat,
+        "GDDCURVE" => DiscoveryNXCommands::GddCurve{curve_num : value.parse().ok()?},
+        "GDDCURVEN" => DiscoveryNXCommands::GddCurveN{curve_name : value.to_string()},
+        "GDD" => DiscoveryNXCommands::Gdd{gdd_val : GddFs2(value.parse().ok()?)},
+        // The `DebugLaser` only tracks one curve name, so renaming it is the same as selecting it
+        "SETCURVEN" => DiscoveryNXCommands::GddCurveN{curve_name : value.to_string()},
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::{LaserCommand, Discovery, AlignmentMode, mock::MockSerialPort};
+
+    #[test]
+    fn test_parse_command_round_trip() {
+        let commands = [
+            DiscoveryNXCommands::Echo{echo_on : false},
+            DiscoveryNXCommands::Laser{state : LaserState::Standby},
+            DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::FixedWavelength, state : ShutterState::Open},
+            DiscoveryNXCommands::FaultClear,
+            DiscoveryNXCommands::AlignmentMode{laser : DiscoveryLaser::VariableWavelength, alignment_mode_on : AlignmentMode::On},
+            DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(812.5)},
+            DiscoveryNXCommands::Heartbeat,
+            DiscoveryNXCommands::GddCurve{curve_num : 4},
+            DiscoveryNXCommands::GddCurveN{curve_name : "25x".to_string()},
+            DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-2500.0)},
+        ];
+        for command in commands {
+            assert_eq!(parse_command(&command.to_string()), Some(command));
+        }
+        assert_eq!(parse_command("S=2"), None);
+        assert_eq!(parse_command("NOPE"), None);
+    }
+
+    #[test]
+    fn test_respond() {
+        let mut sim = DiscoverySimulator::new(true, false, "SIM0001");
+        assert_eq!(sim.respond("?E"), "?E 1\r\n");
+        assert_eq!(sim.respond("WV=840"), "WV=840 \r\n");
+        assert_eq!(sim.respond("?WV"), "?WV 840\r\n");
+        assert_eq!(sim.respond("WV=5000"), "WV=5000 COMMAND NOT EXECUTED\r\n");
+        assert_eq!(sim.respond("E=0"), "\r\n");
+        assert_eq!(sim.respond("?SN"), "SIM0001\r\n");
+        assert_eq!(sim.respond("GARBAGE"), "COMMAND NOT EXECUTED\r\n");
+    }
+
+    /// Serve a mock port: the simulator should answer the handshake so that
+    /// `Discovery::from_serial_port` succeeds.
+    #[test]
+    fn test_serve_handshake() {
+        for (echo, prompt) in [(false, false), (true, false), (false, true), (true, true)] {
+            let mut sim = DiscoverySimulator::new(echo, prompt, "SIM0001");
+            let mut laser_side = MockSerialPort::new();
+            let handshake = [
+                ("?E", sim.respond("?E")),
+                ("?SN", sim.respond("?SN")),
+            ];
+            for (line, reply) in handshake {
+                laser_side = laser_side.expect(line, &reply);
+            }
+            let discovery = Discovery::from_serial_port(Box::new(laser_side)).unwrap();
+            assert_eq!(discovery.serial_number, "SIM0001");
+        }
+    }
+
+    /// The real `Discovery` talking to the simulator in memory.
+    #[test]
+    fn test_discovery_simulated() {
+        for (echo, prompt) in [(false, false), (true, false), (false, true), (true, true)] {
+            let port = SimulatedPort::new(DiscoverySimulator::new(echo, prompt, "SIM0002"));
+            let mut discovery = Discovery::from_serial_port(Box::new(port.clone())).unwrap();
+            assert_eq!(discovery.serial_number, "SIM0002");
+
+            discovery.set_wavelength(812.5).unwrap();
+            assert_eq!(discovery.get_wavelength().unwrap(), 812.5);
+            assert!(matches!(discovery.set_gdd(50000.0), Err(CoherentError::CommandNotExecutedError)));
+            port.simulator().laser().inject_fault(1, "Interlock open");
+            let status = discovery.status().unwrap();
+            assert_eq!(status.faults.bits(), 1);
+            assert_eq!(status.fault_text.as_deref(), Some("Interlock open"));
+            assert_eq!(port.simulator().laser().get_wavelength().unwrap(), 812.5);
+        }
+
+        let mut discovery = Discovery::simulated().unwrap();
+        discovery.set_shutter(DiscoveryLaser::VariableWavelength, ShutterState::Open).unwrap();
+        assert_eq!(discovery.get_shutter(DiscoveryLaser::VariableWavelength).unwrap(), ShutterState::Open);
+    }
+
+    /// The real `Discovery` talking to the simulator over a pseudo-terminal.
+    #[cfg(unix)]
+    #[test]
+    fn test_discovery_over_pty() {
+        use std::sync::Arc;
+        use serialport::TTYPort;
+
+        let (mut master, slave) = TTYPort::pair().unwrap();
+        master.set_timeout(std::time::Duration::from_millis(50)).unwrap();
+        let slave_name = slave.name().unwrap();
+
+        let running = Arc::new(AtomicBool::new(true));
+        let server_running = running.clone();
+        let server = std::thread::spawn(move || {
+            let mut sim = DiscoverySimulator::new(true, true, "SIM0001");
+            sim.serve(&mut master, &server_running).unwrap();
+            sim
+        });
+
+        let mut discovery = Discovery::from_port_info(&serialport::SerialPortInfo{
+            port_name : slave_name,
+            port_type : serialport::SerialPortType::Unknown,
+        }).unwrap();
+        assert_eq!(discovery.serial_number, "SIM0001");
+
+        discovery.set_wavelength(812.5).unwrap();
+        assert_eq!(discovery.get_wavelength().unwrap(), 812.5);
+        discovery.set_shutter(DiscoveryLaser::FixedWavelength, ShutterState::Open).unwrap();
+        assert_eq!(discovery.get_shutter(DiscoveryLaser::FixedWavelength).unwrap(), ShutterState::Open);
+        assert!(matches!(
+            discovery.set_gdd(50000.0),
+            Err(crate::CoherentError::CommandNotExecutedError)
+        ));
+
+        running.store(false, Ordering::Relaxed);
+        let mut sim = server.join().unwrap();
+        assert_eq!(sim.laser().get_wavelength().unwrap(), 812.5);
+        drop(slave);
+    }
+}
@@ -0,0 +1,114 @@
+//! snapshot.rs
+//!
+//! The settings a laser was last commanded to, saved so a controlling
+//! process can put them back after it crashes or the laser is power cycled.
+//! Take one with `Laser::snapshot`, write it with `save`, and after the
+//! restart read it with `load` and re-apply it with `Laser::restore`. Leave
+//! out any setting that shouldn't come back on its own -- e.g. the shutters,
+//! so a restart never opens one -- with `without`.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::CoherentError;
+use crate::laser::StatusValue;
+use crate::laser::history::{encode_value, decode_value};
+
+/// Settings, named as in the laser's status, and when they were taken.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::{Laser, debug::DebugLaser};
+///
+/// let mut laser = DebugLaser::default();
+/// laser.set_wavelength(850.0).unwrap();
+/// let snapshot = laser.snapshot().unwrap();
+///
+/// let mut restarted = DebugLaser::default();
+/// restarted.restore(&snapshot.without("variable_shutter").without("fixed_shutter")).unwrap();
+/// assert_eq!(restarted.get_wavelength().unwrap(), 850.0);
+/// ```
+#[cfg_attr(feature = "network", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LaserSnapshot {
+    /// Seconds since the Unix epoch
+    pub taken : f64,
+    pub settings : BTreeMap<String, StatusValue>,
+}
+
+impl LaserSnapshot {
+    /// The same snapshot without `setting`, so `restore` leaves it alone.
+    pub fn without(mut self, setting : &str) -> Self {
+        self.settings.remove(setting);
+        self
+    }
+
+    /// Reads a snapshot in the format `to_text` writes: a `taken` line, then
+    /// one line per setting, tab-separated `name value`, with the value
+    /// tagged with its type as in a persisted `ParameterHistory`.
+    pub fn parse(text : &str) -> Result<Self, CoherentError> {
+        let mut snapshot = LaserSnapshot::default();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let invalid = || CoherentError::InvalidArgumentsError(format!("Invalid snapshot line `{}`", line));
+            let (name, value) = line.split_once('\t').ok_or_else(invalid)?;
+            match name {
+                "taken" => snapshot.taken = value.parse().map_err(|_| invalid())?,
+                name => { snapshot.settings.insert(name.to_string(), decode_value(value).ok_or_else(invalid)?); },
+            }
+        }
+        Ok(snapshot)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("taken\t{}\n", self.taken);
+        for (name, value) in &self.settings {
+            text.push_str(&format!("{}\t{}\n", name, encode_value(value)));
+        }
+        text
+    }
+
+    /// Reads and parses the snapshot file at `path` (see `parse`).
+    pub fn load<P : AsRef<Path>>(path : P) -> Result<Self, CoherentError> {
+        crate::laser::config::load(path, "snapshot", LaserSnapshot::parse)
+    }
+
+    /// Writes the snapshot to `path`, by way of a file beside it, so a crash
+    /// part way through leaves the last one whole.
+    pub fn save<P : AsRef<Path>>(&self, path : P) -> Result<(), CoherentError> {
+        let path = path.as_ref();
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        std::fs::write(&partial, self.to_text())
+            .and_then(|_| std::fs::rename(&partial, path))
+            .map_err(|e| CoherentError::InvalidArgumentsError(
+                format!("Could not write snapshot {}: {}", path.display(), e)
+            ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let snapshot = LaserSnapshot{
+            taken : 1760000000.5,
+            settings : BTreeMap::from([
+                ("wavelength".to_string(), StatusValue::Float(920.0)),
+                ("variable_shutter".to_string(), StatusValue::Text("Open".to_string())),
+                ("alignment_var".to_string(), StatusValue::Bool(false)),
+                ("gdd_curve".to_string(), StatusValue::Integer(2)),
+            ]),
+        };
+        assert_eq!(LaserSnapshot::parse(&snapshot.to_text()).unwrap(), snapshot);
+        assert!(!snapshot.clone().without("variable_shutter").settings.contains_key("variable_shutter"));
+        assert!(LaserSnapshot::parse("wavelength\tx:920\n").is_err());
+
+        let path = std::env::temp_dir().join(format!("coherent-snapshot-{}.tsv", std::process::id()));
+        snapshot.save(&path).unwrap();
+        assert_eq!(LaserSnapshot::load(&path).unwrap(), snapshot);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
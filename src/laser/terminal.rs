@@ -0,0 +1,106 @@
+//! terminal.rs
+//!
+//! The prompt a laser's serial terminal puts in front of each line it
+//! sends, if any. Coherent's controllers differ -- a Chameleon-family
+//! controller says `Chameleon>`, some Discovery firmware `DISCOVERY>` -- so
+//! the style is worked out from the first line the laser sends, and every
+//! reply after that goes through the same `strip_reply`.
+
+use crate::CoherentError;
+
+/// How a laser's terminal marks the lines it sends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TerminalStyle {
+    /// No prompt
+    #[default]
+    Plain,
+    /// `Chameleon>`
+    Chameleon,
+    /// `DISCOVERY>`
+    Discovery,
+}
+
+impl TerminalStyle {
+    /// Every style, the prompted ones first.
+    pub const ALL : [TerminalStyle; 3] = [TerminalStyle::Chameleon, TerminalStyle::Discovery, TerminalStyle::Plain];
+
+    /// The prompt, without the space that follows it.
+    pub fn prompt(&self) -> Option<&'static str> {
+        match self {
+            TerminalStyle::Plain => None,
+            TerminalStyle::Chameleon => Some("Chameleon>"),
+            TerminalStyle::Discovery => Some("DISCOVERY>"),
+        }
+    }
+
+    /// The style of the terminal that sent `line`: the first prompt found
+    /// in it, or `Plain`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use coherent_rs::laser::terminal::TerminalStyle;
+    ///
+    /// assert_eq!(TerminalStyle::detect("Chameleon> E 1\r\n"), TerminalStyle::Chameleon);
+    /// assert_eq!(TerminalStyle::detect("DISCOVERY> 0\r\n"), TerminalStyle::Discovery);
+    /// assert_eq!(TerminalStyle::detect("0\r\n"), TerminalStyle::Plain);
+    /// ```
+    pub fn detect(line : &str) -> TerminalStyle {
+        TerminalStyle::ALL.into_iter()
+            .find(|style| style.prompt().is_some_and(|prompt| line.contains(prompt)))
+            .unwrap_or(TerminalStyle::Plain)
+    }
+
+    /// Removes the prompt from the front of a line. `InvalidResponseError`
+    /// if a prompt was expected and it's missing.
+    pub fn strip_prompt<'a>(&self, line : &'a str) -> Result<&'a str, CoherentError> {
+        match self.prompt() {
+            None => Ok(line),
+            Some(prompt) => line.split_once(prompt)
+                .map(|(_, rest)| rest)
+                .ok_or(CoherentError::InvalidResponseError(line.to_string())),
+        }
+    }
+
+    /// Strips the prompt and, if `echo` is on, the echoed command from a
+    /// line the laser sent back, leaving just the response to `command`.
+    pub fn strip_reply<'a>(&self, line : &'a str, command : &str, echo : bool) -> Result<&'a str, CoherentError> {
+        let line = self.strip_prompt(line)?.trim();
+        if !echo { return Ok(line); }
+        line.split_once(&(command.to_string() + " "))
+            .map(|(_, response)| response)
+            .ok_or(CoherentError::InvalidResponseError(line.to_string()))
+    }
+
+    /// Formats a line as a laser with this terminal would send it. With
+    /// `echo` on the command comes before the response.
+    pub fn format_reply(&self, command : &str, response : &str, echo : bool) -> String {
+        let mut reply = String::new();
+        if let Some(prompt) = self.prompt() { reply.push_str(prompt); reply.push(' '); }
+        if echo { reply.push_str(command); reply.push(' '); }
+        reply.push_str(response);
+        reply.push_str("\r\n");
+        reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for style in TerminalStyle::ALL {
+            for echo in [false, true] {
+                let line = style.format_reply("?WV", "920", echo);
+                assert_eq!(TerminalStyle::detect(&line), style);
+                assert_eq!(style.strip_reply(&line, "?WV", echo).unwrap(), "920", "{:?} {}", style, echo);
+            }
+        }
+        assert!(matches!(
+            TerminalStyle::Discovery.strip_reply("Chameleon> 920\r\n", "?WV", false),
+            Err(CoherentError::InvalidResponseError(_))
+        ));
+        assert!(TerminalStyle::Plain.strip_reply("920\r\n", "?WV", true).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+//! transcript.rs
+//!
+//! A record of everything that goes over a laser's serial port, byte for
+//! byte and timestamped, for working out why a reply didn't parse -- an
+//! echo that wasn't expected, a prompt from another firmware revision. A
+//! `SerialTranscript` taps the port (see `LaserOpenOptions::with_transcript`
+//! or `DiscoveryBuilder::transcript`) and keeps the most recent chunks in
+//! memory, or writes every one to a file as it goes.
+//!
+//! Each chunk is one read or write, as the port saw it, written as a line:
+//!
+//! ```text
+//! 1718900000.123456 > ?WV\r\n
+//! 1718900000.141207 < 920\r\n
+//! ```
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Write, BufWriter};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serialport::{SerialPort, ClearBuffer, DataBits, FlowControl, Parity, StopBits};
+
+use crate::laser::unix_timestamp;
+
+/// Which way a chunk went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Written to the laser
+    Sent,
+    /// Read from the laser
+    Received,
+}
+
+/// One read from or write to the port.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscriptEntry {
+    /// Seconds since the Unix epoch
+    pub timestamp : f64,
+    pub direction : Direction,
+    pub bytes : Vec<u8>,
+}
+
+impl std::fmt::Display for TranscriptEntry {
+    /// As written to a transcript file: the time, `>` for sent or `<` for
+    /// received, then the bytes with control characters escaped.
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let arrow = match self.direction { Direction::Sent => '>', Direction::Received => '<' };
+        write!(f, "{:.6} {} {}", self.timestamp, arrow, String::from_utf8_lossy(&self.bytes).escape_debug())
+    }
+}
+
+#[derive(Debug)]
+enum Sink {
+    Memory{capacity : usize, entries : VecDeque<TranscriptEntry>},
+    File(BufWriter<File>),
+}
+
+/// Where a tapped port's traffic goes. Clones share it, so keep one to
+/// read the transcript back while the laser uses the port.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::{Discovery, laser::Laser, laser::mock::MockSerialPort};
+/// use coherent_rs::laser::transcript::{SerialTranscript, Direction};
+///
+/// let transcript = SerialTranscript::memory(1000);
+/// let port = MockSerialPort::discovery(false, false, "SN1234").expect("?WV", "920\r\n");
+/// let mut discovery = Discovery::builder()
+///     .transcript(transcript.clone())
+///     .open_port(Box::new(port))
+///     .unwrap();
+/// discovery.get_wavelength().unwrap();
+///
+/// let last = transcript.entries().pop().unwrap();
+/// assert_eq!(last.direction, Direction::Received);
+/// assert_eq!(last.bytes, b"920\r\n");
+/// ```
+#[derive(Debug, Clone)]
+pub struct SerialTranscript {
+    sink : Arc<Mutex<Sink>>,
+}
+
+impl PartialEq for SerialTranscript {
+    /// The same transcript, not the same contents.
+    fn eq(&self, other : &Self) -> bool {
+        Arc::ptr_eq(&self.sink, &other.sink)
+    }
+}
+
+impl SerialTranscript {
+    /// Keeps the last `capacity` chunks in memory.
+    pub fn memory(capacity : usize) -> Self {
+        SerialTranscript::from_sink(Sink::Memory{capacity, entries : VecDeque::new()})
+    }
+
+    /// Appends every chunk to the file at `path`, one per line, creating it
+    /// if need be.
+    pub fn file<P : AsRef<Path>>(path : P) -> std::io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(SerialTranscript::from_sink(Sink::File(BufWriter::new(file))))
+    }
+
+    fn from_sink(sink : Sink) -> Self {
+        SerialTranscript{sink : Arc::new(Mutex::new(sink))}
+    }
+
+    /// Wraps `port` so that everything read from or written to it is
+    /// recorded here.
+    pub fn tap(&self, port : Box<dyn SerialPort>) -> Box<dyn SerialPort> {
+        Box::new(TappedPort{port, transcript : self.clone()})
+    }
+
+    /// Records a chunk. Empty ones are skipped.
+    pub fn record(&self, direction : Direction, bytes : &[u8]) {
+        if bytes.is_empty() { return; }
+        let entry = TranscriptEntry{timestamp : unix_timestamp(), direction, bytes : bytes.to_vec()};
+        let Ok(mut sink) = self.sink.lock() else { return };
+        match &mut *sink {
+            Sink::Memory{capacity, entries} => {
+                if *capacity == 0 { return; }
+                if entries.len() == *capacity { entries.pop_front(); }
+                entries.push_back(entry);
+            },
+            // Line by line, so a crash loses as little as possible
+            Sink::File(file) => { let _ = writeln!(file, "{}", entry).and_then(|_| file.flush()); },
+        }
+    }
+
+    /// The chunks kept in memory, oldest first. Empty for a file.
+    pub fn entries(&self) -> Vec<TranscriptEntry> {
+        match &*self.sink.lock().unwrap() {
+            Sink::Memory{entries, ..} => entries.iter().cloned().collect(),
+            Sink::File(_) => Vec::new(),
+        }
+    }
+
+    /// Forgets the chunks kept in memory. A file is left as it is.
+    pub fn clear(&self) {
+        if let Sink::Memory{entries, ..} = &mut *self.sink.lock().unwrap() {
+            entries.clear();
+        }
+    }
+}
+
+/// A port that records its traffic in a `SerialTranscript`.
+struct TappedPort {
+    port : Box<dyn SerialPort>,
+    transcript : SerialTranscript,
+}
+
+impl Read for TappedPort {
+    fn read(&mut self, buf : &mut [u8]) -> std::io::Result<usize> {
+        let n = self.port.read(buf)?;
+        self.transcript.record(Direction::Received, &buf[..n]);
+        Ok(n)
+    }
+}
+
+impl Write for TappedPort {
+    fn write(&mut self, buf : &[u8]) -> std::io::Result<usize> {
+        let n = self.port.write(buf)?;
+        self.transcript.record(Direction::Sent, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl SerialPort for TappedPort {
+    fn name(&self) -> Option<String> { self.port.name() }
+    fn baud_rate(&self) -> serialport::Result<u32> { self.port.baud_rate() }
+    fn data_bits(&self) -> serialport::Result<DataBits> { self.port.data_bits() }
+    fn flow_control(&self) -> serialport::Result<FlowControl> { self.port.flow_control() }
+    fn parity(&self) -> serialport::Result<Parity> { self.port.parity() }
+    fn stop_bits(&self) -> serialport::Result<StopBits> { self.port.stop_bits() }
+    fn timeout(&self) -> Duration { self.port.timeout() }
+    fn set_baud_rate(&mut self, baud_rate : u32) -> serialport::Result<()> { self.port.set_baud_rate(baud_rate) }
+    fn set_data_bits(&mut self, data_bits : DataBits) -> serialport::Result<()> { self.port.set_data_bits(data_bits) }
+    fn set_flow_control(&mut self, flow_control : FlowControl) -> serialport::Result<()> { self.port.set_flow_control(flow_control) }
+    fn set_parity(&mut self, parity : Parity) -> serialport::Result<()> { self.port.set_parity(parity) }
+    fn set_stop_bits(&mut self, stop_bits : StopBits) -> serialport::Result<()> { self.port.set_stop_bits(stop_bits) }
+    fn set_timeout(&mut self, timeout : Duration) -> serialport::Result<()> { self.port.set_timeout(timeout) }
+    fn write_request_to_send(&mut self, level : bool) -> serialport::Result<()> { self.port.write_request_to_send(level) }
+    fn write_data_terminal_ready(&mut self, level : bool) -> serialport::Result<()> { self.port.write_data_terminal_ready(level) }
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> { self.port.read_clear_to_send() }
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> { self.port.read_data_set_ready() }
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> { self.port.read_ring_indicator() }
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> { self.port.read_carrier_detect() }
+    fn bytes_to_read(&self) -> serialport::Result<u32> { self.port.bytes_to_read() }
+    fn bytes_to_write(&self) -> serialport::Result<u32> { self.port.bytes_to_write() }
+    fn clear(&self, buffer_to_clear : ClearBuffer) -> serialport::Result<()> { self.port.clear(buffer_to_clear) }
+    fn set_break(&self) -> serialport::Result<()> { self.port.set_break() }
+    fn clear_break(&self) -> serialport::Result<()> { self.port.clear_break() }
+
+    /// The clone is tapped into the same transcript.
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(self.transcript.tap(self.port.try_clone()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::mock::MockSerialPort;
+
+    #[test]
+    fn test_tap() {
+        let transcript = SerialTranscript::memory(2);
+        let mut port = transcript.tap(Box::new(
+            MockSerialPort::new().expect("?WV", "920\r\n").expect("?GDD", "0\r\n")
+        ));
+        let mut buf = [0u8; 16];
+        port.write_all(b"?WV\r\n").unwrap();
+        assert_eq!(port.read(&mut buf).unwrap(), 5);
+        let entries = transcript.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].direction, entries[0].bytes.as_slice()), (Direction::Sent, &b"?WV\r\n"[..]));
+        assert!(entries[0].to_string().ends_with(" > ?WV\\r\\n"));
+        assert!(entries[1].to_string().ends_with(" < 920\\r\\n"));
+
+        // Only the last two are kept
+        port.try_clone().unwrap().write_all(b"?GDD\r\n").unwrap();
+        let entries = transcript.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].bytes, b"?GDD\r\n");
+    }
+
+    #[test]
+    fn test_file() {
+        let path = std::env::temp_dir().join(format!("coherent-transcript-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let transcript = SerialTranscript::file(&path).unwrap();
+        let mut port = transcript.tap(Box::new(MockSerialPort::new().expect("?WV", "920\r\n")));
+        port.write_all(b"?WV\r\n").unwrap();
+        assert_eq!(port.read(&mut [0u8; 16]).unwrap(), 5);
+        assert!(transcript.entries().is_empty());
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("> ?WV\\r\\n"));
+        assert!(lines[1].ends_with("< 920\\r\\n"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+//! units.rs
+//!
+//! A lightweight units layer: the physical unit of each status field, so
+//! tools reading the same stream don't disagree about whether a power is in
+//! mW or W. Values stay plain numbers; the units travel alongside them (in
+//! the `schema`, and in exports like `schema::status_to_json`).
+//!
+//! Settings sent to the laser are typed instead: a wavelength is
+//! `Nanometers` and a GDD is `GddFs2`, so one can't be sent as the other.
+//! Both convert to and from `f32`, and go over the network as plain numbers.
+
+#[cfg(feature = "network")]
+use serde::{Serialize, Deserialize};
+
+/// A unit a status value can be in.
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Unit {
+    Nanometers,
+    Micrometers,
+    Milliwatts,
+    Watts,
+    /// Group delay dispersion
+    FemtosecondsSquared,
+    Seconds,
+    Milliseconds,
+    Hours,
+    Celsius,
+    /// Relative humidity
+    Percent,
+    Amperes,
+}
+
+/// What a unit measures. Only units of the same dimension convert.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dimension {
+    Length,
+    Power,
+    Dispersion,
+    Time,
+    Temperature,
+    Fraction,
+    Current,
+}
+
+impl Unit {
+    pub const ALL : [Unit; 11] = [
+        Unit::Nanometers, Unit::Micrometers, Unit::Milliwatts, Unit::Watts,
+        Unit::FemtosecondsSquared, Unit::Seconds, Unit::Milliseconds, Unit::Hours,
+        Unit::Celsius, Unit::Percent, Unit::Amperes,
+    ];
+
+    /// The unit's symbol, in ASCII: `nm`, `um`, `mW`, `W`, `fs^2`, `s`, `ms`,
+    /// `h`, `degC`, `%`, `A`.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::Nanometers => "nm",
+            Unit::Micrometers => "um",
+            Unit::Milliwatts => "mW",
+            Unit::Watts => "W",
+            Unit::FemtosecondsSquared => "fs^2",
+            Unit::Seconds => "s",
+            Unit::Milliseconds => "ms",
+            Unit::Hours => "h",
+            Unit::Celsius => "degC",
+            Unit::Percent => "%",
+            Unit::Amperes => "A",
+        }
+    }
+
+    /// The unit with symbol `symbol` (see `symbol`). Also accepts `µm`, `fs²`
+    /// and `°C`.
+    pub fn from_symbol(symbol : &str) -> Option<Unit> {
+        match symbol {
+            "µm" => Some(Unit::Micrometers),
+            "fs²" => Some(Unit::FemtosecondsSquared),
+            "°C" => Some(Unit::Celsius),
+            symbol => Unit::ALL.into_iter().find(|unit| unit.symbol() == symbol),
+        }
+    }
+
+    pub fn dimension(&self) -> Dimension {
+        match self {
+            Unit::Nanometers | Unit::Micrometers => Dimension::Length,
+            Unit::Milliwatts | Unit::Watts => Dimension::Power,
+            Unit::FemtosecondsSquared => Dimension::Dispersion,
+            Unit::Seconds | Unit::Milliseconds | Unit::Hours => Dimension::Time,
+            Unit::Celsius => Dimension::Temperature,
+            Unit::Percent => Dimension::Fraction,
+            Unit::Amperes => Dimension::Current,
+        }
+    }
+
+    /// How many of the dimension's base unit (nm, mW, fs^2, s, and the
+    /// rest as themselves) one of this unit is.
+    fn scale(&self) -> f64 {
+        match self {
+            Unit::Nanometers | Unit::Milliwatts | Unit::FemtosecondsSquared | Unit::Seconds
+                | Unit::Celsius | Unit::Percent | Unit::Amperes => 1.0,
+            Unit::Micrometers | Unit::Watts => 1e3,
+            Unit::Milliseconds => 1e-3,
+            Unit::Hours => 3600.0,
+        }
+    }
+
+    /// `value`, in this unit, converted to `unit`. `None` if they measure
+    /// different things.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use coherent_rs::laser::units::Unit;
+    /// assert_eq!(Unit::Milliwatts.convert(1500.0, Unit::Watts), Some(1.5));
+    /// assert_eq!(Unit::Milliwatts.convert(1500.0, Unit::Nanometers), None);
+    /// ```
+    pub fn convert(&self, value : f64, unit : Unit) -> Option<f64> {
+        (self.dimension() == unit.dimension()).then(|| value * self.scale() / unit.scale())
+    }
+}
+
+impl std::fmt::Display for Unit {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+/// A value with its unit attached.
+#[cfg_attr(feature = "network", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantity {
+    pub value : f64,
+    pub unit : Unit,
+}
+
+impl Quantity {
+    pub fn new(value : f64, unit : Unit) -> Self {
+        Quantity{value, unit}
+    }
+
+    /// The same quantity in `unit`, or `None` if it measures something else.
+    pub fn to(&self, unit : Unit) -> Option<Quantity> {
+        self.unit.convert(self.value, unit).map(|value| Quantity{value, unit})
+    }
+}
+
+impl std::fmt::Display for Quantity {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.value, self.unit)
+    }
+}
+
+/// Defines a newtype around an `f32` in `unit`.
+macro_rules! unit_newtype {
+    ($(#[$meta:meta])* $name:ident, $unit:expr) => {
+        $(#[$meta])*
+        #[cfg_attr(feature = "network", derive(Serialize, Deserialize), serde(transparent))]
+        #[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+        pub struct $name(pub f32);
+
+        impl $name {
+            pub const UNIT : Unit = $unit;
+
+            pub fn value(&self) -> f32 {
+                self.0
+            }
+        }
+
+        impl From<f32> for $name {
+            fn from(value : f32) -> Self {
+                $name(value)
+            }
+        }
+
+        impl From<$name> for f32 {
+            fn from(value : $name) -> Self {
+                value.0
+            }
+        }
+
+        impl From<$name> for Quantity {
+            fn from(value : $name) -> Self {
+                Quantity::new(value.0 as f64, $name::UNIT)
+            }
+        }
+
+        /// The number and its unit, e.g. `800 nm`.
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{} {}", self.0, $name::UNIT)
+            }
+        }
+    };
+}
+
+unit_newtype!(
+    /// A wavelength, in nm.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use coherent_rs::laser::units::{Nanometers, GddFs2};
+    /// use coherent_rs::DiscoveryNXCommands;
+    ///
+    /// let wavelength = Nanometers(920.0);
+    /// let gdd = GddFs2(-5000.0);
+    /// let command = DiscoveryNXCommands::Wavelength{wavelength_nm : wavelength};
+    /// // DiscoveryNXCommands::Wavelength{wavelength_nm : gdd} doesn't compile
+    /// assert_eq!(f32::from(wavelength), 920.0);
+    /// assert_eq!(gdd.to_string(), "-5000 fs^2");
+    /// ```
+    Nanometers, Unit::Nanometers
+);
+
+unit_newtype!(
+    /// A group delay dispersion, in fs^2.
+    GddFs2, Unit::FemtosecondsSquared
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbols() {
+        for unit in Unit::ALL {
+            assert_eq!(Unit::from_symbol(unit.symbol()), Some(unit));
+        }
+        assert_eq!(Unit::from_symbol("fs²"), Some(Unit::FemtosecondsSquared));
+        assert_eq!(Unit::from_symbol("°C"), Some(Unit::Celsius));
+        assert_eq!(Unit::from_symbol("furlongs"), None);
+    }
+
+    #[test]
+    fn test_convert() {
+        let power = Quantity::new(250.0, Unit::Milliwatts);
+        assert_eq!(power.to(Unit::Watts), Some(Quantity::new(0.25, Unit::Watts)));
+        assert_eq!(power.to(Unit::Milliwatts), Some(power));
+        assert_eq!(Quantity::new(1.5, Unit::Micrometers).to(Unit::Nanometers).unwrap().value, 1500.0);
+        assert_eq!(Unit::Seconds.convert(1.5, Unit::Milliseconds), Some(1500.0));
+        assert_eq!(Unit::Hours.convert(2.0, Unit::Seconds), Some(7200.0));
+        assert_eq!(Unit::Celsius.convert(20.0, Unit::Percent), None);
+        assert_eq!(power.to(Unit::FemtosecondsSquared), None);
+        assert_eq!(power.to_string(), "250 mW");
+    }
+}
@@ -3,22 +3,27 @@
 //! of the laser and provides an asynchronous API to read out and control laser
 //! parameters.
 
-use serialport;
 pub mod laser;
 #[cfg(feature = "network")]
 pub mod network;
+#[cfg(feature = "network")]
+mod error_serde;
 
 use laser::Laser;
 pub use laser::{discoverynx, DiscoveryNXCommands, DiscoveryNXQueries};
 pub use laser::Discovery;
+pub use laser::{Nanometers, GddFs2};
 
 const COHERENT_VENDOR_ID : u16 = 3405;
 
 /// The error types that can be returned by the Coherent-RS library.
+/// With the `network` feature they can be serialized, so a server can tell
+/// its clients why a command failed.
+#[cfg_attr(feature = "network", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug)]
 pub enum CoherentError {
-    SerialError(serialport::Error),
-    WriteError(std::io::Error),
+    SerialError(#[cfg_attr(feature = "network", serde(with = "error_serde::serial_error"))] serialport::Error),
+    WriteError(#[cfg_attr(feature = "network", serde(with = "error_serde::io_error"))] std::io::Error),
     TimeoutError,
     CommandNotExecutedError,
     InvalidArgumentsError(String),
@@ -26,8 +31,19 @@ pub enum CoherentError {
     LaserUnavailableError,
     NoRecognizedLasers,
     UnrecognizedDevice,
+    SoftLimitError(String), // refused by the laser's `SoftLimits`, never sent
+    LockedError, // the laser is locked by an `OperatorLock`
+    LaserBusyError, // another thread holds a `SharedLaser` (from its `try_` methods)
     #[cfg(feature = "network")]
     SerializationError,
+    Unauthorized, // refused by a server's `Authorizer` (see `network::authorization`), or a wrong admin token
+    ReservationDenied(String), // a server's `ReservationCheck` wouldn't make a client primary, and why
+    CommandTimedOut, // a server gave up waiting for the laser to carry out a client's command (see `NetworkLaserServer::set_command_timeout`)
+    Reconnecting, // the serial link dropped and the laser hasn't been found again yet (see `discoverynx::reconnect`)
+    /// A setting read back after a verified command isn't what the command set it to
+    VerificationFailed{parameter : String, expected : laser::StatusValue, actual : laser::StatusValue},
+    Cancelled, // stopped part way through by a `CancelToken` (see `laser::cancel`)
+    Superseded, // not run, because a later command from the same client set the same thing (see `network::coalesce`)
 }
 
 impl From<serialport::Error> for CoherentError {
@@ -40,27 +56,31 @@ impl From<serialport::Error> for CoherentError {
 /// 
 /// # Returns
 /// 
-/// A `Vec` of `SerialPortInfo` objects that are made by Coherent Inc.
+/// A `Vec` of `SerialPortInfo` objects that are made by Coherent Inc., or
+/// `CoherentError::SerialError` if the system's serial ports can't be
+/// listed (e.g. for lack of permissions, or under WSL).
 /// 
 /// # Example
 /// 
 /// ```rust
 /// use coherent_rs::get_all_coherent_devices;
-/// let ports = get_all_coherent_devices();
-/// for port in ports {
-///    println!("{:?}", port);
+/// match get_all_coherent_devices() {
+///     Ok(ports) => for port in ports {
+///         println!("{:?}", port);
+///     },
+///     Err(e) => eprintln!("Could not list ports: {:?}", e),
 /// }
 /// ```
-pub fn get_all_coherent_devices() -> Vec<serialport::SerialPortInfo> {
-    serialport::available_ports().unwrap_or_default()
+pub fn get_all_coherent_devices() -> Result<Vec<serialport::SerialPortInfo>, CoherentError> {
+    Ok(laser::ports::available_ports()?
         .into_iter()
         .filter(
             |port| match &port.port_type {
-                serialport::SerialPortType::UsbPort(info) => {info.vid.clone() == COHERENT_VENDOR_ID},
+                serialport::SerialPortType::UsbPort(info) => {info.vid == COHERENT_VENDOR_ID},
                 _ => false
             }
         )
-        .collect()
+        .collect())
 }
 
 /// Open a serial connection to the Coherent laser.
@@ -75,7 +95,7 @@ pub fn get_all_coherent_devices() -> Vec<serialport::SerialPortInfo> {
 /// 
 /// # Example
 /// 
-/// ```rust
+/// ```no_run
 /// use coherent_rs::{open, Discovery};
 /// let discovery = open::<Discovery>("NotAPort");
 /// assert!(discovery.is_err());
@@ -84,7 +104,7 @@ pub fn get_all_coherent_devices() -> Vec<serialport::SerialPortInfo> {
 /// ```
 pub fn open<L : Laser>(port : &str) -> Result<L, CoherentError> {
     // Open serial port
-    Ok(L::from_port_name(port)?)
+    L::from_port_name(port)
 }
 
 #[cfg(test)]
@@ -93,7 +113,7 @@ mod tests {
 
     #[test]
     fn print_all_coherent_devices(){
-        let ports = get_all_coherent_devices();
+        let ports = get_all_coherent_devices().unwrap_or_default();
         for port in ports {
             println!("{:?}", port);
         }
@@ -5,46 +5,211 @@
 //! It uses `serde` to communicate `Command`s and `Query`s over
 //! the network.
 
+use std::collections::{BTreeMap, VecDeque};
 use std::io::{Read,Write};
 use std::marker::PhantomData;
 use std::sync::{Arc, Mutex, atomic::AtomicBool, MutexGuard};
-use std::net::{TcpListener, TcpStream};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
 use crate::{
-    laser::{Laser, Query, LaserType},
+    laser::{history::ChangeOrigin, cancel::CancelToken, unix_timestamp, retry::{RetryPolicy, RetryableError, ErrorClass, Deadline}, shared::SharedLaser, Laser, Query, LaserType, LaserTypeVisitor, FaultReport, StatusValue, CommonCommand},
     CoherentError,
 };
 
 use serde::{Serialize, Deserialize};
 use rmp_serde::Serializer;
 
+#[cfg(any(test, feature = "test-harness"))]
+pub mod harness;
+pub mod confirmation;
+pub mod locking;
+pub mod stats;
+pub mod codec;
+pub mod conformance;
+pub mod capabilities;
+pub mod stress;
+pub mod clock;
+pub mod group;
+pub mod config;
+pub mod builder;
+pub mod admin;
+pub mod validation;
+pub mod journal;
+pub mod authorization;
+pub mod progress;
+pub mod sweep;
+pub mod coalesce;
+pub mod error_frame;
+mod schedule;
+#[cfg(feature = "opentelemetry")]
+pub mod telemetry;
+
+use confirmation::{ConfirmationPolicy, PendingCommand, DEFAULT_CONFIRMATION_TIMEOUT};
+use locking::{LockLevel, acquire};
+use stats::{ServerStats, StatsRecorder};
+use codec::WireFormat;
+use capabilities::{Capabilities, Capability};
+use clock::{ClockOffset, ServerTime};
+use config::ServerConfig;
+use builder::{ClientBuilder, ReadLimits};
+use admin::AdminRawCommand;
+use validation::Validation;
+use journal::CommandJournal;
+use authorization::{Authorizer, Authorization, ClientInfo, ReservationCheck};
+use progress::{Progress, ProgressWatch, ProgressState, Operation};
+use sweep::{StartSweep, SweepStep, SweepReport, SweepControl, SweepClient};
+use coalesce::{CoalescePolicy, Queued};
+use error_frame::{ErrorCode, ErrorFrame};
+
 pub const COMMAND_MARKER : &[u8] = b"Command: ";
 pub const STATUS_MARKER : &[u8] = b"Status: ";
 pub const TERMINATOR : &[u8] = b"\n";
 pub const LASER_ID : &[u8] = b"Laser ID: ";
 pub const COMMAND_SUCCESSFUL : &[u8] = b"COMMAND SUCCESSFUL\n";
+/// Ends every refusal but `NOT_PRIMARY_CLIENT`'s. Deprecated as a way to
+/// tell what went wrong -- read the `ERROR_FRAME_MARKER` frame in front of
+/// it (see `error_frame`).
 pub const COMMAND_FAILED : &[u8] = b"COMMAND FAILED\n";
+/// Precedes a serialized `CoherentError` explaining a `COMMAND_FAILED`
+/// that follows it.
+pub const ERROR_MARKER : &[u8] = b"Error: ";
+/// Precedes an `error_frame::ErrorFrame`, which starts every refusal.
+pub const ERROR_FRAME_MARKER : &[u8] = b"Error frame: ";
+/// Ends a refusal with `ErrorCode::NotPrimaryClient`. Deprecated, as
+/// `COMMAND_FAILED` is.
 pub const NOT_PRIMARY_CLIENT : &[u8] = b"NOT PRIMARY CLIENT\n";
 pub const DEMAND_PRIMARY_CLIENT : &[u8] = b"DEMAND PRIMARY CLIENT\n";
 pub const FORGET_PRIMARY_CLIENT : &[u8] = b"FORGET PRIMARY CLIENT\n";
 pub const FORGET_ME : &[u8] = b"FORGET ME\n";
+pub const LOCK_MARKER : &[u8] = b"Lock: ";
+pub const UNLOCK_MARKER : &[u8] = b"Unlock: ";
+pub const CONFIRM_MARKER : &[u8] = b"Confirm: ";
+/// Asks the server for its `stats::ServerStats`, which come back after a
+/// `STATS_MARKER`.
+pub const STATS_REQUEST : &[u8] = b"STATS\n";
+pub const STATS_MARKER : &[u8] = b"Stats: ";
+/// Followed by the name of a `codec::WireFormat`: from the server, the one
+/// it's speaking; from a client, the one it wants.
+pub const CODEC_MARKER : &[u8] = b"Codec: ";
+/// Asks the server for the time, which comes back as a `clock::ServerTime`
+/// after a `TIME_MARKER`. Servers also send one in the handshake.
+pub const TIME_REQUEST : &[u8] = b"TIME\n";
+pub const TIME_MARKER : &[u8] = b"Time: ";
+/// Asks the server to reread its config file (see `config`).
+pub const RELOAD_CONFIG : &[u8] = b"RELOAD CONFIG\n";
+/// Asks the server for the laser's `FaultReport`, which comes back after
+/// a `FAULTS_MARKER`.
+pub const FAULTS_REQUEST : &[u8] = b"FAULTS\n";
+/// Asks the server to clear the laser's faults, then read them again: the
+/// `FaultReport` that comes back says whether they cleared.
+pub const CLEAR_FAULTS : &[u8] = b"CLEAR FAULTS\n";
+pub const FAULTS_MARKER : &[u8] = b"Faults: ";
+/// Followed by an `admin::AdminRawCommand`. The laser's reply comes back
+/// after a `RAW_REPLY_MARKER`.
+pub const ADMIN_RAW_MARKER : &[u8] = b"Admin raw: ";
+pub const RAW_REPLY_MARKER : &[u8] = b"Raw reply: ";
+/// Followed by a command to check without running it (see `validation`).
+/// The `validation::Validation` comes back after a `VALIDATION_MARKER`.
+pub const VALIDATE_MARKER : &[u8] = b"Validate: ";
+pub const VALIDATION_MARKER : &[u8] = b"Validation: ";
+/// Followed by the server's limit in bytes, in decimal, then `TERMINATOR`:
+/// the reply to a frame longer than that, which is dropped unread.
+pub const FRAME_TOO_LARGE_MARKER : &[u8] = b"FRAME TOO LARGE: ";
+/// Precedes a `progress::Progress`, pushed to the client that started a
+/// long-running operation.
+pub const PROGRESS_MARKER : &[u8] = b"Progress: ";
+/// Precedes a `sweep::StartSweep` for the server to run; see `sweep`.
+pub const START_SWEEP_MARKER : &[u8] = b"Start sweep: ";
+/// Stops the sweep that's running.
+pub const ABORT_SWEEP : &[u8] = b"ABORT SWEEP\n";
+/// Precedes a `sweep::SweepStep`, pushed to the client running the sweep.
+pub const SWEEP_STEP_MARKER : &[u8] = b"Sweep step: ";
+/// Stops whatever's running on the laser: a command waiting for tuning to
+/// finish, a sweep. See `NetworkLaserClient::abort`.
+pub const ABORT : &[u8] = b"ABORT\n";
+/// The most either end buffers for one frame, unless told otherwise.
+pub const DEFAULT_MAX_FRAME_SIZE : usize = 1 << 20;
+/// How long the command thread waits on a read from a client that's sent
+/// nothing. Short, so checking every client for an `ABORT` while a command
+/// runs doesn't hold up the command.
+const CLIENT_READ_TIMEOUT : Duration = Duration::from_millis(1);
+/// The names of the threads `NetworkLaserServer::poll` starts.
+pub const ACCEPT_THREAD : &str = "coherent-accept";
+pub const POLLING_THREAD : &str = "coherent-poller";
+pub const COMMAND_THREAD : &str = "coherent-commands";
+pub const CONFIG_THREAD : &str = "coherent-config";
+/// The thread a client's sweep runs on (see `sweep`).
+pub const SWEEP_THREAD : &str = "coherent-sweep";
+/// The threads clients' commands run on, one per command, when the server
+/// has a command timeout (see `NetworkLaserServer::set_command_timeout`).
+pub const COMMAND_WORKER_THREAD : &str = "coherent-command-worker";
+/// How long the server waits for the laser to carry out a client's command,
+/// unless told otherwise -- long enough for a wavelength change that waits
+/// for tuning to finish.
+pub const DEFAULT_COMMAND_TIMEOUT : Duration = Duration::from_secs(60);
+/// How often `CONFIG_THREAD` looks at the config file, in milliseconds.
+const CONFIG_CHECK_MS : u64 = 200;
+/// How often the command thread looks for an `ABORT` from the other
+/// clients while one's command waits on the laser.
+const ABORT_CHECK : Duration = Duration::from_millis(50);
+/// Precedes a command with the client's W3C `traceparent` (see `telemetry`).
+/// Servers without the `opentelemetry` feature skip over it.
+pub const TRACE_MARKER : &[u8] = b"Trace: ";
 
 /// Errors during communication with the laser over the network.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum TcpError {
     MultipleReferencesToLaser,
     MutexPoisoned,
     CoherentError(CoherentError),
-    IoError(std::io::Error),
-    SerializationEncodeError(rmp_serde::encode::Error),
-    SerializationDecodeError(rmp_serde::decode::Error),
+    IoError(#[serde(with = "crate::error_serde::io_error")] std::io::Error),
+    SerializationEncodeError(#[serde(with = "crate::error_serde::encode_error")] rmp_serde::encode::Error),
+    SerializationDecodeError(#[serde(with = "crate::error_serde::decode_error")] rmp_serde::decode::Error),
+    /// The server couldn't carry out the request, and didn't say why.
     CommandError,
+    /// The laser refused a command sent through the server -- the
+    /// error the server got from it.
+    Remote(CoherentError),
     NoLaserStatus,
     NotPrimaryClient,
     Disconnected,
+    /// The server is hosting a different model of laser than
+    /// the client was built for. `actual` is what the server reported.
+    LaserTypeMismatch{expected : LaserType, actual : LaserType},
+    /// The server is too old to understand the request (see `capabilities`).
+    Unsupported(Capability),
+    /// More than `limit` bytes arrived without making a whole response
+    /// (see `builder::ReadLimits`), or the server refused a frame longer
+    /// than its own limit (see `NetworkLaserServer::set_max_frame_size`).
+    FrameTooLarge{limit : usize},
+    /// The server refused the request, and said why in an error frame
+    /// (see `error_frame`) but not with a `CoherentError`.
+    Refused(ErrorFrame),
+}
+
+impl RetryableError for TcpError {
+    fn error_class(&self) -> Option<ErrorClass> {
+        match self {
+            TcpError::IoError(e) if matches!(
+                e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+            ) => Some(ErrorClass::Timeout),
+            TcpError::IoError(_) => Some(ErrorClass::Io),
+            TcpError::SerializationDecodeError(_) => Some(ErrorClass::InvalidResponse),
+            TcpError::CommandError => Some(ErrorClass::NotExecuted),
+            TcpError::Refused(refusal) => match refusal.code {
+                ErrorCode::LaserBusy => Some(ErrorClass::Busy),
+                ErrorCode::TimedOut => Some(ErrorClass::Timeout),
+                ErrorCode::NotExecuted => Some(ErrorClass::NotExecuted),
+                _ => None,
+            },
+            TcpError::CoherentError(e) | TcpError::Remote(e) => e.error_class(),
+            _ => None,
+        }
+    }
 }
 
-impl<T> Into<TcpError> for std::sync::PoisonError<T> {
-    fn into(self) -> TcpError {
+impl<T> From<std::sync::PoisonError<T>> for TcpError {
+    fn from(_val: std::sync::PoisonError<T>) -> Self {
         TcpError::MutexPoisoned
     }
 }
@@ -53,23 +218,135 @@ impl<T> Into<TcpError> for std::sync::PoisonError<T> {
 /// the laser in addition to the normal `Laser` methods. Takes ownership
 /// of the `Laser` and maintains exclusive access through a `Mutex`.
 /// 
+/// The server's threads take their locks in the order set out in
+/// `locking`, and the command thread gives up on a busy laser (replying
+/// `LaserBusyError`) rather than stalling every client behind it -- see
+/// `set_lock_retry_policy`.
+/// 
+/// Speaks MessagePack unless told otherwise with `set_wire_format`; each
+/// client can ask for a different format for itself (see `codec`).
+/// 
 /// # Example
 /// 
 /// ```rust
-/// use coherent_rs::{Discovery, create_listener};
-/// let laser = Discovery::find_first();
-/// // To be continued...
+/// use std::time::Duration;
+/// use coherent_rs::laser::{Laser, debug::DebugLaser};
+/// use coherent_rs::network::{NetworkLaserServer, NetworkLaserClient, BasicNetworkLaserClient};
+/// 
+/// let laser = DebugLaser::find_first().unwrap();
+/// let mut server = NetworkLaserServer::new(laser, "127.0.0.1:0", Some(Duration::from_millis(100))).unwrap();
+/// server.poll().unwrap();
+/// 
+/// let address = server.local_addr().unwrap().to_string();
+/// let mut client = BasicNetworkLaserClient::<DebugLaser>::connect(&address, Some(1000)).unwrap();
+/// println!("{:?}", client.query_status().unwrap());
 /// ```
 pub struct NetworkLaserServer<L : Laser + 'static> {
     _listener : TcpListener,
-    _clients : Arc<Mutex<Vec<TcpStream>>>,
+    _clients : Arc<Mutex<Vec<Connection>>>,
     _client_connection_thread : Option<std::thread::JoinHandle<()>>,
-    _laser : Option<Arc<Mutex<L>>>,
-    _polling_interval : Arc<Mutex<f32>>, // seconds
+    _laser : Option<SharedLaser<L>>,
+    _polling_interval : Arc<Mutex<Duration>>,
     _polling_thread : Option<std::thread::JoinHandle<()>>,
     _polling : Arc<AtomicBool>,
     _command_thread : Option<std::thread::JoinHandle<()>>, // polls for commands -- runs faster to ensure commands are executed.
-    _primary_client : Option<Arc<Mutex<TcpStream>>>, // defines a primary client -- if defined, only the primary client can issue commands.
+    _primary_client : Option<SocketAddr>, // defines a primary client -- if defined, only the primary client can issue commands.
+    _confirmation_policy : Arc<Mutex<Option<ConfirmationPolicy<L>>>>, // commands that need a second client's confirmation
+    _coalesce_policy : Arc<Mutex<Option<CoalescePolicy<L>>>>, // queued commands a later one supersedes
+    _authorizer : Arc<Mutex<Option<Authorizer<L>>>>, // asked about every client's commands
+    _reservation_check : Arc<Mutex<Option<ReservationCheck>>>, // asked before making a client primary
+    _lock_retry_policy : RetryPolicy, // how long the command thread waits for a busy laser
+    _command_timeout : Option<Duration>, // how long the command thread waits for a command to run
+    _stats : Arc<Mutex<StatsRecorder>>,
+    _wire_format : Arc<Mutex<WireFormat>>, // what new clients are spoken to in
+    _config_path : Option<std::path::PathBuf>, // reread when it changes, or on `RELOAD_CONFIG`
+    _config_thread : Option<std::thread::JoinHandle<()>>,
+    _max_frame_size : usize, // longest frame the command thread accepts from a client
+    _admin_token : Option<String>, // allows `AdminRawCommand`s
+    _progress_watches : Arc<Mutex<Vec<ProgressWatch>>>, // clients to tell how their operations are going
+    _sweep : Arc<SweepControl>, // the sweep running on the laser, if any
+    _cancel_token : Option<CancelToken>, // the laser's, taken once so an abort needn't wait for the laser
+}
+
+/// A connected client, the format the server speaks to it, whatever it's
+/// sent of a frame that hasn't all arrived yet, and the frames it sent
+/// before the server got to them.
+struct Connection {
+    stream : TcpStream,
+    address : SocketAddr,
+    format : WireFormat,
+    received : Vec<u8>,
+    overflowed : bool, // dropping the rest of a frame that was too long
+    queued : VecDeque<Queued>, // whole frames, oldest first
+    disconnected : bool, // couldn't be written to, or closed its end
+}
+
+impl Connection {
+    fn new(stream : TcpStream, address : SocketAddr, format : WireFormat) -> Self {
+        Connection{stream, address, format, received : Vec::new(), overflowed : false, queued : VecDeque::new(), disconnected : false}
+    }
+
+    /// Sends `response` to the client. If it can't be sent the client has
+    /// gone, and it's marked to be dropped rather than answered again.
+    fn reply(&mut self, response : &[u8]) {
+        if self.stream.write_all(response).is_err() {
+            self.disconnected = true;
+        }
+    }
+
+    /// Adds `data` to what's been received, queues the frames it completes
+    /// -- a frame is complete once a read ends with `TERMINATOR`, or another
+    /// command starts after it -- and takes the oldest queued. Frames in
+    /// the same class by `class` (see `coalesce`) supersede those before
+    /// them. Fails once more than `max_frame_size` bytes have arrived
+    /// without one; the rest of that frame is dropped as it comes in.
+    fn assemble(&mut self, data : &[u8], max_frame_size : usize, class : impl FnMut(&[u8]) -> Option<String>)
+        -> Result<Option<Queued>, TcpError> {
+        self.received.extend_from_slice(data);
+        let complete = self.received.ends_with(TERMINATOR);
+        if self.overflowed {
+            self.received.clear();
+            self.overflowed = !complete;
+            return Ok(self.queued.pop_front());
+        }
+        if self.received.len() > max_frame_size {
+            self.received.clear();
+            self.overflowed = !complete;
+            return Err(TcpError::FrameTooLarge{limit : max_frame_size});
+        }
+        let frames = coalesce::split_frames(&mut self.received, complete);
+        if !frames.is_empty() {
+            self.queued.extend(frames.into_iter().map(Queued::Frame));
+            coalesce::coalesce(&mut self.queued, class);
+        }
+        Ok(self.queued.pop_front())
+    }
+}
+
+/// A client's command that could take a while on the laser, which the
+/// command thread runs once it's let go of the clients -- so the status
+/// broadcast, new clients and aborts aren't held up waiting on it.
+struct DeferredCommand<L : Laser> {
+    command : L::CommandEnum,
+    origin : SocketAddr, // whose command it is
+    received : std::time::Instant,
+    timeout : Duration,
+    replies : Vec<(TcpStream, WireFormat)>, // everyone who hears how it went
+    #[cfg(feature = "opentelemetry")]
+    trace : Option<telemetry::ServerTrace>,
+}
+
+impl std::ops::Deref for Connection {
+    type Target = TcpStream;
+    fn deref(&self) -> &TcpStream {
+        &self.stream
+    }
+}
+
+impl std::ops::DerefMut for Connection {
+    fn deref_mut(&mut self) -> &mut TcpStream {
+        &mut self.stream
+    }
 }
 
 /// Reads a laser status from a stream returns a `Result` with the `LaserStatus`
@@ -78,7 +355,7 @@ pub struct NetworkLaserServer<L : Laser + 'static> {
 /// 
 /// # Example
 /// 
-/// ```rust
+/// ```ignore
 /// use coherent_rs::laser::{Laser, debug::DebugLaser};
 /// use coherent_rs::network::{STATUS_MARKER, deserialize_laser_status, TERMINATOR};
 /// 
@@ -89,27 +366,25 @@ pub struct NetworkLaserServer<L : Laser + 'static> {
 /// sent_message.extend(status_serialized);
 /// sent_message.extend(TERMINATOR);
 /// 
-/// let status = deserialize_laser_status::<DebugLaser>(&sent_message).unwrap();
+/// let status = deserialize_laser_status::<DebugLaser>(&sent_message, WireFormat::MessagePack).unwrap();
 /// println!{"Deserialized : {:?}", status};
 /// assert_eq!(status, laser.status().unwrap());
 /// ```
-fn deserialize_laser_status<L : Laser>(stream : &[u8]) -> Result<L::LaserStatus, TcpError> {
-    if let Some(start_idx) = stream.windows(STATUS_MARKER.len()).rposition(
-        |window| window == STATUS_MARKER
-    ){
-        let status = &stream[start_idx + STATUS_MARKER.len()..];
-        if let Some(end) = status.split(|&x| x == TERMINATOR[0]).next() {
-            let serialized = &status[..end.len()];
-            L::LaserStatus::deserialize(
-                &mut rmp_serde::Deserializer::new(serialized)
-            ).map_err(|e| TcpError::SerializationDecodeError(e))
-        }
-        else {
-            Err(TcpError::NoLaserStatus)
-        }
-    }
-    else {
-        Err(TcpError::NoLaserStatus)
+fn deserialize_laser_status<L : Laser>(stream : &[u8], format : WireFormat) -> Result<L::LaserStatus, TcpError> {
+    deserialize_after(stream, STATUS_MARKER, format, true)
+}
+
+/// Decodes the value framed by the first (or with `last`, the last) `marker`
+/// in `stream`. Decodes from everything after the marker rather than up to
+/// the next `TERMINATOR`, since a binary encoding may contain that byte.
+fn deserialize_after<T : serde::de::DeserializeOwned>(stream : &[u8], marker : &[u8], format : WireFormat, last : bool)
+    -> Result<T, TcpError> {
+    let mut windows = stream.windows(marker.len());
+    let start_idx = if last { windows.rposition(|window| window == marker) }
+        else { windows.position(|window| window == marker) };
+    match start_idx {
+        Some(start_idx) => format.decode(&stream[start_idx + marker.len()..]),
+        None => Err(TcpError::NoLaserStatus),
     }
 }
 
@@ -121,31 +396,303 @@ fn deserialize_laser_status<L : Laser>(stream : &[u8]) -> Result<L::LaserStatus,
 /// ```rust
 /// // TODO
 /// ```
-fn deserialize_command<L : Laser>(stream : &[u8]) -> Result<L::CommandEnum, TcpError> {
-    if let Some(start_idx) = stream.windows(COMMAND_MARKER.len()).position(
-        |window| window == COMMAND_MARKER
-    ){
-        let command = &stream[start_idx + COMMAND_MARKER.len()..];
-        if let Some(end) = command.split(|&x| x == TERMINATOR[0]).next() {
-            let serialized = &command[..end.len()];
-            L::CommandEnum::deserialize(
-                &mut rmp_serde::Deserializer::new(serialized)
-            ).map_err(|e| TcpError::SerializationDecodeError(e))
-        }
-        else {
-            Err(TcpError::NoLaserStatus)
+fn deserialize_command<L : Laser>(stream : &[u8], format : WireFormat) -> Result<L::CommandEnum, TcpError> {
+    deserialize_command_after::<L>(stream, COMMAND_MARKER, format)
+}
+
+/// As `deserialize_command`, for a command framed by `marker` instead
+/// (e.g. the `CONFIRM_MARKER`).
+fn deserialize_command_after<L : Laser>(stream : &[u8], marker : &[u8], format : WireFormat) -> Result<L::CommandEnum, TcpError> {
+    deserialize_after(stream, marker, format, false)
+}
+
+/// Runs a client's command, attributing whatever it changes in the laser's
+/// `ParameterHistory` (if it keeps one) to the client at `origin`.
+fn send_command_from<L : Laser>(laser : &mut L, command : L::CommandEnum, origin : std::net::SocketAddr)
+    -> Result<(), CoherentError> {
+    if let Some(history) = laser.parameter_history() {
+        history.origin = ChangeOrigin::Client(origin);
+    }
+    let result = laser.send_command(command);
+    if let Some(history) = laser.parameter_history() {
+        history.origin = ChangeOrigin::Local;
+    }
+    result
+}
+
+/// `send_command_from` on a `COMMAND_WORKER_THREAD`, giving up with
+/// `CommandTimedOut` after `timeout`. The caller mustn't hold the laser. A
+/// command given up on before it's sent isn't sent; one given up on while
+/// it runs is followed by `Laser::recover_link`, before the worker lets go
+/// of the laser. Calls `while_waiting` every `ABORT_CHECK` until it's done.
+///
+/// `worker_running` is set while a worker is, so there's only ever one: a
+/// command that comes while one given up on is still running fails with
+/// `LaserBusyError`, rather than starting another worker to queue on the
+/// laser behind it.
+fn send_command_within<L : Laser + 'static>(
+    laser : &SharedLaser<L>,
+    worker_running : &Arc<AtomicBool>,
+    command : L::CommandEnum,
+    origin : std::net::SocketAddr,
+    timeout : Duration,
+    mut while_waiting : impl FnMut(),
+) -> Result<(), CoherentError> {
+    if worker_running.swap(true, std::sync::atomic::Ordering::SeqCst) {
+        return Err(CoherentError::LaserBusyError);
+    }
+    let (sender, receiver) = std::sync::mpsc::channel();
+    // Set by whichever gets there first: the worker finishing, or the
+    // command thread giving up
+    let settled = Arc::new(AtomicBool::new(false));
+    let worker_settled = Arc::clone(&settled);
+    let running = Arc::clone(worker_running);
+    let laser = laser.clone();
+    let spawned = std::thread::Builder::new().name(COMMAND_WORKER_THREAD.to_string()).spawn(move || {
+        let result = acquire(LockLevel::Laser, || laser.lock()).and_then(|mut laser| {
+            if worker_settled.load(std::sync::atomic::Ordering::SeqCst) {
+                return Err(CoherentError::CommandTimedOut);
+            }
+            let result = send_command_from(&mut **laser, command, origin);
+            if worker_settled.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                if let Err(e) = laser.recover_link() {
+                    eprintln!("Couldn't recover the serial link after a command timed out: {:?}", e);
+                }
+            }
+            result
+        });
+        running.store(false, std::sync::atomic::Ordering::SeqCst);
+        let _ = sender.send(result);
+    });
+    if let Err(e) = spawned {
+        worker_running.store(false, std::sync::atomic::Ordering::SeqCst);
+        return Err(CoherentError::WriteError(e));
+    }
+
+    let deadline = Deadline::after(timeout);
+    let waited = loop {
+        match receiver.recv_timeout(deadline.cap(ABORT_CHECK)) {
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) if !deadline.is_expired() => while_waiting(),
+            waited => break waited,
         }
+    };
+    match waited {
+        Ok(result) => result,
+        Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+            if settled.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                // Finished just as time ran out
+                receiver.recv().unwrap_or(Err(CoherentError::LaserUnavailableError))
+            }
+            else {
+                Err(CoherentError::CommandTimedOut)
+            }
+        },
+        Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => Err(CoherentError::LaserUnavailableError),
+    }
+}
+
+/// Whether `client` has the primary client's say: it's the primary client,
+/// or there isn't one.
+fn is_primary(client : &Connection, primary_client : &Option<SocketAddr>) -> bool {
+    primary_client.is_none_or(|primary| primary == client.address)
+}
+
+/// Stops whatever's running on the laser for `client`, unless there's a
+/// primary client and it isn't it, and says what happened: a sweep, and, if
+/// `command_running`, the command the command thread is waiting on (through
+/// the laser's `cancel_token`). Fails if there was nothing it could stop.
+fn abort_response(
+    client : &Connection,
+    primary_client : &Option<SocketAddr>,
+    cancel_token : Option<&CancelToken>,
+    command_running : bool,
+    sweep : &SweepControl,
+    format : WireFormat,
+) -> Vec<u8> {
+    if !is_primary(client, primary_client) {
+        return error_response(ErrorCode::NotPrimaryClient, None, format);
+    }
+    let stopped_sweep = sweep.abort().is_ok();
+    let stopped_command = match cancel_token {
+        Some(token) if command_running => {
+            token.cancel();
+            true
+        },
+        _ => false,
+    };
+    let result = if stopped_sweep || stopped_command { Ok(()) } else { Err(CoherentError::CommandNotExecutedError) };
+    command_response(&result, format)
+}
+
+/// Whether `client` has sent an `ABORT` that hasn't been read yet, taking
+/// it if so. Waits no longer than any read from the client does
+/// (`CLIENT_READ_TIMEOUT`), and leaves the socket's settings alone.
+fn take_abort(client : &Connection) -> bool {
+    if !client.received.is_empty() {
+        return false; // in the middle of some other frame
+    }
+    let mut pending = [0u8; ABORT.len()];
+    let aborting = matches!(client.peek(&mut pending), Ok(n) if pending[..n] == *ABORT);
+    aborting && (&client.stream).read_exact(&mut pending).is_ok()
+}
+
+/// Answers an `ABORT` from any of `clients`, for while the command thread
+/// waits on a command and can't read them as it normally would.
+fn answer_aborts<'a>(
+    clients : impl Iterator<Item = &'a Connection>,
+    primary_client : &Option<SocketAddr>,
+    cancel_token : Option<&CancelToken>,
+    sweep : &SweepControl,
+) {
+    for client in clients.filter(|client| take_abort(client)) {
+        let _ = (&client.stream).write_all(&abort_response(client, primary_client, cancel_token, true, sweep, client.format));
+    }
+}
+
+/// What `authorizer` says about `command` from `client`: `Allow` if
+/// there's no authorizer, `Deny` if its mutex is poisoned.
+fn authorize<L : Laser>(
+    authorizer : &Mutex<Option<Authorizer<L>>>,
+    primary_client : &Option<SocketAddr>,
+    client : &Connection,
+    command : &L::CommandEnum,
+) -> Authorization {
+    let address = client.address;
+    let primary = *primary_client == Some(address);
+    match acquire(LockLevel::Authorizer, || authorizer.lock()) {
+        Ok(mut authorizer) => authorizer.as_mut()
+            .map_or(Authorization::Allow, |authorizer| authorizer.authorize(&ClientInfo{address, primary}, command)),
+        Err(_) => Authorization::Deny,
+    }
+}
+
+/// Holds `command` from `client` until a second client confirms it, or
+/// `timeout` passes -- `client` hears back then.
+fn hold_for_confirmation<L : Laser>(
+    pending : &mut Vec<PendingCommand<L>>,
+    client : &mut Connection,
+    format : WireFormat,
+    command : L::CommandEnum,
+    timeout : Duration,
+) {
+    match (client.try_clone(), encode_command::<L>(&command)) {
+        (Ok(requester), Some(encoded)) => pending.push(PendingCommand{
+            requester,
+            requester_address : client.address,
+            requester_format : format,
+            command,
+            encoded,
+            deadline : std::time::Instant::now() + timeout,
+        }),
+        _ => {client.reply(&error_response(ErrorCode::Failed, None, format));},
+    }
+}
+
+/// `value` encoded in `format`, between `marker` and the `TERMINATOR`: the
+/// shape of every frame that carries a value.
+fn frame<T : Serialize + ?Sized>(marker : &[u8], value : &T, format : WireFormat) -> Result<Vec<u8>, TcpError> {
+    let mut buf = marker.to_vec();
+    buf.extend(format.encode(value)?);
+    buf.extend(TERMINATOR);
+    Ok(buf)
+}
+
+/// Names `format`: the server's announcement of the format it speaks, or a
+/// client's request for it.
+fn codec_line(format : WireFormat) -> Vec<u8> {
+    let mut buf = CODEC_MARKER.to_vec();
+    buf.extend(format.name().as_bytes());
+    buf.extend(TERMINATOR);
+    buf
+}
+
+/// What the server sends a client when it connects: the format it's
+/// speaking, what it can do, the time by its clock, then the type of laser
+/// it's hosting in that format.
+fn handshake(laser_type : &LaserType, format : WireFormat, time : ServerTime) -> Result<Vec<u8>, TcpError> {
+    let mut buf = codec_line(format);
+    buf.extend(Capabilities::all().line());
+    buf.extend(frame(TIME_MARKER, &time, format)?);
+    buf.extend(frame(LASER_ID, laser_type, format)?);
+    Ok(buf)
+}
+
+/// The response to a command the laser carried out (`COMMAND_SUCCESSFUL`)
+/// or refused (the error, then its error frame and `COMMAND_FAILED`).
+fn command_response(result : &Result<(), CoherentError>, format : WireFormat) -> Vec<u8> {
+    match result {
+        Ok(_) => COMMAND_SUCCESSFUL.to_vec(),
+        Err(error) => {
+            let mut buf = frame(ERROR_MARKER, error, format).unwrap_or_default();
+            buf.extend(refusal(&ErrorFrame::from(error), format));
+            buf
+        },
     }
-    else {
-        Err(TcpError::NoLaserStatus)
+}
+
+/// The response to a request the server refused for `code`.
+fn error_response(code : ErrorCode, detail : Option<&str>, format : WireFormat) -> Vec<u8> {
+    refusal(&ErrorFrame::new(code, detail), format)
+}
+
+/// `error`'s frame, then the legacy line for its code.
+fn refusal(error : &ErrorFrame, format : WireFormat) -> Vec<u8> {
+    let mut buf = frame(ERROR_FRAME_MARKER, error, format).unwrap_or_default();
+    buf.extend(error.code.legacy_line());
+    buf
+}
+
+/// The server's reply to a frame longer than `limit`.
+fn frame_too_large(limit : usize) -> Vec<u8> {
+    [FRAME_TOO_LARGE_MARKER, limit.to_string().as_bytes(), TERMINATOR].concat()
+}
+
+/// The limit in a `frame_too_large` reply anywhere in `response`.
+fn frame_too_large_limit(response : &[u8]) -> Option<usize> {
+    let start = response.windows(FRAME_TOO_LARGE_MARKER.len()).position(|window| window == FRAME_TOO_LARGE_MARKER)?
+        + FRAME_TOO_LARGE_MARKER.len();
+    let rest = &response[start..];
+    let end = rest.iter().position(|&b| b == TERMINATOR[0])?;
+    std::str::from_utf8(&rest[..end]).ok()?.parse().ok()
+}
+
+/// The error behind a `COMMAND_FAILED` in `response`: `TcpError::Remote`
+/// if the server sent the `CoherentError`, `TcpError::Refused` if it only
+/// sent an error frame, `TcpError::CommandError` if it didn't say why.
+fn failure_reason(response : &[u8], format : WireFormat) -> TcpError {
+    if let Ok(error) = deserialize_after::<CoherentError>(response, ERROR_MARKER, format, true) {
+        return TcpError::Remote(error);
+    }
+    match deserialize_after::<ErrorFrame>(response, ERROR_FRAME_MARKER, format, true) {
+        Ok(refusal) if refusal.code == ErrorCode::NotPrimaryClient => TcpError::NotPrimaryClient,
+        Ok(refusal) => TcpError::Refused(refusal),
+        Err(_) => TcpError::CommandError,
+    }
+}
+
+/// The rest of `stream` after a leading trace frame, if it has one.
+fn skip_trace_frame(stream : &[u8]) -> &[u8] {
+    match stream.strip_prefix(TRACE_MARKER) {
+        Some(rest) => rest.iter().position(|&b| b == TERMINATOR[0])
+            .map(|end| &rest[end + 1..])
+            .unwrap_or(&[]),
+        None => stream,
     }
 }
 
+/// The MessagePack encoding of `command`, used to match a confirmation to
+/// the command it confirms whatever format the two clients speak.
+fn encode_command<L : Laser>(command : &L::CommandEnum) -> Option<Vec<u8>> {
+    let mut buf = Vec::new();
+    command.serialize(&mut Serializer::new(&mut buf)).ok()?;
+    Some(buf)
+}
+
 /// Reads a laser type from a stream and returns a `Result` with the `LaserType`
 /// or a `TcpError`. Looks for the `LASER_ID` and the `TERMINATOR` in the stream.
 /// 
 /// # Example
-/// ```rust
+/// ```ignore
 /// use coherent_rs::laser::LaserType;
 /// use coherent_rs::network::{LASER_ID, deserialize_laser_type, TERMINATOR};
 /// use serde::Serialize;
@@ -158,31 +705,119 @@ fn deserialize_command<L : Laser>(stream : &[u8]) -> Result<L::CommandEnum, TcpE
 /// tp.serialize(&mut Serializer::new(&mut buf)).unwrap();
 /// buf.extend(TERMINATOR);
 /// 
-/// let laser_type = deserialize_laser_type(&buf).unwrap();
+/// let laser_type = deserialize_laser_type(&buf, WireFormat::MessagePack).unwrap();
 /// 
 /// assert_eq!(laser_type, LaserType::DebugLaser);
 /// 
 /// ```
-fn deserialize_laser_type(stream : &[u8]) -> Result<LaserType, TcpError> {
-    if let Some(start_idx) = stream.windows(LASER_ID.len()).position(
-        |window| window == LASER_ID
-    ){
-        let laser_type = &stream[start_idx + LASER_ID.len()..];
-        if let Some(end) = laser_type.split(|&x| x == TERMINATOR[0]).next() {
-            let serialized = &laser_type[..end.len()];
-            LaserType::deserialize(
-                &mut rmp_serde::Deserializer::new(serialized)
-            ).map_err(|e| TcpError::SerializationDecodeError(e))
+fn deserialize_laser_type(stream : &[u8], format : WireFormat) -> Result<LaserType, TcpError> {
+    deserialize_after(stream, LASER_ID, format, false)
+}
+
+/// The `WireFormat` a server announced in `stream` (see `codec`), if it's
+/// there. Servers from before codecs only speak MessagePack, and say nothing.
+fn announced_format(stream : &[u8]) -> Option<Result<WireFormat, TcpError>> {
+    let start = stream.windows(CODEC_MARKER.len()).position(|window| window == CODEC_MARKER)?;
+    let rest = &stream[start + CODEC_MARKER.len()..];
+    let end = rest.iter().position(|&b| b == TERMINATOR[0])?;
+    let name = String::from_utf8_lossy(&rest[..end]);
+    Some(WireFormat::from_name(&name).ok_or(TcpError::CoherentError(
+        CoherentError::InvalidResponseError(format!("Server speaks unknown format {}", name))
+    )))
+}
+
+/// What a server said about itself in its handshake.
+struct Handshake {
+    format : WireFormat,
+    capabilities : Capabilities,
+    laser_type : LaserType,
+    /// From the server's time, if it sent it
+    clock : Option<ClockOffset>,
+}
+
+/// Blocks until the server's handshake has been read off a freshly-connected
+/// stream, to which the client started connecting at `connecting` (a
+/// `unix_timestamp`). Older servers announce only the `LaserType`, and speak
+/// MessagePack with none of the `capabilities` and no `clock`.
+fn read_handshake(stream : &TcpStream, connecting : f64, limits : ReadLimits) -> Result<Handshake, TcpError> {
+    read_until(stream, None, limits, |data| {
+        let laser_id = data.windows(LASER_ID.len()).position(|window| window == LASER_ID)?;
+        let format = match announced_format(&data[..laser_id]) {
+            Some(Ok(format)) => format,
+            Some(Err(e)) => return Some(Err(e)),
+            None => WireFormat::MessagePack,
+        };
+        let capabilities = Capabilities::parse(&data[..laser_id]);
+        let clock = deserialize_after::<ServerTime>(&data[..laser_id], TIME_MARKER, format, false).ok()
+            .map(|time| ClockOffset::from_exchange(connecting, time, unix_timestamp()));
+        deserialize_laser_type(data, format).ok()
+            .map(|laser_type| Ok(Handshake{format, capabilities, laser_type, clock}))
+    }).and_then(|handshake| handshake)
+}
+
+/// Asks the server to speak `format` to this client from now on, unless
+/// it already is.
+fn request_format(mut stream : &TcpStream, handshake : &Handshake, format : WireFormat, limits : ReadLimits) -> Result<(), TcpError> {
+    let current = handshake.format;
+    if current == format { return Ok(()); }
+    handshake.capabilities.require(Capability::Codec)?;
+    stream.write_all(&codec_line(format)).map_err(TcpError::IoError)?;
+    read_until(stream, None, limits, |response| {
+        if response.windows(COMMAND_SUCCESSFUL.len()).any(|window| window == COMMAND_SUCCESSFUL) {
+            Some(Ok(()))
         }
-        else {
-            Err(TcpError::NoLaserStatus)
+        else if response.windows(COMMAND_FAILED.len()).any(|window| window == COMMAND_FAILED) {
+            Some(Err(failure_reason(response, current)))
         }
+        else { None }
+    }).and_then(|response| response)
+}
+
+/// A laser status with its concrete type erased: field name to value.
+pub type StatusMap = BTreeMap<String, StatusValue>;
+
+/// Flattens any serializable status struct into a `StatusMap`,
+/// keyed by field name.
+/// 
+/// # Example
+/// 
+/// ```rust
+/// use coherent_rs::laser::{Laser, StatusValue, debug::DebugLaser};
+/// use coherent_rs::network::to_status_map;
+/// 
+/// let mut laser = DebugLaser::default();
+/// let map = to_status_map(&laser.status().unwrap()).unwrap();
+/// assert_eq!(map["variable_shutter"], StatusValue::Text("Closed".to_string()));
+/// ```
+pub fn to_status_map<S : Serialize>(status : &S) -> Result<StatusMap, TcpError> {
+    let mut buf = Vec::new();
+    status.serialize(&mut Serializer::new(&mut buf).with_struct_map())
+        .map_err(TcpError::SerializationEncodeError)?;
+    rmp_serde::from_slice(&buf).map_err(TcpError::SerializationDecodeError)
+}
+
+/// Reads a laser status from a stream like `deserialize_laser_status`, but
+/// picks the status type at runtime from the `LaserType` the server reported.
+fn deserialize_status_map(laser_type : &LaserType, stream : &[u8], format : WireFormat) -> Result<StatusMap, TcpError> {
+    struct StatusMapReader<'a> {
+        stream : &'a [u8],
+        format : WireFormat,
     }
-    else {
-        Err(TcpError::NoLaserStatus)
+
+    impl LaserTypeVisitor for StatusMapReader<'_> {
+        type Output = Result<StatusMap, TcpError>;
+
+        fn visit<L : Laser>(self) -> Self::Output {
+            to_status_map(&deserialize_laser_status::<L>(self.stream, self.format)?)
+        }
+
+        fn unrecognized(self) -> Self::Output {
+            Err(TcpError::CoherentError(CoherentError::UnrecognizedDevice))
+        }
     }
-}
 
+    laser_type.visit(StatusMapReader{stream, format})
+}
 
 /// Create a network listener that listens on the specified port.
 /// Takes ownership over the `Laser` so that it can be polled and
@@ -190,10 +825,32 @@ fn deserialize_laser_type(stream : &[u8]) -> Result<LaserType, TcpError> {
 /// a `Mutex`.
 /// 
 /// Default polling interval is 1 second.
+#[deprecated(note = "use `NetworkLaserServer::new`, which takes the polling interval as a `Duration`")]
 pub fn create_listener<L : Laser + 'static>(laser : L, port : &str, polling_interval : Option<f32>) -> Result<NetworkLaserServer<L>, TcpError> {
+    let polling_interval = polling_interval.map(polling_interval_from_secs).transpose()?;
     NetworkLaserServer::new(laser, port, polling_interval)
 }
 
+/// `interval` if it's within `L`'s `POLLING_INTERVAL_BOUNDS`, otherwise
+/// `InvalidArgumentsError`.
+pub fn check_polling_interval<L : Laser>(interval : Duration) -> Result<Duration, CoherentError> {
+    let (shortest, longest) = L::POLLING_INTERVAL_BOUNDS;
+    if interval < shortest || interval > longest {
+        return Err(CoherentError::InvalidArgumentsError(format!(
+            "Polling interval {:?} is outside the {:?} to {:?} allowed for {:?}",
+            interval, shortest, longest, L::into_laser_type()
+        )));
+    }
+    Ok(interval)
+}
+
+/// For the deprecated `f32` second APIs.
+fn polling_interval_from_secs(seconds : f32) -> Result<Duration, TcpError> {
+    Duration::try_from_secs_f32(seconds).map_err(|_| TcpError::CoherentError(
+        CoherentError::InvalidArgumentsError(format!("Polling interval {} s is not a duration", seconds))
+    ))
+}
+
 impl<L : Laser + 'static> Clone for NetworkLaserServer<L> {
     fn clone(&self) -> Self {
         NetworkLaserServer {
@@ -205,7 +862,22 @@ impl<L : Laser + 'static> Clone for NetworkLaserServer<L> {
             _clients : Arc::new(Mutex::new(Vec::new())),
             _client_connection_thread : None,
             _command_thread : None,
-            _primary_client : self._primary_client.clone(),
+            _primary_client : self._primary_client,
+            _confirmation_policy : self._confirmation_policy.clone(),
+            _coalesce_policy : self._coalesce_policy.clone(),
+            _authorizer : self._authorizer.clone(),
+            _reservation_check : self._reservation_check.clone(),
+            _lock_retry_policy : self._lock_retry_policy.clone(),
+            _command_timeout : self._command_timeout,
+            _stats : self._stats.clone(),
+            _wire_format : self._wire_format.clone(),
+            _config_path : self._config_path.clone(),
+            _config_thread : None,
+            _max_frame_size : self._max_frame_size,
+            _admin_token : self._admin_token.clone(),
+            _progress_watches : Arc::new(Mutex::new(Vec::new())),
+            _sweep : self._sweep.clone(),
+            _cancel_token : self._cancel_token.clone(),
         }
     }
 }
@@ -224,23 +896,61 @@ impl<L : Laser + 'static> NetworkLaserServer<L> {
     /// 
     /// * `laser` - The laser to control over the network.
     /// * `port` - The port to listen on.
-    /// * `polling_interval` - The interval in seconds to poll the laser. Be sure to
-    /// check the documentation for each laser and make sure it can reasonably be expected
-    /// to be polled at the specified interval. Recommended to be at least 200 milliseconds.
-    pub fn new(laser : L, port : &str, polling_interval : Option<f32>) -> Result<Self, TcpError> {
+    /// * `polling_interval` - How long to wait between polls of the laser. Be sure to
+    ///   check the documentation for each laser and make sure it can reasonably be expected
+    ///   to be polled at the specified interval. Recommended to be at least 200 milliseconds.
+    ///   Fails with `InvalidArgumentsError` outside the laser's `POLLING_INTERVAL_BOUNDS`.
+    pub fn new(laser : L, port : &str, polling_interval : Option<Duration>) -> Result<Self, TcpError> {
+        Self::from_shared(SharedLaser::new(laser), port, polling_interval)
+    }
+
+    /// Like `new`, with the polling interval in seconds.
+    #[deprecated(note = "use `new`, which takes the polling interval as a `Duration`")]
+    pub fn new_secs(laser : L, port : &str, polling_interval : Option<f32>) -> Result<Self, TcpError> {
+        let polling_interval = polling_interval.map(polling_interval_from_secs).transpose()?;
+        Self::new(laser, port, polling_interval)
+    }
+
+    /// Like `new`, but hosts a laser that's also used elsewhere in this
+    /// process, e.g. by a GUI. Clients' commands and local calls through
+    /// any other clone of `laser` take turns on its lock.
+    pub fn from_shared(laser : SharedLaser<L>, port : &str, polling_interval : Option<Duration>) -> Result<Self, TcpError> {
+        let polling_interval = check_polling_interval::<L>(polling_interval.unwrap_or(Duration::from_secs(1)))
+            .map_err(TcpError::CoherentError)?;
         let listener = TcpListener::bind(port)
-        .map_err(|e| TcpError::IoError(e))?;
+        .map_err(TcpError::IoError)?;
+        let cancel_token = acquire(LockLevel::Laser, || laser.lock())
+            .map_err(|_| TcpError::MutexPoisoned)?
+            .cancel_token();
 
         let nl = NetworkLaserServer {
             _listener : listener,
-            _laser : Some(Arc::new(Mutex::new(laser))),
-            _polling_interval : Arc::new(Mutex::new(polling_interval.unwrap_or(1.0))),
+            _laser : Some(laser),
+            _polling_interval : Arc::new(Mutex::new(polling_interval)),
             _polling_thread : None,
             _polling : Arc::new(AtomicBool::new(false)),
             _clients : Arc::new(Mutex::new(Vec::new())),
             _client_connection_thread : None,
             _command_thread : None,
             _primary_client : None,
+            _confirmation_policy : Arc::new(Mutex::new(None)),
+            _coalesce_policy : Arc::new(Mutex::new(None)),
+            _authorizer : Arc::new(Mutex::new(None)),
+            _reservation_check : Arc::new(Mutex::new(None)),
+            _lock_retry_policy : RetryPolicy::new(u32::MAX)
+                .with_backoff(std::time::Duration::from_millis(5), 1.5)
+                .with_max_backoff(std::time::Duration::from_millis(50))
+                .with_total_timeout(std::time::Duration::from_millis(500)),
+            _command_timeout : Some(DEFAULT_COMMAND_TIMEOUT),
+            _stats : Arc::new(Mutex::new(StatsRecorder::default())),
+            _wire_format : Arc::new(Mutex::new(WireFormat::default())),
+            _config_path : None,
+            _config_thread : None,
+            _max_frame_size : DEFAULT_MAX_FRAME_SIZE,
+            _admin_token : None,
+            _progress_watches : Arc::new(Mutex::new(Vec::new())),
+            _sweep : Arc::new(SweepControl::default()),
+            _cancel_token : cancel_token,
         };
 
         Ok(nl)
@@ -251,10 +961,98 @@ impl<L : Laser + 'static> NetworkLaserServer<L> {
         self._listener.local_addr().unwrap().port().to_string()
     }
 
-    /// Sets the polling interval in seconds
-    pub fn set_polling_interval(&mut self, interval : f32) {
-        let mut polling_interval = self._polling_interval.lock().unwrap();
-        *polling_interval = interval;
+    /// Returns the full address the listener is bound to -- useful when
+    /// the server was created on port 0 and the OS picked the port.
+    pub fn local_addr(&self) -> Result<std::net::SocketAddr, TcpError> {
+        self._listener.local_addr().map_err(TcpError::IoError)
+    }
+
+    /// Sets how long to wait between polls of the laser. Fails with
+    /// `InvalidArgumentsError`, changing nothing, outside the laser's
+    /// `POLLING_INTERVAL_BOUNDS`.
+    pub fn set_polling_interval(&mut self, interval : Duration) -> Result<(), TcpError> {
+        let interval = check_polling_interval::<L>(interval).map_err(TcpError::CoherentError)?;
+        let mut polling_interval = acquire(LockLevel::PollingInterval, || self._polling_interval.lock())?;
+        **polling_interval = interval;
+        Ok(())
+    }
+
+    /// Sets the polling interval in seconds.
+    #[deprecated(note = "use `set_polling_interval`, which takes a `Duration`")]
+    pub fn set_polling_interval_secs(&mut self, interval : f32) -> Result<(), TcpError> {
+        self.set_polling_interval(polling_interval_from_secs(interval)?)
+    }
+
+    pub fn polling_interval(&self) -> Duration {
+        **acquire(LockLevel::PollingInterval, || self._polling_interval.lock()).unwrap()
+    }
+
+    /// Sets the format clients are spoken to in until they ask for another
+    /// (see `codec`). Clients already connected keep theirs.
+    pub fn set_wire_format(&mut self, format : WireFormat) {
+        **acquire(LockLevel::WireFormat, || self._wire_format.lock()).unwrap() = format;
+    }
+
+    /// The format new clients are spoken to in.
+    pub fn wire_format(&self) -> WireFormat {
+        **acquire(LockLevel::WireFormat, || self._wire_format.lock()).unwrap()
+    }
+
+    /// Applies `config`'s settings now, without disconnecting anyone.
+    /// Fails, changing nothing, if it sets soft limits and the laser has
+    /// none.
+    pub fn apply_config(&self, config : &ServerConfig) -> Result<(), TcpError> {
+        let mut laser = self.guarded_laser()?;
+        config::apply(config, &mut **laser, &self._polling_interval, &self._wire_format)
+            .map_err(TcpError::CoherentError)
+    }
+
+    /// Applies the config file at `path` now, and from the next time `poll`
+    /// starts the threads, again whenever the file changes or a client
+    /// sends `RELOAD_CONFIG`. A changed file with any problem is reported on
+    /// stderr and changes nothing. See `config::ServerConfig`.
+    pub fn load_config<P : AsRef<std::path::Path>>(&mut self, path : P) -> Result<(), TcpError> {
+        self.apply_config(&ServerConfig::load(&path).map_err(TcpError::CoherentError)?)?;
+        self._config_path = Some(path.as_ref().to_path_buf());
+        Ok(())
+    }
+
+    /// Sets how long the command thread keeps trying for the laser while
+    /// something else (e.g. a status poll, or a local `with_laser`) has it,
+    /// before replying `LaserBusyError` to the client. Only busy-ness is
+    /// retried, whatever `policy.retryable` says. Takes effect the next
+    /// time `poll` starts the threads. Defaults to retrying every 5-50 ms
+    /// for up to 500 ms.
+    pub fn set_lock_retry_policy(&mut self, policy : RetryPolicy) {
+        self._lock_retry_policy = policy;
+    }
+
+    /// Sets how long the command thread waits for the laser to carry out a
+    /// client's command before replying `CommandTimedOut`, so that a hung
+    /// serial link doesn't stall every other client. The command runs on a
+    /// `COMMAND_WORKER_THREAD` of its own; if it does finally return, the
+    /// laser's `recover_link` is called before anything else gets it, and
+    /// until it does, other commands fail with `LaserBusyError`. With
+    /// `None`, commands run on the command thread and are waited for however
+    /// long they take. Takes effect the next time `poll` starts the threads.
+    /// Defaults to `DEFAULT_COMMAND_TIMEOUT`.
+    pub fn set_command_timeout(&mut self, timeout : Option<Duration>) {
+        self._command_timeout = timeout;
+    }
+
+    /// Sets the longest frame a client may send, in bytes. A longer one is
+    /// dropped, and the client told with a `FRAME_TOO_LARGE_MARKER` reply
+    /// (`TcpError::FrameTooLarge`). Takes effect the next time `poll`
+    /// starts the threads. Defaults to `DEFAULT_MAX_FRAME_SIZE`.
+    pub fn set_max_frame_size(&mut self, bytes : usize) {
+        self._max_frame_size = bytes;
+    }
+
+    /// Sets the token a client must send with an `admin::AdminRawCommand`,
+    /// or with `None`, refuses them all. Takes effect the next time `poll`
+    /// starts the threads. Refused by default.
+    pub fn set_admin_token(&mut self, token : Option<&str>) {
+        self._admin_token = token.map(str::to_string);
     }
 
     /// Returns the laser and kills the `NetworkLaserServer`. Stops polling as well.
@@ -264,13 +1062,18 @@ impl<L : Laser + 'static> NetworkLaserServer<L> {
         self.stop_polling();
         for client in self._clients.lock().unwrap().iter_mut() {
             client.shutdown(std::net::Shutdown::Both)
-                .map_err(|e| TcpError::IoError(e))?;
+                .map_err(TcpError::IoError)?;
         }
         self._clients.lock().unwrap().clear();
-        Arc::try_unwrap(self._laser.take()
-            .ok_or(TcpError::MultipleReferencesToLaser)?)
-            .map(|l| l.into_inner().unwrap())
-            .map_err(|_| TcpError::MutexPoisoned)
+        self._laser.take()
+            .ok_or(TcpError::MultipleReferencesToLaser)?
+            .into_inner()
+            .map_err(|_| TcpError::MultipleReferencesToLaser)
+    }
+
+    /// A handle to the hosted laser that can be passed to other threads.
+    pub fn shared_laser(&self) -> Option<SharedLaser<L>> {
+        self._laser.clone()
     }
 
     /// Shorthand for unpacking the laser from the mutex.
@@ -281,47 +1084,55 @@ impl<L : Laser + 'static> NetworkLaserServer<L> {
     /// ```rust
     /// 
     /// ```
-    fn guarded_laser(&self) -> Result<MutexGuard<'_, L>, TcpError> {
-        self._laser.as_ref()
-            .ok_or(TcpError::CommandError)?
-            .lock()
-            .map_err(|_| TcpError::MutexPoisoned)
+    fn guarded_laser(&self) -> Result<locking::Ordered<MutexGuard<'_, L>>, TcpError> {
+        let laser = self._laser.as_ref().ok_or(TcpError::CommandError)?;
+        acquire(LockLevel::Laser, || laser.lock()).map_err(|_| TcpError::MutexPoisoned)
     }
 
     /// Initializes the polling thread. Does nothing if already listening for connections.
+    ///
+    /// Starts three named threads, so a debugger, `top -H` or a panic message
+    /// shows which one is stuck when the laser stops responding:
+    /// `ACCEPT_THREAD` (takes new clients), `POLLING_THREAD` (reads the status
+    /// and broadcasts it), and `COMMAND_THREAD` (reads requests from every
+    /// client and runs their commands). With a config file (see
+    /// `load_config`), a fourth, `CONFIG_THREAD`, watches it for changes.
+    /// The server doesn't run on an async runtime, so there are no tasks for
+    /// `tokio-console` to show -- these names are what to look for instead.
     pub fn poll(&mut self) -> Result<(), TcpError> {
         if self._polling_thread.is_some() {
             return Ok(())
         }
 
-        let _listener = self._listener.try_clone().map_err(|e| TcpError::IoError(e))?;
-        _listener.set_nonblocking(true).map_err(|e| TcpError::IoError(e))?;
+        let _listener = self._listener.try_clone().map_err(TcpError::IoError)?;
+        _listener.set_nonblocking(true).map_err(TcpError::IoError)?;
 
         self._polling.store(true, std::sync::atomic::Ordering::SeqCst);
         let _polling = self._polling.clone();
         let _clients = Arc::clone(&self._clients);
+        let _wire_format = Arc::clone(&self._wire_format);
 
         // Looks for new clients, identifies the type of laser and sends the status.
-        self._client_connection_thread = Some(std::thread::spawn( move || {
+        self._client_connection_thread = Some(std::thread::Builder::new().name(ACCEPT_THREAD.to_string()).spawn( move || {
             while _polling.load(std::sync::atomic::Ordering::SeqCst) {
                 match _listener.accept() {
                 // for stream in _listener.incoming() {
-                    Ok((mut stream, _)) => {
-                            let mut self_id = LASER_ID.to_vec();
-                            if L::into_laser_type().serialize(
-                                &mut Serializer::new(&mut self_id))
-                                .is_err(){ continue; } // is this ok?
-                                // .map_err(|e| TcpError::SerializationEncodeError(e)).unwrap();
-                            self_id.extend(TERMINATOR);
-                            stream.write_all(&self_id).unwrap();
-                            stream.set_read_timeout(Some(std::time::Duration::from_millis(100)))
-                                .unwrap();
-                            let mut clients = _clients.lock().unwrap();
-                            clients.push(stream);
+                    Ok((mut stream, address)) => {
+                            // Say what format we speak, then what we're hosting in it
+                            let time = ServerTime::at(unix_timestamp());
+                            let format = **acquire(LockLevel::WireFormat, || _wire_format.lock()).unwrap();
+                            let Ok(self_id) = handshake(&L::into_laser_type(), format, time) else { continue; };
+                            // A client that's gone before it's heard the handshake is never added
+                            if stream.write_all(&self_id).is_err()
+                                || stream.set_read_timeout(Some(CLIENT_READ_TIMEOUT)).is_err() {
+                                continue;
+                            }
+                            let mut clients = acquire(LockLevel::Clients, || _clients.lock()).unwrap();
+                            clients.push(Connection::new(stream, address, format));
                             drop(clients);
                         },
                         // Err(_) => {}
-                        Err(ref e) => {
+                        Err(_e) => {
                             // if e.kind() == std::io::ErrorKind::WouldBlock {
                             //     // No new clients, continue polling
                             //     std::thread::sleep(std::time::Duration::from_millis(10));
@@ -334,146 +1145,641 @@ impl<L : Laser + 'static> NetworkLaserServer<L> {
                     break;
                 }   
             }
-        }));
+        }).map_err(TcpError::IoError)?);
 
 
         let _polling_interval = self._polling_interval.clone();
         let _laser = self._laser.clone();
         let _polling = self._polling.clone();
         let _clients = Arc::clone(&self._clients);
-
-        // Polls the laser, passes it to all the clients.
-        self._polling_thread = Some(std::thread::spawn( move || {
+        let _stats = Arc::clone(&self._stats);
+        let _progress_watches = Arc::clone(&self._progress_watches);
+
+        // Polls the laser, passes it to all the clients, and tells clients
+        // waiting on an operation how it's going. The laser is released
+        // before the clients are locked, so a slow status read never holds
+        // up the command thread's reads from the clients.
+        self._polling_thread = Some(std::thread::Builder::new().name(POLLING_THREAD.to_string()).spawn( move || {
+            // Each poll is due an interval after the last was, however long
+            // the read took, so the rate doesn't drift with the serial link
+            let mut rate = schedule::FixedRate::new();
             while _polling.load(std::sync::atomic::Ordering::SeqCst) { 
-                let mut clients = _clients.lock().unwrap();
-                let mut laser_lock : MutexGuard<'_, L>;
-                if let Some(ref_laser) = _laser.as_ref() {
-                    if let Ok(l) = ref_laser.lock() { laser_lock = l ;}
-                    else {
-                        _polling.store(false, std::sync::atomic::Ordering::SeqCst);
-                        return;
-                    }
-                }
-                else{_polling.store(false, std::sync::atomic::Ordering::SeqCst);
+                let Some(ref_laser) = _laser.as_ref() else {
+                    _polling.store(false, std::sync::atomic::Ordering::SeqCst);
                     return;
-                }
-                let serialized = match laser_lock.serialized_status() {
-                    Ok(serialized) => {serialized},
-                    Err(_) => {
-                        continue;
-                    }
                 };
-
+                let started = std::time::Instant::now();
+                let Ok(mut laser_lock) = acquire(LockLevel::Laser, || ref_laser.lock()) else {
+                    _polling.store(false, std::sync::atomic::Ordering::SeqCst);
+                    return;
+                };
+                let status = laser_lock.status();
+                let mut progress : Vec<(std::net::SocketAddr, Progress)> = Vec::new();
+                let mut watches = acquire(LockLevel::Progress, || _progress_watches.lock()).unwrap();
+                if !watches.is_empty() {
+                    let tuning = laser_lock.tuning_progress().ok().flatten();
+                    // Each client hears until its operation is done
+                    watches.retain(|watch| match watch.progress(tuning.as_ref()) {
+                        Some(update) => {
+                            progress.push((watch.client, update));
+                            !update.is_done()
+                        },
+                        None => false,
+                    });
+                }
+                drop(watches);
                 drop(laser_lock);
-                clients.retain(|mut client| {
-                    // Write all in one line
-                    let mut to_write = STATUS_MARKER.to_vec();
-                    to_write.extend(serialized.clone());
-                    to_write.extend(TERMINATOR);
-                    client.write_all(&to_write).is_ok()
-                });
-                drop(clients);
-                std::thread::sleep(std::time::Duration::from_millis(
-                    (*_polling_interval.lock().unwrap() * 1000.0) as u64
-                ));
+
+                if let Ok(status) = status {
+                    let mut clients = acquire(LockLevel::Clients, || _clients.lock()).unwrap();
+                    let mut stats = acquire(LockLevel::Stats, || _stats.lock()).unwrap();
+                    // Encoded once for each format the clients speak
+                    let mut encoded : Vec<(WireFormat, Vec<u8>)> = Vec::new();
+                    clients.retain(|client| {
+                        if !encoded.iter().any(|(format, _)| *format == client.format) {
+                            let Ok(to_write) = frame(STATUS_MARKER, &status, client.format) else { return true; };
+                            encoded.push((client.format, to_write));
+                        }
+                        let (_, to_write) = encoded.iter().find(|(format, _)| *format == client.format).unwrap();
+                        let sending = std::time::Instant::now();
+                        let sent = (&client.stream).write_all(to_write).is_ok();
+                        if let (true, Ok(address)) = (sent, client.peer_addr()) {
+                            stats.sent(address, to_write.len(), sending.elapsed());
+                            for (_, update) in progress.iter().filter(|(watcher, _)| *watcher == address) {
+                                if let Ok(to_write) = frame(PROGRESS_MARKER, update, client.format) {
+                                    let _ = (&client.stream).write_all(&to_write);
+                                }
+                            }
+                        }
+                        sent
+                    });
+                    stats.retain_clients(&clients.iter().filter_map(|client| client.peer_addr().ok()).collect::<Vec<_>>());
+                    stats.broadcast(started.elapsed());
+                    drop(stats);
+                    drop(clients);
+                }
+                let interval = **acquire(LockLevel::PollingInterval, || _polling_interval.lock()).unwrap();
+                if let Some(overrun) = rate.wait(interval) {
+                    #[cfg(feature = "tracing")]
+                    ::tracing::warn!(overrun_ms = overrun.as_secs_f64() * 1e3, "status poll overran the polling interval");
+                    acquire(LockLevel::Stats, || _stats.lock()).unwrap().poll_overrun(overrun);
+                }
             }
-        }));
+        }).map_err(TcpError::IoError)?);
 
         // Investigates the clients for commands, deserializes them, then executes
         // them on the laser.
 
         let _command_interval_ms = 50; //milliseconds
-        let _laser = Arc::clone(&self._laser.as_ref().unwrap());
+        let _laser = self._laser.clone().unwrap();
         let _clients = Arc::clone(&self._clients);
         let _polling = self._polling.clone();
-        let mut _primary_client = self._primary_client.clone();
-
-        self._command_thread = Some(std::thread::spawn( move || {
+        let mut _primary_client = self._primary_client;
+        let _confirmation_policy = Arc::clone(&self._confirmation_policy);
+        let _coalesce_policy = Arc::clone(&self._coalesce_policy);
+        let _authorizer = Arc::clone(&self._authorizer);
+        let _reservation_check = Arc::clone(&self._reservation_check);
+        let _lock_retry_policy = self._lock_retry_policy.clone();
+        let _command_timeout = self._command_timeout;
+        let _stats = Arc::clone(&self._stats);
+        let _config_path = self._config_path.clone();
+        let _polling_interval = Arc::clone(&self._polling_interval);
+        let _wire_format = Arc::clone(&self._wire_format);
+        let _max_frame_size = self._max_frame_size;
+        let _admin_token = self._admin_token.clone();
+        let _progress_watches = Arc::clone(&self._progress_watches);
+        let _sweep = Arc::clone(&self._sweep);
+        let _cancel_token = self._cancel_token.clone();
+
+        self._command_thread = Some(std::thread::Builder::new().name(COMMAND_THREAD.to_string()).spawn( move || {
+            // Commands held for a second client's confirmation
+            let mut pending : Vec<PendingCommand<L>> = Vec::new();
+            // Commands to run once the clients are let go
+            let mut deferred : Vec<DeferredCommand<L>> = Vec::new();
+            // Whether a `send_command_within` worker is still going
+            let worker_running = Arc::new(AtomicBool::new(false));
             while _polling.load(std::sync::atomic::Ordering::SeqCst) {
-                match _clients.lock() {
-                Err(_) => {
-                    // Mutex is poisoned, stop polling
-                    eprintln!("Clients mutex poisoned, stopping command thread.");
-                    return;
-                },
-                Ok(mut clients) => {        
-                // Iterate across all connected clients
-                for client in clients.iter_mut() {
-                    let mut buf_ptr = 0;
-                    let mut buf = [0u8; 1024];
-                    match client.read(&mut buf) {
-                        Ok(n) => {
-                            buf_ptr += n;
-                            // Resolve successful reads in order as:
-                            // 1. Forget primary client
-                            // 2. Demand primary client
-                            // 3. Forget me
-                            // 4. Command
-
-                            if buf[0..buf_ptr].starts_with(FORGET_PRIMARY_CLIENT) {
-                                if let Some(primary_client) = _primary_client.take() {
-                                    if primary_client.try_lock().is_ok() {
-                                        client.write_all(COMMAND_SUCCESSFUL).unwrap();
+                match acquire(LockLevel::Clients, || _clients.lock()) {
+                    Err(_) => {
+                        // Mutex is poisoned, stop polling
+                        eprintln!("Clients mutex poisoned, stopping command thread.");
+                        return;
+                    },
+                    Ok(mut clients) => {
+                        // Iterate across all connected clients
+                        for client in clients.iter_mut() {
+                            let mut read = [0u8; 1024];
+                            // A client with frames queued has them taken first
+                            let incoming = if client.queued.is_empty() { client.read(&mut read) } else { Ok(0) };
+                            if matches!(incoming, Ok(0)) && client.queued.is_empty() {
+                                client.disconnected = true; // closed its end
+                                continue;
+                            }
+                            if let Ok(n) = incoming {
+                                let received = std::time::Instant::now();
+                                let received_at = unix_timestamp();
+                                let format = client.format;
+                                // Frames longer than one read are put together
+                                // over several passes, and several in one read
+                                // are queued and taken a pass at a time.
+                                let mut coalesce_policy = acquire(LockLevel::CoalescePolicy, || _coalesce_policy.lock()).unwrap();
+                                let assembled = client.assemble(&read[..n], _max_frame_size, |frame| {
+                                    let policy = coalesce_policy.as_mut()?;
+                                    policy.class_of(&deserialize_command::<L>(skip_trace_frame(frame), format).ok()?)
+                                });
+                                drop(coalesce_policy);
+                                let buf = match assembled {
+                                    Ok(Some(Queued::Frame(buf))) => buf,
+                                    Ok(Some(Queued::Superseded)) => {
+                                        client.reply(&command_response(&Err(CoherentError::Superseded), format));
+                                        acquire(LockLevel::Stats, || _stats.lock()).unwrap().superseded();
+                                        continue;
+                                    },
+                                    Ok(None) => continue,
+                                    Err(_) => {
+                                        client.reply(&frame_too_large(_max_frame_size));
+                                        continue;
+                                    },
+                                };
+                                let buf_ptr = buf.len();
+                                // Resolve successful reads in order as:
+                                // 1. Forget primary client
+                                // 2. Demand primary client
+                                // 3. Forget me
+                                // 4. Lock / unlock
+                                // 5. Confirm
+                                // 6. Command
+                                // 7. Stats
+                                // 8. Time
+                                // 9. Reload config
+                                // 10. Faults
+                                // 11. Raw command
+                                // 12. Validate
+                                // 13. Start / abort sweep
+                                // 14. Abort
+                                // 15. Switch format
+
+                                if buf[0..buf_ptr].starts_with(FORGET_PRIMARY_CLIENT) {
+                                    _primary_client = None;
+                                    client.reply(COMMAND_SUCCESSFUL);
+                                }
+
+                                if buf[0..buf_ptr].starts_with(DEMAND_PRIMARY_CLIENT) {
+                                    let reserved = match acquire(LockLevel::ReservationCheck, || _reservation_check.lock()).unwrap().as_mut() {
+                                        Some(check) if _primary_client.is_none() => check.check(
+                                            &ClientInfo{address : client.address, primary : false}
+                                        ),
+                                        _ => Ok(()),
+                                    };
+                                    if let Err(reason) = reserved {
+                                        client.reply(&command_response(&Err(CoherentError::ReservationDenied(reason)), format));
+                                    }
+                                    else if _primary_client.is_none() {
+                                        _primary_client = Some(client.address);
+                                        client.reply(COMMAND_SUCCESSFUL);
                                     }
                                     else {
-                                        client.write_all(COMMAND_FAILED).unwrap();
+                                        client.reply(&error_response(ErrorCode::NotPrimaryClient, Some("Another client is primary"), format));
                                     }
                                 }
-                                else{
-                                    client.write_all(COMMAND_SUCCESSFUL).unwrap();
+
+                                if buf[0..buf_ptr].starts_with(FORGET_ME) {
+                                    if _primary_client == Some(client.address) {
+                                        _primary_client = None;
+                                        client.reply(COMMAND_SUCCESSFUL);
+                                    }
+                                    else {
+                                        client.reply(&error_response(ErrorCode::Failed, Some("This client isn't the primary client"), format));
+                                    }
                                 }
-                            }
 
-                            if buf[0..buf_ptr].starts_with(DEMAND_PRIMARY_CLIENT) {
-                                if _primary_client.is_none() {
-                                    _primary_client.replace(
-                                        Arc::new(Mutex::new(client.try_clone().unwrap()))
-                                    );
-                                    client.write_all(COMMAND_SUCCESSFUL).unwrap();
+                                // Lock or unlock the laser's `OperatorLock`. Locking shuts
+                                // everyone else out, so only the primary client may, if
+                                // there is one; any client holding the token may unlock --
+                                // the token is the key.
+                                for (marker, locking) in [(LOCK_MARKER, true), (UNLOCK_MARKER, false)] {
+                                    if locking && buf[0..buf_ptr].starts_with(marker) && !is_primary(client, &_primary_client) {
+                                        client.reply(&error_response(ErrorCode::NotPrimaryClient, None, format));
+                                    }
+                                    else if let Some(rest) = buf[0..buf_ptr].strip_prefix(marker) {
+                                        let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+                                        let token = String::from_utf8_lossy(&rest[..end]).into_owned();
+                                        let result = acquire(LockLevel::Laser, || _laser.try_lock_with(&_lock_retry_policy))
+                                            .and_then(|mut laser| match laser.operator_lock() {
+                                                Some(lock) if locking => lock.lock(&token),
+                                                Some(lock) => lock.unlock(&token),
+                                                None => Err(CoherentError::CommandNotExecutedError),
+                                            });
+                                        client.reply(&command_response(&result, format));
+                                    }
                                 }
-                                else {
-                                    client.write_all(NOT_PRIMARY_CLIENT).unwrap();
+
+                                // Confirm a held command: it runs, and both clients
+                                // hear the result.
+                                if let Ok(command) = deserialize_command_after::<L>(&buf[0..buf_ptr], CONFIRM_MARKER, format) {
+                                    let confirmer = client.address;
+                                    let encoded = encode_command::<L>(&command);
+                                    let policy = match acquire(LockLevel::ConfirmationPolicy, || _confirmation_policy.lock()) {
+                                        Ok(policy) => policy,
+                                        Err(_) => {
+                                            client.reply(&command_response(&Err(CoherentError::LaserUnavailableError), format));
+                                            continue;
+                                        },
+                                    };
+                                    let matching = pending.iter().position(|held| {
+                                        Some(&held.encoded) == encoded.as_ref() && policy.as_ref().map_or(
+                                            held.requester_address != confirmer,
+                                            |policy| policy.may_confirm(&held.requester_address, &confirmer)
+                                        )
+                                    });
+                                    drop(policy);
+                                    match matching {
+                                        Some(idx) => {
+                                            let mut held = pending.remove(idx);
+                                            if let Some(timeout) = _command_timeout {
+                                                let mut replies = vec![(held.requester, held.requester_format)];
+                                                replies.extend(client.try_clone().ok().map(|stream| (stream, format)));
+                                                deferred.push(DeferredCommand{
+                                                    command : held.command,
+                                                    origin : held.requester_address,
+                                                    received,
+                                                    timeout,
+                                                    replies,
+                                                    #[cfg(feature = "opentelemetry")]
+                                                    trace : None,
+                                                });
+                                                continue;
+                                            }
+                                            let mut lock_wait = std::time::Duration::ZERO;
+                                            let result = acquire(LockLevel::Laser, || _laser.try_lock_with(&_lock_retry_policy))
+                                                .and_then(|mut laser| {
+                                                    lock_wait = received.elapsed();
+                                                    send_command_from(&mut **laser, held.command, held.requester_address)
+                                                });
+                                            if result.is_ok() {
+                                                acquire(LockLevel::Progress, || _progress_watches.lock()).unwrap()
+                                                    .push(ProgressWatch{client : held.requester_address, since : received});
+                                            }
+                                            let _ = held.requester.write_all(&command_response(&result, held.requester_format));
+                                            client.reply(&command_response(&result, format));
+                                            acquire(LockLevel::Stats, || _stats.lock()).unwrap()
+                                                .command(held.requester_address, result.is_ok(), received.elapsed(), lock_wait);
+                                        },
+                                        None => {client.reply(&error_response(ErrorCode::NothingToConfirm, None, format));}
+                                    }
                                 }
-                            }
 
-                            if buf[0..buf_ptr].starts_with(FORGET_ME) {
-                                if _primary_client.is_some() &&
-                                    ( _primary_client.as_ref().unwrap().try_lock().unwrap().peer_addr().unwrap()
-                                    == client.peer_addr().unwrap()) {
-                                    _primary_client = None;
-                                    client.write_all(COMMAND_SUCCESSFUL).unwrap();
+                                // If a command is in the buffer, execute it.
+                                if let Ok(command) = deserialize_command::<L>(&buf[0..buf_ptr], format) {
+                                    #[cfg(feature = "opentelemetry")]
+                                    let mut trace = telemetry::ServerTrace::received(&buf[0..buf_ptr]);
+                                    // unless you're not the primary client
+                                    if !is_primary(client, &_primary_client) {
+                                        client.reply(&error_response(ErrorCode::NotPrimaryClient, None, format));
+                                        continue;
+                                    }
+                                    let authorization = authorize(&_authorizer, &_primary_client, client, &command);
+                                    if authorization == Authorization::Deny {
+                                        client.reply(&command_response(&Err(CoherentError::Unauthorized), format));
+                                        acquire(LockLevel::Stats, || _stats.lock()).unwrap()
+                                            .command(client.address, false, received.elapsed(), std::time::Duration::ZERO);
+                                        continue;
+                                    }
+                                    // Don't hold every other client up waiting for a busy laser
+                                    let mut laser = match acquire(LockLevel::Laser, || _laser.try_lock_with(&_lock_retry_policy)) {
+                                        Ok(laser) => laser,
+                                        Err(e) => {
+                                            client.reply(&command_response(&Err(e), format));
+                                            acquire(LockLevel::Stats, || _stats.lock()).unwrap()
+                                                .command(client.address, false, received.elapsed(), received.elapsed());
+                                            continue;
+                                        },
+                                    };
+                                    let lock_wait = received.elapsed();
+                                    #[cfg(feature = "opentelemetry")]
+                                    let _current = trace.dequeued();
+                                    // Hold hazardous commands -- the client hears back
+                                    // once a second client confirms, or it times out.
+                                    let mut policy = match acquire(LockLevel::ConfirmationPolicy, || _confirmation_policy.lock()) {
+                                        Ok(policy) => policy,
+                                        Err(_) => {
+                                            client.reply(&command_response(&Err(CoherentError::LaserUnavailableError), format));
+                                            continue;
+                                        },
+                                    };
+                                    if authorization == Authorization::RequireConfirmation
+                                        || policy.as_mut().is_some_and(|policy| policy.requires_confirmation(&mut laser, &command)) {
+                                        let timeout = policy.as_ref().map_or(DEFAULT_CONFIRMATION_TIMEOUT, |policy| policy.timeout);
+                                        hold_for_confirmation(&mut pending, client, format, command, timeout);
+                                        continue;
+                                    }
+                                    drop(policy);
+                                    if let Some(timeout) = _command_timeout {
+                                        drop(laser);
+                                        match client.try_clone() {
+                                            Ok(stream) => deferred.push(DeferredCommand{
+                                                command,
+                                                origin : client.address,
+                                                received,
+                                                timeout,
+                                                replies : vec![(stream, format)],
+                                                #[cfg(feature = "opentelemetry")]
+                                                trace : Some(trace),
+                                            }),
+                                            Err(_) => client.reply(&error_response(ErrorCode::Failed, None, format)),
+                                        }
+                                        continue;
+                                    }
+                                    let result = send_command_from(&mut **laser, command, client.address);
+                                    #[cfg(feature = "opentelemetry")]
+                                    trace.finish(&result);
+                                    // In case the command started something long-running
+                                    if result.is_ok() {
+                                        acquire(LockLevel::Progress, || _progress_watches.lock()).unwrap()
+                                            .push(ProgressWatch{client : client.address, since : received});
+                                    }
+                                    client.reply(&command_response(&result, format));
+                                    acquire(LockLevel::Stats, || _stats.lock()).unwrap()
+                                        .command(client.address, result.is_ok(), received.elapsed(), lock_wait);
                                 }
-                                else {
-                                    client.write_all(COMMAND_FAILED).unwrap();
+                                // A command meant for some other model -- tell the client
+                                // rather than leaving it waiting for a response.
+                                else if skip_trace_frame(&buf[0..buf_ptr]).starts_with(COMMAND_MARKER) {
+                                    client.reply(&error_response(ErrorCode::UnknownCommand, None, format));
+                                }
+
+                                if buf[0..buf_ptr].starts_with(STATS_REQUEST) {
+                                    let stats = acquire(LockLevel::Stats, || _stats.lock()).unwrap().snapshot();
+                                    match frame(STATS_MARKER, &stats, format) {
+                                        Ok(response) => {client.reply(&response);},
+                                        Err(_) => {client.reply(&error_response(ErrorCode::Failed, None, format));},
+                                    }
+                                }
+
+                                if buf[0..buf_ptr].starts_with(TIME_REQUEST) {
+                                    let time = ServerTime{received : received_at, sent : unix_timestamp()};
+                                    match frame(TIME_MARKER, &time, format) {
+                                        Ok(response) => {client.reply(&response);},
+                                        Err(_) => {client.reply(&error_response(ErrorCode::Failed, None, format));},
+                                    }
+                                }
+
+                                // Only the primary client, if there is one, may change
+                                // the server's settings.
+                                if buf[0..buf_ptr].starts_with(RELOAD_CONFIG) {
+                                    if !is_primary(client, &_primary_client) {
+                                        client.reply(&error_response(ErrorCode::NotPrimaryClient, None, format));
+                                    }
+                                    else {
+                                        let result = match &_config_path {
+                                            Some(path) => config::reload(path, &_laser, &_lock_retry_policy, &_polling_interval, &_wire_format),
+                                            None => Err(CoherentError::InvalidArgumentsError("The server has no config file".to_string())),
+                                        };
+                                        client.reply(&command_response(&result, format));
+                                    }
+                                }
+
+                                // Read the faults, clearing them first if asked. Clearing
+                                // is a command like any other: only the primary client
+                                // may, if there is one, and it's put to the authorizer
+                                // and the confirmation policy. A held clear answers like
+                                // a held command once it's confirmed.
+                                let clearing = buf[0..buf_ptr].starts_with(CLEAR_FAULTS);
+                                if clearing || buf[0..buf_ptr].starts_with(FAULTS_REQUEST) {
+                                    if clearing && !is_primary(client, &_primary_client) {
+                                        client.reply(&error_response(ErrorCode::NotPrimaryClient, None, format));
+                                        continue;
+                                    }
+                                    let clear = match clearing.then(|| L::CommandEnum::try_from(CommonCommand::ClearFaults)).transpose() {
+                                        Ok(clear) => clear,
+                                        Err(e) => {
+                                            client.reply(&command_response(&Err(e), format));
+                                            continue;
+                                        },
+                                    };
+                                    let authorization = clear.as_ref()
+                                        .map_or(Authorization::Allow, |command| authorize(&_authorizer, &_primary_client, client, command));
+                                    if authorization == Authorization::Deny {
+                                        client.reply(&command_response(&Err(CoherentError::Unauthorized), format));
+                                        continue;
+                                    }
+                                    let mut laser = match acquire(LockLevel::Laser, || _laser.try_lock_with(&_lock_retry_policy)) {
+                                        Ok(laser) => laser,
+                                        Err(e) => {
+                                            client.reply(&command_response(&Err(e), format));
+                                            continue;
+                                        },
+                                    };
+                                    if let Some(command) = clear {
+                                        let mut policy = match acquire(LockLevel::ConfirmationPolicy, || _confirmation_policy.lock()) {
+                                            Ok(policy) => policy,
+                                            Err(_) => {
+                                                client.reply(&command_response(&Err(CoherentError::LaserUnavailableError), format));
+                                                continue;
+                                            },
+                                        };
+                                        if authorization == Authorization::RequireConfirmation
+                                            || policy.as_mut().is_some_and(|policy| policy.requires_confirmation(&mut laser, &command)) {
+                                            let timeout = policy.as_ref().map_or(DEFAULT_CONFIRMATION_TIMEOUT, |policy| policy.timeout);
+                                            hold_for_confirmation(&mut pending, client, format, command, timeout);
+                                            continue;
+                                        }
+                                        drop(policy);
+                                        if let Err(e) = send_command_from(&mut **laser, command, client.address) {
+                                            client.reply(&command_response(&Err(e), format));
+                                            continue;
+                                        }
+                                    }
+                                    let report = laser.fault_report();
+                                    drop(laser);
+                                    let response = report.map_err(TcpError::CoherentError)
+                                        .and_then(|report| frame(FAULTS_MARKER, &report, format));
+                                    match response {
+                                        Ok(response) => {client.reply(&response);},
+                                        Err(TcpError::CoherentError(e)) => {client.reply(&command_response(&Err(e), format));},
+                                        Err(_) => {client.reply(&error_response(ErrorCode::Failed, None, format));},
+                                    }
+                                }
+
+                                // Pass a serial command straight through, for admins
+                                if let Ok(command) = deserialize_after::<AdminRawCommand>(&buf[0..buf_ptr], ADMIN_RAW_MARKER, format, false) {
+                                    let reply = acquire(LockLevel::Laser, || _laser.try_lock_with(&_lock_retry_policy))
+                                        .and_then(|mut laser| admin::run(&command, _admin_token.as_deref(), &mut **laser));
+                                    match reply.map(|reply| frame(RAW_REPLY_MARKER, &reply, format)) {
+                                        Ok(Ok(response)) => {client.reply(&response);},
+                                        Ok(Err(_)) => {client.reply(&error_response(ErrorCode::Failed, None, format));},
+                                        Err(e) => {client.reply(&command_response(&Err(e), format));},
+                                    }
+                                }
+
+                                // Check a command without running it: the primary
+                                // client rule, then everything the laser checks itself.
+                                if let Ok(command) = deserialize_command_after::<L>(&buf[0..buf_ptr], VALIDATE_MARKER, format) {
+                                    if !is_primary(client, &_primary_client) {
+                                        client.reply(&error_response(ErrorCode::NotPrimaryClient, None, format));
+                                    }
+                                    else {
+                                        let authorization = authorize(&_authorizer, &_primary_client, client, &command);
+                                        let result = match authorization {
+                                            Authorization::Deny => Err(CoherentError::Unauthorized),
+                                            _ => acquire(LockLevel::Laser, || _laser.try_lock_with(&_lock_retry_policy)),
+                                        }.and_then(|mut laser| {
+                                                let mut policy = acquire(LockLevel::ConfirmationPolicy, || _confirmation_policy.lock())
+                                                    .map_err(|_| CoherentError::LaserUnavailableError)?;
+                                                validation::validate(&mut **laser, policy.as_mut(), &command)
+                                            })
+                                            .map(|mut validation| {
+                                                validation.requires_confirmation |= authorization == Authorization::RequireConfirmation;
+                                                validation
+                                            });
+                                        match result.map(|validation| frame(VALIDATION_MARKER, &validation, format)) {
+                                            Ok(Ok(response)) => {client.reply(&response);},
+                                            Ok(Err(_)) => {client.reply(&error_response(ErrorCode::Failed, None, format));},
+                                            Err(e) => {client.reply(&command_response(&Err(e), format));},
+                                        }
+                                    }
+                                }
+
+                                // Run a sweep on a thread of its own, reporting to this
+                                // client, if every step would go through as a command would
+                                // without confirmation.
+                                if let Ok(sweep) = deserialize_after::<StartSweep>(&buf[0..buf_ptr], START_SWEEP_MARKER, format, false) {
+                                    if !is_primary(client, &_primary_client) {
+                                        client.reply(&error_response(ErrorCode::NotPrimaryClient, None, format));
+                                    }
+                                    else {
+                                        let result = acquire(LockLevel::Laser, || _laser.try_lock_with(&_lock_retry_policy))
+                                            .and_then(|laser| sweep.wavelengths(laser.capabilities().tuning_range_nm))
+                                            .and_then(|wavelengths| {
+                                                let commands = wavelengths.iter()
+                                                    .map(|&nm| L::CommandEnum::try_from(CommonCommand::SetWavelength(nm)))
+                                                    .collect::<Result<Vec<_>, _>>()?;
+                                                if commands.iter().any(|command| authorize(&_authorizer, &_primary_client, client, command) != Authorization::Allow) {
+                                                    return Err(CoherentError::Unauthorized);
+                                                }
+                                                let mut laser = acquire(LockLevel::Laser, || _laser.try_lock_with(&_lock_retry_policy))?;
+                                                let mut policy = acquire(LockLevel::ConfirmationPolicy, || _confirmation_policy.lock())
+                                                    .map_err(|_| CoherentError::LaserUnavailableError)?;
+                                                if let Some(policy) = policy.as_mut() {
+                                                    if commands.iter().any(|command| policy.requires_confirmation(&mut laser, command)) {
+                                                        return Err(CoherentError::Unauthorized);
+                                                    }
+                                                }
+                                                drop(policy);
+                                                drop(laser);
+                                                let stream = client.try_clone().map_err(CoherentError::WriteError)?;
+                                                let to = SweepClient{stream, address : client.address, format};
+                                                sweep::start(wavelengths, sweep.dwell, &_laser, &_lock_retry_policy, to, &_sweep)
+                                            });
+                                        client.reply(&command_response(&result, format));
+                                    }
                                 }
-                            }
 
-                            // If a command is in the buffer, execute it.
-                            if let Ok(command) = deserialize_command::<L>(&buf[0..buf_ptr]) {
-                                // unless you're not the primary client
-                                if _primary_client.is_some() &&
-                                    ( _primary_client.as_ref().unwrap().try_lock().unwrap().peer_addr().unwrap()
-                                    != client.peer_addr().unwrap()) {
-                                    client.write_all(NOT_PRIMARY_CLIENT).unwrap();
-                                    continue;
+                                if buf[0..buf_ptr].starts_with(ABORT_SWEEP) {
+                                    if !is_primary(client, &_primary_client) {
+                                        client.reply(&error_response(ErrorCode::NotPrimaryClient, None, format));
+                                    }
+                                    else {
+                                        client.reply(&command_response(&_sweep.abort(), format));
+                                    }
+                                }
+
+                                if buf[0..buf_ptr].starts_with(ABORT) {
+                                    // Commands run to the end before the next frame's read,
+                                    // so only a sweep can be running now
+                                    let response = abort_response(client, &_primary_client, _cancel_token.as_ref(), false, &_sweep, format);
+                                    client.reply(&response);
                                 }
-                                let mut laser = _laser.lock().unwrap();
-                                match laser.send_command(command) {
-                                    Ok(_) => {
-                                        client.write_all(COMMAND_SUCCESSFUL).unwrap();},
-                                    Err(_) => {client.write_all(COMMAND_FAILED).unwrap();}
+
+                                // Speak another format to this client from now on.
+                                // Refused in the format it's speaking now.
+                                if let Some(rest) = buf[0..buf_ptr].strip_prefix(CODEC_MARKER) {
+                                    let end = rest.iter().position(|&b| b == TERMINATOR[0]).unwrap_or(rest.len());
+                                    let name = String::from_utf8_lossy(&rest[..end]).into_owned();
+                                    match WireFormat::from_name(&name) {
+                                        Some(requested) => {
+                                            client.format = requested;
+                                            client.reply(COMMAND_SUCCESSFUL);
+                                        },
+                                        None => {
+                                            let refusal = Err(CoherentError::InvalidArgumentsError(format!("Unsupported format {}", name)));
+                                            client.reply(&command_response(&refusal, format));
+                                        },
                                     }
                                 }
-                            },
-                            Err(_) => {}
+                            }
+                        };
+                        // Clients that have gone are dropped, as the status broadcast does
+                        clients.retain(|client| !client.disconnected);
+                        drop(clients); // free it BEFORE you sleep, or wait on the laser!
+                        // Each waits its turn for the laser. Meanwhile the clients
+                        // are only taken to answer aborts.
+                        for job in deferred.drain(..) {
+                            let DeferredCommand{command, origin, received, timeout, mut replies, ..} = job;
+                            let mut lock_wait = std::time::Duration::ZERO;
+                            let result = acquire(LockLevel::Laser, || _laser.try_lock_with(&_lock_retry_policy))
+                                .and_then(|laser| {
+                                    lock_wait = received.elapsed();
+                                    drop(laser);
+                                    send_command_within(&_laser, &worker_running, command, origin, timeout, || {
+                                        if let Ok(clients) = acquire(LockLevel::Clients, || _clients.lock()) {
+                                            answer_aborts(clients.iter(), &_primary_client, _cancel_token.as_ref(), &_sweep);
+                                        }
+                                    })
+                                });
+                            #[cfg(feature = "opentelemetry")]
+                            if let Some(trace) = job.trace {
+                                trace.finish(&result);
+                            }
+                            // In case the command started something long-running
+                            if result.is_ok() {
+                                acquire(LockLevel::Progress, || _progress_watches.lock()).unwrap()
+                                    .push(ProgressWatch{client : origin, since : received});
+                            }
+                            for (stream, format) in replies.iter_mut() {
+                                let _ = stream.write_all(&command_response(&result, *format));
+                            }
+                            acquire(LockLevel::Stats, || _stats.lock()).unwrap()
+                                .command(origin, result.is_ok(), received.elapsed(), lock_wait);
                         }
-                    };
-                    drop(clients); // free it BEFORE you sleep!
-                    // sleep prevents over-locking the mutexes
-                    std::thread::sleep(std::time::Duration::from_millis(_command_interval_ms));   
+                        // Unconfirmed commands that ran out of time fail
+                        let now = std::time::Instant::now();
+                        pending.retain_mut(|held| {
+                            if held.deadline > now { return true; }
+                            let _ = held.requester.write_all(&command_response(&Err(CoherentError::TimeoutError), held.requester_format));
+                            false
+                        });
+                        // sleep prevents over-locking the mutexes
+                        std::thread::sleep(std::time::Duration::from_millis(_command_interval_ms));
+                    }
                 }
             }
-        }}));
+        }).map_err(TcpError::IoError)?);
+
+        // Rereads the config file whenever its contents change.
+        if let Some(path) = self._config_path.clone() {
+            let _laser = self._laser.clone().unwrap();
+            let _polling = self._polling.clone();
+            let _polling_interval = Arc::clone(&self._polling_interval);
+            let _wire_format = Arc::clone(&self._wire_format);
+            let _lock_retry_policy = self._lock_retry_policy.clone();
+            self._config_thread = Some(std::thread::Builder::new().name(CONFIG_THREAD.to_string()).spawn( move || {
+                let mut applied = std::fs::read(&path).ok();
+                while _polling.load(std::sync::atomic::Ordering::SeqCst) {
+                    std::thread::sleep(std::time::Duration::from_millis(CONFIG_CHECK_MS));
+                    let current = std::fs::read(&path).ok();
+                    if current.is_none() || current == applied { continue; }
+                    match config::reload(&path, &_laser, &_lock_retry_policy, &_polling_interval, &_wire_format) {
+                        Ok(()) => applied = current,
+                        Err(CoherentError::LaserBusyError) => {}, // try again next time
+                        Err(e) => {
+                            eprintln!("Not applying the changes to {}: {:?}", path.display(), e);
+                            applied = current;
+                        },
+                    }
+                }
+            }).map_err(TcpError::IoError)?);
+        }
 
         Ok(())
     }
@@ -483,6 +1789,7 @@ impl<L : Laser + 'static> NetworkLaserServer<L> {
             return;
         }
         self._polling.store(false, std::sync::atomic::Ordering::SeqCst);
+        let _ = self._sweep.abort();
         if let Some(thread) = self._client_connection_thread.take() {
             thread.join().unwrap_or(())
         }
@@ -492,7 +1799,10 @@ impl<L : Laser + 'static> NetworkLaserServer<L> {
         if let Some(thread) = self._command_thread.take() {
             thread.join().unwrap_or(())
         }
-    }
+        if let Some(thread) = self._config_thread.take() {
+            thread.join().unwrap_or(())
+        }
+    }
 
     /// Returns whether the poll thread is polling
     pub fn polling(&self) -> bool {
@@ -502,18 +1812,79 @@ impl<L : Laser + 'static> NetworkLaserServer<L> {
     /// Send a command to the laser through the mutex
     pub fn command(&self, command : L::CommandEnum) -> Result<(), TcpError> {
         let mut laser = self.guarded_laser()?;
-        laser.send_command(command).map_err(|e| TcpError::CoherentError(e))
+        laser.send_command(command).map_err(TcpError::CoherentError)
     }
 
     /// Send a query to the laser through the mutex
     pub fn query<Q : Query> (&self, query : Q) -> Result<Q::Result, TcpError> {
         let mut laser = self.guarded_laser()?;
-        laser.query(query).map_err(|e| TcpError::CoherentError(e))
+        laser.query(query).map_err(TcpError::CoherentError)
     }
 
     pub fn status(&self) -> Result<L::LaserStatus, TcpError> {
         let mut laser = self.guarded_laser()?;
-        laser.status().map_err(|e| TcpError::CoherentError(e))
+        laser.status().map_err(TcpError::CoherentError)
+    }
+
+    /// The server's counters and timings so far. See `stats::ServerStats`.
+    pub fn stats(&self) -> Result<ServerStats, TcpError> {
+        Ok(acquire(LockLevel::Stats, || self._stats.lock())?.snapshot())
+    }
+
+    /// Sets (or with `None`, removes) the policy deciding which commands
+    /// from clients must be confirmed by a second client before they run.
+    /// Commands issued locally through the server are never held.
+    /// See `confirmation::ConfirmationPolicy`.
+    pub fn set_confirmation_policy(&self, policy : Option<ConfirmationPolicy<L>>) -> Result<(), TcpError> {
+        **acquire(LockLevel::ConfirmationPolicy, || self._confirmation_policy.lock())? = policy;
+        Ok(())
+    }
+
+    /// With `Some`, of the commands a client sends before the server gets
+    /// to them, only the latest in each of `policy`'s classes runs; the
+    /// others are answered `CoherentError::Superseded`. Off by default. See
+    /// `coalesce::CoalescePolicy`.
+    pub fn set_coalesce_policy(&self, policy : Option<CoalescePolicy<L>>) -> Result<(), TcpError> {
+        **acquire(LockLevel::CoalescePolicy, || self._coalesce_policy.lock())? = policy;
+        Ok(())
+    }
+
+    /// With `Some`, every command from a client is put to `authorizer`
+    /// first, and refused or held as it says. Commands issued locally
+    /// through the server are never asked about. See
+    /// `authorization::Authorizer`.
+    pub fn set_authorizer(&self, authorizer : Option<Authorizer<L>>) -> Result<(), TcpError> {
+        **acquire(LockLevel::Authorizer, || self._authorizer.lock())? = authorizer;
+        Ok(())
+    }
+
+    /// With `Some`, a client that demands to be the primary client only
+    /// becomes it if `check` says it has the laser reserved; otherwise it's
+    /// refused with `CoherentError::ReservationDenied` and the reason. See
+    /// `authorization::ReservationCheck`.
+    pub fn set_reservation_check(&self, check : Option<ReservationCheck>) -> Result<(), TcpError> {
+        **acquire(LockLevel::ReservationCheck, || self._reservation_check.lock())? = check;
+        Ok(())
+    }
+
+    /// Runs `f` with exclusive access to the hosted laser, e.g. to register
+    /// tuning hooks that should fire for commands from every client.
+    /// 
+    /// # Example
+    /// 
+    /// ```rust
+    /// use coherent_rs::laser::debug::DebugLaser;
+    /// use coherent_rs::network::NetworkLaserServer;
+    /// 
+    /// let server = NetworkLaserServer::new(DebugLaser::default(), "127.0.0.1:0", None).unwrap();
+    /// server.with_laser(|laser| laser.tuning_hooks.after_tuning(|event| {
+    ///     println!("Now at {} nm", event.to_nm);
+    ///     Ok(())
+    /// })).unwrap();
+    /// ```
+    pub fn with_laser<R>(&self, f : impl FnOnce(&mut L) -> R) -> Result<R, TcpError> {
+        let mut laser = self.guarded_laser()?;
+        Ok(f(&mut laser))
     }
 }
 
@@ -523,49 +1894,274 @@ impl<L : Laser + 'static> Drop for NetworkLaserServer<L> {
     }
 }
 
+/// Reads from `stream` until `parse` makes something of everything that's
+/// arrived, the server hangs up, or `deadline` passes (`TimeoutError`). With
+/// a deadline, each read waits no longer than the time left, nor than the
+/// stream's own read timeout. Gives up with `FrameTooLarge` once more than
+/// `limits.max_frame_size` bytes haven't parsed.
+fn read_until<T>(
+    stream : &TcpStream,
+    deadline : Option<Deadline>,
+    limits : ReadLimits,
+    parse : impl FnMut(&[u8]) -> Option<T>,
+) -> Result<T, TcpError> {
+    read_more_until(stream, deadline, limits, &mut Vec::new(), parse)
+}
+
+/// As `read_until`, adding to `data` -- which may already hold the start
+/// of what's wanted -- and leaving it there with whatever arrived after.
+fn read_more_until<T>(
+    mut stream : &TcpStream,
+    deadline : Option<Deadline>,
+    limits : ReadLimits,
+    data : &mut Vec<u8>,
+    mut parse : impl FnMut(&[u8]) -> Option<T>,
+) -> Result<T, TcpError> {
+    let read_timeout = stream.read_timeout().map_err(TcpError::IoError)?;
+    let result = (|| {
+        let mut buf = vec![0u8; limits.buffer_size.max(1)];
+        loop {
+            if let Some(parsed) = parse(data) {
+                return Ok(parsed);
+            }
+            if data.len() > limits.max_frame_size {
+                return Err(TcpError::FrameTooLarge{limit : limits.max_frame_size});
+            }
+            if let Some(deadline) = deadline {
+                deadline.check().map_err(TcpError::CoherentError)?;
+                stream.set_read_timeout(Some(deadline.cap(read_timeout.unwrap_or(std::time::Duration::MAX))))
+                    .map_err(TcpError::IoError)?;
+            }
+            match stream.read(&mut buf) {
+                // A zero-byte read means the server hung up
+                Ok(0) => return Err(TcpError::Disconnected),
+                Ok(n) => data.extend_from_slice(&buf[..n]),
+                Err(_) if deadline.is_some_and(|deadline| deadline.is_expired()) => {
+                    return Err(TcpError::CoherentError(CoherentError::TimeoutError));
+                },
+                Err(e) => return Err(TcpError::IoError(e)),
+            }
+        }
+    })();
+    if deadline.is_some() {
+        let _ = stream.set_read_timeout(read_timeout);
+    }
+    result
+}
+
+/// Sends a `STATS_REQUEST` and reads back the `ServerStats`, skipping any
+/// status broadcasts that arrive first.
+fn request_stats(mut stream : &TcpStream, format : WireFormat, deadline : Option<Deadline>, limits : ReadLimits) -> Result<ServerStats, TcpError> {
+    if let Some(deadline) = deadline {
+        deadline.check().map_err(TcpError::CoherentError)?;
+    }
+    stream.write_all(STATS_REQUEST).map_err(TcpError::IoError)?;
+    read_until(stream, deadline, limits, |data| {
+        let start = data.windows(STATS_MARKER.len()).rposition(|window| window == STATS_MARKER)?;
+        // Read the stats from everything after the marker, since their
+        // encoding may well contain a `TERMINATOR` byte.
+        format.decode(&data[start + STATS_MARKER.len()..]).ok()
+    })
+}
+
+/// Sends `request` (`FAULTS_REQUEST` or `CLEAR_FAULTS`) and reads back the
+/// `FaultReport`, or why the server couldn't get one, skipping any status
+/// broadcasts that arrive first.
+fn request_faults(mut stream : &TcpStream, request : &[u8], format : WireFormat, deadline : Option<Deadline>, limits : ReadLimits)
+    -> Result<FaultReport, TcpError> {
+    if let Some(deadline) = deadline {
+        deadline.check().map_err(TcpError::CoherentError)?;
+    }
+    stream.write_all(request).map_err(TcpError::IoError)?;
+    let contains = |haystack : &[u8], needle : &[u8]| haystack.windows(needle.len()).any(|window| window == needle);
+    let report = read_until(stream, deadline, limits, |data| {
+        if let Some(start) = data.windows(FAULTS_MARKER.len()).rposition(|window| window == FAULTS_MARKER) {
+            return format.decode(&data[start + FAULTS_MARKER.len()..]).ok().map(|report| Ok(Some(report)));
+        }
+        // A clear held for confirmation is answered like a command once
+        // it's confirmed, so ask for the faults afterwards
+        if contains(data, COMMAND_SUCCESSFUL) { return Some(Ok(None)); }
+        if contains(data, COMMAND_FAILED) { return Some(Err(failure_reason(data, format))); }
+        if contains(data, NOT_PRIMARY_CLIENT) { return Some(Err(TcpError::NotPrimaryClient)); }
+        None
+    }).and_then(|report| report)?;
+    match report {
+        Some(report) => Ok(report),
+        None => request_faults(stream, FAULTS_REQUEST, format, deadline, limits),
+    }
+}
+
+/// Sends an `AdminRawCommand` and reads back the laser's reply, or why the
+/// server refused, skipping any status broadcasts that arrive first.
+fn request_raw(mut stream : &TcpStream, command : &AdminRawCommand, format : WireFormat, deadline : Option<Deadline>, limits : ReadLimits)
+    -> Result<String, TcpError> {
+    if let Some(deadline) = deadline {
+        deadline.check().map_err(TcpError::CoherentError)?;
+    }
+    stream.write_all(&frame(ADMIN_RAW_MARKER, command, format)?).map_err(TcpError::IoError)?;
+    let contains = |haystack : &[u8], needle : &[u8]| haystack.windows(needle.len()).any(|window| window == needle);
+    read_until(stream, deadline, limits, |data| {
+        if let Some(start) = data.windows(RAW_REPLY_MARKER.len()).rposition(|window| window == RAW_REPLY_MARKER) {
+            return format.decode(&data[start + RAW_REPLY_MARKER.len()..]).ok().map(Ok);
+        }
+        if contains(data, COMMAND_FAILED) { return Some(Err(failure_reason(data, format))); }
+        None
+    }).and_then(|reply| reply)
+}
+
+/// Sends `request` (a `VALIDATE_MARKER` frame) and reads back the
+/// `Validation`, or why the command would fail, skipping any status
+/// broadcasts that arrive first.
+fn request_validation(mut stream : &TcpStream, request : &[u8], format : WireFormat, deadline : Option<Deadline>, limits : ReadLimits)
+    -> Result<Validation, TcpError> {
+    if let Some(deadline) = deadline {
+        deadline.check().map_err(TcpError::CoherentError)?;
+    }
+    stream.write_all(request).map_err(TcpError::IoError)?;
+    let contains = |haystack : &[u8], needle : &[u8]| haystack.windows(needle.len()).any(|window| window == needle);
+    read_until(stream, deadline, limits, |data| {
+        if let Some(start) = data.windows(VALIDATION_MARKER.len()).rposition(|window| window == VALIDATION_MARKER) {
+            return format.decode(&data[start + VALIDATION_MARKER.len()..]).ok().map(Ok);
+        }
+        if contains(data, COMMAND_FAILED) { return Some(Err(failure_reason(data, format))); }
+        if contains(data, NOT_PRIMARY_CLIENT) { return Some(Err(TcpError::NotPrimaryClient)); }
+        None
+    }).and_then(|validation| validation)
+}
+
+/// What a client running a sweep hears next.
+enum SweepEvent {
+    Step(SweepStep),
+    End(ProgressState),
+}
+
+/// The first of the sweep's steps in `data`, or if there are none, how the
+/// sweep ended, and where in `data` to carry on from.
+fn next_sweep_event(data : &[u8], format : WireFormat) -> Option<(Result<SweepEvent, TcpError>, usize)> {
+    let positions = |marker : &'static [u8]| data.windows(marker.len())
+        .enumerate()
+        .filter(move |(_, window)| *window == marker)
+        .map(move |(start, _)| start + marker.len());
+    if let Some(end) = positions(SWEEP_STEP_MARKER).next() {
+        // Not all here yet if it doesn't decode
+        return format.decode(&data[end..]).ok().map(|step| (Ok(SweepEvent::Step(step)), end));
+    }
+    let contains = |needle : &[u8]| data.windows(needle.len()).any(|window| window == needle);
+    let ended = positions(PROGRESS_MARKER)
+        .filter_map(|start| format.decode::<Progress>(&data[start..]).ok())
+        .find(|progress| progress.operation == Operation::WavelengthSweep && progress.is_finished());
+    let event = match ended {
+        Some(progress) if progress.state == ProgressState::Failed => Err(failure_reason(data, format)),
+        Some(progress) => Ok(SweepEvent::End(progress.state)),
+        None if contains(NOT_PRIMARY_CLIENT) => Err(TcpError::NotPrimaryClient),
+        None if contains(COMMAND_FAILED) => Err(failure_reason(data, format)),
+        None => return None,
+    };
+    Some((event, data.len()))
+}
+
+/// Sends `request` (a `START_SWEEP_MARKER` frame), then hands each step to
+/// `on_step` as it arrives, skipping any status broadcasts, until the
+/// sweep's over. Sends `ABORT_SWEEP` the first time `on_step` says to stop.
+/// `deadline` is for the whole sweep.
+fn request_sweep(
+    mut stream : &TcpStream,
+    request : &[u8],
+    format : WireFormat,
+    deadline : Option<Deadline>,
+    limits : ReadLimits,
+    mut on_step : impl FnMut(&SweepStep) -> bool,
+) -> Result<SweepReport, TcpError> {
+    if let Some(deadline) = deadline {
+        deadline.check().map_err(TcpError::CoherentError)?;
+    }
+    stream.write_all(request).map_err(TcpError::IoError)?;
+    let (mut data, mut steps, mut aborting) = (Vec::new(), Vec::new(), false);
+    loop {
+        let (event, end) = read_more_until(stream, deadline, limits, &mut data, |data| next_sweep_event(data, format))?;
+        // Only what's after the last step is kept, so a long sweep
+        // doesn't run into `limits`
+        data.drain(..end);
+        match event? {
+            SweepEvent::Step(step) => {
+                steps.push(step);
+                if !on_step(&step) && !aborting {
+                    aborting = true;
+                    stream.write_all(ABORT_SWEEP).map_err(TcpError::IoError)?;
+                }
+            },
+            SweepEvent::End(state) => return Ok(SweepReport{steps, state}),
+        }
+    }
+}
+
+/// Estimates the offset to the server's clock from `samples` exchanges of
+/// `TIME_REQUEST`s, keeping the one with the shortest round trip. Skips any
+/// status broadcasts that arrive in between.
+fn request_clock_offset(mut stream : &TcpStream, format : WireFormat, deadline : Option<Deadline>, limits : ReadLimits, samples : usize)
+    -> Result<ClockOffset, TcpError> {
+    let mut offsets = Vec::with_capacity(samples);
+    for _ in 0..samples.max(1) {
+        if let Some(deadline) = deadline {
+            deadline.check().map_err(TcpError::CoherentError)?;
+        }
+        let asked = unix_timestamp();
+        stream.write_all(TIME_REQUEST).map_err(TcpError::IoError)?;
+        let time = read_until(stream, deadline, limits, |data| {
+            let start = data.windows(TIME_MARKER.len()).rposition(|window| window == TIME_MARKER)?;
+            format.decode::<ServerTime>(&data[start + TIME_MARKER.len()..]).ok()
+        })?;
+        offsets.push(ClockOffset::from_exchange(asked, time, unix_timestamp()));
+    }
+    ClockOffset::best(offsets).ok_or(TcpError::NoLaserStatus)
+}
+
 /// Boilerplate for sending a command and waiting for the few
 /// types of responses from the `Server`.
 /// 
 /// # Syntax
 /// 
-/// `call_and_wait_for_response!($self : ident, $command : expr)`
+/// `call_and_wait_for_response!($self : ident, $command : expr)`, bounded by
+/// `$self.deadline()`, or `call_and_wait_for_response!($self, $command, $deadline)`
 /// 
 /// # Example
-/// ```rust
+/// ```ignore
 /// let mut buf = Vec::new();
-/// buf.extend(COMMAND_MARKER);
-/// command.serialize(&mut Serializer::new(&mut buf))
-///     .map_err(|e| TcpError::SerializationEncodeError(e))?;
-/// buf.extend(TERMINATOR);
+/// buf.extend(frame(COMMAND_MARKER, &command, self.wire_format())?);
 /// call_and_wait_for_response!(self, &buf);
 /// ```
 macro_rules! call_and_wait_for_response {
     ($self:ident, $command:expr) => {
+        call_and_wait_for_response!($self, $command, $self.deadline());
+    };
+    ($self:ident, $command:expr, $deadline:expr) => {
+        let deadline : Option<Deadline> = $deadline;
+        if let Some(deadline) = deadline {
+            deadline.check().map_err(TcpError::CoherentError)?;
+        }
         $self.access_stream().write_all($command)
             .map_err(|e| TcpError::IoError(e))?;
 
-        // Wait for command evaluation
-        let mut response = [0u8; 1024];
-        let mut response_ptr = 0;
-        loop {
-            match $self.access_stream().read(&mut response) {
-                Ok(n) => {
-                    response_ptr += n;
-                    if response[0..response_ptr].starts_with(COMMAND_SUCCESSFUL) {
-                        return Ok(());
-                    }
-                    else if response[0..response_ptr].starts_with(COMMAND_FAILED) {
-                        return Err(TcpError::CommandError);
-                    }
-                    else if response[0..response_ptr].starts_with(NOT_PRIMARY_CLIENT) {
-                        return Err(TcpError::NotPrimaryClient);
-                    }
-                },
-                Err(e) => { // stream is dead, or I/O error occurred
-                    return Err(TcpError::IoError(e));
-                }
+        // Wait for command evaluation. Status broadcasts can arrive
+        // before (or in the same read as) the response, so accumulate
+        // everything and look for the response anywhere in it.
+        let format = $self.wire_format();
+        let limits = $self.read_limits();
+        let contains = |haystack : &[u8], needle : &[u8]| {
+            haystack.windows(needle.len()).any(|window| window == needle)
+        };
+        return read_until($self.access_stream(), deadline, limits, |response| {
+            if contains(response, COMMAND_SUCCESSFUL) {
+                Some(Ok(()))
             }
-        }
+            else if contains(response, COMMAND_FAILED) {
+                Some(Err(failure_reason(response, format)))
+            }
+            else if contains(response, NOT_PRIMARY_CLIENT) {
+                Some(Err(TcpError::NotPrimaryClient))
+            }
+            else { frame_too_large_limit(response).map(|limit| Err(TcpError::FrameTooLarge{limit})) }
+        }).and_then(|response| response);
     }
 }
 
@@ -585,8 +2181,65 @@ pub trait NetworkLaserClient<L : Laser> : Sized {
     /// Access a laser type parameter
     fn get_laser_type(&self) -> LaserType {L::into_laser_type()}
 
+    /// Applied to `command` and `query_status`. Tries once unless
+    /// the implementing struct says otherwise.
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// When the operation in progress has to be finished by, e.g. as set by
+    /// `BasicNetworkLaserClient::with_timeout`. Every call that waits on the
+    /// server gives up with `TimeoutError` once it passes. `None` unless the
+    /// implementing struct says otherwise.
+    fn deadline(&self) -> Option<Deadline> {
+        None
+    }
+
+    /// The `WireFormat` the server is speaking to this client. MessagePack
+    /// unless the implementing struct negotiated another.
+    fn wire_format(&self) -> WireFormat {
+        WireFormat::MessagePack
+    }
+
+    /// How replies are read from the server. The defaults unless the
+    /// implementing struct was built with others (see `builder`).
+    fn read_limits(&self) -> ReadLimits {
+        ReadLimits::default()
+    }
+
+    /// What the server announced it can do. Requests it can't handle fail
+    /// with `TcpError::Unsupported` instead of being sent. Assumes a current
+    /// server unless the implementing struct read the handshake.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::all()
+    }
+
+    /// The latest estimate of how far the server's clock is ahead of this
+    /// machine's, to convert status timestamps into local time (see `clock`).
+    /// `None` unless the implementing struct keeps one.
+    fn clock_offset(&self) -> Option<ClockOffset> {
+        None
+    }
+
+    /// Where `command` writes down the settings it sends, to be sent again
+    /// with `replay_pending` (see `journal`). `None` unless the
+    /// implementing struct keeps one.
+    fn journal(&mut self) -> Option<&mut CommandJournal> {
+        None
+    }
+
+    /// Measures the offset to the server's clock over `samples` exchanges,
+    /// more precisely than the handshake can. Implementing structs that keep
+    /// a `clock_offset` update it.
+    fn synchronize_clock(&mut self, samples : usize) -> Result<ClockOffset, TcpError> {
+        self.capabilities().require(Capability::Time)?;
+        let (format, deadline, limits) = (self.wire_format(), self.deadline(), self.read_limits());
+        request_clock_offset(self.access_stream(), format, deadline, limits, samples)
+    }
+
     /// Tests whether the stream is live by trying to write
     /// to it.
+    #[allow(clippy::unused_io_amount)]
     fn test_stream(&mut self) -> Result<(), TcpError> {
         let mut buf = [0u8; 1];
         match self.access_stream().read(&mut buf) {
@@ -607,121 +2260,723 @@ pub trait NetworkLaserClient<L : Laser> : Sized {
         self.test_stream()?;
 
         let mut buf = Vec::new();
-        buf.extend(COMMAND_MARKER);
-        command.serialize(&mut Serializer::new(&mut buf))
-            .map_err(|e| TcpError::SerializationEncodeError(e))?;
-        buf.extend(TERMINATOR);
-        call_and_wait_for_response!(self, &buf);
+        #[cfg(feature = "opentelemetry")]
+        let trace = telemetry::ClientTrace::start(&crate::laser::LaserCommand::to_string(&command));
+        #[cfg(feature = "opentelemetry")]
+        if self.capabilities().supports(Capability::Trace) {
+            buf.extend(trace.frame());
+        }
+        buf.extend(frame(COMMAND_MARKER, &command, self.wire_format())?);
+        let policy = self.retry_policy();
+        let deadline = Deadline::earliest(self.deadline(), policy.deadline());
+        let result = policy.run_until(deadline, || { call_and_wait_for_response!(self, &buf, deadline); });
+        #[cfg(feature = "opentelemetry")]
+        trace.finish(&result);
+        if let Some(journal) = self.journal() {
+            journal.record_result(&command, &result)?;
+        }
+        result
+    }
+
+    /// Sends the `journal`'s pending commands again, oldest first, e.g.
+    /// after `BasicNetworkLaserClient::reconnect`. Stops at the first that
+    /// fails, leaving it and the rest pending. Returns how many were sent;
+    /// 0 without a journal.
+    fn replay_pending(&mut self) -> Result<usize, TcpError> {
+        let pending = match self.journal() {
+            Some(journal) => journal.pending_commands::<L::CommandEnum>()?,
+            None => return Ok(0),
+        };
+        let count = pending.len();
+        for command in pending {
+            self.command(command)?;
+        }
+        Ok(count)
     }
     
     /// Returns a full status of the laser from the network. Warning: blocking!
     fn query_status(&mut self) -> Result<L::LaserStatus, TcpError>{
-        let mut buf = [0u8; 1024]; // Fixed-size buffer for reading from the stream
-        let mut data = Vec::new(); // Accumulated data
+        let policy = self.retry_policy();
+        let deadline = Deadline::earliest(self.deadline(), policy.deadline());
+        let (format, limits) = (self.wire_format(), self.read_limits());
+        policy.run_until(deadline, || read_until(
+            self.access_stream(), deadline, limits, |data| deserialize_laser_status::<L>(data, format).ok()
+        ))
+    }
 
-        loop {
-            // Attempt to deserialize the current data
-            if let Ok(status) = deserialize_laser_status::<L>(&data) {
-                return Ok(status);
+    /// The next `Progress` the server pushes about an operation this client
+    /// started, e.g. a wavelength change -- one each time the server polls
+    /// the laser, until it's done. Blocks until one arrives, so only call
+    /// it after a command that starts something long-running.
+    ///
+    /// ```no_run
+    /// # use coherent_rs::{Discovery, laser::DiscoveryNXCommands};
+    /// # use coherent_rs::network::{NetworkLaserClient, BasicNetworkLaserClient};
+    /// let mut client = BasicNetworkLaserClient::<Discovery>::connect("127.0.0.1:907", Some(1000)).unwrap();
+    /// client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : 1040.0.into()}).unwrap();
+    /// loop {
+    ///     let progress = client.query_progress().unwrap();
+    ///     println!("{:.0}%", progress.percent.unwrap_or(0.0));
+    ///     if progress.is_done() { break; }
+    /// }
+    /// ```
+    fn query_progress(&mut self) -> Result<Progress, TcpError> {
+        self.capabilities().require(Capability::Progress)?;
+        let policy = self.retry_policy();
+        let deadline = Deadline::earliest(self.deadline(), policy.deadline());
+        let (format, limits) = (self.wire_format(), self.read_limits());
+        policy.run_until(deadline, || read_until(
+            self.access_stream(), deadline, limits, |data| deserialize_after(data, PROGRESS_MARKER, format, true).ok()
+        ))
+    }
+
+    /// Demand that the client be the primary client.
+    /// If the network already has a primary client, this will fail
+    /// and return a `TcpError::NotPrimaryClient`; if the server's
+    /// `ReservationCheck` refuses, with `CoherentError::ReservationDenied`
+    /// and its reason. Will block until it receives confirmation.
+    fn demand_primary_client(&mut self) -> Result<(), TcpError> {
+        call_and_wait_for_response!(
+            self, DEMAND_PRIMARY_CLIENT
+        );
+    }
+
+    fn forget_me(&mut self) -> Result<(), TcpError> {
+        call_and_wait_for_response!(
+            self, FORGET_ME
+        );
+    }
+
+    /// Forces the server to forget the primary client. Will block until
+    /// it receives confirmation.
+    fn force_forget_primary_client(&mut self) -> Result<(), TcpError> {
+        call_and_wait_for_response!(
+            self, FORGET_PRIMARY_CLIENT
+        );
+    }
+
+    /// Locks the laser's `OperatorLock` with `token`, blocking every
+    /// state-changing command from any client (or the server's own code)
+    /// until `unlock` is called with the same token. Only the primary
+    /// client may, if there is one. Will block until it receives
+    /// confirmation.
+    fn lock(&mut self, token : &str) -> Result<(), TcpError> {
+        self.capabilities().require(Capability::Lock)?;
+        let mut buf = LOCK_MARKER.to_vec();
+        buf.extend(token.as_bytes());
+        buf.extend(TERMINATOR);
+        call_and_wait_for_response!(self, &buf);
+    }
+
+    /// Unlocks the laser's `OperatorLock`. Fails unless `token` is the
+    /// one it was locked with.
+    fn unlock(&mut self, token : &str) -> Result<(), TcpError> {
+        self.capabilities().require(Capability::Lock)?;
+        let mut buf = UNLOCK_MARKER.to_vec();
+        buf.extend(token.as_bytes());
+        buf.extend(TERMINATOR);
+        call_and_wait_for_response!(self, &buf);
+    }
+
+    /// Confirms a command another client sent that the server is holding
+    /// under its `ConfirmationPolicy`. `command` must be the same command.
+    /// Returns the result of running it, or `TcpError::CommandError` if
+    /// there's no such command waiting for this client's confirmation.
+    fn confirm(&mut self, command : L::CommandEnum) -> Result<(), TcpError> {
+        self.capabilities().require(Capability::Confirm)?;
+        let buf = frame(CONFIRM_MARKER, &command, self.wire_format())?;
+        call_and_wait_for_response!(self, &buf);
+    }
+
+    /// The server's counters and timings -- e.g. to see whether slow
+    /// commands are waiting on the laser or on other clients. Blocks until
+    /// they arrive.
+    fn server_stats(&mut self) -> Result<ServerStats, TcpError> {
+        self.capabilities().require(Capability::Stats)?;
+        let (format, deadline, limits) = (self.wire_format(), self.deadline(), self.read_limits());
+        request_stats(self.access_stream(), format, deadline, limits)
+    }
+
+    /// Asks the server to reread its config file and apply it (see
+    /// `config`), e.g. after editing it on a machine where the server
+    /// can't see the change. Only the primary client may, if there is one.
+    fn reload_config(&mut self) -> Result<(), TcpError> {
+        self.capabilities().require(Capability::Reload)?;
+        call_and_wait_for_response!(self, RELOAD_CONFIG);
+    }
+
+    /// The laser's faults, read there and then rather than waiting for
+    /// the next status.
+    fn fault_report(&mut self) -> Result<FaultReport, TcpError> {
+        self.capabilities().require(Capability::Faults)?;
+        let (format, deadline, limits) = (self.wire_format(), self.deadline(), self.read_limits());
+        request_faults(self.access_stream(), FAULTS_REQUEST, format, deadline, limits)
+    }
+
+    /// Clears the laser's faults, and reports the faults it has afterwards
+    /// -- check `is_clear` to see whether they actually cleared. Only the
+    /// primary client may, if there is one, and the server's authorizer
+    /// and confirmation policy have their say as for any other command: a
+    /// clear held for confirmation returns once it's confirmed.
+    fn clear_faults(&mut self) -> Result<FaultReport, TcpError> {
+        self.capabilities().require(Capability::Faults)?;
+        let (format, deadline, limits) = (self.wire_format(), self.deadline(), self.read_limits());
+        request_faults(self.access_stream(), CLEAR_FAULTS, format, deadline, limits)
+    }
+
+    /// Sends `cmd` to the laser exactly as written and returns its reply
+    /// (see `admin`). Only carried out if `token` is the server's admin
+    /// token; fails with `Unauthorized` if it isn't.
+    fn raw_command(&mut self, token : &str, cmd : &str) -> Result<String, TcpError> {
+        self.capabilities().require(Capability::Raw)?;
+        let command = AdminRawCommand{token : token.to_string(), cmd : cmd.to_string()};
+        let (format, deadline, limits) = (self.wire_format(), self.deadline(), self.read_limits());
+        request_raw(self.access_stream(), &command, format, deadline, limits)
+    }
+
+    /// Asks whether `command` would go through, without running it: fails
+    /// as `command` would, or says what the server would send the laser
+    /// and whether it would wait for confirmation (see `validation`).
+    fn validate(&mut self, command : &L::CommandEnum) -> Result<Validation, TcpError> {
+        self.capabilities().require(Capability::Validate)?;
+        let (format, deadline, limits) = (self.wire_format(), self.deadline(), self.read_limits());
+        let request = frame(VALIDATE_MARKER, command, format)?;
+        request_validation(self.access_stream(), &request, format, deadline, limits)
+    }
+
+    /// Has the server sweep the wavelength and read the power at each step
+    /// (see `sweep`), calling `on_step` with each step as it arrives. The
+    /// sweep stops early once `on_step` returns `false`, or another client
+    /// calls `abort_sweep`. Blocks until it's over; fails if it can't
+    /// start, or with the laser's error if a step fails.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use coherent_rs::{Discovery, network::{NetworkLaserClient, BasicNetworkLaserClient, sweep::StartSweep}};
+    ///
+    /// let mut client = BasicNetworkLaserClient::<Discovery>::connect("127.0.0.1:907", None).unwrap();
+    /// let sweep = StartSweep{start_nm : 700.0, stop_nm : 1000.0, step_nm : 10.0, dwell : Duration::from_secs(1)};
+    /// let report = client.run_sweep(&sweep, |step| {
+    ///     println!("{} nm: {} mW", step.wavelength_nm, step.power_mw);
+    ///     step.power_mw > 100.0 // stop if the power drops away
+    /// }).unwrap();
+    /// println!("{:?} after {} steps", report.state, report.steps.len());
+    /// ```
+    fn run_sweep(&mut self, sweep : &StartSweep, on_step : impl FnMut(&SweepStep) -> bool) -> Result<SweepReport, TcpError> {
+        self.capabilities().require(Capability::Sweep)?;
+        let (format, deadline, limits) = (self.wire_format(), self.deadline(), self.read_limits());
+        let request = frame(START_SWEEP_MARKER, sweep, format)?;
+        request_sweep(self.access_stream(), &request, format, deadline, limits, on_step)
+    }
+
+    /// Stops the sweep that's running, after the step in progress. Only the
+    /// primary client may, if there is one.
+    fn abort_sweep(&mut self) -> Result<(), TcpError> {
+        self.capabilities().require(Capability::Sweep)?;
+        call_and_wait_for_response!(self, ABORT_SWEEP);
+    }
+
+    /// Stops whatever's running on the laser now, e.g. from a Stop button
+    /// on a second connection: another client's command that's waiting for
+    /// tuning to finish fails with `CoherentError::Cancelled`, and a sweep
+    /// ends `Aborted`. Only the primary client may, if there is one. Fails
+    /// if nothing was running. A server with no command timeout can't read
+    /// the abort until the command's done.
+    fn abort(&mut self) -> Result<(), TcpError> {
+        self.capabilities().require(Capability::Abort)?;
+        call_and_wait_for_response!(self, ABORT);
+    }
+
+}
+
+/// A struct to generically connect to and communicate with a
+/// `NetworkLaser` over the network. Doesn't have any unique functionality
+/// or ability to query specific details, but can be used to send commands
+/// or get the full status of the laser.
+pub struct BasicNetworkLaserClient<L : Laser>{
+    _stream : TcpStream,
+    _laser : PhantomData<L>,
+    /// See `NetworkLaserClient::retry_policy`.
+    pub retry_policy : RetryPolicy,
+    _deadline : Option<Deadline>,
+    _format : WireFormat,
+    _capabilities : Capabilities,
+    _clock_offset : Option<ClockOffset>,
+    _limits : ReadLimits,
+    _address : String,
+    _read_timeout : Option<std::time::Duration>,
+    /// See `NetworkLaserClient::journal`. `None` unless built
+    /// `with_journal`, or set.
+    pub journal : Option<CommandJournal>,
+}
+
+impl<L : Laser> BasicNetworkLaserClient<L> {
+    /// Starts configuring a client for the server at `address`; see
+    /// `builder::ClientBuilder`.
+    pub fn builder(address : &str) -> ClientBuilder<L> {
+        ClientBuilder::new(address)
+    }
+
+    /// Like `connect`, but asks the server to speak `format` rather than
+    /// MessagePack. Fails if the server wasn't built with it, or is too old
+    /// to switch formats.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use coherent_rs::Discovery;
+    /// use coherent_rs::network::{BasicNetworkLaserClient, codec::WireFormat};
+    /// let client = BasicNetworkLaserClient::<Discovery>::connect_with_format(
+    ///     "127.0.0.1:907", Some(500), WireFormat::MessagePack
+    /// ).unwrap();
+    /// ```
+    pub fn connect_with_format(port : &str, timeout_duration : Option<u32>, format : WireFormat) -> Result<Self, TcpError> {
+        let builder = Self::builder(port).with_format(format);
+        match timeout_duration {
+            Some(timeout) => builder.with_read_timeout(std::time::Duration::from_millis(timeout as u64)),
+            None => builder,
+        }.connect()
+    }
+
+    /// Runs `operation` with a wall-clock bound: every call in it that waits
+    /// on the server (including retries under the `retry_policy`) gives up
+    /// with `TimeoutError` once `timeout` has passed. Nests -- the earliest
+    /// deadline wins.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use coherent_rs::Discovery;
+    /// use coherent_rs::network::{NetworkLaserClient, BasicNetworkLaserClient};
+    /// let mut client = BasicNetworkLaserClient::<Discovery>::connect("127.0.0.1:907", None).unwrap();
+    /// let status = client.with_timeout(Duration::from_secs(2), |client| client.query_status());
+    /// ```
+    pub fn with_timeout<T, F>(&mut self, timeout : std::time::Duration, operation : F) -> Result<T, TcpError>
+    where F : FnOnce(&mut Self) -> Result<T, TcpError> {
+        let outer_deadline = self._deadline;
+        self._deadline = Deadline::earliest(outer_deadline, Some(Deadline::after(timeout)));
+        let result = operation(self);
+        self._deadline = outer_deadline;
+        result
+    }
+
+    /// Connects to the same server again, with the same settings, after
+    /// the connection drops or the server restarts. Every command in the
+    /// `journal` becomes pending -- call `replay_pending` to send them.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use coherent_rs::Discovery;
+    /// use coherent_rs::network::{NetworkLaserClient, BasicNetworkLaserClient, TcpError};
+    /// let mut client = BasicNetworkLaserClient::<Discovery>::builder("127.0.0.1:907")
+    ///     .with_journal()
+    ///     .connect()
+    ///     .unwrap();
+    /// if let Err(TcpError::IoError(_)) = client.query_status() {
+    ///     client.reconnect().unwrap();
+    ///     client.replay_pending().unwrap();
+    /// }
+    /// ```
+    pub fn reconnect(&mut self) -> Result<(), TcpError> {
+        let builder = Self::builder(&self._address)
+            .with_format(self._format)
+            .with_buffer_size(self._limits.buffer_size)
+            .with_max_frame_size(self._limits.max_frame_size);
+        let reconnected = match self._read_timeout {
+            Some(timeout) => builder.with_read_timeout(timeout),
+            None => builder,
+        }.connect()?;
+        self._stream = reconnected._stream;
+        self._capabilities = reconnected._capabilities;
+        self._clock_offset = reconnected._clock_offset;
+        if let Some(journal) = self.journal.as_mut() {
+            journal.mark_all_pending();
+        }
+        Ok(())
+    }
+}
+
+impl<L : Laser> NetworkLaserClient<L> for  BasicNetworkLaserClient<L> {
+    /// Connect to a `NetworkLaser` over the network, if it exists
+    /// If timeout_duration is `Some`, it will wait for that many milliseconds
+    /// before giving up on the connection. If `None`, it will wait indefinitely.
+    /// # Example
+    /// ```no_run
+    /// use coherent_rs::Discovery;
+    /// use coherent_rs::network::{NetworkLaserClient, BasicNetworkLaserClient};
+    /// let mut client = BasicNetworkLaserClient::<Discovery>::connect("127.0.0.1:907", Some(500)).unwrap();
+    /// println!("{:?}", client.query_status().unwrap());
+    /// ```
+    fn connect(port : &str, timeout_duration : Option<u32>) -> Result<Self, TcpError> {
+        BasicNetworkLaserClient::connect_with_format(port, timeout_duration, WireFormat::MessagePack)
+    }
+
+    /// Allows access to the underlying `TcpStream`
+    fn access_stream(&mut self) -> &TcpStream {
+        &self._stream
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+
+    fn deadline(&self) -> Option<Deadline> {
+        self._deadline
+    }
+
+    fn wire_format(&self) -> WireFormat {
+        self._format
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self._capabilities.clone()
+    }
+
+    fn read_limits(&self) -> ReadLimits {
+        self._limits
+    }
+
+    fn clock_offset(&self) -> Option<ClockOffset> {
+        self._clock_offset
+    }
+
+    fn journal(&mut self) -> Option<&mut CommandJournal> {
+        self.journal.as_mut()
+    }
+
+    fn synchronize_clock(&mut self, samples : usize) -> Result<ClockOffset, TcpError> {
+        self._capabilities.require(Capability::Time)?;
+        let offset = request_clock_offset(&self._stream, self._format, self._deadline, self._limits, samples)?;
+        self._clock_offset = Some(offset);
+        Ok(offset)
+    }
+}
+
+/// A command for some laser model, serialized for the wire ahead of time
+/// so that it can be handed to a `DynNetworkLaserClient` without knowing
+/// the model at compile time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynCommand {
+    laser_type : LaserType,
+    payload : Vec<u8>,
+}
+
+impl DynCommand {
+    /// Erase a typed command for the laser `L`.
+    pub fn new<L : Laser>(command : &L::CommandEnum) -> Result<Self, TcpError> {
+        let mut payload = Vec::new();
+        command.serialize(&mut Serializer::new(&mut payload))
+            .map_err(TcpError::SerializationEncodeError)?;
+        Ok(DynCommand{laser_type : L::into_laser_type(), payload})
+    }
+
+    /// Build a command from an already-encoded `CommandEnum` payload
+    /// (without the `COMMAND_MARKER` or `TERMINATOR`).
+    pub fn from_raw(laser_type : LaserType, payload : Vec<u8>) -> Self {
+        DynCommand{laser_type, payload}
+    }
+
+    /// Translate a `CommonCommand` into the command for `laser_type`,
+    /// e.g. the type reported by `DynNetworkLaserClient::get_laser_type`.
+    pub fn from_common(laser_type : &LaserType, command : CommonCommand) -> Result<Self, TcpError> {
+        struct FromCommon(CommonCommand);
+
+        impl LaserTypeVisitor for FromCommon {
+            type Output = Result<DynCommand, TcpError>;
+
+            fn visit<L : Laser>(self) -> Self::Output {
+                DynCommand::new::<L>(&self.0.try_into().map_err(TcpError::CoherentError)?)
             }
 
-            // Read more data from the stream
-            match self.access_stream().read(&mut buf) {
-                Ok(n) => {
-                    // Append the new data to the accumulated buffer
-                    if n>0 { data.extend_from_slice(&buf[..n]); }
-                    else {
-                        // Check that the stream is still live.
-                        self.test_stream()?;
-                    }
-                }
-                Err(e) => {
-                    // Handle I/O errors
-                    return Err(TcpError::IoError(e));
-                }
+            fn unrecognized(self) -> Self::Output {
+                Err(TcpError::CoherentError(CoherentError::UnrecognizedDevice))
             }
         }
+
+        laser_type.visit(FromCommon(command))
+    }
+
+    /// The laser model this command was built for.
+    pub fn laser_type(&self) -> &LaserType {
+        &self.laser_type
+    }
+}
+
+/// A client that connects to whatever laser the server is hosting. The
+/// status is returned as a `StatusMap` and commands are sent as `DynCommand`s,
+/// so tooling (dashboards, the force-free utility) doesn't need to know the
+/// laser model at compile time.
+/// 
+/// # Example
+/// 
+/// ```no_run
+/// use coherent_rs::network::DynNetworkLaserClient;
+/// 
+/// let mut client = DynNetworkLaserClient::connect("127.0.0.1:907", Some(500)).unwrap();
+/// println!("Server is hosting a {:?}", client.get_laser_type());
+/// for (field, value) in client.query_status().unwrap() {
+///     println!("{} : {:?}", field, value);
+/// }
+/// ```
+pub struct DynNetworkLaserClient {
+    _stream : TcpStream,
+    _laser_type : LaserType,
+    _capabilities : Capabilities,
+    _clock_offset : Option<ClockOffset>,
+    /// Applied to `command` and `query_status`. Tries once by default.
+    pub retry_policy : RetryPolicy,
+    _deadline : Option<Deadline>,
+}
+
+impl DynNetworkLaserClient {
+    /// Connect to a `NetworkLaserServer` hosting any type of laser.
+    /// If `timeout_duration` is `Some`, reads will wait that many milliseconds
+    /// before giving up. If `None`, they will wait indefinitely.
+    pub fn connect(port : &str, timeout_duration : Option<u32>) -> Result<Self, TcpError> {
+        let connecting = unix_timestamp();
+        let stream = TcpStream::connect(port)
+            .map_err(TcpError::IoError)?;
+
+        stream.set_read_timeout(
+            timeout_duration.map(|timeout| std::time::Duration::from_millis(timeout as u64))
+        ).map_err(TcpError::IoError)?;
+
+        // `DynCommand` payloads are MessagePack, so that's what we speak
+        let handshake = read_handshake(&stream, connecting, ReadLimits::default())?;
+        request_format(&stream, &handshake, WireFormat::MessagePack, ReadLimits::default())?;
+
+        Ok(DynNetworkLaserClient{
+            _stream : stream,
+            _laser_type : handshake.laser_type,
+            _capabilities : handshake.capabilities,
+            _clock_offset : handshake.clock,
+            retry_policy : RetryPolicy::default(),
+            _deadline : None,
+        })
+    }
+
+    /// See `BasicNetworkLaserClient::with_timeout`.
+    pub fn with_timeout<T, F>(&mut self, timeout : std::time::Duration, operation : F) -> Result<T, TcpError>
+    where F : FnOnce(&mut Self) -> Result<T, TcpError> {
+        let outer_deadline = self._deadline;
+        self._deadline = Deadline::earliest(outer_deadline, Some(Deadline::after(timeout)));
+        let result = operation(self);
+        self._deadline = outer_deadline;
+        result
+    }
+
+    fn deadline(&self) -> Option<Deadline> {
+        self._deadline
+    }
+
+    fn wire_format(&self) -> WireFormat {
+        WireFormat::MessagePack
+    }
+
+    fn read_limits(&self) -> ReadLimits {
+        ReadLimits::default()
+    }
+
+    /// Allows access to the underlying `TcpStream`
+    pub fn access_stream(&mut self) -> &TcpStream {
+        &self._stream
+    }
+
+    /// The type of laser the server reported at connection.
+    pub fn get_laser_type(&self) -> LaserType {
+        self._laser_type.clone()
+    }
+
+    /// See `NetworkLaserClient::capabilities`.
+    pub fn capabilities(&self) -> Capabilities {
+        self._capabilities.clone()
+    }
+
+    /// Sends a command to the server. Fails with `TcpError::LaserTypeMismatch`
+    /// without touching the network if the command was built for another model.
+    /// Blocks until the server responds.
+    pub fn command(&mut self, command : &DynCommand) -> Result<(), TcpError> {
+        if command.laser_type != self._laser_type {
+            return Err(TcpError::LaserTypeMismatch{
+                expected : command.laser_type.clone(),
+                actual : self._laser_type.clone(),
+            });
+        }
+
+        let mut buf = Vec::new();
+        #[cfg(feature = "opentelemetry")]
+        let trace = telemetry::ClientTrace::start(&format!("{:?} command", command.laser_type));
+        #[cfg(feature = "opentelemetry")]
+        if self._capabilities.supports(Capability::Trace) {
+            buf.extend(trace.frame());
+        }
+        buf.extend(COMMAND_MARKER);
+        buf.extend(&command.payload);
+        buf.extend(TERMINATOR);
+        let policy = self.retry_policy.clone();
+        let deadline = Deadline::earliest(self._deadline, policy.deadline());
+        let result = policy.run_until(deadline, || { call_and_wait_for_response!(self, &buf, deadline); });
+        #[cfg(feature = "opentelemetry")]
+        trace.finish(&result);
+        result
+    }
+
+    /// Returns a full status of the laser from the network as a map
+    /// of field name to value. Warning: blocking!
+    pub fn query_status(&mut self) -> Result<StatusMap, TcpError> {
+        let policy = self.retry_policy.clone();
+        let deadline = Deadline::earliest(self._deadline, policy.deadline());
+        let laser_type = self._laser_type.clone();
+        policy.run_until(deadline, || read_until(
+            self.access_stream(), deadline, ReadLimits::default(), |data| deserialize_status_map(&laser_type, data, WireFormat::MessagePack).ok()
+        ))
+    }
+
+    /// See `NetworkLaserClient::query_progress`.
+    pub fn query_progress(&mut self) -> Result<Progress, TcpError> {
+        self._capabilities.require(Capability::Progress)?;
+        let policy = self.retry_policy.clone();
+        let deadline = Deadline::earliest(self._deadline, policy.deadline());
+        policy.run_until(deadline, || read_until(
+            self.access_stream(), deadline, ReadLimits::default(),
+            |data| deserialize_after(data, PROGRESS_MARKER, WireFormat::MessagePack, true).ok()
+        ))
+    }
+
+    /// See `NetworkLaserClient::demand_primary_client`.
+    pub fn demand_primary_client(&mut self) -> Result<(), TcpError> {
+        call_and_wait_for_response!(
+            self, DEMAND_PRIMARY_CLIENT
+        );
+    }
+
+    /// See `NetworkLaserClient::forget_me`.
+    pub fn forget_me(&mut self) -> Result<(), TcpError> {
+        call_and_wait_for_response!(
+            self, FORGET_ME
+        );
+    }
+
+    /// See `NetworkLaserClient::force_forget_primary_client`.
+    pub fn force_forget_primary_client(&mut self) -> Result<(), TcpError> {
+        call_and_wait_for_response!(
+            self, FORGET_PRIMARY_CLIENT
+        );
+    }
+
+    /// See `NetworkLaserClient::lock`.
+    pub fn lock(&mut self, token : &str) -> Result<(), TcpError> {
+        self._capabilities.require(Capability::Lock)?;
+        let mut buf = LOCK_MARKER.to_vec();
+        buf.extend(token.as_bytes());
+        buf.extend(TERMINATOR);
+        call_and_wait_for_response!(self, &buf);
+    }
+
+    /// See `NetworkLaserClient::unlock`.
+    pub fn unlock(&mut self, token : &str) -> Result<(), TcpError> {
+        self._capabilities.require(Capability::Lock)?;
+        let mut buf = UNLOCK_MARKER.to_vec();
+        buf.extend(token.as_bytes());
+        buf.extend(TERMINATOR);
+        call_and_wait_for_response!(self, &buf);
+    }
+
+    /// See `NetworkLaserClient::confirm`.
+    pub fn confirm(&mut self, command : &DynCommand) -> Result<(), TcpError> {
+        self._capabilities.require(Capability::Confirm)?;
+        if command.laser_type != self._laser_type {
+            return Err(TcpError::LaserTypeMismatch{
+                expected : command.laser_type.clone(),
+                actual : self._laser_type.clone(),
+            });
+        }
+        let mut buf = CONFIRM_MARKER.to_vec();
+        buf.extend(&command.payload);
+        buf.extend(TERMINATOR);
+        call_and_wait_for_response!(self, &buf);
     }
 
-    /// Demand that the client be the primary client.
-    /// If the network already has a primary client, this will fail
-    /// and return a `TcpError::NotPrimaryClient`. Will block until
-    /// it receives confirmation.
-    fn demand_primary_client(&mut self) -> Result<(), TcpError> {
-        call_and_wait_for_response!(
-            self, DEMAND_PRIMARY_CLIENT
-        );
+    /// See `NetworkLaserClient::server_stats`.
+    pub fn server_stats(&mut self) -> Result<ServerStats, TcpError> {
+        self._capabilities.require(Capability::Stats)?;
+        request_stats(&self._stream, WireFormat::MessagePack, self._deadline, ReadLimits::default())
     }
 
-    fn forget_me(&mut self) -> Result<(), TcpError> {
-        call_and_wait_for_response!(
-            self, FORGET_ME
-        );
+    /// See `NetworkLaserClient::reload_config`.
+    pub fn reload_config(&mut self) -> Result<(), TcpError> {
+        self._capabilities.require(Capability::Reload)?;
+        call_and_wait_for_response!(self, RELOAD_CONFIG);
     }
 
-    /// Forces the server to forget the primary client. Will block until
-    /// it receives confirmation.
-    fn force_forget_primary_client(&mut self) -> Result<(), TcpError> {
-        call_and_wait_for_response!(
-            self, FORGET_PRIMARY_CLIENT
-        );
+    /// See `NetworkLaserClient::fault_report`.
+    pub fn fault_report(&mut self) -> Result<FaultReport, TcpError> {
+        self._capabilities.require(Capability::Faults)?;
+        request_faults(&self._stream, FAULTS_REQUEST, WireFormat::MessagePack, self._deadline, ReadLimits::default())
     }
 
-}
-
-/// A struct to generically connect to and communicate with a
-/// `NetworkLaser` over the network. Doesn't have any unique functionality
-/// or ability to query specific details, but can be used to send commands
-/// or get the full status of the laser.
-pub struct BasicNetworkLaserClient<L : Laser>{
-    _stream : TcpStream,
-    _laser : PhantomData<L>,
-}
+    /// See `NetworkLaserClient::clear_faults`.
+    pub fn clear_faults(&mut self) -> Result<FaultReport, TcpError> {
+        self._capabilities.require(Capability::Faults)?;
+        request_faults(&self._stream, CLEAR_FAULTS, WireFormat::MessagePack, self._deadline, ReadLimits::default())
+    }
 
-impl<L : Laser> NetworkLaserClient<L> for  BasicNetworkLaserClient<L> {
-    /// Connect to a `NetworkLaser` over the network, if it exists
-    /// If timeout_duration is `Some`, it will wait for that many milliseconds
-    /// before giving up on the connection. If `None`, it will wait indefinitely.
-    /// # Example
-    /// ```rust
-    /// use coherent_rs::{Discovery, create_listener, NetworkLaserInterface};
-    /// ```
-    fn connect(port : &str, timeout_duration : Option<u32>) -> Result<Self, TcpError> {
-        let mut stream = TcpStream::connect(port)
-            .map_err(|e| TcpError::IoError(e))?;
+    /// See `NetworkLaserClient::raw_command`.
+    pub fn raw_command(&mut self, token : &str, cmd : &str) -> Result<String, TcpError> {
+        self._capabilities.require(Capability::Raw)?;
+        let command = AdminRawCommand{token : token.to_string(), cmd : cmd.to_string()};
+        request_raw(&self._stream, &command, WireFormat::MessagePack, self._deadline, ReadLimits::default())
+    }
 
-        if let Some(timeout) = timeout_duration {
-            stream.set_read_timeout(Some(std::time::Duration::from_millis(timeout as u64)))
-                .map_err(|e| TcpError::IoError(e))?;
-        }
-        else {
-            stream.set_read_timeout(None)
-                .map_err(|e| TcpError::IoError(e))?;
+    /// See `NetworkLaserClient::validate`.
+    pub fn validate(&mut self, command : &DynCommand) -> Result<Validation, TcpError> {
+        self._capabilities.require(Capability::Validate)?;
+        if command.laser_type != self._laser_type {
+            return Err(TcpError::LaserTypeMismatch{
+                expected : command.laser_type.clone(),
+                actual : self._laser_type.clone(),
+            });
         }
+        let mut request = VALIDATE_MARKER.to_vec();
+        request.extend(&command.payload);
+        request.extend(TERMINATOR);
+        request_validation(&self._stream, &request, WireFormat::MessagePack, self._deadline, ReadLimits::default())
+    }
 
-        let mut state_stream_buf = [0u8; 1024];
-        while deserialize_laser_type(&state_stream_buf).is_err() {
-            stream.read(&mut state_stream_buf)
-                .map_err(|e| TcpError::IoError(e))?; // Read until we get the laser type
-        }
+    /// See `NetworkLaserClient::run_sweep`.
+    pub fn run_sweep(&mut self, sweep : &StartSweep, on_step : impl FnMut(&SweepStep) -> bool) -> Result<SweepReport, TcpError> {
+        self._capabilities.require(Capability::Sweep)?;
+        let request = frame(START_SWEEP_MARKER, sweep, WireFormat::MessagePack)?;
+        request_sweep(&self._stream, &request, WireFormat::MessagePack, self._deadline, ReadLimits::default(), on_step)
+    }
 
-        let laser_type = deserialize_laser_type(&state_stream_buf)?;
+    /// See `NetworkLaserClient::abort_sweep`.
+    pub fn abort_sweep(&mut self) -> Result<(), TcpError> {
+        self._capabilities.require(Capability::Sweep)?;
+        call_and_wait_for_response!(self, ABORT_SWEEP);
+    }
 
-        if !(laser_type == L::into_laser_type()) {
-            return Err(TcpError::CoherentError(CoherentError::UnrecognizedDevice))
-        }
+    /// See `NetworkLaserClient::abort`.
+    pub fn abort(&mut self) -> Result<(), TcpError> {
+        self._capabilities.require(Capability::Abort)?;
+        call_and_wait_for_response!(self, ABORT);
+    }
 
-        Ok(
-            BasicNetworkLaserClient::<L> {
-                _stream : stream,
-                _laser : PhantomData
-            }
-        )
+    /// See `NetworkLaserClient::clock_offset`.
+    pub fn clock_offset(&self) -> Option<ClockOffset> {
+        self._clock_offset
     }
 
-    /// Allows access to the underlying `TcpStream`
-    fn access_stream(&mut self) -> &TcpStream {
-        &self._stream
+    /// See `NetworkLaserClient::synchronize_clock`.
+    pub fn synchronize_clock(&mut self, samples : usize) -> Result<ClockOffset, TcpError> {
+        self._capabilities.require(Capability::Time)?;
+        let offset = request_clock_offset(&self._stream, WireFormat::MessagePack, self._deadline, ReadLimits::default(), samples)?;
+        self._clock_offset = Some(offset);
+        Ok(offset)
     }
 }
 
@@ -730,8 +2985,8 @@ mod tests {
     use super::*;
     use crate::laser::{Discovery, DiscoveryNXCommands, DiscoveryLaser};
     use crate::laser::debug::DebugLaser;
-
-    const TEST_IP : &str = "127.0.0.1:9999";
+    use crate::network::harness::TestServer;
+    use crate::laser::units::{Nanometers, GddFs2};
 
     #[test]
     fn test_deserialize_laser_type(){
@@ -743,7 +2998,7 @@ mod tests {
         tp.serialize(&mut Serializer::new(&mut buf)).unwrap();
         buf.extend(TERMINATOR);
 
-        let laser_type = deserialize_laser_type(&buf).unwrap();
+        let laser_type = deserialize_laser_type(&buf, WireFormat::MessagePack).unwrap();
 
         assert_eq!(laser_type, LaserType::DebugLaser);
     }
@@ -760,11 +3015,12 @@ mod tests {
         sent_message.extend(status_serialized);
         sent_message.extend(TERMINATOR);
 
-        let status = deserialize_laser_status::<DebugLaser>(&sent_message).unwrap();
+        let status = deserialize_laser_status::<DebugLaser>(&sent_message, WireFormat::MessagePack).unwrap();
         println!{"Deserialized : {:?}", status};
     }
 
     #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
     fn make_floating_server() {
         let discovery = Discovery::find_first().unwrap();
         let network_laser = NetworkLaserServer::new(discovery, "127.0.0.1:907", None);
@@ -774,6 +3030,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
     fn get_laser() {
         let discovery = Discovery::find_first().unwrap();
         let network_laser = NetworkLaserServer::new(discovery, "127.0.0.1:907", None);
@@ -782,6 +3039,29 @@ mod tests {
     }
 
     #[test]
+    fn get_laser_debug() {
+        let mut harness = TestServer::debug().unwrap();
+        harness.server().command(
+            DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(840.0)}
+        ).unwrap();
+        let mut laser_again = harness.into_server().get_laser().unwrap();
+        assert_eq!(laser_again.get_wavelength().unwrap(), 840.0);
+    }
+
+    #[test]
+    fn test_serialize_speed_debug() {
+        let mut laser = DebugLaser::default();
+
+        let now = std::time::Instant::now();
+        for _i in 0..100 {
+            let _serialized = laser.serialized_status().unwrap();
+        }
+
+        println!{"Average speed : {:?}", now.elapsed() / 100};
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
     fn test_serialize_speed() {
         let mut discovery = Discovery::find_first().unwrap();
         
@@ -801,6 +3081,8 @@ mod tests {
     }
 
     #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
+    #[allow(clippy::single_match)]
     fn test_command_speed() {
         use crate::laser::discoverynx::DiscoveryNXQueries;
         let mut discovery = Discovery::find_first().unwrap();
@@ -836,13 +3118,14 @@ mod tests {
         println!{"Average speed : {:?}", total / speeds.len() as u32};
     }
 
-     #[test]
+    #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
     fn test_network_laser_discovery() {
         let discovery = Discovery::find_first().unwrap();
 
         let mut network_laser = NetworkLaserServer::new(
             discovery, "127.0.0.1:9070", 
-            Some(0.2),
+            Some(Duration::from_millis(200)),
             // None
             ).unwrap();
 
@@ -911,17 +3194,12 @@ mod tests {
     /// listening on a network port.
     #[test]
     fn test_network_laser_debug() {
-        // let mut discovery = Discovery::find_first().unwrap();
-        let discovery = DebugLaser::find_first().unwrap();
+        let mut harness = TestServer::new(DebugLaser::default(), Some(Duration::from_millis(500))).unwrap();
 
-        let mut network_laser = NetworkLaserServer::new(
-            // discovery, "127.0.0.1:9070", 
-            discovery, TEST_IP,
-            Some(0.5),
-            // None
-            ).unwrap();
+        let mut my_interface = harness.client().unwrap();
+        let mut second_interface = harness.client().unwrap();
 
-        network_laser.poll().unwrap();
+        let network_laser = harness.server();
 
         network_laser.command(
             DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::VariableWavelength, state : false.into()}
@@ -931,8 +3209,6 @@ mod tests {
 
         println!{"Server created"};
 
-        let mut my_interface = BasicNetworkLaserClient::<DebugLaser>::connect(
-            TEST_IP, None).unwrap();
         assert_eq!(crate::laser::LaserType::DebugLaser, my_interface.get_laser_type());
 
 
@@ -943,9 +3219,6 @@ mod tests {
         println!{"Query took {:?}", start.elapsed()};
         assert_eq!(read_status.variable_shutter, false.into());
 
-        let mut second_interface = BasicNetworkLaserClient::<DebugLaser>::connect(
-            TEST_IP, None).unwrap();
-
         //print how long the command takes
         let start = std::time::Instant::now();
         second_interface.command(
@@ -991,23 +3264,12 @@ mod tests {
     /// UNFINISHED!
     #[test]
     fn test_disconnect_debug(){
-        use crate::{laser::debug::DebugLaser, laser::DiscoveryNXCommands,
-            network::{NetworkLaserServer, BasicNetworkLaserClient}
-        };
-
-        let discovery = DebugLaser::find_first().unwrap();
-        // let discovery = Discovery::find_first().unwrap();
-
-        let mut server = NetworkLaserServer::new(discovery, TEST_IP, Some(0.2))
-            .unwrap(); // polling interval = 200 ms
-        server.poll().unwrap();
-
-        // let mut client = BasicNetworkLaserClient::<Discovery>::connect(TEST_IP, Some(500)).unwrap();
-        let mut client = BasicNetworkLaserClient::<DebugLaser>::connect(TEST_IP, Some(500)).unwrap();
+        let harness = TestServer::new(DebugLaser::default(), Some(Duration::from_millis(200))).unwrap();
+        let mut client = harness.client().unwrap();
 
         println!("{:?}", client.query_status().unwrap());
         // Now destroy the server and poison the mutex
-        let mut laser_ref = server.get_laser().unwrap();
+        let mut laser_ref = harness.into_server().get_laser().unwrap();
         println!("{:?}", laser_ref.get_fault_text());
         
         client.query_status()
@@ -1015,7 +3277,841 @@ mod tests {
 
     }
 
+    /// A client built for the wrong model should be told what the server actually hosts.
+    #[test]
+    fn test_laser_type_mismatch(){
+        let harness = TestServer::debug().unwrap();
+
+        match BasicNetworkLaserClient::<Discovery>::connect(&harness.address(), Some(500)) {
+            Err(TcpError::LaserTypeMismatch{expected, actual}) => {
+                assert_eq!(expected, LaserType::DiscoveryNX);
+                assert_eq!(actual, LaserType::DebugLaser);
+            },
+            Err(e) => panic!("Unexpected error : {:?}", e),
+            Ok(_) => panic!("Should not connect a Discovery client to a DebugLaser server"),
+        }
+    }
+
+    #[test]
+    fn test_dyn_client(){
+        use crate::laser::{LaserType, StatusValue};
+
+        let mut harness = TestServer::debug().unwrap();
+
+        let mut client = harness.dyn_client().unwrap();
+        assert_eq!(client.get_laser_type(), LaserType::DebugLaser);
+
+        let status = client.query_status().unwrap();
+        assert_eq!(status["variable_shutter"], StatusValue::Text("Closed".to_string()));
+        assert_eq!(status["wavelength"], StatusValue::Float(920.0));
+        assert_eq!(status["calibrated_power_var"], StatusValue::Missing);
+
+        let open = DynCommand::new::<DebugLaser>(
+            &DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::VariableWavelength, state : true.into()}
+        ).unwrap();
+        client.command(&open).unwrap();
+        assert_eq!(harness.server().status().unwrap().variable_shutter, true.into());
+
+        let wrong_model = DynCommand::new::<Discovery>(
+            &DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::VariableWavelength, state : false.into()}
+        ).unwrap();
+        assert!(matches!(client.command(&wrong_model), Err(TcpError::LaserTypeMismatch{..})));
+
+        // Garbage in a command frame gets an answer instead of a hang
+        let garbage = DynCommand::from_raw(LaserType::DebugLaser, vec![0xc1]);
+        assert!(matches!(client.command(&garbage), Err(TcpError::Refused(ErrorFrame{code : ErrorCode::UnknownCommand, ..}))));
+
+        // A command the laser refuses comes back with the reason
+        let out_of_range = DynCommand::new::<DebugLaser>(
+            &DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(5000.0)}
+        ).unwrap();
+        assert!(matches!(
+            client.command(&out_of_range),
+            Err(TcpError::Remote(CoherentError::CommandNotExecutedError))
+        ));
+    }
+
+    #[test]
+    fn test_failure_reason(){
+        let mut response = STATUS_MARKER.to_vec();
+        response.extend(TERMINATOR);
+        response.extend(command_response(&Err(CoherentError::InvalidArgumentsError("no\nway".to_string())), WireFormat::MessagePack));
+        assert!(matches!(
+            failure_reason(&response, WireFormat::MessagePack),
+            TcpError::Remote(CoherentError::InvalidArgumentsError(message)) if message == "no\nway"
+        ));
+        assert!(matches!(failure_reason(COMMAND_FAILED, WireFormat::MessagePack), TcpError::CommandError));
+        assert_eq!(command_response(&Ok(()), WireFormat::MessagePack), COMMAND_SUCCESSFUL);
+
+        // Every refusal has an error frame, and still ends with the legacy line
+        assert!(response.ends_with(COMMAND_FAILED));
+        assert_eq!(
+            deserialize_after::<ErrorFrame>(&response, ERROR_FRAME_MARKER, WireFormat::MessagePack, true).unwrap(),
+            ErrorFrame::new(ErrorCode::InvalidArguments, Some("no\nway"))
+        );
+        let refused = error_response(ErrorCode::NothingToConfirm, Some("No held command matches"), WireFormat::MessagePack);
+        assert!(refused.ends_with(COMMAND_FAILED));
+        assert!(matches!(
+            failure_reason(&refused, WireFormat::MessagePack),
+            TcpError::Refused(ErrorFrame{code : ErrorCode::NothingToConfirm, detail : Some(detail)}) if detail == "No held command matches"
+        ));
+        let not_primary = error_response(ErrorCode::NotPrimaryClient, None, WireFormat::MessagePack);
+        assert!(not_primary.ends_with(NOT_PRIMARY_CLIENT));
+        assert!(matches!(failure_reason(&not_primary, WireFormat::MessagePack), TcpError::NotPrimaryClient));
+
+        // `TcpError`s travel too
+        let error = TcpError::IoError(std::io::Error::other("connection reset"));
+        let decoded : TcpError = rmp_serde::from_slice(&rmp_serde::to_vec(&error).unwrap()).unwrap();
+        assert!(matches!(decoded, TcpError::IoError(e) if e.to_string() == "connection reset"));
+    }
+
+    #[test]
+    fn test_server_side_tuning_hooks(){
+        use std::sync::{Arc, Mutex};
+
+        let mut harness = TestServer::debug().unwrap();
+        let tuned_to = Arc::new(Mutex::new(Vec::new()));
+        {
+            let tuned_to = tuned_to.clone();
+            harness.server().with_laser(move |laser| laser.tuning_hooks.after_tuning(move |event| {
+                tuned_to.lock().unwrap().push(event.to_nm);
+                Ok(())
+            })).unwrap();
+        }
+
+        let mut client = harness.client().unwrap();
+        client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(780.0)}).unwrap();
+        assert_eq!(*tuned_to.lock().unwrap(), vec![780.0]);
+    }
+
+    #[test]
+    fn test_server_side_soft_limits(){
+        use crate::laser::discoverynx::limits::SoftLimits;
+
+        let mut harness = TestServer::debug().unwrap();
+        harness.server().with_laser(|laser| laser.soft_limits = SoftLimits{
+            wavelength_nm : Some((750.0, 950.0)), ..Default::default()
+        }).unwrap();
+
+        let mut client = harness.client().unwrap();
+        // The client hears why
+        assert!(matches!(
+            client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(990.0)}),
+            Err(TcpError::Remote(CoherentError::SoftLimitError(_)))
+        ));
+        client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(900.0)}).unwrap();
+        assert_eq!(harness.server().status().unwrap().wavelength, 900.0);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_polling_interval(){
+        assert!(matches!(
+            NetworkLaserServer::new(DebugLaser::default(), "127.0.0.1:0", Some(Duration::ZERO)),
+            Err(TcpError::CoherentError(CoherentError::InvalidArgumentsError(_)))
+        ));
+        let mut server = NetworkLaserServer::new(DebugLaser::default(), "127.0.0.1:0", None).unwrap();
+        assert_eq!(server.polling_interval(), Duration::from_secs(1));
+        server.set_polling_interval(Duration::from_millis(50)).unwrap();
+        assert!(server.set_polling_interval(Duration::from_secs(7200)).is_err());
+        assert_eq!(server.polling_interval(), Duration::from_millis(50));
+
+        // The old seconds API still works, and is checked the same way
+        server.set_polling_interval_secs(0.25).unwrap();
+        assert_eq!(server.polling_interval(), Duration::from_millis(250));
+        assert!(server.set_polling_interval_secs(-1.0).is_err());
+        assert!(server.set_polling_interval_secs(0.0).is_err());
+        assert!(create_listener(DebugLaser::default(), "127.0.0.1:0", Some(0.001)).is_err());
+        let server = NetworkLaserServer::new_secs(DebugLaser::default(), "127.0.0.1:0", Some(0.5)).unwrap();
+        assert_eq!(server.polling_interval(), Duration::from_millis(500));
+        assert!(NetworkLaserServer::new_secs(DebugLaser::default(), "127.0.0.1:0", Some(f32::NAN)).is_err());
+    }
+
+    #[test]
+    fn test_config_reload(){
+        let path = std::env::temp_dir().join(format!("coherent-server-{}.conf", std::process::id()));
+        std::fs::write(&path, "polling_interval = 0.1\nwavelength_limits = 750 950\n").unwrap();
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        assert!(matches!(client.reload_config(), Err(TcpError::Remote(CoherentError::InvalidArgumentsError(_)))));
+
+        harness.server().stop_polling();
+        harness.server().load_config(&path).unwrap();
+        harness.server().poll().unwrap();
+        // Still connected, and the limits apply straight away
+        assert!(matches!(
+            client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(990.0)}),
+            Err(TcpError::Remote(CoherentError::SoftLimitError(_)))
+        ));
+
+        // Edited behind the server's back: picked up by the watcher
+        std::fs::write(&path, "polling_interval = 0.2\nwavelength_limits = 750 1000\n").unwrap();
+        // Limits are applied before the interval, so once it shows so do they
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while harness.server().polling_interval() != Duration::from_millis(200) {
+            assert!(std::time::Instant::now() < deadline, "The watcher never picked up the edit");
+            std::thread::sleep(Duration::from_millis(CONFIG_CHECK_MS / 4));
+        }
+        client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(990.0)}).unwrap();
+
+        // A broken edit changes nothing, even when asked for
+        std::fs::write(&path, "polling_interval = 0.3\nwavelength_limits = 1000\n").unwrap();
+        assert!(matches!(client.reload_config(), Err(TcpError::Remote(CoherentError::InvalidArgumentsError(_)))));
+        assert_eq!(harness.server().polling_interval(), Duration::from_millis(200));
+
+        std::fs::write(&path, "polling_interval = 0.3\n").unwrap();
+        client.reload_config().unwrap();
+        assert_eq!(harness.server().polling_interval(), Duration::from_millis(300));
+        harness.server().stop_polling();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_faults(){
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        assert!(client.fault_report().unwrap().is_clear());
+
+        harness.server().with_laser(|laser| laser.inject_fault(4, "Chiller flow")).unwrap();
+        assert_eq!(client.fault_report().unwrap(), FaultReport{code : 4, text : "Chiller flow".to_string()});
+
+        // Only the primary client may clear them
+        let mut primary = harness.client().unwrap();
+        primary.demand_primary_client().unwrap();
+        assert!(matches!(client.clear_faults(), Err(TcpError::NotPrimaryClient)));
+        assert!(!client.fault_report().unwrap().is_clear());
+        let report = primary.clear_faults().unwrap();
+        assert!(report.is_clear(), "{:?}", report);
+        harness.server().stop_polling();
+    }
+
+    #[test]
+    fn test_raw_command(){
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        // Refused until the server has a token
+        assert!(matches!(client.raw_command("", "?WV"), Err(TcpError::Remote(CoherentError::InvalidArgumentsError(_)))));
+
+        harness.server().stop_polling();
+        harness.server().set_admin_token(Some("hunter2"));
+        harness.server().poll().unwrap();
+        assert!(matches!(client.raw_command("guess", "WV=800"), Err(TcpError::Remote(CoherentError::Unauthorized))));
+        assert_eq!(client.raw_command("hunter2", "WV=800").unwrap(), "");
+        assert_eq!(client.raw_command("hunter2", "?WV").unwrap(), "800");
+        assert_eq!(harness.server().with_laser(|laser| laser.get_wavelength()).unwrap().unwrap(), 800.0);
+        harness.server().stop_polling();
+    }
+
+    #[test]
+    fn test_validate(){
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        let mut other = harness.client().unwrap();
+        let validation = client.validate(&DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)}).unwrap();
+        assert_eq!(validation.serial_commands, vec!["WV=800".to_string()]);
+        assert!(!validation.requires_confirmation);
+        assert_eq!(harness.server().with_laser(|laser| laser.get_wavelength()).unwrap().unwrap(), 920.0);
+
+        assert!(matches!(
+            client.validate(&DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(1200.0)}),
+            Err(TcpError::Remote(CoherentError::CommandNotExecutedError))
+        ));
+        harness.server().with_laser(|laser| laser.lock("alice")).unwrap().unwrap();
+        assert!(matches!(
+            client.validate(&DiscoveryNXCommands::Gdd{gdd_val : GddFs2(0.0)}),
+            Err(TcpError::Remote(CoherentError::LockedError))
+        ));
+        harness.server().with_laser(|laser| laser.unlock("alice")).unwrap().unwrap();
+
+        other.demand_primary_client().unwrap();
+        assert!(matches!(
+            client.validate(&DiscoveryNXCommands::Gdd{gdd_val : GddFs2(0.0)}),
+            Err(TcpError::NotPrimaryClient)
+        ));
+        assert!(other.validate(&DiscoveryNXCommands::Gdd{gdd_val : GddFs2(0.0)}).is_ok());
+        harness.server().stop_polling();
+    }
+
+    #[test]
+    fn test_progress(){
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        let mut other = harness.client().unwrap();
+        client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)}).unwrap();
+        let progress = client.query_progress().unwrap();
+        assert_eq!((progress.operation, progress.percent), (progress::Operation::Tuning, Some(100.0)));
+        assert!(progress.is_done());
+
+        // Nothing more once it's done, and nothing for the other client
+        let wait = std::time::Duration::from_millis(300);
+        assert!(client.with_timeout(wait, |client| client.query_progress()).is_err());
+        assert!(other.with_timeout(wait, |other| other.query_progress()).is_err());
+
+        // Nor after a command that doesn't tune
+        client.command(DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::VariableWavelength, state : crate::laser::ShutterState::Open}).unwrap();
+        assert!(client.with_timeout(wait, |client| client.query_progress()).is_err());
+        harness.server().stop_polling();
+    }
+
+    #[test]
+    fn test_sweep(){
+        use sweep::StartSweep;
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        let mut other = harness.client().unwrap();
+        let sweep = |start_nm, stop_nm, dwell| StartSweep{start_nm, stop_nm, step_nm : 50.0, dwell};
+
+        let mut heard = Vec::new();
+        let report = client.run_sweep(&sweep(800.0, 900.0, Duration::ZERO), |step| { heard.push(step.wavelength_nm); true }).unwrap();
+        assert_eq!(report.state, ProgressState::Done);
+        assert_eq!(heard, vec![800.0, 850.0, 900.0]);
+        assert_eq!(report.steps.iter().map(|step| step.wavelength_nm).collect::<Vec<_>>(), heard);
+        assert_eq!(client.query_status().unwrap().wavelength, 900.0);
+
+        // Stopped after the first step, while the second dwells
+        let report = client.run_sweep(&sweep(700.0, 1000.0, Duration::from_millis(500)), |_| false).unwrap();
+        assert_eq!((report.state, report.steps.len()), (ProgressState::Aborted, 1));
+        assert!(matches!(other.abort_sweep(), Err(TcpError::Remote(CoherentError::CommandNotExecutedError))));
+
+        assert!(matches!(
+            client.run_sweep(&sweep(600.0, 900.0, Duration::ZERO), |_| true),
+            Err(TcpError::Remote(CoherentError::InvalidArgumentsError(_)))
+        ));
+        other.demand_primary_client().unwrap();
+        assert!(matches!(client.run_sweep(&sweep(800.0, 900.0, Duration::ZERO), |_| true), Err(TcpError::NotPrimaryClient)));
+        harness.server().stop_polling();
+    }
+
+    #[test]
+    fn test_apply_preset(){
+        use crate::laser::{ShutterState, discoverynx::presets::Preset};
+        let mut laser = DebugLaser::default();
+        laser.presets.insert("imaging-920", Preset{
+            wavelength_nm : Some(920.0), gdd_fs2 : Some(-5000.0), variable_shutter : Some(ShutterState::Open), ..Default::default()
+        }).unwrap();
+        let harness = TestServer::new(laser, None).unwrap();
+        let mut client = harness.client().unwrap();
+        client.command(DiscoveryNXCommands::ApplyPreset{name : "imaging-920".to_string()}).unwrap();
+        let status = client.query_status().unwrap();
+        assert_eq!((status.wavelength, status.gdd, status.variable_shutter), (920.0, Some(-5000.0), ShutterState::Open));
+        assert!(matches!(
+            client.command(DiscoveryNXCommands::ApplyPreset{name : "imaging-930".to_string()}),
+            Err(TcpError::Remote(CoherentError::InvalidArgumentsError(_)))
+        ));
+    }
+
+    #[test]
+    fn test_abort(){
+        use sweep::StartSweep;
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        let mut other = harness.client().unwrap();
+        // Nothing running is nothing to stop
+        assert!(other.abort().is_err());
+
+        let running = std::thread::spawn(move || {
+            let sweep = StartSweep{start_nm : 700.0, stop_nm : 1000.0, step_nm : 10.0, dwell : Duration::from_millis(200)};
+            client.run_sweep(&sweep, |_| true)
+        });
+        std::thread::sleep(Duration::from_millis(300));
+        other.abort().unwrap();
+        let report = running.join().unwrap().unwrap();
+        assert_eq!(report.state, ProgressState::Aborted);
+        assert!(report.steps.len() < 31);
+        harness.server().stop_polling();
+    }
+
+    #[test]
+    fn test_is_primary(){
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _sender = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, address) = listener.accept().unwrap();
+        let connection = Connection::new(stream, address, WireFormat::MessagePack);
+        assert!(is_primary(&connection, &None));
+        assert!(is_primary(&connection, &Some(address)));
+        assert!(!is_primary(&connection, &Some(listener.local_addr().unwrap())));
+    }
+
+    #[test]
+    fn test_take_abort(){
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut sender = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, address) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(CLIENT_READ_TIMEOUT)).unwrap();
+        let connection = Connection::new(stream, address, WireFormat::MessagePack);
+        assert!(!take_abort(&connection));
+
+        // Only an abort is taken, and nothing after it
+        sender.write_all(STATS_REQUEST).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!take_abort(&connection));
+        let mut stats = vec![0u8; STATS_REQUEST.len()];
+        (&connection.stream).read_exact(&mut stats).unwrap();
+
+        sender.write_all(&[ABORT, STATS_REQUEST].concat()).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(take_abort(&connection));
+        (&connection.stream).read_exact(&mut stats).unwrap();
+        assert_eq!(stats, STATS_REQUEST);
+    }
+
+    /// Clients that go while they're being answered are dropped, and the
+    /// server carries on for everyone else.
+    #[test]
+    fn test_client_gone_mid_reply(){
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        for _ in 0..5 {
+            let mut gone = TcpStream::connect(harness.address()).unwrap();
+            gone.write_all(&[STATS_REQUEST, TIME_REQUEST, STATS_REQUEST].concat()).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(500));
+        assert_eq!(harness.server()._clients.lock().unwrap().len(), 1);
+        client.demand_primary_client().unwrap();
+        client.command(DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::FixedWavelength, state : crate::laser::ShutterState::Open}).unwrap();
+        harness.server().stop_polling();
+    }
+
+    #[test]
+    fn test_large_frames(){
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        // Longer than one read, both ways
+        let long_name = "x".repeat(5000);
+        client.command(DiscoveryNXCommands::GddCurveN{curve_name : long_name.clone()}).unwrap();
+        assert_eq!(client.query_status().unwrap().gdd_curve_n, Some(long_name));
+
+        harness.server().stop_polling();
+        harness.server().set_max_frame_size(2048);
+        harness.server().poll().unwrap();
+        assert!(matches!(
+            client.command(DiscoveryNXCommands::GddCurveN{curve_name : "y".repeat(3000)}),
+            Err(TcpError::FrameTooLarge{limit : 2048})
+        ));
+        // The rest of the frame is dropped, and the next one understood
+        client.command(DiscoveryNXCommands::GddCurveN{curve_name : "Default".to_string()}).unwrap();
+        assert_eq!(frame_too_large_limit(&frame_too_large(2048)), Some(2048));
+        harness.server().stop_polling();
+    }
+
+    #[test]
+    fn test_busy_laser(){
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        let laser = harness.server().shared_laser().unwrap();
+
+        // Something local holds the laser longer than the command thread waits
+        let guard = laser.lock().unwrap();
+        // The client hears why, rather than timing out
+        assert!(matches!(
+            client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(900.0)}),
+            Err(TcpError::Remote(CoherentError::LaserBusyError))
+        ));
+        drop(guard);
+
+        client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(900.0)}).unwrap();
+        assert_eq!(laser.status().unwrap().wavelength, 900.0);
+    }
+
+    #[test]
+    fn test_coalesce_commands(){
+        let mut harness = TestServer::debug().unwrap();
+        harness.server().set_coalesce_policy(Some(CoalescePolicy::parameters(&["wavelength", "gdd"]))).unwrap();
+        let laser = harness.server().shared_laser().unwrap();
+        let mut stream = TcpStream::connect(harness.address()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+
+        // A slider's worth of commands, sent without waiting for the answers
+        let commands = [
+            DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)},
+            DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-500.0)},
+            DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(900.0)},
+        ];
+        let frames = commands.iter()
+            .map(|command| frame(COMMAND_MARKER, command, WireFormat::MessagePack).unwrap())
+            .collect::<Vec<_>>();
+        stream.write_all(&frames.concat()).unwrap();
+
+        // Status broadcasts keep coming, so read for a while rather than to the end
+        let mut replies = Vec::new();
+        let mut read = [0u8; 4096];
+        let deadline = std::time::Instant::now() + Duration::from_millis(500);
+        while std::time::Instant::now() < deadline {
+            if let Ok(n) = stream.read(&mut read) {
+                replies.extend_from_slice(&read[..n]);
+            }
+        }
+        let find = |pattern : &[u8]| replies.windows(pattern.len()).position(|window| window == pattern);
+        let successes = replies.windows(COMMAND_SUCCESSFUL.len()).filter(|window| *window == COMMAND_SUCCESSFUL).count();
+        // One answer per command, the superseded one first
+        assert_eq!(successes, 2);
+        assert!(find(ERROR_MARKER).unwrap() < find(COMMAND_SUCCESSFUL).unwrap());
+        assert!(matches!(failure_reason(&replies, WireFormat::MessagePack), TcpError::Remote(CoherentError::Superseded)));
+
+        let status = laser.status().unwrap();
+        assert_eq!((status.wavelength, status.gdd), (900.0, Some(-500.0)));
+        let stats = harness.server().stats().unwrap();
+        assert_eq!((stats.commands, stats.commands_superseded), (2, 1));
+        harness.server().stop_polling();
+    }
+
+    #[test]
+    fn test_command_timeout(){
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        let mut other = harness.client().unwrap();
+        // A serial write that hangs for a while
+        harness.server().with_laser(|laser| laser.tuning_hooks.before_tuning(|_| {
+            std::thread::sleep(Duration::from_millis(700));
+            Ok(())
+        })).unwrap();
+        harness.server().stop_polling();
+        harness.server().set_command_timeout(Some(Duration::from_millis(100)));
+        harness.server().poll().unwrap();
+
+        let started = std::time::Instant::now();
+        assert!(matches!(
+            client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(900.0)}),
+            Err(TcpError::Remote(CoherentError::CommandTimedOut))
+        ));
+        assert!(started.elapsed() < Duration::from_millis(500));
+        // Everyone else is still answered
+        other.server_stats().unwrap();
+
+        // Finishes in the end, and the laser is free again
+        std::thread::sleep(Duration::from_millis(800));
+        assert_eq!(harness.server().status().unwrap().wavelength, 900.0);
+        other.command(DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-5000.0)}).unwrap();
+        harness.server().stop_polling();
+    }
+
+    /// Only one worker at a time: while one that timed out is still going,
+    /// the next command is refused rather than queued behind it.
+    #[test]
+    fn test_one_command_worker(){
+        let mut debug = DebugLaser::default();
+        debug.tuning_hooks.before_tuning(|_| {
+            std::thread::sleep(Duration::from_millis(300));
+            Ok(())
+        });
+        let laser = SharedLaser::new(debug);
+        let worker_running = Arc::new(AtomicBool::new(false));
+        let origin : SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let send = |command| send_command_within(&laser, &worker_running, command, origin, Duration::from_millis(50), || {});
+
+        assert!(matches!(send(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(900.0)}), Err(CoherentError::CommandTimedOut)));
+        assert!(matches!(send(DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-5000.0)}), Err(CoherentError::LaserBusyError)));
+
+        // Free again once it's done
+        std::thread::sleep(Duration::from_millis(400));
+        send(DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-5000.0)}).unwrap();
+        assert_eq!(laser.status().unwrap().wavelength, 900.0);
+    }
+
+    /// A command waiting on the laser doesn't keep the clients locked, so new
+    /// clients are still taken on.
+    #[test]
+    fn test_clients_free_while_command_runs(){
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        harness.server().with_laser(|laser| laser.tuning_hooks.before_tuning(|_| {
+            std::thread::sleep(Duration::from_millis(800));
+            Ok(())
+        })).unwrap();
+        harness.server().stop_polling();
+        harness.server().set_command_timeout(Some(Duration::from_secs(5)));
+        harness.server().poll().unwrap();
+
+        let running = std::thread::spawn(move || {
+            client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(900.0)})
+        });
+        std::thread::sleep(Duration::from_millis(200));
+        let started = std::time::Instant::now();
+        let _other = harness.client().unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(harness.server()._clients.lock().unwrap().len(), 2);
+        assert!(started.elapsed() < Duration::from_millis(400));
+        running.join().unwrap().unwrap();
+        harness.server().stop_polling();
+    }
+
+    #[test]
+    fn test_server_stats(){
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        for wavelength in [800.0, 850.0, 900.0] {
+            client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(wavelength)}).unwrap();
+        }
+        assert!(client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(10.0)}).is_err());
+        client.query_status().unwrap();
+
+        let stats = client.server_stats().unwrap();
+        assert_eq!((stats.commands, stats.commands_failed), (4, 1));
+        assert!(stats.commands_per_sec > 0.0);
+        assert!(stats.broadcasts > 0);
+        assert_eq!(stats.clients.len(), 1);
+        assert_eq!(stats.clients[0].commands, 4);
+        assert!(stats.clients[0].bytes_sent > 0);
+
+        let mut dyn_client = DynNetworkLaserClient::connect(&harness.address(), Some(1000)).unwrap();
+        assert_eq!(dyn_client.server_stats().unwrap().commands, 4);
+        assert_eq!(harness.server().stats().unwrap().commands, 4);
+    }
+
+    #[test]
+    fn test_clock_offset(){
+        use crate::laser::LaserStatus;
+
+        let harness = TestServer::debug().unwrap();
+        let mut client = BasicNetworkLaserClient::<DebugLaser>::connect(&harness.address(), Some(1000)).unwrap();
+
+        // Same clock on both ends, so the true offset is 0
+        let handshake = client.clock_offset().unwrap();
+        assert!(handshake.offset.abs() <= handshake.uncertainty() + 1e-3, "{:?}", handshake);
+        let synchronized = client.synchronize_clock(5).unwrap();
+        assert_eq!(client.clock_offset(), Some(synchronized));
+        assert!(synchronized.offset.abs() <= synchronized.uncertainty() + 1e-3, "{:?}", synchronized);
+        assert!(synchronized.round_trip < 0.5);
+
+        let status = client.query_status().unwrap();
+        let local = synchronized.local_timestamp(&status);
+        let drift = local.duration_since(status.timestamp()).unwrap_or_else(|e| e.duration());
+        assert!(drift < std::time::Duration::from_millis(50));
+
+        let mut dyn_client = DynNetworkLaserClient::connect(&harness.address(), Some(1000)).unwrap();
+        assert!(dyn_client.clock_offset().is_some());
+        assert!(dyn_client.synchronize_clock(2).is_ok());
+    }
+
+    #[test]
+    fn test_wire_format_negotiation(){
+        use std::io::Read;
+
+        let mut harness = TestServer::debug().unwrap();
+        let mut raw = TcpStream::connect(harness.address()).unwrap();
+        raw.set_read_timeout(Some(std::time::Duration::from_millis(1000))).unwrap();
+        let handshake = read_handshake(&raw, unix_timestamp(), ReadLimits::default()).unwrap();
+        assert_eq!((handshake.format, handshake.laser_type), (WireFormat::MessagePack, LaserType::DebugLaser));
+        assert_eq!(handshake.capabilities, Capabilities::all());
+
+        // Unknown formats are refused, and the connection carries on as before
+        raw.write_all(b"Codec: yaml\n").unwrap();
+        let mut response = Vec::new();
+        while !response.windows(COMMAND_FAILED.len()).any(|window| window == COMMAND_FAILED) {
+            let mut buf = [0u8; 1024];
+            let n = raw.read(&mut buf).unwrap();
+            response.extend(&buf[..n]);
+        }
+        assert!(matches!(
+            failure_reason(&response, WireFormat::MessagePack),
+            TcpError::Remote(CoherentError::InvalidArgumentsError(_))
+        ));
+
+        for format in WireFormat::available() {
+            let mut client = BasicNetworkLaserClient::<DebugLaser>::connect_with_format(
+                &harness.address(), Some(1000), format
+            ).unwrap();
+            assert_eq!(client.wire_format(), format);
+            client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(850.0)}).unwrap();
+            assert!(matches!(client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(10.0)}), Err(TcpError::Remote(_))));
+            assert_eq!(client.query_status().unwrap().wavelength, 850.0);
+            assert!(client.server_stats().unwrap().commands > 0);
+        }
+        assert_eq!(harness.server().status().unwrap().wavelength, 850.0);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_server(){
+        use std::io::Read;
+
+        let mut server = NetworkLaserServer::new(DebugLaser::default(), "127.0.0.1:0", Some(Duration::from_millis(100))).unwrap();
+        server.set_wire_format(WireFormat::Json);
+        server.poll().unwrap();
+        let address = server.local_addr().unwrap().to_string();
+
+        // A client with nothing but a JSON parser can read the handshake
+        let mut raw = TcpStream::connect(&address).unwrap();
+        raw.set_read_timeout(Some(std::time::Duration::from_millis(1000))).unwrap();
+        let mut hello = Vec::new();
+        while !hello.windows(LASER_ID.len()).any(|window| window == LASER_ID) || !hello.ends_with(TERMINATOR) {
+            let mut buf = [0u8; 1024];
+            let n = raw.read(&mut buf).unwrap();
+            hello.extend(&buf[..n]);
+        }
+        let hello = String::from_utf8_lossy(&hello);
+        assert!(hello.starts_with("Codec: json\n"), "{}", hello);
+        assert!(hello.contains("\"DebugLaser\""), "{}", hello);
+
+        // Rust clients still get MessagePack by default
+        let mut client = BasicNetworkLaserClient::<DebugLaser>::connect(&address, Some(1000)).unwrap();
+        assert_eq!(client.wire_format(), WireFormat::MessagePack);
+        client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(900.0)}).unwrap();
+        let mut dyn_client = DynNetworkLaserClient::connect(&address, Some(1000)).unwrap();
+        assert_eq!(dyn_client.query_status().unwrap()["wavelength"], StatusValue::Float(900.0));
+    }
+
+    #[test]
+    fn test_network_operator_lock(){
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        let mut other = harness.client().unwrap();
+
+        client.lock("rig-2").unwrap();
+        assert!(harness.server().status().unwrap().locked);
+        assert!(other.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(900.0)}).is_err());
+        assert!(harness.server().command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(900.0)}).is_err());
+        assert!(matches!(other.unlock("guess"), Err(TcpError::Remote(CoherentError::LockedError))));
+        assert!(other.lock("mine").is_err());
+
+        // Any client with the token can unlock
+        other.unlock("rig-2").unwrap();
+        assert!(!harness.server().status().unwrap().locked);
+        client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(900.0)}).unwrap();
+        assert_eq!(harness.server().status().unwrap().wavelength, 900.0);
+
+        // Only the primary client may lock, once there is one
+        client.demand_primary_client().unwrap();
+        assert!(matches!(other.lock("stray"), Err(TcpError::NotPrimaryClient)));
+        assert!(!harness.server().status().unwrap().locked);
+        client.lock("rig-2").unwrap();
+        other.unlock("rig-2").unwrap();
+    }
+
+    #[test]
+    fn test_two_person_confirmation(){
+        use crate::laser::{ShutterState, discoverynx::DiscoveryLaser};
+        use confirmation::ConfirmationPolicy;
+
+        let open = || DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::FixedWavelength, state : ShutterState::Open};
+        let mut harness = TestServer::debug().unwrap();
+        harness.server().set_confirmation_policy(Some(
+            ConfirmationPolicy::new(|_, command| matches!(
+                command, DiscoveryNXCommands::Shutter{state : ShutterState::Open, ..}
+            )).with_timeout(std::time::Duration::from_millis(500))
+        )).unwrap();
+
+        // Unflagged commands run straight away
+        let mut operator = harness.client().unwrap();
+        operator.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(900.0)}).unwrap();
+
+        // Nothing to confirm yet
+        let mut colleague = harness.client().unwrap();
+        assert!(matches!(colleague.confirm(open()), Err(TcpError::Refused(ErrorFrame{code : ErrorCode::NothingToConfirm, ..}))));
+
+        // Confirmed by a second client
+        let request = std::thread::spawn(move || operator.command(open()).map(|_| operator));
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert_eq!(harness.server().status().unwrap().fixed_shutter, ShutterState::Closed);
+        colleague.confirm(open()).unwrap();
+        let mut operator = request.join().unwrap().unwrap();
+        assert_eq!(harness.server().status().unwrap().fixed_shutter, ShutterState::Open);
+
+        // Unconfirmed, it times out and never runs
+        harness.server().command(
+            DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::FixedWavelength, state : ShutterState::Closed}
+        ).unwrap();
+        assert!(matches!(operator.command(open()), Err(TcpError::Remote(CoherentError::TimeoutError))));
+        assert_eq!(harness.server().status().unwrap().fixed_shutter, ShutterState::Closed);
+    }
+
+    #[test]
+    fn test_poisoned_confirmation_policy(){
+        use crate::laser::{ShutterState, discoverynx::DiscoveryLaser};
+
+        let open = || DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::FixedWavelength, state : ShutterState::Open};
+        let mut harness = TestServer::debug().unwrap();
+        let policy = Arc::clone(&harness.server()._confirmation_policy);
+        let _ = std::thread::spawn(move || {
+            let _held = policy.lock().unwrap();
+            panic!("poisoning the confirmation policy");
+        }).join();
+
+        // Refused, but the poll thread keeps serving
+        let mut client = harness.client().unwrap();
+        assert!(matches!(client.command(open()), Err(TcpError::Remote(CoherentError::LaserUnavailableError))));
+        assert!(matches!(client.confirm(open()), Err(TcpError::Remote(CoherentError::LaserUnavailableError))));
+        assert_eq!(client.query_status().unwrap().fixed_shutter, ShutterState::Closed);
+    }
+
+    #[test]
+    fn test_network_parameter_history(){
+        use crate::laser::{StatusValue, history::{ParameterHistory, ChangeOrigin}};
+
+        let mut harness = TestServer::debug().unwrap();
+        harness.server().with_laser(|laser| laser.parameter_history = Some(ParameterHistory::default())).unwrap();
+
+        let mut client = harness.client().unwrap();
+        let client_address = client.access_stream().local_addr().unwrap();
+        client.command(DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-2000.0)}).unwrap();
+        harness.server().command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)}).unwrap();
+
+        let changes = harness.server().with_laser(
+            |laser| laser.parameter_history.as_ref().unwrap().changes().to_vec()
+        ).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].parameter, "gdd");
+        assert_eq!(changes[0].before, StatusValue::Float(0.0));
+        assert_eq!(changes[0].origin, ChangeOrigin::Client(client_address));
+        assert_eq!(changes[1].parameter, "wavelength");
+        assert_eq!(changes[1].origin, ChangeOrigin::Local);
+    }
+
+    #[test]
+    fn test_traced_command(){
+        // A command preceded by a trace frame runs whether or not the
+        // server records spans
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        let mut buf = TRACE_MARKER.to_vec();
+        buf.extend(b"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+        buf.extend(TERMINATOR);
+        buf.extend(COMMAND_MARKER);
+        DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)}.serialize(&mut Serializer::new(&mut buf)).unwrap();
+        buf.extend(TERMINATOR);
+        client.access_stream().write_all(&buf).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(300));
+        assert_eq!(harness.server().status().unwrap().wavelength, 800.0);
+
+        assert!(skip_trace_frame(&buf).starts_with(COMMAND_MARKER));
+        assert_eq!(skip_trace_frame(COMMAND_MARKER), COMMAND_MARKER);
+    }
+
     #[test]
+    fn test_dyn_common_command(){
+        use crate::laser::{CommonCommand, LaserState, ShutterState};
+
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = harness.dyn_client().unwrap();
+        let laser_type = client.get_laser_type();
+
+        client.command(&DynCommand::from_common(&laser_type, CommonCommand::OpenShutter).unwrap()).unwrap();
+        client.command(&DynCommand::from_common(&laser_type, CommonCommand::Standby).unwrap()).unwrap();
+
+        let status = harness.server().status().unwrap();
+        assert_eq!(status.variable_shutter, ShutterState::Open);
+        assert_eq!(status.laser, LaserState::Standby);
+
+        assert!(DynCommand::from_common(&LaserType::UnrecognizedDevice, CommonCommand::On).is_err());
+    }
+
+    /// The force-free utility has to work without knowing the laser model.
+    #[test]
+    fn test_dyn_force_forget_primary_client(){
+        let harness = TestServer::debug().unwrap();
+
+        let mut primary = harness.client().unwrap();
+        let mut other = harness.client().unwrap();
+        primary.demand_primary_client().unwrap();
+        assert!(matches!(other.demand_primary_client(), Err(TcpError::NotPrimaryClient)));
+
+        let mut freer = harness.dyn_client().unwrap();
+        freer.force_forget_primary_client().unwrap();
+        assert!(other.demand_primary_client().is_ok());
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "hardware-tests"), ignore = "needs a Discovery NX connected")]
     fn test_readme_functionality(){
         use crate::{Discovery, DiscoveryNXCommands,
             network::{NetworkLaserServer, BasicNetworkLaserClient}
@@ -1023,7 +4119,7 @@ mod tests {
 
         let discovery = Discovery::find_first().unwrap();
 
-        let mut server = NetworkLaserServer::new(discovery, "127.0.0.1:9070", Some(0.2))
+        let mut server = NetworkLaserServer::new(discovery, "127.0.0.1:9070", Some(Duration::from_millis(200)))
             .unwrap(); // polling interval = 200 ms
         server.poll().unwrap();
 
@@ -1049,18 +4145,10 @@ mod tests {
     /// Tests spamming a debuglaser
     #[test]
     fn test_spamming_network() {
-        let discovery = DebugLaser::find_first().unwrap();
-
-        let mut network_laser = NetworkLaserServer::new(
-            discovery, "127.0.0.1:9070", 
-            Some(0.5),
-            // None
-            ).unwrap();
+        let harness = TestServer::new(DebugLaser::default(), Some(Duration::from_millis(500))).unwrap();
 
-        network_laser.poll().unwrap();
+        let mut my_interface = harness.client().unwrap();
 
-        let mut my_interface = BasicNetworkLaserClient::<DebugLaser>::connect("127.0.0.1:9070", None).unwrap();
-        
         // spam the laser!
         let start = std::time::Instant::now();
         for _i in 0..50 {
@@ -1075,22 +4163,11 @@ mod tests {
     /// Test primary client functionality on a debug laser
     #[test]
     fn test_primary_client_debug() {
-        let discovery = DebugLaser::find_first().unwrap();
-
-        let mut network_laser = NetworkLaserServer::new(
-            discovery, "127.0.0.1:9070",
-            Some(0.5),
-        ).unwrap();
-
-        network_laser.poll().unwrap();
+        let mut harness = TestServer::new(DebugLaser::default(), Some(Duration::from_millis(500))).unwrap();
 
-        let mut my_interface = BasicNetworkLaserClient::<DebugLaser>::connect(
-            "127.0.0.1:9070", None
-        ).unwrap();
-
-        let mut second_interface = BasicNetworkLaserClient::<DebugLaser>::connect(
-            "127.0.0.1:9070", None
-        ).unwrap();
+        let mut my_interface = harness.client().unwrap();
+        let mut second_interface = harness.client().unwrap();
+        let network_laser = harness.server();
 
         my_interface.command(
             DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::VariableWavelength, state : true.into()}
@@ -1136,5 +4213,106 @@ mod tests {
         assert_eq!(network_laser.status().unwrap().variable_shutter, true.into());
         
     }
-    
-}
\ No newline at end of file
+
+    #[test]
+    fn test_legacy_server(){
+        use std::io::Read;
+
+        // A server from before capabilities: introduces itself with just the
+        // laser type, broadcasts statuses, and runs commands
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                std::thread::spawn(move || {
+                    let mut hello = LASER_ID.to_vec();
+                    LaserType::DebugLaser.serialize(&mut Serializer::new(&mut hello)).unwrap();
+                    hello.extend(TERMINATOR);
+                    stream.write_all(&hello).unwrap();
+                    stream.set_read_timeout(Some(std::time::Duration::from_millis(20))).unwrap();
+                    let status = frame(STATUS_MARKER, &conformance::example_status(), WireFormat::MessagePack).unwrap();
+                    let mut buf = [0u8; 1024];
+                    loop {
+                        let connected = match stream.read(&mut buf) {
+                            Ok(0) => false,
+                            Ok(n) if buf[..n].starts_with(COMMAND_MARKER) => stream.write_all(COMMAND_SUCCESSFUL).is_ok(),
+                            _ => true,
+                        };
+                        if !connected || stream.write_all(&status).is_err() { return; }
+                    }
+                });
+            }
+        });
+
+        let mut client = BasicNetworkLaserClient::<DebugLaser>::connect(&address, Some(1000)).unwrap();
+        assert!(client.capabilities().is_legacy());
+        assert_eq!(client.clock_offset(), None);
+        client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(850.0)}).unwrap();
+        assert_eq!(client.query_status().unwrap().wavelength, 920.0);
+
+        // Newer requests fail straight away instead of waiting on the server
+        let start = std::time::Instant::now();
+        assert!(matches!(client.server_stats(), Err(TcpError::Unsupported(Capability::Stats))));
+        assert!(matches!(client.lock("rig-2"), Err(TcpError::Unsupported(Capability::Lock))));
+        assert!(matches!(client.synchronize_clock(4), Err(TcpError::Unsupported(Capability::Time))));
+        assert!(matches!(
+            client.confirm(DiscoveryNXCommands::FaultClear), Err(TcpError::Unsupported(Capability::Confirm))
+        ));
+        assert!(start.elapsed() < std::time::Duration::from_millis(100), "{:?}", start.elapsed());
+        for format in WireFormat::available().into_iter().skip(1) {
+            assert!(matches!(
+                BasicNetworkLaserClient::<DebugLaser>::connect_with_format(&address, Some(1000), format),
+                Err(TcpError::Unsupported(Capability::Codec))
+            ));
+        }
+
+        let mut dyn_client = DynNetworkLaserClient::connect(&address, Some(1000)).unwrap();
+        assert!(dyn_client.capabilities().is_legacy());
+        assert!(matches!(dyn_client.unlock("rig-2"), Err(TcpError::Unsupported(Capability::Lock))));
+        assert_eq!(dyn_client.query_status().unwrap()["wavelength"], StatusValue::Float(920.0));
+    }
+
+    #[test]
+    fn test_client_with_timeout(){
+        // A server that introduces itself, then never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut hello = LASER_ID.to_vec();
+                LaserType::DebugLaser.serialize(&mut Serializer::new(&mut hello)).unwrap();
+                hello.extend(TERMINATOR);
+                stream.write_all(&hello).unwrap();
+                std::thread::spawn(move || { let _ = std::io::copy(&mut stream, &mut std::io::sink()); });
+            }
+        });
+
+        // Reads would otherwise wait forever
+        let mut client = DynNetworkLaserClient::connect(&address, None).unwrap();
+        let heartbeat = DynCommand::new::<DebugLaser>(&DiscoveryNXCommands::Heartbeat).unwrap();
+        let start = std::time::Instant::now();
+        let result = client.with_timeout(std::time::Duration::from_millis(200), |client| {
+            client.command(&heartbeat)?;
+            client.query_status()
+        });
+        assert!(matches!(result, Err(TcpError::CoherentError(CoherentError::TimeoutError))), "{:?}", result.err());
+        assert!(start.elapsed() < std::time::Duration::from_millis(500), "{:?}", start.elapsed());
+        assert_eq!(client.access_stream().read_timeout().unwrap(), None);
+
+        // Retries stop when the policy's total timeout runs out
+        let mut client = BasicNetworkLaserClient::<DebugLaser>::connect(&address, Some(50)).unwrap();
+        client.retry_policy = RetryPolicy::new(1000)
+            .with_backoff(std::time::Duration::from_millis(10), 1.0)
+            .with_total_timeout(std::time::Duration::from_millis(200));
+        let start = std::time::Instant::now();
+        assert!(client.query_status().is_err());
+        assert!(start.elapsed() < std::time::Duration::from_millis(500), "{:?}", start.elapsed());
+
+        // Plenty of time for a server that does answer
+        let harness = TestServer::debug().unwrap();
+        let mut client = harness.client().unwrap();
+        client.with_timeout(std::time::Duration::from_secs(5), |client| {
+            client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(850.0)})
+        }).unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+//! admin.rs
+//!
+//! Sending the laser a serial command exactly as written, from a client --
+//! the remote `send_serial_command`, for debugging firmware from an office
+//! machine. The command skips everything the server normally checks (soft
+//! limits, the operator lock, confirmation, the primary client), so it's
+//! only carried out for clients that send the server's admin token, and
+//! not at all unless the server was given one with
+//! `NetworkLaserServer::set_admin_token`.
+//!
+//! ```text
+//! Admin raw: <AdminRawCommand>\n
+//! Raw reply: <the laser's reply, as a string>\n
+//! ```
+
+use serde::{Serialize, Deserialize};
+use subtle::ConstantTimeEq;
+
+use crate::CoherentError;
+use crate::laser::Laser;
+
+/// A serial command for the laser, and the token that allows it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminRawCommand {
+    pub token : String,
+    /// Sent as is, without the `\r\n`
+    pub cmd : String,
+}
+
+/// Carries out `command` on `laser` if its token is `admin_token`, and
+/// returns the laser's reply. `Unauthorized` for any other token. The
+/// tokens are compared in constant time, so how long a wrong one takes to
+/// refuse doesn't say how much of it was right.
+pub(crate) fn run<L : Laser>(command : &AdminRawCommand, admin_token : Option<&str>, laser : &mut L)
+    -> Result<String, CoherentError> {
+    match admin_token {
+        Some(token) if bool::from(token.as_bytes().ct_eq(command.token.as_bytes())) => laser.raw_command(&command.cmd),
+        Some(_) => Err(CoherentError::Unauthorized),
+        None => Err(CoherentError::InvalidArgumentsError("This server doesn't take raw commands".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::debug::DebugLaser;
+
+    #[test]
+    fn test_run() {
+        let mut laser = DebugLaser::default();
+        let command = |token : &str, cmd : &str| AdminRawCommand{token : token.to_string(), cmd : cmd.to_string()};
+        assert_eq!(run(&command("hunter2", "WV=840"), Some("hunter2"), &mut laser).unwrap(), "");
+        assert_eq!(run(&command("hunter2", "?WV"), Some("hunter2"), &mut laser).unwrap(), "840");
+        assert_eq!(run(&command("hunter2", "?NOPE"), Some("hunter2"), &mut laser).unwrap(), "COMMAND NOT EXECUTED");
+
+        assert!(matches!(run(&command("guess", "WV=800"), Some("hunter2"), &mut laser), Err(CoherentError::Unauthorized)));
+        assert!(matches!(run(&command("hunter", "WV=800"), Some("hunter2"), &mut laser), Err(CoherentError::Unauthorized)));
+        assert!(matches!(run(&command("", "WV=800"), None, &mut laser), Err(CoherentError::InvalidArgumentsError(_))));
+        assert_eq!(laser.get_wavelength().unwrap(), 840.0);
+    }
+}
@@ -0,0 +1,228 @@
+//! authorization.rs
+//!
+//! Letting the application hosting a `NetworkLaserServer` decide who may
+//! do what, with its own user database or booking system, rather than with
+//! a token. An `Authorizer` is asked about every command a client sends,
+//! before the laser is touched, and answers with an `Authorization`: run
+//! it, refuse it (the client gets `CoherentError::Unauthorized`), or hold it
+//! for a second client's confirmation as a `ConfirmationPolicy` would.
+//! Commands issued locally through the server are never asked about.
+//!
+//! A `ReservationCheck` does the same for becoming the primary client: a
+//! booking system can refuse control to anyone who doesn't have the laser
+//! booked right now, and say why.
+
+use std::net::SocketAddr;
+
+use serde::{Serialize, Deserialize};
+
+use crate::laser::Laser;
+
+/// What the server knows about the client sending a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub address : SocketAddr,
+    /// Whether the client is the server's primary client
+    pub primary : bool,
+}
+
+/// An `Authorizer`'s answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Authorization {
+    Allow,
+    Deny,
+    /// Hold the command until a second client confirms it, for as long as
+    /// the server's `ConfirmationPolicy` says (or
+    /// `confirmation::DEFAULT_CONFIRMATION_TIMEOUT` without one)
+    RequireConfirmation,
+}
+
+/// Decides whether a client's command runs.
+pub type AuthorizationCallback<L> = Box<dyn FnMut(&ClientInfo, &<L as Laser>::CommandEnum) -> Authorization + Send>;
+
+/// Asks a callback about each command clients send. Set with
+/// `NetworkLaserServer::set_authorizer`.
+///
+/// # Example
+///
+/// ```rust
+/// use std::net::IpAddr;
+/// use coherent_rs::laser::{debug::DebugLaser, discoverynx::DiscoveryNXCommands};
+/// use coherent_rs::network::NetworkLaserServer;
+/// use coherent_rs::network::authorization::{Authorizer, Authorization};
+///
+/// // Whoever has the laser booked right now
+/// let booked : IpAddr = "10.0.0.7".parse().unwrap();
+/// let server = NetworkLaserServer::new(DebugLaser::default(), "127.0.0.1:0", None).unwrap();
+/// server.set_authorizer(Some(Authorizer::new(move |client, command| {
+///     match command {
+///         DiscoveryNXCommands::Heartbeat => Authorization::Allow,
+///         _ if client.address.ip() == booked => Authorization::Allow,
+///         DiscoveryNXCommands::Laser{..} => Authorization::RequireConfirmation,
+///         _ => Authorization::Deny,
+///     }
+/// }))).unwrap();
+/// ```
+pub struct Authorizer<L : Laser> {
+    callback : AuthorizationCallback<L>,
+}
+
+impl<L : Laser> std::fmt::Debug for Authorizer<L> {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Authorizer").finish_non_exhaustive()
+    }
+}
+
+impl<L : Laser> Authorizer<L> {
+    pub fn new<F>(callback : F) -> Self
+    where F : FnMut(&ClientInfo, &L::CommandEnum) -> Authorization + Send + 'static {
+        Authorizer{callback : Box::new(callback)}
+    }
+
+    pub fn authorize(&mut self, client : &ClientInfo, command : &L::CommandEnum) -> Authorization {
+        (self.callback)(client, command)
+    }
+}
+
+/// Decides whether a client may become the primary client: `Err` with the
+/// reason it may not.
+pub type ReservationCallback = Box<dyn FnMut(&ClientInfo) -> Result<(), String> + Send>;
+
+/// Asks a callback whether each client that demands to be the primary
+/// client has the laser reserved. Set with
+/// `NetworkLaserServer::set_reservation_check`. It's asked on the server's
+/// command thread, so a slow calendar holds up every client: keep a cached
+/// copy of the bookings if the lookup takes more than a few milliseconds.
+///
+/// # Example
+///
+/// ```rust
+/// use std::net::IpAddr;
+/// use coherent_rs::laser::debug::DebugLaser;
+/// use coherent_rs::network::NetworkLaserServer;
+/// use coherent_rs::network::authorization::ReservationCheck;
+///
+/// let booked : IpAddr = "10.0.0.7".parse().unwrap();
+/// let server = NetworkLaserServer::new(DebugLaser::default(), "127.0.0.1:0", None).unwrap();
+/// server.set_reservation_check(Some(ReservationCheck::new(move |client| {
+///     if client.address.ip() == booked { Ok(()) }
+///     else { Err("The laser is booked by 10.0.0.7 until 17:00".to_string()) }
+/// }))).unwrap();
+/// ```
+pub struct ReservationCheck {
+    callback : ReservationCallback,
+}
+
+impl std::fmt::Debug for ReservationCheck {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReservationCheck").finish_non_exhaustive()
+    }
+}
+
+impl ReservationCheck {
+    pub fn new<F>(callback : F) -> Self
+    where F : FnMut(&ClientInfo) -> Result<(), String> + Send + 'static {
+        ReservationCheck{callback : Box::new(callback)}
+    }
+
+    pub fn check(&mut self, client : &ClientInfo) -> Result<(), String> {
+        (self.callback)(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CoherentError;
+    use crate::laser::{ShutterState, DiscoveryLaser, DiscoveryNXCommands, debug::DebugLaser};
+    use crate::network::{NetworkLaserClient, TcpError, harness::TestServer};
+    use crate::laser::units::Nanometers;
+
+    #[test]
+    fn test_authorizer() {
+        let open = || DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::FixedWavelength, state : ShutterState::Open};
+        let mut harness = TestServer::debug().unwrap();
+        let mut operator = harness.client().unwrap();
+        let mut colleague = harness.client().unwrap();
+        let operator_address = operator.access_stream().local_addr().unwrap();
+        harness.server().set_authorizer(Some(Authorizer::<DebugLaser>::new(move |client, command| {
+            match command {
+                DiscoveryNXCommands::Shutter{..} => Authorization::RequireConfirmation,
+                _ if client.address == operator_address => Authorization::Allow,
+                _ => Authorization::Deny,
+            }
+        }))).unwrap();
+
+        operator.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(900.0)}).unwrap();
+        assert!(matches!(
+            colleague.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)}),
+            Err(TcpError::Remote(CoherentError::Unauthorized))
+        ));
+        assert!(matches!(
+            colleague.validate(&DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)}),
+            Err(TcpError::Remote(CoherentError::Unauthorized))
+        ));
+        assert!(colleague.validate(&open()).unwrap().requires_confirmation);
+        assert_eq!(harness.server().status().unwrap().wavelength, 900.0);
+
+        // Held without a `ConfirmationPolicy`, until someone else confirms
+        let request = std::thread::spawn(move || operator.command(open()));
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert_eq!(harness.server().status().unwrap().fixed_shutter, ShutterState::Closed);
+        colleague.confirm(open()).unwrap();
+        request.join().unwrap().unwrap();
+        assert_eq!(harness.server().status().unwrap().fixed_shutter, ShutterState::Open);
+
+        // Local commands aren't asked about
+        harness.server().command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(1000.0)}).unwrap();
+    }
+
+    #[test]
+    fn test_authorizer_clear_faults() {
+        let mut harness = TestServer::debug().unwrap();
+        let mut operator = harness.client().unwrap();
+        let mut colleague = harness.client().unwrap();
+        harness.server().with_laser(|laser| laser.inject_fault(1, "Interlock open")).unwrap();
+        harness.server().set_authorizer(Some(Authorizer::<DebugLaser>::new(|_, command| match command {
+            DiscoveryNXCommands::FaultClear => Authorization::Deny,
+            _ => Authorization::Allow,
+        }))).unwrap();
+
+        // Reading the faults is fine, clearing them isn't
+        assert_eq!(operator.fault_report().unwrap().code, 1);
+        assert!(matches!(operator.clear_faults(), Err(TcpError::Remote(CoherentError::Unauthorized))));
+        assert_eq!(operator.fault_report().unwrap().code, 1);
+
+        // Or only once someone else confirms it
+        harness.server().set_authorizer(Some(Authorizer::<DebugLaser>::new(|_, command| match command {
+            DiscoveryNXCommands::FaultClear => Authorization::RequireConfirmation,
+            _ => Authorization::Allow,
+        }))).unwrap();
+        let request = std::thread::spawn(move || operator.clear_faults());
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert_eq!(harness.server().status().unwrap().faults.bits(), 1);
+        colleague.confirm(DiscoveryNXCommands::FaultClear).unwrap();
+        assert!(request.join().unwrap().unwrap().is_clear());
+    }
+
+    #[test]
+    fn test_reservation_check() {
+        let mut harness = TestServer::debug().unwrap();
+        let mut booked = harness.client().unwrap();
+        let mut walk_in = harness.client().unwrap();
+        let booked_address = booked.access_stream().local_addr().unwrap();
+        harness.server().set_reservation_check(Some(ReservationCheck::new(move |client| {
+            if client.address == booked_address { Ok(()) } else { Err("Booked until 17:00".to_string()) }
+        }))).unwrap();
+
+        match walk_in.demand_primary_client() {
+            Err(TcpError::Remote(CoherentError::ReservationDenied(reason))) => assert_eq!(reason, "Booked until 17:00"),
+            other => panic!("{:?}", other),
+        }
+        booked.demand_primary_client().unwrap();
+        assert!(matches!(
+            walk_in.command(DiscoveryNXCommands::Heartbeat),
+            Err(TcpError::NotPrimaryClient)
+        ));
+    }
+}
@@ -0,0 +1,178 @@
+//! builder.rs
+//!
+//! Connecting a `BasicNetworkLaserClient` with more than the defaults: how
+//! long each read may block, how much is read from the socket at a time,
+//! and how much a client will buffer waiting for one response before it
+//! decides the server is sending it something it will never make sense of.
+
+use std::marker::PhantomData;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::laser::{Laser, unix_timestamp, retry::RetryPolicy};
+use super::{BasicNetworkLaserClient, TcpError, DEFAULT_MAX_FRAME_SIZE, read_handshake, request_format};
+use super::codec::WireFormat;
+use super::journal::CommandJournal;
+
+/// How a client reads from its server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadLimits {
+    /// Bytes taken from the socket per read
+    pub buffer_size : usize,
+    /// The most a client buffers waiting for one response, including any
+    /// status broadcasts that arrive ahead of it. A read that leaves more
+    /// than this without a whole response fails with `TcpError::FrameTooLarge`.
+    pub max_frame_size : usize,
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        ReadLimits{buffer_size : 1024, max_frame_size : DEFAULT_MAX_FRAME_SIZE}
+    }
+}
+
+/// Settings for a `BasicNetworkLaserClient`, applied when it connects.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use coherent_rs::Discovery;
+/// use coherent_rs::network::BasicNetworkLaserClient;
+///
+/// let client = BasicNetworkLaserClient::<Discovery>::builder("192.168.1.20:907")
+///     .with_read_timeout(Duration::from_millis(500))
+///     .with_buffer_size(8192)
+///     .with_max_frame_size(64 * 1024)
+///     .connect()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientBuilder<L : Laser> {
+    address : String,
+    read_timeout : Option<Duration>,
+    format : WireFormat,
+    limits : ReadLimits,
+    retry_policy : RetryPolicy,
+    journal : Option<CommandJournal>,
+    _laser : PhantomData<L>,
+}
+
+impl<L : Laser> ClientBuilder<L> {
+    /// Reads block indefinitely, 1 KiB at a time, with at most 1 MiB
+    /// buffered for a response, in MessagePack, trying everything once.
+    pub fn new(address : &str) -> Self {
+        ClientBuilder{
+            address : address.to_string(),
+            read_timeout : None,
+            format : WireFormat::MessagePack,
+            limits : ReadLimits::default(),
+            retry_policy : RetryPolicy::default(),
+            journal : None,
+            _laser : PhantomData,
+        }
+    }
+
+    /// How long one read from the server may block. Use `with_timeout` on
+    /// the client to bound a whole operation instead.
+    pub fn with_read_timeout(mut self, timeout : Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Bytes taken from the socket per read. At least 1.
+    pub fn with_buffer_size(mut self, bytes : usize) -> Self {
+        self.limits.buffer_size = bytes.max(1);
+        self
+    }
+
+    /// See `ReadLimits::max_frame_size`.
+    pub fn with_max_frame_size(mut self, bytes : usize) -> Self {
+        self.limits.max_frame_size = bytes;
+        self
+    }
+
+    /// Asks the server to speak `format` (see `codec`).
+    pub fn with_format(mut self, format : WireFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, policy : RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Keeps a `CommandJournal` of the settings sent, to replay after an
+    /// outage.
+    pub fn with_journal(mut self) -> Self {
+        self.journal = Some(CommandJournal::new());
+        self
+    }
+
+    pub fn read_limits(&self) -> ReadLimits {
+        self.limits
+    }
+
+    /// Connects and reads the handshake. Fails if the server hosts another
+    /// model of laser, or can't speak the format.
+    pub fn connect(self) -> Result<BasicNetworkLaserClient<L>, TcpError> {
+        let connecting = unix_timestamp();
+        let stream = TcpStream::connect(&self.address).map_err(TcpError::IoError)?;
+        stream.set_read_timeout(self.read_timeout).map_err(TcpError::IoError)?;
+
+        let handshake = read_handshake(&stream, connecting, self.limits)?;
+        if handshake.laser_type != L::into_laser_type() {
+            return Err(TcpError::LaserTypeMismatch{
+                expected : L::into_laser_type(),
+                actual : handshake.laser_type,
+            })
+        }
+        request_format(&stream, &handshake, self.format, self.limits)?;
+
+        Ok(BasicNetworkLaserClient{
+            _stream : stream,
+            _laser : PhantomData,
+            retry_policy : self.retry_policy,
+            _deadline : None,
+            _format : self.format,
+            _capabilities : handshake.capabilities,
+            _clock_offset : handshake.clock,
+            _limits : self.limits,
+            _address : self.address,
+            _read_timeout : self.read_timeout,
+            journal : self.journal,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::debug::DebugLaser;
+    use crate::network::{NetworkLaserClient, harness::TestServer};
+
+    #[test]
+    fn test_builder() {
+        let harness = TestServer::debug().unwrap();
+        let mut client = BasicNetworkLaserClient::<DebugLaser>::builder(&harness.address())
+            .with_read_timeout(Duration::from_millis(500))
+            .with_buffer_size(7)
+            .connect()
+            .unwrap();
+        assert_eq!(client.read_limits().buffer_size, 7);
+        assert_eq!(client.access_stream().read_timeout().unwrap(), Some(Duration::from_millis(500)));
+        // Small reads still add up to a whole status
+        assert_eq!(client.query_status().unwrap().wavelength, 920.0);
+
+        // The handshake is bigger than this, and arrives in pieces
+        assert!(matches!(
+            ClientBuilder::<DebugLaser>::new(&harness.address()).with_buffer_size(16).with_max_frame_size(64).connect(),
+            Err(TcpError::FrameTooLarge{limit : 64})
+        ));
+        assert!(matches!(
+            ClientBuilder::<crate::Discovery>::new(&harness.address()).connect(),
+            Err(TcpError::LaserTypeMismatch{..})
+        ));
+    }
+}
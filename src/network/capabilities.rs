@@ -0,0 +1,183 @@
+//! capabilities.rs
+//!
+//! What a server can do beyond the original protocol (status broadcasts,
+//! commands, and the primary-client requests). A server lists its
+//! capabilities in the handshake, after the `Codec` line:
+//!
+//! ```text
+//! Capabilities: codec,stats,confirm,lock,trace,time,reload,faults,raw,validate,progress,sweep,abort
+//! ```
+//!
+//! Servers from before capabilities say nothing, and are taken to have
+//! none (`Capabilities::legacy`). Clients check before sending a request the
+//! server wouldn't understand, and fail straight away with
+//! `TcpError::Unsupported` rather than waiting on an answer that never comes,
+//! so labs can upgrade their machines one at a time. Names a client doesn't
+//! recognize are ignored, so servers can add capabilities freely.
+
+use serde::{Serialize, Deserialize};
+
+use super::{TcpError, TERMINATOR};
+
+/// Precedes the comma-separated names of the server's `Capability`s.
+pub const CAPABILITIES_MARKER : &[u8] = b"Capabilities: ";
+
+/// A request a server may or may not understand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Capability {
+    /// Switching wire formats (see `codec`)
+    Codec,
+    /// `STATS_REQUEST`
+    Stats,
+    /// `CONFIRM_MARKER` frames for two-person confirmation
+    Confirm,
+    /// `LOCK_MARKER` and `UNLOCK_MARKER`
+    Lock,
+    /// `TRACE_MARKER` frames ahead of commands. An older server would drop
+    /// the command along with the frame it doesn't recognize.
+    Trace,
+    /// `TIME_REQUEST`, and the server's time in the handshake (see `clock`)
+    Time,
+    /// `RELOAD_CONFIG`
+    Reload,
+    /// `FAULTS_REQUEST` and `CLEAR_FAULTS`
+    Faults,
+    /// `ADMIN_RAW_MARKER` frames (see `admin`)
+    Raw,
+    /// `VALIDATE_MARKER` frames (see `validation`)
+    Validate,
+    /// `PROGRESS_MARKER` frames pushed during long-running operations (see
+    /// `progress`)
+    Progress,
+    /// `START_SWEEP_MARKER` and `ABORT_SWEEP` (see `sweep`)
+    Sweep,
+    /// `ABORT`
+    Abort,
+}
+
+impl Capability {
+    pub const ALL : [Capability; 13] = [
+        Capability::Codec,
+        Capability::Stats,
+        Capability::Confirm,
+        Capability::Lock,
+        Capability::Trace,
+        Capability::Time,
+        Capability::Reload,
+        Capability::Faults,
+        Capability::Raw,
+        Capability::Validate,
+        Capability::Progress,
+        Capability::Sweep,
+        Capability::Abort,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::Codec => "codec",
+            Capability::Stats => "stats",
+            Capability::Confirm => "confirm",
+            Capability::Lock => "lock",
+            Capability::Trace => "trace",
+            Capability::Time => "time",
+            Capability::Reload => "reload",
+            Capability::Faults => "faults",
+            Capability::Raw => "raw",
+            Capability::Validate => "validate",
+            Capability::Progress => "progress",
+            Capability::Sweep => "sweep",
+            Capability::Abort => "abort",
+        }
+    }
+
+    pub fn from_name(name : &str) -> Option<Capability> {
+        Capability::ALL.into_iter().find(|capability| capability.name() == name.trim())
+    }
+}
+
+/// The set of `Capability`s a server announced.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::network::capabilities::{Capabilities, Capability};
+///
+/// let announced = Capabilities::parse(b"Capabilities: stats,teleport\nLaser ID: ...");
+/// assert!(announced.supports(Capability::Stats));
+/// assert!(!announced.supports(Capability::Lock));
+/// assert!(Capabilities::parse(b"Laser ID: ...").is_legacy());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Capabilities {
+    supported : Vec<Capability>,
+}
+
+impl Capabilities {
+    /// A server from before capabilities: none of them.
+    pub fn legacy() -> Self {
+        Capabilities::default()
+    }
+
+    /// Everything this version of the crate can do.
+    pub fn all() -> Self {
+        Capabilities{supported : Capability::ALL.to_vec()}
+    }
+
+    pub fn supports(&self, capability : Capability) -> bool {
+        self.supported.contains(&capability)
+    }
+
+    /// `TcpError::Unsupported` unless the server has `capability`.
+    pub fn require(&self, capability : Capability) -> Result<(), TcpError> {
+        if self.supports(capability) { Ok(()) } else { Err(TcpError::Unsupported(capability)) }
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.supported.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Capability> + '_ {
+        self.supported.iter().copied()
+    }
+
+    /// The handshake line announcing these capabilities.
+    pub fn line(&self) -> Vec<u8> {
+        let mut buf = CAPABILITIES_MARKER.to_vec();
+        buf.extend(self.iter().map(|capability| capability.name()).collect::<Vec<_>>().join(",").as_bytes());
+        buf.extend(TERMINATOR);
+        buf
+    }
+
+    /// The capabilities announced in a handshake, or `legacy` if there's no
+    /// announcement in it.
+    pub fn parse(handshake : &[u8]) -> Self {
+        let Some(start) = handshake.windows(CAPABILITIES_MARKER.len()).position(|window| window == CAPABILITIES_MARKER)
+            else { return Capabilities::legacy(); };
+        let rest = &handshake[start + CAPABILITIES_MARKER.len()..];
+        let end = rest.iter().position(|&b| b == TERMINATOR[0]).unwrap_or(rest.len());
+        let mut supported = Vec::new();
+        for capability in String::from_utf8_lossy(&rest[..end]).split(',').filter_map(Capability::from_name) {
+            if !supported.contains(&capability) { supported.push(capability); }
+        }
+        Capabilities{supported}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let all = Capabilities::all();
+        assert_eq!(all.line(), b"Capabilities: codec,stats,confirm,lock,trace,time,reload,faults,raw,validate,progress,sweep,abort\n");
+        assert_eq!(Capabilities::parse(&all.line()), all);
+        assert_eq!(Capabilities::parse(b"Capabilities: \n"), Capabilities::legacy());
+        assert!(matches!(
+            Capabilities::legacy().require(Capability::Confirm),
+            Err(TcpError::Unsupported(Capability::Confirm))
+        ));
+        let some = Capabilities::parse(b"Capabilities: lock, lock,warp\n");
+        assert_eq!(some.iter().collect::<Vec<_>>(), vec![Capability::Lock]);
+    }
+}
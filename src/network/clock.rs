@@ -0,0 +1,115 @@
+//! clock.rs
+//!
+//! Relating the server's clock to a client's, so the `timestamp` on a
+//! status (read with the server's clock) can be lined up with frames a
+//! client acquired (read with its own). The estimate is NTP's: the client
+//! notes when it asked and when it heard back, the server notes when it
+//! heard the request and when it answered, and the offset is taken from the
+//! midpoints. The true offset is always within `uncertainty` of the estimate.
+//!
+//! Every handshake carries the server's time, which gives a first estimate
+//! bracketed by the TCP connection's round trip. `synchronize_clock` on the
+//! clients refines it with a few `TIME_REQUEST`s, keeping the sample with the
+//! shortest round trip.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::laser::LaserStatus;
+
+/// When the server received a request and sent its reply, in seconds since
+/// the Unix epoch by its clock. Both are the same in a handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ServerTime {
+    pub received : f64,
+    pub sent : f64,
+}
+
+impl ServerTime {
+    pub fn at(now : f64) -> Self {
+        ServerTime{received : now, sent : now}
+    }
+}
+
+/// How far the server's clock is ahead of the client's.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::network::clock::{ClockOffset, ServerTime};
+///
+/// // Asked at 100.0 and answered at 100.2 by our clock; the server's clock
+/// // read 150.05 to 150.15 while it handled the request
+/// let offset = ClockOffset::from_exchange(100.0, ServerTime{received : 150.05, sent : 150.15}, 100.2);
+/// assert!((offset.offset - 50.0).abs() < 1e-9);
+/// assert!((offset.uncertainty() - 0.05).abs() < 1e-9);
+/// assert!((offset.to_local_secs(160.0) - 110.0).abs() < 1e-9);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockOffset {
+    /// Server time minus client time, in seconds
+    pub offset : f64,
+    /// The time spent on the network, not counting the server's handling, in
+    /// seconds
+    pub round_trip : f64,
+}
+
+impl ClockOffset {
+    /// The offset from one exchange: the client sent its request at `asked`
+    /// and had the reply by `answered`, both by its own clock.
+    pub fn from_exchange(asked : f64, server : ServerTime, answered : f64) -> Self {
+        ClockOffset{
+            offset : ((server.received - asked) + (server.sent - answered)) / 2.0,
+            round_trip : ((answered - asked) - (server.sent - server.received)).max(0.0),
+        }
+    }
+
+    /// The most the estimate can be off by, in seconds.
+    pub fn uncertainty(&self) -> f64 {
+        self.round_trip / 2.0
+    }
+
+    /// The most certain of `samples`.
+    pub fn best(samples : impl IntoIterator<Item = ClockOffset>) -> Option<ClockOffset> {
+        samples.into_iter().min_by(|a, b| a.round_trip.total_cmp(&b.round_trip))
+    }
+
+    /// A time in seconds since the Unix epoch by the server's clock, by the
+    /// client's.
+    pub fn to_local_secs(&self, server_secs : f64) -> f64 {
+        server_secs - self.offset
+    }
+
+    pub fn to_local(&self, server_time : SystemTime) -> SystemTime {
+        let offset = Duration::from_secs_f64(self.offset.abs());
+        let local = if self.offset >= 0.0 { server_time.checked_sub(offset) } else { server_time.checked_add(offset) };
+        local.unwrap_or(UNIX_EPOCH)
+    }
+
+    /// When `status` was read from the laser, by the client's clock.
+    pub fn local_timestamp<S : LaserStatus>(&self, status : &S) -> SystemTime {
+        self.to_local(status.timestamp())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset() {
+        // Server 2 s behind, 10 ms each way, 1 ms to answer
+        let offset = ClockOffset::from_exchange(10.0, ServerTime{received : 8.010, sent : 8.011}, 10.021);
+        assert!((offset.offset + 2.0).abs() < 1e-9);
+        assert!((offset.round_trip - 0.020).abs() < 1e-9);
+        let server_now = UNIX_EPOCH + Duration::from_secs(100);
+        assert_eq!(offset.to_local(server_now).duration_since(UNIX_EPOCH).unwrap().as_millis(), 102_000);
+
+        // Asymmetric delays only widen the uncertainty, which still covers the true offset
+        let skewed = ClockOffset::from_exchange(10.0, ServerTime::at(8.030), 10.040);
+        assert!((skewed.offset + 2.0).abs() <= skewed.uncertainty());
+        assert_eq!(ClockOffset::best([skewed, offset]), Some(offset));
+        assert_eq!(ClockOffset::best([]), None);
+    }
+}
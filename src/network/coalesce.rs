@@ -0,0 +1,159 @@
+//! coalesce.rs
+//!
+//! Dropping commands that a later one from the same client makes pointless.
+//! A GUI slider bound to the wavelength or the GDD sends a command for every
+//! step it's dragged through, faster than the laser can take them; running
+//! each in turn keeps the serial link busy, and the laser moving, long after
+//! the slider has stopped. With a `CoalescePolicy`, the server puts commands
+//! that arrive while it's busy in a queue per client, and of the queued
+//! commands in each class -- "the wavelength", say -- runs only the latest.
+//! The others are answered `CoherentError::Superseded` in their turn, so a
+//! client still hears back once per command, in order.
+
+use std::collections::{HashSet, VecDeque};
+
+use crate::laser::{Laser, discoverynx::DiscoveryNXCommands};
+use super::{COMMAND_MARKER, TRACE_MARKER, TERMINATOR};
+
+/// The class of a command, if commands of its class coalesce.
+pub type CommandClass<L> = Box<dyn FnMut(&<L as Laser>::CommandEnum) -> Option<String> + Send>;
+
+/// Which commands supersede which: queued commands the classifier puts in
+/// the same class are coalesced, and those it gives no class always run.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::debug::DebugLaser;
+/// use coherent_rs::network::{NetworkLaserServer, coalesce::CoalescePolicy};
+///
+/// let server = NetworkLaserServer::new(DebugLaser::default(), "127.0.0.1:0", None).unwrap();
+/// server.set_coalesce_policy(Some(CoalescePolicy::parameters(&["wavelength", "gdd"]))).unwrap();
+/// ```
+pub struct CoalescePolicy<L : Laser> {
+    class : CommandClass<L>,
+}
+
+impl<L : Laser> std::fmt::Debug for CoalescePolicy<L> {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CoalescePolicy").finish_non_exhaustive()
+    }
+}
+
+impl<L : Laser> CoalescePolicy<L> {
+    /// Coalesces the commands `class` gives the same class.
+    pub fn new<F>(class : F) -> Self
+    where F : FnMut(&L::CommandEnum) -> Option<String> + Send + 'static {
+        CoalescePolicy{class : Box::new(class)}
+    }
+
+    pub fn class_of(&mut self, command : &L::CommandEnum) -> Option<String> {
+        (self.class)(command)
+    }
+}
+
+impl<L : Laser<CommandEnum = DiscoveryNXCommands>> CoalescePolicy<L> {
+    /// Coalesces the commands that set each of `parameters`, named as in
+    /// `DiscoveryNXCommands::parameter_change` -- e.g. `wavelength`, `gdd`.
+    /// The two shutters are separate parameters, so closing one never
+    /// supersedes opening the other.
+    pub fn parameters(parameters : &[&str]) -> Self {
+        let parameters = parameters.iter().map(|parameter| parameter.to_string()).collect::<HashSet<_>>();
+        CoalescePolicy::new(move |command : &DiscoveryNXCommands| command.parameter_change()
+            .map(|(parameter, _)| parameter.to_string())
+            .filter(|parameter| parameters.contains(parameter))
+        )
+    }
+}
+
+/// A frame waiting its turn in a client's queue.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Queued {
+    Frame(Vec<u8>),
+    /// A command a later one in the queue made pointless, to be answered
+    /// `Superseded`
+    Superseded,
+}
+
+/// Takes the frames `received` holds out of it: all of them if it's
+/// `complete`, otherwise those before the last, which may still be
+/// arriving. Only commands (with any trace frame in front) are split off
+/// the frames before them -- they're what a client sends without waiting
+/// for each answer.
+pub(crate) fn split_frames(received : &mut Vec<u8>, complete : bool) -> Vec<Vec<u8>> {
+    let mut starts = Vec::new();
+    let mut traced = received.starts_with(TRACE_MARKER);
+    for (idx, _) in received.iter().enumerate().filter(|(_, byte)| **byte == TERMINATOR[0]) {
+        let rest = &received[idx + 1..];
+        if rest.starts_with(TRACE_MARKER) {
+            starts.push(idx + 1);
+            traced = true;
+        } else if rest.starts_with(COMMAND_MARKER) {
+            // A command right after a trace frame goes with it
+            if !std::mem::take(&mut traced) {
+                starts.push(idx + 1);
+            }
+        }
+    }
+    let end = if complete { received.len() } else { starts.pop().unwrap_or(0) };
+    let rest = received.split_off(end);
+    let whole = std::mem::replace(received, rest);
+    let mut frames = Vec::new();
+    let mut from = 0;
+    for start in starts.into_iter().chain(std::iter::once(whole.len())).filter(|&start| start > 0) {
+        frames.push(whole[from..start].to_vec());
+        from = start;
+    }
+    frames.retain(|frame| !frame.is_empty());
+    frames
+}
+
+/// Marks `Superseded` every frame in `queue` that `class` puts in the same
+/// class as a later one.
+pub(crate) fn coalesce(queue : &mut VecDeque<Queued>, mut class : impl FnMut(&[u8]) -> Option<String>) {
+    let mut later = HashSet::new();
+    for queued in queue.iter_mut().rev() {
+        let Queued::Frame(frame) = queued else { continue; };
+        if let Some(class) = class(frame) {
+            if !later.insert(class) {
+                *queued = Queued::Superseded;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_frames() {
+        let mut received = b"Command: a\nSTATS\nCommand: b\nTrace: t\nCommand: c\nCommand: d".to_vec();
+        assert_eq!(split_frames(&mut received, false), vec![
+            b"Command: a\nSTATS\n".to_vec(),
+            b"Command: b\n".to_vec(),
+            b"Trace: t\nCommand: c\n".to_vec(),
+        ]);
+        assert_eq!(received, b"Command: d");
+        received.extend(b"\n");
+        assert_eq!(split_frames(&mut received, true), vec![b"Command: d\n".to_vec()]);
+        assert!(received.is_empty());
+
+        let mut partial = b"Command: e".to_vec();
+        assert!(split_frames(&mut partial, false).is_empty());
+        assert_eq!(partial, b"Command: e");
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let frame = |text : &str| Queued::Frame(text.as_bytes().to_vec());
+        let mut queue = VecDeque::from([frame("WV=800"), frame("S=1"), frame("GDD=0"), frame("WV=850"), frame("WV=900")]);
+        coalesce(&mut queue, |frame| {
+            let text = std::str::from_utf8(frame).unwrap();
+            (!text.starts_with('S')).then(|| text.split('=').next().unwrap().to_string())
+        });
+        assert_eq!(queue, VecDeque::from([
+            Queued::Superseded, frame("S=1"), frame("GDD=0"), Queued::Superseded, frame("WV=900"),
+        ]));
+    }
+}
@@ -0,0 +1,210 @@
+//! codec.rs
+//!
+//! The wire encodings a `NetworkLaserServer` can speak. Everything framed
+//! by a marker -- the laser type, statuses, commands, errors, stats -- is
+//! encoded with the connection's `WireFormat`. MessagePack is always
+//! available and is what Rust clients use; JSON (the `json` feature) and
+//! CBOR (the `cbor` feature) are there for clients written in languages
+//! without a good MessagePack library.
+//!
+//! A server greets each new client with its default format as text
+//! (`CODEC_MARKER`, then the name, then the `TERMINATOR`) ahead of the
+//! `LASER_ID`. A client can switch formats at any time by sending the same
+//! line with the name of the one it wants; the server answers
+//! `COMMAND_SUCCESSFUL` and from then on speaks that format to it alone.
+
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+
+use super::TcpError;
+
+/// An encoding for values sent over the wire.
+pub trait Codec {
+    /// The name a client asks for the codec by.
+    const NAME : &'static str;
+
+    fn encode<T : Serialize + ?Sized>(value : &T) -> Result<Vec<u8>, TcpError>;
+
+    /// Decodes the value at the start of `bytes`, ignoring anything after
+    /// it (e.g. the `TERMINATOR`, or the next frame).
+    fn decode<T : DeserializeOwned>(bytes : &[u8]) -> Result<T, TcpError>;
+}
+
+/// Errors from codecs other than MessagePack are reported as MessagePack's,
+/// carrying their message, so `TcpError` doesn't change with the features.
+#[cfg(any(feature = "json", feature = "cbor"))]
+fn encode_error(error : impl std::fmt::Display) -> TcpError {
+    TcpError::SerializationEncodeError(rmp_serde::encode::Error::Syntax(error.to_string()))
+}
+
+#[cfg(any(feature = "json", feature = "cbor"))]
+fn decode_error(error : impl std::fmt::Display) -> TcpError {
+    TcpError::SerializationDecodeError(rmp_serde::decode::Error::Syntax(error.to_string()))
+}
+
+/// MessagePack, via `rmp_serde`.
+pub struct MessagePack;
+
+impl Codec for MessagePack {
+    const NAME : &'static str = "msgpack";
+
+    fn encode<T : Serialize + ?Sized>(value : &T) -> Result<Vec<u8>, TcpError> {
+        let mut buf = Vec::new();
+        value.serialize(&mut rmp_serde::Serializer::new(&mut buf))
+            .map_err(TcpError::SerializationEncodeError)?;
+        Ok(buf)
+    }
+
+    fn decode<T : DeserializeOwned>(bytes : &[u8]) -> Result<T, TcpError> {
+        T::deserialize(&mut rmp_serde::Deserializer::new(bytes))
+            .map_err(TcpError::SerializationDecodeError)
+    }
+}
+
+/// JSON, via `serde_json`. Never contains a raw newline, so a JSON frame
+/// always ends at its `TERMINATOR`.
+#[cfg(feature = "json")]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    const NAME : &'static str = "json";
+
+    fn encode<T : Serialize + ?Sized>(value : &T) -> Result<Vec<u8>, TcpError> {
+        serde_json::to_vec(value).map_err(encode_error)
+    }
+
+    fn decode<T : DeserializeOwned>(bytes : &[u8]) -> Result<T, TcpError> {
+        serde_json::Deserializer::from_slice(bytes).into_iter::<T>().next()
+            .unwrap_or_else(|| Err(serde::de::Error::custom("no JSON value")))
+            .map_err(decode_error)
+    }
+}
+
+/// CBOR, via `ciborium`.
+#[cfg(feature = "cbor")]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    const NAME : &'static str = "cbor";
+
+    fn encode<T : Serialize + ?Sized>(value : &T) -> Result<Vec<u8>, TcpError> {
+        let mut buf = Vec::new();
+        ciborium::into_writer(value, &mut buf).map_err(encode_error)?;
+        Ok(buf)
+    }
+
+    fn decode<T : DeserializeOwned>(bytes : &[u8]) -> Result<T, TcpError> {
+        ciborium::from_reader(bytes).map_err(decode_error)
+    }
+}
+
+/// Which `Codec` a connection uses, chosen at runtime.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::LaserType;
+/// use coherent_rs::network::codec::WireFormat;
+///
+/// let format = WireFormat::from_name("msgpack").unwrap();
+/// let bytes = format.encode(&LaserType::DebugLaser).unwrap();
+/// assert_eq!(format.decode::<LaserType>(&bytes).unwrap(), LaserType::DebugLaser);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum WireFormat {
+    #[default]
+    MessagePack,
+    #[cfg(feature = "json")]
+    Json,
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl WireFormat {
+    /// The formats this build can speak.
+    pub fn available() -> Vec<WireFormat> {
+        vec![
+            WireFormat::MessagePack,
+            #[cfg(feature = "json")]
+            WireFormat::Json,
+            #[cfg(feature = "cbor")]
+            WireFormat::Cbor,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WireFormat::MessagePack => MessagePack::NAME,
+            #[cfg(feature = "json")]
+            WireFormat::Json => Json::NAME,
+            #[cfg(feature = "cbor")]
+            WireFormat::Cbor => Cbor::NAME,
+        }
+    }
+
+    /// The format called `name`, if this build has it.
+    pub fn from_name(name : &str) -> Option<WireFormat> {
+        WireFormat::available().into_iter().find(|format| format.name() == name.trim())
+    }
+
+    pub fn encode<T : Serialize + ?Sized>(&self, value : &T) -> Result<Vec<u8>, TcpError> {
+        match self {
+            WireFormat::MessagePack => MessagePack::encode(value),
+            #[cfg(feature = "json")]
+            WireFormat::Json => Json::encode(value),
+            #[cfg(feature = "cbor")]
+            WireFormat::Cbor => Cbor::encode(value),
+        }
+    }
+
+    /// See `Codec::decode`.
+    pub fn decode<T : DeserializeOwned>(&self, bytes : &[u8]) -> Result<T, TcpError> {
+        match self {
+            WireFormat::MessagePack => MessagePack::decode(bytes),
+            #[cfg(feature = "json")]
+            WireFormat::Json => Json::decode(bytes),
+            #[cfg(feature = "cbor")]
+            WireFormat::Cbor => Cbor::decode(bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::{Laser, debug::DebugLaser, discoverynx::{DiscoveryNXCommands, DiscoveryNXStatus}};
+    use crate::CoherentError;
+    use crate::laser::units::Nanometers;
+
+    #[test]
+    fn test_round_trips() {
+        let status = DebugLaser::default().status().unwrap();
+        let command = DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(850.0)};
+        for format in WireFormat::available() {
+            assert_eq!(WireFormat::from_name(format.name()), Some(format));
+
+            let mut framed = format.encode(&status).unwrap();
+            framed.extend(b"\nCOMMAND SUCCESSFUL\n");
+            assert_eq!(format.decode::<DiscoveryNXStatus>(&framed).unwrap(), status, "{}", format.name());
+
+            let bytes = format.encode(&command).unwrap();
+            assert!(matches!(
+                format.decode::<DiscoveryNXCommands>(&bytes).unwrap(),
+                DiscoveryNXCommands::Wavelength{wavelength_nm} if wavelength_nm == Nanometers(850.0)
+            ));
+
+            let error = format.encode(&CoherentError::SoftLimitError("too far".to_string())).unwrap();
+            assert!(matches!(format.decode::<CoherentError>(&error).unwrap(), CoherentError::SoftLimitError(_)));
+            assert!(format.decode::<DiscoveryNXStatus>(&bytes[..bytes.len() / 2]).is_err());
+        }
+        assert_eq!(WireFormat::from_name("yaml"), None);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_is_readable() {
+        let bytes = WireFormat::Json.encode(&DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(850.0)}).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), "{\"Wavelength\":{\"wavelength_nm\":850.0}}");
+    }
+}
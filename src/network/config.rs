@@ -0,0 +1,222 @@
+//! config.rs
+//!
+//! Server settings that can change while clients stay connected: the
+//! polling interval, the format new clients are spoken to in, and the
+//! laser's soft limits. They're kept in a file next to the server; the
+//! server rereads it when it changes, or when a client sends
+//! `RELOAD_CONFIG`, so tightening a limit or slowing the polling doesn't
+//! mean restarting the server and kicking every client off.
+//!
+//! ```text
+//! # rig 3
+//! polling_interval = 0.5     # seconds
+//! wire_format = msgpack
+//! wavelength_limits = 750 1000
+//! max_abs_gdd = 20000
+//! shutter_lockout = false
+//! clamp_to_limits = true     # set the nearest allowed value instead of refusing
+//! ```
+
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::CoherentError;
+use crate::laser::{Laser, config, discoverynx::limits::SoftLimits, retry::RetryPolicy, shared::SharedLaser};
+use super::codec::WireFormat;
+use super::locking::{LockLevel, acquire};
+use super::check_polling_interval;
+
+/// The settings a server config file can name.
+const KEYS : [&str; 6] = ["polling_interval", "wire_format", "wavelength_limits", "max_abs_gdd", "shutter_lockout", "clamp_to_limits"];
+
+/// Any laser's `POLLING_INTERVAL_BOUNDS` are within these, in seconds.
+/// Checked again against the laser's own when the config is applied.
+const POLLING_INTERVAL_RANGE : (f32, f32) = (0.01, 3600.0);
+
+/// The contents of a server config file. Settings the file doesn't mention
+/// are `None` and left as they are.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::laser::debug::DebugLaser;
+/// use coherent_rs::network::{NetworkLaserServer, config::ServerConfig};
+///
+/// let config = ServerConfig::parse("polling_interval = 0.5\nwavelength_limits = 750 1000\n").unwrap();
+/// assert_eq!(config.soft_limits.as_ref().unwrap().wavelength_nm, Some((750.0, 1000.0)));
+///
+/// let mut server = NetworkLaserServer::new(DebugLaser::default(), "127.0.0.1:0", None).unwrap();
+/// server.apply_config(&config).unwrap();
+/// assert_eq!(server.polling_interval(), std::time::Duration::from_millis(500));
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ServerConfig {
+    /// Written in seconds
+    pub polling_interval : Option<Duration>,
+    /// For clients that connect from now on
+    pub wire_format : Option<WireFormat>,
+    /// If the file names any limit, every limit: the ones it doesn't name
+    /// are lifted. The laser's `unsafe_override` is never changed.
+    pub soft_limits : Option<SoftLimits>,
+}
+
+impl ServerConfig {
+    /// Reads `<setting> = <value>` lines, with `#` starting a comment.
+    /// Reports every problem in the file (see `laser::config`).
+    pub fn parse(text : &str) -> Result<Self, CoherentError> {
+        let mut parsed = ServerConfig::default();
+        let mut limits : Option<SoftLimits> = None;
+        let mut errors = Vec::new();
+        for (line_number, line) in config::content_lines(text) {
+            let Some((key, value)) = line.split_once('=').map(|(key, value)| (key.trim(), value.trim())) else {
+                errors.push(config::ConfigError::new(line_number, "line", "`<setting> = <value>`", line)
+                    .suggest("e.g. `polling_interval = 0.5`"));
+                continue;
+            };
+            let result = match key {
+                "polling_interval" => config::number(line_number, key, value, "a time in seconds", Some(POLLING_INTERVAL_RANGE))
+                    // to the microsecond, so `0.2` is 200 ms rather than 0.2f32 s
+                    .map(|interval| parsed.polling_interval = Some(Duration::from_micros((interval as f64 * 1e6).round() as u64))),
+                "wire_format" => {
+                    let names = WireFormat::available().iter().map(WireFormat::name).collect::<Vec<_>>();
+                    WireFormat::from_name(value)
+                        .map(|format| parsed.wire_format = Some(format))
+                        .ok_or_else(|| config::ConfigError::new(line_number, key, &format!("one of {}", names.join(", ")), value)
+                            .suggest_closest(value, &names))
+                },
+                "wavelength_limits" => {
+                    let bounds = value.split_whitespace().collect::<Vec<_>>();
+                    match bounds.as_slice() {
+                        [low, high] => config::number(line_number, key, low, "a wavelength in nm", None)
+                            .and_then(|low| Ok((low, config::number(line_number, key, high, "a wavelength in nm", None)?)))
+                            .and_then(|(low, high)| {
+                                if low > high {
+                                    return Err(config::ConfigError::new(line_number, key, "`<lowest> <highest>`", value)
+                                        .suggest(format!("did you mean `{} {}`?", high, low)));
+                                }
+                                limits.get_or_insert_with(SoftLimits::default).wavelength_nm = Some((low, high));
+                                Ok(())
+                            }),
+                        _ => Err(config::ConfigError::new(line_number, key, "`<lowest> <highest>` in nm", value)
+                            .suggest("e.g. `wavelength_limits = 750 1000`")),
+                    }
+                },
+                "max_abs_gdd" => config::number(line_number, key, value, "a GDD in fs^2", Some((0.0, f32::MAX)))
+                    .map(|max| limits.get_or_insert_with(SoftLimits::default).max_abs_gdd = Some(max)),
+                "shutter_lockout" | "clamp_to_limits" => match value {
+                    "true" | "false" => {
+                        let limits = limits.get_or_insert_with(SoftLimits::default);
+                        if key == "shutter_lockout" { limits.shutter_lockout = value == "true"; }
+                        else { limits.clamp = value == "true"; }
+                        Ok(())
+                    },
+                    _ => Err(config::ConfigError::new(line_number, key, "`true` or `false`", value)
+                        .suggest_closest(value, &["true", "false"])),
+                },
+                _ => Err(config::ConfigError::new(line_number, key, &format!("one of {}", KEYS.join(", ")), line)
+                    .suggest_closest(key, &KEYS)),
+            };
+            if let Err(e) = result { errors.push(e); }
+        }
+        parsed.soft_limits = limits;
+        config::report(parsed, errors)
+    }
+
+    /// Reads and parses a server config file (see `parse`).
+    pub fn load<P : AsRef<Path>>(path : P) -> Result<Self, CoherentError> {
+        config::load(path, "server config", ServerConfig::parse)
+    }
+}
+
+/// Applies `config` to a server's `laser` and settings. Nothing changes if
+/// the config has soft limits and the laser doesn't have `SoftLimits` --
+/// they're limits on `DiscoveryNXCommands` -- or a polling interval
+/// outside its `POLLING_INTERVAL_BOUNDS`. Takes the
+/// `PollingInterval` lock, then `WireFormat`, so callers may hold the
+/// laser's.
+pub(crate) fn apply<L : Laser>(config : &ServerConfig, laser : &mut L, polling_interval : &Mutex<Duration>, wire_format : &Mutex<WireFormat>)
+    -> Result<(), CoherentError> {
+    if let Some(interval) = config.polling_interval {
+        check_polling_interval::<L>(interval)?;
+    }
+    if let Some(limits) = &config.soft_limits {
+        let current = laser.soft_limits()
+            .and_then(|current| (current as &mut dyn std::any::Any).downcast_mut::<SoftLimits>())
+            .ok_or_else(|| CoherentError::InvalidArgumentsError(
+            "This laser has no soft limits to configure".to_string()
+        ))?;
+        *current = SoftLimits{unsafe_override : current.unsafe_override, ..limits.clone()};
+    }
+    if let Some(interval) = config.polling_interval {
+        **acquire(LockLevel::PollingInterval, || polling_interval.lock()).map_err(|_| CoherentError::LaserUnavailableError)? = interval;
+    }
+    if let Some(format) = config.wire_format {
+        **acquire(LockLevel::WireFormat, || wire_format.lock()).map_err(|_| CoherentError::LaserUnavailableError)? = format;
+    }
+    Ok(())
+}
+
+/// Rereads the config file at `path` and applies it, waiting for the laser
+/// as long as `policy` allows. A file with any problem changes nothing.
+pub(crate) fn reload<L : Laser>(path : &Path, laser : &SharedLaser<L>, policy : &RetryPolicy,
+    polling_interval : &Mutex<Duration>, wire_format : &Mutex<WireFormat>) -> Result<(), CoherentError> {
+    let config = ServerConfig::load(path)?;
+    let mut laser = acquire(LockLevel::Laser, || laser.try_lock_with(policy))?;
+    apply(&config, &mut **laser, polling_interval, wire_format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::debug::DebugLaser;
+
+    #[test]
+    fn test_parse() {
+        let config = ServerConfig::parse(
+            "# rig 3\npolling_interval = 0.25\nwire_format = msgpack\nmax_abs_gdd = 20000 # fs^2\n"
+        ).unwrap();
+        assert_eq!(config.polling_interval, Some(Duration::from_millis(250)));
+        assert_eq!(config.wire_format, Some(WireFormat::MessagePack));
+        assert_eq!(config.soft_limits, Some(SoftLimits{max_abs_gdd : Some(20000.0), ..Default::default()}));
+        assert_eq!(ServerConfig::parse("").unwrap(), ServerConfig::default());
+        assert!(ServerConfig::parse("clamp_to_limits = true").unwrap().soft_limits.unwrap().clamp);
+
+        let Err(CoherentError::InvalidArgumentsError(message)) = ServerConfig::parse(
+            "poling_interval = 1\nwavelength_limits = 1000 750\nshutter_lockout = yes\npolling_interval = 0.001\nmax_abs_gdd\n"
+        ) else { panic!() };
+        let lines = message.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5, "{}", message);
+        assert!(lines[0].ends_with("did you mean `polling_interval`?"), "{}", lines[0]);
+        assert!(lines[1].ends_with("did you mean `750 1000`?"), "{}", lines[1]);
+        assert!(lines[3].ends_with("the nearest allowed is 0.01"), "{}", lines[3]);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut laser = DebugLaser::default();
+        laser.soft_limits.unsafe_override = true;
+        laser.soft_limits.shutter_lockout = true;
+        let (interval, format) = (Mutex::new(Duration::from_secs(1)), Mutex::new(WireFormat::MessagePack));
+
+        let config = ServerConfig::parse("polling_interval = 2\nwavelength_limits = 750 1000\n").unwrap();
+        apply(&config, &mut laser, &interval, &format).unwrap();
+        assert_eq!(*interval.lock().unwrap(), Duration::from_secs(2));
+        assert_eq!(laser.soft_limits.wavelength_nm, Some((750.0, 1000.0)));
+        // Limits the file doesn't name are lifted, but not the override
+        assert!(!laser.soft_limits.shutter_lockout);
+        assert!(laser.soft_limits.unsafe_override);
+
+        // Settings a file doesn't mention are left alone
+        apply(&ServerConfig::default(), &mut laser, &interval, &format).unwrap();
+        assert_eq!(*interval.lock().unwrap(), Duration::from_secs(2));
+        assert_eq!(laser.soft_limits.wavelength_nm, Some((750.0, 1000.0)));
+
+        // Too fast for this laser: nothing changes
+        let config = ServerConfig::parse("polling_interval = 0.005\nmax_abs_gdd = 1000\n");
+        assert!(config.is_err());
+        let config = ServerConfig{polling_interval : Some(Duration::from_millis(1)), ..ServerConfig::parse("max_abs_gdd = 1000").unwrap()};
+        assert!(apply(&config, &mut laser, &interval, &format).is_err());
+        assert_eq!(laser.soft_limits.max_abs_gdd, None);
+    }
+}
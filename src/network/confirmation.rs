@@ -0,0 +1,134 @@
+//! confirmation.rs
+//!
+//! Two-person confirmation for hazardous commands. A `NetworkLaserServer`
+//! with a `ConfirmationPolicy` holds back any command the policy flags
+//! until a second client sends a matching confirmation frame -- and drops
+//! it, failing the request, if none arrives in time.
+
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use crate::laser::Laser;
+
+/// How long a held command waits for its confirmation, unless a
+/// `ConfirmationPolicy` says otherwise.
+pub const DEFAULT_CONFIRMATION_TIMEOUT : Duration = Duration::from_secs(30);
+
+/// Returns whether a command needs confirming, given the laser it's for.
+pub type ConfirmationPredicate<L> = Box<dyn FnMut(&mut L, &<L as Laser>::CommandEnum) -> bool + Send>;
+
+/// Decides which commands need a second client's confirmation, and who may
+/// give it. The predicate is handed the laser as well as the command, so it
+/// can depend on the laser's state (e.g. only opening a shutter above some
+/// power).
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use coherent_rs::laser::{ShutterState, debug::DebugLaser, discoverynx::{DiscoveryNXCommands, DiscoveryLaser}};
+/// use coherent_rs::network::{NetworkLaserServer, confirmation::ConfirmationPolicy};
+///
+/// let server = NetworkLaserServer::new(DebugLaser::default(), "127.0.0.1:0", None).unwrap();
+/// server.set_confirmation_policy(Some(
+///     ConfirmationPolicy::new(|laser : &mut DebugLaser, command| match command {
+///         DiscoveryNXCommands::Shutter{laser : output, state : ShutterState::Open} => {
+///             laser.get_power(*output).map(|mw| mw > 500.0).unwrap_or(true)
+///         },
+///         _ => false,
+///     }).with_timeout(Duration::from_secs(10))
+/// )).unwrap();
+/// ```
+pub struct ConfirmationPolicy<L : Laser> {
+    requires_confirmation : ConfirmationPredicate<L>,
+    /// How long a held command waits for its confirmation. Defaults to
+    /// `DEFAULT_CONFIRMATION_TIMEOUT`.
+    pub timeout : Duration,
+    /// The addresses allowed to confirm. If `None`, any client other
+    /// than the one that sent the command may.
+    pub authorized : Option<Vec<IpAddr>>,
+}
+
+impl<L : Laser> std::fmt::Debug for ConfirmationPolicy<L> {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConfirmationPolicy")
+            .field("timeout", &self.timeout)
+            .field("authorized", &self.authorized)
+            .finish()
+    }
+}
+
+impl<L : Laser> ConfirmationPolicy<L> {
+    /// A policy that holds every command for which `requires_confirmation`
+    /// returns `true`.
+    pub fn new<F>(requires_confirmation : F) -> Self
+    where F : FnMut(&mut L, &L::CommandEnum) -> bool + Send + 'static {
+        ConfirmationPolicy{
+            requires_confirmation : Box::new(requires_confirmation),
+            timeout : DEFAULT_CONFIRMATION_TIMEOUT,
+            authorized : None,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout : Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Adds `address` to the clients allowed to confirm.
+    pub fn authorize(mut self, address : IpAddr) -> Self {
+        self.authorized.get_or_insert_with(Vec::new).push(address);
+        self
+    }
+
+    pub fn requires_confirmation(&mut self, laser : &mut L, command : &L::CommandEnum) -> bool {
+        (self.requires_confirmation)(laser, command)
+    }
+
+    /// Whether the client at `confirmer` may confirm a command sent from
+    /// `requester`. Nobody confirms their own command.
+    pub fn may_confirm(&self, requester : &SocketAddr, confirmer : &SocketAddr) -> bool {
+        requester != confirmer && self.authorized.as_ref().is_none_or(
+            |authorized| authorized.contains(&confirmer.ip())
+        )
+    }
+}
+
+/// A command held by the server until it's confirmed or times out.
+pub(crate) struct PendingCommand<L : Laser> {
+    /// The client that sent the command, to answer once it's resolved
+    pub requester : TcpStream,
+    pub requester_address : SocketAddr,
+    /// The format the requester speaks, to answer it in
+    pub requester_format : super::codec::WireFormat,
+    pub command : L::CommandEnum,
+    /// The command as it was encoded, to match against confirmations
+    pub encoded : Vec<u8>,
+    pub deadline : Instant,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::debug::DebugLaser;
+    use crate::laser::discoverynx::DiscoveryNXCommands;
+
+    #[test]
+    fn test_may_confirm() {
+        let requester : SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let colleague : SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        let stranger : SocketAddr = "10.0.0.3:5000".parse().unwrap();
+
+        let mut policy = ConfirmationPolicy::<DebugLaser>::new(
+            |_, command| matches!(command, DiscoveryNXCommands::FaultClear)
+        );
+        assert!(policy.requires_confirmation(&mut DebugLaser::default(), &DiscoveryNXCommands::FaultClear));
+        assert!(!policy.requires_confirmation(&mut DebugLaser::default(), &DiscoveryNXCommands::Heartbeat));
+        assert!(policy.may_confirm(&requester, &stranger));
+        assert!(!policy.may_confirm(&requester, &requester));
+
+        let policy = policy.authorize(colleague.ip());
+        assert!(policy.may_confirm(&requester, &colleague));
+        assert!(!policy.may_confirm(&requester, &stranger));
+    }
+}
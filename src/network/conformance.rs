@@ -0,0 +1,362 @@
+//! conformance.rs
+//!
+//! Canonical frames of the network protocol, for checking clients written
+//! in other languages (Python, LabVIEW, C#...) against the exact bytes a
+//! `NetworkLaserServer` sends and expects. The same vectors ship with the
+//! crate under `vectors/<format>/`, one `<name>.bin` file per frame plus a
+//! `MANIFEST` listing each frame's name and contents, so a client's test
+//! suite can use them without running any Rust.
+//!
+//! A client should be able to parse every frame it receives (`handshake_*`,
+//! `status`, `response_*`, `stats_response`, `time_response`, `faults_response`, `raw_reply`,
+//! `validation_response`, `progress`, `sweep_step`) and produce every frame it sends
+//! (`command_*`, `confirm_*`, `validate_*`, the sweep requests, `abort`, and the admin requests). `verify` checks
+//! a frame a client produced: it passes if the bytes match exactly, or if
+//! they decode to the same value (e.g. a float sent as 64 rather than 32 bits).
+//!
+//! Regenerate the shipped vectors with `coherent vectors vectors` (built
+//! with `json,cbor`, so every format is written) after changing the
+//! protocol -- `test_shipped_vectors` fails until you do.
+
+use std::path::Path;
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use super::{
+    TcpError, codec::WireFormat, stats::{ServerStats, ClientStats}, clock::ServerTime,
+    frame, frame_too_large, handshake, codec_line, command_response, error_response, refusal, failure_reason, announced_format, deserialize_laser_type,
+    deserialize_after,
+    COMMAND_MARKER, CONFIRM_MARKER, STATUS_MARKER, STATS_MARKER, TERMINATOR, COMMAND_SUCCESSFUL, COMMAND_FAILED,
+    DEMAND_PRIMARY_CLIENT, FORGET_PRIMARY_CLIENT, FORGET_ME, LOCK_MARKER, UNLOCK_MARKER,
+    STATS_REQUEST, TIME_REQUEST, TIME_MARKER, FAULTS_REQUEST, CLEAR_FAULTS, FAULTS_MARKER, DEFAULT_MAX_FRAME_SIZE,
+    ADMIN_RAW_MARKER, RAW_REPLY_MARKER, admin::AdminRawCommand, VALIDATE_MARKER, VALIDATION_MARKER,
+    validation::Validation, PROGRESS_MARKER, progress::{Progress, Operation, ProgressState},
+    START_SWEEP_MARKER, ABORT_SWEEP, SWEEP_STEP_MARKER, ABORT, sweep::{StartSweep, SweepStep},
+    ERROR_FRAME_MARKER, error_frame::{ErrorCode, ErrorFrame},
+};
+use crate::CoherentError;
+use crate::laser::{LaserType, LaserState, ShutterState, AlignmentMode, Keyswitch, TuningStatus, FaultReport, Nanometers};
+use crate::laser::discoverynx::{DiscoveryNXCommands, DiscoveryNXStatus, DiscoveryLaser, faults::FaultFlags};
+
+/// Parses a frame and builds it again the way the server would, so frames
+/// that only differ in encoding choices compare equal.
+type Canonicalize = Box<dyn Fn(&[u8]) -> Result<Vec<u8>, TcpError>>;
+
+/// One canonical frame.
+pub struct TestVector {
+    /// Stable, and the file name of the shipped vector
+    pub name : &'static str,
+    /// What the frame carries, e.g. the `Debug` rendering of its value
+    pub description : String,
+    /// The exact bytes
+    pub frame : Vec<u8>,
+    canonicalize : Canonicalize,
+}
+
+/// How a frame matched its `TestVector`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conformance {
+    /// Byte for byte
+    Exact,
+    /// Encodes the same value differently
+    Equivalent,
+}
+
+#[derive(Debug)]
+pub enum ConformanceError {
+    /// There's no vector by this name
+    UnknownVector(String),
+    /// The frame couldn't be parsed at all
+    Undecodable(TcpError),
+    /// The frame parsed, but to something else. `actual` is the frame
+    /// as the server would have written what was parsed.
+    Mismatch{expected : Vec<u8>, actual : Vec<u8>},
+}
+
+impl TestVector {
+    /// Checks `frame`, produced by some other implementation, against this
+    /// vector.
+    pub fn verify(&self, frame : &[u8]) -> Result<Conformance, ConformanceError> {
+        if frame == self.frame.as_slice() {
+            return Ok(Conformance::Exact);
+        }
+        let actual = (self.canonicalize)(frame).map_err(ConformanceError::Undecodable)?;
+        if actual == self.frame {
+            Ok(Conformance::Equivalent)
+        }
+        else {
+            Err(ConformanceError::Mismatch{expected : self.frame.clone(), actual})
+        }
+    }
+}
+
+/// The value of a frame between `marker` and the `TERMINATOR`.
+fn framed_value<T : DeserializeOwned>(bytes : &[u8], marker : &[u8], format : WireFormat) -> Result<T, TcpError> {
+    let value = bytes.strip_prefix(marker)
+        .and_then(|rest| rest.strip_suffix(TERMINATOR))
+        .ok_or(TcpError::CoherentError(CoherentError::InvalidResponseError("Frame is missing its marker or terminator".to_string())))?;
+    format.decode(value)
+}
+
+fn value_vector<T>(name : &'static str, marker : &'static [u8], value : T, format : WireFormat) -> Result<TestVector, TcpError>
+where T : Serialize + DeserializeOwned + std::fmt::Debug + 'static {
+    Ok(TestVector{
+        name,
+        description : format!("{:?}", value),
+        frame : frame(marker, &value, format)?,
+        canonicalize : Box::new(move |bytes| frame(marker, &framed_value::<T>(bytes, marker, format)?, format)),
+    })
+}
+
+/// A frame with no encoded value in it, which has to match exactly.
+fn fixed_vector(name : &'static str, frame : Vec<u8>) -> TestVector {
+    let expected = frame.clone();
+    TestVector{
+        name,
+        description : String::from_utf8_lossy(&frame).trim_end().to_string(),
+        frame,
+        canonicalize : Box::new(move |bytes| if bytes == expected.as_slice() { Ok(bytes.to_vec()) }
+            else { Err(TcpError::CommandError) }),
+    }
+}
+
+fn handshake_vector(name : &'static str, laser_type : LaserType, format : WireFormat) -> Result<TestVector, TcpError> {
+    Ok(TestVector{
+        name,
+        description : format!("{} {:?}", format.name(), laser_type),
+        frame : handshake(&laser_type, format, ServerTime::at(example_time().sent))?,
+        canonicalize : Box::new(|bytes| {
+            let format = announced_format(bytes).ok_or(TcpError::NoLaserStatus)??;
+            let time = deserialize_after(bytes, TIME_MARKER, format, false)?;
+            handshake(&deserialize_laser_type(bytes, format)?, format, time)
+        }),
+    })
+}
+
+fn response_vector(name : &'static str, result : Result<(), CoherentError>, format : WireFormat) -> TestVector {
+    TestVector{
+        name,
+        description : format!("{:?}", result),
+        frame : command_response(&result, format),
+        canonicalize : Box::new(move |bytes| {
+            if bytes == COMMAND_SUCCESSFUL { return Ok(command_response(&Ok(()), format)); }
+            if !bytes.ends_with(COMMAND_FAILED) { return Err(TcpError::CommandError); }
+            match failure_reason(bytes, format) {
+                TcpError::Remote(error) => Ok(command_response(&Err(error), format)),
+                _ => Ok(COMMAND_FAILED.to_vec()),
+            }
+        }),
+    }
+}
+
+fn error_vector(name : &'static str, code : ErrorCode, detail : Option<&'static str>, format : WireFormat) -> TestVector {
+    TestVector{
+        name,
+        description : format!("{:?}", ErrorFrame::new(code, detail)),
+        frame : error_response(code, detail, format),
+        canonicalize : Box::new(move |bytes| {
+            let error = deserialize_after::<ErrorFrame>(bytes, ERROR_FRAME_MARKER, format, false)?;
+            if !bytes.ends_with(error.code.legacy_line()) { return Err(TcpError::CommandError); }
+            Ok(refusal(&error, format))
+        }),
+    }
+}
+
+/// The status in the `status` vector.
+pub fn example_status() -> DiscoveryNXStatus {
+    DiscoveryNXStatus{
+        echo : false,
+        laser : LaserState::On,
+        variable_shutter : ShutterState::Open,
+        fixed_shutter : ShutterState::Closed,
+        keyswitch : Keyswitch::On,
+        faults : FaultFlags::empty(),
+        fault_text : Some("No faults".to_string()),
+        tuning : TuningStatus::Ready,
+        alignment_var : AlignmentMode::Off,
+        alignment_fixed : AlignmentMode::Off,
+        status : "Ready".to_string(),
+        wavelength : 920.0,
+        power_var : Some(1250.0),
+        power_fixed : 800.0,
+        calibrated_power_var : Some(1180.5),
+        calibrated_power_fixed : None,
+        gdd_curve : Some(1),
+        gdd_curve_n : Some("Default".to_string()),
+        gdd : Some(-5000.0),
+        locked : false,
+        timestamp : 1700000000.5,
+        operating_hours : Some(1250.0),
+        baseplate_temperature : Some(25.5),
+        humidity : Some(8.0),
+        diode_current : Some(27.5),
+        heatsink_ok : Some(true),
+    }
+}
+
+/// The stats in the `stats_response` vector.
+pub fn example_stats() -> ServerStats {
+    ServerStats{
+        uptime : 3600.0,
+        commands : 42,
+        commands_failed : 2,
+        commands_per_sec : 0.5,
+        command_latency_ms : 12.5,
+        lock_wait_ms : 0.25,
+        broadcasts : 36000,
+        broadcast_latency_ms : 4.0,
+        clients : vec![ClientStats{
+            address : "192.168.1.20:50312".parse().unwrap(),
+            send_lag_ms : 0.125,
+            bytes_sent : 9000000,
+            commands : 42,
+        }],
+        poll_overruns : 3,
+        poll_overrun_ms : 150.0,
+        commands_superseded : 12,
+    }
+}
+
+/// The server's time in the `time_response` vector. The `handshake_*`
+/// vectors carry its `sent`.
+pub fn example_time() -> ServerTime {
+    ServerTime{received : 1700000000.25, sent : 1700000000.375}
+}
+
+/// Every vector for `format`, in a fixed order.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::network::{codec::WireFormat, conformance::{test_vectors, Conformance}};
+///
+/// for vector in test_vectors(WireFormat::MessagePack).unwrap() {
+///     // A client that re-encodes each frame it decodes should get it back
+///     assert_eq!(vector.verify(&vector.frame).unwrap(), Conformance::Exact);
+/// }
+/// ```
+pub fn test_vectors(format : WireFormat) -> Result<Vec<TestVector>, TcpError> {
+    let wavelength = || DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(850.0)};
+    let mut token = b"rig-2".to_vec();
+    token.extend(TERMINATOR);
+    Ok(vec![
+        handshake_vector("handshake_discovery_nx", LaserType::DiscoveryNX, format)?,
+        handshake_vector("handshake_debug_laser", LaserType::DebugLaser, format)?,
+        value_vector("status", STATUS_MARKER, example_status(), format)?,
+        value_vector("command_wavelength", COMMAND_MARKER, wavelength(), format)?,
+        value_vector("command_shutter", COMMAND_MARKER, DiscoveryNXCommands::Shutter{
+            laser : DiscoveryLaser::VariableWavelength, state : ShutterState::Open
+        }, format)?,
+        value_vector("command_fault_clear", COMMAND_MARKER, DiscoveryNXCommands::FaultClear, format)?,
+        value_vector("command_gdd_curve_name", COMMAND_MARKER, DiscoveryNXCommands::GddCurveN{
+            curve_name : "Custom".to_string()
+        }, format)?,
+        value_vector("command_apply_preset", COMMAND_MARKER, DiscoveryNXCommands::ApplyPreset{
+            name : "imaging-920".to_string()
+        }, format)?,
+        value_vector("confirm_wavelength", CONFIRM_MARKER, wavelength(), format)?,
+        response_vector("response_success", Ok(()), format),
+        response_vector("response_failure", Err(CoherentError::InvalidArgumentsError(
+            "Wavelength out of range".to_string()
+        )), format),
+        error_vector("response_not_primary_client", ErrorCode::NotPrimaryClient, Some("Another client is primary"), format),
+        error_vector("response_unknown_command", ErrorCode::UnknownCommand, None, format),
+        fixed_vector("response_frame_too_large", frame_too_large(DEFAULT_MAX_FRAME_SIZE)),
+        fixed_vector("demand_primary_client", DEMAND_PRIMARY_CLIENT.to_vec()),
+        fixed_vector("forget_primary_client", FORGET_PRIMARY_CLIENT.to_vec()),
+        fixed_vector("forget_me", FORGET_ME.to_vec()),
+        fixed_vector("lock", [LOCK_MARKER, &token].concat()),
+        fixed_vector("unlock", [UNLOCK_MARKER, &token].concat()),
+        fixed_vector("codec_request", codec_line(format)),
+        fixed_vector("stats_request", STATS_REQUEST.to_vec()),
+        value_vector("stats_response", STATS_MARKER, example_stats(), format)?,
+        fixed_vector("time_request", TIME_REQUEST.to_vec()),
+        value_vector("time_response", TIME_MARKER, example_time(), format)?,
+        fixed_vector("faults_request", FAULTS_REQUEST.to_vec()),
+        fixed_vector("clear_faults", CLEAR_FAULTS.to_vec()),
+        value_vector("faults_response", FAULTS_MARKER, FaultReport{code : 4, text : "Chiller flow".to_string()}, format)?,
+        value_vector("admin_raw", ADMIN_RAW_MARKER, AdminRawCommand{token : "hunter2".to_string(), cmd : "?FT".to_string()}, format)?,
+        value_vector("raw_reply", RAW_REPLY_MARKER, "Chiller flow".to_string(), format)?,
+        value_vector("validate_wavelength", VALIDATE_MARKER, wavelength(), format)?,
+        value_vector("validation_response", VALIDATION_MARKER, Validation{
+            serial_commands : vec!["WV=850".to_string(), "GDD=-5000".to_string()], requires_confirmation : false
+        }, format)?,
+        value_vector("progress", PROGRESS_MARKER, Progress{
+            operation : Operation::Tuning, state : ProgressState::Running, percent : Some(40.0), eta : Some(std::time::Duration::from_secs(3))
+        }, format)?,
+        value_vector("start_sweep", START_SWEEP_MARKER, StartSweep{
+            start_nm : 750.0, stop_nm : 950.0, step_nm : 50.0, dwell : std::time::Duration::from_millis(500)
+        }, format)?,
+        value_vector("sweep_step", SWEEP_STEP_MARKER, SweepStep{wavelength_nm : 800.0, power_mw : 1250.5}, format)?,
+        fixed_vector("abort_sweep", ABORT_SWEEP.to_vec()),
+        fixed_vector("abort", ABORT.to_vec()),
+    ])
+}
+
+/// Checks `frame` against the vector called `name` for `format`.
+pub fn verify(name : &str, format : WireFormat, frame : &[u8]) -> Result<Conformance, ConformanceError> {
+    test_vectors(format).map_err(ConformanceError::Undecodable)?
+        .into_iter()
+        .find(|vector| vector.name == name)
+        .ok_or(ConformanceError::UnknownVector(name.to_string()))?
+        .verify(frame)
+}
+
+/// Writes the vectors for `format` to `dir/<format name>/`, as the crate
+/// ships them.
+pub fn write_vectors(dir : &Path, format : WireFormat) -> Result<(), TcpError> {
+    let dir = dir.join(format.name());
+    std::fs::create_dir_all(&dir).map_err(TcpError::IoError)?;
+    let mut manifest = String::new();
+    for vector in test_vectors(format)? {
+        std::fs::write(dir.join(format!("{}.bin", vector.name)), &vector.frame).map_err(TcpError::IoError)?;
+        manifest.push_str(&format!("{}\t{}\n", vector.name, vector.description));
+    }
+    std::fs::write(dir.join("MANIFEST"), manifest).map_err(TcpError::IoError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shipped_vectors() {
+        let shipped = Path::new(env!("CARGO_MANIFEST_DIR")).join("vectors");
+        for format in WireFormat::available() {
+            for vector in test_vectors(format).unwrap() {
+                let path = shipped.join(format.name()).join(format!("{}.bin", vector.name));
+                let bytes = std::fs::read(&path).unwrap_or_else(|_| panic!("missing {}", path.display()));
+                assert!(
+                    bytes == vector.frame,
+                    "{} differs from the server's -- regenerate with `coherent vectors vectors`", path.display()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let format = WireFormat::MessagePack;
+        let vector = test_vectors(format).unwrap().into_iter().find(|v| v.name == "command_wavelength").unwrap();
+
+        // 850.0 as a 64-bit float, as e.g. msgpack-python would send it
+        let mut wide = COMMAND_MARKER.to_vec();
+        wide.extend(rmp_serde::to_vec(&std::collections::BTreeMap::from([
+            ("Wavelength", std::collections::BTreeMap::from([("wavelength_nm", 850.0f64)]))
+        ])).unwrap());
+        wide.extend(TERMINATOR);
+        assert_ne!(wide, vector.frame);
+        assert_eq!(vector.verify(&wide).unwrap(), Conformance::Equivalent);
+
+        let other = frame(COMMAND_MARKER, &DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(851.0)}, format).unwrap();
+        assert!(matches!(vector.verify(&other), Err(ConformanceError::Mismatch{..})));
+        assert!(matches!(vector.verify(b"Command: \n"), Err(ConformanceError::Undecodable(_))));
+
+        assert_eq!(verify("forget_me", format, FORGET_ME).unwrap(), Conformance::Exact);
+        assert!(verify("forget_me", format, b"FORGET ME").is_err());
+        assert!(matches!(verify("nope", format, b""), Err(ConformanceError::UnknownVector(_))));
+        for vector in test_vectors(format).unwrap() {
+            assert_eq!(vector.verify(&vector.frame).unwrap(), Conformance::Exact, "{}", vector.name);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! error_frame.rs
+//!
+//! Why the server refused a request, in a form a program can act on. Every
+//! refusal starts with an `ERROR_FRAME_MARKER` frame holding an
+//! `ErrorFrame`: an `ErrorCode`, whose name never changes meaning and is
+//! never reused, and, where there's more to say, a detail for people. A
+//! refused command still carries its `CoherentError` after an
+//! `ERROR_MARKER` as well, but that follows the error enum as it grows;
+//! the code is what to match on.
+//!
+//! Each refusal still ends with one of the legacy lines, `COMMAND_FAILED`
+//! or (for `ErrorCode::NotPrimaryClient`) `NOT_PRIMARY_CLIENT`, so clients
+//! written before error frames keep working. Those lines are deprecated as
+//! a way to tell what went wrong, and will go in a future protocol version
+//! -- but until then they're what ends the response, so a client should
+//! read up to one before acting on the frame in front of it.
+
+use serde::{Serialize, Deserialize};
+
+use crate::CoherentError;
+use super::{COMMAND_FAILED, NOT_PRIMARY_CLIENT};
+
+/// What went wrong, by a name that stays the same from release to release.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// Anything without a more specific code
+    Failed,
+    /// Another client is the primary client
+    NotPrimaryClient,
+    /// The laser didn't carry out the command, or answered it with
+    /// something that couldn't be understood
+    NotExecuted,
+    /// The command's arguments are out of range, or refused by the laser's
+    /// soft limits
+    InvalidArguments,
+    /// The laser is locked by an operator lock
+    Locked,
+    /// Someone else had the laser for longer than the server would wait
+    LaserBusy,
+    /// Refused by the server's authorizer
+    Unauthorized,
+    /// The server's reservation check wouldn't make this client primary
+    ReservationDenied,
+    /// Not run, because a later command from the same client set the same
+    /// thing
+    Superseded,
+    /// The laser didn't answer, or didn't finish, in time
+    TimedOut,
+    /// The serial link to the laser is down
+    LaserUnavailable,
+    /// Stopped part way through
+    Cancelled,
+    /// A confirmation that matched no held command
+    NothingToConfirm,
+    /// A command the server couldn't read -- e.g. one for another model of
+    /// laser
+    UnknownCommand,
+    /// A code this build doesn't know, from a newer server
+    #[serde(other)]
+    Unknown,
+}
+
+impl ErrorCode {
+    /// The legacy line that ends a refusal with this code.
+    pub fn legacy_line(&self) -> &'static [u8] {
+        match self {
+            ErrorCode::NotPrimaryClient => NOT_PRIMARY_CLIENT,
+            _ => COMMAND_FAILED,
+        }
+    }
+}
+
+impl From<&CoherentError> for ErrorCode {
+    fn from(error : &CoherentError) -> Self {
+        match error {
+            CoherentError::CommandNotExecutedError
+            | CoherentError::InvalidResponseError(_)
+            | CoherentError::VerificationFailed{..} => ErrorCode::NotExecuted,
+            CoherentError::InvalidArgumentsError(_) | CoherentError::SoftLimitError(_) => ErrorCode::InvalidArguments,
+            CoherentError::LockedError => ErrorCode::Locked,
+            CoherentError::LaserBusyError => ErrorCode::LaserBusy,
+            CoherentError::Unauthorized => ErrorCode::Unauthorized,
+            CoherentError::ReservationDenied(_) => ErrorCode::ReservationDenied,
+            CoherentError::Superseded => ErrorCode::Superseded,
+            CoherentError::TimeoutError | CoherentError::CommandTimedOut => ErrorCode::TimedOut,
+            CoherentError::SerialError(_)
+            | CoherentError::WriteError(_)
+            | CoherentError::LaserUnavailableError
+            | CoherentError::NoRecognizedLasers
+            | CoherentError::UnrecognizedDevice
+            | CoherentError::Reconnecting => ErrorCode::LaserUnavailable,
+            CoherentError::Cancelled => ErrorCode::Cancelled,
+            CoherentError::SerializationError => ErrorCode::Failed,
+        }
+    }
+}
+
+/// A refusal: what went wrong, and maybe more about it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ErrorFrame {
+    pub code : ErrorCode,
+    /// For people, not programs -- the wording may change
+    pub detail : Option<String>,
+}
+
+impl ErrorFrame {
+    pub fn new(code : ErrorCode, detail : Option<&str>) -> Self {
+        ErrorFrame{code, detail : detail.map(str::to_string)}
+    }
+}
+
+/// The code for `error`, with the reason it gives, if it gives one.
+impl From<&CoherentError> for ErrorFrame {
+    fn from(error : &CoherentError) -> Self {
+        let detail = match error {
+            CoherentError::InvalidArgumentsError(reason)
+            | CoherentError::InvalidResponseError(reason)
+            | CoherentError::SoftLimitError(reason)
+            | CoherentError::ReservationDenied(reason) => Some(reason.clone()),
+            CoherentError::VerificationFailed{parameter, expected, actual} =>
+                Some(format!("{} is {:?}, not {:?}", parameter, actual, expected)),
+            CoherentError::SerialError(e) => Some(e.to_string()),
+            CoherentError::WriteError(e) => Some(e.to_string()),
+            _ => None,
+        };
+        ErrorFrame{code : error.into(), detail}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::codec::WireFormat;
+
+    #[test]
+    fn test_error_codes() {
+        let frame = ErrorFrame::from(&CoherentError::ReservationDenied("Booked by rig-2".to_string()));
+        assert_eq!(frame, ErrorFrame::new(ErrorCode::ReservationDenied, Some("Booked by rig-2")));
+        assert_eq!(ErrorFrame::from(&CoherentError::LockedError), ErrorFrame::new(ErrorCode::Locked, None));
+        assert_eq!(ErrorCode::NotPrimaryClient.legacy_line(), NOT_PRIMARY_CLIENT);
+        assert_eq!(ErrorCode::Locked.legacy_line(), COMMAND_FAILED);
+
+        // The names are the stable part
+        #[derive(Serialize)]
+        struct Future {
+            code : &'static str,
+            detail : Option<String>,
+        }
+        let format = WireFormat::MessagePack;
+        let encoded = format.encode(&ErrorFrame::new(ErrorCode::NotPrimaryClient, None)).unwrap();
+        assert_eq!(encoded, format.encode(&Future{code : "NOT_PRIMARY_CLIENT", detail : None}).unwrap());
+        // and ones added later don't stop older clients reading the frame
+        let newer = format.encode(&Future{code : "LASER_ON_FIRE", detail : Some("Call someone".to_string())}).unwrap();
+        assert_eq!(format.decode::<ErrorFrame>(&newer).unwrap(), ErrorFrame::new(ErrorCode::Unknown, Some("Call someone")));
+    }
+}
@@ -0,0 +1,187 @@
+//! group.rs
+//!
+//! Several lasers on one rig, each behind its own `NetworkLaserServer`,
+//! commanded together: a `LaserGroup` sends every laser its commands at the
+//! same time, waits until each has answered, and reports which succeeded,
+//! so e.g. closing every shutter in the room doesn't stop at the first
+//! laser that fails.
+
+use super::{DynNetworkLaserClient, DynCommand, TcpError};
+use crate::CoherentError;
+use crate::laser::CommonCommand;
+
+/// How each laser in a `LaserGroup` answered, in the order the lasers were
+/// added. Lasers that weren't sent anything aren't listed.
+#[derive(Debug)]
+pub struct GroupOutcome {
+    pub results : Vec<(String, Result<(), TcpError>)>,
+}
+
+impl GroupOutcome {
+    /// Whether every laser carried out its commands.
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+
+    pub fn succeeded(&self) -> Vec<&str> {
+        self.results.iter().filter(|(_, result)| result.is_ok()).map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// The lasers that didn't, and the first error each one hit.
+    pub fn failures(&self) -> Vec<(&str, &TcpError)> {
+        self.results.iter()
+            .filter_map(|(name, result)| result.as_ref().err().map(|e| (name.as_str(), e)))
+            .collect()
+    }
+}
+
+/// Named connections to several servers, hosting any models of laser.
+///
+/// # Example
+///
+/// ```no_run
+/// use coherent_rs::network::group::LaserGroup;
+///
+/// let mut room = LaserGroup::new();
+/// room.connect("discovery", "192.168.1.20:907", Some(1000)).unwrap();
+/// room.connect("chameleon", "192.168.1.21:907", Some(1000)).unwrap();
+///
+/// let outcome = room.close_all_shutters();
+/// for (laser, error) in outcome.failures() {
+///     eprintln!("{}'s shutter may still be open: {:?}", laser, error);
+/// }
+/// ```
+#[derive(Default)]
+pub struct LaserGroup {
+    members : Vec<(String, DynNetworkLaserClient)>,
+}
+
+impl LaserGroup {
+    pub fn new() -> Self {
+        LaserGroup::default()
+    }
+
+    /// Adds `client` as `name`, replacing any laser already called that.
+    pub fn add(&mut self, name : &str, client : DynNetworkLaserClient) {
+        self.members.retain(|(member, _)| member != name);
+        self.members.push((name.to_string(), client));
+    }
+
+    /// Connects to the server at `address` and adds it as `name`.
+    pub fn connect(&mut self, name : &str, address : &str, timeout_duration : Option<u32>) -> Result<(), TcpError> {
+        self.add(name, DynNetworkLaserClient::connect(address, timeout_duration)?);
+        Ok(())
+    }
+
+    /// Takes the laser called `name` out of the group.
+    pub fn remove(&mut self, name : &str) -> Option<DynNetworkLaserClient> {
+        let index = self.members.iter().position(|(member, _)| member == name)?;
+        Some(self.members.remove(index).1)
+    }
+
+    pub fn client(&mut self, name : &str) -> Option<&mut DynNetworkLaserClient> {
+        self.members.iter_mut().find(|(member, _)| member == name).map(|(_, client)| client)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.members.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Sends each laser the commands addressed to it by name, all lasers at
+    /// once, and waits for every answer. Each laser's commands go in order,
+    /// stopping at its first failure. A name that isn't in the group fails
+    /// with `InvalidArgumentsError`.
+    pub fn command(&mut self, commands : &[(&str, DynCommand)]) -> GroupOutcome {
+        let mut unknown : Vec<(String, Result<(), TcpError>)> = Vec::new();
+        for (name, _) in commands {
+            if !self.members.iter().any(|(member, _)| member == name) && !unknown.iter().any(|(seen, _)| seen == name) {
+                unknown.push((name.to_string(), Err(TcpError::CoherentError(
+                    CoherentError::InvalidArgumentsError(format!("No laser named `{}` in the group", name))
+                ))));
+            }
+        }
+
+        let sent = std::thread::scope(|scope| {
+            let workers = self.members.iter_mut().filter_map(|(name, client)| {
+                let own = commands.iter().filter(|(to, _)| to == name).map(|(_, command)| command).collect::<Vec<_>>();
+                if own.is_empty() { return None; }
+                let worker = scope.spawn(move || own.into_iter().try_for_each(|command| client.command(command)));
+                Some((name.clone(), worker))
+            }).collect::<Vec<_>>();
+            workers.into_iter()
+                .map(|(name, worker)| (name, worker.join().unwrap_or(Err(TcpError::MutexPoisoned))))
+                .collect::<Vec<_>>()
+        });
+
+        GroupOutcome{results : sent.into_iter().chain(unknown).collect()}
+    }
+
+    /// Sends every laser `command`, translated for its model.
+    pub fn command_all(&mut self, command : CommonCommand) -> GroupOutcome {
+        let mut translated = Vec::new();
+        let mut untranslatable = Vec::new();
+        for (name, client) in &self.members {
+            match DynCommand::from_common(&client.get_laser_type(), command) {
+                Ok(dyn_command) => translated.push((name.clone(), dyn_command)),
+                Err(e) => untranslatable.push((name.clone(), Err(e))),
+            }
+        }
+        let addressed = translated.iter().map(|(name, command)| (name.as_str(), command.clone())).collect::<Vec<_>>();
+        let mut outcome = self.command(&addressed);
+        outcome.results.extend(untranslatable);
+        outcome
+    }
+
+    /// Closes the main shutter of every laser.
+    pub fn close_all_shutters(&mut self) -> GroupOutcome {
+        self.command_all(CommonCommand::CloseShutter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::{StatusValue, debug::DebugLaser, discoverynx::DiscoveryNXCommands};
+    use crate::network::harness::TestServer;
+
+    #[test]
+    fn test_group() {
+        let (left, right) = (TestServer::debug().unwrap(), TestServer::debug().unwrap());
+        let mut group = LaserGroup::new();
+        group.connect("left", &left.address(), Some(1000)).unwrap();
+        group.connect("right", &right.address(), Some(1000)).unwrap();
+        assert_eq!(group.names(), vec!["left", "right"]);
+
+        let outcome = group.command_all(CommonCommand::OpenShutter);
+        assert!(outcome.is_success(), "{:?}", outcome);
+        assert_eq!(outcome.succeeded(), vec!["left", "right"]);
+
+        // One laser refuses, the other still goes ahead
+        let wavelength = |nm : f32| DynCommand::new::<DebugLaser>(&DiscoveryNXCommands::Wavelength{wavelength_nm : nm.into()}).unwrap();
+        let outcome = group.command(&[("left", wavelength(850.0)), ("right", wavelength(50.0)), ("middle", wavelength(800.0))]);
+        assert_eq!(outcome.succeeded(), vec!["left"]);
+        let failures = outcome.failures();
+        assert_eq!(failures.iter().map(|(name, _)| *name).collect::<Vec<_>>(), vec!["right", "middle"]);
+        assert!(matches!(failures[0].1, TcpError::Remote(_)));
+
+        assert!(group.close_all_shutters().is_success());
+        for name in ["left", "right"] {
+            let status = group.client(name).unwrap().query_status().unwrap();
+            assert_eq!(status["variable_shutter"], StatusValue::Text("Closed".to_string()));
+        }
+        assert_eq!(
+            group.client("left").unwrap().query_status().unwrap()["wavelength"],
+            StatusValue::Float(850.0)
+        );
+        assert!(group.remove("left").is_some());
+        assert_eq!(group.len(), 1);
+    }
+}
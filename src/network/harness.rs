@@ -0,0 +1,148 @@
+//! harness.rs
+//!
+//! Spins up a `NetworkLaserServer` on an ephemeral port so that tests
+//! (and soak tools) can run side by side without fighting over a
+//! hard-coded port number. Only built for this crate's own tests, or with
+//! the `test-harness` feature.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::laser::{Laser, debug::DebugLaser};
+use crate::network::{
+    NetworkLaserServer, NetworkLaserClient, BasicNetworkLaserClient,
+    DynNetworkLaserClient, TcpError,
+};
+
+/// Default polling interval for harness servers. Fast enough that tests
+/// waiting on a status broadcast don't crawl.
+const HARNESS_POLLING_INTERVAL : Duration = Duration::from_millis(100);
+
+/// A polling `NetworkLaserServer` bound to port 0 on the loopback interface,
+/// so the OS picks a free port. Connect clients with `client`, `dyn_client`,
+/// or hand a whole scenario to `run_clients`.
+///
+/// # Example
+///
+/// ```rust
+/// use coherent_rs::network::{harness::TestServer, NetworkLaserClient};
+///
+/// let harness = TestServer::debug().unwrap();
+/// let mut client = harness.client().unwrap();
+/// println!("{:?}", client.query_status().unwrap());
+/// ```
+pub struct TestServer<L : Laser + 'static> {
+    server : NetworkLaserServer<L>,
+    address : SocketAddr,
+}
+
+impl TestServer<DebugLaser> {
+    /// Hosts a default `DebugLaser`.
+    pub fn debug() -> Result<Self, TcpError> {
+        TestServer::new(DebugLaser::default(), None)
+    }
+}
+
+impl<L : Laser + 'static> TestServer<L> {
+    /// Hosts `laser` on an ephemeral loopback port and starts polling.
+    /// `polling_interval` defaults to 100 ms.
+    pub fn new(laser : L, polling_interval : Option<Duration>) -> Result<Self, TcpError> {
+        let mut server = NetworkLaserServer::new(
+            laser, "127.0.0.1:0",
+            Some(polling_interval.unwrap_or(HARNESS_POLLING_INTERVAL))
+        )?;
+        let address = server.local_addr()?;
+        server.poll()?;
+        Ok(TestServer{server, address})
+    }
+
+    /// The address the server is listening on, e.g. `127.0.0.1:53127`.
+    pub fn address(&self) -> String {
+        self.address.to_string()
+    }
+
+    /// The port the OS assigned.
+    pub fn port(&self) -> u16 {
+        self.address.port()
+    }
+
+    /// The underlying server, e.g. to issue commands locally.
+    pub fn server(&mut self) -> &mut NetworkLaserServer<L> {
+        &mut self.server
+    }
+
+    /// Gives up the harness and returns the server it was running.
+    pub fn into_server(self) -> NetworkLaserServer<L> {
+        self.server
+    }
+
+    /// Connects a typed client with a 1 second read timeout, so a broken
+    /// server fails the test rather than hanging it.
+    pub fn client(&self) -> Result<BasicNetworkLaserClient<L>, TcpError> {
+        BasicNetworkLaserClient::<L>::connect(&self.address(), Some(1000))
+    }
+
+    /// Connects a `DynNetworkLaserClient` with a 1 second read timeout.
+    pub fn dyn_client(&self) -> Result<DynNetworkLaserClient, TcpError> {
+        DynNetworkLaserClient::connect(&self.address(), Some(1000))
+    }
+
+    /// Connects `n_clients` clients up front, then runs `scenario` for each on
+    /// its own thread (with the client's index) and collects the results in order.
+    /// A panicking scenario is returned as an `Err` rather than tearing down the
+    /// other threads.
+    pub fn run_clients<R, F>(&self, n_clients : usize, scenario : F)
+        -> Result<Vec<std::thread::Result<R>>, TcpError>
+    where
+        R : Send,
+        F : Fn(usize, BasicNetworkLaserClient<L>) -> R + Sync,
+    {
+        let clients = (0..n_clients)
+            .map(|_| self.client())
+            .collect::<Result<Vec<_>, TcpError>>()?;
+
+        let scenario = &scenario;
+        Ok(std::thread::scope(|scope| {
+            let handles = clients.into_iter().enumerate()
+                .map(|(idx, client)| scope.spawn(move || scenario(idx, client)))
+                .collect::<Vec<_>>();
+            handles.into_iter().map(|handle| handle.join()).collect()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::{DiscoveryNXCommands, DiscoveryLaser, ShutterState};
+
+    #[test]
+    fn test_harnesses_get_distinct_ports() {
+        let first = TestServer::debug().unwrap();
+        let second = TestServer::debug().unwrap();
+        assert_ne!(first.port(), 0);
+        assert_ne!(first.port(), second.port());
+    }
+
+    #[test]
+    fn test_run_clients() {
+        let mut harness = TestServer::debug().unwrap();
+
+        let results = harness.run_clients(4, |idx, mut client| {
+            let state = ShutterState::from(idx % 2 == 0);
+            for _ in 0..10 {
+                client.command(
+                    DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::FixedWavelength, state}
+                ).unwrap();
+            }
+            client.query_status().unwrap();
+            idx
+        }).unwrap();
+
+        assert_eq!(
+            results.into_iter().map(|r| r.unwrap()).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        assert!(harness.server().polling());
+    }
+}
@@ -0,0 +1,199 @@
+//! journal.rs
+//!
+//! Keeping track, on the client, of the state it's asked the laser to be
+//! in, so it can be asked again after an outage. With a `CommandJournal`
+//! set, every command that changes a setting (one with an `=` on the wire,
+//! like `WV=800` -- not `FC`) is written down, the newest per setting. A
+//! command the client couldn't get through to the server is kept as
+//! pending; after `BasicNetworkLaserClient::reconnect` everything is, since
+//! the server or the laser behind it may have lost it. Then
+//! `NetworkLaserClient::replay_pending` sends the pending commands again,
+//! oldest first, so an overnight protocol picks up where it was.
+
+use serde::{Serialize, de::DeserializeOwned};
+use rmp_serde::Serializer;
+
+use crate::laser::{LaserCommand, retry::{RetryableError, ErrorClass}};
+use super::TcpError;
+
+/// The latest command sent for one setting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    /// The setting, as named on the wire, e.g. `WV`
+    pub parameter : String,
+    /// The command as the laser sees it, e.g. `WV=800`
+    pub command : String,
+    /// Whether the server last reported the command carried out. `false`
+    /// until it's been sent again after an outage.
+    pub applied : bool,
+    payload : Vec<u8>,
+}
+
+/// The settings a client has asked for, in the order it asked.
+///
+/// # Example
+///
+/// ```no_run
+/// use coherent_rs::Discovery;
+/// use coherent_rs::network::{NetworkLaserClient, BasicNetworkLaserClient};
+/// use coherent_rs::laser::{DiscoveryNXCommands, Nanometers};
+///
+/// let mut client = BasicNetworkLaserClient::<Discovery>::builder("192.168.1.20:907")
+///     .with_journal()
+///     .connect()
+///     .unwrap();
+/// client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(920.0)}).unwrap();
+/// // ... the server goes away overnight, and comes back ...
+/// client.reconnect().unwrap();
+/// let replayed = client.replay_pending().unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandJournal {
+    entries : Vec<JournalEntry>,
+}
+
+impl CommandJournal {
+    pub fn new() -> Self {
+        CommandJournal::default()
+    }
+
+    /// The setting `command` changes, or `None` if it doesn't change one.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use coherent_rs::laser::{DiscoveryNXCommands, GddFs2};
+    /// use coherent_rs::network::journal::CommandJournal;
+    ///
+    /// assert_eq!(CommandJournal::parameter(&DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-5000.0)}), Some("GDD".to_string()));
+    /// assert_eq!(CommandJournal::parameter(&DiscoveryNXCommands::FaultClear), None);
+    /// ```
+    pub fn parameter<C : LaserCommand>(command : &C) -> Option<String> {
+        command.to_string().split_once('=').map(|(parameter, _)| parameter.to_string())
+    }
+
+    /// Writes down `command`, replacing any earlier one for the same
+    /// setting. Does nothing for commands that don't change a setting.
+    pub fn record<C : LaserCommand + Serialize>(&mut self, command : &C, applied : bool) -> Result<(), TcpError> {
+        let Some(parameter) = CommandJournal::parameter(command) else { return Ok(()) };
+        let mut payload = Vec::new();
+        command.serialize(&mut Serializer::new(&mut payload))
+            .map_err(TcpError::SerializationEncodeError)?;
+        self.entries.retain(|entry| entry.parameter != parameter);
+        self.entries.push(JournalEntry{parameter, command : command.to_string(), applied, payload});
+        Ok(())
+    }
+
+    /// Writes down `command` as the server answered it. A command the
+    /// laser refused isn't the state anyone wants, so it's left out; one
+    /// that never got an answer is kept as pending.
+    pub(crate) fn record_result<C : LaserCommand + Serialize>(&mut self, command : &C, result : &Result<(), TcpError>)
+        -> Result<(), TcpError> {
+        match result {
+            Ok(()) => self.record(command, true),
+            Err(e) if is_outage(e) => self.record(command, false),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Every setting written down, oldest first.
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// The entries still to be sent again, oldest first.
+    pub fn pending(&self) -> impl Iterator<Item = &JournalEntry> {
+        self.entries.iter().filter(|entry| !entry.applied)
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.pending().next().is_some()
+    }
+
+    /// Marks every entry as pending, e.g. when something else (a
+    /// `keepalive`, a fault) says the laser was restarted.
+    pub fn mark_all_pending(&mut self) {
+        self.entries.iter_mut().for_each(|entry| entry.applied = false);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The pending commands, decoded, oldest first.
+    pub(crate) fn pending_commands<C : DeserializeOwned>(&self) -> Result<Vec<C>, TcpError> {
+        self.pending()
+            .map(|entry| rmp_serde::from_slice(&entry.payload).map_err(TcpError::SerializationDecodeError))
+            .collect()
+    }
+}
+
+/// Whether `error` means the command may never have reached the laser.
+fn is_outage(error : &TcpError) -> bool {
+    matches!(error, TcpError::Disconnected)
+        || matches!(error.error_class(), Some(ErrorClass::Io) | Some(ErrorClass::Timeout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CoherentError;
+    use crate::laser::{DiscoveryNXCommands, DiscoveryLaser, ShutterState};
+    use crate::laser::debug::DebugLaser;
+    use crate::network::{NetworkLaserClient, BasicNetworkLaserClient, harness::TestServer};
+    use crate::laser::units::{Nanometers, GddFs2};
+
+    #[test]
+    fn test_record() {
+        let mut journal = CommandJournal::new();
+        journal.record(&DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(920.0)}, true).unwrap();
+        journal.record(&DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-5000.0)}, true).unwrap();
+        journal.record(&DiscoveryNXCommands::FaultClear, true).unwrap();
+        journal.record(&DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)}, true).unwrap();
+        let commands = journal.entries().iter().map(|entry| entry.command.as_str()).collect::<Vec<_>>();
+        assert_eq!(commands, vec!["GDD=-5000", "WV=800"]);
+        assert!(!journal.has_pending());
+
+        let refused = DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::VariableWavelength, state : ShutterState::Open};
+        journal.record_result(&refused, &Err(TcpError::Remote(CoherentError::CommandNotExecutedError))).unwrap();
+        assert_eq!(journal.len(), 2);
+        journal.record_result(&refused, &Err(TcpError::Disconnected)).unwrap();
+        assert_eq!(journal.pending().map(|entry| entry.parameter.as_str()).collect::<Vec<_>>(), vec!["S"]);
+
+        journal.mark_all_pending();
+        let pending = journal.pending_commands::<DiscoveryNXCommands>().unwrap();
+        assert_eq!(pending[1], DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)});
+        assert_eq!(pending[2], refused);
+    }
+
+    #[test]
+    fn test_replay_pending() {
+        let mut harness = TestServer::debug().unwrap();
+        let mut client = BasicNetworkLaserClient::<DebugLaser>::builder(&harness.address())
+            .with_read_timeout(std::time::Duration::from_secs(1))
+            .with_journal()
+            .connect()
+            .unwrap();
+        client.command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(800.0)}).unwrap();
+        client.command(DiscoveryNXCommands::Gdd{gdd_val : GddFs2(-5000.0)}).unwrap();
+        assert_eq!(client.replay_pending().unwrap(), 0);
+
+        // The laser loses its settings while the client is away
+        harness.server().command(DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(920.0)}).unwrap();
+        harness.server().command(DiscoveryNXCommands::Gdd{gdd_val : GddFs2(0.0)}).unwrap();
+        client.reconnect().unwrap();
+        assert_eq!(client.journal.as_ref().unwrap().pending().count(), 2);
+        assert_eq!(client.replay_pending().unwrap(), 2);
+        let status = client.query_status().unwrap();
+        assert_eq!((status.wavelength, status.gdd), (800.0, Some(-5000.0)));
+        assert!(!client.journal.as_ref().unwrap().has_pending());
+    }
+}
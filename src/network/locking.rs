@@ -0,0 +1,145 @@
+//! locking.rs
+//!
+//! The order a `NetworkLaserServer` takes its locks in. Any two threads that
+//! take the same two locks take them in the same order, so they can't
+//! deadlock waiting on each other:
+//!
+//! 1. `Clients` -- the list of connected clients
+//! 2. `PrimaryClient`
+//! 3. `Authorizer`
+//! 4. `ReservationCheck`
+//! 5. `Laser`
+//! 6. `ConfirmationPolicy`
+//! 7. `CoalescePolicy`
+//! 8. `PollingInterval`
+//! 9. `WireFormat` -- what new clients are spoken to in
+//! 10. `Progress` -- the clients waiting to hear how an operation is going
+//! 11. `Stats`
+//!
+//! A thread holding a lock may only take locks further down the list. Locks
+//! taken through `acquire` are tracked per thread, and taking one out of
+//! order panics -- in release builds as well as debug ones, so a new code
+//! path that breaks the hierarchy fails the tests, and a lab's server
+//! stops with the reason rather than stalling without one.
+
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+
+/// One of the server's locks. Ordered by the hierarchy: a lock may be taken
+/// while holding only locks that compare less than it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LockLevel {
+    Clients,
+    PrimaryClient,
+    Authorizer,
+    ReservationCheck,
+    Laser,
+    ConfirmationPolicy,
+    CoalescePolicy,
+    PollingInterval,
+    WireFormat,
+    Progress,
+    Stats,
+}
+
+/// The hierarchy, first to last.
+pub const LOCK_ORDER : [LockLevel; 11] = [
+    LockLevel::Clients,
+    LockLevel::PrimaryClient,
+    LockLevel::Authorizer,
+    LockLevel::ReservationCheck,
+    LockLevel::Laser,
+    LockLevel::ConfirmationPolicy,
+    LockLevel::CoalescePolicy,
+    LockLevel::PollingInterval,
+    LockLevel::WireFormat,
+    LockLevel::Progress,
+    LockLevel::Stats,
+];
+
+thread_local! {
+    static HELD : RefCell<Vec<LockLevel>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The server locks the current thread holds, in the order it took them.
+pub fn held_locks() -> Vec<LockLevel> {
+    HELD.with(|held| held.borrow().clone())
+}
+
+/// Whether the current thread may take `level` now, i.e. every lock it
+/// holds comes before `level` in the hierarchy.
+pub fn may_acquire(level : LockLevel) -> bool {
+    HELD.with(|held| held.borrow().iter().all(|&h| h < level))
+}
+
+/// A lock guard that's tracked in the hierarchy until it's dropped.
+pub(crate) struct Ordered<G> {
+    guard : G,
+    level : LockLevel,
+}
+
+/// Takes the lock at `level` with `lock` (e.g. `|| mutex.lock()`), checking
+/// the hierarchy first.
+pub(crate) fn acquire<G, E>(level : LockLevel, lock : impl FnOnce() -> Result<G, E>) -> Result<Ordered<G>, E> {
+    assert!(
+        may_acquire(level),
+        "lock order violated: taking {:?} while holding {:?}", level, held_locks()
+    );
+    let guard = lock()?;
+    HELD.with(|held| held.borrow_mut().push(level));
+    Ok(Ordered{guard, level})
+}
+
+impl<G> Deref for Ordered<G> {
+    type Target = G;
+    fn deref(&self) -> &G {
+        &self.guard
+    }
+}
+
+impl<G> DerefMut for Ordered<G> {
+    fn deref_mut(&mut self) -> &mut G {
+        &mut self.guard
+    }
+}
+
+impl<G> Drop for Ordered<G> {
+    fn drop(&mut self) {
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(idx) = held.iter().rposition(|&h| h == self.level) {
+                held.remove(idx);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_tracking() {
+        let (clients, laser) = (Mutex::new(0), Mutex::new(1));
+        let c = acquire(LockLevel::Clients, || clients.lock()).unwrap();
+        let l = acquire(LockLevel::Laser, || laser.lock()).unwrap();
+        assert_eq!(**c + **l, 1);
+        assert_eq!(held_locks(), vec![LockLevel::Clients, LockLevel::Laser]);
+        assert!(!may_acquire(LockLevel::PrimaryClient));
+        assert!(may_acquire(LockLevel::PollingInterval));
+        drop(c);
+        assert_eq!(held_locks(), vec![LockLevel::Laser]);
+        drop(l);
+        assert!(held_locks().is_empty());
+        assert!(may_acquire(LockLevel::Clients));
+    }
+
+    #[test]
+    #[should_panic(expected = "lock order violated")]
+    fn test_out_of_order() {
+        let (clients, laser) = (Mutex::new(()), Mutex::new(()));
+        let _l = acquire(LockLevel::Laser, || laser.lock()).unwrap();
+        let _c = acquire(LockLevel::Clients, || clients.lock()).unwrap();
+    }
+}
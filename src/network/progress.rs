@@ -0,0 +1,115 @@
+//! progress.rs
+//!
+//! How a long-running operation a client started on the server is getting
+//! on, so a GUI can show a progress bar rather than freezing until it's
+//! done. The server pushes a frame to the client that started the operation
+//! (and no one else) each time it polls the laser, until it's done:
+//!
+//! ```text
+//! Progress: <Progress>\n
+//! ```
+//!
+//! A client that changes the wavelength hears how far the laser has come,
+//! from `Laser::tuning_progress`, and a client that starts a `sweep` hears
+//! after each of its steps.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+
+use crate::laser::discoverynx::progress::TuningProgress;
+
+/// A long-running operation on the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operation {
+    /// A wavelength change
+    Tuning,
+    /// A `sweep::StartSweep`
+    WavelengthSweep,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProgressState {
+    Running,
+    Done,
+    /// Stopped early on request
+    Aborted,
+    /// Stopped by an error, sent just before
+    Failed,
+}
+
+/// One `PROGRESS_MARKER` frame.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    pub operation : Operation,
+    pub state : ProgressState,
+    /// From 0 to 100, for operations that can tell
+    pub percent : Option<f32>,
+    /// The time left, once there's an estimate
+    pub eta : Option<Duration>,
+}
+
+impl Progress {
+    pub fn is_done(&self) -> bool {
+        self.state == ProgressState::Done
+    }
+
+    /// Whether the operation has stopped, whether or not it got to the end.
+    pub fn is_finished(&self) -> bool {
+        self.state != ProgressState::Running
+    }
+}
+
+impl From<&TuningProgress> for Progress {
+    fn from(tuning : &TuningProgress) -> Self {
+        Progress{
+            operation : Operation::Tuning,
+            state : if tuning.is_done() { ProgressState::Done } else { ProgressState::Running },
+            percent : Some(tuning.percent()),
+            eta : tuning.eta,
+        }
+    }
+}
+
+/// A client that's just had a command carried out, waiting to hear about
+/// any operation the command started.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ProgressWatch {
+    pub client : SocketAddr,
+    /// When the server got the command
+    pub since : Instant,
+}
+
+impl ProgressWatch {
+    /// What to tell the client, given the laser's latest `tuning`: `None`
+    /// if the tuning started before the client's command (so someone
+    /// else's, or nothing's), and the watch is over.
+    pub fn progress(&self, tuning : Option<&TuningProgress>) -> Option<Progress> {
+        tuning.filter(|tuning| tuning.elapsed <= self.since.elapsed()).map(Progress::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch() {
+        let watch = ProgressWatch{client : "127.0.0.1:9000".parse().unwrap(), since : Instant::now()};
+        let halfway = TuningProgress::estimate(800.0, 1000.0, 900.0, Duration::ZERO, false);
+        assert_eq!(watch.progress(Some(&halfway)), Some(Progress{
+            operation : Operation::Tuning,
+            state : ProgressState::Running,
+            percent : Some(50.0),
+            eta : Some(Duration::ZERO),
+        }));
+        let done = TuningProgress::estimate(800.0, 1000.0, 1000.0, Duration::ZERO, true);
+        assert!(watch.progress(Some(&done)).unwrap().is_done());
+
+        // A tuning from before the command isn't this client's
+        let earlier = TuningProgress::estimate(800.0, 1000.0, 900.0, Duration::from_secs(60), false);
+        assert_eq!(watch.progress(Some(&earlier)), None);
+        assert_eq!(watch.progress(None), None);
+    }
+}
@@ -0,0 +1,72 @@
+//! schedule.rs
+//!
+//! The polling thread's clock. Sleeping a whole interval after each status
+//! read makes the real period the interval plus however long the read took,
+//! which wanders with the serial link's latency. `FixedRate` instead puts
+//! each poll an interval after the one before was due, so the rate holds --
+//! and when a poll runs past the next one's slot, it says by how much, and
+//! starts the next straight away rather than trying to catch up.
+
+use std::time::{Duration, Instant};
+
+/// Slots every `interval`, counted from when it was created.
+#[derive(Debug, Clone)]
+pub(crate) struct FixedRate {
+    next : Instant,
+}
+
+impl FixedRate {
+    /// The first slot is now.
+    pub fn new() -> Self {
+        FixedRate{next : Instant::now()}
+    }
+
+    /// Moves on to the slot `interval` after the last one, given it's `now`.
+    /// `Ok` with how long to wait for it, or `Err` with how far past it `now`
+    /// already is -- an overrun -- in which case the slots missed are
+    /// dropped and the next is taken to be now.
+    pub fn advance(&mut self, interval : Duration, now : Instant) -> Result<Duration, Duration> {
+        self.next += interval;
+        if now < self.next {
+            Ok(self.next - now)
+        } else {
+            let overrun = now - self.next;
+            self.next = now;
+            Err(overrun)
+        }
+    }
+
+    /// Sleeps until the slot `interval` after the last one. `Some` overrun
+    /// if it had already passed, as in `advance`.
+    pub fn wait(&mut self, interval : Duration) -> Option<Duration> {
+        match self.advance(interval, Instant::now()) {
+            Ok(wait) => {
+                std::thread::sleep(wait);
+                None
+            },
+            Err(overrun) => Some(overrun),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_rate() {
+        let mut rate = FixedRate::new();
+        let start = rate.next;
+        let ms = Duration::from_millis;
+
+        // The time each poll took comes out of the wait, so the slots don't drift
+        assert_eq!(rate.advance(ms(100), start + ms(30)), Ok(ms(70)));
+        assert_eq!(rate.advance(ms(100), start + ms(110)), Ok(ms(90)));
+        assert_eq!(rate.advance(ms(100), start + ms(240)), Ok(ms(60)));
+        // A poll that ran past the next slot starts the next straight away
+        assert_eq!(rate.advance(ms(100), start + ms(425)), Err(ms(25)));
+        assert_eq!(rate.advance(ms(100), start + ms(450)), Ok(ms(75)));
+        // The interval can change between polls
+        assert_eq!(rate.advance(ms(50), start + ms(530)), Ok(ms(45)));
+    }
+}
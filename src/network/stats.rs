@@ -0,0 +1,192 @@
+//! stats.rs
+//!
+//! Counters and timings kept by a `NetworkLaserServer`'s threads, for
+//! working out where a slow remote command spent its time: waiting its turn
+//! among the clients, waiting for the laser, or talking to it. Read them
+//! locally with `NetworkLaserServer::stats`, or from any client with
+//! `NetworkLaserClient::server_stats`.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+
+/// How much each new sample moves a moving average.
+pub const MOVING_AVERAGE_WEIGHT : f64 = 0.1;
+
+/// Commands per second are counted over this trailing window.
+pub const RATE_WINDOW : Duration = Duration::from_secs(10);
+
+/// A snapshot of a server's statistics. Times are in milliseconds, and
+/// the `_ms` averages are exponential moving averages (see
+/// `MOVING_AVERAGE_WEIGHT`), so they follow recent behavior.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerStats {
+    /// Seconds since the server was created
+    pub uptime : f64,
+    /// Commands run for clients (including confirmed ones), successful or not
+    pub commands : u64,
+    /// Of `commands`, those that failed
+    pub commands_failed : u64,
+    /// Commands per second over the last `RATE_WINDOW`
+    pub commands_per_sec : f64,
+    /// From reading a command off the socket to writing the response
+    pub command_latency_ms : f64,
+    /// Of `command_latency_ms`, the time spent waiting for the laser's lock
+    pub lock_wait_ms : f64,
+    /// Status broadcasts sent
+    pub broadcasts : u64,
+    /// From starting a status read to the last client being sent it
+    pub broadcast_latency_ms : f64,
+    /// One entry per connected client
+    pub clients : Vec<ClientStats>,
+    /// Status polls that took longer than the polling interval, so the next
+    /// started late
+    #[serde(default)]
+    pub poll_overruns : u64,
+    /// How far past the next poll's slot an overrunning poll ran
+    #[serde(default)]
+    pub poll_overrun_ms : f64,
+    /// Commands not run because a later one superseded them (see
+    /// `coalesce`). Not counted in `commands`.
+    #[serde(default)]
+    pub commands_superseded : u64,
+}
+
+/// Statistics for one connected client.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientStats {
+    pub address : SocketAddr,
+    /// How long writing a status broadcast to this client takes. A client
+    /// that isn't keeping up with its socket shows up here first.
+    pub send_lag_ms : f64,
+    pub bytes_sent : u64,
+    pub commands : u64,
+}
+
+impl ClientStats {
+    fn new(address : SocketAddr) -> Self {
+        ClientStats{address, send_lag_ms : 0.0, bytes_sent : 0, commands : 0}
+    }
+}
+
+/// Folds `sample` into the moving average `average`. The first sample
+/// (while `count` is 0) is taken as is.
+fn fold(average : &mut f64, sample : Duration, count : u64) {
+    let sample = sample.as_secs_f64() * 1e3;
+    *average = if count == 0 { sample } else { *average + MOVING_AVERAGE_WEIGHT * (sample - *average) };
+}
+
+/// The live statistics the server's threads update.
+#[derive(Debug)]
+pub(crate) struct StatsRecorder {
+    stats : ServerStats,
+    started : Instant,
+    recent_commands : VecDeque<Instant>,
+}
+
+impl Default for StatsRecorder {
+    fn default() -> Self {
+        StatsRecorder{stats : ServerStats::default(), started : Instant::now(), recent_commands : VecDeque::new()}
+    }
+}
+
+impl StatsRecorder {
+    fn client(&mut self, address : SocketAddr) -> &mut ClientStats {
+        match self.stats.clients.iter().position(|client| client.address == address) {
+            Some(idx) => &mut self.stats.clients[idx],
+            None => {
+                self.stats.clients.push(ClientStats::new(address));
+                self.stats.clients.last_mut().unwrap()
+            },
+        }
+    }
+
+    /// A command from `address` got its response: `latency` after it was
+    /// read, `lock_wait` of that spent waiting for the laser.
+    pub fn command(&mut self, address : SocketAddr, succeeded : bool, latency : Duration, lock_wait : Duration) {
+        fold(&mut self.stats.command_latency_ms, latency, self.stats.commands);
+        fold(&mut self.stats.lock_wait_ms, lock_wait, self.stats.commands);
+        self.stats.commands += 1;
+        if !succeeded { self.stats.commands_failed += 1; }
+        self.recent_commands.push_back(Instant::now());
+        self.client(address).commands += 1;
+    }
+
+    /// A status broadcast went out, `latency` after the status read began.
+    pub fn broadcast(&mut self, latency : Duration) {
+        fold(&mut self.stats.broadcast_latency_ms, latency, self.stats.broadcasts);
+        self.stats.broadcasts += 1;
+    }
+
+    /// A command was answered `Superseded` instead of being run.
+    pub fn superseded(&mut self) {
+        self.stats.commands_superseded += 1;
+    }
+
+    /// A status poll ran `overrun` past the next poll's slot.
+    pub fn poll_overrun(&mut self, overrun : Duration) {
+        fold(&mut self.stats.poll_overrun_ms, overrun, self.stats.poll_overruns);
+        self.stats.poll_overruns += 1;
+    }
+
+    /// Writing `bytes` to the client at `address` took `lag`.
+    pub fn sent(&mut self, address : SocketAddr, bytes : usize, lag : Duration) {
+        let client = self.client(address);
+        fold(&mut client.send_lag_ms, lag, client.bytes_sent);
+        client.bytes_sent += bytes as u64;
+    }
+
+    /// Drops the clients that aren't in `connected`.
+    pub fn retain_clients(&mut self, connected : &[SocketAddr]) {
+        self.stats.clients.retain(|client| connected.contains(&client.address));
+    }
+
+    pub fn snapshot(&mut self) -> ServerStats {
+        let now = Instant::now();
+        while self.recent_commands.front().is_some_and(|&t| now.duration_since(t) > RATE_WINDOW) {
+            self.recent_commands.pop_front();
+        }
+        let uptime = now.duration_since(self.started);
+        let window = uptime.min(RATE_WINDOW).as_secs_f64();
+        ServerStats{
+            uptime : uptime.as_secs_f64(),
+            commands_per_sec : if window > 0.0 { self.recent_commands.len() as f64 / window } else { 0.0 },
+            ..self.stats.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorder() {
+        let mut recorder = StatsRecorder::default();
+        let (a, b) : (SocketAddr, SocketAddr) = ("127.0.0.1:1000".parse().unwrap(), "127.0.0.1:1001".parse().unwrap());
+
+        recorder.command(a, true, Duration::from_millis(10), Duration::from_millis(2));
+        recorder.command(a, false, Duration::from_millis(20), Duration::from_millis(2));
+        recorder.sent(a, 100, Duration::from_millis(1));
+        recorder.sent(b, 100, Duration::from_millis(3));
+        recorder.broadcast(Duration::from_millis(5));
+        recorder.poll_overrun(Duration::from_millis(40));
+        recorder.superseded();
+
+        let stats = recorder.snapshot();
+        assert_eq!((stats.commands, stats.commands_failed, stats.broadcasts), (2, 1, 1));
+        assert!((stats.command_latency_ms - 11.0).abs() < 1e-9);
+        assert!((stats.lock_wait_ms - 2.0).abs() < 1e-9);
+        assert_eq!((stats.poll_overruns, stats.commands_superseded), (1, 1));
+        assert!((stats.poll_overrun_ms - 40.0).abs() < 1e-9);
+        assert!(stats.commands_per_sec > 0.0);
+        assert_eq!(stats.clients.len(), 2);
+        assert_eq!((stats.clients[0].commands, stats.clients[0].bytes_sent), (2, 100));
+        assert!((stats.clients[1].send_lag_ms - 3.0).abs() < 1e-9);
+
+        recorder.retain_clients(&[b]);
+        assert_eq!(recorder.snapshot().clients[0].address, b);
+    }
+}
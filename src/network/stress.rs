@@ -0,0 +1,302 @@
+//! stress.rs
+//!
+//! Soak testing for a `NetworkLaserServer`: many clients at once, each
+//! sending randomized commands and status queries for as long as asked,
+//! with pass/fail criteria on latency and errors. Meant to qualify a
+//! server (and the machine it runs on) against a `DebugLaser` before it
+//! guards a real laser -- see the `stress_client` binary.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{NetworkLaserClient, BasicNetworkLaserClient, TcpError};
+use crate::laser::{ShutterState, Nanometers, GddFs2, debug::DebugLaser, discoverynx::{DiscoveryNXCommands, DiscoveryLaser}};
+
+/// Errors kept verbatim in a `StressReport`; past this only the count grows.
+pub const MAX_REPORTED_ERRORS : usize = 20;
+
+/// What to run and what counts as a pass.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StressConfig {
+    /// Concurrent clients
+    pub clients : usize,
+    /// How long each client keeps going
+    pub duration : Duration,
+    /// Of each client's operations, the fraction that are status queries
+    /// (the rest are commands)
+    pub query_fraction : f64,
+    /// Of the commands, the fraction deliberately out of range, which the
+    /// server has to refuse
+    pub invalid_fraction : f64,
+    /// Fails if the 99th percentile latency is above this
+    pub max_p99_latency : Duration,
+    /// Fails if more operations than this went wrong
+    pub max_errors : u64,
+    /// Seeds each client's sequence of operations, so a failing run can be
+    /// repeated
+    pub seed : u64,
+    /// How often `run` reports progress
+    pub report_interval : Duration,
+    /// Each client's read timeout
+    pub timeout : Duration,
+}
+
+impl Default for StressConfig {
+    fn default() -> Self {
+        StressConfig{
+            clients : 8,
+            duration : Duration::from_secs(60),
+            query_fraction : 0.5,
+            invalid_fraction : 0.05,
+            max_p99_latency : Duration::from_secs(2),
+            max_errors : 0,
+            seed : 1,
+            report_interval : Duration::from_secs(10),
+            timeout : Duration::from_secs(5),
+        }
+    }
+}
+
+/// The results of a run, so far or in total.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StressReport {
+    pub elapsed : Duration,
+    /// Commands and queries that got an answer, right or wrong
+    pub operations : u64,
+    pub commands : u64,
+    pub queries : u64,
+    /// Out-of-range commands the server correctly refused
+    pub refused : u64,
+    /// Operations that failed, or out-of-range commands that were accepted
+    pub errors : u64,
+    /// The first `MAX_REPORTED_ERRORS` errors
+    pub first_errors : Vec<String>,
+    pub latency_p50 : Duration,
+    pub latency_p99 : Duration,
+    pub latency_max : Duration,
+}
+
+impl StressReport {
+    /// Why the run failed `config`'s criteria. Empty if it passed.
+    pub fn failures(&self, config : &StressConfig) -> Vec<String> {
+        let mut failures = Vec::new();
+        if self.errors > config.max_errors {
+            failures.push(format!("{} errors (at most {} allowed)", self.errors, config.max_errors));
+        }
+        if self.latency_p99 > config.max_p99_latency {
+            failures.push(format!(
+                "99th percentile latency {:?} (at most {:?} allowed)", self.latency_p99, config.max_p99_latency
+            ));
+        }
+        if self.operations == 0 {
+            failures.push("no operations completed".to_string());
+        }
+        failures
+    }
+
+    pub fn passed(&self, config : &StressConfig) -> bool {
+        self.failures(config).is_empty()
+    }
+
+    pub fn operations_per_sec(&self) -> f64 {
+        let elapsed = self.elapsed.as_secs_f64();
+        if elapsed > 0.0 { self.operations as f64 / elapsed } else { 0.0 }
+    }
+}
+
+/// What the clients have done so far.
+#[derive(Default)]
+struct Tally {
+    report : StressReport,
+    latencies : Vec<Duration>,
+}
+
+impl Tally {
+    fn error(&mut self, error : String) {
+        self.report.errors += 1;
+        if self.report.first_errors.len() < MAX_REPORTED_ERRORS {
+            self.report.first_errors.push(error);
+        }
+    }
+
+    fn report(&mut self, elapsed : Duration) -> StressReport {
+        self.latencies.sort_unstable();
+        let percentile = |p : f64| self.latencies.get(((self.latencies.len() as f64 - 1.0) * p).round() as usize)
+            .copied().unwrap_or_default();
+        StressReport{
+            elapsed,
+            latency_p50 : percentile(0.5),
+            latency_p99 : percentile(0.99),
+            latency_max : self.latencies.last().copied().unwrap_or_default(),
+            ..self.report.clone()
+        }
+    }
+}
+
+/// xorshift64*: plenty random enough to vary the commands, and repeatable.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed : u64) -> Self {
+        // xorshift is stuck at 0
+        Rng(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn range(&mut self, min : f32, max : f32) -> f32 {
+        min + (max - min) * self.unit() as f32
+    }
+}
+
+/// A command the `DebugLaser` accepts with its default `HeadRanges`.
+fn valid_command(rng : &mut Rng) -> DiscoveryNXCommands {
+    let laser = if rng.unit() < 0.5 { DiscoveryLaser::VariableWavelength } else { DiscoveryLaser::FixedWavelength };
+    let state = if rng.unit() < 0.5 { ShutterState::Open } else { ShutterState::Closed };
+    match rng.next() % 5 {
+        0 => DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(rng.range(700.0, 1000.0))},
+        1 => DiscoveryNXCommands::Gdd{gdd_val : GddFs2(rng.range(-10000.0, 10000.0))},
+        2 => DiscoveryNXCommands::Shutter{laser, state},
+        3 => DiscoveryNXCommands::FaultClear,
+        _ => DiscoveryNXCommands::Heartbeat,
+    }
+}
+
+/// One client's loop, until `deadline`.
+fn client_loop(address : &str, config : &StressConfig, index : usize, deadline : Instant, tally : &Mutex<Tally>)
+    -> Result<(), TcpError> {
+    let mut client = BasicNetworkLaserClient::<DebugLaser>::connect(address, Some(config.timeout.as_millis() as u32))?;
+    let mut rng = Rng::new(config.seed.wrapping_add(index as u64));
+    while Instant::now() < deadline {
+        let started = Instant::now();
+        let mut refused = false;
+        let (outcome, query) = if rng.unit() < config.query_fraction {
+            (client.query_status().map(|_| ()).map_err(|e| format!("query: {:?}", e)), true)
+        }
+        else if rng.unit() < config.invalid_fraction {
+            let command = DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(rng.range(10.0, 100.0))};
+            (match client.command(command) {
+                Err(TcpError::Remote(_)) => { refused = true; Ok(()) },
+                Ok(()) => Err("an out-of-range wavelength was accepted".to_string()),
+                Err(e) => Err(format!("out-of-range wavelength: {:?}", e)),
+            }, false)
+        }
+        else {
+            let command = valid_command(&mut rng);
+            let described = format!("{:?}", command);
+            (client.command(command).map_err(|e| format!("{}: {:?}", described, e)), false)
+        };
+        let latency = started.elapsed();
+
+        let mut tally = tally.lock().unwrap();
+        tally.latencies.push(latency);
+        tally.report.operations += 1;
+        if query { tally.report.queries += 1; } else { tally.report.commands += 1; }
+        if refused { tally.report.refused += 1; }
+        if let Err(error) = outcome {
+            tally.error(format!("client {}: {}", index, error));
+        }
+    }
+    Ok(())
+}
+
+/// Runs `config` against the server at `address`, which must be hosting a
+/// `DebugLaser`, calling `progress` every `report_interval`. Blocks until
+/// every client is done.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use coherent_rs::laser::debug::DebugLaser;
+/// use coherent_rs::network::{NetworkLaserServer, stress::{run, StressConfig}};
+///
+/// let mut server = NetworkLaserServer::new(DebugLaser::default(), "127.0.0.1:0", Some(Duration::from_millis(100))).unwrap();
+/// server.poll().unwrap();
+/// let address = server.local_addr().unwrap().to_string();
+/// let config = StressConfig{clients : 2, duration : Duration::from_millis(500), ..Default::default()};
+/// let report = run(&address, &config, |report| println!("{:?}", report));
+/// assert!(report.passed(&config), "{:?}", report.failures(&config));
+/// ```
+pub fn run(address : &str, config : &StressConfig, mut progress : impl FnMut(&StressReport)) -> StressReport {
+    let started = Instant::now();
+    let deadline = started + config.duration;
+    let tally = Arc::new(Mutex::new(Tally::default()));
+
+    let workers = (0..config.clients).map(|index| {
+        let (address, config) = (address.to_string(), config.clone());
+        let tally = Arc::clone(&tally);
+        std::thread::spawn(move || {
+            if let Err(e) = client_loop(&address, &config, index, deadline, &tally) {
+                tally.lock().unwrap().error(format!("client {} disconnected: {:?}", index, e));
+            }
+        })
+    }).collect::<Vec<_>>();
+
+    let mut next_report = started + config.report_interval;
+    while workers.iter().any(|worker| !worker.is_finished()) {
+        std::thread::sleep(Duration::from_millis(50));
+        if Instant::now() >= next_report {
+            progress(&tally.lock().unwrap().report(started.elapsed()));
+            next_report += config.report_interval;
+        }
+    }
+    for worker in workers {
+        if worker.join().is_err() {
+            tally.lock().unwrap().error("a client thread panicked".to_string());
+        }
+    }
+    let mut tally = tally.lock().unwrap();
+    tally.report(started.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::harness::TestServer;
+
+    #[test]
+    fn test_rng() {
+        let (mut a, mut b) = (Rng::new(7), Rng::new(7));
+        for _ in 0..100 {
+            let x = a.unit();
+            assert_eq!(x, b.unit());
+            assert!((0.0..1.0).contains(&x));
+        }
+        assert_ne!(Rng::new(0).next(), 0);
+    }
+
+    #[test]
+    fn test_run() {
+        let harness = TestServer::debug().unwrap();
+        let config = StressConfig{
+            clients : 3,
+            duration : Duration::from_secs(2),
+            invalid_fraction : 0.3,
+            report_interval : Duration::from_millis(500),
+            ..Default::default()
+        };
+        let mut reports = 0;
+        let report = run(&harness.address(), &config, |_| reports += 1);
+        assert!(report.passed(&config), "{:?}", report);
+        assert!(reports >= 2);
+        assert_eq!(report.operations, report.commands + report.queries);
+        assert!(report.latency_p50 <= report.latency_p99 && report.latency_p99 <= report.latency_max);
+
+        // Nobody's listening
+        let strict = StressConfig{clients : 1, duration : Duration::from_millis(100), ..Default::default()};
+        let report = run("127.0.0.1:1", &strict, |_| {});
+        assert_eq!(report.errors, 1);
+        assert!(!report.passed(&strict));
+    }
+}
@@ -0,0 +1,265 @@
+//! sweep.rs
+//!
+//! Wavelength sweeps run by the server itself, so a client on a flaky link
+//! only has to start one and listen, rather than send every step and wait
+//! out every tuning. The server tunes to each wavelength in turn, waits for
+//! tuning to finish and then the dwell, reads the main beam's power, and
+//! pushes it to the client that started the sweep (and no one else), then
+//! how far the sweep has got:
+//!
+//! ```text
+//! Start sweep: <StartSweep>\n    -> COMMAND SUCCESSFUL, or why not
+//! Sweep step: <SweepStep>\n      one per wavelength
+//! Progress: <Progress>\n         after each step, then Done, Aborted or Failed
+//! ABORT SWEEP\n                  -> COMMAND SUCCESSFUL; stops before the next step
+//! ```
+//!
+//! A `Failed` progress frame follows an `Error: <CoherentError>\n` saying
+//! why. An `ABORT` stops a sweep too, even part way through tuning. One
+//! sweep runs at a time. Only the primary client may start or abort one, if
+//! there is one, and only if the server's `Authorizer` and
+//! `ConfirmationPolicy` would let every step through without confirmation.
+
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+
+use crate::CoherentError;
+use crate::laser::{Laser, LaserStatus, CommonCommand, shared::SharedLaser, retry::RetryPolicy};
+use super::{
+    codec::WireFormat, frame, send_command_from, locking::{LockLevel, acquire},
+    progress::{Progress, Operation, ProgressState},
+    ERROR_MARKER, PROGRESS_MARKER, SWEEP_STEP_MARKER, SWEEP_THREAD, DEFAULT_COMMAND_TIMEOUT,
+};
+
+/// How often a sweep checks whether tuning has finished, and whether it's
+/// been aborted while it dwells.
+const CHECK_INTERVAL : Duration = Duration::from_millis(50);
+
+/// A sweep for the server to run.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StartSweep {
+    pub start_nm : f32,
+    /// The last wavelength, if it's a whole number of steps from `start_nm`
+    pub stop_nm : f32,
+    pub step_nm : f32,
+    /// How long to wait at each wavelength once it's tuned, before reading
+    /// the power
+    pub dwell : Duration,
+}
+
+/// One wavelength of a sweep, and the power there.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SweepStep {
+    pub wavelength_nm : f32,
+    /// The main beam's power, in mW
+    pub power_mw : f32,
+}
+
+/// How a sweep `NetworkLaserClient::run_sweep` waited on went.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepReport {
+    /// Every step, in the order measured
+    pub steps : Vec<SweepStep>,
+    /// `Done`, or `Aborted` if it was stopped early
+    pub state : ProgressState,
+}
+
+impl StartSweep {
+    /// The wavelengths from `start_nm` to `stop_nm` (in either direction)
+    /// every `step_nm`. Both ends must be within `tuning_range_nm`; a laser
+    /// without one can't sweep.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use coherent_rs::network::sweep::StartSweep;
+    ///
+    /// let sweep = StartSweep{start_nm : 900.0, stop_nm : 800.0, step_nm : 40.0, dwell : Duration::ZERO};
+    /// assert_eq!(sweep.wavelengths(Some((680.0, 1300.0))).unwrap(), vec![900.0, 860.0, 820.0]);
+    /// assert!(sweep.wavelengths(Some((850.0, 1300.0))).is_err());
+    /// ```
+    pub fn wavelengths(&self, tuning_range_nm : Option<(f32, f32)>) -> Result<Vec<f32>, CoherentError> {
+        let (shortest, longest) = tuning_range_nm
+            .ok_or(CoherentError::InvalidArgumentsError("This laser doesn't tune".to_string()))?;
+        let in_range = |nm : f32| nm.is_finite() && (shortest..=longest).contains(&nm);
+        if !(self.step_nm.is_finite() && self.step_nm > 0.0) {
+            return Err(CoherentError::InvalidArgumentsError(format!("Sweep step must be positive, not {}", self.step_nm)));
+        }
+        if !in_range(self.start_nm) || !in_range(self.stop_nm) {
+            return Err(CoherentError::InvalidArgumentsError(format!(
+                "Sweep from {} to {} nm is outside {:?} nm", self.start_nm, self.stop_nm, (shortest, longest)
+            )));
+        }
+        let span = self.stop_nm - self.start_nm;
+        let count = (span.abs() / self.step_nm + 1e-3).floor() as usize;
+        Ok((0..=count).map(|i| self.start_nm + span.signum() * self.step_nm * i as f32).collect())
+    }
+}
+
+/// Whether a sweep is running, and whether it's been asked to stop. Shared
+/// by the command thread and the `SWEEP_THREAD`.
+#[derive(Debug, Default)]
+pub(crate) struct SweepControl {
+    running : AtomicBool,
+    abort : AtomicBool,
+}
+
+impl SweepControl {
+    /// Asks the running sweep to stop after the step in progress;
+    /// `CommandNotExecutedError` if there isn't one.
+    pub fn abort(&self) -> Result<(), CoherentError> {
+        if !self.running.load(Ordering::SeqCst) {
+            return Err(CoherentError::CommandNotExecutedError);
+        }
+        self.abort.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn aborted(&self) -> bool {
+        self.abort.load(Ordering::SeqCst)
+    }
+}
+
+/// The client a sweep reports to.
+pub(crate) struct SweepClient {
+    pub stream : TcpStream,
+    pub address : SocketAddr,
+    pub format : WireFormat,
+}
+
+impl SweepClient {
+    /// Whether it could be sent -- if not, the client's gone.
+    fn send<T : Serialize>(&mut self, marker : &[u8], value : &T) -> bool {
+        frame(marker, value, self.format).is_ok_and(|frame| self.stream.write_all(&frame).is_ok())
+    }
+}
+
+/// Runs a sweep over `wavelengths` on a `SWEEP_THREAD`, reporting to
+/// `client`. `LaserBusyError` if one's already running.
+pub(crate) fn start<L : Laser + 'static>(
+    wavelengths : Vec<f32>,
+    dwell : Duration,
+    laser : &SharedLaser<L>,
+    lock_retry_policy : &RetryPolicy,
+    mut client : SweepClient,
+    control : &Arc<SweepControl>,
+) -> Result<(), CoherentError> {
+    if control.running.swap(true, Ordering::SeqCst) {
+        return Err(CoherentError::LaserBusyError);
+    }
+    control.abort.store(false, Ordering::SeqCst);
+    let (laser, policy, thread_control) = (laser.clone(), lock_retry_policy.clone(), Arc::clone(control));
+    std::thread::Builder::new().name(SWEEP_THREAD.to_string()).spawn(move || {
+        let state = match run(&wavelengths, dwell, &laser, &policy, &mut client, &thread_control) {
+            Ok(state) => state,
+            // An `ABORT` while a step was tuning
+            Err(CoherentError::Cancelled) => ProgressState::Aborted,
+            Err(e) => {
+                client.send(ERROR_MARKER, &e);
+                ProgressState::Failed
+            },
+        };
+        let percent = (state == ProgressState::Done).then_some(100.0);
+        client.send(PROGRESS_MARKER, &Progress{operation : Operation::WavelengthSweep, state, percent, eta : None});
+        thread_control.running.store(false, Ordering::SeqCst);
+    }).map(|_| ()).map_err(|e| {
+        control.running.store(false, Ordering::SeqCst);
+        CoherentError::WriteError(e)
+    })
+}
+
+/// The body of the `SWEEP_THREAD`: how the sweep ended, short of failing.
+/// A client that's gone stops it, as if it had been aborted.
+fn run<L : Laser>(
+    wavelengths : &[f32],
+    dwell : Duration,
+    laser : &SharedLaser<L>,
+    lock_retry_policy : &RetryPolicy,
+    client : &mut SweepClient,
+    control : &SweepControl,
+) -> Result<ProgressState, CoherentError> {
+    let started = Instant::now();
+    for (done, &wavelength_nm) in (1..).zip(wavelengths) {
+        if control.aborted() {
+            return Ok(ProgressState::Aborted);
+        }
+        let Some(power_mw) = measure(wavelength_nm, dwell, laser, lock_retry_policy, client.address, control)? else {
+            return Ok(ProgressState::Aborted);
+        };
+        if !client.send(SWEEP_STEP_MARKER, &SweepStep{wavelength_nm, power_mw}) {
+            return Ok(ProgressState::Aborted);
+        }
+        let remaining = (wavelengths.len() - done) as f32;
+        client.send(PROGRESS_MARKER, &Progress{
+            operation : Operation::WavelengthSweep,
+            state : ProgressState::Running,
+            percent : Some(100.0 * done as f32 / wavelengths.len() as f32),
+            eta : Some(started.elapsed().mul_f32(remaining / done as f32)),
+        });
+    }
+    Ok(ProgressState::Done)
+}
+
+/// Tunes to `wavelength_nm`, waits out the dwell, and reads the power:
+/// `None` if the sweep was aborted while it dwelt. The laser is free to
+/// poll while it dwells.
+fn measure<L : Laser>(
+    wavelength_nm : f32,
+    dwell : Duration,
+    laser : &SharedLaser<L>,
+    lock_retry_policy : &RetryPolicy,
+    origin : SocketAddr,
+    control : &SweepControl,
+) -> Result<Option<f32>, CoherentError> {
+    let mut tuning = acquire(LockLevel::Laser, || laser.try_lock_with(lock_retry_policy))?;
+    send_command_from(&mut **tuning, CommonCommand::SetWavelength(wavelength_nm).try_into()?, origin)?;
+    tuning.wait_for_tuning(DEFAULT_COMMAND_TIMEOUT, CHECK_INTERVAL)?;
+    drop(tuning);
+
+    let dwelt = Instant::now() + dwell;
+    while let Some(left) = dwelt.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) {
+        if control.aborted() {
+            return Ok(None);
+        }
+        std::thread::sleep(left.min(CHECK_INTERVAL));
+    }
+    let mut reading = acquire(LockLevel::Laser, || laser.try_lock_with(lock_retry_policy))?;
+    Ok(Some(reading.status()?.primary_power()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wavelengths() {
+        let sweep = |start_nm, stop_nm, step_nm| StartSweep{start_nm, stop_nm, step_nm, dwell : Duration::ZERO};
+        let range = Some((680.0, 1300.0));
+        assert_eq!(sweep(800.0, 900.0, 50.0).wavelengths(range).unwrap(), vec![800.0, 850.0, 900.0]);
+        assert_eq!(sweep(900.0, 800.0, 60.0).wavelengths(range).unwrap(), vec![900.0, 840.0]);
+        assert_eq!(sweep(800.0, 800.0, 10.0).wavelengths(range).unwrap(), vec![800.0]);
+        for (sweep, range) in [
+            (sweep(800.0, 900.0, 0.0), range),
+            (sweep(800.0, 900.0, f32::NAN), range),
+            (sweep(600.0, 900.0, 10.0), range),
+            (sweep(800.0, f32::INFINITY, 10.0), range),
+            (sweep(800.0, 900.0, 10.0), None),
+        ] {
+            assert!(matches!(sweep.wavelengths(range), Err(CoherentError::InvalidArgumentsError(_))));
+        }
+    }
+
+    #[test]
+    fn test_control() {
+        let control = SweepControl::default();
+        assert!(matches!(control.abort(), Err(CoherentError::CommandNotExecutedError)));
+        control.running.store(true, Ordering::SeqCst);
+        control.abort().unwrap();
+        assert!(control.aborted());
+    }
+}
@@ -0,0 +1,174 @@
+//! telemetry.rs
+//!
+//! OpenTelemetry spans for commands, end to end: the client's request, the
+//! time the server spends waiting for the laser, and the serial exchange
+//! itself. The client sends its trace context ahead of the command as a
+//! W3C `traceparent` (see `TRACE_MARKER`), so all three land in one trace.
+//!
+//! Spans go to whatever tracer provider the application installs with
+//! `opentelemetry::global::set_tracer_provider` -- with none installed they
+//! cost next to nothing, and no trace context is sent.
+
+use std::time::SystemTime;
+
+use opentelemetry::{
+    Context, KeyValue, global,
+    trace::{
+        Span, SpanContext, SpanId, SpanKind, Status, TraceContextExt, TraceFlags, TraceId,
+        TraceState, Tracer,
+    },
+};
+
+use super::{TRACE_MARKER, TERMINATOR};
+
+/// The name spans are recorded under.
+pub const TRACER_NAME : &str = "coherent-rs";
+
+/// Formats `span_context` as a W3C `traceparent`,
+/// e.g. `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+/// `None` if it isn't valid (e.g. no tracer provider is installed).
+pub fn traceparent(span_context : &SpanContext) -> Option<String> {
+    if !span_context.is_valid() { return None; }
+    Some(format!(
+        "00-{}-{}-{:02x}",
+        span_context.trace_id(), span_context.span_id(), span_context.trace_flags().to_u8()
+    ))
+}
+
+/// Reads a W3C `traceparent` into the (remote) `SpanContext` it describes.
+pub fn parse_traceparent(traceparent : &str) -> Option<SpanContext> {
+    let fields = traceparent.trim().split('-').collect::<Vec<_>>();
+    let ["00", trace_id, span_id, flags] = fields[..] else { return None; };
+    if trace_id.len() != 32 || span_id.len() != 16 || flags.len() != 2 { return None; }
+    let span_context = SpanContext::new(
+        TraceId::from_hex(trace_id).ok()?,
+        SpanId::from_hex(span_id).ok()?,
+        TraceFlags::new(u8::from_str_radix(flags, 16).ok()?),
+        true,
+        TraceState::default(),
+    );
+    span_context.is_valid().then_some(span_context)
+}
+
+/// The span around a client's command, from sending it to hearing back.
+pub(crate) struct ClientTrace {
+    cx : Context,
+}
+
+impl ClientTrace {
+    pub fn start(command : &str) -> Self {
+        let tracer = global::tracer(TRACER_NAME);
+        let span = tracer.span_builder("coherent.client.command")
+            .with_kind(SpanKind::Client)
+            .with_attributes([KeyValue::new("coherent.command", command.to_string())])
+            .start(&tracer);
+        ClientTrace{cx : Context::current_with_span(span)}
+    }
+
+    /// The trace frame to send ahead of the command -- empty if
+    /// there's no trace to continue.
+    pub fn frame(&self) -> Vec<u8> {
+        match traceparent(self.cx.span().span_context()) {
+            Some(traceparent) => [TRACE_MARKER, traceparent.as_bytes(), TERMINATOR].concat(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn finish<T, E : std::fmt::Debug>(self, result : &Result<T, E>) {
+        if let Err(e) = result {
+            self.cx.span().set_status(Status::error(format!("{:?}", e)));
+        }
+        self.cx.span().end();
+    }
+}
+
+/// The spans the server records for a command from a client: one for the
+/// whole command, continuing the client's trace if it sent one, and one
+/// for the wait for the laser.
+pub(crate) struct ServerTrace {
+    cx : Context,
+    queue : Option<global::BoxedSpan>,
+}
+
+impl ServerTrace {
+    /// Starts the spans for a command read from `stream`, which may
+    /// begin with a trace frame.
+    pub fn received(stream : &[u8]) -> Self {
+        let parent = stream.strip_prefix(TRACE_MARKER)
+            .and_then(|rest| rest.split(|&b| b == TERMINATOR[0]).next())
+            .and_then(|traceparent| parse_traceparent(&String::from_utf8_lossy(traceparent)))
+            .map(|span_context| Context::new().with_remote_span_context(span_context))
+            .unwrap_or_default();
+
+        let tracer = global::tracer(TRACER_NAME);
+        let now = SystemTime::now();
+        let span = tracer.span_builder("coherent.server.command")
+            .with_kind(SpanKind::Server)
+            .with_start_time(now)
+            .start_with_context(&tracer, &parent);
+        let cx = parent.with_span(span);
+        let queue = tracer.span_builder("coherent.server.queue")
+            .with_start_time(now)
+            .start_with_context(&tracer, &cx);
+        ServerTrace{cx, queue : Some(queue)}
+    }
+
+    /// Ends the queueing span once the server has the laser, and makes the
+    /// command's span current so the serial exchange is recorded under it.
+    pub fn dequeued(&mut self) -> opentelemetry::ContextGuard {
+        if let Some(mut queue) = self.queue.take() { queue.end(); }
+        self.cx.clone().attach()
+    }
+
+    pub fn finish<T, E : std::fmt::Debug>(mut self, result : &Result<T, E>) {
+        if let Some(mut queue) = self.queue.take() { queue.end(); }
+        if let Err(e) = result {
+            self.cx.span().set_status(Status::error(format!("{:?}", e)));
+        }
+        self.cx.span().end();
+    }
+}
+
+/// A span for one command's exchange over the serial port, under whatever
+/// span is current (the server's, for a client's command). Ends when dropped.
+pub fn serial_span(command : &str) -> global::BoxedSpan {
+    let tracer = global::tracer(TRACER_NAME);
+    tracer.span_builder("coherent.serial.execute")
+        .with_attributes([KeyValue::new("coherent.command", command.to_string())])
+        .start(&tracer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traceparent() {
+        let parsed = parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01\n").unwrap();
+        assert!(parsed.is_remote());
+        assert!(parsed.trace_flags().is_sampled());
+        assert_eq!(
+            traceparent(&parsed).unwrap(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+
+        for bad in [
+            "",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e47-00f067aa0ba902b7-01",
+        ] {
+            assert!(parse_traceparent(bad).is_none(), "{}", bad);
+        }
+        assert_eq!(traceparent(&SpanContext::NONE), None);
+    }
+
+    #[test]
+    fn test_untraced_client_sends_no_frame() {
+        // No tracer provider is installed, so there's no trace to continue
+        let trace = ClientTrace::start("WV=800");
+        assert!(trace.frame().is_empty());
+        trace.finish::<(), ()>(&Ok(()));
+    }
+}
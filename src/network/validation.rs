@@ -0,0 +1,68 @@
+//! validation.rs
+//!
+//! Asking the server whether a command would go through, without sending
+//! it -- so a UI can grey out a setting before the user tries it. The
+//! server runs every check it would run on the command itself (the primary
+//! client, then `Laser::validate`) and says what it would send to the
+//! laser, and whether it would hold the command for confirmation.
+//!
+//! ```text
+//! Validate: <command>\n
+//! Validation: <Validation>\n
+//! ```
+//!
+//! A command that would fail gets the `COMMAND_FAILED` it would get if it
+//! were sent (or `NOT_PRIMARY_CLIENT`), with the reason.
+
+use serde::{Serialize, Deserialize};
+
+use crate::CoherentError;
+use crate::laser::Laser;
+use super::confirmation::ConfirmationPolicy;
+
+/// What the server would do with a command.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Validation {
+    /// What would be written to the laser, in order
+    pub serial_commands : Vec<String>,
+    /// Whether the server's `ConfirmationPolicy` would hold the command
+    /// for a second client
+    pub requires_confirmation : bool,
+}
+
+/// Checks `command` against `laser` and the server's confirmation policy.
+pub(crate) fn validate<L : Laser>(laser : &mut L, policy : Option<&mut ConfirmationPolicy<L>>, command : &L::CommandEnum)
+    -> Result<Validation, CoherentError> {
+    let serial_commands = laser.validate(command)?;
+    let requires_confirmation = policy.is_some_and(|policy| policy.requires_confirmation(laser, command));
+    Ok(Validation{serial_commands, requires_confirmation})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laser::{ShutterState, DiscoveryLaser, DiscoveryNXCommands, debug::DebugLaser};
+    use crate::laser::units::Nanometers;
+
+    #[test]
+    fn test_validate() {
+        let mut laser = DebugLaser::default();
+        let open = DiscoveryNXCommands::Shutter{laser : DiscoveryLaser::VariableWavelength, state : ShutterState::Open};
+        let mut policy = ConfirmationPolicy::new(|_ : &mut DebugLaser, command| matches!(
+            command, DiscoveryNXCommands::Shutter{state : ShutterState::Open, ..}
+        ));
+
+        assert_eq!(
+            validate(&mut laser, Some(&mut policy), &open).unwrap(),
+            Validation{serial_commands : vec!["S=1".to_string()], requires_confirmation : true}
+        );
+        assert!(!validate(&mut laser, None, &open).unwrap().requires_confirmation);
+        // Nothing was sent
+        assert_eq!(laser.get_shutter(DiscoveryLaser::VariableWavelength).unwrap(), ShutterState::Closed);
+
+        assert!(matches!(
+            validate(&mut laser, Some(&mut policy), &DiscoveryNXCommands::Wavelength{wavelength_nm : Nanometers(1200.0)}),
+            Err(CoherentError::CommandNotExecutedError)
+        ));
+    }
+}